@@ -0,0 +1,179 @@
+//! Benchmarks for parsing, evaluation, analysis and export, run on programs from
+//! `generate_program` so they're the same from run to run.
+//!
+//! Run them with `cargo bench --features bench`. Program sizes default to 10,000 and 100,000
+//! gates; set `MCIRCUIT_BENCH_GATES` to a comma-separated list of sizes to use others.
+//!
+//! To check a change for regressions, save a baseline before making it and compare against it
+//! afterwards:
+//!
+//! ```text
+//! cargo bench --features bench -- --save-baseline before
+//! # make the change
+//! cargo bench --features bench -- --baseline before
+//! ```
+//!
+//! Criterion reports the change in each benchmark's time against the baseline, and whether it's
+//! statistically significant.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use mcircuit::analysis::{AnalysisPass, WireCounter};
+use mcircuit::exporters::{BristolFashion, Dot, Export, IR1};
+use mcircuit::parsers::bristol::BristolParser;
+use mcircuit::{
+    evaluate_composite_program, generate_program, validate_program, write_program,
+    CombineOperation, Generated, Operation, Profile, Program, ProgramReader, DEFAULT_REPORT_CAP,
+};
+
+const SEED: u64 = 0x6d63_6972;
+
+fn sizes() -> Vec<usize> {
+    match std::env::var("MCIRCUIT_BENCH_GATES") {
+        Ok(sizes) => sizes
+            .split(',')
+            .map(|size| {
+                size.trim()
+                    .parse()
+                    .expect("MCIRCUIT_BENCH_GATES should be a comma-separated list of sizes")
+            })
+            .collect(),
+        Err(_) => vec![10_000, 100_000],
+    }
+}
+
+fn profiles() -> [(&'static str, Profile); 3] {
+    [
+        ("boolean", Profile::yosys_boolean()),
+        ("arithmetic", Profile::arithmetic()),
+        ("mixed", Profile::mixed_conversions()),
+    ]
+}
+
+fn boolean_gates(generated: &Generated) -> Vec<Operation<bool>> {
+    generated
+        .gates
+        .iter()
+        .filter_map(|gate| match gate {
+            CombineOperation::GF2(op) => Some(*op),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for size in sizes() {
+        group.throughput(Throughput::Elements(size as u64));
+
+        let generated = generate_program(&Profile::yosys_boolean(), size, SEED);
+        let mut bristol = Vec::new();
+        BristolFashion::export_circuit(
+            &boolean_gates(&generated),
+            &generated.bool_inputs,
+            &mut bristol,
+        )
+        .unwrap();
+        group.bench_with_input(BenchmarkId::new("bristol", size), &bristol, |b, text| {
+            b.iter(|| {
+                BristolParser::from_reader(Cursor::new(text))
+                    .read_all()
+                    .unwrap()
+            })
+        });
+
+        let generated = generate_program(&Profile::mixed_conversions(), size, SEED);
+        let mut mcir = Vec::new();
+        write_program(&Program::from(generated.gates), &mut mcir).unwrap();
+        group.bench_with_input(BenchmarkId::new("mcir", size), &mcir, |b, bytes| {
+            b.iter(|| {
+                ProgramReader::new(Cursor::new(bytes))
+                    .and_then(|mut reader| reader.gates())
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn evaluation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("evaluate");
+    for size in sizes() {
+        group.throughput(Throughput::Elements(size as u64));
+        for (name, profile) in profiles() {
+            let generated = generate_program(&profile, size, SEED);
+            group.bench_with_input(BenchmarkId::new(name, size), &generated, |b, generated| {
+                b.iter(|| {
+                    evaluate_composite_program(
+                        &generated.gates,
+                        &generated.bool_inputs,
+                        &generated.arith_inputs,
+                    )
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn analysis(c: &mut Criterion) {
+    let mut group = c.benchmark_group("analyze");
+    for size in sizes() {
+        group.throughput(Throughput::Elements(size as u64));
+        let generated = generate_program(&Profile::mixed_conversions(), size, SEED);
+        group.bench_with_input(
+            BenchmarkId::new("wire_counter", size),
+            &generated.gates,
+            |b, gates| b.iter(|| WireCounter::analyze(gates.iter())),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("validate", size),
+            &generated.gates,
+            |b, gates| b.iter(|| validate_program(gates, DEFAULT_REPORT_CAP)),
+        );
+    }
+    group.finish();
+}
+
+fn export(c: &mut Criterion) {
+    let mut group = c.benchmark_group("export");
+    for size in sizes() {
+        group.throughput(Throughput::Elements(size as u64));
+
+        let generated = generate_program(&Profile::yosys_boolean(), size, SEED);
+        let gates = boolean_gates(&generated);
+        group.bench_with_input(BenchmarkId::new("ir1", size), &gates, |b, gates| {
+            b.iter(|| {
+                let mut sink = Vec::new();
+                IR1::export_circuit(gates, &generated.bool_inputs, &mut sink).unwrap();
+                sink
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("bristol", size), &gates, |b, gates| {
+            b.iter(|| {
+                let mut sink = Vec::new();
+                BristolFashion::export_circuit(gates, &generated.bool_inputs, &mut sink).unwrap();
+                sink
+            })
+        });
+
+        let generated = generate_program(&Profile::mixed_conversions(), size, SEED);
+        group.bench_with_input(
+            BenchmarkId::new("dot", size),
+            &generated.gates,
+            |b, gates| {
+                b.iter(|| {
+                    let mut sink = Vec::new();
+                    Dot::default().export_program(gates, &mut sink).unwrap();
+                    sink
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing, evaluation, analysis, export);
+criterion_main!(benches);
@@ -0,0 +1,421 @@
+//! Recovers word-level adders from bit-blasted GF2 circuits. Synthesis tools like Yosys lower
+//! `$add` cells to individual gates before mcircuit ever sees them; finding the ripple-carry
+//! chains again makes the circuit easier to read, and tells exporters with arithmetic where they
+//! could use it.
+//!
+//! Recognition is by function rather than by shape, so it doesn't matter how the synthesis tool
+//! chose to write each full adder. Every GF2 value gets a handful of cuts: small sets of earlier
+//! values (at most three) that it's a function of, with the function written out in algebraic
+//! normal form. A sum bit is a value that's the XOR of three others; the carry out of the same
+//! bit is their majority; and a chain links bits through the carries.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::analysis::AnalysisPass;
+use crate::{CombineOperation, HasIO, Operation, Program};
+
+/// A product of values, as sorted value ids. The empty monomial is the constant one.
+type Monomial = Vec<usize>;
+
+/// A boolean function in algebraic normal form: the XOR of its monomials.
+type Anf = BTreeSet<Monomial>;
+
+/// Values with more cuts than this keep the smallest ones.
+const MAX_CUTS: usize = 16;
+
+/// Full adders have three inputs, so cuts never need more leaves than that.
+const MAX_LEAVES: usize = 3;
+
+/// A ripple-carry adder found in a GF2 circuit. All wires are GF2, least significant bit first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Adder {
+    /// The operands. Addition commutes, so which bit goes in which operand is a guess: the
+    /// lower-numbered wire of each pair goes in `a`.
+    pub a: Vec<usize>,
+    pub b: Vec<usize>,
+    pub sum: Vec<usize>,
+    /// The carry out of the top bit, if the circuit computes it
+    pub carry: Option<usize>,
+    /// Index of the gate writing each sum bit
+    pub sum_gates: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Cut {
+    /// The values the function reads, sorted
+    leaves: Vec<usize>,
+    anf: Anf,
+}
+
+impl Cut {
+    fn new(anf: Anf) -> Self {
+        let leaves: BTreeSet<usize> = anf.iter().flatten().copied().collect();
+        Cut {
+            leaves: leaves.into_iter().collect(),
+            anf,
+        }
+    }
+
+    fn constant(value: bool) -> Self {
+        Cut::new(if value {
+            std::iter::once(Vec::new()).collect()
+        } else {
+            Anf::new()
+        })
+    }
+}
+
+fn xor(a: &Anf, b: &Anf) -> Anf {
+    a.symmetric_difference(b).cloned().collect()
+}
+
+fn and(a: &Anf, b: &Anf) -> Anf {
+    let mut product = Anf::new();
+    for x in a {
+        for y in b {
+            // x * x = x, so repeated values collapse
+            let mut monomial: Monomial = x.iter().chain(y).copied().collect();
+            monomial.sort_unstable();
+            monomial.dedup();
+            if !product.remove(&monomial) {
+                product.insert(monomial);
+            }
+        }
+    }
+    product
+}
+
+/// A value written by a GF2 gate (or read without being written).
+struct Value {
+    wire: usize,
+    /// The gate that wrote it, if one did
+    gate: Option<usize>,
+}
+
+/// What a cut says a value computes, when it's part of an adder.
+#[derive(Default)]
+struct Patterns {
+    /// x ^ y, by (x, y)
+    half_sums: BTreeMap<Vec<usize>, usize>,
+    /// x & y, by (x, y)
+    half_carries: BTreeMap<Vec<usize>, Vec<usize>>,
+    /// x ^ y ^ z, by (x, y, z)
+    sums: BTreeMap<Vec<usize>, usize>,
+    /// majority(x, y, z), by (x, y, z)
+    carries: BTreeMap<Vec<usize>, Vec<usize>>,
+}
+
+impl Patterns {
+    fn record(&mut self, value: usize, cut: &Cut) {
+        let degrees: Vec<usize> = cut.anf.iter().map(Vec::len).collect();
+        match (cut.leaves.len(), degrees.as_slice()) {
+            (2, [1, 1]) => {
+                self.half_sums.entry(cut.leaves.clone()).or_insert(value);
+            }
+            (2, [2]) => self
+                .half_carries
+                .entry(cut.leaves.clone())
+                .or_default()
+                .push(value),
+            (3, [1, 1, 1]) => {
+                self.sums.entry(cut.leaves.clone()).or_insert(value);
+            }
+            (3, [2, 2, 2]) => self
+                .carries
+                .entry(cut.leaves.clone())
+                .or_default()
+                .push(value),
+            _ => {}
+        }
+    }
+}
+
+/// Finds ripple-carry adders in the GF2 part of a program (see the module documentation). Chains
+/// start with a half adder, which is also what a full adder with a constant carry in reduces to,
+/// and need at least two bits. The pass keeps all its state between gates, so a program can be
+/// fed to it in pieces, as it's parsed.
+#[derive(Default)]
+pub struct FindAdders {
+    index: usize,
+    /// The value each GF2 wire currently holds
+    current: HashMap<usize, usize>,
+    values: Vec<Value>,
+    /// The nontrivial cuts of each value
+    cuts: HashMap<usize, Vec<Cut>>,
+    patterns: Patterns,
+}
+
+impl FindAdders {
+    fn new_value(&mut self, wire: usize, gate: Option<usize>) -> usize {
+        self.values.push(Value { wire, gate });
+        self.values.len() - 1
+    }
+
+    /// The cuts of the value on `wire`, including the trivial one.
+    fn cuts(&mut self, wire: usize) -> Vec<Cut> {
+        let value = match self.current.get(&wire) {
+            Some(value) => *value,
+            None => {
+                let value = self.new_value(wire, None);
+                self.current.insert(wire, value);
+                value
+            }
+        };
+        let mut cuts = self.cuts.get(&value).cloned().unwrap_or_default();
+        cuts.push(Cut::new(std::iter::once(vec![value]).collect()));
+        cuts
+    }
+
+    fn unary(&mut self, wire: usize, f: impl Fn(&Anf) -> Anf) -> Vec<Cut> {
+        let cuts = self.cuts(wire);
+        cuts.iter().map(|cut| Cut::new(f(&cut.anf))).collect()
+    }
+
+    fn binary(&mut self, a: usize, b: usize, op: fn(&Anf, &Anf) -> Anf) -> Vec<Cut> {
+        let (a, b) = (self.cuts(a), self.cuts(b));
+        let mut merged = Vec::new();
+        for x in &a {
+            for y in &b {
+                let leaves: BTreeSet<&usize> = x.leaves.iter().chain(&y.leaves).collect();
+                if leaves.len() <= MAX_LEAVES {
+                    merged.push(Cut::new(op(&x.anf, &y.anf)));
+                }
+            }
+        }
+        merged
+    }
+
+    /// Turns a chain of (operand values, sum value) pairs into wires.
+    fn adder(&self, bits: &[(Vec<usize>, usize)], carry: Option<usize>) -> Adder {
+        let mut adder = Adder {
+            a: Vec::new(),
+            b: Vec::new(),
+            sum: Vec::new(),
+            carry: carry.map(|value| self.values[value].wire),
+            sum_gates: Vec::new(),
+        };
+        for (operands, value) in bits {
+            let mut wires: Vec<usize> = operands.iter().map(|v| self.values[*v].wire).collect();
+            wires.sort_unstable();
+            adder.a.push(wires[0]);
+            adder.b.push(wires[1]);
+            adder.sum.push(self.values[*value].wire);
+            adder.sum_gates.push(
+                self.values[*value]
+                    .gate
+                    .expect("Sums are computed by gates"),
+            );
+        }
+        adder
+    }
+}
+
+impl AnalysisPass for FindAdders {
+    type Output = Vec<Adder>;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        if let CombineOperation::GF2(op) = gate {
+            let cuts = match *op {
+                Operation::Const(_, c) => vec![Cut::constant(c)],
+                Operation::AddConst(_, a, c) | Operation::SubConst(_, a, c) => {
+                    let constant = Cut::constant(c).anf;
+                    self.unary(a, |anf| xor(anf, &constant))
+                }
+                Operation::MulConst(_, a, true) => self.unary(a, Clone::clone),
+                Operation::MulConst(_, _, false) => vec![Cut::constant(false)],
+                Operation::Add(_, a, b) | Operation::Sub(_, a, b) => self.binary(a, b, xor),
+                Operation::Mul(_, a, b) => self.binary(a, b, and),
+                Operation::Input(_) | Operation::Random(_) | Operation::AssertZero(_) => Vec::new(),
+            };
+
+            if let Some(dst) = op.dst() {
+                let value = self.new_value(dst, Some(self.index));
+                self.current.insert(dst, value);
+
+                let mut kept: Vec<Cut> = Vec::new();
+                for cut in cuts {
+                    if !kept.iter().any(|other| other.leaves == cut.leaves) {
+                        kept.push(cut);
+                    }
+                }
+                kept.sort_by_key(|cut| cut.leaves.len());
+                kept.truncate(MAX_CUTS);
+                for cut in &kept {
+                    self.patterns.record(value, cut);
+                }
+                self.cuts.insert(value, kept);
+            }
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        let patterns = &self.patterns;
+        let mut containing: HashMap<usize, Vec<&Vec<usize>>> = HashMap::new();
+        for leaves in patterns.sums.keys() {
+            for leaf in leaves {
+                containing.entry(*leaf).or_default().push(leaves);
+            }
+        }
+
+        let mut adders = Vec::new();
+        let mut started = HashSet::new();
+        for (operands, carries) in &patterns.half_carries {
+            // Copies of the operands give the same chain more than once. Leaves are ordered by
+            // value, so the first time it's found is in terms of the originals.
+            let first = match patterns.half_sums.get(operands) {
+                Some(sum) if started.insert(*sum) => *sum,
+                _ => continue,
+            };
+            let mut bits = vec![(operands.clone(), first)];
+            let mut carries = carries.clone();
+            // Follow the carry into the next bit's sum, until there isn't one
+            let carry = loop {
+                let next = carries.iter().find_map(|carry| {
+                    containing
+                        .get(carry)
+                        .and_then(|sums| sums.first())
+                        .map(|leaves| (*carry, *leaves))
+                });
+                let (carry_in, leaves) = match next {
+                    Some(next) => next,
+                    None => break carries.first().copied(),
+                };
+                let operands = leaves.iter().copied().filter(|v| *v != carry_in).collect();
+                bits.push((operands, patterns.sums[leaves]));
+                match patterns.carries.get(leaves) {
+                    Some(next) => carries = next.clone(),
+                    None => break None,
+                }
+            };
+            if bits.len() >= 2 {
+                adders.push(self.adder(&bits, carry));
+            }
+        }
+        adders
+    }
+}
+
+/// Describes wires compactly, as a range if they're consecutive.
+fn describe(wires: &[usize]) -> String {
+    match (wires.first(), wires.last()) {
+        (Some(first), Some(last)) if wires.windows(2).all(|pair| pair[1] == pair[0] + 1) => {
+            format!("wires {}..{}", first, last + 1)
+        }
+        _ => format!("wires {:?}", wires),
+    }
+}
+
+/// Finds the adders in a program and annotates the gate writing the top bit of each sum with
+/// what it adds. Annotations already on those gates are kept, with the adder appended, and
+/// running it again on an annotated program changes nothing.
+pub fn annotate_adders(program: &mut Program) -> Vec<Adder> {
+    let adders = FindAdders::analyze(program.gates.iter());
+    for adder in &adders {
+        let note = format!(
+            "{}-bit adder: {} = {} + {}",
+            adder.sum.len(),
+            describe(&adder.sum),
+            describe(&adder.a),
+            describe(&adder.b)
+        );
+        let gate = *adder
+            .sum_gates
+            .last()
+            .expect("Adders have at least two bits");
+        let existing = program
+            .annotations
+            .as_ref()
+            .and_then(|notes| notes.get(&gate));
+        match existing {
+            Some(existing) if existing.contains(&note) => {}
+            Some(existing) => {
+                let combined = format!("{}; {}", existing, note);
+                program.annotate(gate, &combined);
+            }
+            None => program.annotate(gate, &note),
+        }
+    }
+    adders
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adders::{annotate_adders, Adder, FindAdders};
+    use crate::analysis::AnalysisPass;
+    use crate::gadgets::bits::add;
+    use crate::{CircuitBuilder, CombineOperation, Operation, Program};
+
+    #[test]
+    fn test_find_adders() {
+        // The gadget's adder, which computes carries as c ^ ((x ^ c) & (y ^ c))
+        let mut builder = CircuitBuilder::new();
+        let wires = |builder: &mut CircuitBuilder| -> Vec<usize> {
+            builder.inputs::<bool>(4).iter().map(|w| w.index).collect()
+        };
+        let (x, y) = (wires(&mut builder), wires(&mut builder));
+        let (sum, carry) = add(&mut builder, &x, &y);
+        let adders = FindAdders::analyze(builder.gates().iter());
+        assert_eq!(adders.len(), 1);
+        assert_eq!((&adders[0].a, &adders[0].b), (&x, &y));
+        assert_eq!(adders[0].sum, sum);
+        assert_eq!(adders[0].carry, Some(carry));
+
+        // A 2-bit adder written the way a synthesis tool might, with ORs made of XORs and ANDs,
+        // and no carry out
+        let gf2 = CombineOperation::GF2;
+        let gates = [
+            gf2(Operation::Input(0)),
+            gf2(Operation::Input(1)),
+            gf2(Operation::Input(2)),
+            gf2(Operation::Input(3)),
+            // Bit 0: half adder on 0 and 2
+            gf2(Operation::Add(4, 0, 2)),
+            gf2(Operation::Mul(5, 2, 0)),
+            // Bit 1: sum of 1, 3 and the carry on 5
+            gf2(Operation::Add(6, 1, 3)),
+            gf2(Operation::Add(7, 5, 6)),
+        ];
+        let mut program = Program::from(gates.to_vec());
+        let adders = annotate_adders(&mut program);
+        assert_eq!(
+            adders,
+            [Adder {
+                a: vec![0, 1],
+                b: vec![2, 3],
+                sum: vec![4, 7],
+                carry: None,
+                sum_gates: vec![4, 7],
+            }]
+        );
+        let note = "2-bit adder: wires [4, 7] = wires 0..2 + wires 2..4";
+        assert_eq!(program.annotations.as_ref().unwrap()[&7], note);
+        annotate_adders(&mut program);
+        assert_eq!(program.annotations.as_ref().unwrap()[&7], note);
+
+        // A carry out as (a & b) | (a & c) | (b & c), with each OR as x ^ y ^ xy
+        let mut gates = gates.to_vec();
+        gates.extend([
+            gf2(Operation::Mul(8, 1, 3)),
+            gf2(Operation::Mul(9, 5, 1)),
+            gf2(Operation::Mul(10, 5, 3)),
+            gf2(Operation::Add(11, 8, 9)),
+            gf2(Operation::Mul(12, 8, 9)),
+            gf2(Operation::Add(13, 11, 12)),
+            gf2(Operation::Add(14, 13, 10)),
+            gf2(Operation::Mul(15, 13, 10)),
+            gf2(Operation::Add(16, 14, 15)),
+        ]);
+        let adders = FindAdders::analyze(gates.iter());
+        assert_eq!(adders[0].carry, Some(16));
+
+        // Nothing adds here
+        let gates = [
+            gf2(Operation::Input(0)),
+            gf2(Operation::Input(1)),
+            gf2(Operation::Add(2, 0, 1)),
+            gf2(Operation::Mul(3, 2, 1)),
+        ];
+        assert!(FindAdders::analyze(gates.iter()).is_empty());
+    }
+}
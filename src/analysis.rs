@@ -1,8 +1,32 @@
-use std::cmp::{max, min};
+use std::cmp::{max, min, Reverse};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::ops::Range;
 
-use crate::{CombineOperation, HasIO};
+use serde::{Deserialize, Serialize};
+
+use crate::{CombineOperation, Domain, HasIO, Operation, WireValue};
 
 /// Generic trait for running something on all the gates in a circuit. Currently used to count wires
+///
+/// Passes can be fused into one, so they all see each gate during a single walk over the
+/// circuit: tuples of up to six passes are passes producing a tuple of their outputs, and so are
+/// vectors of passes of the same type. On big programs, walking once instead of once per pass
+/// saves most of the time spent on analysis.
+///
+/// ```
+/// use mcircuit::analysis::{AnalysisPass, Rounds, WireCounter};
+/// use mcircuit::{CombineOperation, Operation};
+///
+/// let program = [
+///     CombineOperation::GF2(Operation::Input(0)),
+///     CombineOperation::GF2(Operation::Mul(1, 0, 0)),
+/// ];
+/// let (wires, rounds) = <(WireCounter, Rounds)>::analyze(program.iter());
+/// assert_eq!(wires.0, (1, 2));
+/// assert_eq!(rounds[0].rounds, 1);
+/// ```
 pub trait AnalysisPass {
     type Output;
 
@@ -10,16 +34,141 @@ pub trait AnalysisPass {
 
     fn finish_analysis(self) -> Self::Output;
 
+    /// Runs a pass that needs some initial state (e.g. how to segment the circuit).
+    fn run<'a>(mut self, circuit: impl Iterator<Item = &'a CombineOperation>) -> Self::Output
+    where
+        Self: Sized,
+    {
+        for gate in circuit {
+            self.analyze_gate(gate);
+        }
+        self.finish_analysis()
+    }
+
     fn analyze<'a>(circuit: impl Iterator<Item = &'a CombineOperation>) -> Self::Output
     where
         Self: Default,
     {
-        let mut result = Self::default();
+        Self::default().run(circuit)
+    }
+}
 
-        for gate in circuit {
-            result.analyze_gate(gate);
+/// Like `AnalysisPass`, but visits gates from last to first, so every gate is seen after all the
+/// gates that use its output. Suits passes that propagate demand from uses back to definitions,
+/// like liveness and cone-of-influence slicing. The driver walks the circuit in reverse directly,
+/// without collecting it into a reversed copy first. Backward passes fuse the same way forward
+/// ones do.
+pub trait BackwardAnalysisPass {
+    type Output;
+
+    /// `index` is the gate's position in the circuit, counting from the front.
+    fn analyze_gate(&mut self, index: usize, gate: &CombineOperation);
+
+    fn finish_analysis(self) -> Self::Output;
+
+    /// Lets a pass stop early once the rest of the circuit can't affect its result. Checked
+    /// before each gate.
+    fn is_done(&self) -> bool {
+        false
+    }
+
+    /// Runs a pass that needs some initial state (e.g. the wires to start from).
+    fn run<'a, I>(mut self, circuit: I) -> Self::Output
+    where
+        Self: Sized,
+        I: DoubleEndedIterator<Item = &'a CombineOperation> + ExactSizeIterator,
+    {
+        for (index, gate) in circuit.enumerate().rev() {
+            if self.is_done() {
+                break;
+            }
+            self.analyze_gate(index, gate);
         }
-        result.finish_analysis()
+        self.finish_analysis()
+    }
+
+    fn analyze<'a, I>(circuit: I) -> Self::Output
+    where
+        Self: Default,
+        I: DoubleEndedIterator<Item = &'a CombineOperation> + ExactSizeIterator,
+    {
+        Self::default().run(circuit)
+    }
+}
+
+// Tuples of passes are passes too, which is how they get fused
+macro_rules! fuse_passes {
+    ($($pass:ident $index:tt),+) => {
+        impl<$($pass: AnalysisPass),+> AnalysisPass for ($($pass,)+) {
+            type Output = ($($pass::Output,)+);
+
+            fn analyze_gate(&mut self, gate: &CombineOperation) {
+                $(self.$index.analyze_gate(gate);)+
+            }
+
+            fn finish_analysis(self) -> Self::Output {
+                ($(self.$index.finish_analysis(),)+)
+            }
+        }
+
+        /// Passes that are done stop seeing gates, and the fused pass is done once they all are.
+        impl<$($pass: BackwardAnalysisPass),+> BackwardAnalysisPass for ($($pass,)+) {
+            type Output = ($($pass::Output,)+);
+
+            fn analyze_gate(&mut self, index: usize, gate: &CombineOperation) {
+                $(
+                    if !self.$index.is_done() {
+                        self.$index.analyze_gate(index, gate);
+                    }
+                )+
+            }
+
+            fn finish_analysis(self) -> Self::Output {
+                ($(self.$index.finish_analysis(),)+)
+            }
+
+            fn is_done(&self) -> bool {
+                $(self.$index.is_done())&&+
+            }
+        }
+    };
+}
+
+fuse_passes!(A 0, B 1);
+fuse_passes!(A 0, B 1, C 2);
+fuse_passes!(A 0, B 1, C 2, D 3);
+fuse_passes!(A 0, B 1, C 2, D 3, E 4);
+fuse_passes!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+impl<P: AnalysisPass> AnalysisPass for Vec<P> {
+    type Output = Vec<P::Output>;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        for pass in self.iter_mut() {
+            pass.analyze_gate(gate);
+        }
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        self.into_iter().map(P::finish_analysis).collect()
+    }
+}
+
+impl<P: BackwardAnalysisPass> BackwardAnalysisPass for Vec<P> {
+    type Output = Vec<P::Output>;
+
+    fn analyze_gate(&mut self, index: usize, gate: &CombineOperation) {
+        for pass in self.iter_mut().filter(|pass| !pass.is_done()) {
+            pass.analyze_gate(index, gate);
+        }
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        self.into_iter().map(P::finish_analysis).collect()
+    }
+
+    fn is_done(&self) -> bool {
+        self.iter().all(P::is_done)
     }
 }
 
@@ -79,3 +228,1435 @@ impl AnalysisPass for WireCounter {
         )
     }
 }
+
+/// Measures how densely a program uses its wire index spaces by comparing the span of each domain
+/// against the number of gates that write into it. Used to decide whether to allocate a flat buffer
+/// for evaluation.
+#[derive(Default)]
+pub struct WireDensity {
+    counter: WireCounter,
+    arith_writes: usize,
+    bool_writes: usize,
+}
+
+impl AnalysisPass for WireDensity {
+    /// ((arith span, bool span), (arith writes, bool writes))
+    type Output = ((usize, usize), (usize, usize));
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        match gate {
+            CombineOperation::GF2(gf2_insn) => self.bool_writes += gf2_insn.outputs().count(),
+            CombineOperation::Z64(z64_insn) => self.arith_writes += z64_insn.outputs().count(),
+            CombineOperation::B2A(_, _) => self.arith_writes += 1,
+            CombineOperation::SizeHint(_, _) => {}
+        }
+        self.counter.analyze_gate(gate);
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        (
+            self.counter.finish_analysis().0,
+            (self.arith_writes, self.bool_writes),
+        )
+    }
+}
+
+/// Finds which gates can affect an assertion. A gate is live if it's an assertion or size hint,
+/// or if some live gate reads its output before it's overwritten. This is purely about data
+/// flow: an `Input` gate whose value is never used is dead, even though dropping it would change
+/// which witness values the rest of the inputs read.
+#[derive(Default)]
+pub struct LiveGates {
+    /// Wires whose current value is read by a live gate further down
+    needed: HashSet<(Domain, usize)>,
+    /// Liveness of each gate seen so far, last gate first
+    live: Vec<bool>,
+    /// Whether B2A gates are live whether or not anything reads them
+    keep_conversions: bool,
+}
+
+impl LiveGates {
+    /// Also treats every B2A as live, along with the bits it converts.
+    pub fn keeping_conversions() -> Self {
+        LiveGates {
+            keep_conversions: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl BackwardAnalysisPass for LiveGates {
+    /// Whether each gate is live, in program order
+    type Output = Vec<bool>;
+
+    fn analyze_gate(&mut self, _: usize, gate: &CombineOperation) {
+        let live = match gate {
+            CombineOperation::GF2(Operation::AssertZero(_))
+            | CombineOperation::Z64(Operation::AssertZero(_))
+            | CombineOperation::SizeHint(_, _) => true,
+            CombineOperation::B2A(dst, _) if self.keep_conversions => {
+                self.needed.remove(&(Domain::Z64, *dst));
+                true
+            }
+            _ => match (gate.output_domain(), gate.dst()) {
+                (Some(domain), Some(dst)) => self.needed.remove(&(domain, dst)),
+                _ => false,
+            },
+        };
+
+        if live {
+            if let Some(domain) = gate.input_domain() {
+                self.needed.extend(gate.inputs().map(|w| (domain, w)));
+            }
+        }
+        self.live.push(live);
+    }
+
+    fn finish_analysis(mut self) -> Self::Output {
+        self.live.reverse();
+        self.live
+    }
+}
+
+/// A gate reading a wire that nothing has written yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnwrittenRead {
+    /// Index of the reading gate
+    pub gate: usize,
+    pub domain: Domain,
+    pub wire: usize,
+}
+
+/// Finds every read of a wire before anything has written it. Only the first read of each wire
+/// is reported.
+#[derive(Default)]
+pub struct UnwrittenReads {
+    index: usize,
+    /// Wires that have been written, or already reported
+    seen: HashSet<(Domain, usize)>,
+    found: Vec<UnwrittenRead>,
+}
+
+impl AnalysisPass for UnwrittenReads {
+    type Output = Vec<UnwrittenRead>;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        if let Some(domain) = gate.input_domain() {
+            for wire in gate.inputs() {
+                if self.seen.insert((domain, wire)) {
+                    self.found.push(UnwrittenRead {
+                        gate: self.index,
+                        domain,
+                        wire,
+                    });
+                }
+            }
+        }
+        if let Some(domain) = gate.output_domain() {
+            self.seen.extend(gate.outputs().map(|w| (domain, w)));
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        self.found
+    }
+}
+
+/// Why a program can't be put in an order where every wire is written before it's read.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TopoSortError {
+    /// Wires read somewhere but never written anywhere, with the first gate to read each
+    pub undefined: Vec<UnwrittenRead>,
+    /// Wires written by more than one gate, with every gate that writes each. Which write a read
+    /// sees depends on where it is, so these programs can't be reordered.
+    pub redefined: Vec<(Domain, usize, Vec<usize>)>,
+    /// Reads that come before the gate writing the wire. Reordering fixes these, unless they're
+    /// also in `cycle`.
+    pub out_of_order: Vec<UnwrittenRead>,
+    /// Gates that depend on each other's outputs, directly or not, so no order works for them
+    pub cycle: Vec<usize>,
+}
+
+impl fmt::Display for TopoSortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut problems = Vec::new();
+        if !self.undefined.is_empty() {
+            problems.push(format!("{} wire(s) never written", self.undefined.len()));
+        }
+        if !self.redefined.is_empty() {
+            problems.push(format!(
+                "{} wire(s) written more than once",
+                self.redefined.len()
+            ));
+        }
+        if !self.out_of_order.is_empty() {
+            problems.push(format!(
+                "{} read(s) before the write",
+                self.out_of_order.len()
+            ));
+        }
+        if !self.cycle.is_empty() {
+            problems.push(format!("{} gate(s) in a cycle", self.cycle.len()));
+        }
+        write!(
+            f,
+            "Gates aren't in evaluation order: {}",
+            problems.join(", ")
+        )?;
+        if let Some(read) = self.undefined.first().or_else(|| self.out_of_order.first()) {
+            write!(
+                f,
+                " (first: gate {} reads {:?} wire {})",
+                read.gate, read.domain, read.wire
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TopoSortError {}
+
+/// Checks that every wire is written exactly once, before anything reads it. Evaluators read
+/// unwritten wires as zero, so a program with its gates out of order evaluates without complaint,
+/// just wrongly. `TopoSort::sort` goes further and fixes the order where it can.
+#[derive(Default)]
+pub struct TopoSort {
+    index: usize,
+    /// Every gate that writes each wire
+    writers: HashMap<(Domain, usize), Vec<usize>>,
+    /// The first read of each wire that came before any write
+    early_reads: Vec<UnwrittenRead>,
+}
+
+impl AnalysisPass for TopoSort {
+    type Output = Result<(), TopoSortError>;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        if let Some(domain) = gate.input_domain() {
+            for wire in gate.inputs() {
+                // Recording an empty writer list marks the wire as reported
+                if let Entry::Vacant(entry) = self.writers.entry((domain, wire)) {
+                    entry.insert(Vec::new());
+                    self.early_reads.push(UnwrittenRead {
+                        gate: self.index,
+                        domain,
+                        wire,
+                    });
+                }
+            }
+        }
+        if let Some(domain) = gate.output_domain() {
+            for wire in gate.outputs() {
+                self.writers
+                    .entry((domain, wire))
+                    .or_default()
+                    .push(self.index);
+            }
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        let mut error = TopoSortError::default();
+        for read in self.early_reads {
+            if self.writers[&(read.domain, read.wire)].is_empty() {
+                error.undefined.push(read);
+            } else {
+                error.out_of_order.push(read);
+            }
+        }
+        error.redefined = self
+            .writers
+            .into_iter()
+            .filter(|(_, gates)| gates.len() > 1)
+            .map(|((domain, wire), gates)| (domain, wire, gates))
+            .collect();
+        error.redefined.sort();
+
+        if error == TopoSortError::default() {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
+impl TopoSort {
+    /// Reorders `gates` so every wire is written before it's read, moving as little as possible:
+    /// of the gates that are ready to go next, the one that came first always does. Inputs keep
+    /// their order within each domain, so they read the same witness values. Fails if some wire is
+    /// never written, or written more than once, or if gates depend on each other in a cycle.
+    pub fn sort(gates: &[CombineOperation]) -> Result<Vec<CombineOperation>, TopoSortError> {
+        let mut error = match Self::analyze(gates.iter()) {
+            Ok(()) => return Ok(gates.to_vec()),
+            Err(error) if error.undefined.is_empty() && error.redefined.is_empty() => error,
+            Err(error) => return Err(error),
+        };
+
+        let mut writer = HashMap::new();
+        for (idx, gate) in gates.iter().enumerate() {
+            if let Some(domain) = gate.output_domain() {
+                writer.extend(gate.outputs().map(|wire| ((domain, wire), idx)));
+            }
+        }
+
+        // Edges from each gate to the ones that have to wait for it
+        let mut waiting: Vec<Vec<usize>> = vec![Vec::new(); gates.len()];
+        let mut blockers = vec![0; gates.len()];
+        let mut last_input: HashMap<Domain, usize> = HashMap::new();
+        for (idx, gate) in gates.iter().enumerate() {
+            let mut after: BTreeSet<usize> = match gate.input_domain() {
+                Some(domain) => gate.inputs().map(|wire| writer[&(domain, wire)]).collect(),
+                None => BTreeSet::new(),
+            };
+            if let CombineOperation::GF2(Operation::Input(_))
+            | CombineOperation::Z64(Operation::Input(_)) = gate
+            {
+                let domain = gate.output_domain().expect("Inputs write a wire");
+                after.extend(last_input.insert(domain, idx));
+            }
+            blockers[idx] = after.len();
+            for earlier in after {
+                waiting[earlier].push(idx);
+            }
+        }
+
+        match ready_order(blockers, &waiting) {
+            Ok(order) => Ok(order.into_iter().map(|idx| gates[idx]).collect()),
+            Err(stuck) => {
+                error.cycle = stuck;
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Orders gates by Kahn's algorithm, where `blockers[i]` is the number of gates gate `i` has to
+/// wait for and `waiting[i]` lists the gates waiting for it. Of the gates that are ready to go
+/// next, the one that came first always does. Returns the gate indices in order, or, if some
+/// gates depend on each other in a cycle, the ones that never became ready.
+pub(crate) fn ready_order(
+    mut blockers: Vec<usize>,
+    waiting: &[Vec<usize>],
+) -> Result<Vec<usize>, Vec<usize>> {
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..blockers.len())
+        .filter(|idx| blockers[*idx] == 0)
+        .map(Reverse)
+        .collect();
+    let mut sorted = Vec::with_capacity(blockers.len());
+    while let Some(Reverse(idx)) = ready.pop() {
+        sorted.push(idx);
+        for later in &waiting[idx] {
+            blockers[*later] -= 1;
+            if blockers[*later] == 0 {
+                ready.push(Reverse(*later));
+            }
+        }
+    }
+
+    if sorted.len() == blockers.len() {
+        Ok(sorted)
+    } else {
+        Err((0..blockers.len())
+            .filter(|idx| blockers[*idx] > 0)
+            .collect())
+    }
+}
+
+/// An assertion that depends on a B2A conversion whose source bits weren't all written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnderconstrainedConversion {
+    /// Index of the B2A gate
+    pub conversion: usize,
+    /// Index of the assertion that depends on it
+    pub assertion: usize,
+    /// Offsets (0-63) of the source bits that had no writer before the conversion, and so just
+    /// read as zero
+    pub unwritten_bits: Vec<usize>,
+}
+
+/// Flags Z64 assertions that (directly or through other Z64 gates) depend on a B2A whose source
+/// window includes GF2 wires that were never written before the conversion. Those bits silently
+/// default to zero, so the assertion constrains less than it looks like it does.
+#[derive(Default)]
+pub struct UnderconstrainedConversions {
+    index: usize,
+    written_bool: HashSet<usize>,
+    /// Unwritten source bits of each under-constrained conversion, keyed by gate index
+    conversions: HashMap<usize, Vec<usize>>,
+    /// The under-constrained conversions each Z64 wire currently depends on
+    tainted: HashMap<usize, BTreeSet<usize>>,
+    found: Vec<UnderconstrainedConversion>,
+}
+
+impl AnalysisPass for UnderconstrainedConversions {
+    type Output = Vec<UnderconstrainedConversion>;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        match gate {
+            CombineOperation::GF2(gf2_insn) => self.written_bool.extend(gf2_insn.outputs()),
+            CombineOperation::Z64(Operation::AssertZero(w)) => {
+                if let Some(sources) = self.tainted.get(w) {
+                    for conversion in sources {
+                        self.found.push(UnderconstrainedConversion {
+                            conversion: *conversion,
+                            assertion: self.index,
+                            unwritten_bits: self.conversions[conversion].clone(),
+                        });
+                    }
+                }
+            }
+            CombineOperation::Z64(z64_insn) => {
+                let sources: BTreeSet<usize> = z64_insn
+                    .inputs()
+                    .filter_map(|w| self.tainted.get(&w))
+                    .flatten()
+                    .copied()
+                    .collect();
+                if let Some(dst) = z64_insn.dst() {
+                    if sources.is_empty() {
+                        self.tainted.remove(&dst);
+                    } else {
+                        self.tainted.insert(dst, sources);
+                    }
+                }
+            }
+            CombineOperation::B2A(dst, low) => {
+                let unwritten: Vec<usize> = (0..64)
+                    .filter(|bit| !self.written_bool.contains(&(low + bit)))
+                    .collect();
+                if unwritten.is_empty() {
+                    self.tainted.remove(dst);
+                } else {
+                    self.conversions.insert(self.index, unwritten);
+                    self.tainted
+                        .insert(*dst, std::iter::once(self.index).collect());
+                }
+            }
+            CombineOperation::SizeHint(_, _) => {}
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        self.found
+    }
+}
+
+/// A size hint that doesn't mean what it looks like it means. See the crate documentation for
+/// how size hints are read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeHintIssue {
+    /// A hint after the first gate. It still counts, but tools that only look at the first gate
+    /// will miss it.
+    Misplaced { gate: usize },
+    /// The largest hint for `domain` is smaller than the number of wires the program uses there.
+    /// `gate` is the first gate to use a wire past the hint.
+    TooSmall {
+        domain: Domain,
+        hinted: usize,
+        needed: usize,
+        gate: usize,
+    },
+    /// The largest hint for `domain` is bigger than the number of wires the program uses there, so
+    /// evaluators allocate more than they need
+    Oversized {
+        domain: Domain,
+        hinted: usize,
+        needed: usize,
+    },
+}
+
+/// Checks a program's size hints against the wires it actually uses.
+#[derive(Default)]
+pub struct SizeHintCheck {
+    index: usize,
+    /// Largest hint seen so far, as (Z64, GF2)
+    hinted: Option<(usize, usize)>,
+    /// One more than the largest wire used in each domain, as (Z64, GF2)
+    needed: (usize, usize),
+    /// Every gate that raised `needed` in each domain, with what it raised it to
+    raised_z64: Vec<(usize, usize)>,
+    raised_gf2: Vec<(usize, usize)>,
+    found: Vec<SizeHintIssue>,
+}
+
+impl AnalysisPass for SizeHintCheck {
+    type Output = Vec<SizeHintIssue>;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        let before = self.needed;
+        let (z64, gf2) = &mut self.needed;
+        match gate {
+            CombineOperation::GF2(op) => {
+                *gf2 = op
+                    .inputs()
+                    .chain(op.outputs())
+                    .fold(*gf2, |n, w| n.max(w + 1))
+            }
+            CombineOperation::Z64(op) => {
+                *z64 = op
+                    .inputs()
+                    .chain(op.outputs())
+                    .fold(*z64, |n, w| n.max(w + 1))
+            }
+            CombineOperation::B2A(dst, low) => {
+                *z64 = max(*z64, dst + 1);
+                *gf2 = max(*gf2, low + 64);
+            }
+            CombineOperation::SizeHint(hint_z64, hint_gf2) => {
+                if self.index > 0 {
+                    self.found
+                        .push(SizeHintIssue::Misplaced { gate: self.index });
+                }
+                let (z64, gf2) = self.hinted.unwrap_or((0, 0));
+                self.hinted = Some((max(z64, *hint_z64), max(gf2, *hint_gf2)));
+            }
+        }
+        if self.needed.0 > before.0 {
+            self.raised_z64.push((self.index, self.needed.0));
+        }
+        if self.needed.1 > before.1 {
+            self.raised_gf2.push((self.index, self.needed.1));
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(mut self) -> Self::Output {
+        if let Some((z64, gf2)) = self.hinted {
+            for (domain, hinted, needed, raised) in [
+                (Domain::Z64, z64, self.needed.0, &self.raised_z64),
+                (Domain::GF2, gf2, self.needed.1, &self.raised_gf2),
+            ] {
+                if hinted < needed {
+                    let (gate, _) = raised
+                        .iter()
+                        .find(|(_, needed)| *needed > hinted)
+                        .expect("Something raised the count past the hint");
+                    self.found.push(SizeHintIssue::TooSmall {
+                        domain,
+                        hinted,
+                        needed,
+                        gate: *gate,
+                    });
+                } else if hinted > needed {
+                    self.found.push(SizeHintIssue::Oversized {
+                        domain,
+                        hinted,
+                        needed,
+                    });
+                }
+            }
+        }
+        self.found
+    }
+}
+
+/// A wire index that's used heavily in both domains, near a conversion. Wires in different
+/// domains don't interfere, but a GF2 wire and a Z64 wire with the same index close to a B2A is
+/// what a translation that lost track of domains tends to leave behind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharedWire {
+    pub wire: usize,
+    /// Number of gates that use the wire in each domain
+    pub gf2_uses: usize,
+    pub z64_uses: usize,
+    /// Index of the first B2A gate the wire was used near, in either domain
+    pub conversion: usize,
+}
+
+/// The wires a program uses in each domain, from `DomainAudit`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DomainUsage {
+    pub gf2: BTreeSet<usize>,
+    pub z64: BTreeSet<usize>,
+    /// Shared wires that look like mistakes, in wire order
+    pub suspicious: Vec<SharedWire>,
+}
+
+impl DomainUsage {
+    /// Wire indices used in both domains.
+    pub fn shared(&self) -> impl Iterator<Item = usize> + '_ {
+        self.gf2.intersection(&self.z64).copied()
+    }
+}
+
+/// Collects the wires each domain uses, and flags indices that are used at least `min_uses` times
+/// in both domains, at least once within `window` gates of a conversion. A B2A uses its
+/// destination and its whole source window. `optimize::separate_domains` renumbers programs so
+/// no index is shared.
+pub struct DomainAudit {
+    min_uses: usize,
+    window: usize,
+    index: usize,
+    uses: HashMap<(Domain, usize), usize>,
+    /// The first conversion each wire was used near
+    near: HashMap<(Domain, usize), usize>,
+    /// Wires used by the last `window` gates, with the index of the gate
+    recent: VecDeque<(usize, Domain, usize)>,
+    last_conversion: Option<usize>,
+}
+
+impl DomainAudit {
+    pub fn new(min_uses: usize, window: usize) -> Self {
+        DomainAudit {
+            min_uses,
+            window,
+            index: 0,
+            uses: HashMap::new(),
+            near: HashMap::new(),
+            recent: VecDeque::new(),
+            last_conversion: None,
+        }
+    }
+}
+
+/// Flags wires used at least 4 times in each domain, within 4 gates of a conversion.
+impl Default for DomainAudit {
+    fn default() -> Self {
+        DomainAudit::new(4, 4)
+    }
+}
+
+impl AnalysisPass for DomainAudit {
+    type Output = DomainUsage;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        let used: Vec<(Domain, usize)> = match gate {
+            CombineOperation::GF2(op) => {
+                let wires: BTreeSet<usize> = op.inputs().chain(op.outputs()).collect();
+                wires.into_iter().map(|w| (Domain::GF2, w)).collect()
+            }
+            CombineOperation::Z64(op) => {
+                let wires: BTreeSet<usize> = op.inputs().chain(op.outputs()).collect();
+                wires.into_iter().map(|w| (Domain::Z64, w)).collect()
+            }
+            CombineOperation::B2A(dst, _) => std::iter::once((Domain::Z64, *dst))
+                .chain(gate.inputs().map(|w| (Domain::GF2, w)))
+                .collect(),
+            CombineOperation::SizeHint(_, _) => vec![],
+        };
+
+        while let Some((idx, _, _)) = self.recent.front() {
+            if idx + self.window >= self.index {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if let CombineOperation::B2A(_, _) = gate {
+            for (_, domain, wire) in &self.recent {
+                self.near.entry((*domain, *wire)).or_insert(self.index);
+            }
+            self.last_conversion = Some(self.index);
+        }
+        let near = self
+            .last_conversion
+            .filter(|conversion| conversion + self.window >= self.index);
+
+        for key in used {
+            *self.uses.entry(key).or_default() += 1;
+            if let Some(conversion) = near {
+                self.near.entry(key).or_insert(conversion);
+            }
+            if self.window > 0 {
+                self.recent.push_back((self.index, key.0, key.1));
+            }
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        let mut usage = DomainUsage::default();
+        for (domain, wire) in self.uses.keys() {
+            match domain {
+                Domain::GF2 => usage.gf2.insert(*wire),
+                Domain::Z64 => usage.z64.insert(*wire),
+            };
+        }
+
+        let shared: Vec<usize> = usage.shared().collect();
+        for wire in shared {
+            let (gf2, z64) = ((Domain::GF2, wire), (Domain::Z64, wire));
+            let (gf2_uses, z64_uses) = (self.uses[&gf2], self.uses[&z64]);
+            let conversion = match (self.near.get(&gf2), self.near.get(&z64)) {
+                (Some(a), Some(b)) => Some(*min(a, b)),
+                (a, b) => a.or(b).copied(),
+            };
+            if let Some(conversion) = conversion {
+                if gf2_uses >= self.min_uses && z64_uses >= self.min_uses {
+                    usage.suspicious.push(SharedWire {
+                        wire,
+                        gf2_uses,
+                        z64_uses,
+                        conversion,
+                    });
+                }
+            }
+        }
+        usage
+    }
+}
+
+/// The gates that use a wire, from `Liveness`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiveRange {
+    /// Index of the first gate that reads or writes the wire
+    pub first: usize,
+    /// Index of the last gate that reads or writes it
+    pub last: usize,
+    /// Whether the first gate reads the wire before anything has written it, so it reads as zero
+    pub read_first: bool,
+}
+
+/// The live range of every wire a program uses, in each domain.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WireLiveness {
+    pub gf2: HashMap<usize, LiveRange>,
+    pub z64: HashMap<usize, LiveRange>,
+}
+
+impl WireLiveness {
+    pub fn get(&self, domain: Domain, wire: usize) -> Option<&LiveRange> {
+        match domain {
+            Domain::GF2 => self.gf2.get(&wire),
+            Domain::Z64 => self.z64.get(&wire),
+        }
+    }
+
+    /// The most wires of `domain` live during any one gate. Renumbering that gives every wire a
+    /// single index for its whole live range, like `optimize::reuse_wires`, can't use fewer.
+    pub fn peak(&self, domain: Domain) -> usize {
+        let ranges = match domain {
+            Domain::GF2 => &self.gf2,
+            Domain::Z64 => &self.z64,
+        };
+        // Ends sort before starts at the same gate, since a range ending at `last` is over by
+        // `last + 1`
+        let mut events: Vec<(usize, bool)> = ranges
+            .values()
+            .flat_map(|range| [(range.first, true), (range.last + 1, false)])
+            .collect();
+        events.sort_unstable();
+        let (mut live, mut peak) = (0, 0);
+        for (_, starts) in events {
+            if starts {
+                live += 1;
+                peak = max(peak, live);
+            } else {
+                live -= 1;
+            }
+        }
+        peak
+    }
+}
+
+/// Finds the first and last gate to use every wire. A B2A uses its destination and its whole
+/// source window. Wires written more than once have one range covering every write.
+#[derive(Default)]
+pub struct Liveness {
+    index: usize,
+    ranges: WireLiveness,
+}
+
+impl Liveness {
+    fn touch(&mut self, domain: Domain, wire: usize, read: bool) {
+        let index = self.index;
+        let ranges = match domain {
+            Domain::GF2 => &mut self.ranges.gf2,
+            Domain::Z64 => &mut self.ranges.z64,
+        };
+        ranges
+            .entry(wire)
+            .and_modify(|range| range.last = index)
+            .or_insert(LiveRange {
+                first: index,
+                last: index,
+                read_first: read,
+            });
+    }
+}
+
+impl AnalysisPass for Liveness {
+    type Output = WireLiveness;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        // Gates read before they write, so a gate that reads its own destination reads it first
+        if let Some(domain) = gate.input_domain() {
+            for wire in gate.inputs() {
+                self.touch(domain, wire, true);
+            }
+        }
+        if let Some(domain) = gate.output_domain() {
+            for wire in gate.outputs() {
+                self.touch(domain, wire, false);
+            }
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        self.ranges
+    }
+}
+
+/// Communication rounds needed by one segment of a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentRounds {
+    /// Indices of the gates in the segment
+    pub gates: Range<usize>,
+    /// Multiplicative depth of the segment
+    pub rounds: usize,
+    /// Indices of the gates along a deepest chain of dependencies, first to last. Empty if the
+    /// segment has no multiplications.
+    pub critical_path: Vec<usize>,
+}
+
+/// Estimates communication rounds for protocols where each multiplication of two wires costs a
+/// round and everything else (including constant multiplications and conversions) is local. A
+/// segment is evaluated after everything before it, so wires from earlier segments are available
+/// from its first round. By default the whole program is one segment.
+#[derive(Default)]
+pub struct Rounds {
+    /// Where each segment after the current one starts
+    boundaries: VecDeque<usize>,
+    index: usize,
+    segment_start: usize,
+    /// Rounds before each wire written in this segment is ready, and the gate that wrote it
+    ready: HashMap<(Domain, usize), (usize, usize)>,
+    /// Rounds for each gate in this segment, and the gate that wrote its latest input
+    gates: Vec<(usize, Option<usize>)>,
+    /// The first gate in this segment to reach the most rounds
+    deepest: Option<usize>,
+    segments: Vec<SegmentRounds>,
+}
+
+impl Rounds {
+    /// Splits the program into segments starting at each of `boundaries` (as well as at the first
+    /// gate).
+    pub fn with_segments(boundaries: impl IntoIterator<Item = usize>) -> Self {
+        let mut boundaries: Vec<usize> = boundaries.into_iter().filter(|b| *b > 0).collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        Rounds {
+            boundaries: boundaries.into(),
+            ..Default::default()
+        }
+    }
+
+    fn rounds_of(&self, gate: usize) -> usize {
+        self.gates[gate - self.segment_start].0
+    }
+
+    fn finish_segment(&mut self) {
+        let rounds = self.deepest.map_or(0, |gate| self.rounds_of(gate));
+        let mut critical_path = Vec::new();
+        if rounds > 0 {
+            let mut next = self.deepest;
+            while let Some(gate) = next {
+                critical_path.push(gate);
+                next = self.gates[gate - self.segment_start].1;
+            }
+            critical_path.reverse();
+        }
+
+        self.segments.push(SegmentRounds {
+            gates: self.segment_start..self.index,
+            rounds,
+            critical_path,
+        });
+        self.segment_start = self.index;
+        self.ready.clear();
+        self.gates.clear();
+        self.deepest = None;
+    }
+}
+
+impl AnalysisPass for Rounds {
+    type Output = Vec<SegmentRounds>;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        if self.boundaries.front() == Some(&self.index) {
+            self.boundaries.pop_front();
+            self.finish_segment();
+        }
+
+        let (mut rounds, mut latest) = (0, None);
+        if let Some(domain) = gate.input_domain() {
+            for (ready, writer) in gate.inputs().filter_map(|w| self.ready.get(&(domain, w))) {
+                if latest.is_none() || *ready > rounds {
+                    rounds = *ready;
+                    latest = Some(*writer);
+                }
+            }
+        }
+        if let CombineOperation::GF2(Operation::Mul(_, _, _))
+        | CombineOperation::Z64(Operation::Mul(_, _, _)) = gate
+        {
+            rounds += 1;
+        }
+
+        if let Some(domain) = gate.output_domain() {
+            for wire in gate.outputs() {
+                self.ready.insert((domain, wire), (rounds, self.index));
+            }
+        }
+        self.gates.push((rounds, latest));
+        if self
+            .deepest
+            .map_or(rounds > 0, |d| rounds > self.rounds_of(d))
+        {
+            self.deepest = Some(self.index);
+        }
+        self.index += 1;
+    }
+
+    fn finish_analysis(mut self) -> Self::Output {
+        // Boundaries past the end of the program would only add empty segments
+        self.finish_segment();
+        self.segments
+    }
+}
+
+/// Counts of the gates of one domain, grouped by what they cost a prover.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainStats {
+    pub inputs: usize,
+    pub randoms: usize,
+    /// `Add`, `Sub`, `AddConst` and `SubConst` gates
+    pub additions: usize,
+    /// `Mul` gates. Multiplications by a constant are counted separately, since they're linear.
+    pub multiplications: usize,
+    pub constant_multiplications: usize,
+    pub constants: usize,
+    pub assertions: usize,
+}
+
+impl DomainStats {
+    pub fn gates(&self) -> usize {
+        self.inputs
+            + self.randoms
+            + self.additions
+            + self.multiplications
+            + self.constant_multiplications
+            + self.constants
+            + self.assertions
+    }
+
+    fn count<T: WireValue>(&mut self, op: &Operation<T>) {
+        let count = match op {
+            Operation::Input(_) => &mut self.inputs,
+            Operation::Random(_) => &mut self.randoms,
+            Operation::Add(_, _, _)
+            | Operation::AddConst(_, _, _)
+            | Operation::Sub(_, _, _)
+            | Operation::SubConst(_, _, _) => &mut self.additions,
+            Operation::Mul(_, _, _) => &mut self.multiplications,
+            Operation::MulConst(_, _, _) => &mut self.constant_multiplications,
+            Operation::Const(_, _) => &mut self.constants,
+            Operation::AssertZero(_) => &mut self.assertions,
+        };
+        *count += 1;
+    }
+
+    fn cost(&self, weights: &DomainCosts) -> f64 {
+        self.inputs as f64 * weights.input
+            + self.randoms as f64 * weights.random
+            + self.additions as f64 * weights.addition
+            + self.multiplications as f64 * weights.multiplication
+            + self.constant_multiplications as f64 * weights.constant_multiplication
+            + self.constants as f64 * weights.constant
+            + self.assertions as f64 * weights.assertion
+    }
+}
+
+/// Counts the gates of a program by domain and by kind, for predicting what proving it will cost
+/// without running a prover. The pass is its own output.
+///
+/// ```
+/// use mcircuit::analysis::{AnalysisPass, CostModel, GateStats};
+/// use mcircuit::{CombineOperation, Operation};
+///
+/// let program = [
+///     CombineOperation::GF2(Operation::Input(0)),
+///     CombineOperation::GF2(Operation::Mul(1, 0, 0)),
+///     CombineOperation::GF2(Operation::AssertZero(1)),
+/// ];
+/// let stats = GateStats::analyze(program.iter());
+/// assert_eq!(stats.gf2.multiplications, 1);
+/// assert_eq!(stats.cost(&CostModel::default()), 3.0);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GateStats {
+    pub gf2: DomainStats,
+    pub z64: DomainStats,
+    /// B2A gates
+    pub conversions: usize,
+    pub size_hints: usize,
+}
+
+impl GateStats {
+    /// Number of gates counted, size hints included.
+    pub fn gates(&self) -> usize {
+        self.gf2.gates() + self.z64.gates() + self.conversions + self.size_hints
+    }
+
+    /// Multiplications of two wires, in both domains.
+    pub fn multiplications(&self) -> usize {
+        self.gf2.multiplications + self.z64.multiplications
+    }
+
+    /// Estimated cost of proving the program, as the sum of each gate's weight in `model`.
+    pub fn cost(&self, model: &CostModel) -> f64 {
+        self.gf2.cost(&model.gf2)
+            + self.z64.cost(&model.z64)
+            + self.conversions as f64 * model.conversion
+    }
+}
+
+impl AnalysisPass for GateStats {
+    type Output = GateStats;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        match gate {
+            CombineOperation::GF2(op) => self.gf2.count(op),
+            CombineOperation::Z64(op) => self.z64.count(op),
+            CombineOperation::B2A(_, _) => self.conversions += 1,
+            CombineOperation::SizeHint(_, _) => self.size_hints += 1,
+        }
+    }
+
+    fn finish_analysis(self) -> Self::Output {
+        self
+    }
+}
+
+/// What each kind of gate of one domain costs, in a `CostModel`'s units.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DomainCosts {
+    pub input: f64,
+    pub random: f64,
+    pub addition: f64,
+    pub multiplication: f64,
+    pub constant_multiplication: f64,
+    pub constant: f64,
+    pub assertion: f64,
+}
+
+impl DomainCosts {
+    /// Weights for a prover that only pays for nonlinear gates and for getting values in and out
+    /// of the proof, `width` bits at a time.
+    fn nonlinear(width: f64) -> Self {
+        DomainCosts {
+            input: width,
+            random: 0.0,
+            addition: 0.0,
+            multiplication: width,
+            constant_multiplication: 0.0,
+            constant: 0.0,
+            assertion: width,
+        }
+    }
+}
+
+/// Weights for `GateStats::cost`. The default approximates the proof size of an MPC-in-the-head
+/// prover like Reverie, in bits per repetition: each multiplication, input and assertion costs
+/// one element of its domain, linear gates are free, and a B2A conversion costs about what the
+/// 64-bit addition circuit that checks it does. Absolute numbers depend on the prover's
+/// parameters, so costs are only meaningful relative to each other; tune the weights to match
+/// measurements where that matters.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CostModel {
+    pub gf2: DomainCosts,
+    pub z64: DomainCosts,
+    pub conversion: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            gf2: DomainCosts::nonlinear(1.0),
+            z64: DomainCosts::nonlinear(64.0),
+            conversion: 128.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::{
+        AnalysisPass, BackwardAnalysisPass, CostModel, DomainAudit, DomainUsage, GateStats,
+        LiveGates, LiveRange, Liveness, Rounds, SegmentRounds, SharedWire, SizeHintCheck,
+        SizeHintIssue, TopoSort, TopoSortError, UnderconstrainedConversion,
+        UnderconstrainedConversions, UnwrittenRead, UnwrittenReads, WireCounter,
+    };
+    use crate::exporters::{Summary, SummaryPass};
+    use crate::{CombineOperation, Domain, Operation};
+
+    #[test]
+    fn test_unwritten_reads() {
+        let program = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Add(2, 0, 1)),
+            CombineOperation::GF2(Operation::Mul(3, 1, 2)),
+            CombineOperation::Z64(Operation::AddConst(0, 0, 1)),
+        ];
+
+        assert_eq!(
+            UnwrittenReads::analyze(program.iter()),
+            vec![
+                UnwrittenRead {
+                    gate: 1,
+                    domain: Domain::GF2,
+                    wire: 1
+                },
+                UnwrittenRead {
+                    gate: 3,
+                    domain: Domain::Z64,
+                    wire: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_liveness() {
+        let program = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+            CombineOperation::GF2(Operation::Mul(2, 1, 3)),
+            CombineOperation::GF2(Operation::AssertZero(2)),
+            CombineOperation::B2A(0, 1),
+            CombineOperation::Z64(Operation::AssertZero(0)),
+        ];
+        let liveness = Liveness::analyze(program.iter());
+        let range = |first, last, read_first| LiveRange {
+            first,
+            last,
+            read_first,
+        };
+        assert_eq!(liveness.gf2[&0], range(0, 1, false));
+        assert_eq!(liveness.gf2[&1], range(1, 4, false));
+        assert_eq!(liveness.gf2[&3], range(2, 4, true));
+        assert_eq!(liveness.get(Domain::GF2, 64), Some(&range(4, 4, true)));
+        assert_eq!(liveness.get(Domain::Z64, 0), Some(&range(4, 5, false)));
+        assert_eq!(liveness.get(Domain::Z64, 1), None);
+        // Just the conversion's window, which covers every other wire still live
+        assert_eq!(liveness.peak(Domain::GF2), 64);
+        assert_eq!(liveness.peak(Domain::Z64), 1);
+    }
+
+    #[test]
+    fn test_domain_audit() {
+        let mut program = vec![CombineOperation::GF2(Operation::Input(0))];
+        // GF2 wire 1 is busy right before the conversion, and Z64 wire 1 right after it
+        for _ in 0..4 {
+            program.push(CombineOperation::GF2(Operation::AddConst(1, 0, true)));
+        }
+        program.push(CombineOperation::B2A(2, 64));
+        for _ in 0..4 {
+            program.push(CombineOperation::Z64(Operation::AddConst(1, 2, 1)));
+        }
+        // Z64 wire 70 is shared with the conversion's window, but hardly used
+        program.push(CombineOperation::Z64(Operation::AddConst(70, 1, 1)));
+
+        let usage = DomainAudit::analyze(program.iter());
+        assert_eq!(usage.gf2, (0..2).chain(64..128).collect());
+        assert_eq!(usage.z64, [1, 2, 70].iter().copied().collect());
+        assert_eq!(usage.shared().collect::<Vec<_>>(), [1, 70]);
+        assert_eq!(
+            usage.suspicious,
+            [SharedWire {
+                wire: 1,
+                gf2_uses: 4,
+                z64_uses: 5,
+                conversion: 5
+            }]
+        );
+
+        // Only the uses right next to the conversion matter
+        let usage = DomainAudit::new(4, 2).run(program.iter());
+        assert_eq!(usage.suspicious.len(), 1);
+        let usage = DomainAudit::new(4, 0).run(program.iter());
+        assert_eq!(
+            usage,
+            DomainUsage {
+                suspicious: vec![],
+                ..usage.clone()
+            }
+        );
+    }
+
+    #[test]
+    fn test_topo_sort() {
+        // Both multiplications come before what they read
+        let program = [
+            CombineOperation::SizeHint(3, 2),
+            CombineOperation::GF2(Operation::Mul(1, 0, 0)),
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Mul(2, 1, 0)),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Input(1)),
+            CombineOperation::GF2(Operation::AssertZero(1)),
+        ];
+        assert_eq!(
+            TopoSort::analyze(program.iter()),
+            Err(TopoSortError {
+                out_of_order: vec![
+                    UnwrittenRead {
+                        gate: 1,
+                        domain: Domain::GF2,
+                        wire: 0
+                    },
+                    UnwrittenRead {
+                        gate: 3,
+                        domain: Domain::Z64,
+                        wire: 1
+                    },
+                ],
+                ..Default::default()
+            })
+        );
+
+        // Inputs stay in order within their domain
+        let sorted = TopoSort::sort(&program).unwrap();
+        let order = [0, 2, 4, 1, 5, 3, 6];
+        assert_eq!(
+            sorted,
+            order.iter().map(|idx| program[*idx]).collect::<Vec<_>>()
+        );
+        assert_eq!(TopoSort::analyze(sorted.iter()), Ok(()));
+        assert_eq!(TopoSort::sort(&sorted).unwrap(), sorted);
+    }
+
+    #[test]
+    fn test_topo_sort_errors() {
+        let program = [
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Add(1, 0, 2)),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 3)),
+        ];
+        let error = TopoSort::sort(&program).unwrap_err();
+        assert_eq!(
+            error.undefined,
+            [UnwrittenRead {
+                gate: 1,
+                domain: Domain::Z64,
+                wire: 2
+            }]
+        );
+        assert_eq!(error.redefined, [(Domain::Z64, 1, vec![1, 2])]);
+
+        let program = [
+            CombineOperation::Z64(Operation::AddConst(0, 1, 1)),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 1)),
+            CombineOperation::Z64(Operation::Input(2)),
+        ];
+        assert_eq!(
+            TopoSort::sort(&program),
+            Err(TopoSortError {
+                out_of_order: vec![UnwrittenRead {
+                    gate: 0,
+                    domain: Domain::Z64,
+                    wire: 1
+                }],
+                cycle: vec![0, 1],
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_live_gates() {
+        let program = [
+            CombineOperation::SizeHint(2, 3),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            // Overwritten before it's read
+            CombineOperation::GF2(Operation::Const(2, true)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            // Never read
+            CombineOperation::Z64(Operation::Const(0, 5)),
+            CombineOperation::GF2(Operation::AssertZero(2)),
+        ];
+
+        assert_eq!(
+            LiveGates::analyze(program.iter()),
+            vec![true, true, true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn test_underconstrained_conversions() {
+        let mut program: Vec<CombineOperation> = (0..64)
+            .filter(|w| *w != 3 && *w != 40)
+            .map(|w| CombineOperation::GF2(Operation::Input(w)))
+            .collect();
+        program.extend(vec![
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 7)),
+            CombineOperation::Z64(Operation::AssertZero(1)),
+            // Never asserted on, so not flagged
+            CombineOperation::B2A(2, 0),
+            // Fully written by now, so not flagged either
+            CombineOperation::GF2(Operation::Const(3, false)),
+            CombineOperation::GF2(Operation::Const(40, true)),
+            CombineOperation::B2A(3, 0),
+            CombineOperation::Z64(Operation::AssertZero(3)),
+            // Overwriting the tainted wire clears it
+            CombineOperation::Z64(Operation::Const(1, 0)),
+            CombineOperation::Z64(Operation::AssertZero(1)),
+        ]);
+
+        assert_eq!(
+            UnderconstrainedConversions::analyze(program.iter()),
+            vec![UnderconstrainedConversion {
+                conversion: 62,
+                assertion: 64,
+                unwritten_bits: vec![3, 40],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_size_hint_check() {
+        let program = [
+            CombineOperation::SizeHint(1, 2),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(3)),
+            CombineOperation::SizeHint(0, 3),
+            CombineOperation::Z64(Operation::Input(0)),
+        ];
+        assert_eq!(
+            SizeHintCheck::analyze(program.iter()),
+            vec![
+                SizeHintIssue::Misplaced { gate: 3 },
+                SizeHintIssue::TooSmall {
+                    domain: Domain::GF2,
+                    hinted: 3,
+                    needed: 4,
+                    gate: 2,
+                },
+            ]
+        );
+        assert!(SizeHintCheck::analyze(program[1..3].iter()).is_empty());
+
+        // Only the hint gets bigger
+        let stale = [
+            CombineOperation::SizeHint(8, 4),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Input(3)),
+        ];
+        assert_eq!(
+            SizeHintCheck::analyze(stale.iter()),
+            vec![SizeHintIssue::Oversized {
+                domain: Domain::Z64,
+                hinted: 8,
+                needed: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_rounds() {
+        let program = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            // Independent of the first multiplication, so it happens in the same round
+            CombineOperation::GF2(Operation::Mul(3, 1, 1)),
+            CombineOperation::GF2(Operation::AddConst(4, 2, true)),
+            CombineOperation::GF2(Operation::Mul(5, 4, 3)),
+            CombineOperation::GF2(Operation::MulConst(6, 5, true)),
+            CombineOperation::GF2(Operation::Mul(7, 6, 0)),
+            CombineOperation::GF2(Operation::AssertZero(7)),
+        ];
+
+        assert_eq!(
+            Rounds::analyze(program.iter()),
+            vec![SegmentRounds {
+                gates: 0..9,
+                rounds: 3,
+                critical_path: vec![0, 2, 4, 5, 6, 7],
+            }]
+        );
+
+        let segments = Rounds::with_segments([5, 20]).run(program.iter());
+        assert_eq!(
+            segments.iter().map(|s| s.rounds).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(segments[1].gates, 5..9);
+        assert_eq!(segments[1].critical_path, vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn test_fused_passes() {
+        let program = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            CombineOperation::GF2(Operation::Mul(3, 2, 4)),
+            CombineOperation::GF2(Operation::AssertZero(3)),
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 1)),
+        ];
+
+        let mut walked = 0;
+        let (wires, rounds, reads, summary) =
+            <(WireCounter, Rounds, UnwrittenReads, SummaryPass)>::analyze(
+                program.iter().inspect(|_| walked += 1),
+            );
+        assert_eq!(walked, program.len());
+        assert_eq!(wires, WireCounter::analyze(program.iter()));
+        assert_eq!(rounds, Rounds::analyze(program.iter()));
+        assert_eq!(reads, UnwrittenReads::analyze(program.iter()));
+        assert_eq!(summary, Summary::of(&program));
+
+        let segmented = vec![Rounds::with_segments([3]), Rounds::default()].run(program.iter());
+        assert_eq!(segmented[0].len(), 2);
+        assert_eq!(segmented[1], rounds);
+
+        let (live, live_again) = <(LiveGates, LiveGates)>::analyze(program.iter());
+        assert_eq!(live, LiveGates::analyze(program.iter()));
+        assert_eq!(live, live_again);
+    }
+
+    #[test]
+    fn test_gate_stats() {
+        let program = [
+            CombineOperation::SizeHint(2, 64),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Mul(1, 0, 0)),
+            CombineOperation::GF2(Operation::MulConst(2, 1, true)),
+            CombineOperation::GF2(Operation::Add(3, 2, 0)),
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::Input(1)),
+            CombineOperation::Z64(Operation::Mul(1, 0, 1)),
+            CombineOperation::Z64(Operation::SubConst(1, 1, 3)),
+            CombineOperation::Z64(Operation::AssertZero(1)),
+        ];
+        let stats = GateStats::analyze(program.iter());
+        assert_eq!(stats.gates(), program.len());
+        assert_eq!(stats.multiplications(), 2);
+        assert_eq!(
+            (stats.gf2.constant_multiplications, stats.gf2.additions),
+            (1, 1)
+        );
+        assert_eq!((stats.z64.inputs, stats.z64.assertions), (1, 1));
+        assert_eq!((stats.conversions, stats.size_hints), (1, 1));
+
+        // GF2 input and multiplication, Z64 input, multiplication and assertion, and B2A
+        let model = CostModel::default();
+        assert_eq!(stats.cost(&model), 2.0 + 3.0 * 64.0 + 128.0);
+        let mut multiplications_only = model;
+        multiplications_only.gf2.input = 0.0;
+        multiplications_only.z64.input = 0.0;
+        multiplications_only.z64.assertion = 0.0;
+        multiplications_only.conversion = 0.0;
+        assert_eq!(stats.cost(&multiplications_only), 65.0);
+
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(serde_json::from_str::<GateStats>(&json).unwrap(), stats);
+    }
+}
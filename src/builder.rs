@@ -0,0 +1,636 @@
+//! Building circuits in code, without numbering wires by hand.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::gadgets::rom::{self, Rom, RomError};
+use crate::gadgets::{bits, decompose, GateSink};
+use crate::optimize::refresh_size_hints;
+use crate::{Bus, CombineOperation, ConstVector, Domain, Generator, Operation, Program, WireValue};
+
+/// A wire allocated by a `CircuitBuilder`, carrying its domain in its type so gates can't mix
+/// them up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Wire<T> {
+    pub index: usize,
+    value: PhantomData<T>,
+}
+
+impl<T> Wire<T> {
+    /// Refers to a wire by index, for wires allocated some other way (like through `GateSink`).
+    pub fn new(index: usize) -> Self {
+        Wire {
+            index,
+            value: PhantomData,
+        }
+    }
+}
+
+/// Operands of a bus operation that don't fit together. Only a checked builder reports these.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusError {
+    WidthMismatch {
+        op: &'static str,
+        left: String,
+        left_width: usize,
+        right: String,
+        right_width: usize,
+    },
+    DomainMismatch {
+        op: &'static str,
+        left: String,
+        left_domain: Domain,
+        right: String,
+        right_domain: Domain,
+    },
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusError::WidthMismatch {
+                op,
+                left,
+                left_width,
+                right,
+                right_width,
+            } => write!(
+                f,
+                "Can't {} {}-bit bus {} and {}-bit bus {}",
+                op, left_width, left, right_width, right
+            ),
+            BusError::DomainMismatch {
+                op,
+                left,
+                left_domain,
+                right,
+                right_domain,
+            } => write!(
+                f,
+                "Can't {} {:?} bus {} and {:?} bus {}",
+                op, left_domain, left, right_domain, right
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BusError {}
+
+/// Builds the gate a bus operation applies to `(dst, a, b)`, in whichever domain it turns out to
+/// need.
+type BusGate = fn(usize, usize, usize) -> (Operation<bool>, Operation<u64>);
+
+/// Allocates wires and collects the gates that write them. Every method that computes something
+/// writes it to a fresh wire, so wires are never reassigned. `finish` returns the program with a
+/// size hint.
+///
+/// Buses group wires under a name, and operate on them a wire at a time. A builder made with
+/// `checked` makes sure both operands of a bus operation have the same domain and width;
+/// otherwise the operation pairs up wires until the narrower bus runs out, and emits gates in the
+/// left operand's domain.
+///
+/// ```
+/// use mcircuit::{evaluate_composite_program, CircuitBuilder};
+///
+/// let mut builder = CircuitBuilder::new();
+/// let x = builder.input::<u64>();
+/// let square = builder.mul(x, x);
+/// let check = builder.sub_const(square, 49);
+/// builder.assert_zero(check);
+/// evaluate_composite_program(&builder.finish(), &[], &[7]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CircuitBuilder {
+    gates: Vec<CombineOperation>,
+    next_bool: usize,
+    next_arith: usize,
+    buses: Vec<Bus>,
+    const_vectors: Vec<ConstVector>,
+    checked: bool,
+}
+
+impl CircuitBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A builder that rejects bus operations on mismatched buses, instead of building something
+    /// that fails (or worse, doesn't) when it's evaluated.
+    pub fn checked() -> Self {
+        CircuitBuilder {
+            checked: true,
+            ..Self::default()
+        }
+    }
+
+    /// Allocates `count` consecutive wires without writing them.
+    pub fn wires<T: WireValue>(&mut self, count: usize) -> Vec<Wire<T>> {
+        let low = self.fresh_wires(T::DOMAIN, count);
+        (low..low + count).map(Wire::new).collect()
+    }
+
+    /// Allocates a wire and writes it with the gate `op` builds.
+    pub(crate) fn write<T: WireValue>(&mut self, op: impl FnOnce(usize) -> Operation<T>) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        let dst = self.fresh_wire(T::DOMAIN);
+        self.gates.push(op(dst).into());
+        Wire::new(dst)
+    }
+
+    pub fn input<T: WireValue>(&mut self) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(Operation::Input)
+    }
+
+    /// `count` inputs on consecutive wires, read in order.
+    pub fn inputs<T: WireValue>(&mut self, count: usize) -> Vec<Wire<T>>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        let wires = self.wires(count);
+        for wire in &wires {
+            self.gates.push(Operation::Input(wire.index).into());
+        }
+        wires
+    }
+
+    pub fn random<T: WireValue>(&mut self) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(Operation::Random)
+    }
+
+    pub fn constant<T: WireValue>(&mut self, value: T) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(|dst| Operation::Const(dst, value))
+    }
+
+    pub fn add<T: WireValue>(&mut self, a: Wire<T>, b: Wire<T>) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(|dst| Operation::Add(dst, a.index, b.index))
+    }
+
+    pub fn sub<T: WireValue>(&mut self, a: Wire<T>, b: Wire<T>) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(|dst| Operation::Sub(dst, a.index, b.index))
+    }
+
+    pub fn mul<T: WireValue>(&mut self, a: Wire<T>, b: Wire<T>) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(|dst| Operation::Mul(dst, a.index, b.index))
+    }
+
+    pub fn add_const<T: WireValue>(&mut self, a: Wire<T>, c: T) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(|dst| Operation::AddConst(dst, a.index, c))
+    }
+
+    pub fn sub_const<T: WireValue>(&mut self, a: Wire<T>, c: T) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(|dst| Operation::SubConst(dst, a.index, c))
+    }
+
+    pub fn mul_const<T: WireValue>(&mut self, a: Wire<T>, c: T) -> Wire<T>
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.write(|dst| Operation::MulConst(dst, a.index, c))
+    }
+
+    pub fn assert_zero<T: WireValue>(&mut self, wire: Wire<T>)
+    where
+        CombineOperation: From<Operation<T>>,
+    {
+        self.gates.push(Operation::AssertZero(wire.index).into());
+    }
+
+    /// Asserts that a GF2 wire is set, by asserting that its inverse is zero.
+    pub fn assert_true(&mut self, wire: Wire<bool>) {
+        bits::assert_true(self, wire.index);
+    }
+
+    /// Asserts that a Z64 wire isn't zero. The prover supplies the wire's 64 bits as GF2 inputs,
+    /// least significant first, read where this is called; see `decompose::assert_nonzero` for
+    /// the constraints, and for why there's no cheaper inverse-based check over Z64.
+    pub fn assert_nonzero(&mut self, wire: Wire<u64>) {
+        decompose::assert_nonzero(self, wire.index, None);
+    }
+
+    /// Converts 64 bits, least significant first, to a Z64 value. B2A reads consecutive wires, so
+    /// if `bits` aren't, they're copied to ones that are first.
+    pub fn b2a(&mut self, bits: &[Wire<bool>]) -> Wire<u64> {
+        assert_eq!(bits.len(), 64, "B2A converts exactly 64 bits");
+        let consecutive = bits
+            .windows(2)
+            .all(|pair| pair[1].index == pair[0].index + 1);
+        let low = if consecutive {
+            bits[0].index
+        } else {
+            let copies = self.wires::<bool>(64);
+            for (copy, bit) in copies.iter().zip(bits) {
+                self.gates.push(CombineOperation::GF2(Operation::AddConst(
+                    copy.index, bit.index, false,
+                )));
+            }
+            copies[0].index
+        };
+        let dst = self.fresh_wire(Domain::Z64);
+        self.gates.push(CombineOperation::B2A(dst, low));
+        Wire::new(dst)
+    }
+
+    /// GF2 constants on consecutive wires, least significant first. They're kept packed rather
+    /// than as a gate per bit: `finish_program` keeps them that way in `Program::const_vectors`,
+    /// and `finish` turns them into `Const` gates at the front.
+    pub fn constant_bits(&mut self, bits: &[bool]) -> Vec<Wire<bool>> {
+        let wires = self.wires::<bool>(bits.len());
+        if let Some(first) = wires.first() {
+            self.const_vectors
+                .push(ConstVector::from_bits(first.index, bits));
+        }
+        wires
+    }
+
+    /// A bus holding a constant written in hex, like a hash IV, four bits per digit. See
+    /// `ConstVector::from_hex` for the format, and `constant_bits` for how it's stored. Panics if
+    /// `hex` isn't a hex number.
+    pub fn constant_hex(&mut self, name: &str, hex: &str) -> Bus {
+        let low = self.next_bool;
+        let vector = ConstVector::from_hex(low, hex)
+            .unwrap_or_else(|| panic!("{:?} is not a hex number", hex));
+        self.next_bool += vector.len;
+        let wires = vector.wires().collect();
+        self.const_vectors.push(vector);
+        self.bus(name, Domain::GF2, wires)
+    }
+
+    /// A bus holding the word of `rom` at `address`, which has to be a GF2 bus wide enough to
+    /// address every word. Addresses past the end of the image read zero.
+    pub fn rom(&mut self, name: &str, rom: &Rom, address: &Bus) -> Result<Bus, RomError> {
+        if address.domain != Domain::GF2 {
+            return Err(RomError::AddressDomain {
+                bus: address.name.clone(),
+                domain: address.domain,
+            });
+        }
+        let wires = rom::read(self, rom, &address.wires)?;
+        Ok(self.bus(name, Domain::GF2, wires))
+    }
+
+    /// Names `wires` as a bus, so bus operations can use them. The bus is also kept for
+    /// `finish_program`.
+    pub fn bus(&mut self, name: &str, domain: Domain, wires: Vec<usize>) -> Bus {
+        let bus = Bus {
+            name: name.to_string(),
+            domain,
+            wires,
+        };
+        self.buses.push(bus.clone());
+        bus
+    }
+
+    /// A bus of `width` inputs on consecutive wires, read least significant first.
+    pub fn input_bus(&mut self, name: &str, domain: Domain, width: usize) -> Bus {
+        let wires = match domain {
+            Domain::GF2 => self.inputs::<bool>(width).iter().map(|w| w.index).collect(),
+            Domain::Z64 => self.inputs::<u64>(width).iter().map(|w| w.index).collect(),
+        };
+        self.bus(name, domain, wires)
+    }
+
+    /// Applies `op` to each pair of wires from `a` and `b`, collecting the results in a new bus.
+    fn zip_buses(
+        &mut self,
+        op: &'static str,
+        name: &str,
+        a: &Bus,
+        b: &Bus,
+        gate: BusGate,
+    ) -> Result<Bus, BusError> {
+        if self.checked && a.domain != b.domain {
+            return Err(BusError::DomainMismatch {
+                op,
+                left: a.name.clone(),
+                left_domain: a.domain,
+                right: b.name.clone(),
+                right_domain: b.domain,
+            });
+        }
+        if self.checked && a.wires.len() != b.wires.len() {
+            return Err(BusError::WidthMismatch {
+                op,
+                left: a.name.clone(),
+                left_width: a.wires.len(),
+                right: b.name.clone(),
+                right_width: b.wires.len(),
+            });
+        }
+        let wires = a
+            .wires
+            .iter()
+            .zip(&b.wires)
+            .map(|(x, y)| {
+                let dst = self.fresh_wire(a.domain);
+                let (gf2, z64) = gate(dst, *x, *y);
+                self.gates.push(match a.domain {
+                    Domain::GF2 => CombineOperation::GF2(gf2),
+                    Domain::Z64 => CombineOperation::Z64(z64),
+                });
+                dst
+            })
+            .collect();
+        Ok(self.bus(name, a.domain, wires))
+    }
+
+    /// `a + b`, a wire at a time (so XOR for GF2 buses, without carries).
+    pub fn add_buses(&mut self, name: &str, a: &Bus, b: &Bus) -> Result<Bus, BusError> {
+        self.zip_buses("add", name, a, b, |dst, x, y| {
+            (Operation::Add(dst, x, y), Operation::Add(dst, x, y))
+        })
+    }
+
+    /// `a - b`, a wire at a time.
+    pub fn sub_buses(&mut self, name: &str, a: &Bus, b: &Bus) -> Result<Bus, BusError> {
+        self.zip_buses("subtract", name, a, b, |dst, x, y| {
+            (Operation::Sub(dst, x, y), Operation::Sub(dst, x, y))
+        })
+    }
+
+    /// `a * b`, a wire at a time (so AND for GF2 buses).
+    pub fn mul_buses(&mut self, name: &str, a: &Bus, b: &Bus) -> Result<Bus, BusError> {
+        self.zip_buses("multiply", name, a, b, |dst, x, y| {
+            (Operation::Mul(dst, x, y), Operation::Mul(dst, x, y))
+        })
+    }
+
+    /// The buses named so far, in the order they were named.
+    pub fn buses(&self) -> &[Bus] {
+        &self.buses
+    }
+
+    /// The gates so far, without a size hint or the constants from `constant_bits`.
+    pub fn gates(&self) -> &[CombineOperation] {
+        &self.gates
+    }
+
+    /// The finished program, starting with a size hint that covers its wires (see
+    /// `refresh_size_hints`), then the constants from `constant_bits` as gates.
+    pub fn finish(self) -> Vec<CombineOperation> {
+        let mut program = self.finish_program();
+        program.lower_const_vectors();
+        refresh_size_hints(&program.gates)
+    }
+
+    /// Like `finish`, but keeps the buses too, and the constants packed. The size hint covers
+    /// the constants' wires. The program records this crate as its generator.
+    pub fn finish_program(self) -> Program {
+        let buses = self.buses;
+        let vectors = self.const_vectors;
+        let size_hint = vectors
+            .iter()
+            .map(|vector| vector.wires().end)
+            .max()
+            .map(|gf2| (0, gf2));
+        Program {
+            gates: refresh_size_hints(&self.gates),
+            buses: if buses.is_empty() { None } else { Some(buses) },
+            size_hint,
+            const_vectors: if vectors.is_empty() {
+                None
+            } else {
+                Some(vectors)
+            },
+            generators: Some(vec![Generator::mcircuit()]),
+            ..Program::default()
+        }
+    }
+}
+
+/// Gadgets can build into a `CircuitBuilder`. Gates emitted directly should only write wires
+/// allocated through `fresh_wires`.
+impl GateSink for CircuitBuilder {
+    fn fresh_wires(&mut self, domain: Domain, count: usize) -> usize {
+        let next = match domain {
+            Domain::GF2 => &mut self.next_bool,
+            Domain::Z64 => &mut self.next_arith,
+        };
+        let low = *next;
+        *next += count;
+        low
+    }
+
+    fn emit(&mut self, gate: CombineOperation) {
+        self.gates.push(gate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{BusError, CircuitBuilder, Wire};
+    use crate::gadgets::bits::add;
+    use crate::gadgets::rom::{Rom, RomError};
+    use crate::{evaluate_composite_program, CombineOperation, Domain, Operation};
+
+    #[test]
+    fn test_builder() {
+        let mut builder = CircuitBuilder::new();
+        let a = builder.input::<u64>();
+        let b = builder.input();
+        let product = builder.mul(a, b);
+        let check = builder.add_const(product, 1);
+        builder.assert_zero(check);
+        let bit = builder.input::<bool>();
+        let flipped = builder.add_const(bit, true);
+        builder.assert_zero(flipped);
+
+        let program = builder.finish();
+        assert_eq!(
+            program,
+            [
+                CombineOperation::SizeHint(4, 2),
+                CombineOperation::Z64(Operation::Input(0)),
+                CombineOperation::Z64(Operation::Input(1)),
+                CombineOperation::Z64(Operation::Mul(2, 0, 1)),
+                CombineOperation::Z64(Operation::AddConst(3, 2, 1)),
+                CombineOperation::Z64(Operation::AssertZero(3)),
+                CombineOperation::GF2(Operation::Input(0)),
+                CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+                CombineOperation::GF2(Operation::AssertZero(1)),
+            ]
+        );
+        evaluate_composite_program(&program, &[true], &[3, u64::MAX / 3]);
+    }
+
+    #[test]
+    fn test_builder_with_gadgets() {
+        // A gadget's 64-bit sum, converted to Z64 and checked there
+        let mut builder = CircuitBuilder::new();
+        let x = builder.inputs::<bool>(64);
+        let y = builder.inputs::<bool>(64);
+        let index = |wires: &[Wire<bool>]| wires.iter().map(|w| w.index).collect::<Vec<_>>();
+        let (sum, _carry) = add(&mut builder, &index(&x), &index(&y));
+        let sum: Vec<Wire<bool>> = sum.into_iter().map(Wire::new).collect();
+        let converted = builder.b2a(&sum);
+        let expected = builder.input::<u64>();
+        let difference = builder.sub(converted, expected);
+        builder.assert_zero(difference);
+
+        let bits = |value: u64| (0..64).map(move |i| (value >> i) & 1 == 1);
+        let witness: Vec<bool> = bits(40).chain(bits(2)).collect();
+        evaluate_composite_program(&builder.finish(), &witness, &[42]);
+    }
+
+    #[test]
+    fn test_checked_buses() {
+        let mut builder = CircuitBuilder::checked();
+        let a = builder.input_bus("a", Domain::GF2, 4);
+        let b = builder.input_bus("b", Domain::GF2, 4);
+        let wide = builder.input_bus("wide", Domain::GF2, 8);
+        let arith = builder.input_bus("arith", Domain::Z64, 4);
+
+        let both = builder.mul_buses("both", &a, &b).unwrap();
+        assert_eq!(both.wires, [16, 17, 18, 19]);
+        let error = builder.add_buses("sum", &a, &wide).unwrap_err();
+        assert_eq!(
+            error,
+            BusError::WidthMismatch {
+                op: "add",
+                left: "a".to_string(),
+                left_width: 4,
+                right: "wide".to_string(),
+                right_width: 8,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Can't add 4-bit bus a and 8-bit bus wide"
+        );
+        assert!(matches!(
+            builder.sub_buses("difference", &arith, &b),
+            Err(BusError::DomainMismatch { .. })
+        ));
+        // Nothing was emitted for the rejected operations
+        assert_eq!(builder.gates().len(), 24);
+
+        let difference = builder.sub_buses("difference", &arith, &arith).unwrap();
+        for wire in &difference.wires {
+            builder.assert_zero(Wire::<u64>::new(*wire));
+        }
+        let program = builder.finish_program();
+        let names: Vec<&str> = program
+            .buses
+            .iter()
+            .flatten()
+            .map(|bus| bus.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "b", "wide", "arith", "both", "difference"]);
+        evaluate_composite_program(&program.gates, &[true; 16], &[1, 2, 3, 4]);
+
+        // Unchecked, the same mistake goes through and only covers the narrower bus
+        let mut builder = CircuitBuilder::new();
+        let a = builder.input_bus("a", Domain::GF2, 4);
+        let wide = builder.input_bus("wide", Domain::GF2, 8);
+        assert_eq!(builder.add_buses("sum", &a, &wide).unwrap().wires.len(), 4);
+    }
+
+    #[test]
+    fn test_assert_polarity() {
+        let build = || {
+            let mut builder = CircuitBuilder::new();
+            let bit = builder.input::<bool>();
+            builder.assert_true(bit);
+            let x = builder.input::<u64>();
+            builder.assert_nonzero(x);
+            builder.finish()
+        };
+        let passes = |bool_inputs: &[bool], x: u64| {
+            let program = build();
+            std::panic::catch_unwind(|| evaluate_composite_program(&program, bool_inputs, &[x]))
+                .is_ok()
+        };
+        // The flag, then x's bits
+        let mut witness = vec![true; 65];
+        assert!(passes(&witness, u64::MAX));
+        witness[0] = false;
+        assert!(!passes(&witness, u64::MAX));
+
+        let mut witness = vec![false; 65];
+        witness[0] = true;
+        witness[3] = true;
+        assert!(passes(&witness, 4));
+        // Zero has no bits set, and claiming one is caught by the decomposition
+        assert!(!passes(&witness, 0));
+        witness[3] = false;
+        assert!(!passes(&witness, 0));
+    }
+
+    #[test]
+    fn test_constant_bus() {
+        let mut builder = CircuitBuilder::new();
+        let x = builder.input_bus("x", Domain::GF2, 8);
+        let key = builder.constant_hex("key", "a5");
+        let bits = builder.constant_bits(&[true, false]);
+        let mixed = builder.add_buses("mixed", &x, &key).unwrap();
+        for wire in &mixed.wires {
+            builder.assert_zero(Wire::<bool>::new(*wire));
+        }
+        builder.assert_zero(bits[1]);
+        assert_eq!(builder.gates().len(), 25);
+
+        let program = builder.clone().finish_program();
+        let vectors = program.const_vectors.as_ref().unwrap();
+        assert_eq!(vectors[0].wires(), 8..16);
+        assert_eq!(vectors[1].wires(), 16..18);
+        assert_eq!(program.size_hint().map(|(_, gf2)| gf2), Some(26));
+
+        let gates = builder.finish();
+        assert!(matches!(gates[0], CombineOperation::SizeHint(_, 26)));
+        assert_eq!(gates[1], CombineOperation::GF2(Operation::Const(8, true)));
+        let x: Vec<bool> = (0..8).map(|i| 0xa5 >> i & 1 == 1).collect();
+        evaluate_composite_program(&gates, &x, &[]);
+    }
+
+    #[test]
+    fn test_rom_bus() {
+        let rom = Rom::from_bytes(8, &[0x11, 0x22, 0x33, 0x44]).unwrap();
+        let mut builder = CircuitBuilder::checked();
+        let address = builder.input_bus("pc", Domain::GF2, 2);
+        let word = builder.rom("insn", &rom, &address).unwrap();
+        let expected = builder.constant_hex("expected", "33");
+        let diff = builder.add_buses("diff", &word, &expected).unwrap();
+        for wire in &diff.wires {
+            builder.assert_zero(Wire::<bool>::new(*wire));
+        }
+        assert_eq!(builder.buses()[1].name, "insn");
+        evaluate_composite_program(&builder.finish(), &[false, true], &[]);
+
+        let mut builder = CircuitBuilder::new();
+        let narrow = builder.input_bus("pc", Domain::GF2, 1);
+        let arith = builder.input_bus("count", Domain::Z64, 2);
+        assert_eq!(
+            builder.rom("insn", &rom, &narrow),
+            Err(RomError::AddressTooNarrow { bits: 1, needed: 2 })
+        );
+        assert!(matches!(
+            builder.rom("insn", &rom, &arith),
+            Err(RomError::AddressDomain { .. })
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+//! Reproducibility bundles: a single tar file holding a program, a witness for it, what
+//! evaluating the two is supposed to do, and which version of mcircuit produced them. Meant to be
+//! attached to bug reports, so whoever picks the report up can check they're seeing the same
+//! thing with `verify_bundle`.
+//!
+//! A bundle contains three files:
+//! * `program.mcir`, written by `write_program`
+//! * `witness.txt`, written by `write_witness`
+//! * `manifest.json`, a `Manifest`
+
+use std::collections::HashMap;
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    evaluate_composite_program_checked, read_witness, write_program, write_witness, EvalOptions,
+    Program, ProgramReader, WitnessMode,
+};
+
+const PROGRAM: &str = "program.mcir";
+const WITNESS: &str = "witness.txt";
+const MANIFEST: &str = "manifest.json";
+
+/// Bumped whenever the layout of a bundle changes incompatibly.
+const BUNDLE_VERSION: u32 = 1;
+
+/// What happens when a program is evaluated on a witness.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// Every assertion holds
+    Accepts,
+    /// Evaluation fails, usually because an assertion doesn't hold
+    Rejects,
+}
+
+impl Outcome {
+    /// Evaluates `program` on the given witness and reports whether it got to the end.
+    pub fn of(program: &Program, bool_inputs: &[bool], arith_inputs: &[u64]) -> Self {
+        let result = evaluate_composite_program_checked(
+            &program.gates,
+            bool_inputs,
+            arith_inputs,
+            &[],
+            &[],
+            EvalOptions::default().into(),
+        );
+        match result {
+            Ok(_) => Outcome::Accepts,
+            Err(_) => Outcome::Rejects,
+        }
+    }
+}
+
+/// Everything in a bundle besides the program and witness.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub bundle_version: u32,
+    /// Version of mcircuit that wrote the bundle
+    pub mcircuit_version: String,
+    /// `content_hash` of the program, to catch bundles that were edited or damaged
+    pub content_hash: u64,
+    pub expected: Outcome,
+    /// Free-form notes from whoever made the bundle
+    pub notes: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bundle {
+    pub program: Program,
+    pub bool_inputs: Vec<bool>,
+    pub arith_inputs: Vec<u64>,
+    pub manifest: Manifest,
+}
+
+impl Bundle {
+    /// Packages up a program and witness, recording the outcome they're claimed to have.
+    pub fn new(
+        program: Program,
+        bool_inputs: Vec<bool>,
+        arith_inputs: Vec<u64>,
+        expected: Outcome,
+        notes: &str,
+    ) -> Self {
+        let manifest = Manifest {
+            bundle_version: BUNDLE_VERSION,
+            mcircuit_version: env!("CARGO_PKG_VERSION").to_string(),
+            content_hash: program.content_hash(),
+            expected,
+            notes: notes.to_string(),
+        };
+        Bundle {
+            program,
+            bool_inputs,
+            arith_inputs,
+            manifest,
+        }
+    }
+
+    /// Writes the bundle as a tar file. The output only depends on the bundle's contents, so
+    /// bundling the same thing twice gives identical files.
+    pub fn write(&self, sink: impl Write) -> Result<()> {
+        let mut program = Vec::new();
+        write_program(&self.program, &mut program)?;
+        let mut witness = Vec::new();
+        write_witness(
+            &self.program,
+            &self.bool_inputs,
+            &self.arith_inputs,
+            &mut witness,
+        )?;
+        let manifest = serde_json::to_vec_pretty(&self.manifest).map_err(Error::other)?;
+
+        let mut archive = tar::Builder::new(sink);
+        for (path, contents) in [(MANIFEST, manifest), (PROGRAM, program), (WITNESS, witness)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(0);
+            header.set_cksum();
+            archive.append_data(&mut header, path, contents.as_slice())?;
+        }
+        archive.into_inner()?.flush()
+    }
+
+    pub fn read(reader: impl Read) -> Result<Self> {
+        let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+        for entry in tar::Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            files.insert(path, contents);
+        }
+        let mut file = |name: &str| {
+            files.remove(name).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("bundle is missing {}", name),
+                )
+            })
+        };
+
+        let manifest: Manifest = serde_json::from_slice(&file(MANIFEST)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if manifest.bundle_version != BUNDLE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "bundle version {} isn't supported (expected {})",
+                    manifest.bundle_version, BUNDLE_VERSION
+                ),
+            ));
+        }
+        let program = ProgramReader::new(Cursor::new(file(PROGRAM)?))?.read_program()?;
+        let witness = read_witness(&program, file(WITNESS)?.as_slice(), WitnessMode::Strict)?;
+
+        Ok(Bundle {
+            program,
+            bool_inputs: witness.bool_inputs,
+            arith_inputs: witness.arith_inputs,
+            manifest,
+        })
+    }
+}
+
+/// The result of re-running a bundle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verification {
+    pub expected: Outcome,
+    pub actual: Outcome,
+    /// Whether the program still has the hash recorded in the manifest
+    pub hash_matches: bool,
+    /// Version of mcircuit that wrote the bundle, for comparing against this one
+    pub bundled_with: String,
+}
+
+impl Verification {
+    /// Whether the bundle is intact and evaluates the way it claims to.
+    pub fn reproduces(&self) -> bool {
+        self.hash_matches && self.expected == self.actual
+    }
+}
+
+/// Reads a bundle and evaluates it again.
+pub fn verify_bundle(reader: impl Read) -> Result<Verification> {
+    let bundle = Bundle::read(reader)?;
+    Ok(Verification {
+        expected: bundle.manifest.expected,
+        actual: Outcome::of(&bundle.program, &bundle.bool_inputs, &bundle.arith_inputs),
+        hash_matches: bundle.program.content_hash() == bundle.manifest.content_hash,
+        bundled_with: bundle.manifest.mcircuit_version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::{verify_bundle, Bundle, Outcome};
+    use crate::{CombineOperation, Operation, Program};
+
+    fn program() -> Program {
+        let mut program: Program = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AssertZero(0)),
+        ]
+        .into();
+        program.annotate(1, "input must be false");
+        program
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let bundle = Bundle::new(program(), vec![true], vec![], Outcome::Rejects, "issue #12");
+        let mut file = Vec::new();
+        bundle.write(&mut file).unwrap();
+
+        let mut again = Vec::new();
+        bundle.write(&mut again).unwrap();
+        assert_eq!(file, again);
+
+        assert_eq!(Bundle::read(file.as_slice()).unwrap(), bundle);
+        let verification = verify_bundle(file.as_slice()).unwrap();
+        assert_eq!(verification.actual, Outcome::Rejects);
+        assert!(verification.reproduces());
+    }
+
+    #[test]
+    fn test_bundle_mismatch() {
+        let mut bundle = Bundle::new(program(), vec![false], vec![], Outcome::Rejects, "");
+        let mut file = Vec::new();
+        bundle.write(&mut file).unwrap();
+        let verification = verify_bundle(file.as_slice()).unwrap();
+        assert_eq!(verification.actual, Outcome::Accepts);
+        assert!(!verification.reproduces());
+
+        // The claimed outcome holds, but the program isn't the one that was bundled
+        bundle.manifest.expected = Outcome::Accepts;
+        bundle
+            .program
+            .gates
+            .push(CombineOperation::GF2(Operation::Const(1, true)));
+        let mut file = Vec::new();
+        bundle.write(&mut file).unwrap();
+        let verification = verify_bundle(file.as_slice()).unwrap();
+        assert!(!verification.hash_matches);
+        assert!(!verification.reproduces());
+    }
+}
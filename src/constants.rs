@@ -0,0 +1,233 @@
+//! Manifests of the constants baked into a program, so public parameters embedded as gates (hash
+//! IVs, round constants and the like) can be checked against a specification without reading
+//! the gates by hand.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CombineOperation, Domain, HasIO, Program, Provenance};
+
+/// One constant in a program, and what's known about where it came from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstantEntry {
+    /// Index of the gate holding the constant
+    pub gate: usize,
+    pub domain: Domain,
+    /// The wire the gate writes
+    pub wire: usize,
+    /// The constant, with GF2 constants as 0 or 1
+    pub value: u64,
+    /// The wire's name, if the program has one for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The parameter the constant stands for, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameter: Option<String>,
+    /// The gate's annotation, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Every constant in a program, in gate order. Serializes to JSON (or anything else serde
+/// supports) for auditors to compare against a specification, or to keep alongside a program and
+/// check later builds with `verify`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstantManifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    pub constants: Vec<ConstantEntry>,
+}
+
+/// A constant in a manifest that the program doesn't agree with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConstantMismatch {
+    /// The program has no constant where the manifest expects one
+    Missing(ConstantEntry),
+    /// The program has a different constant there
+    Value {
+        expected: ConstantEntry,
+        found: ConstantEntry,
+    },
+}
+
+/// Describes where an entry lives, by name if it has one.
+fn location(entry: &ConstantEntry) -> String {
+    match &entry.name {
+        Some(name) => format!("{:?} wire {}", entry.domain, name),
+        None => format!("gate {}", entry.gate),
+    }
+}
+
+impl fmt::Display for ConstantMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstantMismatch::Missing(expected) => {
+                write!(f, "no constant at {}", location(expected))
+            }
+            ConstantMismatch::Value { expected, found } => write!(
+                f,
+                "{} is {}, expected {}",
+                location(expected),
+                found.value,
+                expected.value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConstantMismatch {}
+
+/// The constant in `gate` and its domain, if it has one.
+fn constant(gate: &CombineOperation) -> Option<(Domain, u64)> {
+    match gate {
+        CombineOperation::GF2(_) => gate.gf2_constants().next().map(|c| (Domain::GF2, c as u64)),
+        CombineOperation::Z64(_) => gate.z64_constants().next().map(|c| (Domain::Z64, c)),
+        _ => None,
+    }
+}
+
+impl ConstantManifest {
+    /// Lists every constant in the program, with the names, parameters and annotations it has for
+    /// them. Packed constants are listed as the gates `Program::lower_const_vectors` turns them
+    /// into, with gate indices to match.
+    pub fn extract(program: &Program) -> Self {
+        let lowered;
+        let program = if program.const_vectors.is_some() {
+            let mut copy = program.clone();
+            copy.lower_const_vectors();
+            lowered = copy;
+            &lowered
+        } else {
+            program
+        };
+        let constants = program
+            .gates
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, gate)| {
+                let (domain, value) = constant(gate)?;
+                let wire = gate.dst()?;
+                let lookup = |map: Option<&BTreeMap<usize, String>>| {
+                    map.and_then(|map| map.get(&idx)).cloned()
+                };
+                Some(ConstantEntry {
+                    gate: idx,
+                    domain,
+                    wire,
+                    value,
+                    name: program
+                        .names
+                        .as_ref()
+                        .and_then(|names| names.get(domain, wire))
+                        .cloned(),
+                    parameter: lookup(program.parameters.as_ref()),
+                    note: lookup(program.annotations.as_ref()),
+                })
+            })
+            .collect();
+        ConstantManifest {
+            provenance: program.provenance.clone(),
+            constants,
+        }
+    }
+
+    /// Checks the program's constants against the manifest. Entries with a name are looked up by
+    /// the wire's name, so they survive changes elsewhere in the program; entries without one are
+    /// looked up by gate index. Constants the manifest doesn't list aren't checked.
+    pub fn verify(&self, program: &Program) -> Result<(), Vec<ConstantMismatch>> {
+        let actual = ConstantManifest::extract(program);
+        let by_gate: HashMap<usize, &ConstantEntry> = actual
+            .constants
+            .iter()
+            .map(|entry| (entry.gate, entry))
+            .collect();
+        // A name written more than once resolves to its last constant, the one that sticks
+        let by_name: HashMap<(Domain, &str), &ConstantEntry> = actual
+            .constants
+            .iter()
+            .filter_map(|entry| Some(((entry.domain, entry.name.as_deref()?), entry)))
+            .collect();
+
+        let mismatches: Vec<ConstantMismatch> = self
+            .constants
+            .iter()
+            .filter_map(|expected| {
+                let found = match &expected.name {
+                    Some(name) => by_name.get(&(expected.domain, name.as_str())),
+                    None => by_gate
+                        .get(&expected.gate)
+                        .filter(|found| found.domain == expected.domain),
+                };
+                match found {
+                    None => Some(ConstantMismatch::Missing(expected.clone())),
+                    Some(found) if found.value != expected.value => Some(ConstantMismatch::Value {
+                        expected: expected.clone(),
+                        found: (*found).clone(),
+                    }),
+                    Some(_) => None,
+                }
+            })
+            .collect();
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{ConstantManifest, ConstantMismatch};
+    use crate::{CombineOperation, Domain, NameTable, Operation, Program};
+
+    #[test]
+    fn test_constant_manifest() {
+        let mut names = NameTable::default();
+        names.insert(Domain::Z64, 0, "iv[0]".to_string());
+        names.insert(Domain::Z64, 1, "iv[1]".to_string());
+        let mut program = Program::from(vec![
+            CombineOperation::Z64(Operation::Const(0, 0x6a09e667)),
+            CombineOperation::Z64(Operation::Const(1, 0xbb67ae85)),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+            CombineOperation::Z64(Operation::Add(2, 0, 1)),
+        ]);
+        program.names = Some(names);
+        program.annotate(3, "negate");
+
+        let manifest = ConstantManifest::extract(&program);
+        assert_eq!(manifest.constants.len(), 3);
+        assert_eq!(manifest.constants[1].name.as_deref(), Some("iv[1]"));
+        assert_eq!(manifest.constants[2].domain, Domain::GF2);
+        assert_eq!(manifest.constants[2].value, 1);
+        assert_eq!(manifest.constants[2].note.as_deref(), Some("negate"));
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        let manifest: ConstantManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(manifest.verify(&program), Ok(()));
+
+        // Named constants are still found after the gates move, but unnamed ones aren't
+        let mut moved = program.clone();
+        moved
+            .gates
+            .insert(0, CombineOperation::Z64(Operation::Input(5)));
+        moved.gates[2] = CombineOperation::Z64(Operation::Const(1, 0));
+        let errors = manifest.verify(&moved).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            &errors[0],
+            ConstantMismatch::Value { expected, found } if expected.gate == 1 && found.value == 0
+        ));
+        assert_eq!(
+            errors[1],
+            ConstantMismatch::Missing(manifest.constants[2].clone())
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "Z64 wire iv[1] is 0, expected 3144134277"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Which gates write and read each wire, built once and saved next to the program so tools that
+//! need it don't each pay for a pass over the whole program at startup.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{content_hash, CombineOperation, Domain, HasIO};
+
+/// Def-use lists for every wire in a program: the gates that write it (its definitions, more than
+/// one if it's reassigned) and the gates that read it, both in program order. Records the
+/// `content_hash` of the gates it was built from, so a saved index can be checked against the
+/// program it's loaded with.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefUseIndex {
+    program_hash: u64,
+    gates: usize,
+    gf2: DomainIndex,
+    z64: DomainIndex,
+}
+
+/// The lists for one domain, flattened: the gates for wire `w` are
+/// `defs[def_starts[w]..def_starts[w + 1]]`, and likewise for uses.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct DomainIndex {
+    def_starts: Vec<usize>,
+    defs: Vec<usize>,
+    use_starts: Vec<usize>,
+    uses: Vec<usize>,
+}
+
+/// A saved `DefUseIndex` that doesn't belong to the program it was loaded with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaleIndexError {
+    /// Hash of the program the index was built from
+    pub indexed: u64,
+    /// Hash of the program it was checked against
+    pub program: u64,
+}
+
+impl fmt::Display for StaleIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "def-use index was built for program {:016x}, not {:016x}",
+            self.indexed, self.program
+        )
+    }
+}
+
+impl std::error::Error for StaleIndexError {}
+
+/// Flattens `(wire, gate)` pairs, already in gate order, into per-wire lists.
+fn flatten(pairs: &[(usize, usize)], wires: usize) -> (Vec<usize>, Vec<usize>) {
+    let mut starts = vec![0; wires + 1];
+    for (wire, _) in pairs {
+        starts[wire + 1] += 1;
+    }
+    for wire in 0..wires {
+        starts[wire + 1] += starts[wire];
+    }
+    let mut next = starts.clone();
+    let mut gates = vec![0; pairs.len()];
+    for (wire, gate) in pairs {
+        gates[next[*wire]] = *gate;
+        next[*wire] += 1;
+    }
+    (starts, gates)
+}
+
+impl DomainIndex {
+    fn build(defs: &[(usize, usize)], uses: &[(usize, usize)]) -> Self {
+        let wires = defs
+            .iter()
+            .chain(uses)
+            .map(|(wire, _)| wire + 1)
+            .max()
+            .unwrap_or(0);
+        let (def_starts, defs) = flatten(defs, wires);
+        let (use_starts, uses) = flatten(uses, wires);
+        DomainIndex {
+            def_starts,
+            defs,
+            use_starts,
+            uses,
+        }
+    }
+
+    /// Whether `starts` could have been built by `flatten` for `gates`, all of them indices into
+    /// a program of `program_len` gates, so `lookup` can't go out of bounds.
+    fn well_formed(starts: &[usize], gates: &[usize], program_len: usize) -> bool {
+        let ends_at_gates = match (starts.first(), starts.last()) {
+            (Some(first), Some(last)) => *first == 0 && *last == gates.len(),
+            _ => gates.is_empty(),
+        };
+        ends_at_gates
+            && starts.windows(2).all(|pair| pair[0] <= pair[1])
+            && gates.iter().all(|gate| *gate < program_len)
+    }
+
+    fn lookup<'a>(starts: &[usize], gates: &'a [usize], wire: usize) -> &'a [usize] {
+        match (starts.get(wire), starts.get(wire + 1)) {
+            (Some(start), Some(end)) => &gates[*start..*end],
+            _ => &[],
+        }
+    }
+}
+
+impl DefUseIndex {
+    pub fn build(gates: &[CombineOperation]) -> Self {
+        let (mut gf2, mut z64) = ((Vec::new(), Vec::new()), (Vec::new(), Vec::new()));
+        for (idx, gate) in gates.iter().enumerate() {
+            if let Some(domain) = gate.input_domain() {
+                let uses = match domain {
+                    Domain::GF2 => &mut gf2.1,
+                    Domain::Z64 => &mut z64.1,
+                };
+                uses.extend(gate.inputs().map(|wire| (wire, idx)));
+            }
+            if let Some(domain) = gate.output_domain() {
+                let defs = match domain {
+                    Domain::GF2 => &mut gf2.0,
+                    Domain::Z64 => &mut z64.0,
+                };
+                defs.extend(gate.outputs().map(|wire| (wire, idx)));
+            }
+        }
+        DefUseIndex {
+            program_hash: content_hash(gates),
+            gates: gates.len(),
+            gf2: DomainIndex::build(&gf2.0, &gf2.1),
+            z64: DomainIndex::build(&z64.0, &z64.1),
+        }
+    }
+
+    fn domain(&self, domain: Domain) -> &DomainIndex {
+        match domain {
+            Domain::GF2 => &self.gf2,
+            Domain::Z64 => &self.z64,
+        }
+    }
+
+    /// Indices of the gates that write `wire`, in order.
+    pub fn defs(&self, domain: Domain, wire: usize) -> &[usize] {
+        let index = self.domain(domain);
+        DomainIndex::lookup(&index.def_starts, &index.defs, wire)
+    }
+
+    /// Indices of the gates that read `wire`, in order. A gate that reads it twice is listed
+    /// twice.
+    pub fn uses(&self, domain: Domain, wire: usize) -> &[usize] {
+        let index = self.domain(domain);
+        DomainIndex::lookup(&index.use_starts, &index.uses, wire)
+    }
+
+    /// The `content_hash` of the gates the index was built from.
+    pub fn program_hash(&self) -> u64 {
+        self.program_hash
+    }
+
+    /// Whether the lists are consistent with each other and only name gates of the program the
+    /// index claims to be for. Only a corrupt saved index isn't.
+    pub(crate) fn is_well_formed(&self) -> bool {
+        [&self.gf2, &self.z64].iter().all(|index| {
+            DomainIndex::well_formed(&index.def_starts, &index.defs, self.gates)
+                && DomainIndex::well_formed(&index.use_starts, &index.uses, self.gates)
+        })
+    }
+
+    /// Checks that the index was built from `gates`.
+    pub fn check(&self, gates: &[CombineOperation]) -> Result<(), StaleIndexError> {
+        let program = content_hash(gates);
+        if program == self.program_hash && gates.len() == self.gates {
+            Ok(())
+        } else {
+            Err(StaleIndexError {
+                indexed: self.program_hash,
+                program,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::def_use::DefUseIndex;
+    use crate::{CombineOperation, Domain, Operation};
+
+    #[test]
+    fn test_def_use() {
+        let gates = [
+            CombineOperation::SizeHint(2, 65),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Mul(1, 0, 0)),
+            CombineOperation::B2A(0, 1),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 3)),
+            CombineOperation::Z64(Operation::Const(1, 3)),
+            CombineOperation::Z64(Operation::AssertZero(1)),
+        ];
+        let index = DefUseIndex::build(&gates);
+        assert_eq!(index.defs(Domain::GF2, 0), [1]);
+        assert_eq!(index.uses(Domain::GF2, 0), [2, 2]);
+        assert_eq!(index.uses(Domain::GF2, 1), [3]);
+        assert_eq!(index.uses(Domain::GF2, 64), [3]);
+        assert!(index.defs(Domain::GF2, 64).is_empty());
+        assert_eq!(index.defs(Domain::Z64, 0), [3]);
+        assert_eq!(index.defs(Domain::Z64, 1), [4, 5]);
+        assert_eq!(index.uses(Domain::Z64, 1), [6]);
+        assert!(index.uses(Domain::Z64, 1000).is_empty());
+
+        assert!(index.is_well_formed());
+        assert!(DefUseIndex::default().is_well_formed());
+        assert!(index.check(&gates).is_ok());
+        let err = index.check(&gates[1..]).unwrap_err();
+        assert_eq!(err.indexed, index.program_hash());
+
+        let mut corrupt = index.clone();
+        corrupt.z64.def_starts[1] = 5;
+        assert!(!corrupt.is_well_formed());
+        let mut corrupt = index.clone();
+        corrupt.gf2.uses[0] = gates.len();
+        assert!(!corrupt.is_well_formed());
+        let mut corrupt = index;
+        corrupt.gf2.use_starts.pop();
+        assert!(!corrupt.is_well_formed());
+    }
+}
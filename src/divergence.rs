@@ -0,0 +1,341 @@
+//! Finds where two programs that should compute the same values stop doing so, like a program
+//! before and after an optimization, without diffing VCD dumps of both by hand.
+//!
+//! Both programs are evaluated on the same witness, and every wire they both write is compared by
+//! its final value. Of the wires that disagree, the one reported is the earliest (in the first
+//! program) whose own inputs all agree: since every wire it reads holds the same value in both
+//! programs, the gate that writes it is where the programs start computing different things, and
+//! every other disagreement downstream of it is just a consequence.
+//!
+//! Wires that only one of the programs writes (like temporaries an optimization removed) aren't
+//! compared. Both programs draw their `Random` values from the same `Randomness`, so with a seed
+//! or a tape, `Random` gates in the same order get the same values. With `Randomness::Entropy`
+//! they don't, so they're only reported when nothing else disagrees.
+//!
+//! Passes that renumber wires without moving gates, like `renumber_wires` and `reuse_wires`,
+//! leave nothing to match up by wire index. `find_divergence_by_position` compares those gate by
+//! gate instead.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::eval::{final_values, gate_values};
+use crate::{CombineOperation, Domain, EvaluationError, HasIO, Operation, Randomness};
+
+/// A wire with a different value in each program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub domain: Domain,
+    pub wire: usize,
+    /// The wire's value in the first and second program. GF2 values are 0 or 1.
+    pub values: (u64, u64),
+    /// Index of the gate that wrote the wire in the first and second program
+    pub gates: (usize, usize),
+    /// The wires the gate in the first program reads, with their values in each program (`None`
+    /// where the second program doesn't write the wire)
+    pub inputs: Vec<(Domain, usize, u64, Option<u64>)>,
+    /// How many wires disagree in all, counting this one
+    pub total: usize,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} wire {} is {} after gate {} of the first program, but {} after gate {} of the \
+             second",
+            self.domain, self.wire, self.values.0, self.gates.0, self.values.1, self.gates.1
+        )?;
+        if self.total > 1 {
+            write!(f, " ({} wires disagree in all)", self.total)?;
+        }
+        Ok(())
+    }
+}
+
+/// The wires `gate` reads, along with their domain.
+fn read_wires(gate: &CombineOperation) -> impl Iterator<Item = (Domain, usize)> + '_ {
+    let domain = match gate {
+        CombineOperation::Z64(_) => Domain::Z64,
+        _ => Domain::GF2,
+    };
+    gate.inputs().map(move |wire| (domain, wire))
+}
+
+/// Evaluates `first` and `second` on the same witness and randomness and returns the wire where
+/// they start to disagree, or `None` if every wire they both write ends up with the same value.
+/// Assertions aren't checked, so programs that fail them can still be compared; running out of
+/// inputs or random values is an error, with the gate index in whichever program ran out.
+pub fn find_divergence(
+    first: &[CombineOperation],
+    second: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    randomness: Randomness,
+) -> Result<Option<Divergence>, EvaluationError> {
+    let ours = final_values(first, bool_inputs, arith_inputs, randomness)?;
+    let theirs = final_values(second, bool_inputs, arith_inputs, randomness)?;
+
+    let disagrees = |key: &(Domain, usize)| match (ours.get(key), theirs.get(key)) {
+        (Some((_, a)), Some((_, b))) => a != b,
+        _ => false,
+    };
+    let mut divergent: Vec<((Domain, usize), usize)> = ours
+        .iter()
+        .filter(|(key, _)| disagrees(key))
+        .map(|(key, (gate, _))| (*key, *gate))
+        .collect();
+    divergent.sort_by_key(|(_, gate)| *gate);
+
+    let writer_inputs: HashMap<(Domain, usize), Vec<(Domain, usize)>> = divergent
+        .iter()
+        .map(|(key, gate)| (*key, read_wires(&first[*gate]).collect()))
+        .collect();
+    // Rewritten wires can leave no gate whose inputs all agree, and `Random` gates never count as
+    // the root; fall back to the earliest divergence then
+    let &(key, gate) = match divergent
+        .iter()
+        .find(|(key, gate)| !is_random(&first[*gate]) && !writer_inputs[key].iter().any(&disagrees))
+        .or_else(|| divergent.first())
+    {
+        Some(root) => root,
+        None => return Ok(None),
+    };
+
+    let (domain, wire) = key;
+    Ok(Some(Divergence {
+        domain,
+        wire,
+        values: (ours[&key].1, theirs[&key].1),
+        gates: (gate, theirs[&key].0),
+        inputs: writer_inputs[&key]
+            .iter()
+            .map(|input| {
+                let value = ours.get(input).map_or(0, |(_, value)| *value);
+                let other = theirs.get(input).map(|(_, value)| *value);
+                (input.0, input.1, value, other)
+            })
+            .collect(),
+        total: divergent.len(),
+    }))
+}
+
+fn is_random(gate: &CombineOperation) -> bool {
+    matches!(
+        gate,
+        CombineOperation::GF2(Operation::Random(_)) | CombineOperation::Z64(Operation::Random(_))
+    )
+}
+
+/// Like `find_divergence`, but matches up gates by their position in each program, not counting
+/// size hints, rather than wires by their index. For comparing a program with one whose wires
+/// were renumbered, where the same index means something else in each. Gates past the end of the
+/// shorter program aren't compared. `wire` and `inputs` are the first program's wires, with the
+/// second program's values for the wires in the same places of its gate.
+pub fn find_divergence_by_position(
+    first: &[CombineOperation],
+    second: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    randomness: Randomness,
+) -> Result<Option<Divergence>, EvaluationError> {
+    let ours = gate_values(first, bool_inputs, arith_inputs, randomness)?;
+    let theirs = gate_values(second, bool_inputs, arith_inputs, randomness)?;
+    let divergent: Vec<_> = ours
+        .iter()
+        .zip(&theirs)
+        .filter(|(a, b)| matches!((a.output, b.output), (Some(x), Some(y)) if x != y))
+        .collect();
+
+    // Same as by wire: the root is the first gate that read the same values and wrote different
+    // ones
+    let &(mine, other) = match divergent
+        .iter()
+        .find(|(a, b)| !is_random(&first[a.gate]) && a.inputs == b.inputs)
+        .or_else(|| divergent.first())
+    {
+        Some(root) => root,
+        None => return Ok(None),
+    };
+
+    let gate = &first[mine.gate];
+    let domain = match gate {
+        CombineOperation::GF2(_) => Domain::GF2,
+        _ => Domain::Z64,
+    };
+    Ok(Some(Divergence {
+        domain,
+        wire: gate
+            .dst()
+            .expect("Only gates that write a wire are compared"),
+        values: (mine.output.unwrap_or(0), other.output.unwrap_or(0)),
+        gates: (mine.gate, other.gate),
+        inputs: read_wires(gate)
+            .zip(&mine.inputs)
+            .enumerate()
+            .map(|(idx, ((domain, wire), value))| {
+                (domain, wire, *value, other.inputs.get(idx).copied())
+            })
+            .collect(),
+        total: divergent.len(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::divergence::{find_divergence, find_divergence_by_position};
+    use crate::optimize::{renumber_wires, reuse_wires};
+    use crate::{CombineOperation, Domain, EvaluationError, Operation, Randomness};
+
+    #[test]
+    fn test_find_divergence() {
+        // (a + b) * 3 + 1
+        let original = [
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Input(1)),
+            CombineOperation::Z64(Operation::Add(2, 0, 1)),
+            CombineOperation::Z64(Operation::MulConst(3, 2, 3)),
+            CombineOperation::Z64(Operation::AddConst(4, 3, 1)),
+            CombineOperation::Z64(Operation::SubConst(5, 4, 16)),
+            CombineOperation::Z64(Operation::AssertZero(5)),
+        ];
+        assert_eq!(
+            find_divergence(&original, &original, &[], &[2, 3], Randomness::Entropy).unwrap(),
+            None
+        );
+
+        // A broken rewrite that adds a temporary and gets the multiplication wrong
+        let mut broken = original.to_vec();
+        broken[3] = CombineOperation::Z64(Operation::Add(3, 2, 2));
+        broken.insert(3, CombineOperation::Z64(Operation::Const(6, 0)));
+        let divergence = find_divergence(&original, &broken, &[], &[2, 3], Randomness::Entropy)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.domain, Domain::Z64);
+        assert_eq!(divergence.wire, 3);
+        assert_eq!(divergence.values, (15, 10));
+        assert_eq!(divergence.gates, (3, 4));
+        assert_eq!(divergence.inputs, [(Domain::Z64, 2, 5, Some(5))]);
+        // Wires 4 and 5 follow from wire 3
+        assert_eq!(divergence.total, 3);
+        assert_eq!(
+            divergence.to_string(),
+            "Z64 wire 3 is 15 after gate 3 of the first program, but 10 after gate 4 of the \
+             second (3 wires disagree in all)"
+        );
+
+        // Boolean wires are compared as 0 or 1
+        let boolean = [
+            CombineOperation::GF2(Operation::Input(5)),
+            CombineOperation::GF2(Operation::AddConst(0, 5, true)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+        ];
+        let mut inverted = boolean;
+        inverted[1] = CombineOperation::GF2(Operation::AddConst(0, 5, false));
+        let divergence =
+            find_divergence(&boolean, &inverted, &[true, true], &[], Randomness::Entropy)
+                .unwrap()
+                .unwrap();
+        assert_eq!((divergence.wire, divergence.values), (0, (0, 1)));
+        assert_eq!(divergence.total, 2);
+    }
+
+    #[test]
+    fn test_find_divergence_by_position() {
+        // (a + b) * 3 + 1, with gaps between the wires
+        let original = [
+            CombineOperation::SizeHint(60, 0),
+            CombineOperation::Z64(Operation::Input(10)),
+            CombineOperation::Z64(Operation::Input(20)),
+            CombineOperation::Z64(Operation::Add(30, 10, 20)),
+            CombineOperation::Z64(Operation::MulConst(40, 30, 3)),
+            CombineOperation::Z64(Operation::AddConst(50, 40, 1)),
+            CombineOperation::Z64(Operation::AssertZero(50)),
+        ];
+        for renumbered in [renumber_wires(&original), reuse_wires(&original)] {
+            assert_eq!(
+                find_divergence_by_position(
+                    &original,
+                    &renumbered,
+                    &[],
+                    &[2, 3],
+                    Randomness::Entropy
+                )
+                .unwrap(),
+                None
+            );
+        }
+
+        // The multiplication is wrong after renumbering
+        let mut broken = renumber_wires(&original);
+        assert_eq!(
+            broken[4],
+            CombineOperation::Z64(Operation::MulConst(3, 2, 3))
+        );
+        broken[4] = CombineOperation::Z64(Operation::MulConst(3, 2, 2));
+        let broken = reuse_wires(&broken);
+        // Comparing by wire misses it, since no index means the same wire in both
+        assert_eq!(
+            find_divergence(&original, &broken, &[], &[2, 3], Randomness::Entropy).unwrap(),
+            None
+        );
+        let divergence =
+            find_divergence_by_position(&original, &broken, &[], &[2, 3], Randomness::Entropy)
+                .unwrap()
+                .unwrap();
+        assert_eq!((divergence.domain, divergence.wire), (Domain::Z64, 40));
+        assert_eq!(divergence.values, (15, 10));
+        assert_eq!(divergence.inputs, [(Domain::Z64, 30, 5, Some(5))]);
+        assert_eq!(divergence.total, 2);
+    }
+
+    #[test]
+    fn test_find_divergence_with_randomness() {
+        // A random mask that's added and taken away again
+        let masked = [
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Random(1)),
+            CombineOperation::Z64(Operation::Add(2, 0, 1)),
+            CombineOperation::Z64(Operation::Sub(3, 2, 1)),
+        ];
+        let seed = Randomness::Seed(7);
+        assert_eq!(find_divergence(&masked, &masked, &[], &[4], seed), Ok(None));
+        assert_eq!(
+            find_divergence_by_position(&masked, &masked, &[], &[4], seed),
+            Ok(None)
+        );
+        let tape = Randomness::Tape {
+            bool_tape: &[],
+            arith_tape: &[9],
+        };
+        assert_eq!(find_divergence(&masked, &masked, &[], &[4], tape), Ok(None));
+
+        // A short witness is reported, not a panic
+        let short = find_divergence(&masked, &masked, &[], &[], seed);
+        assert_eq!(
+            short,
+            Err(EvaluationError::OutOfInputs {
+                gate: 0,
+                domain: Domain::Z64,
+            })
+        );
+        let short = find_divergence_by_position(
+            &masked,
+            &masked,
+            &[],
+            &[4],
+            Randomness::Tape {
+                bool_tape: &[],
+                arith_tape: &[],
+            },
+        );
+        assert_eq!(
+            short,
+            Err(EvaluationError::OutOfRandomness {
+                gate: 1,
+                domain: Domain::Z64,
+            })
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! A small embedded language for writing circuits by hand, mostly in tests and examples. Needs the
+//! `dsl` feature.
+//!
+//! `circuit!` takes a list of statements and evaluates to the gates they describe:
+//!
+//! ```
+//! use mcircuit::{circuit, evaluate_composite_program};
+//!
+//! let gates = circuit! {
+//!     a = input;
+//!     b = input;
+//!     c = a * b;
+//!     assert_zero(c + 1);
+//! };
+//! evaluate_composite_program(&gates, &[], &[3, u64::MAX / 3]);
+//! ```
+//!
+//! Each statement is either `name = input;`, `name = <expression>;` or
+//! `assert_zero(<expression>);`. Expressions are ordinary Rust expressions over wires and constants
+//! using `+`, `-` and `*`, so they follow Rust's precedence and can use any variable in scope.
+//! Every operator emits a gate into a fresh wire, and a bare constant becomes a `Const` gate.
+//! Circuits are over Z64 unless the statements are wrapped in a domain, as in
+//! `circuit!(gf2 { ... })`, where `+` is XOR and `*` is AND. The statements expand to
+//! `CircuitBuilder` calls, so wires are numbered from zero in the order they're created, and the
+//! circuit starts with a size hint.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::ops::{Add, Mul, Sub};
+
+use crate::{CircuitBuilder, CombineOperation, Operation, WireValue};
+
+/// The `CircuitBuilder` behind a `circuit!`, shared by all its wires. Not meant to be used
+/// directly.
+#[derive(Debug, Default)]
+pub struct Builder<T> {
+    builder: RefCell<CircuitBuilder>,
+    value: PhantomData<T>,
+}
+
+/// A wire in a circuit being built by `circuit!`.
+#[derive(Clone, Copy, Debug)]
+pub struct Wire<'b, T> {
+    pub id: usize,
+    builder: &'b Builder<T>,
+}
+
+impl<T: WireValue> Builder<T>
+where
+    CombineOperation: From<Operation<T>>,
+{
+    pub fn new() -> Self {
+        Builder {
+            builder: RefCell::new(CircuitBuilder::new()),
+            value: PhantomData,
+        }
+    }
+
+    /// Allocates a fresh wire and emits the gate `op` builds to write it.
+    fn emit(&self, op: impl FnOnce(usize) -> Operation<T>) -> Wire<'_, T> {
+        let id = self.builder.borrow_mut().write(op).index;
+        Wire { id, builder: self }
+    }
+
+    pub fn input(&self) -> Wire<'_, T> {
+        self.emit(Operation::Input)
+    }
+
+    pub fn assert_zero<'b>(&'b self, wire: impl IntoWire<'b, T>) {
+        let wire = wire.into_wire(self);
+        self.builder
+            .borrow_mut()
+            .assert_zero(crate::Wire::<T>::new(wire.id));
+    }
+
+    pub fn finish(self) -> Vec<CombineOperation> {
+        self.builder.into_inner().finish()
+    }
+}
+
+/// Things that can stand for a wire in a `circuit!` statement: wires themselves, and constants.
+pub trait IntoWire<'b, T> {
+    fn into_wire(self, builder: &'b Builder<T>) -> Wire<'b, T>;
+}
+
+impl<'b, T> IntoWire<'b, T> for Wire<'b, T> {
+    fn into_wire(self, _builder: &'b Builder<T>) -> Wire<'b, T> {
+        self
+    }
+}
+
+impl<'b, T: WireValue> IntoWire<'b, T> for T
+where
+    CombineOperation: From<Operation<T>>,
+{
+    fn into_wire(self, builder: &'b Builder<T>) -> Wire<'b, T> {
+        builder.emit(|dst| Operation::Const(dst, self))
+    }
+}
+
+/// Implements a binary operator between wires, and between a wire and a constant on either side.
+macro_rules! operator {
+    ($trait:ident, $method:ident, $gate:ident, $const_gate:ident, $reversed:expr) => {
+        impl<'b, T: WireValue> $trait for Wire<'b, T>
+        where
+            CombineOperation: From<Operation<T>>,
+        {
+            type Output = Wire<'b, T>;
+
+            fn $method(self, other: Self) -> Self::Output {
+                self.builder
+                    .emit(|dst| Operation::$gate(dst, self.id, other.id))
+            }
+        }
+
+        impl<'b, T: WireValue> $trait<T> for Wire<'b, T>
+        where
+            CombineOperation: From<Operation<T>>,
+        {
+            type Output = Wire<'b, T>;
+
+            fn $method(self, other: T) -> Self::Output {
+                self.builder
+                    .emit(|dst| Operation::$const_gate(dst, self.id, other))
+            }
+        }
+
+        operator!(@reversed $trait, $method, $reversed, bool);
+        operator!(@reversed $trait, $method, $reversed, u64);
+    };
+    (@reversed $trait:ident, $method:ident, $reversed:expr, $value:ty) => {
+        impl<'b> $trait<Wire<'b, $value>> for $value {
+            type Output = Wire<'b, $value>;
+
+            fn $method(self, other: Wire<'b, $value>) -> Self::Output {
+                let reversed: fn(Wire<'b, $value>, $value) -> Wire<'b, $value> = $reversed;
+                reversed(other, self)
+            }
+        }
+    };
+}
+
+operator!(Add, add, Add, AddConst, |w, c| w + c);
+operator!(Mul, mul, Mul, MulConst, |w, c| w * c);
+// There's no gate for subtracting a wire from a constant, so the constant gets a wire of its own
+operator!(Sub, sub, Sub, SubConst, |w, c| {
+    let constant = c.into_wire(w.builder);
+    constant - w
+});
+
+/// Builds a circuit from a list of statements. See the `dsl` module.
+#[macro_export]
+macro_rules! circuit {
+    (gf2 { $($body:tt)* }) => {
+        $crate::circuit!(@build bool; $($body)*)
+    };
+    (z64 { $($body:tt)* }) => {
+        $crate::circuit!(@build u64; $($body)*)
+    };
+    (@build $value:ty; $($body:tt)*) => {{
+        let builder = $crate::dsl::Builder::<$value>::new();
+        $crate::circuit!(@statements builder; $($body)*);
+        builder.finish()
+    }};
+    (@statements $builder:ident;) => {};
+    (@statements $builder:ident; $name:ident = input; $($rest:tt)*) => {
+        let $name = $builder.input();
+        $crate::circuit!(@statements $builder; $($rest)*);
+    };
+    (@statements $builder:ident; assert_zero($value:expr); $($rest:tt)*) => {
+        $builder.assert_zero($value);
+        $crate::circuit!(@statements $builder; $($rest)*);
+    };
+    (@statements $builder:ident; $name:ident = $value:expr; $($rest:tt)*) => {
+        let $name = $crate::dsl::IntoWire::into_wire($value, &$builder);
+        $crate::circuit!(@statements $builder; $($rest)*);
+    };
+    ($($body:tt)*) => {
+        $crate::circuit!(z64 { $($body)* })
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{evaluate_composite_program, CombineOperation, Operation};
+
+    #[test]
+    fn test_circuit() {
+        let gates = circuit! {
+            a = input;
+            b = input;
+            c = a * b;
+            assert_zero(c + 1);
+        };
+        assert_eq!(
+            gates,
+            [
+                CombineOperation::SizeHint(4, 1),
+                CombineOperation::Z64(Operation::Input(0)),
+                CombineOperation::Z64(Operation::Input(1)),
+                CombineOperation::Z64(Operation::Mul(2, 0, 1)),
+                CombineOperation::Z64(Operation::AddConst(3, 2, 1)),
+                CombineOperation::Z64(Operation::AssertZero(3)),
+            ]
+        );
+
+        // Constants on either side, and Rust's precedence
+        let gates = circuit! {
+            x = input;
+            y = 5;
+            z = 2 * x + y - x * x;
+            w = 25 - x * x;
+            assert_zero(z + 10);
+            assert_zero(w);
+        };
+        assert_eq!(gates[2], CombineOperation::Z64(Operation::Const(1, 5)));
+        assert_eq!(
+            gates[3],
+            CombineOperation::Z64(Operation::MulConst(2, 0, 2))
+        );
+        // x = 5: 10 + 5 - 25 = -10, and 25 - 25 = 0
+        evaluate_composite_program(&gates, &[], &[5]);
+
+        let gates = circuit!(gf2 {
+            a = input;
+            b = input;
+            c = a * b + true;
+            assert_zero(c);
+        });
+        assert_eq!(
+            gates[4],
+            CombineOperation::GF2(Operation::AddConst(3, 2, true))
+        );
+        evaluate_composite_program(&gates, &[true, true], &[]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::analysis::{AnalysisPass, WireCounter};
+use crate::eval::size_hint;
+use crate::{CombineOperation, Domain};
+
+/// Batches up insertions and removals on a program. Every edit refers to gate indices in the
+/// _original_ program, so passes can keep using the indices they computed up front no matter how
+/// many edits they've already made. Nothing changes until `commit`.
+pub struct ProgramEditor {
+    gates: Vec<CombineOperation>,
+    /// Gates to emit before the original gate at each index. An index of `gates.len()` appends.
+    inserted: BTreeMap<usize, Vec<CombineOperation>>,
+    removed: HashSet<usize>,
+    next_arith: usize,
+    next_bool: usize,
+}
+
+impl ProgramEditor {
+    pub fn new(gates: Vec<CombineOperation>) -> Self {
+        // Size hints are counts rather than wire indices, so handle them separately
+        let ((mut next_arith, mut next_bool), _) = WireCounter::analyze(
+            gates
+                .iter()
+                .filter(|g| !matches!(g, CombineOperation::SizeHint(_, _))),
+        );
+        if let Some((z64, gf2)) = size_hint(&gates) {
+            next_arith = next_arith.max(z64);
+            next_bool = next_bool.max(gf2);
+        }
+
+        ProgramEditor {
+            gates,
+            inserted: BTreeMap::new(),
+            removed: HashSet::new(),
+            next_arith,
+            next_bool,
+        }
+    }
+
+    /// Allocates a wire that isn't used anywhere in the program.
+    pub fn fresh_wire(&mut self, domain: Domain) -> usize {
+        self.fresh_wires(domain, 1)
+    }
+
+    /// Allocates `count` contiguous unused wires (e.g. the source window for a B2A gate) and
+    /// returns the lowest.
+    pub fn fresh_wires(&mut self, domain: Domain, count: usize) -> usize {
+        let next = match domain {
+            Domain::GF2 => &mut self.next_bool,
+            Domain::Z64 => &mut self.next_arith,
+        };
+        let first = *next;
+        *next += count;
+        first
+    }
+
+    /// Inserts a gate before the original gate at `index`. Gates inserted at the same position
+    /// appear in the order they were inserted.
+    pub fn insert_before(&mut self, index: usize, gate: CombineOperation) {
+        assert!(index <= self.gates.len(), "No gate at index {}", index);
+        self.inserted.entry(index).or_default().push(gate);
+    }
+
+    /// Inserts a gate after the original gate at `index`.
+    pub fn insert_after(&mut self, index: usize, gate: CombineOperation) {
+        self.insert_before(index + 1, gate);
+    }
+
+    /// Adds a gate to the end of the program.
+    pub fn append(&mut self, gate: CombineOperation) {
+        self.insert_before(self.gates.len(), gate);
+    }
+
+    /// Removes the original gate at `index`. Gates inserted around it are unaffected.
+    pub fn remove(&mut self, index: usize) {
+        assert!(index < self.gates.len(), "No gate at index {}", index);
+        self.removed.insert(index);
+    }
+
+    /// Swaps the original gate at `index` for a new one.
+    pub fn replace(&mut self, index: usize, gate: CombineOperation) {
+        self.remove(index);
+        self.insert_after(index, gate);
+    }
+
+    /// Applies all the edits. Returns the new program, plus the new index of every original gate
+    /// (`None` for ones that were removed). If the program started with a size hint, it's updated
+    /// to cover any newly allocated wires.
+    pub fn commit(mut self) -> (Vec<CombineOperation>, Vec<Option<usize>>) {
+        let inserted_count: usize = self.inserted.values().map(Vec::len).sum();
+        let mut program = Vec::with_capacity(self.gates.len() + inserted_count);
+        let mut index_map = Vec::with_capacity(self.gates.len());
+
+        for (idx, gate) in self.gates.iter().enumerate() {
+            if let Some(before) = self.inserted.remove(&idx) {
+                program.extend(before);
+            }
+            if self.removed.contains(&idx) {
+                index_map.push(None);
+            } else {
+                index_map.push(Some(program.len()));
+                program.push(*gate);
+            }
+        }
+        if let Some(tail) = self.inserted.remove(&self.gates.len()) {
+            program.extend(tail);
+        }
+
+        if let Some(CombineOperation::SizeHint(z64, gf2)) = program.first_mut() {
+            *z64 = self.next_arith.max(*z64);
+            *gf2 = self.next_bool.max(*gf2);
+        }
+
+        (program, index_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::edit::ProgramEditor;
+    use crate::{evaluate_composite_program, CombineOperation, Domain, Operation};
+
+    #[test]
+    fn test_editing() {
+        let program = vec![
+            CombineOperation::SizeHint(1, 3),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            CombineOperation::GF2(Operation::AssertZero(2)),
+        ];
+
+        let mut editor = ProgramEditor::new(program);
+
+        // Probe the product by inverting it twice, then move the assertion onto the probe
+        let probe = editor.fresh_wire(Domain::GF2);
+        assert_eq!(probe, 3);
+        editor.insert_after(
+            3,
+            CombineOperation::GF2(Operation::AddConst(probe, 2, true)),
+        );
+        editor.insert_after(
+            3,
+            CombineOperation::GF2(Operation::SubConst(probe + 1, probe, true)),
+        );
+        assert_eq!(editor.fresh_wire(Domain::GF2), 4);
+        editor.replace(4, CombineOperation::GF2(Operation::AssertZero(4)));
+        editor.append(CombineOperation::Z64(Operation::Const(0, 0)));
+        editor.remove(1);
+        editor.insert_before(1, CombineOperation::GF2(Operation::Const(0, false)));
+
+        let (edited, index_map) = editor.commit();
+        assert_eq!(
+            edited,
+            vec![
+                CombineOperation::SizeHint(1, 5),
+                CombineOperation::GF2(Operation::Const(0, false)),
+                CombineOperation::GF2(Operation::Input(1)),
+                CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+                CombineOperation::GF2(Operation::AddConst(3, 2, true)),
+                CombineOperation::GF2(Operation::SubConst(4, 3, true)),
+                CombineOperation::GF2(Operation::AssertZero(4)),
+                CombineOperation::Z64(Operation::Const(0, 0)),
+            ]
+        );
+        assert_eq!(index_map, vec![Some(0), None, Some(2), Some(3), None]);
+
+        evaluate_composite_program(&edited, &[true], &[]);
+    }
+}
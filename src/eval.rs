@@ -1,111 +1,1375 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::analysis::{AnalysisPass, WireCounter};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{
+    AnalysisPass, SizeHintCheck, SizeHintIssue, UnwrittenRead, UnwrittenReads, WireCounter,
+    WireDensity,
+};
 use crate::parsers::WireHasher;
-use crate::{CombineOperation, HasIO, Operation};
+use crate::{
+    Annotations, CombineOperation, Domain, HasIO, Operation, Program, ProgramEditor, WireValue,
+};
+
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "parallel")]
+pub use parallel::{evaluate_parallel, evaluate_parallel_with_randomness};
+
+/// Number of wires held by each page of `WireStorage::Paged`.
+const PAGE_SIZE: usize = 1 << 12;
+
+/// Index spaces smaller than this are always stored densely; it's never worth paying for the
+/// page lookups on circuits this small.
+const DENSE_THRESHOLD: usize = 1 << 20;
+
+/// `StorageStrategy::Auto` picks paged storage for a domain when fewer than one in this many wires
+/// of its index space is ever written.
+const SPARSITY_RATIO: usize = 8;
+
+/// Bits in each word of `WireStorage::Packed`
+const PACKED_BITS: usize = 64;
+
+/// Selects how the evaluator stores wire values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StorageStrategy {
+    /// One flat vector per domain, sized to the largest wire index. Fastest, but allocates the
+    /// entire index space up front.
+    Dense,
+    /// Fixed-size pages, allocated the first time a wire inside them is written. Use this for
+    /// linked circuits that haven't been compacted, where most of the index space is empty.
+    Paged,
+    /// Like `Dense`, but with GF2 wires packed one bit each, which takes an eighth of the memory
+    /// and so keeps more of a big boolean circuit in cache. Z64 wires are stored as with `Dense`.
+    Packed,
+    /// Chooses `Dense`, `Packed` or `Paged` separately for each domain based on how densely the
+    /// program uses its wire indices. See `EvalConfig::for_program`.
+    #[default]
+    Auto,
+}
+
+/// The storage `StorageStrategy::Auto` picks for a domain with an index space of `span` wires,
+/// `writes` of which are (at most) ever written.
+fn choose_storage(domain: Domain, span: usize, writes: usize) -> StorageStrategy {
+    if span <= DENSE_THRESHOLD {
+        StorageStrategy::Dense
+    } else if writes.saturating_mul(SPARSITY_RATIO) < span {
+        StorageStrategy::Paged
+    } else if domain == Domain::GF2 {
+        StorageStrategy::Packed
+    } else {
+        StorageStrategy::Dense
+    }
+}
+
+/// What reading a wire that hasn't been written yet means. See the crate documentation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnwrittenWires {
+    /// The wire reads as zero (or false)
+    #[default]
+    Zero,
+    /// The program is invalid, and evaluating it panics before running any gates
+    Error,
+    /// The first read of each unwritten wire consumes the next witness value for its domain, as
+    /// if an `Input` gate had been inserted just before the reading gate
+    Input,
+}
+
+/// Where `Random` gates get their values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Randomness<'t> {
+    /// Fresh values from the thread's generator, so no two evaluations are alike
+    #[default]
+    Entropy,
+    /// Values from ChaCha20 seeded with this (as `ChaCha20Rng::seed_from_u64` does), drawn in
+    /// program order: the low bit of the next `u32` for a `GF2` gate, the next `u64` for a `Z64`
+    /// one. The same seed gives the same values on every platform, and in every release with the
+    /// same major version, so seeds can be written into test vectors.
+    Seed(u64),
+    /// Values read in order from a tape for each domain, the way `Input` gates read the witness
+    Tape {
+        bool_tape: &'t [bool],
+        arith_tape: &'t [u64],
+    },
+}
+
+/// What an evaluator draws random values from.
+enum RandomSource {
+    Entropy,
+    Seeded(Box<ChaCha20Rng>),
+    /// The tapes, and how much of each has been used
+    Tape {
+        bools: Vec<bool>,
+        ariths: Vec<u64>,
+        used: (usize, usize),
+    },
+}
+
+impl From<Randomness<'_>> for RandomSource {
+    fn from(randomness: Randomness) -> Self {
+        match randomness {
+            Randomness::Entropy => RandomSource::Entropy,
+            Randomness::Seed(seed) => {
+                RandomSource::Seeded(Box::new(ChaCha20Rng::seed_from_u64(seed)))
+            }
+            Randomness::Tape {
+                bool_tape,
+                arith_tape,
+            } => RandomSource::Tape {
+                bools: bool_tape.to_vec(),
+                ariths: arith_tape.to_vec(),
+                used: (0, 0),
+            },
+        }
+    }
+}
 
-/// Evaluates a composite program (in the clear). Uses assert! to check `AssertZero` gates
+impl RandomSource {
+    fn next_bool(&mut self) -> Option<bool> {
+        match self {
+            RandomSource::Entropy => Some(rand::random()),
+            RandomSource::Seeded(rng) => Some(rng.next_u32() & 1 == 1),
+            RandomSource::Tape { bools, used, .. } => {
+                let value = bools.get(used.0).copied();
+                used.0 += 1;
+                value
+            }
+        }
+    }
+
+    fn next_arith(&mut self) -> Option<u64> {
+        match self {
+            RandomSource::Entropy => Some(rand::random()),
+            RandomSource::Seeded(rng) => Some(rng.next_u64()),
+            RandomSource::Tape { ariths, used, .. } => {
+                let value = ariths.get(used.1).copied();
+                used.1 += 1;
+                value
+            }
+        }
+    }
+}
+
+/// Settings for `evaluate_composite_program_with`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvalOptions {
+    pub strategy: StorageStrategy,
+    pub unwritten: UnwrittenWires,
+}
+
+/// Backing store for the wire values of a single domain. Wires that have never been written read
+/// as zero (or false).
+pub enum WireStorage<T> {
+    Dense(Vec<T>),
+    Paged(HashMap<usize, Box<[T]>>),
+    /// One bit per wire, for GF2 values only
+    Packed(Vec<u64>),
+}
+
+impl<T: WireValue + Default + From<bool>> WireStorage<T> {
+    /// Allocates storage for an index space of `span` wires, `writes` of which are (at most)
+    /// ever written.
+    pub fn new(strategy: StorageStrategy, span: usize, writes: usize) -> Self {
+        match strategy {
+            StorageStrategy::Dense => WireStorage::Dense(vec![T::default(); span]),
+            StorageStrategy::Paged => WireStorage::Paged(HashMap::new()),
+            StorageStrategy::Packed => match T::DOMAIN {
+                Domain::GF2 => WireStorage::Packed(vec![0; span.div_ceil(PACKED_BITS)]),
+                Domain::Z64 => WireStorage::new(StorageStrategy::Dense, span, writes),
+            },
+            StorageStrategy::Auto => {
+                WireStorage::new(choose_storage(T::DOMAIN, span, writes), span, writes)
+            }
+        }
+    }
+
+    pub fn get(&self, wire: usize) -> T {
+        match self {
+            WireStorage::Dense(wires) => wires.get(wire).copied().unwrap_or_default(),
+            WireStorage::Paged(pages) => pages
+                .get(&(wire / PAGE_SIZE))
+                .map(|page| page[wire % PAGE_SIZE])
+                .unwrap_or_default(),
+            WireStorage::Packed(words) => words
+                .get(wire / PACKED_BITS)
+                .map(|word| T::from(word >> (wire % PACKED_BITS) & 1 == 1))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn set(&mut self, wire: usize, value: T) {
+        match self {
+            WireStorage::Dense(wires) => {
+                // Stale size hints shouldn't crash the evaluator, so grow if we have to
+                if wire >= wires.len() {
+                    wires.resize(wire + 1, T::default());
+                }
+                wires[wire] = value;
+            }
+            WireStorage::Paged(pages) => {
+                pages
+                    .entry(wire / PAGE_SIZE)
+                    .or_insert_with(|| vec![T::default(); PAGE_SIZE].into_boxed_slice())
+                    [wire % PAGE_SIZE] = value;
+            }
+            WireStorage::Packed(words) => {
+                let word = wire / PACKED_BITS;
+                if word >= words.len() {
+                    words.resize(word + 1, 0);
+                }
+                let bit = 1 << (wire % PACKED_BITS);
+                if value.is_zero() {
+                    words[word] &= !bit;
+                } else {
+                    words[word] |= bit;
+                }
+            }
+        }
+    }
+
+    /// Makes room for at least `len` wires. Does nothing for paged storage.
+    pub fn reserve(&mut self, len: usize) {
+        match self {
+            WireStorage::Dense(wires) => {
+                if wires.len() < len {
+                    wires.resize(len, T::default());
+                }
+            }
+            WireStorage::Packed(words) => {
+                let len = len.div_ceil(PACKED_BITS);
+                if words.len() < len {
+                    words.resize(len, 0);
+                }
+            }
+            WireStorage::Paged(_) => {}
+        }
+    }
+}
+
+/// Evaluates a composite program (in the clear). Panics if an `AssertZero` gate fails; see
+/// `evaluate_composite_program_checked` for a version that doesn't.
 pub fn evaluate_composite_program(
     program: &[CombineOperation],
     bool_inputs: &[bool],
     arith_inputs: &[u64],
 ) {
-    let (bool_wire_count, arith_wire_count) = largest_wires(program);
+    evaluate_composite_program_with_strategy(
+        program,
+        bool_inputs,
+        arith_inputs,
+        StorageStrategy::Auto,
+    )
+}
 
-    let mut bool_wires = vec![false; bool_wire_count];
-    let mut bool_inputs = bool_inputs.iter().cloned();
+/// Same as `evaluate_composite_program`, but lets you choose how wire values are stored.
+pub fn evaluate_composite_program_with_strategy(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    strategy: StorageStrategy,
+) {
+    evaluate_composite_program_with(
+        program,
+        bool_inputs,
+        arith_inputs,
+        EvalOptions {
+            strategy,
+            ..Default::default()
+        },
+    )
+}
 
-    let mut arith_wires = vec![0u64; arith_wire_count];
-    let mut arith_inputs = arith_inputs.iter().cloned();
+/// Same as `evaluate_composite_program`, but with configurable storage and semantics for
+/// unwritten wires.
+pub fn evaluate_composite_program_with(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    options: EvalOptions,
+) {
+    evaluate_composite_program_configured(program, bool_inputs, arith_inputs, options.into())
+}
 
-    for step in program {
-        match step {
+/// Evaluation settings for a particular program, with the storage for each domain chosen
+/// separately. `for_program` picks them from the program's statistics; change any of the fields
+/// afterwards to override its choices.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvalConfig {
+    pub bool_storage: StorageStrategy,
+    pub arith_storage: StorageStrategy,
+    pub unwritten: UnwrittenWires,
+    /// Check the program's size hints against the wires it uses before running any gates, and
+    /// panic if they're too small (see `SizeHintCheck`)
+    pub check_size_hints: bool,
+    /// Evaluate independent gates on several threads, as `evaluate_parallel` does. Only
+    /// `evaluate_composite_program_checked` and `evaluate_composite_program_with_randomness` look
+    /// at it, and only with the `parallel` feature; wires are then stored densely, whatever the
+    /// storage fields say.
+    pub parallel: bool,
+}
+
+impl EvalConfig {
+    /// Picks settings from the program's statistics.
+    ///
+    /// Storage is chosen for each domain from the size of its own index space and how many of its
+    /// wires are written, so a program that's mostly one domain doesn't pay for the other. Small
+    /// index spaces are always stored densely. Big ones are paged if less than an eighth of them
+    /// is used, and otherwise stored densely, with GF2 wires packed into bits. A domain the
+    /// program doesn't use gets an empty dense vector.
+    ///
+    /// With the `parallel` feature, programs whose depth is small next to their length (so that
+    /// each level of gates that can run at once averages a thousand or so) are evaluated in
+    /// parallel, unless a domain is sparse enough to be paged, since the parallel evaluator stores
+    /// every wire densely. A program that's one long chain of gates stays on the calling thread.
+    pub fn for_program(program: &[CombineOperation]) -> Self {
+        let ((arith_span, bool_span), (arith_writes, bool_writes)) =
+            WireDensity::analyze(program.iter());
+        let (arith_wires, bool_wires) = largest_wires(program);
+        let bool_storage = choose_storage(Domain::GF2, bool_wires.max(bool_span), bool_writes);
+        let arith_storage = choose_storage(Domain::Z64, arith_wires.max(arith_span), arith_writes);
+        #[cfg(feature = "parallel")]
+        let parallel = bool_storage != StorageStrategy::Paged
+            && arith_storage != StorageStrategy::Paged
+            && parallel::worth_parallelizing(program);
+        #[cfg(not(feature = "parallel"))]
+        let parallel = false;
+        EvalConfig {
+            bool_storage,
+            arith_storage,
+            unwritten: UnwrittenWires::default(),
+            check_size_hints: false,
+            parallel,
+        }
+    }
+}
+
+impl From<EvalOptions> for EvalConfig {
+    fn from(options: EvalOptions) -> Self {
+        EvalConfig {
+            bool_storage: options.strategy,
+            arith_storage: options.strategy,
+            unwritten: options.unwritten,
+            check_size_hints: false,
+            parallel: false,
+        }
+    }
+}
+
+/// Same as `evaluate_composite_program`, but with the storage for each domain chosen separately.
+pub fn evaluate_composite_program_configured(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    config: EvalConfig,
+) {
+    match evaluate_composite_program_limited(
+        program,
+        bool_inputs,
+        arith_inputs,
+        config,
+        &EvalLimits::default(),
+    ) {
+        Ok(()) => {}
+        Err(LimitedEvaluationError::Failed(error)) => panic!("{}", error),
+        Err(LimitedEvaluationError::Interrupted(_)) => {
+            unreachable!("Evaluation without limits can't be interrupted")
+        }
+    }
+}
+
+/// Lets another thread stop an evaluation. Clones share the same flag, so keep one and hand a
+/// clone to the evaluation through `EvalLimits`.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks evaluations using this token to stop. They notice the next time they check it.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Bounds on how long `evaluate_composite_program_limited` may run. The evaluator checks them
+/// every `check_interval` gates, so a cancellation or the deadline takes effect within that many
+/// gates of happening; the gate limit is exact.
+#[derive(Clone, Debug)]
+pub struct EvalLimits {
+    pub token: Option<CancellationToken>,
+    /// Most gates to evaluate, counting the `Input` gates `UnwrittenWires::Input` adds
+    pub max_gates: Option<usize>,
+    /// Longest to spend evaluating, measured from the start of the call
+    pub timeout: Option<Duration>,
+    pub check_interval: usize,
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        EvalLimits {
+            token: None,
+            max_gates: None,
+            timeout: None,
+            check_interval: 1 << 12,
+        }
+    }
+}
+
+/// Why an evaluation stopped early.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Cancelled,
+    GateLimit,
+    Timeout,
+}
+
+/// An evaluation that stopped before the end of the program. Everything before `gates` was
+/// evaluated, and any assertions there held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interrupted {
+    pub reason: StopReason,
+    /// Number of gates evaluated before stopping
+    pub gates: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            StopReason::Cancelled => "cancelled",
+            StopReason::GateLimit => "stopped at the gate limit",
+            StopReason::Timeout => "timed out",
+        };
+        write!(
+            f,
+            "evaluation {} after {} gates ({:?})",
+            reason, self.gates, self.elapsed
+        )
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Why a limited evaluation didn't finish: either it hit one of its limits, or the program or
+/// witness was bad.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LimitedEvaluationError {
+    Interrupted(Interrupted),
+    Failed(EvaluationError),
+}
+
+impl fmt::Display for LimitedEvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitedEvaluationError::Interrupted(interrupted) => interrupted.fmt(f),
+            LimitedEvaluationError::Failed(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LimitedEvaluationError {}
+
+impl From<Interrupted> for LimitedEvaluationError {
+    fn from(interrupted: Interrupted) -> Self {
+        LimitedEvaluationError::Interrupted(interrupted)
+    }
+}
+
+impl From<EvaluationError> for LimitedEvaluationError {
+    fn from(error: EvaluationError) -> Self {
+        LimitedEvaluationError::Failed(error)
+    }
+}
+
+/// Same as `evaluate_composite_program_configured`, but stops early once any of `limits` is hit,
+/// and reports failures instead of panicking, like `evaluate_composite_program_checked`.
+pub fn evaluate_composite_program_limited(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    config: EvalConfig,
+    limits: &EvalLimits,
+) -> Result<(), LimitedEvaluationError> {
+    let prepared = Prepared::new(program, config)?;
+    evaluate(&prepared, bool_inputs, arith_inputs, config, limits)
+}
+
+/// Why a checked evaluation failed. Gate indices are in the program as it was passed in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EvaluationError {
+    /// An `AssertZero` gate found a wire that isn't zero
+    AssertionFailed {
+        gate: usize,
+        domain: Domain,
+        wire: usize,
+        /// The wire's value (0 or 1 for GF2)
+        value: u64,
+    },
+    /// An `Input` gate needed more of the witness than there was
+    OutOfInputs { gate: usize, domain: Domain },
+    /// A `Random` gate needed more of the random tape than there was
+    OutOfRandomness { gate: usize, domain: Domain },
+    /// A gate read a wire before anything wrote it, under `UnwrittenWires::Error`
+    UnwrittenRead(UnwrittenRead),
+    /// The program's size hints are too small, with `EvalConfig::check_size_hints` set
+    StaleSizeHints(Vec<SizeHintIssue>),
+    /// The program has `needed` inputs in the domain, but the witness only has `got` values for
+    /// it. Only reported by evaluations that check the witness before running any gates.
+    WitnessTooShort {
+        needed: usize,
+        got: usize,
+        domain: Domain,
+    },
+}
+
+impl EvaluationError {
+    /// The gate the evaluation failed at, if it failed at one.
+    pub fn gate(&self) -> Option<usize> {
+        match self {
+            EvaluationError::AssertionFailed { gate, .. }
+            | EvaluationError::OutOfInputs { gate, .. }
+            | EvaluationError::OutOfRandomness { gate, .. } => Some(*gate),
+            EvaluationError::UnwrittenRead(read) => Some(read.gate),
+            EvaluationError::StaleSizeHints(_) | EvaluationError::WitnessTooShort { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvaluationError::AssertionFailed {
+                gate,
+                domain,
+                wire,
+                value,
+            } => write!(
+                f,
+                "Gate {} asserts that {:?} wire {} is zero, but it's {}",
+                gate, domain, wire, value
+            ),
+            EvaluationError::OutOfInputs { gate, domain } => {
+                write!(f, "Gate {} ran out of {:?} inputs", gate, domain)
+            }
+            EvaluationError::OutOfRandomness { gate, domain } => {
+                write!(f, "Gate {} ran out of {:?} random values", gate, domain)
+            }
+            EvaluationError::UnwrittenRead(read) => write!(
+                f,
+                "Gate {} reads {:?} wire {} before it's written",
+                read.gate, read.domain, read.wire
+            ),
+            EvaluationError::StaleSizeHints(issues) => {
+                let problems: Vec<String> = issues
+                    .iter()
+                    .filter_map(|issue| match issue {
+                        SizeHintIssue::TooSmall {
+                            domain,
+                            hinted,
+                            needed,
+                            gate,
+                        } => Some(format!(
+                            "size hints allow {} {:?} wires, but gate {} uses more (the program \
+                             needs {})",
+                            hinted, domain, gate, needed
+                        )),
+                        _ => None,
+                    })
+                    .collect();
+                write!(f, "Stale size hints: {}", problems.join("; "))
+            }
+            EvaluationError::WitnessTooShort {
+                needed,
+                got,
+                domain,
+            } => write!(
+                f,
+                "The program reads {} {:?} inputs, but the witness only has {}",
+                needed, domain, got
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EvaluationError {}
+
+/// The values of the wires a checked evaluation was asked for, in the order they were asked for.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvaluationOutput {
+    pub bool_outputs: Vec<bool>,
+    pub arith_outputs: Vec<u64>,
+}
+
+/// Same as `evaluate_composite_program_configured`, but reports failures instead of panicking,
+/// and returns the final values of `bool_outputs` and `arith_outputs`. For services that can't
+/// afford to panic on a bad witness.
+pub fn evaluate_composite_program_checked(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    bool_outputs: &[usize],
+    arith_outputs: &[usize],
+    config: EvalConfig,
+) -> Result<EvaluationOutput, EvaluationError> {
+    evaluate_composite_program_with_randomness(
+        program,
+        bool_inputs,
+        arith_inputs,
+        bool_outputs,
+        arith_outputs,
+        config,
+        Randomness::Entropy,
+    )
+}
+
+/// Same as `evaluate_composite_program_checked`, but with `Random` gates drawing their values
+/// from `randomness`, so the evaluation can be reproduced (for test vectors, say) by passing the
+/// same seed or tape again. Tapes are used up the way the witness is, and running out of one is
+/// an error like running out of inputs.
+pub fn evaluate_composite_program_with_randomness(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    bool_outputs: &[usize],
+    arith_outputs: &[usize],
+    config: EvalConfig,
+    randomness: Randomness,
+) -> Result<EvaluationOutput, EvaluationError> {
+    let prepared = Prepared::new(program, config)?;
+    #[cfg(feature = "parallel")]
+    if config.parallel {
+        return evaluate_parallel_with_randomness(
+            &prepared.gates,
+            bool_inputs,
+            arith_inputs,
+            bool_outputs,
+            arith_outputs,
+            randomness,
+        )
+        .map_err(|e| prepared.locate(e));
+    }
+    let mut evaluator = Evaluator::for_program(&prepared.gates, bool_inputs, arith_inputs, config)
+        .with_randomness(randomness);
+    for gate in prepared.gates.iter() {
+        evaluator.try_step(gate).map_err(|e| prepared.locate(e))?;
+    }
+    Ok(EvaluationOutput {
+        bool_outputs: bool_outputs
+            .iter()
+            .map(|w| evaluator.bool_wire(*w))
+            .collect(),
+        arith_outputs: arith_outputs
+            .iter()
+            .map(|w| evaluator.arith_wire(*w))
+            .collect(),
+    })
+}
+
+/// One value of the witness, and the `Input` gate that read it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputUse {
+    /// The `Input` gate's index in the program. Inputs added for unwritten wires belong to the
+    /// gate they were added for.
+    pub gate: usize,
+    pub domain: Domain,
+    /// Position of the value in its domain's witness
+    pub ordinal: usize,
+    pub wire: usize,
+}
+
+/// Where every value of the witness went, in the order the program read them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputMap {
+    pub uses: Vec<InputUse>,
+}
+
+impl InputMap {
+    /// The inputs read in one domain, in witness order.
+    pub fn inputs(&self, domain: Domain) -> impl Iterator<Item = &InputUse> + '_ {
+        self.uses.iter().filter(move |input| input.domain == domain)
+    }
+
+    /// The wire that the `ordinal`th value of the domain's witness was written to.
+    pub fn wire(&self, domain: Domain, ordinal: usize) -> Option<usize> {
+        self.inputs(domain).nth(ordinal).map(|input| input.wire)
+    }
+}
+
+/// Same as `evaluate_composite_program_checked`, but checks that the witness is long enough
+/// before running any gates, and returns which wire each witness value was read into. Useful for
+/// working out which part of a mixed GF2 and Z64 witness feeds which wire.
+pub fn evaluate_composite_program_mapped(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    config: EvalConfig,
+) -> Result<InputMap, EvaluationError> {
+    let prepared = Prepared::new(program, config)?;
+    let mut counts = (0, 0);
+    let mut map = InputMap::default();
+    for (idx, gate) in prepared.gates.iter().enumerate() {
+        let (domain, wire, count) = match gate {
+            CombineOperation::GF2(Operation::Input(wire)) => (Domain::GF2, *wire, &mut counts.0),
+            CombineOperation::Z64(Operation::Input(wire)) => (Domain::Z64, *wire, &mut counts.1),
+            _ => continue,
+        };
+        map.uses.push(InputUse {
+            gate: prepared.original(idx),
+            domain,
+            ordinal: *count,
+            wire,
+        });
+        *count += 1;
+    }
+    for (domain, needed, got) in [
+        (Domain::GF2, counts.0, bool_inputs.len()),
+        (Domain::Z64, counts.1, arith_inputs.len()),
+    ] {
+        if needed > got {
+            return Err(EvaluationError::WitnessTooShort {
+                needed,
+                got,
+                domain,
+            });
+        }
+    }
+
+    let mut evaluator = Evaluator::for_program(&prepared.gates, bool_inputs, arith_inputs, config);
+    for gate in prepared.gates.iter() {
+        evaluator.try_step(gate).map_err(|e| prepared.locate(e))?;
+    }
+    Ok(map)
+}
+
+/// A program checked and rewritten as `EvalConfig` asks, ready to evaluate.
+struct Prepared<'p> {
+    gates: Cow<'p, [CombineOperation]>,
+    /// The new index of every original gate, if gates were added
+    index_map: Option<Vec<Option<usize>>>,
+}
+
+impl<'p> Prepared<'p> {
+    fn new(program: &'p [CombineOperation], config: EvalConfig) -> Result<Self, EvaluationError> {
+        if config.check_size_hints {
+            let issues: Vec<SizeHintIssue> = SizeHintCheck::analyze(program.iter())
+                .into_iter()
+                .filter(|issue| matches!(issue, SizeHintIssue::TooSmall { .. }))
+                .collect();
+            if !issues.is_empty() {
+                return Err(EvaluationError::StaleSizeHints(issues));
+            }
+        }
+        match config.unwritten {
+            UnwrittenWires::Zero => Ok(Prepared {
+                gates: Cow::Borrowed(program),
+                index_map: None,
+            }),
+            UnwrittenWires::Error => match UnwrittenReads::analyze(program.iter()).first() {
+                Some(read) => Err(EvaluationError::UnwrittenRead(*read)),
+                None => Ok(Prepared {
+                    gates: Cow::Borrowed(program),
+                    index_map: None,
+                }),
+            },
+            UnwrittenWires::Input => {
+                let mut editor = ProgramEditor::new(program.to_vec());
+                for read in UnwrittenReads::analyze(program.iter()) {
+                    let input = match read.domain {
+                        Domain::GF2 => CombineOperation::GF2(Operation::Input(read.wire)),
+                        Domain::Z64 => CombineOperation::Z64(Operation::Input(read.wire)),
+                    };
+                    editor.insert_before(read.gate, input);
+                }
+                let (gates, index_map) = editor.commit();
+                Ok(Prepared {
+                    gates: Cow::Owned(gates),
+                    index_map: Some(index_map),
+                })
+            }
+        }
+    }
+
+    /// Moves a gate index from the rewritten program back to the original one. An added `Input`
+    /// belongs to the gate it was added for.
+    fn original(&self, gate: usize) -> usize {
+        match &self.index_map {
+            // Nothing is removed, so every gate has a new index, and they're in order
+            Some(index_map) => index_map.partition_point(|new| new.is_some_and(|n| n < gate)),
+            None => gate,
+        }
+    }
+
+    /// Moves the gate index of an error back to the original program.
+    fn locate(&self, error: EvaluationError) -> EvaluationError {
+        let original = |gate: usize| self.original(gate);
+        match error {
+            EvaluationError::AssertionFailed {
+                gate,
+                domain,
+                wire,
+                value,
+            } => EvaluationError::AssertionFailed {
+                gate: original(gate),
+                domain,
+                wire,
+                value,
+            },
+            EvaluationError::OutOfInputs { gate, domain } => EvaluationError::OutOfInputs {
+                gate: original(gate),
+                domain,
+            },
+            EvaluationError::OutOfRandomness { gate, domain } => EvaluationError::OutOfRandomness {
+                gate: original(gate),
+                domain,
+            },
+            error => error,
+        }
+    }
+}
+
+fn evaluate(
+    prepared: &Prepared,
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    config: EvalConfig,
+    limits: &EvalLimits,
+) -> Result<(), LimitedEvaluationError> {
+    let program = &prepared.gates[..];
+    let start = Instant::now();
+    let mut evaluator = Evaluator::for_program(program, bool_inputs, arith_inputs, config);
+    let end = limits
+        .max_gates
+        .map_or(program.len(), |max| max.min(program.len()));
+    let interval = limits.check_interval.max(1);
+
+    let mut done = 0;
+    while done < end {
+        let reason = if limits.token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            Some(StopReason::Cancelled)
+        } else if limits.timeout.is_some_and(|t| start.elapsed() >= t) {
+            Some(StopReason::Timeout)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Err(Interrupted {
+                reason,
+                gates: done,
+                elapsed: start.elapsed(),
+            }
+            .into());
+        }
+
+        let next = end.min(done + interval);
+        for gate in &program[done..next] {
+            evaluator.try_step(gate).map_err(|e| prepared.locate(e))?;
+        }
+        done = next;
+    }
+
+    if done < program.len() {
+        return Err(Interrupted {
+            reason: StopReason::GateLimit,
+            gates: done,
+            elapsed: start.elapsed(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Wire values partway through evaluating a program: just the ones the rest of the program reads
+/// before writing, along with how much of the witness has been used. Pass it to
+/// `resume_evaluation` to finish evaluating the program.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvalState {
+    /// Number of gates that have been evaluated
+    pub gates: usize,
+    pub bool_wires: BTreeMap<usize, bool>,
+    pub arith_wires: BTreeMap<usize, u64>,
+    pub bool_inputs_used: usize,
+    pub arith_inputs_used: usize,
+}
+
+/// Evaluates the first `gates` gates of a program (e.g. up to, but not including, a failing
+/// assertion) and summarizes the state at that point. Fails like `resume_evaluation` if the
+/// witness runs out or an assertion in the prefix doesn't hold.
+pub fn evaluate_prefix(
+    program: &[CombineOperation],
+    gates: usize,
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+) -> Result<EvalState, EvaluationError> {
+    let gates = gates.min(program.len());
+    let mut evaluator =
+        Evaluator::for_program(program, bool_inputs, arith_inputs, EvalConfig::default());
+    for gate in &program[..gates] {
+        evaluator.try_step(gate)?;
+    }
+
+    let mut state = EvalState {
+        gates,
+        bool_inputs_used: bool_inputs.len() - evaluator.bool_inputs.len(),
+        arith_inputs_used: arith_inputs.len() - evaluator.arith_inputs.len(),
+        ..Default::default()
+    };
+    for read in UnwrittenReads::analyze(program[gates..].iter()) {
+        match read.domain {
+            Domain::GF2 => {
+                let value = evaluator.bool_wires.get(read.wire);
+                state.bool_wires.insert(read.wire, value);
+            }
+            Domain::Z64 => {
+                let value = evaluator.arith_wires.get(read.wire);
+                state.arith_wires.insert(read.wire, value);
+            }
+        }
+    }
+    Ok(state)
+}
+
+/// Evaluates the rest of a program from a state returned by `evaluate_prefix`. Takes the whole
+/// witness, and skips the part of it that was already used. Gate indices in errors are in the
+/// whole program, and a witness shorter than the part the state already used is
+/// `EvaluationError::WitnessTooShort`.
+pub fn resume_evaluation(
+    program: &[CombineOperation],
+    state: &EvalState,
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+) -> Result<(), EvaluationError> {
+    let too_short = |needed, got, domain| EvaluationError::WitnessTooShort {
+        needed,
+        got,
+        domain,
+    };
+    let bool_rest = bool_inputs
+        .get(state.bool_inputs_used..)
+        .ok_or_else(|| too_short(state.bool_inputs_used, bool_inputs.len(), Domain::GF2))?;
+    let arith_rest = arith_inputs
+        .get(state.arith_inputs_used..)
+        .ok_or_else(|| too_short(state.arith_inputs_used, arith_inputs.len(), Domain::Z64))?;
+
+    let mut evaluator =
+        Evaluator::for_program(program, bool_rest, arith_rest, EvalConfig::default());
+    for (wire, value) in &state.bool_wires {
+        evaluator.bool_wires.set(*wire, *value);
+    }
+    for (wire, value) in &state.arith_wires {
+        evaluator.arith_wires.set(*wire, *value);
+    }
+    evaluator.gates = state.gates;
+    for gate in program.get(state.gates..).unwrap_or_default() {
+        evaluator.try_step(gate)?;
+    }
+    Ok(())
+}
+
+/// The last value written to each wire, with the index of the gate that wrote it.
+pub(crate) type FinalValues = HashMap<(Domain, usize), (usize, u64)>;
+
+/// Evaluates `program` without checking its assertions, and returns the last value written to
+/// each wire, along with the index of the gate that wrote it. GF2 values are 0 or 1.
+pub(crate) fn final_values(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    randomness: Randomness,
+) -> Result<FinalValues, EvaluationError> {
+    let mut evaluator =
+        Evaluator::for_program(program, bool_inputs, arith_inputs, EvalConfig::default())
+            .with_randomness(randomness);
+    let mut values = HashMap::new();
+    for (idx, gate) in program.iter().enumerate() {
+        let domain = match gate {
+            CombineOperation::GF2(Operation::AssertZero(_))
+            | CombineOperation::Z64(Operation::AssertZero(_))
+            | CombineOperation::SizeHint(_, _) => continue,
+            CombineOperation::GF2(_) => Domain::GF2,
+            CombineOperation::Z64(_) | CombineOperation::B2A(_, _) => Domain::Z64,
+        };
+        // Skipped gates aren't stepped, so keep the evaluator's count in line with the program's
+        evaluator.gates = idx;
+        evaluator.try_step(gate)?;
+        if let Some(dst) = gate.dst() {
+            let value = match domain {
+                Domain::GF2 => u64::from(evaluator.bool_wires.get(dst)),
+                Domain::Z64 => evaluator.arith_wires.get(dst),
+            };
+            values.insert((domain, dst), (idx, value));
+        }
+    }
+    Ok(values)
+}
+
+/// What one gate read and wrote, as `gate_values` records it.
+pub(crate) struct GateValues {
+    /// Index of the gate in the program
+    pub gate: usize,
+    /// The values of the wires the gate reads, in order, just before it ran
+    pub inputs: Vec<u64>,
+    /// The value the gate wrote, if it writes a wire
+    pub output: Option<u64>,
+}
+
+/// Evaluates `program` like `final_values`, but records what every gate besides size hints read
+/// and wrote as it ran, so programs whose wires are numbered differently can be compared gate by
+/// gate.
+pub(crate) fn gate_values(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    randomness: Randomness,
+) -> Result<Vec<GateValues>, EvaluationError> {
+    let mut evaluator =
+        Evaluator::for_program(program, bool_inputs, arith_inputs, EvalConfig::default())
+            .with_randomness(randomness);
+    let mut values = Vec::with_capacity(program.len());
+    for (idx, gate) in program.iter().enumerate() {
+        let (inputs, output) = match gate {
+            CombineOperation::SizeHint(_, _) => continue,
+            CombineOperation::GF2(op) => {
+                let inputs = op.inputs().map(|w| u64::from(evaluator.bool_wires.get(w)));
+                (inputs.collect(), op.dst().map(|_| Domain::GF2))
+            }
+            CombineOperation::Z64(op) => {
+                let inputs = op.inputs().map(|w| evaluator.arith_wires.get(w));
+                (inputs.collect(), op.dst().map(|_| Domain::Z64))
+            }
+            CombineOperation::B2A(_, _) => {
+                let inputs = gate
+                    .inputs()
+                    .map(|w| u64::from(evaluator.bool_wires.get(w)));
+                (inputs.collect(), Some(Domain::Z64))
+            }
+        };
+        if !matches!(
+            gate,
+            CombineOperation::GF2(Operation::AssertZero(_))
+                | CombineOperation::Z64(Operation::AssertZero(_))
+        ) {
+            evaluator.gates = idx;
+            evaluator.try_step(gate)?;
+        }
+        let output = output.zip(gate.dst()).map(|(domain, dst)| match domain {
+            Domain::GF2 => u64::from(evaluator.bool_wires.get(dst)),
+            Domain::Z64 => evaluator.arith_wires.get(dst),
+        });
+        values.push(GateValues {
+            gate: idx,
+            inputs,
+            output,
+        });
+    }
+    Ok(values)
+}
+
+/// Evaluates a program a gate at a time, for programs too big to hold in memory, like ones
+/// streamed straight from a parser. Holds the wire values and the unused part of the witness,
+/// which can come from any iterator.
+///
+/// ```
+/// use mcircuit::{CombineOperation, EvalConfig, Operation, StreamingEvaluator};
+///
+/// let mut evaluator = StreamingEvaluator::new(vec![], vec![3, 4], EvalConfig::default());
+/// for gate in [
+///     CombineOperation::Z64(Operation::Input(0)),
+///     CombineOperation::Z64(Operation::Input(1)),
+///     CombineOperation::Z64(Operation::Mul(2, 0, 1)),
+/// ] {
+///     evaluator.step(&gate);
+/// }
+/// assert_eq!(evaluator.arith_wire(2), 12);
+/// assert_eq!(evaluator.gates(), 3);
+/// ```
+pub struct StreamingEvaluator<B = std::vec::IntoIter<bool>, A = std::vec::IntoIter<u64>> {
+    bool_wires: WireStorage<bool>,
+    arith_wires: WireStorage<u64>,
+    bool_inputs: B,
+    arith_inputs: A,
+    random: RandomSource,
+    gates: usize,
+}
+
+/// How the whole-program evaluation functions use `StreamingEvaluator`.
+type Evaluator<'w> = StreamingEvaluator<
+    std::iter::Copied<std::slice::Iter<'w, bool>>,
+    std::iter::Copied<std::slice::Iter<'w, u64>>,
+>;
+
+impl<'w> Evaluator<'w> {
+    /// Allocates storage for every wire in `program`.
+    fn for_program(
+        program: &[CombineOperation],
+        bool_inputs: &'w [bool],
+        arith_inputs: &'w [u64],
+        config: EvalConfig,
+    ) -> Self {
+        let ((arith_span, bool_span), (arith_writes, bool_writes)) =
+            WireDensity::analyze(program.iter());
+        let (arith_wire_count, bool_wire_count) = largest_wires(program);
+
+        StreamingEvaluator {
+            bool_wires: WireStorage::new(
+                config.bool_storage,
+                bool_wire_count.max(bool_span),
+                bool_writes,
+            ),
+            arith_wires: WireStorage::new(
+                config.arith_storage,
+                arith_wire_count.max(arith_span),
+                arith_writes,
+            ),
+            bool_inputs: bool_inputs.iter().copied(),
+            arith_inputs: arith_inputs.iter().copied(),
+            random: RandomSource::Entropy,
+            gates: 0,
+        }
+    }
+}
+
+impl<B: Iterator<Item = bool>, A: Iterator<Item = u64>> StreamingEvaluator<B, A> {
+    /// Starts with every wire unwritten. Since the evaluator can't look ahead at the program,
+    /// `StorageStrategy::Auto` means dense storage that grows as wires are written (or as size
+    /// hints are seen); choose `Paged` for programs with sparse wire numbering. Only the storage
+    /// settings in `config` are used: unwritten wires always read as zero, and size hints aren't
+    /// checked.
+    pub fn new(
+        bool_inputs: impl IntoIterator<IntoIter = B>,
+        arith_inputs: impl IntoIterator<IntoIter = A>,
+        config: EvalConfig,
+    ) -> Self {
+        StreamingEvaluator {
+            bool_wires: WireStorage::new(config.bool_storage, 0, 0),
+            arith_wires: WireStorage::new(config.arith_storage, 0, 0),
+            bool_inputs: bool_inputs.into_iter(),
+            arith_inputs: arith_inputs.into_iter(),
+            random: RandomSource::Entropy,
+            gates: 0,
+        }
+    }
+
+    /// Draws the values of `Random` gates from `randomness` instead of the thread's generator.
+    pub fn with_randomness(mut self, randomness: Randomness) -> Self {
+        self.random = randomness.into();
+        self
+    }
+
+    /// Evaluates one gate. Panics if it's a failing assertion, or an input the witness has no
+    /// more values for.
+    pub fn step(&mut self, gate: &CombineOperation) {
+        if let Err(error) = self.try_step(gate) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Same as `step`, but returns an error instead of panicking. Gate indices in the error count
+    /// the gates this evaluator has been given, starting from zero.
+    pub fn try_step(&mut self, gate: &CombineOperation) -> Result<(), EvaluationError> {
+        let index = self.gates;
+        let StreamingEvaluator {
+            bool_wires,
+            arith_wires,
+            bool_inputs,
+            arith_inputs,
+            random,
+            gates,
+        } = self;
+        *gates += 1;
+
+        match gate {
             CombineOperation::GF2(gf2_insn) => match *gf2_insn {
                 Operation::Input(dst) => {
-                    bool_wires[dst] = bool_inputs.next().expect("Ran out of boolean inputs");
+                    let value = bool_inputs.next().ok_or(EvaluationError::OutOfInputs {
+                        gate: index,
+                        domain: Domain::GF2,
+                    })?;
+                    bool_wires.set(dst, value);
                 }
                 Operation::Random(dst) => {
-                    let val: bool = rand::random();
-                    bool_wires[dst] = val;
+                    let value = random.next_bool().ok_or(EvaluationError::OutOfRandomness {
+                        gate: index,
+                        domain: Domain::GF2,
+                    })?;
+                    bool_wires.set(dst, value);
                 }
                 Operation::Add(dst, src1, src2) => {
-                    bool_wires[dst] = bool_wires[src1] ^ bool_wires[src2];
+                    bool_wires.set(dst, bool_wires.get(src1) ^ bool_wires.get(src2));
                 }
                 Operation::Sub(dst, src1, src2) => {
-                    bool_wires[dst] = bool_wires[src1] ^ bool_wires[src2];
+                    bool_wires.set(dst, bool_wires.get(src1) ^ bool_wires.get(src2));
                 }
                 Operation::Mul(dst, src1, src2) => {
-                    bool_wires[dst] = bool_wires[src1] & bool_wires[src2];
+                    bool_wires.set(dst, bool_wires.get(src1) & bool_wires.get(src2));
                 }
                 Operation::AddConst(dst, src, c) => {
-                    bool_wires[dst] = bool_wires[src] ^ c;
+                    bool_wires.set(dst, bool_wires.get(src) ^ c);
                 }
                 Operation::SubConst(dst, src, c) => {
-                    bool_wires[dst] = bool_wires[src] ^ c;
+                    bool_wires.set(dst, bool_wires.get(src) ^ c);
                 }
                 Operation::MulConst(dst, src, c) => {
-                    bool_wires[dst] = bool_wires[src] & c;
+                    bool_wires.set(dst, bool_wires.get(src) & c);
                 }
                 Operation::AssertZero(src) => {
-                    assert!(!bool_wires[src]);
+                    if bool_wires.get(src) {
+                        return Err(EvaluationError::AssertionFailed {
+                            gate: index,
+                            domain: Domain::GF2,
+                            wire: src,
+                            value: 1,
+                        });
+                    }
                 }
                 Operation::Const(dst, c) => {
-                    bool_wires[dst] = c;
+                    bool_wires.set(dst, c);
                 }
             },
             CombineOperation::Z64(z64_insn) => match *z64_insn {
                 Operation::Input(dst) => {
-                    arith_wires[dst] = arith_inputs.next().expect("Ran out of arithmetic inputs");
+                    let value = arith_inputs.next().ok_or(EvaluationError::OutOfInputs {
+                        gate: index,
+                        domain: Domain::Z64,
+                    })?;
+                    arith_wires.set(dst, value);
                 }
                 Operation::Random(dst) => {
-                    let val: u64 = rand::random();
-                    arith_wires[dst] = val;
+                    let value = random
+                        .next_arith()
+                        .ok_or(EvaluationError::OutOfRandomness {
+                            gate: index,
+                            domain: Domain::Z64,
+                        })?;
+                    arith_wires.set(dst, value);
                 }
                 Operation::Add(dst, src1, src2) => {
-                    arith_wires[dst] = arith_wires[src1].wrapping_add(arith_wires[src2]);
+                    arith_wires.set(
+                        dst,
+                        arith_wires.get(src1).wrapping_add(arith_wires.get(src2)),
+                    );
                 }
                 Operation::Sub(dst, src1, src2) => {
-                    arith_wires[dst] = arith_wires[src1].wrapping_sub(arith_wires[src2]);
+                    arith_wires.set(
+                        dst,
+                        arith_wires.get(src1).wrapping_sub(arith_wires.get(src2)),
+                    );
                 }
                 Operation::Mul(dst, src1, src2) => {
-                    arith_wires[dst] = arith_wires[src1].wrapping_mul(arith_wires[src2]);
+                    arith_wires.set(
+                        dst,
+                        arith_wires.get(src1).wrapping_mul(arith_wires.get(src2)),
+                    );
                 }
                 Operation::AddConst(dst, src, c) => {
-                    arith_wires[dst] = arith_wires[src].wrapping_add(c);
+                    arith_wires.set(dst, arith_wires.get(src).wrapping_add(c));
                 }
                 Operation::SubConst(dst, src, c) => {
-                    arith_wires[dst] = arith_wires[src].wrapping_sub(c);
+                    arith_wires.set(dst, arith_wires.get(src).wrapping_sub(c));
                 }
                 Operation::MulConst(dst, src, c) => {
-                    arith_wires[dst] = arith_wires[src].wrapping_mul(c);
+                    arith_wires.set(dst, arith_wires.get(src).wrapping_mul(c));
                 }
                 Operation::AssertZero(src) => {
-                    assert_eq!(arith_wires[src], 0u64);
+                    let value = arith_wires.get(src);
+                    if value != 0 {
+                        return Err(EvaluationError::AssertionFailed {
+                            gate: index,
+                            domain: Domain::Z64,
+                            wire: src,
+                            value,
+                        });
+                    }
                 }
                 Operation::Const(dst, c) => {
-                    arith_wires[dst] = c;
+                    arith_wires.set(dst, c);
                 }
             },
             CombineOperation::B2A(dst, low) => {
                 let mut running_val: u64 = 0;
                 let mut power: u64 = 1;
-                for bit in bool_wires.iter().skip(*low).take(64) {
-                    running_val = running_val.wrapping_add(if *bit { power } else { 0 });
+                for bit in (*low..*low + 64).map(|w| bool_wires.get(w)) {
+                    running_val = running_val.wrapping_add(if bit { power } else { 0 });
                     power = power.wrapping_shl(1);
                 }
-                arith_wires[*dst] = running_val;
+                arith_wires.set(*dst, running_val);
             }
             CombineOperation::SizeHint(z64, gf2) => {
-                if bool_wires.len() < *gf2 {
-                    bool_wires.resize(*gf2, false);
-                }
-                if arith_wires.len() < *z64 {
-                    arith_wires.resize(*z64, 0);
-                }
+                bool_wires.reserve(*gf2);
+                arith_wires.reserve(*z64);
             }
         }
+        Ok(())
+    }
+
+    pub fn bool_wire(&self, wire: usize) -> bool {
+        self.bool_wires.get(wire)
+    }
+
+    pub fn arith_wire(&self, wire: usize) -> u64 {
+        self.arith_wires.get(wire)
+    }
+
+    /// Number of gates evaluated so far
+    pub fn gates(&self) -> usize {
+        self.gates
+    }
+
+    /// Gives up the evaluator, returning the parts of the witness it didn't use.
+    pub fn into_remaining_inputs(self) -> (B, A) {
+        (self.bool_inputs, self.arith_inputs)
+    }
+}
+
+/// Adds a wire to the scope its name puts it in, below `root`. We use :: to differentiate between
+/// scopes. This is a convention only used by the BLIF parser, so it won't apply for other circuits.
+fn add_to_scope(
+    scopes: &mut HashMap<String, HashSet<ScopeEntry>>,
+    root: &str,
+    backref: &str,
+    wire: usize,
+) {
+    let mut current_scope = root.to_string();
+    // I didn't want to implement a nested hashmap, so instead we store all the scopes in the same
+    // hashmap, and use "subscope" entries as pointers to different entries. This involves some
+    // chasing to get to the correct entry. Scopes below the root are keyed by their whole path,
+    // so that two scopes with the same name in different parents stay apart.
+    let mut scope_tokens = backref.split("::").peekable();
+    let mut path = String::new();
+    while let Some(t) = scope_tokens.next() {
+        if scope_tokens.peek().is_some() {
+            // If there are more scopes after this one, this is an intermediate scope. We add a
+            // subscope entry and then chase it to the next scope.
+            if !path.is_empty() {
+                path.push_str("::");
+            }
+            path.push_str(t);
+            scopes
+                .entry(current_scope)
+                .or_default()
+                .insert(ScopeEntry::SubScope(path.clone()));
+            current_scope = path.clone();
+        } else {
+            // When we get to the final entry, we add this wire to the current scope.
+            scopes
+                .entry(current_scope.clone())
+                .or_default()
+                .insert(ScopeEntry::Terminal((t.into(), wire)));
+        }
     }
 }
 
@@ -113,6 +1377,7 @@ pub fn evaluate_composite_program(
 #[derive(std::cmp::Eq, std::cmp::PartialEq, std::hash::Hash)]
 enum ScopeEntry {
     Terminal((String, usize)),
+    /// The subscope's whole path below the root, like `counter0::adder0`
     SubScope(String),
 }
 
@@ -128,139 +1393,78 @@ pub struct VcdDumper {
 }
 
 impl VcdDumper {
-    /// Uses `WireHasher.backref` to recover scope information from hashed wires in a circuit. With
+    /// Uses `WireHasher::backref` to recover scope information from hashed wires in a circuit, so
+    /// the hashers need to remember names (like `BackrefHasher` does). With
     /// our circuit pipeline, this is ONLY RELIABLE FOR TOP-LEVEL INPUTS & OUTPUTS because the flattener
     /// translates & minimizes all other wires after hashing occurs. Still, it can be useful for
     /// diagnosing whether you're seeing the output you expect when crossing from the boolean to the
     /// arithmetic bound, and with changes to the flattener it could be made to work for all wires.
     pub fn for_circuit(
+        writer: BufWriter<File>,
+        circuit: &[CombineOperation],
+        bool_hasher: &dyn WireHasher,
+        arith_hasher: &dyn WireHasher,
+    ) -> Self {
+        VcdDumper::with_names(
+            writer,
+            circuit,
+            |wire| bool_hasher.backref(wire).map(str::to_string),
+            |wire| arith_hasher.backref(wire).map(str::to_string),
+        )
+    }
+
+    /// Like `for_circuit`, but takes wire names from the program's name table. Unlike hasher
+    /// names, these cover every wire that was named when the program was built, including the
+    /// ones inside generated gadgets.
+    pub fn for_program(writer: BufWriter<File>, program: &Program) -> Self {
+        let names = program.names.as_ref();
+        VcdDumper::with_names(
+            writer,
+            &program.gates,
+            |wire| names.and_then(|n| n.get(Domain::GF2, wire)).cloned(),
+            |wire| names.and_then(|n| n.get(Domain::Z64, wire)).cloned(),
+        )
+    }
+
+    /// Writes the VCD header, naming wires with `bool_name` and `arith_name` where they can.
+    fn with_names(
         mut writer: BufWriter<File>,
         circuit: &[CombineOperation],
-        bool_hasher: &WireHasher,
-        arith_hasher: &WireHasher,
+        bool_name: impl Fn(usize) -> Option<String>,
+        arith_name: impl Fn(usize) -> Option<String>,
     ) -> Self {
         let mut bool_scopes: HashMap<String, HashSet<ScopeEntry>> = HashMap::new();
         let mut arith_scopes: HashMap<String, HashSet<ScopeEntry>> = HashMap::new();
+        // (gate index, dst, low) of every B2A gate
+        let mut conversions = Vec::new();
 
-        for step in circuit {
+        for (idx, step) in circuit.iter().enumerate() {
             match step {
                 CombineOperation::GF2(gate) => {
                     for wire in gate.inputs().chain(gate.outputs()) {
-                        let backref: String = match bool_hasher.backref(wire) {
-                            None => wire.to_string(),
-                            Some(s) => s.clone(),
-                        };
-                        let mut current_scope: &str = "bool_context";
-
-                        // We use :: to differentiate between scopes. This is a convention only used
-                        // by the BLIF parser, so it won't apply for other circuits.
-                        let mut scope_tokens = backref.split("::").peekable();
-                        // I didn't want to implement a nested hashmap, so instead we store all the
-                        // scopes in the same hashmap, and use "subscope" entries as pointers to different
-                        // entries. This involves some chasing to get to the correct entry.
-                        while let Some(t) = scope_tokens.next() {
-                            if scope_tokens.peek().is_some() {
-                                // If there are more scopes after this one, this is an intermediate scope.
-                                // We add a subscope entry and then chase it to the next scope.
-                                bool_scopes
-                                    .entry(current_scope.into())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(ScopeEntry::SubScope(t.into()));
-                                current_scope = t;
-                            } else {
-                                // When we get to the final entry, we add this wire to the current scope.
-                                bool_scopes
-                                    .entry(current_scope.into())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(ScopeEntry::Terminal((t.into(), wire)));
-                            }
-                        }
+                        let backref: String = bool_name(wire).unwrap_or_else(|| wire.to_string());
+                        add_to_scope(&mut bool_scopes, "bool_context", &backref, wire);
                     }
                 }
                 CombineOperation::Z64(gate) => {
                     for wire in gate.inputs().chain(gate.outputs()) {
-                        let backref: String = match arith_hasher.backref(wire) {
-                            None => wire.to_string(),
-                            Some(s) => s.clone(),
-                        };
-
-                        // Ditto on how the boolean scope parsing works, but we use a different
-                        // hashmap to store the arithmetic wires.
-                        let mut current_scope: &str = "arith_context";
-
-                        let mut scope_tokens = backref.split("::").peekable();
-                        while let Some(t) = scope_tokens.next() {
-                            if scope_tokens.peek().is_some() {
-                                // If this is an intermediate scope
-                                arith_scopes
-                                    .entry(current_scope.into())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(ScopeEntry::SubScope(t.into()));
-                                current_scope = t;
-                            } else {
-                                arith_scopes
-                                    .entry(current_scope.into())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(ScopeEntry::Terminal((t.into(), wire)));
-                            }
-                        }
+                        let backref: String = arith_name(wire).unwrap_or_else(|| wire.to_string());
+                        add_to_scope(&mut arith_scopes, "arith_context", &backref, wire);
                     }
                 }
                 CombineOperation::B2A(dst, low) => {
-                    // B2A gates are weird because they live in both the boolean and arithmetic
-                    // contexts. Right now, we track them, but don't actually dump them to the file.
+                    // B2A gates live in both contexts. Their wires are declared along with all the
+                    // others, and again in a scope of their own (see `write_conversions`).
+                    let backref: String = arith_name(*dst).unwrap_or_else(|| dst.to_string());
+                    add_to_scope(&mut arith_scopes, "arith_context", &backref, *dst);
 
-                    let backref: String = match arith_hasher.backref(*dst) {
-                        None => dst.to_string(),
-                        Some(s) => s.clone(),
-                    };
-                    let mut current_scope: &str = "b2a_context";
-
-                    // Arithmetic wires are handled normally
-                    let mut scope_tokens = backref.split("::").peekable();
-                    while let Some(t) = scope_tokens.next() {
-                        if scope_tokens.peek().is_some() {
-                            // If this is an intermediate scope
-                            arith_scopes
-                                .entry(current_scope.into())
-                                .or_insert_with(HashSet::new)
-                                .insert(ScopeEntry::SubScope(t.into()));
-                            current_scope = t;
-                        } else {
-                            arith_scopes
-                                .entry(current_scope.into())
-                                .or_insert_with(HashSet::new)
-                                .insert(ScopeEntry::Terminal((t.into(), *dst)));
-                        }
-                    }
-
-                    // For boolean wires, we need to track all 64 bits. I guess. They're inputs so
-                    // they really ought to be captured by the gates that write to them already, but
-                    // you might have a bad circuit structure.
+                    // The source bits ought to be captured by the gates that write to them
+                    // already, but you might have a bad circuit structure.
                     for wire in *low..*low + 64 {
-                        let backref: String = match bool_hasher.backref(wire) {
-                            None => wire.to_string(),
-                            Some(s) => s.clone(),
-                        };
-                        let mut current_scope: &str = "b2a_context";
-
-                        let mut scope_tokens = backref.split("::").peekable();
-                        while let Some(t) = scope_tokens.next() {
-                            if scope_tokens.peek().is_some() {
-                                // If this is an intermediate scope
-                                bool_scopes
-                                    .entry(current_scope.into())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(ScopeEntry::SubScope(t.into()));
-                                current_scope = t;
-                            } else {
-                                bool_scopes
-                                    .entry(current_scope.into())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(ScopeEntry::Terminal((t.into(), wire)));
-                            }
-                        }
+                        let backref: String = bool_name(wire).unwrap_or_else(|| wire.to_string());
+                        add_to_scope(&mut bool_scopes, "bool_context", &backref, wire);
                     }
+                    conversions.push((idx, *dst, *low));
                 }
                 CombineOperation::SizeHint(_, _) => {}
             }
@@ -270,30 +1474,27 @@ impl VcdDumper {
         writer
             .write_all("$version Generated by mcircuit $end\n$timescale 1ns $end\n\n".as_ref())
             .unwrap();
-        // Write the boolean scope.
-        VcdDumper::write_scope("bool_context", ScopeType::Bool, &mut writer, &bool_scopes)
-            .expect("Failed to write Boolean scopes");
+        // Write the boolean scope, unless the circuit doesn't have any boolean wires.
+        if !bool_scopes.is_empty() {
+            VcdDumper::write_scope("bool_context", ScopeType::Bool, &mut writer, &bool_scopes)
+                .expect("Failed to write Boolean scopes");
+        }
         // Write the arithmetic scope
-        VcdDumper::write_scope(
-            "arith_context",
-            ScopeType::Arith,
-            &mut writer,
-            &arith_scopes,
-        )
-        .expect("Failed to write Arithmetic scopes");
-
-        // VcdDumper::write_scope(
-        //     &"b2a_context".to_string(),
-        //     ScopeType::Bool,
-        //     &mut writer,
-        //     &bool_scopes,
-        // ).expect("Failed to write boolean B2A scope");
-        // VcdDumper::write_scope(
-        //     &"b2a_context".to_string(),
-        //     ScopeType::Arith,
-        //     &mut writer,
-        //     &arith_scopes,
-        // ).expect("Failed to write arithmetic B2A scope");
+        if !arith_scopes.is_empty() {
+            VcdDumper::write_scope(
+                "arith_context",
+                ScopeType::Arith,
+                &mut writer,
+                &arith_scopes,
+            )
+            .expect("Failed to write Arithmetic scopes");
+        }
+
+        // Write a scope for each conversion gate
+        if !conversions.is_empty() {
+            VcdDumper::write_conversions(&mut writer, &conversions)
+                .expect("Failed to write conversion scopes");
+        }
 
         // Write the end of the VCD header. This one worked with GTKWave for me, but didn't quite
         // match what I found on wikipedia and in this blog post: https://zipcpu.com/blog/2017/07/31/vcd.html
@@ -315,8 +1516,9 @@ impl VcdDumper {
     ) -> Result<(), ()> {
         if let Some(current) = scopes.get(scope) {
             // Write the scope header
+            let name = scope.rsplit("::").next().unwrap_or(scope);
             writer
-                .write_all(format!("$scope module {} $end\n", scope).as_ref())
+                .write_all(format!("$scope module {} $end\n", name).as_ref())
                 .unwrap();
 
             for entry in current {
@@ -363,6 +1565,26 @@ impl VcdDumper {
         }
     }
 
+    /// Writes a `conversions` scope with a scope for each B2A gate, named after the gate's index,
+    /// holding its output as `out` and the 64-bit window it reads as `in(0)` to `in(63)`. These
+    /// reuse the identifiers of the wires declared in the domain scopes, so they show the same
+    /// values.
+    fn write_conversions(
+        writer: &mut BufWriter<File>,
+        conversions: &[(usize, usize, usize)],
+    ) -> std::io::Result<()> {
+        writeln!(writer, "$scope module conversions $end")?;
+        for (idx, dst, low) in conversions {
+            writeln!(writer, "$scope module b2a_{} $end", idx)?;
+            writeln!(writer, "$var wire 64 @{} out $end", dst)?;
+            for bit in 0..64 {
+                writeln!(writer, "$var wire 1 !{} in({}) $end", low + bit, bit)?;
+            }
+            writeln!(writer, "$upscope $end")?;
+        }
+        writeln!(writer, "$upscope $end")
+    }
+
     /// Write a formatted boolean value into the VCD file. Can only be one bit.
     pub fn dump_bool(&mut self, dst: usize, val: bool) {
         self.writer
@@ -377,6 +1599,15 @@ impl VcdDumper {
             .unwrap();
     }
 
+    /// Write a comment into the value change section. VCD comments end at `$end`, so any in the
+    /// text is written as `$ end`, and newlines become spaces.
+    pub fn comment(&mut self, text: &str) {
+        let text = text.replace('\n', " ").replace("$end", "$ end");
+        self.writer
+            .write_all(format!("$comment {} $end\n", text).as_ref())
+            .unwrap();
+    }
+
     /// Write the end of the data dump section with some extra timing entries to make gtkwave show
     /// a wider display.
     pub fn finish(&mut self) {
@@ -391,9 +1622,43 @@ pub fn dump_vcd(
     program: &[CombineOperation],
     bool_inputs: &[bool],
     arith_inputs: &[u64],
+    dumper: VcdDumper,
+) {
+    dump_vcd_with_notes(
+        program,
+        &Annotations::new(),
+        bool_inputs,
+        arith_inputs,
+        dumper,
+    )
+}
+
+/// Like `dump_vcd`, but also writes the program's gate annotations as comments just before the
+/// values of the gates they describe.
+pub fn dump_annotated_vcd(
+    program: &Program,
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    dumper: VcdDumper,
+) {
+    let no_notes = Annotations::new();
+    dump_vcd_with_notes(
+        &program.gates,
+        program.annotations.as_ref().unwrap_or(&no_notes),
+        bool_inputs,
+        arith_inputs,
+        dumper,
+    )
+}
+
+fn dump_vcd_with_notes(
+    program: &[CombineOperation],
+    annotations: &Annotations,
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
     mut dumper: VcdDumper,
 ) {
-    let (bool_wire_count, arith_wire_count) = largest_wires(program);
+    let (arith_wire_count, bool_wire_count) = largest_wires(program);
 
     let mut bool_wires = vec![false; bool_wire_count];
     let mut bool_inputs = bool_inputs.iter().cloned();
@@ -401,7 +1666,10 @@ pub fn dump_vcd(
     let mut arith_wires = vec![0u64; arith_wire_count];
     let mut arith_inputs = arith_inputs.iter().cloned();
 
-    for step in program {
+    for (idx, step) in program.iter().enumerate() {
+        if let Some(note) = annotations.get(&idx) {
+            dumper.comment(note);
+        }
         match step {
             CombineOperation::GF2(gf2_insn) => match *gf2_insn {
                 Operation::Input(dst) => {
@@ -521,13 +1789,20 @@ pub fn dump_vcd(
 }
 
 /// Get the largest (arithmetic, boolean) wires in a program so we know how much memory to allocate.
-/// Respects size hints, if present at the start of the circuit
+/// Respects size hints wherever they are, taking the largest in each domain.
 pub fn largest_wires(program: &[CombineOperation]) -> (usize, usize) {
-    if let CombineOperation::SizeHint(z64_cells, gf2_cells) = program[0] {
-        (z64_cells, gf2_cells)
-    } else {
-        WireCounter::analyze(program.iter()).0
-    }
+    size_hint(program).unwrap_or_else(|| WireCounter::analyze(program.iter()).0)
+}
+
+/// The largest size hint in each domain, if the program has any. See the crate documentation.
+pub fn size_hint(program: &[CombineOperation]) -> Option<(usize, usize)> {
+    program
+        .iter()
+        .filter_map(|gate| match gate {
+            CombineOperation::SizeHint(z64, gf2) => Some((*z64, *gf2)),
+            _ => None,
+        })
+        .reduce(|(z64, gf2), (hint_z64, hint_gf2)| (z64.max(hint_z64), gf2.max(hint_gf2)))
 }
 
 /// Get the largest (arithmetic, boolean) wires in a program so we know how much memory to allocate.
@@ -0,0 +1,489 @@
+//! Evaluation on several threads. Gates are grouped into levels, where every gate in a level only
+//! depends on gates in earlier ones, and each level is evaluated in parallel with rayon.
+//!
+//! A gate depends on the gates that last wrote the wires it reads, and, since wires can be
+//! written more than once, on the gates that last read or wrote the wires it writes. Inputs are
+//! matched to witness values, and `Random` gates to random values, in program order before
+//! anything runs, so the result is the same as evaluating the gates one at a time.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use rayon::prelude::*;
+
+use crate::analysis::{AnalysisPass, WireCounter};
+use crate::eval::{size_hint, EvaluationError, EvaluationOutput, RandomSource, Randomness};
+use crate::{CombineOperation, Domain, HasIO, Operation};
+
+/// Levels with fewer gates than this are evaluated on the calling thread; splitting them up
+/// costs more than it saves.
+const PARALLEL_THRESHOLD: usize = 1 << 10;
+
+/// Wire values, shared between threads. Gates in the same level never touch the same wire (unless
+/// they both only read it), so relaxed atomics are enough; rayon orders the levels.
+struct Wires {
+    bool_wires: Vec<AtomicBool>,
+    arith_wires: Vec<AtomicU64>,
+}
+
+impl Wires {
+    fn bool(&self, wire: usize) -> bool {
+        self.bool_wires[wire].load(Ordering::Relaxed)
+    }
+
+    fn arith(&self, wire: usize) -> u64 {
+        self.arith_wires[wire].load(Ordering::Relaxed)
+    }
+
+    fn set_bool(&self, wire: usize, value: bool) {
+        self.bool_wires[wire].store(value, Ordering::Relaxed)
+    }
+
+    fn set_arith(&self, wire: usize, value: u64) {
+        self.arith_wires[wire].store(value, Ordering::Relaxed)
+    }
+}
+
+/// Number of (arithmetic, boolean) wires to allocate. Unlike the sequential evaluator, storage
+/// can't grow once the threads are running, so a stale size hint can't be trusted on its own.
+fn wire_counts(program: &[CombineOperation]) -> (usize, usize) {
+    let (arith, bool) = WireCounter::analyze(program.iter()).0;
+    let (arith_hint, bool_hint) = size_hint(program).unwrap_or_default();
+    (arith.max(arith_hint), bool.max(bool_hint))
+}
+
+/// A gate, its index in the program, and the witness value it reads if it's an `Input` (or the
+/// value it writes if it's a `Random`).
+type Scheduled<'p> = (usize, &'p CombineOperation, u64);
+
+/// Last level (plus one, so zero means none) to write and to read each wire of a domain.
+struct Hazards {
+    written: Vec<usize>,
+    read: Vec<usize>,
+}
+
+impl Hazards {
+    fn new(wires: usize) -> Self {
+        Hazards {
+            written: vec![0; wires],
+            read: vec![0; wires],
+        }
+    }
+}
+
+/// Index into a pair of per-domain tables.
+fn slot(domain: Domain) -> usize {
+    match domain {
+        Domain::GF2 => 0,
+        Domain::Z64 => 1,
+    }
+}
+
+/// Assigns gates to levels one at a time, in program order.
+struct Leveler {
+    hazards: [Hazards; 2],
+}
+
+impl Leveler {
+    fn new(program: &[CombineOperation]) -> Self {
+        let (arith_count, bool_count) = wire_counts(program);
+        Leveler {
+            hazards: [Hazards::new(bool_count), Hazards::new(arith_count)],
+        }
+    }
+
+    /// The level `gate` goes in, recording it as the last gate to read and write its wires.
+    fn place(&mut self, gate: &CombineOperation) -> usize {
+        let hazards = &mut self.hazards;
+        let (reads, writes) = (
+            gate.input_domain().map(slot),
+            gate.output_domain().map(slot),
+        );
+        let mut level = 0;
+        if let Some(domain) = reads {
+            let read = &hazards[domain];
+            level = gate
+                .inputs()
+                .map(|w| read.written[w])
+                .fold(level, usize::max);
+        }
+        if let Some(domain) = writes {
+            let written = &hazards[domain];
+            level = gate
+                .outputs()
+                .map(|w| written.written[w].max(written.read[w]))
+                .fold(level, usize::max);
+        }
+        if let Some(domain) = reads {
+            for wire in gate.inputs() {
+                let read = &mut hazards[domain].read[wire];
+                *read = (*read).max(level + 1);
+            }
+        }
+        if let Some(domain) = writes {
+            for wire in gate.outputs() {
+                hazards[domain].written[wire] = level + 1;
+            }
+        }
+        level
+    }
+}
+
+/// Whether `program` is wide enough for `evaluate_parallel` to be worth it: its levels have to
+/// average at least `PARALLEL_THRESHOLD` gates, or most of them would be evaluated on the
+/// calling thread anyway.
+pub(crate) fn worth_parallelizing(program: &[CombineOperation]) -> bool {
+    if program.len() < PARALLEL_THRESHOLD {
+        return false;
+    }
+    let mut leveler = Leveler::new(program);
+    let depth = program
+        .iter()
+        .filter(|gate| !matches!(gate, CombineOperation::SizeHint(_, _)))
+        .map(|gate| leveler.place(gate) + 1)
+        .max()
+        .unwrap_or(0);
+    program.len() / depth.max(1) >= PARALLEL_THRESHOLD
+}
+
+/// Groups the gates into levels, and the first `Input` the witness (or `Random` the randomness)
+/// has no value for, if any.
+fn levelize<'p>(
+    program: &'p [CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    mut random: RandomSource,
+) -> (Vec<Vec<Scheduled<'p>>>, Option<EvaluationError>) {
+    let mut leveler = Leveler::new(program);
+    let (mut bool_inputs, mut arith_inputs) = (bool_inputs.iter(), arith_inputs.iter());
+    let mut out_of_inputs = None;
+    let mut levels: Vec<Vec<Scheduled>> = Vec::new();
+
+    for (idx, gate) in program.iter().enumerate() {
+        if let CombineOperation::SizeHint(_, _) = gate {
+            continue;
+        }
+        let level = leveler.place(gate);
+
+        let input = match gate {
+            CombineOperation::GF2(Operation::Input(_)) => Some((
+                bool_inputs.next().map(|b| u64::from(*b)),
+                Domain::GF2,
+                false,
+            )),
+            CombineOperation::Z64(Operation::Input(_)) => {
+                Some((arith_inputs.next().copied(), Domain::Z64, false))
+            }
+            CombineOperation::GF2(Operation::Random(_)) => {
+                Some((random.next_bool().map(u64::from), Domain::GF2, true))
+            }
+            CombineOperation::Z64(Operation::Random(_)) => {
+                Some((random.next_arith(), Domain::Z64, true))
+            }
+            _ => None,
+        };
+        let value = match input {
+            Some((None, domain, random)) => {
+                out_of_inputs.get_or_insert(if random {
+                    EvaluationError::OutOfRandomness { gate: idx, domain }
+                } else {
+                    EvaluationError::OutOfInputs { gate: idx, domain }
+                });
+                0
+            }
+            Some((Some(value), _, _)) => value,
+            None => 0,
+        };
+
+        if levels.len() <= level {
+            levels.resize_with(level + 1, Vec::new);
+        }
+        levels[level].push((idx, gate, value));
+    }
+    (levels, out_of_inputs)
+}
+
+/// Evaluates one gate, returning the assertion it fails, if it's one that does.
+fn step(wires: &Wires, (idx, gate, input): Scheduled) -> Option<EvaluationError> {
+    let failed = |domain, wire, value| EvaluationError::AssertionFailed {
+        gate: idx,
+        domain,
+        wire,
+        value,
+    };
+    match *gate {
+        CombineOperation::GF2(op) => match op {
+            Operation::Input(dst) => wires.set_bool(dst, input == 1),
+            Operation::Random(dst) => wires.set_bool(dst, input == 1),
+            Operation::Add(dst, a, b) | Operation::Sub(dst, a, b) => {
+                wires.set_bool(dst, wires.bool(a) ^ wires.bool(b))
+            }
+            Operation::Mul(dst, a, b) => wires.set_bool(dst, wires.bool(a) & wires.bool(b)),
+            Operation::AddConst(dst, a, c) | Operation::SubConst(dst, a, c) => {
+                wires.set_bool(dst, wires.bool(a) ^ c)
+            }
+            Operation::MulConst(dst, a, c) => wires.set_bool(dst, wires.bool(a) & c),
+            Operation::AssertZero(src) => {
+                if wires.bool(src) {
+                    return Some(failed(Domain::GF2, src, 1));
+                }
+            }
+            Operation::Const(dst, c) => wires.set_bool(dst, c),
+        },
+        CombineOperation::Z64(op) => match op {
+            Operation::Input(dst) => wires.set_arith(dst, input),
+            Operation::Random(dst) => wires.set_arith(dst, input),
+            Operation::Add(dst, a, b) => {
+                wires.set_arith(dst, wires.arith(a).wrapping_add(wires.arith(b)))
+            }
+            Operation::Sub(dst, a, b) => {
+                wires.set_arith(dst, wires.arith(a).wrapping_sub(wires.arith(b)))
+            }
+            Operation::Mul(dst, a, b) => {
+                wires.set_arith(dst, wires.arith(a).wrapping_mul(wires.arith(b)))
+            }
+            Operation::AddConst(dst, a, c) => wires.set_arith(dst, wires.arith(a).wrapping_add(c)),
+            Operation::SubConst(dst, a, c) => wires.set_arith(dst, wires.arith(a).wrapping_sub(c)),
+            Operation::MulConst(dst, a, c) => wires.set_arith(dst, wires.arith(a).wrapping_mul(c)),
+            Operation::AssertZero(src) => {
+                let value = wires.arith(src);
+                if value != 0 {
+                    return Some(failed(Domain::Z64, src, value));
+                }
+            }
+            Operation::Const(dst, c) => wires.set_arith(dst, c),
+        },
+        CombineOperation::B2A(dst, low) => {
+            let value = (0..64).fold(0, |value, i| value | u64::from(wires.bool(low + i)) << i);
+            wires.set_arith(dst, value);
+        }
+        CombineOperation::SizeHint(_, _) => {}
+    }
+    None
+}
+
+/// Same as `evaluate_composite_program_checked` with the default `EvalConfig`, but evaluates
+/// independent gates in parallel, on rayon's thread pool. Worth it for programs that are wide as
+/// well as long; a program that's one long chain of gates gets nothing from it. Failures are the
+/// ones a sequential evaluation would report: if several gates fail, the error is for the first
+/// of them in program order.
+pub fn evaluate_parallel(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    bool_outputs: &[usize],
+    arith_outputs: &[usize],
+) -> Result<EvaluationOutput, EvaluationError> {
+    evaluate_parallel_with_randomness(
+        program,
+        bool_inputs,
+        arith_inputs,
+        bool_outputs,
+        arith_outputs,
+        Randomness::Entropy,
+    )
+}
+
+/// Same as `evaluate_parallel`, but with `Random` gates drawing their values from `randomness`.
+/// Values are drawn in program order, so a seed or tape gives the same result as it does with
+/// `evaluate_composite_program_with_randomness`.
+pub fn evaluate_parallel_with_randomness(
+    program: &[CombineOperation],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    bool_outputs: &[usize],
+    arith_outputs: &[usize],
+    randomness: Randomness,
+) -> Result<EvaluationOutput, EvaluationError> {
+    let (levels, out_of_inputs) = levelize(program, bool_inputs, arith_inputs, randomness.into());
+    let (arith_count, bool_count) = wire_counts(program);
+    let wires = Wires {
+        bool_wires: (0..bool_count).map(|_| AtomicBool::new(false)).collect(),
+        arith_wires: (0..arith_count).map(|_| AtomicU64::new(0)).collect(),
+    };
+
+    let mut error = out_of_inputs;
+    for level in levels {
+        let failed = if level.len() < PARALLEL_THRESHOLD {
+            level
+                .into_iter()
+                .filter_map(|gate| step(&wires, gate))
+                .min_by_key(|e| e.gate())
+        } else {
+            level
+                .into_par_iter()
+                .filter_map(|gate| step(&wires, gate))
+                .min_by_key(|e| e.gate())
+        };
+        error = match (error, failed) {
+            (Some(a), Some(b)) => Some(if b.gate() < a.gate() { b } else { a }),
+            (a, b) => a.or(b),
+        };
+    }
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    let read = |wire: usize, storage: &dyn Fn(usize) -> u64, count: usize| {
+        if wire < count {
+            storage(wire)
+        } else {
+            0
+        }
+    };
+    Ok(EvaluationOutput {
+        bool_outputs: bool_outputs
+            .iter()
+            .map(|w| read(*w, &|w| u64::from(wires.bool(w)), bool_count) == 1)
+            .collect(),
+        arith_outputs: arith_outputs
+            .iter()
+            .map(|w| read(*w, &|w| wires.arith(w), arith_count))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::parallel::{evaluate_parallel, evaluate_parallel_with_randomness};
+    use crate::eval::{
+        evaluate_composite_program_checked, evaluate_composite_program_with_randomness, EvalConfig,
+        EvaluationError, Randomness,
+    };
+    use crate::{generate_program, CombineOperation, Domain, Operation, Profile};
+
+    #[test]
+    fn test_parallel_evaluation() {
+        // Wire 1 is written twice, and read in between
+        let program = [
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 1)),
+            CombineOperation::Z64(Operation::Mul(2, 1, 1)),
+            CombineOperation::Z64(Operation::Const(1, 7)),
+            CombineOperation::Z64(Operation::Add(3, 1, 2)),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Const(1, false)),
+            CombineOperation::B2A(4, 0),
+            CombineOperation::Z64(Operation::SubConst(5, 3, 23)),
+            CombineOperation::Z64(Operation::AssertZero(5)),
+        ];
+        let output = evaluate_parallel(&program, &[true], &[3], &[0], &[1, 2, 3, 4]).unwrap();
+        assert_eq!(output.bool_outputs, [true]);
+        assert_eq!(output.arith_outputs, [7, 16, 23, 1]);
+
+        // The first failure in program order wins, whichever level it's in
+        assert_eq!(
+            evaluate_parallel(&program, &[true], &[], &[], &[]),
+            Err(EvaluationError::OutOfInputs {
+                gate: 0,
+                domain: Domain::Z64
+            })
+        );
+        assert!(matches!(
+            evaluate_parallel(&program, &[true], &[4], &[], &[]),
+            Err(EvaluationError::AssertionFailed { gate: 9, .. })
+        ));
+
+        // Big enough for levels to be split between threads
+        let generated = generate_program(&Profile::mixed_conversions(), 1 << 16, 7);
+        let outputs: Vec<usize> = (0..64).collect();
+        assert_eq!(
+            evaluate_parallel(
+                &generated.gates,
+                &generated.bool_inputs,
+                &generated.arith_inputs,
+                &outputs,
+                &outputs
+            ),
+            evaluate_composite_program_checked(
+                &generated.gates,
+                &generated.bool_inputs,
+                &generated.arith_inputs,
+                &outputs,
+                &outputs,
+                EvalConfig::default()
+            )
+        );
+    }
+    #[test]
+    fn test_parallel_randomness() {
+        // Random values are drawn in program order, whichever level their gates end up in
+        let program = [
+            CombineOperation::Z64(Operation::Random(0)),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 1)),
+            CombineOperation::Z64(Operation::Random(2)),
+            CombineOperation::GF2(Operation::Random(0)),
+            CombineOperation::Z64(Operation::Mul(3, 1, 2)),
+            CombineOperation::Z64(Operation::Random(1)),
+        ];
+        let outputs = [0, 1, 2, 3];
+        for randomness in [
+            Randomness::Seed(7),
+            Randomness::Tape {
+                bool_tape: &[true],
+                arith_tape: &[3, 4, 5],
+            },
+        ] {
+            assert_eq!(
+                evaluate_parallel_with_randomness(&program, &[], &[], &[0], &outputs, randomness),
+                evaluate_composite_program_with_randomness(
+                    &program,
+                    &[],
+                    &[],
+                    &[0],
+                    &outputs,
+                    EvalConfig::default(),
+                    randomness
+                )
+            );
+        }
+        assert_eq!(
+            evaluate_parallel_with_randomness(
+                &program,
+                &[],
+                &[],
+                &[],
+                &[],
+                Randomness::Tape {
+                    bool_tape: &[true],
+                    arith_tape: &[3, 4],
+                }
+            ),
+            Err(EvaluationError::OutOfRandomness {
+                gate: 5,
+                domain: Domain::Z64
+            })
+        );
+    }
+    #[test]
+    fn test_parallel_config() {
+        // Thousands of gates in two levels, and the same number in a chain
+        let mut wide: Vec<CombineOperation> = (0..1 << 12)
+            .map(|i| CombineOperation::Z64(Operation::Const(i, i as u64)))
+            .collect();
+        wide.push(CombineOperation::Z64(Operation::SubConst(1 << 12, 7, 7)));
+        wide.push(CombineOperation::Z64(Operation::AssertZero(1 << 12)));
+        let chain: Vec<CombineOperation> = (0..1 << 12)
+            .map(|i| CombineOperation::Z64(Operation::AddConst(i + 1, i, 1)))
+            .collect();
+        assert!(EvalConfig::for_program(&wide).parallel);
+        assert!(!EvalConfig::for_program(&chain).parallel);
+        assert!(!EvalConfig::for_program(&wide[..10]).parallel);
+
+        // The checked evaluators hand it to `evaluate_parallel`, which gives the same answers
+        let config = EvalConfig::for_program(&wide);
+        let serial = EvalConfig {
+            parallel: false,
+            ..config
+        };
+        let run = |program: &[CombineOperation], config| {
+            evaluate_composite_program_checked(program, &[], &[], &[], &[3, 4095], config)
+        };
+        assert_eq!(run(&wide, config), run(&wide, serial));
+        assert_eq!(run(&wide, config).unwrap().arith_outputs, [3, 4095]);
+        wide.push(CombineOperation::Z64(Operation::AssertZero(1)));
+        assert!(matches!(
+            run(&wide, config),
+            Err(EvaluationError::AssertionFailed { gate: 4098, .. })
+        ));
+    }
+}
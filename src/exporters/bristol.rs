@@ -1,22 +1,212 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind, Result, Write};
 
-use crate::exporters::Export;
-use crate::io_extractors::{InputIterator, OutputIterator};
-use crate::Operation;
+use crate::exporters::{check_witness, write_bit_lines, CircuitSignature, Export};
+use crate::{Bus, Domain, HasIO, Operation, Translatable};
 
 pub struct BristolFashion;
 
+/// A renumbered circuit, along with a map from its original wires to the new ones.
+pub type BristolLayout = (Vec<Operation<bool>>, HashMap<usize, usize>);
+
+/// Renumbers a circuit into the wire layout Bristol Fashion requires: the `Input` gates' wires
+/// come first (in program order), then every other gate's output (each write gets a new wire, so
+/// the result is in single-assignment form), and finally one output wire per `AssertZero`.
+/// Each assertion is replaced by an identity gate that copies the asserted wire into its output
+/// wire, followed by an assertion on that output wire; these all come at the end of the program.
+///
+/// Returns the renumbered circuit and a map from each original wire to the new wire holding its
+/// final value.
+pub fn bristol_layout(gates: &[Operation<bool>]) -> Result<BristolLayout> {
+    let mut wires: HashMap<usize, usize> = HashMap::new();
+    let mut layout = Vec::with_capacity(gates.len());
+    let mut asserted = Vec::new();
+
+    for gate in gates {
+        if let Operation::Input(w) = gate {
+            wires.insert(*w, layout.len());
+            layout.push(Operation::Input(layout.len()));
+        }
+    }
+
+    let mut next = layout.len();
+    for gate in gates {
+        if let Operation::Input(_) = gate {
+            continue;
+        }
+
+        let inputs = gate
+            .inputs()
+            .map(|w| {
+                wires.get(&w).copied().ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("wire {} is read before it's written", w),
+                    )
+                })
+            })
+            .collect::<Result<Vec<usize>>>()?;
+
+        match gate {
+            Operation::AssertZero(_) => asserted.push(inputs[0]),
+            _ => {
+                layout.push(
+                    gate.translate(inputs.iter().copied(), std::iter::once(next))
+                        .expect("Operations are always translatable"),
+                );
+                wires.insert(gate.dst().expect("Only assertions lack outputs"), next);
+                next += 1;
+            }
+        }
+    }
+
+    for (i, src) in asserted.iter().enumerate() {
+        layout.push(Operation::AddConst(next + i, *src, false));
+    }
+    for i in 0..asserted.len() {
+        layout.push(Operation::AssertZero(next + i));
+    }
+
+    Ok((layout, wires))
+}
+
+/// Splits a run of wires into values, one per bus whose wires appear next in the run (in bus
+/// order), and one for each wire that doesn't start such a bus. Returns the width of each value.
+fn value_widths(wires: &[usize], buses: &[Bus]) -> Vec<usize> {
+    let mut starts: HashMap<usize, &Bus> = HashMap::new();
+    for bus in buses.iter().filter(|bus| bus.domain == Domain::GF2) {
+        if let Some(first) = bus.wires.first() {
+            starts.entry(*first).or_insert(bus);
+        }
+    }
+
+    let mut widths = Vec::new();
+    let mut next = 0;
+    while next < wires.len() {
+        let width = starts
+            .get(&wires[next])
+            .filter(|bus| wires.get(next..next + bus.wires.len()) == Some(&bus.wires[..]))
+            .map_or(1, |bus| bus.wires.len());
+        widths.push(width);
+        next += width;
+    }
+    widths
+}
+
+/// Writes one of the value count lines of a Bristol Fashion header.
+fn write_widths(widths: &[usize], sink: &mut impl Write) -> Result<()> {
+    let widths: Vec<String> = widths.iter().map(|w| w.to_string()).collect();
+    writeln!(sink, "{} {}", widths.len(), widths.join(" "))
+}
+
+impl BristolFashion {
+    /// Like `export_circuit`, but groups inputs and outputs into multi-bit values, so the header
+    /// gives consumers like SCALE-MAMBA and MP-SPDZ the word widths they expect. A GF2 bus
+    /// becomes an input value if its wires are read by consecutive `Input` gates, in bus order,
+    /// and an output value if they're checked by consecutive `AssertZero` gates. Every other
+    /// input and output is a 1-bit value of its own.
+    pub fn export_with_buses(
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        buses: &[Bus],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let inputs: Vec<usize> = gates
+            .iter()
+            .filter_map(|g| match g {
+                Operation::Input(w) => Some(*w),
+                _ => None,
+            })
+            .collect();
+        let outputs: Vec<usize> = gates
+            .iter()
+            .filter_map(|g| match g {
+                Operation::AssertZero(w) => Some(*w),
+                _ => None,
+            })
+            .collect();
+
+        Self::export_grouped(
+            gates,
+            witness,
+            &value_widths(&inputs, buses),
+            &value_widths(&outputs, buses),
+            sink,
+        )
+    }
+
+    fn export_grouped(
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        input_widths: &[usize],
+        output_widths: &[usize],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        // Every Bristol Fashion circuit begins with a "header", which predeclares
+        // a few different input an output cardinalities. It looks like this:
+        //
+        //     {ngates} {nwires}
+        //     {niv} {ni_1,...,ni_niv}
+        //     {nov} {no_1,...,no_nov}
+        //
+        // Where {ngates} is the total number of gates, {nwires} is the total
+        // number of wires, {niv} and {nov} are the number of input and output
+        // values, respectively, and the lists that follow them describe the
+        // number of wires per input or output value.
+        //
+        // For example, a circuit with 6 gates, 12 wires, 2 input values of
+        // 1 wire each, and 1 output value of 1 wire would look like this:
+        //
+        //     6 12
+        //     2 1 1
+        //     1 1
+
+        check_witness(gates, witness)?;
+        let (gates, _) = bristol_layout(gates)?;
+
+        let output_count = gates
+            .iter()
+            .filter(|g| matches!(g, Operation::AssertZero(_)))
+            .count();
+        // Every gate but the assertions defines exactly one wire
+        let wire_count = gates.len() - output_count;
+
+        // {ngates} {nwires}
+        // Inputs are written as constant gates, and assertions are implied by the output wires, so
+        // every wire has exactly one gate.
+        writeln!(sink, "{} {}", wire_count, wire_count)?;
+
+        // {niv} {ni_1,...,ni_niv}
+        write_widths(input_widths, sink)?;
+
+        // {nov} {no_1,...,no_nov}
+        write_widths(output_widths, sink)?;
+
+        let mut wit_iter = witness.iter();
+
+        for gate in &gates {
+            match gate {
+                Operation::Input(o) => Self::export_gate(
+                    &Operation::Const(*o, *wit_iter.next().expect("Checked witness length")),
+                    sink,
+                )?,
+                // Bristol outputs are just the last wires, so there's nothing to write
+                Operation::AssertZero(_) => {}
+                _ => Self::export_gate(gate, sink)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl Export<bool> for BristolFashion {
     fn export_gate(gate: &Operation<bool>, sink: &mut impl Write) -> Result<()> {
         match gate {
             Operation::Input(w) => {
                 writeln!(sink, "0 1 {} INPUT", w)
             }
-            Operation::Random(_) => Err(Error::new(
-                ErrorKind::Other,
-                "can't use random gates in Bristol",
-            )),
+            Operation::Random(_) => Err(Error::other("can't use random gates in Bristol")),
             Operation::Add(o, l, r) => {
                 writeln!(sink, "2 1 {} {} {} XOR", l, r, o)
             }
@@ -63,90 +253,26 @@ impl Export<bool> for BristolFashion {
         witness: &[bool],
         sink: &mut impl Write,
     ) -> Result<()> {
-        // Every Bristol Fashion circuit begins with a "header", which predeclares
-        // a few different input an output cardinalities. It looks like this:
-        //
-        //     {ngates} {nwires}
-        //     {niv} {ni_1,...,ni_niv}
-        //     {nov} {no_1,...,no_nov}
-        //
-        // Where {ngates} is the total number of gates, {nwires} is the total
-        // number of wires, {niv} and {nov} are the number of input and output
-        // values, respectively, and the lists that follow them describe the
-        // number of wires per output value.
-        //
-        // For example, a circuit with 6 gates, 12 wires, 2 input values of
-        // 1 wire each, and 1 output value of 1 wire would look like this:
-        //
-        //     6 12
-        //     2 1 1
-        //     1 1
-
-        let mut wires = HashSet::new();
-        let mut output_count = 0;
-        for gate in gates {
-            // Add all input and output wires in the operation to the set of seen wires.
-            wires.extend(InputIterator::new(gate));
-            wires.extend(OutputIterator::new(gate));
-
-            if matches!(gate, Operation::AssertZero(_)) {
-                output_count += 1;
-            }
-        }
-
-        // {ngates} {nwires}
-        writeln!(sink, "{} {}", gates.len(), wires.len())?;
-
-        // {niv} {ni_1,...,ni_niv}
-        // Each input is 1 bit.
-        writeln!(
-            sink,
-            "{} {}",
-            witness.len(),
-            std::iter::repeat("1")
-                .take(witness.len())
-                .collect::<Vec<_>>()
-                .join(" ")
-        )?;
-
-        // {nov} {no_1,...,no_nov}
-        // Each output is 1 bit...I think.
-        writeln!(
-            sink,
-            "{} {}",
-            output_count,
-            std::iter::repeat("1")
-                .take(output_count)
-                .collect::<Vec<_>>()
-                .join(" ")
-        )?;
-
-        let mut wit_iter = witness.iter();
-
-        for gate in gates {
-            match gate {
-                Operation::Input(o) => Self::export_gate(
-                    &Operation::Const(
-                        *o,
-                        *wit_iter
-                            .next()
-                            .ok_or_else(|| Error::new(ErrorKind::Other, "witness too short"))?,
-                    ),
-                    sink,
-                )?,
-                _ => Self::export_gate(gate, sink)?,
-            }
-        }
+        Self::export_with_buses(gates, witness, &[], sink)
+    }
 
-        Ok(())
+    /// Writes the input values as bit strings, one bit per line, in the order of the circuit's
+    /// `Input` gates (which is also the order of the input values in its header).
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        signature.check(witness)?;
+        write_bit_lines(witness, sink)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::exporters::bristol::BristolFashion;
-    use crate::exporters::Export;
-    use crate::Operation;
+    use crate::exporters::bristol::{bristol_layout, BristolFashion};
+    use crate::exporters::{CircuitSignature, Export};
+    use crate::{Bus, Domain, Operation};
 
     #[test]
     fn print_example() {
@@ -171,7 +297,100 @@ mod tests {
         let bf = std::str::from_utf8(&sink).unwrap();
         assert_eq!(
             bf,
-            "8 7\n3 1 1 1\n1 1\n1 1 0 1 EQ\n1 1 0 2 EQ\n1 1 1 3 EQ\n2 1 1 3 4 XOR\n2 1 2 3 5 XOR\n2 1 5 4 6 AND\n1 1 6 0 INV\n0 1 0 OUTPUT\n"
+            "8 8\n3 1 1 1\n1 1\n1 1 0 0 EQ\n1 1 0 1 EQ\n1 1 1 2 EQ\n2 1 0 2 3 XOR\n2 1 1 2 4 XOR\n2 1 4 3 5 AND\n1 1 5 6 INV\n1 1 6 7 EQW\n"
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let (layout, wires) = bristol_layout(&[
+            Operation::Const(9, true),
+            Operation::Input(4),
+            Operation::AddConst(9, 9, true),
+            Operation::AssertZero(9),
+            Operation::Input(2),
+            Operation::Mul(3, 2, 4),
+            Operation::AssertZero(4),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            layout,
+            vec![
+                Operation::Input(0),
+                Operation::Input(1),
+                Operation::Const(2, true),
+                Operation::AddConst(3, 2, true),
+                Operation::Mul(4, 1, 0),
+                Operation::AddConst(5, 3, false),
+                Operation::AddConst(6, 0, false),
+                Operation::AssertZero(5),
+                Operation::AssertZero(6),
+            ]
         );
+        assert_eq!(wires[&9], 3);
+        assert_eq!(wires[&3], 4);
+
+        assert!(bristol_layout(&[Operation::AssertZero(0)]).is_err());
+    }
+
+    #[test]
+    fn test_witness_length() {
+        let mut sink = Vec::new();
+        assert!(BristolFashion::export_circuit(
+            &[Operation::Input(0), Operation::AssertZero(0)],
+            &[],
+            &mut sink
+        )
+        .is_err());
+        assert!(sink.is_empty());
+
+        let gates: [Operation<bool>; 2] = [Operation::Input(0), Operation::Input(1)];
+        let signature = CircuitSignature::of(&gates);
+        assert!(BristolFashion::export_witness(&signature, &[true], &mut sink).is_err());
+        assert!(sink.is_empty());
+        BristolFashion::export_witness(&signature, &[true, false], &mut sink).unwrap();
+        assert_eq!(sink, b"1\n0\n");
+    }
+
+    #[test]
+    fn test_multi_bit_values() {
+        let bus = |name: &str, wires: &[usize]| Bus {
+            name: name.into(),
+            domain: Domain::GF2,
+            wires: wires.to_vec(),
+        };
+        let gates = [
+            Operation::Input(0),
+            Operation::Input(1),
+            Operation::Input(2),
+            Operation::Input(3),
+            Operation::Add(4, 0, 2),
+            Operation::Add(5, 1, 3),
+            Operation::Mul(6, 4, 5),
+            Operation::AssertZero(4),
+            Operation::AssertZero(5),
+            Operation::AssertZero(6),
+        ];
+        let buses = [
+            bus("a", &[0, 1]),
+            bus("b", &[2, 3]),
+            bus("sum", &[4, 5]),
+            // Not read in bus order, so these stay separate values
+            bus("backwards", &[6, 5]),
+        ];
+
+        let mut sink = Vec::new();
+        BristolFashion::export_with_buses(&gates, &[false; 4], &buses, &mut sink).unwrap();
+        let bf = std::str::from_utf8(&sink).unwrap();
+        let header: Vec<&str> = bf.lines().take(3).collect();
+        assert_eq!(header, vec!["10 10", "2 2 2", "2 2 1"]);
+
+        // Without buses, every value is one bit
+        let mut sink = Vec::new();
+        BristolFashion::export_circuit(&gates, &[false; 4], &mut sink).unwrap();
+        let bf = std::str::from_utf8(&sink).unwrap();
+        let header: Vec<&str> = bf.lines().take(3).collect();
+        assert_eq!(header, vec!["10 10", "4 1 1 1 1", "3 1 1 1"]);
     }
 }
@@ -0,0 +1,184 @@
+//! Header parameters for the SIEVE IR exporters, so circuits can target other fields and versions
+//! of the specs than the ones the exporters default to, and the details of IR1's text that
+//! consumers disagree on.
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Result};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Field;
+
+/// Details of IR1 text that the spec leaves open, or that consumers read more strictly than it
+/// asks. Deserializes from partial configs, with anything missing left at its default, so a
+/// dialect can be kept in a tool's config file instead of code.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ir1Dialect {
+    /// The `version` declared unless an `ExportConfig` sets one
+    pub version: String,
+    /// Whether constants have spaces inside their brackets, as in `< 1 >`, or not, as in `<1>`
+    pub spaced_constants: bool,
+    /// Whether witness values are indented with a tab
+    pub indent_witness: bool,
+    /// Whether the gate set lists the directives the circuit uses, like `@and,@xor`, instead of
+    /// naming the whole set (`boolean` or `arithmetic`). Only applies when an `ExportConfig`
+    /// doesn't set the gate set itself.
+    pub list_gate_set: bool,
+}
+
+impl Default for Ir1Dialect {
+    /// What the IR1 exporter has always written
+    fn default() -> Self {
+        Ir1Dialect {
+            version: "1.0.0".into(),
+            spaced_constants: true,
+            indent_witness: true,
+            list_gate_set: false,
+        }
+    }
+}
+
+impl Ir1Dialect {
+    /// Names of the presets, for `preset`.
+    pub const PRESETS: &'static [&'static str] = &["default", "zki-sieve", "wiztoolkit"];
+
+    /// A preset by name. The names say which tool each preset is meant for, but the presets are
+    /// only settings; they haven't been checked against particular releases of those tools.
+    /// * `default`: what the exporter writes without a dialect
+    /// * `zki-sieve`: constants without spaces in their brackets (`<1>`), and witness values
+    ///   without indentation
+    /// * `wiztoolkit`: a gate set that lists the directives the circuit uses, like `@and,@xor`,
+    ///   rather than naming the whole set
+    pub fn preset(name: &str) -> Option<Self> {
+        let default = Ir1Dialect::default();
+        match name {
+            "default" => Some(default),
+            "zki-sieve" => Some(Ir1Dialect {
+                spaced_constants: false,
+                indent_witness: false,
+                ..default
+            }),
+            "wiztoolkit" => Some(Ir1Dialect {
+                list_gate_set: true,
+                ..default
+            }),
+            _ => None,
+        }
+    }
+
+    /// A constant in brackets.
+    pub(crate) fn constant(&self, value: impl Display) -> String {
+        if self.spaced_constants {
+            format!("< {} >", value)
+        } else {
+            format!("<{}>", value)
+        }
+    }
+}
+
+/// What the IR1 and IR0 exporters declare in their headers. Every exporter still checks that the
+/// circuit can be represented under the declared header, and fails without writing anything if it
+/// can't.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportConfig {
+    /// The field the circuit is over. GF2 gates can only be exported in `Field::GF2`.
+    pub field: Field,
+    /// The `version` the output claims to follow. `None` for the exporter's own version (the
+    /// dialect's for IR1, 2.0.0-beta for IR0).
+    pub version: Option<String>,
+    /// IR1's `gate_set`: `boolean`, `arithmetic`, or a comma-separated list of the directives
+    /// allowed, like `@and,@xor`. `None` for whichever named set matches the circuit's gates.
+    /// IR0 has no gate set.
+    pub gate_set: Option<String>,
+    /// IR1's `features`, besides `@function`, which is added whenever the export uses functions.
+    /// IR0 has no features.
+    pub features: Vec<String>,
+    /// How IR1's text is laid out. IR0 ignores it.
+    pub dialect: Ir1Dialect,
+}
+
+impl ExportConfig {
+    /// The default header for circuits over `field`.
+    pub fn new(field: Field) -> Self {
+        ExportConfig {
+            field,
+            version: None,
+            gate_set: None,
+            features: Vec::new(),
+            dialect: Ir1Dialect::default(),
+        }
+    }
+
+    pub(crate) fn version_or<'a>(&'a self, default: &'a str) -> &'a str {
+        self.version.as_deref().unwrap_or(default)
+    }
+
+    /// The IR1 `version`, from the config or else the dialect.
+    pub(crate) fn ir1_version(&self) -> &str {
+        self.version_or(&self.dialect.version)
+    }
+
+    /// The gate set to declare for gates whose named set is `named`, checking that it allows
+    /// every directive in `directives`.
+    pub(crate) fn gate_set_for(
+        &self,
+        named: &str,
+        directives: impl IntoIterator<Item = &'static str>,
+    ) -> Result<String> {
+        let gate_set = match &self.gate_set {
+            None if self.dialect.list_gate_set => {
+                let used: BTreeSet<&str> = directives.into_iter().collect();
+                if used.is_empty() {
+                    return Ok(named.to_string());
+                }
+                return Ok(used.into_iter().collect::<Vec<_>>().join(","));
+            }
+            None => return Ok(named.to_string()),
+            Some(gate_set) => gate_set.as_str(),
+        };
+        if gate_set == "boolean" || gate_set == "arithmetic" {
+            return if gate_set == named {
+                Ok(gate_set.to_string())
+            } else {
+                Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} gates can't be declared as gate set {}", named, gate_set),
+                ))
+            };
+        }
+
+        let allowed: Vec<&str> = gate_set.split(',').map(str::trim).collect();
+        match directives.into_iter().find(|d| !allowed.contains(d)) {
+            None => Ok(gate_set.to_string()),
+            Some(missing) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "the circuit uses {}, which gate set {} doesn't allow",
+                    missing, gate_set
+                ),
+            )),
+        }
+    }
+
+    /// The `features` to declare, with `@function` if the export uses functions.
+    pub(crate) fn features_with(&self, uses_functions: bool) -> Vec<&str> {
+        let mut features: Vec<&str> = self.features.iter().map(String::as_str).collect();
+        if uses_functions && !features.contains(&"@function") {
+            features.push("@function");
+        }
+        features
+    }
+
+    /// Fails if the config sets anything only IR1 has a header for.
+    pub(crate) fn check_ir0(&self) -> Result<()> {
+        if self.gate_set.is_some() || !self.features.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "IR0 has no gate_set or features header",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Compares the output of two export formats (or two versions of one) directive by directive,
+//! rather than line by line. Meant for checking format migrations: the diff shows which headers
+//! and gates changed meaning, without the noise of renumbered lines or reworded comments.
+//!
+//! Directives are read from SIEVE-style text: statements end at `;` or the end of a line, and
+//! `//` comments are ignored. Assignments (`$3 <- @xor($1, $2)`) are matched up by the wire they
+//! assign; every other directive is matched up by its kind and how many of that kind came before
+//! it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, Result, Write};
+
+use crate::exporters::export_by_name;
+use crate::Program;
+
+/// One statement of an export, split into its parts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Directive {
+    /// The wire being assigned, for assignments
+    pub target: Option<String>,
+    /// The operation (like `@xor` or `const`) for assignments, or the first word otherwise
+    pub op: String,
+    pub operands: Vec<String>,
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(target) = &self.target {
+            write!(f, "{} <- ", target)?;
+        }
+        write!(f, "{}", self.op)?;
+        if !self.operands.is_empty() {
+            write!(f, "({})", self.operands.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DirectiveChange {
+    Added(Directive),
+    Removed(Directive),
+    Changed { before: Directive, after: Directive },
+}
+
+/// The differences between two exports: removals and changes in the order of the first export,
+/// followed by additions in the order of the second.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportDiff {
+    pub changes: Vec<DirectiveChange>,
+}
+
+fn parse_statement(statement: &str) -> Directive {
+    let split_operands = |args: &str| -> Vec<String> {
+        args.split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let split_call = |call: &str| -> (String, Vec<String>) {
+        match call.split_once('(') {
+            Some((op, args)) => (
+                op.trim().to_string(),
+                split_operands(args.trim_end().trim_end_matches(')')),
+            ),
+            None => {
+                let mut words = call.split_whitespace();
+                let op = words.next().unwrap_or_default().to_string();
+                (op, words.map(String::from).collect())
+            }
+        }
+    };
+
+    match statement.split_once("<-") {
+        Some((target, value)) => {
+            let value = value.trim();
+            let (op, operands) = if value.starts_with('@') {
+                split_call(value)
+            } else {
+                ("const".to_string(), vec![value.to_string()])
+            };
+            Directive {
+                target: Some(target.trim().to_string()),
+                op,
+                operands,
+            }
+        }
+        None => {
+            let (op, operands) = split_call(statement);
+            Directive {
+                target: None,
+                op,
+                operands,
+            }
+        }
+    }
+}
+
+/// Splits an export into directives.
+pub fn parse_directives(text: &str) -> Vec<Directive> {
+    text.lines()
+        .map(|line| line.split("//").next().unwrap_or_default())
+        .flat_map(|line| line.split(';'))
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(parse_statement)
+        .collect()
+}
+
+/// Pairs each directive with the key used to match it against the other export.
+fn keyed(directives: Vec<Directive>) -> Vec<(String, Directive)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    directives
+        .into_iter()
+        .map(|d| {
+            let base = d.target.clone().unwrap_or_else(|| d.op.clone());
+            let count = seen.entry(base.clone()).or_default();
+            *count += 1;
+            (format!("{}#{}", base, count), d)
+        })
+        .collect()
+}
+
+/// Compares two exports directive by directive.
+pub fn diff_directives(before: &str, after: &str) -> ExportDiff {
+    let after = keyed(parse_directives(after));
+    let mut unmatched: HashMap<&str, &Directive> =
+        after.iter().map(|(key, d)| (key.as_str(), d)).collect();
+    let mut changes = Vec::new();
+
+    for (key, old) in keyed(parse_directives(before)) {
+        match unmatched.remove(key.as_str()) {
+            Some(new) if *new == old => {}
+            Some(new) => changes.push(DirectiveChange::Changed {
+                before: old,
+                after: new.clone(),
+            }),
+            None => changes.push(DirectiveChange::Removed(old)),
+        }
+    }
+    changes.extend(
+        after
+            .iter()
+            .filter(|(key, _)| unmatched.contains_key(key.as_str()))
+            .map(|(_, d)| DirectiveChange::Added(d.clone())),
+    );
+
+    ExportDiff { changes }
+}
+
+/// Exports `program` with the formats registered as `before` and `after` and compares the
+/// results. Only the first sink of each export is compared.
+pub fn diff_exports(
+    before: &str,
+    after: &str,
+    program: &Program,
+    bool_witness: &[bool],
+    arith_witness: &[u64],
+) -> Result<ExportDiff> {
+    let export = |format: &str| -> Result<String> {
+        let mut sink = Vec::new();
+        export_by_name(
+            format,
+            program,
+            bool_witness,
+            arith_witness,
+            &mut [&mut sink],
+        )?;
+        String::from_utf8(sink).map_err(Error::other)
+    };
+    Ok(diff_directives(&export(before)?, &export(after)?))
+}
+
+impl ExportDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Writes the diff one change per line, prefixed with `-`, `+`, or `~`.
+    pub fn write_text(&self, sink: &mut impl Write) -> Result<()> {
+        for change in &self.changes {
+            match change {
+                DirectiveChange::Removed(d) => writeln!(sink, "- {}", d)?,
+                DirectiveChange::Added(d) => writeln!(sink, "+ {}", d)?,
+                DirectiveChange::Changed { before, after } => {
+                    writeln!(sink, "~ {} => {}", before, after)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::diff::{diff_directives, diff_exports, Directive, DirectiveChange};
+    use crate::{CombineOperation, Operation, Program};
+
+    #[test]
+    fn test_ignores_formatting() {
+        let before = "version 1.0.0;\n$2 <- @xor($0, $1);\n@assert_zero($2);\n";
+        let after = "// reformatted\nversion 1.0.0;  $2 <- @xor( $0,$1 ) ;\n@assert_zero($2);";
+        assert!(diff_directives(before, after).is_empty());
+    }
+
+    #[test]
+    fn test_ir_upgrade() {
+        let program: Program = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+            CombineOperation::GF2(Operation::AssertZero(1)),
+        ]
+        .into();
+        let diff = diff_exports("ir1", "ir0", &program, &[true], &[]).unwrap();
+
+        let gate = |op: &str, operands: &[&str]| Directive {
+            target: Some("$1".into()),
+            op: op.into(),
+            operands: operands.iter().map(|o| o.to_string()).collect(),
+        };
+        assert!(diff.changes.contains(&DirectiveChange::Changed {
+            before: gate("@xor", &["$0", "< 1 >"]),
+            after: gate("@addc", &["$0", "< 1 >"]),
+        }));
+        // The assertion means the same thing in both versions
+        assert!(!diff.changes.iter().any(|c| match c {
+            DirectiveChange::Changed { before, .. } => before.op == "@assert_zero",
+            _ => false,
+        }));
+
+        let mut text = Vec::new();
+        diff.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("~ version(1.0.0) => version(2.0.0-beta)"));
+    }
+}
@@ -0,0 +1,297 @@
+//! Export to Graphviz DOT, for looking at the structure of small mixed-domain circuits.
+//!
+//! Each gate is a node, with an edge from the gate that last wrote each wire it reads. B2A gates
+//! also get a record node for the 64-bit GF2 window they consume, with a field per bit, so it's
+//! easy to see where the bits of each conversion come from. Windows are collapsed to their first
+//! and last few bits, with a single field standing in for the rest.
+//!
+//! Wires are labelled with their names when the program has a name table, and with `b3`/`z3`
+//! (for GF2/Z64 wire 3) otherwise. Gate annotations become the node's tooltip.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Result, Write};
+
+use crate::{Annotations, CombineOperation, Domain, HasIO, NameTable};
+
+/// Options for DOT export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dot {
+    /// How many bits of a B2A window to show individually. Set it to 64 or more to see them all.
+    pub bus_fields: usize,
+}
+
+impl Default for Dot {
+    fn default() -> Self {
+        Dot { bus_fields: 8 }
+    }
+}
+
+/// The wire's name from `names`, or `b`/`z` and its index if it has none.
+fn wire_label(names: Option<&NameTable>, domain: Domain, wire: usize) -> String {
+    match names.and_then(|names| names.get(domain, wire)) {
+        Some(name) => name.clone(),
+        None => match domain {
+            Domain::GF2 => format!("b{}", wire),
+            Domain::Z64 => format!("z{}", wire),
+        },
+    }
+}
+
+/// Escapes `text` for a quoted DOT string.
+fn quote(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Escapes `text` for a field of a record label, where braces, bars and angle brackets mean
+/// something.
+fn record_field(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in quote(text).chars() {
+        if matches!(c, '{' | '}' | '|' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Dot {
+    /// The record field of a B2A window that shows `bit`.
+    fn port(&self, bit: usize) -> String {
+        let (head, tail) = self.split();
+        if bit < head || bit >= 64 - tail {
+            format!("p{}", bit)
+        } else {
+            "rest".to_string()
+        }
+    }
+
+    /// How many bits to show at the start and the end of a window
+    fn split(&self) -> (usize, usize) {
+        if self.bus_fields >= 64 {
+            (64, 0)
+        } else {
+            (self.bus_fields.div_ceil(2), self.bus_fields / 2)
+        }
+    }
+
+    fn bus_label(&self, names: Option<&NameTable>, low: usize) -> String {
+        let (head, tail) = self.split();
+        let bit_label = |bit: usize| record_field(&wire_label(names, Domain::GF2, low + bit));
+        let mut fields: Vec<String> = (0..head)
+            .map(|bit| format!("<p{}> {}", bit, bit_label(bit)))
+            .collect();
+        if head + tail < 64 {
+            fields.push(format!(
+                "<rest> {} ... {}",
+                bit_label(head),
+                bit_label(63 - tail)
+            ));
+        }
+        fields.extend((64 - tail..64).map(|bit| format!("<p{}> {}", bit, bit_label(bit))));
+        fields.join("|")
+    }
+
+    /// Writes `gates` as a directed graph. Size hints are left out.
+    pub fn export_program(&self, gates: &[CombineOperation], sink: &mut impl Write) -> Result<()> {
+        self.export_annotated_program(gates, None, &Annotations::new(), sink)
+    }
+
+    /// Like `export_program`, but labels wires with their names from `names`, and gives each gate
+    /// with an annotation (keyed by index into `gates`) a tooltip showing it.
+    pub fn export_annotated_program(
+        &self,
+        gates: &[CombineOperation],
+        names: Option<&NameTable>,
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let mut writer = DotWriter::new(*self, names, annotations, sink)?;
+        for (idx, gate) in gates.iter().enumerate() {
+            writer.write_gate(idx, gate)?;
+        }
+        writer.finish()
+    }
+}
+
+/// Writes a DOT graph a gate at a time.
+pub(crate) struct DotWriter<'a, W> {
+    dot: Dot,
+    names: Option<&'a NameTable>,
+    annotations: &'a Annotations,
+    /// The gate that last wrote each wire
+    writers: HashMap<(Domain, usize), usize>,
+    sink: W,
+}
+
+impl<'a, W: Write> DotWriter<'a, W> {
+    /// Starts the graph. `annotations` are keyed by the indices that will be passed to
+    /// `write_gate`.
+    pub fn new(
+        dot: Dot,
+        names: Option<&'a NameTable>,
+        annotations: &'a Annotations,
+        mut sink: W,
+    ) -> Result<Self> {
+        writeln!(sink, "digraph circuit {{")?;
+        writeln!(sink, "  node [shape=box];")?;
+        Ok(DotWriter {
+            dot,
+            names,
+            annotations,
+            writers: HashMap::new(),
+            sink,
+        })
+    }
+
+    /// Writes the gate at index `idx` in the program.
+    pub fn write_gate(&mut self, idx: usize, gate: &CombineOperation) -> Result<()> {
+        let DotWriter {
+            dot,
+            names,
+            annotations,
+            writers,
+            sink,
+        } = self;
+        let names = *names;
+        let label = |domain, wire| quote(&wire_label(names, domain, wire));
+        let tooltip = match annotations.get(&idx) {
+            Some(note) => format!(", tooltip=\"{}\"", quote(note)),
+            None => String::new(),
+        };
+        let (domain, kind) = match gate {
+            CombineOperation::GF2(op) => (Domain::GF2, format!("GF2 {}", op.kind())),
+            CombineOperation::Z64(op) => (Domain::Z64, format!("Z64 {}", op.kind())),
+            CombineOperation::B2A(dst, low) => {
+                writeln!(
+                    sink,
+                    "  g{} [label=\"{}: B2A -> {}\", shape=diamond{}];",
+                    idx,
+                    idx,
+                    label(Domain::Z64, *dst),
+                    tooltip
+                )?;
+                writeln!(
+                    sink,
+                    "  bus{} [shape=record, label=\"{}\"];",
+                    idx,
+                    dot.bus_label(names, *low)
+                )?;
+                writeln!(sink, "  bus{} -> g{};", idx, idx)?;
+
+                // One edge per writer and field, so collapsed bits don't repeat edges
+                let mut edges = BTreeSet::new();
+                for bit in 0..64 {
+                    if let Some(writer) = writers.get(&(Domain::GF2, low + bit)) {
+                        edges.insert((*writer, dot.port(bit)));
+                    }
+                }
+                for (writer, port) in edges {
+                    writeln!(sink, "  g{} -> bus{}:{};", writer, idx, port)?;
+                }
+                writers.insert((Domain::Z64, *dst), idx);
+                return Ok(());
+            }
+            CombineOperation::SizeHint(_, _) => return Ok(()),
+        };
+
+        let node = match gate.dst() {
+            Some(dst) => format!("{}: {} -> {}", idx, kind, label(domain, dst)),
+            None => format!("{}: {}", idx, kind),
+        };
+        writeln!(sink, "  g{} [label=\"{}\"{}];", idx, node, tooltip)?;
+        for wire in gate.inputs() {
+            if let Some(writer) = writers.get(&(domain, wire)) {
+                writeln!(
+                    sink,
+                    "  g{} -> g{} [label=\"{}\"];",
+                    writer,
+                    idx,
+                    label(domain, wire)
+                )?;
+            }
+        }
+        if let Some(dst) = gate.dst() {
+            writers.insert((domain, dst), idx);
+        }
+        Ok(())
+    }
+
+    /// Ends the graph.
+    pub fn finish(mut self) -> Result<()> {
+        writeln!(self.sink, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::Dot;
+    use crate::{Annotations, CombineOperation, Domain, NameTable, Operation};
+
+    #[test]
+    fn test_dot() {
+        let mut program: Vec<CombineOperation> = (0..64)
+            .map(|w| CombineOperation::GF2(Operation::Input(w)))
+            .collect();
+        program.push(CombineOperation::GF2(Operation::AddConst(1, 0, true)));
+        program.push(CombineOperation::B2A(0, 0));
+        program.push(CombineOperation::Z64(Operation::AssertZero(0)));
+
+        let mut sink = Vec::new();
+        Dot::default().export_program(&program, &mut sink).unwrap();
+        let dot = String::from_utf8(sink).unwrap();
+        assert!(
+            dot.contains("  g64 [label=\"64: GF2 AddConst -> b1\"];\n  g0 -> g64 [label=\"b0\"];")
+        );
+        assert!(dot.contains("  g65 [label=\"65: B2A -> z0\", shape=diamond];"));
+        assert!(dot.contains(
+            "  bus65 [shape=record, label=\"<p0> b0|<p1> b1|<p2> b2|<p3> b3|<rest> b4 ... b59|\
+             <p60> b60|<p61> b61|<p62> b62|<p63> b63\"];"
+        ));
+        // Bit 1 was overwritten, and each collapsed bit has its own writer here
+        assert!(dot.contains("  g64 -> bus65:p1;"));
+        assert!(!dot.contains("  g1 -> bus65:p1;"));
+        assert!(dot.contains("  g4 -> bus65:rest;"));
+        assert_eq!(dot.matches("-> bus65:rest;").count(), 56);
+        assert!(dot.contains("  g65 -> g66 [label=\"z0\"];"));
+
+        let mut sink = Vec::new();
+        Dot { bus_fields: 64 }
+            .export_program(&program, &mut sink)
+            .unwrap();
+        let dot = String::from_utf8(sink).unwrap();
+        assert!(!dot.contains("rest"));
+        assert!(dot.contains("  g33 -> bus65:p33;"));
+    }
+
+    #[test]
+    fn test_dot_names_and_annotations() {
+        let mut program: Vec<CombineOperation> = (0..64)
+            .map(|w| CombineOperation::GF2(Operation::Input(w)))
+            .collect();
+        program.push(CombineOperation::B2A(0, 0));
+        program.push(CombineOperation::Z64(Operation::AddConst(1, 0, 1)));
+        let mut names = NameTable::default();
+        names.insert(Domain::GF2, 0, "carry<0>".to_string());
+        names.insert(Domain::Z64, 0, "sum".to_string());
+        let mut annotations = Annotations::new();
+        annotations.insert(64, "pack the \"carry\" bits".to_string());
+        annotations.insert(65, "increment".to_string());
+
+        let mut sink = Vec::new();
+        Dot::default()
+            .export_annotated_program(&program, Some(&names), &annotations, &mut sink)
+            .unwrap();
+        let dot = String::from_utf8(sink).unwrap();
+        assert!(dot.contains(
+            "  g64 [label=\"64: B2A -> sum\", shape=diamond, \
+             tooltip=\"pack the \\\"carry\\\" bits\"];"
+        ));
+        // Record fields escape the characters that structure them
+        assert!(dot.contains("label=\"<p0> carry\\<0\\>|<p1> b1|"));
+        assert!(dot.contains("  g65 [label=\"65: Z64 AddConst -> z1\", tooltip=\"increment\"];"));
+        assert!(dot.contains("  g64 -> g65 [label=\"sum\"];"));
+    }
+}
@@ -0,0 +1,333 @@
+//! Finds repeated stretches of gates, like the bodies of unrolled loops, so the SIEVE IR exporters
+//! can write each one out once as a `@function` and call it everywhere else.
+//!
+//! Two stretches have the same body if they're made of the same gates, wired up the same way
+//! relative to each other. Wires a stretch reads before writing are its inputs, and the values
+//! it writes that are read after it ends are its outputs. Inside a body, wires are numbered the
+//! way SIEVE functions number them: outputs first, then inputs, then everything else, and every
+//! wire is written only once.
+
+use std::collections::{HashMap, HashSet};
+
+use std::io::{Result, Write};
+
+use crate::exporters::{write_line_comment, Export};
+use crate::{Annotations, HasIO, Operation, Translatable};
+
+/// How hard to look for repeated bodies. Detection takes time proportional to the number of
+/// gates times `max_len` squared in the worst case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FunctionOptions {
+    /// Fewest gates in a function body
+    pub min_len: usize,
+    /// Most gates in a function body
+    pub max_len: usize,
+}
+
+impl Default for FunctionOptions {
+    fn default() -> Self {
+        FunctionOptions {
+            min_len: 4,
+            max_len: 32,
+        }
+    }
+}
+
+/// A function body, with its wires renumbered.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Function {
+    pub gates: Vec<Operation<bool>>,
+    pub outputs: usize,
+    pub inputs: usize,
+    /// Number of `Input` gates in the body
+    pub witness: usize,
+}
+
+/// What to write for part of a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Item {
+    /// The gate at this index, as is
+    Gate(usize),
+    /// A call standing in for the gates in `gates`
+    Call {
+        function: usize,
+        outputs: Vec<usize>,
+        inputs: Vec<usize>,
+        gates: std::ops::Range<usize>,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Slot {
+    Input(usize),
+    Local(usize),
+}
+
+/// For every gate, the index of the last gate that reads the value it writes
+fn last_uses(gates: &[Operation<bool>]) -> Vec<Option<usize>> {
+    let mut last_use = vec![None; gates.len()];
+    let mut writers = HashMap::new();
+    for (idx, gate) in gates.iter().enumerate() {
+        for wire in gate.inputs() {
+            if let Some(writer) = writers.get(&wire) {
+                last_use[*writer] = Some(idx);
+            }
+        }
+        if let Some(dst) = gate.dst() {
+            writers.insert(dst, idx);
+        }
+    }
+    last_use
+}
+
+/// The body of `gates[range]`, along with the outputs and inputs a call to it would have.
+fn extract(
+    gates: &[Operation<bool>],
+    last_use: &[Option<usize>],
+    range: std::ops::Range<usize>,
+) -> (Function, Vec<usize>, Vec<usize>) {
+    let end = range.end;
+    let mut current: HashMap<usize, Slot> = HashMap::new();
+    let mut input_wires = Vec::new();
+    // The gate that writes each local
+    let mut locals = Vec::new();
+    let mut shaped = Vec::with_capacity(range.len());
+    for idx in range {
+        let gate = &gates[idx];
+        let inputs: Vec<Slot> = gate
+            .inputs()
+            .map(|wire| {
+                *current.entry(wire).or_insert_with(|| {
+                    input_wires.push(wire);
+                    Slot::Input(input_wires.len() - 1)
+                })
+            })
+            .collect();
+        let output = gate.dst().map(|wire| {
+            locals.push(idx);
+            let slot = Slot::Local(locals.len() - 1);
+            current.insert(wire, slot);
+            slot
+        });
+        shaped.push((gate, inputs, output));
+    }
+
+    let is_output = |local: usize| last_use[locals[local]].is_some_and(|u| u >= end);
+    let outputs: Vec<usize> = (0..locals.len()).filter(|l| is_output(*l)).collect();
+    let mut numbers = vec![0; locals.len()];
+    let mut next = outputs.len() + input_wires.len();
+    for (local, number) in numbers.iter_mut().enumerate() {
+        if let Some(position) = outputs.iter().position(|o| *o == local) {
+            *number = position;
+        } else {
+            *number = next;
+            next += 1;
+        }
+    }
+    let number = |slot: &Slot| match slot {
+        Slot::Input(k) => outputs.len() + k,
+        Slot::Local(l) => numbers[*l],
+    };
+
+    let function = Function {
+        gates: shaped
+            .iter()
+            .map(|(gate, inputs, output)| {
+                gate.translate(inputs.iter().map(number), output.iter().map(number))
+                    .expect("Operations are always translatable")
+            })
+            .collect(),
+        outputs: outputs.len(),
+        inputs: input_wires.len(),
+        witness: shaped
+            .iter()
+            .filter(|(gate, _, _)| matches!(gate, Operation::Input(_)))
+            .count(),
+    };
+    let output_wires = outputs
+        .iter()
+        .map(|local| gates[locals[*local]].dst().unwrap())
+        .collect();
+    (function, output_wires, input_wires)
+}
+
+/// Splits `gates` into calls to repeated bodies and the gates between them. A body becomes a
+/// function once it shows up twice in a row; after that, it's called wherever else it shows up.
+pub(crate) fn find_functions(
+    gates: &[Operation<bool>],
+    options: FunctionOptions,
+) -> (Vec<Function>, Vec<Item>) {
+    let last_use = last_uses(gates);
+    let min_len = options.min_len.max(1);
+    let mut functions: Vec<Function> = Vec::new();
+    let mut known: HashMap<Function, usize> = HashMap::new();
+    let mut items = Vec::new();
+
+    // Lengths and first gates of the known bodies, to skip stretches that can't match cheaply
+    let mut shapes: HashSet<(usize, &str)> = HashSet::new();
+
+    let mut idx = 0;
+    'scan: while idx < gates.len() {
+        let longest = options.max_len.min(gates.len() - idx);
+        for len in (min_len..=longest).rev() {
+            let next = idx + len..idx + 2 * len;
+            let may_repeat =
+                next.end <= gates.len() && gates[idx].kind() == gates[next.start].kind();
+            if !may_repeat && !shapes.contains(&(len, gates[idx].kind())) {
+                continue;
+            }
+
+            let (function, outputs, inputs) = extract(gates, &last_use, idx..idx + len);
+            let id = match known.get(&function) {
+                Some(id) => *id,
+                // Only worth defining if the next stretch repeats it
+                None if may_repeat && extract(gates, &last_use, next).0 == function => {
+                    shapes.insert((len, gates[idx].kind()));
+                    functions.push(function.clone());
+                    known.insert(function, functions.len() - 1);
+                    functions.len() - 1
+                }
+                None => continue,
+            };
+            items.push(Item::Call {
+                function: id,
+                outputs,
+                inputs,
+                gates: idx..idx + len,
+            });
+            idx += len;
+            continue 'scan;
+        }
+        items.push(Item::Gate(idx));
+        idx += 1;
+    }
+    (functions, items)
+}
+
+fn wire_list(wires: &[usize]) -> String {
+    wires
+        .iter()
+        .map(|w| format!("${}", w))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes the definitions of `functions`, then `items` with calls to them. `signature` gives the
+/// rest of a `@function(...)` line after the function's name, which is `f` and its index.
+/// Annotations of gates replaced by a call are written before the call.
+pub(crate) fn write_items<E: Export<bool>>(
+    gates: &[Operation<bool>],
+    (functions, items): &(Vec<Function>, Vec<Item>),
+    annotations: &Annotations,
+    signature: fn(&Function) -> String,
+    sink: &mut impl Write,
+) -> Result<()> {
+    for (id, function) in functions.iter().enumerate() {
+        writeln!(sink, "@function(f{}, {})", id, signature(function))?;
+        writeln!(sink, "@begin")?;
+        for gate in &function.gates {
+            E::export_gate(gate, sink)?;
+        }
+        writeln!(sink, "@end")?;
+    }
+
+    for item in items {
+        match item {
+            Item::Gate(idx) => {
+                if let Some(note) = annotations.get(idx) {
+                    write_line_comment(note, sink)?;
+                }
+                E::export_gate(&gates[*idx], sink)?;
+            }
+            Item::Call {
+                function,
+                outputs,
+                inputs,
+                gates,
+            } => {
+                for idx in gates.clone() {
+                    if let Some(note) = annotations.get(&idx) {
+                        write_line_comment(note, sink)?;
+                    }
+                }
+                let args = if inputs.is_empty() {
+                    String::new()
+                } else {
+                    format!(", {}", wire_list(inputs))
+                };
+                if outputs.is_empty() {
+                    writeln!(sink, "@call(f{}{});", function, args)?;
+                } else {
+                    writeln!(
+                        sink,
+                        "{} <- @call(f{}{});",
+                        wire_list(outputs),
+                        function,
+                        args
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::functions::{find_functions, FunctionOptions, Item};
+    use crate::Operation;
+
+    #[test]
+    fn test_find_functions() {
+        // A ripple of full adders, sharing the carry
+        let mut gates = vec![Operation::Input(0)];
+        for i in 0..4 {
+            let (a, b, carry) = (10 * i + 1, 10 * i + 2, 10 * i);
+            gates.extend([
+                Operation::Input(a),
+                Operation::Input(b),
+                Operation::Add(a + 2, a, b),
+                Operation::Add(a + 3, a + 2, carry),
+                Operation::Mul(a + 4, a, b),
+                Operation::Mul(a + 5, a + 2, carry),
+                Operation::Add(carry + 10, a + 4, a + 5),
+                Operation::AssertZero(a + 3),
+            ]);
+        }
+        gates.push(Operation::AssertZero(40));
+
+        // Two adders at a time is the longest body that repeats
+        let (functions, items) = find_functions(&gates, FunctionOptions::default());
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].gates.len(), 16);
+        assert_eq!(items.len(), 4);
+
+        let options = FunctionOptions {
+            min_len: 4,
+            max_len: 8,
+        };
+        let (functions, items) = find_functions(&gates, options);
+        assert_eq!(functions.len(), 1);
+        let adder = &functions[0];
+        assert_eq!((adder.outputs, adder.inputs, adder.witness), (1, 1, 2));
+        assert_eq!(adder.gates[0], Operation::Input(2));
+        assert_eq!(adder.gates[6], Operation::Add(0, 6, 7));
+        assert_eq!(items.len(), 6);
+        assert_eq!(items[0], Item::Gate(0));
+        assert_eq!(
+            items[4],
+            Item::Call {
+                function: 0,
+                outputs: vec![40],
+                inputs: vec![30],
+                gates: 25..33,
+            }
+        );
+        assert_eq!(items[5], Item::Gate(33));
+
+        // Nothing repeats
+        let (functions, items) = find_functions(&gates[..9], FunctionOptions::default());
+        assert!(functions.is_empty());
+        assert_eq!(items.len(), 9);
+    }
+}
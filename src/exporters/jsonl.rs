@@ -0,0 +1,206 @@
+//! Export to the line-oriented JSON format `parsers::jsonl` reads, which documents it. Every gate
+//! survives the round trip, including conversions and size hints, so it works as an interchange
+//! format between tools.
+
+use std::io::{Result, Write};
+
+use crate::exporters::{CircuitSignature, Export};
+use crate::{CombineOperation, Operation, WireValue};
+
+pub struct JSONL;
+
+/// Largest integer every JSON library can represent exactly. Bigger Z64 values are written as
+/// strings.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// A value written the way the format writes constants.
+trait JsonlValue: WireValue {
+    const DOMAIN_NAME: &'static str;
+
+    fn to_json(&self) -> String;
+}
+
+impl JsonlValue for bool {
+    const DOMAIN_NAME: &'static str = "gf2";
+
+    fn to_json(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl JsonlValue for u64 {
+    const DOMAIN_NAME: &'static str = "z64";
+
+    fn to_json(&self) -> String {
+        if *self <= MAX_SAFE_INTEGER {
+            self.to_string()
+        } else {
+            format!("\"{}\"", self)
+        }
+    }
+}
+
+fn write_line(
+    op: &str,
+    domain: Option<&str>,
+    args: &[usize],
+    c: Option<String>,
+    sink: &mut impl Write,
+) -> Result<()> {
+    write!(sink, "{{\"op\": \"{}\"", op)?;
+    if let Some(domain) = domain {
+        write!(sink, ", \"domain\": \"{}\"", domain)?;
+    }
+    let args: Vec<String> = args.iter().map(usize::to_string).collect();
+    write!(sink, ", \"args\": [{}]", args.join(", "))?;
+    if let Some(c) = c {
+        write!(sink, ", \"const\": {}", c)?;
+    }
+    writeln!(sink, "}}")
+}
+
+fn write_operation<T: JsonlValue>(gate: &Operation<T>, sink: &mut impl Write) -> Result<()> {
+    let (op, args, c) = match gate {
+        Operation::Input(w) => ("input", vec![*w], None),
+        Operation::Random(w) => ("random", vec![*w], None),
+        Operation::Add(o, l, r) => ("add", vec![*o, *l, *r], None),
+        Operation::AddConst(o, i, c) => ("addc", vec![*o, *i], Some(c.to_json())),
+        Operation::Sub(o, l, r) => ("sub", vec![*o, *l, *r], None),
+        Operation::SubConst(o, i, c) => ("subc", vec![*o, *i], Some(c.to_json())),
+        Operation::Mul(o, l, r) => ("mul", vec![*o, *l, *r], None),
+        Operation::MulConst(o, i, c) => ("mulc", vec![*o, *i], Some(c.to_json())),
+        Operation::AssertZero(w) => ("assert_zero", vec![*w], None),
+        Operation::Const(w, c) => ("const", vec![*w], Some(c.to_json())),
+    };
+    write_line(op, Some(T::DOMAIN_NAME), &args, c, sink)
+}
+
+/// Writes the witness one value per line, in the same form as constants.
+fn write_values<T: JsonlValue>(witness: &[T], sink: &mut impl Write) -> Result<()> {
+    for value in witness {
+        writeln!(sink, "{}", value.to_json())?;
+    }
+    Ok(())
+}
+
+impl JSONL {
+    /// Writes one gate of a program, on its own line.
+    pub fn write_gate(gate: &CombineOperation, sink: &mut impl Write) -> Result<()> {
+        match gate {
+            CombineOperation::GF2(op) => write_operation(op, sink),
+            CombineOperation::Z64(op) => write_operation(op, sink),
+            CombineOperation::B2A(dst, low) => write_line("b2a", None, &[*dst, *low], None, sink),
+            CombineOperation::SizeHint(z64, gf2) => {
+                write_line("size_hint", None, &[*z64, *gf2], None, sink)
+            }
+        }
+    }
+
+    /// Writes a whole program, which can mix domains.
+    pub fn export_program(gates: &[CombineOperation], sink: &mut impl Write) -> Result<()> {
+        for gate in gates {
+            Self::write_gate(gate, sink)?;
+        }
+        Ok(())
+    }
+}
+
+/// Gates only; the witness goes in a separate file of one value per line.
+impl Export<bool> for JSONL {
+    const WRITES_WITNESS: bool = false;
+
+    fn export_gate(gate: &Operation<bool>, sink: &mut impl Write) -> Result<()> {
+        write_operation(gate, sink)
+    }
+
+    fn export_circuit(gates: &[Operation<bool>], _: &[bool], sink: &mut impl Write) -> Result<()> {
+        for gate in gates {
+            Self::export_gate(gate, sink)?;
+        }
+        Ok(())
+    }
+
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        signature.check(witness)?;
+        write_values(witness, sink)
+    }
+}
+
+/// Gates only, as for GF2.
+impl Export<u64> for JSONL {
+    const WRITES_WITNESS: bool = false;
+
+    fn export_gate(gate: &Operation<u64>, sink: &mut impl Write) -> Result<()> {
+        write_operation(gate, sink)
+    }
+
+    fn export_circuit(gates: &[Operation<u64>], _: &[u64], sink: &mut impl Write) -> Result<()> {
+        for gate in gates {
+            Self::export_gate(gate, sink)?;
+        }
+        Ok(())
+    }
+
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[u64],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        signature.check(witness)?;
+        write_values(witness, sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::exporters::{CircuitSignature, Export, JSONL};
+    use crate::parsers::jsonl::JsonlParser;
+    use crate::{CombineOperation, Operation};
+
+    #[test]
+    fn test_round_trip() {
+        let program = [
+            CombineOperation::SizeHint(3, 64),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Random(1)),
+            CombineOperation::GF2(Operation::MulConst(2, 0, true)),
+            CombineOperation::GF2(Operation::SubConst(3, 1, false)),
+            CombineOperation::GF2(Operation::Sub(4, 2, 3)),
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::Const(1, 1 << 53)),
+            CombineOperation::Z64(Operation::AddConst(2, 0, u64::MAX)),
+            CombineOperation::Z64(Operation::Mul(2, 1, 2)),
+            CombineOperation::Z64(Operation::AssertZero(2)),
+        ];
+        let mut sink = Vec::new();
+        JSONL::export_program(&program, &mut sink).unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.starts_with(
+            "{\"op\": \"size_hint\", \"args\": [3, 64]}
+{\"op\": \"input\", \"domain\": \"gf2\", \"args\": [0]}
+"
+        ));
+        assert!(text.contains(
+            "{\"op\": \"const\", \"domain\": \"z64\", \"args\": [1], \"const\": \"9007199254740992\"}"
+        ));
+
+        let (parsed, errors) = JsonlParser::from_reader(Cursor::new(&sink)).read_all();
+        assert_eq!(errors, []);
+        assert_eq!(parsed, program);
+    }
+
+    #[test]
+    fn print_witness() {
+        let gates: [Operation<u64>; 2] = [Operation::Input(0), Operation::Input(1)];
+        let mut sink = Vec::new();
+        JSONL::export_witness(&CircuitSignature::of(&gates), &[7, u64::MAX], &mut sink).unwrap();
+        assert_eq!(sink, b"7\n\"18446744073709551615\"\n");
+        assert!(JSONL::export_witness(&CircuitSignature::of(&gates), &[7], &mut sink).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Circuit statistics and pipeline timings in the Prometheus text exposition format, for services
+//! that compile circuits continuously and want to track their size and cost over time.
+//!
+//! Every metric is a gauge named `mcircuit_*`, carrying the labels set on `Metrics` along with
+//! any of its own (like `domain` or `stage`). Write the output somewhere a Prometheus scraper (or
+//! the node exporter's textfile collector) can pick it up.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Error, ErrorKind, Result, Write};
+
+use crate::exporters::{DomainSummary, Summary};
+use crate::StageReport;
+
+/// What to report, and the labels to attach to every sample.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    /// Labels for every sample, like the name of the circuit
+    pub labels: BTreeMap<String, String>,
+    pub summary: Option<Summary>,
+    /// Reports from a pipeline run, in order
+    pub stages: Vec<StageReport>,
+}
+
+/// Labels that some metrics set on their own samples, so they can't be set on `Metrics` too.
+const SAMPLE_LABELS: [&str; 5] = ["domain", "index", "kind", "stage", "when"];
+
+/// Checks that `key` can be used as a label on every sample: it has to be a valid Prometheus
+/// label name (`[a-zA-Z_][a-zA-Z0-9_]*`, without the reserved `__` prefix), and not one of
+/// `SAMPLE_LABELS`, since a sample with the same label twice is rejected by scrapers.
+fn check_label(key: &str) -> Result<()> {
+    let mut chars = key.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !key.starts_with("__");
+    let problem = if !valid {
+        "isn't a valid Prometheus label name"
+    } else if SAMPLE_LABELS.contains(&key) {
+        "is set by the metrics themselves"
+    } else {
+        return Ok(());
+    };
+    Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!("label {:?} {}", key, problem),
+    ))
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the samples of one metric, with its help text and type.
+struct Family<'a> {
+    name: &'static str,
+    labels: &'a BTreeMap<String, String>,
+    samples: Vec<(Vec<(&'static str, String)>, String)>,
+}
+
+impl<'a> Family<'a> {
+    fn new(name: &'static str, labels: &'a BTreeMap<String, String>) -> Self {
+        Family {
+            name,
+            labels,
+            samples: Vec::new(),
+        }
+    }
+
+    fn sample(mut self, labels: Vec<(&'static str, String)>, value: impl ToString) -> Self {
+        self.samples.push((labels, value.to_string()));
+        self
+    }
+
+    fn write(self, help: &str, sink: &mut impl Write) -> Result<()> {
+        writeln!(sink, "# HELP mcircuit_{} {}", self.name, help)?;
+        writeln!(sink, "# TYPE mcircuit_{} gauge", self.name)?;
+        for (labels, value) in self.samples {
+            let mut text = String::new();
+            let all = self
+                .labels
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .chain(labels.iter().map(|(k, v)| (*k, v.as_str())));
+            for (key, value) in all {
+                let sep = if text.is_empty() { "" } else { "," };
+                let _ = write!(text, "{}{}=\"{}\"", sep, key, escape(value));
+            }
+            if text.is_empty() {
+                writeln!(sink, "mcircuit_{} {}", self.name, value)?;
+            } else {
+                writeln!(sink, "mcircuit_{}{{{}}} {}", self.name, text, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Adds a label to every sample. `write` fails if `key` isn't a valid Prometheus label name,
+    /// or is one of the labels metrics set themselves (`domain`, `index`, `kind`, `stage` and
+    /// `when`).
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.labels.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn summary(mut self, summary: Summary) -> Self {
+        self.summary = Some(summary);
+        self
+    }
+
+    pub fn stages(mut self, stages: &[StageReport]) -> Self {
+        self.stages = stages.to_vec();
+        self
+    }
+
+    /// Writes every metric there's data for. Metrics without samples are left out entirely.
+    /// Nothing is written if any of the labels is invalid.
+    pub fn write(&self, sink: &mut impl Write) -> Result<()> {
+        for key in self.labels.keys() {
+            check_label(key)?;
+        }
+        let labels = &self.labels;
+        if let Some(summary) = &self.summary {
+            let domains = [("gf2", &summary.gf2), ("z64", &summary.z64)];
+            let per_domain = |name, value: fn(&DomainSummary) -> usize| {
+                let mut family = Family::new(name, labels);
+                for (domain, stats) in domains {
+                    family = family.sample(vec![("domain", domain.to_string())], value(stats));
+                }
+                family
+            };
+
+            Family::new("gates", labels)
+                .sample(vec![], summary.gates)
+                .write("Gates in the program", sink)?;
+            per_domain("domain_gates", |d| d.gates).write("Gates in each domain", sink)?;
+            let mut kinds = Family::new("gates_by_kind", labels);
+            for (domain, stats) in domains {
+                for (kind, count) in &stats.kinds {
+                    kinds = kinds.sample(
+                        vec![("domain", domain.to_string()), ("kind", kind.clone())],
+                        count,
+                    );
+                }
+            }
+            kinds.write("Gates of each kind in each domain", sink)?;
+            per_domain("inputs", |d| d.inputs).write("Input gates in each domain", sink)?;
+            per_domain("assertions", |d| d.assertions)
+                .write("AssertZero gates in each domain", sink)?;
+            per_domain("mul_depth", |d| d.mul_depth)
+                .write("Multiplicative depth of each domain", sink)?;
+            Family::new("conversions", labels)
+                .sample(vec![], summary.conversions)
+                .write("B2A gates in the program", sink)?;
+            Family::new("depth", labels)
+                .sample(vec![], summary.depth)
+                .write("Longest chain of dependent gates", sink)?;
+        }
+
+        if !self.stages.is_empty() {
+            let stage_labels = |report: &StageReport| {
+                vec![
+                    ("index", report.index.to_string()),
+                    ("stage", report.stage.name().to_string()),
+                ]
+            };
+            let mut seconds = Family::new("pipeline_stage_seconds", labels);
+            let mut gates = Family::new("pipeline_stage_gates", labels);
+            let mut wires = Family::new("pipeline_stage_wires", labels);
+            for report in &self.stages {
+                seconds = seconds.sample(stage_labels(report), report.elapsed.as_secs_f64());
+                for (when, count) in [
+                    ("before", report.gates_before),
+                    ("after", report.gates_after),
+                ] {
+                    let mut l = stage_labels(report);
+                    l.push(("when", when.to_string()));
+                    gates = gates.sample(l, count);
+                }
+                for (when, (z64, gf2)) in [
+                    ("before", report.wires_before),
+                    ("after", report.wires_after),
+                ] {
+                    for (domain, count) in [("gf2", gf2), ("z64", z64)] {
+                        let mut l = stage_labels(report);
+                        l.push(("when", when.to_string()));
+                        l.push(("domain", domain.to_string()));
+                        wires = wires.sample(l, count);
+                    }
+                }
+            }
+            seconds.write("Time each pipeline stage took", sink)?;
+            gates.write("Gates before and after each pipeline stage", sink)?;
+            wires.write("Wires used before and after each pipeline stage", sink)?;
+
+            let total: f64 = self.stages.iter().map(|r| r.elapsed.as_secs_f64()).sum();
+            Family::new("pipeline_seconds", labels)
+                .sample(vec![], total)
+                .write("Time the whole pipeline took", sink)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::exporters::{Metrics, Summary};
+    use crate::{CombineOperation, Operation, Pipeline, Stage};
+
+    #[test]
+    fn test_metrics() {
+        let program = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            CombineOperation::GF2(Operation::Mul(3, 0, 1)),
+            CombineOperation::GF2(Operation::AssertZero(3)),
+        ];
+        let (optimized, reports) = Pipeline::new()
+            .then(Stage::Cse)
+            .then(Stage::DeadCode { keep_inputs: true })
+            .run(program);
+
+        let mut sink = Vec::new();
+        Metrics::new()
+            .label("circuit", "and \"gate\"")
+            .summary(Summary::of(&optimized))
+            .stages(&reports)
+            .write(&mut sink)
+            .unwrap();
+        let text = String::from_utf8(sink).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines[0], "# HELP mcircuit_gates Gates in the program");
+        assert_eq!(lines[1], "# TYPE mcircuit_gates gauge");
+        assert_eq!(lines[2], "mcircuit_gates{circuit=\"and \\\"gate\\\"\"} 5");
+        assert!(lines.contains(
+            &"mcircuit_gates_by_kind{circuit=\"and \\\"gate\\\"\",domain=\"gf2\",kind=\"Mul\"} 1"
+        ));
+        assert!(lines.contains(
+            &"mcircuit_pipeline_stage_gates{circuit=\"and \\\"gate\\\"\",index=\"1\",\
+              stage=\"dead_code\",when=\"before\"} 5"
+        ));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("mcircuit_pipeline_seconds{")));
+        // Every sample is a name, optional labels and a number
+        for line in lines.iter().filter(|l| !l.starts_with('#')) {
+            let value = line.rsplit(' ').next().unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{}", line);
+        }
+
+        let mut sink = Vec::new();
+        Metrics::new().write(&mut sink).unwrap();
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_labels() {
+        let summary = Summary::of(&[CombineOperation::GF2(Operation::Input(0))]);
+        for key in ["stage", "domain", "9lives", "circuit-name", "", "__name__"] {
+            let mut sink = Vec::new();
+            let err = Metrics::new()
+                .label(key, "x")
+                .summary(summary.clone())
+                .write(&mut sink)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", key);
+            assert!(sink.is_empty());
+        }
+
+        let mut sink = Vec::new();
+        Metrics::new()
+            .label("_circuit2", "x")
+            .summary(summary)
+            .write(&mut sink)
+            .unwrap();
+        assert!(!sink.is_empty());
+    }
+}
@@ -1,23 +1,226 @@
-use std::io::{Result, Write};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result, Write};
 
-use crate::{Operation, WireValue};
+use serde::{Deserialize, Serialize};
+
+use crate::program::ContentHasher;
+use crate::{Annotations, CombineOperation, Domain, Operation, Program, WireValue};
 
 mod bristol;
+mod config;
+mod diff;
+mod dot;
+mod functions;
 mod json;
+mod jsonl;
+mod metrics;
+mod multi;
+mod parallel;
+mod registry;
+mod shdl;
 mod sieve;
 mod sievephase2;
+mod skeleton;
+mod stats;
+mod summary;
+mod window;
 
-pub use bristol::BristolFashion;
+pub use bristol::{bristol_layout, BristolFashion, BristolLayout};
+pub use config::{ExportConfig, Ir1Dialect};
+pub use diff::{
+    diff_directives, diff_exports, parse_directives, Directive, DirectiveChange, ExportDiff,
+};
+pub use dot::Dot;
+pub use functions::FunctionOptions;
 pub use json::bool_circuit_to_json;
-pub use sieve::IR1;
+pub use jsonl::JSONL;
+pub use metrics::Metrics;
+pub use multi::{FileSinks, MultiSink};
+pub use registry::{
+    export_by_name, export_many, exporter_names, register_exporter, BooleanExporter, Exporter,
+    GateWriter,
+};
+pub use shdl::Shdl;
+pub use sieve::{IR1Violation, IR1};
 pub use sievephase2::IR0;
+pub use skeleton::Skeleton;
+pub use stats::{ModuleStats, StatsReport, STATS_SCHEMA_VERSION};
+pub use summary::{DomainSummary, Summary, SummaryPass};
+pub use window::{export_window, Window};
 
 /// The core export trait.
 ///
 /// Individual exporters (such as for Bristol-fashion circuits) are expected
 /// to implement this trait.
 pub trait Export<T: WireValue> {
+    /// Whether `export_circuit` writes the witness out. Exporters that do check it against the
+    /// circuit's inputs with `check_witness` before writing anything.
+    const WRITES_WITNESS: bool = true;
+
     fn export_gate(gate: &Operation<T>, sink: &mut impl Write) -> Result<()>;
 
     fn export_circuit(gates: &[Operation<T>], witness: &[T], sink: &mut impl Write) -> Result<()>;
+
+    /// Like `export_circuit`, but also writes `annotations` (keyed by index into `gates`) as
+    /// comments before the gates they describe. Formats without comments just drop them.
+    fn export_annotated_circuit(
+        gates: &[Operation<T>],
+        witness: &[T],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let _ = annotations;
+        Self::export_circuit(gates, witness, sink)
+    }
+
+    /// Writes a witness on its own, as the file the format keeps its inputs in, for a circuit
+    /// exported earlier. `signature` is that circuit's, so the relation doesn't have to be around
+    /// (or exported again) each time the witness changes. Fails without writing anything if the
+    /// witness doesn't fit the signature. Exporters that can't write a witness on its own fail
+    /// with `ErrorKind::Unsupported`.
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[T],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let _ = (signature, witness, sink);
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "this format can't write a witness on its own",
+        ))
+    }
+}
+
+/// What a witness has to agree with about the circuit it's for, small enough to store next to an
+/// exported relation: the circuit's domain, how many inputs it has, and a hash of its gates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitSignature {
+    pub domain: Domain,
+    /// Number of `Input` gates
+    pub inputs: usize,
+    /// FNV-1a hash of the gates, like `content_hash`
+    pub hash: u64,
+}
+
+impl CircuitSignature {
+    pub fn of<T: WireValue>(gates: &[Operation<T>]) -> Self {
+        let mut hasher = ContentHasher::default();
+        for gate in gates {
+            hasher.update_bytes(&bincode::serialize(gate).expect("Gates are always serializable"));
+        }
+        CircuitSignature {
+            domain: T::DOMAIN,
+            inputs: gates
+                .iter()
+                .filter(|g| matches!(g, Operation::Input(_)))
+                .count(),
+            hash: hasher.finish(),
+        }
+    }
+
+    /// Checks that `gates` are the circuit this is the signature of. The error is `InvalidData`.
+    pub fn verify<T: WireValue>(&self, gates: &[Operation<T>]) -> Result<()> {
+        if CircuitSignature::of(gates) == *self {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "circuit doesn't match its signature (hash {:016x})",
+                self.hash
+            ),
+        ))
+    }
+
+    /// Checks that `witness` has one value per input of the circuit, in the circuit's domain. As
+    /// with `check_witness`, a wrong length is a `WitnessLengthError`.
+    pub fn check<T: WireValue>(&self, witness: &[T]) -> Result<()> {
+        if T::DOMAIN != self.domain {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} witness for a {:?} circuit", T::DOMAIN, self.domain),
+            ));
+        }
+        check_length(self.domain, self.inputs, witness.len())
+    }
+}
+
+/// A witness with a different number of values than the program has inputs in some domain.
+/// Exporters return it (wrapped in an `InvalidInput` error) before writing any output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WitnessLengthError {
+    pub domain: Domain,
+    /// Number of `Input` gates in the domain
+    pub inputs: usize,
+    /// Number of witness values given for the domain
+    pub witness: usize,
+}
+
+impl fmt::Display for WitnessLengthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "circuit has {} {:?} inputs but the witness has {} values",
+            self.inputs, self.domain, self.witness
+        )
+    }
+}
+
+impl std::error::Error for WitnessLengthError {}
+
+fn check_length(domain: Domain, inputs: usize, witness: usize) -> Result<()> {
+    if inputs == witness {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::InvalidInput,
+        WitnessLengthError {
+            domain,
+            inputs,
+            witness,
+        },
+    ))
+}
+
+/// Checks that `witness` has one value per `Input` gate. The error wraps a `WitnessLengthError`.
+pub fn check_witness<T: WireValue>(gates: &[Operation<T>], witness: &[T]) -> Result<()> {
+    let inputs = gates
+        .iter()
+        .filter(|g| matches!(g, Operation::Input(_)))
+        .count();
+    check_length(T::DOMAIN, inputs, witness.len())
+}
+
+/// Checks both witnesses against a whole program, GF2 first.
+pub fn check_program_witness(
+    program: &Program,
+    bool_witness: &[bool],
+    arith_witness: &[u64],
+) -> Result<()> {
+    let (mut bool_inputs, mut arith_inputs) = (0, 0);
+    for gate in &program.gates {
+        match gate {
+            CombineOperation::GF2(Operation::Input(_)) => bool_inputs += 1,
+            CombineOperation::Z64(Operation::Input(_)) => arith_inputs += 1,
+            _ => {}
+        }
+    }
+    check_length(Domain::GF2, bool_inputs, bool_witness.len())?;
+    check_length(Domain::Z64, arith_inputs, arith_witness.len())
+}
+
+/// Writes a note as one or more `//` comment lines, for formats with C-style comments.
+pub(crate) fn write_line_comment(note: &str, sink: &mut impl Write) -> Result<()> {
+    for line in note.lines() {
+        writeln!(sink, "// {}", line)?;
+    }
+    Ok(())
+}
+
+/// Writes one bit per line, for formats whose input files are plain bit strings.
+pub(crate) fn write_bit_lines(bits: &[bool], sink: &mut impl Write) -> Result<()> {
+    for bit in bits {
+        writeln!(sink, "{}", u8::from(*bit))?;
+    }
+    Ok(())
 }
@@ -0,0 +1,59 @@
+//! Output for formats that write several files, like IR0's circuit and its private and public
+//! inputs. Exporters ask a `MultiSink` for each file by name, so where the files end up (memory,
+//! a directory, an archive) is up to the caller.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::PathBuf;
+
+/// Named destinations for the files of a multi-file export. Each exporter documents the names it
+/// uses. Asking for the same name twice should give the same destination.
+pub trait MultiSink {
+    fn sink(&mut self, name: &str) -> Result<&mut dyn Write>;
+}
+
+/// Keeps each file in memory, keyed by name.
+impl MultiSink for BTreeMap<String, Vec<u8>> {
+    fn sink(&mut self, name: &str) -> Result<&mut dyn Write> {
+        Ok(self.entry(name.to_string()).or_default())
+    }
+}
+
+/// Writes each file next to the others, at the same path prefix with the file's name as its
+/// extension: `out/sha256` becomes `out/sha256.circuit`, `out/sha256.private_input`, and so on.
+/// Files are created the first time they're asked for.
+pub struct FileSinks {
+    prefix: PathBuf,
+    files: HashMap<String, BufWriter<File>>,
+}
+
+impl FileSinks {
+    pub fn new(prefix: impl Into<PathBuf>) -> Self {
+        FileSinks {
+            prefix: prefix.into(),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Flushes every file. Dropping a `FileSinks` flushes them too, but ignores any errors.
+    pub fn finish(self) -> Result<()> {
+        for (_, mut file) in self.files {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl MultiSink for FileSinks {
+    fn sink(&mut self, name: &str) -> Result<&mut dyn Write> {
+        if !self.files.contains_key(name) {
+            let mut path = self.prefix.clone().into_os_string();
+            path.push(".");
+            path.push(name);
+            let file = BufWriter::new(File::create(path)?);
+            self.files.insert(name.to_string(), file);
+        }
+        Ok(self.files.get_mut(name).expect("Just inserted"))
+    }
+}
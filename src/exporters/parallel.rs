@@ -0,0 +1,137 @@
+//! Formatting gates on several threads. Gates are split into chunks that are formatted
+//! independently and written in order, so the output is exactly what a sequential export writes,
+//! however many threads there are.
+
+use std::io::{Result, Write};
+use std::num::NonZeroUsize;
+use std::thread;
+
+use crate::exporters::{write_line_comment, Export};
+use crate::{Annotations, Operation, WireValue};
+
+/// Gates per chunk. Big enough that spawning a thread is cheap next to formatting the chunk.
+const CHUNK: usize = 1 << 14;
+
+/// `threads`, or one per core if it's zero.
+pub(crate) fn thread_count(threads: usize) -> usize {
+    match threads {
+        0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        threads => threads,
+    }
+}
+
+fn format_chunk<T: WireValue, E: Export<T>>(
+    gates: &[Operation<T>],
+    offset: usize,
+    annotations: &Annotations,
+) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for (idx, gate) in gates.iter().enumerate() {
+        if let Some(note) = annotations.get(&(offset + idx)) {
+            write_line_comment(note, &mut out)?;
+        }
+        E::export_gate(gate, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// Writes `gates` (and their annotations) the way `E::export_gate` would one at a time, using up
+/// to `threads` threads. At most `threads` chunks are held in memory at once. An error stops the
+/// export after the chunks before the failing one have been written.
+pub(crate) fn write_gates<T, E>(
+    gates: &[Operation<T>],
+    annotations: &Annotations,
+    threads: usize,
+    sink: &mut impl Write,
+) -> Result<()>
+where
+    T: WireValue + Sync,
+    E: Export<T>,
+{
+    let chunks: Vec<(usize, &[Operation<T>])> = gates
+        .chunks(CHUNK)
+        .enumerate()
+        .map(|(idx, chunk)| (idx * CHUNK, chunk))
+        .collect();
+    for wave in chunks.chunks(thread_count(threads)) {
+        let formatted: Vec<Result<Vec<u8>>> = thread::scope(|scope| {
+            let handles: Vec<_> = wave
+                .iter()
+                .map(|(offset, chunk)| {
+                    scope.spawn(move || format_chunk::<T, E>(chunk, *offset, annotations))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Formatting gates doesn't panic"))
+                .collect()
+        });
+        for chunk in formatted {
+            sink.write_all(&chunk?)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::parallel::CHUNK;
+    use crate::exporters::{Export, IR0, IR1};
+    use crate::{Annotations, Operation};
+
+    #[test]
+    fn test_parallel_export() {
+        // Enough gates for a few chunks, with annotations on either side of a boundary
+        let mut gates = vec![Operation::Input(0), Operation::Input(1)];
+        for wire in 2..3 * CHUNK {
+            gates.push(match wire % 3 {
+                0 => Operation::Add(wire, wire - 1, wire - 2),
+                1 => Operation::Mul(wire, wire - 1, wire - 2),
+                _ => Operation::AddConst(wire, wire - 1, true),
+            });
+        }
+        gates.push(Operation::AssertZero(5));
+        let witness = [true, false];
+        let mut annotations = Annotations::new();
+        annotations.insert(CHUNK - 1, "last of the first chunk".to_string());
+        annotations.insert(CHUNK, "first of the second".to_string());
+
+        let mut sequential = Vec::new();
+        IR1::export_annotated_circuit(&gates, &witness, &annotations, &mut sequential).unwrap();
+        for threads in [1, 2, 4] {
+            let mut parallel = Vec::new();
+            IR1::export_parallel(&gates, &witness, &annotations, threads, &mut parallel).unwrap();
+            assert!(parallel == sequential, "{} threads", threads);
+        }
+
+        let (mut relation, mut private_input) = (Vec::new(), Vec::new());
+        IR0::export_annotated_circuit(&gates, &witness, &annotations, &mut relation).unwrap();
+        IR0::export_private_input(&witness, &mut private_input).unwrap();
+        let (mut parallel_relation, mut parallel_input) = (Vec::new(), Vec::new());
+        IR0::export_parallel(
+            &gates,
+            &witness,
+            &annotations,
+            0,
+            &mut parallel_relation,
+            &mut parallel_input,
+        )
+        .unwrap();
+        assert!(parallel_relation == relation);
+        assert_eq!(parallel_input, private_input);
+
+        // A gate neither format has fails the export
+        gates[7] = Operation::Random(7);
+        let mut sink = Vec::new();
+        assert!(IR0::export_parallel(
+            &gates,
+            &witness,
+            &annotations,
+            2,
+            &mut sink,
+            &mut Vec::new()
+        )
+        .is_err());
+        assert!(IR1::export_parallel(&gates, &witness, &annotations, 2, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,676 @@
+//! Runtime lookup of export formats by name, so applications (and crates that add their own
+//! formats) can pick an exporter from a command-line flag or config file instead of a type.
+//!
+//! The built-in formats are always available as `bristol`, `dot`, `ir0`, `ir1`, `jsonl`, `shdl`,
+//! `stats-json`, `summary`, and `summary-json`. Other crates can add to the list with
+//! `register_exporter`.
+//!
+//! `export_many` writes several formats in a single walk over the program, for formats that can be
+//! written a gate at a time.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::marker::PhantomData;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::analysis::{AnalysisPass, CostModel};
+use crate::exporters::dot::DotWriter;
+use crate::exporters::sieve::IR1Writer;
+use crate::exporters::{
+    check_program_witness, BristolFashion, Dot, Export, Shdl, StatsReport, Summary, SummaryPass,
+    IR0, IR1, JSONL,
+};
+use crate::{Annotations, CombineOperation, Field, Operation, Program};
+
+/// An export format that can be selected at runtime. Unlike `Export`, this works on whole
+/// programs (which may mix domains) and can write to several sinks, for formats that split their
+/// output across files. What each sink is for is up to the format.
+pub trait Exporter: Send + Sync {
+    fn export(
+        &self,
+        program: &Program,
+        bool_witness: &[bool],
+        arith_witness: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()>;
+
+    /// Whether this format writes the witness out. If it does, `export_by_name` checks the
+    /// witness against the program's inputs before the format writes anything.
+    fn writes_witness(&self, sinks: usize) -> bool {
+        let _ = sinks;
+        false
+    }
+
+    /// Starts writing `program` to `sinks` a gate at a time, for `export_many`. Formats that need
+    /// the whole program up front can leave this alone: the default writer ignores the gates it's
+    /// given and calls `export` once it's finished.
+    fn writer<'a, 'b: 'a>(
+        &'a self,
+        program: &'a Program,
+        bool_witness: &'a [bool],
+        arith_witness: &'a [u64],
+        sinks: &'a mut [&'b mut dyn Write],
+    ) -> Result<Box<dyn GateWriter + 'a>> {
+        Ok(Box::new(WholeProgram(move || {
+            self.export(program, bool_witness, arith_witness, sinks)
+        })))
+    }
+}
+
+/// Receives a program's gates one at a time, in order. See `Exporter::writer`.
+pub trait GateWriter {
+    /// Writes the gate at `index` in the program.
+    fn write_gate(&mut self, index: usize, gate: &CombineOperation) -> Result<()>;
+
+    /// Writes whatever comes after the last gate.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// The default `GateWriter`, which exports the whole program when it's finished.
+struct WholeProgram<F>(F);
+
+impl<F: FnOnce() -> Result<()>> GateWriter for WholeProgram<F> {
+    fn write_gate(&mut self, _: usize, _: &CombineOperation) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        (self.0)()
+    }
+}
+
+/// Adapts an `Export<bool>` implementation to `Exporter`. Fails on programs with any Z64 or B2A
+/// gates. Writes the circuit to the first sink.
+pub struct BooleanExporter<E: Export<bool>>(PhantomData<fn() -> E>);
+
+impl<E: Export<bool>> Default for BooleanExporter<E> {
+    fn default() -> Self {
+        BooleanExporter(PhantomData)
+    }
+}
+
+/// Pulls the GF2 gates out of a program that shouldn't have anything else (other than hints),
+/// along with their annotations, re-keyed by index into the extracted gates.
+fn boolean_gates(program: &Program) -> Result<(Vec<Operation<bool>>, Annotations)> {
+    let no_notes = Annotations::new();
+    let notes = program.annotations.as_ref().unwrap_or(&no_notes);
+    let mut gates = Vec::with_capacity(program.gates.len());
+    let mut annotations = Annotations::new();
+
+    for (idx, gate) in program.gates.iter().enumerate() {
+        match gate {
+            CombineOperation::GF2(op) => {
+                if let Some(note) = notes.get(&idx) {
+                    annotations.insert(gates.len(), note.clone());
+                }
+                gates.push(*op);
+            }
+            CombineOperation::SizeHint(_, _) => {}
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("format only supports boolean circuits, found {:?}", gate),
+                ))
+            }
+        }
+    }
+    Ok((gates, annotations))
+}
+
+fn first_sink<'a, 'b>(sinks: &'a mut [&'b mut dyn Write]) -> Result<&'a mut &'b mut dyn Write> {
+    sinks
+        .first_mut()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no sink to export to"))
+}
+
+impl<E: Export<bool>> Exporter for BooleanExporter<E> {
+    fn export(
+        &self,
+        program: &Program,
+        bool_witness: &[bool],
+        _: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        let (gates, annotations) = boolean_gates(program)?;
+        E::export_annotated_circuit(&gates, bool_witness, &annotations, first_sink(sinks)?)
+    }
+
+    fn writes_witness(&self, _: usize) -> bool {
+        E::WRITES_WITNESS
+    }
+}
+
+/// Like `BooleanExporter<IR1>`, but can also be written a gate at a time.
+struct IR1Exporter;
+
+impl Exporter for IR1Exporter {
+    fn export(
+        &self,
+        program: &Program,
+        bool_witness: &[bool],
+        arith_witness: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        BooleanExporter::<IR1>::default().export(program, bool_witness, arith_witness, sinks)
+    }
+
+    fn writes_witness(&self, _: usize) -> bool {
+        true
+    }
+
+    fn writer<'a, 'b: 'a>(
+        &'a self,
+        program: &'a Program,
+        bool_witness: &'a [bool],
+        _: &'a [u64],
+        sinks: &'a mut [&'b mut dyn Write],
+    ) -> Result<Box<dyn GateWriter + 'a>> {
+        static NO_NOTES: Annotations = Annotations::new();
+        let annotations = program.annotations.as_ref().unwrap_or(&NO_NOTES);
+        let writer = IR1Writer::new(Field::GF2, bool_witness, annotations, first_sink(sinks)?)?;
+        Ok(Box::new(writer))
+    }
+}
+
+impl<'a, W: Write> GateWriter for IR1Writer<'a, W> {
+    fn write_gate(&mut self, index: usize, gate: &CombineOperation) -> Result<()> {
+        match gate {
+            CombineOperation::GF2(op) => IR1Writer::write_gate(self, index, op),
+            CombineOperation::SizeHint(_, _) => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("format only supports boolean circuits, found {:?}", gate),
+            )),
+        }
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        IR1Writer::finish(*self)
+    }
+}
+
+/// IR0 keeps the witness in a separate file, so this writes the circuit to the first sink and,
+/// if there's a second one, the private input to it.
+struct IR0Exporter;
+
+impl Exporter for IR0Exporter {
+    fn export(
+        &self,
+        program: &Program,
+        bool_witness: &[bool],
+        _: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        let (gates, annotations) = boolean_gates(program)?;
+        IR0::export_annotated_circuit(&gates, bool_witness, &annotations, first_sink(sinks)?)?;
+        if let Some(witness_sink) = sinks.get_mut(1) {
+            IR0::export_private_input(bool_witness, witness_sink)?;
+        }
+        Ok(())
+    }
+
+    fn writes_witness(&self, sinks: usize) -> bool {
+        sinks > 1
+    }
+}
+
+/// Writes every gate, whatever its domain, so the program can be loaded back as it was.
+struct JsonlExporter;
+
+impl Exporter for JsonlExporter {
+    fn export(
+        &self,
+        program: &Program,
+        _: &[bool],
+        _: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        JSONL::export_program(&program.gates, first_sink(sinks)?)
+    }
+
+    fn writer<'a, 'b: 'a>(
+        &'a self,
+        _: &'a Program,
+        _: &'a [bool],
+        _: &'a [u64],
+        sinks: &'a mut [&'b mut dyn Write],
+    ) -> Result<Box<dyn GateWriter + 'a>> {
+        Ok(Box::new(JsonlWriter(first_sink(sinks)?)))
+    }
+}
+
+struct JsonlWriter<W>(W);
+
+impl<W: Write> GateWriter for JsonlWriter<W> {
+    fn write_gate(&mut self, _: usize, gate: &CombineOperation) -> Result<()> {
+        JSONL::write_gate(gate, &mut self.0)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Groups inputs and outputs into multi-bit values using the program's buses.
+struct BristolExporter;
+
+impl Exporter for BristolExporter {
+    fn export(
+        &self,
+        program: &Program,
+        bool_witness: &[bool],
+        _: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        let (gates, _) = boolean_gates(program)?;
+        let buses = program.buses.as_deref().unwrap_or_default();
+        BristolFashion::export_with_buses(&gates, bool_witness, buses, first_sink(sinks)?)
+    }
+
+    fn writes_witness(&self, _: usize) -> bool {
+        true
+    }
+}
+
+impl Exporter for Dot {
+    fn export(
+        &self,
+        program: &Program,
+        _: &[bool],
+        _: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        static NO_NOTES: Annotations = Annotations::new();
+        let annotations = program.annotations.as_ref().unwrap_or(&NO_NOTES);
+        self.export_annotated_program(
+            &program.gates,
+            program.names.as_ref(),
+            annotations,
+            first_sink(sinks)?,
+        )
+    }
+
+    fn writer<'a, 'b: 'a>(
+        &'a self,
+        program: &'a Program,
+        _: &'a [bool],
+        _: &'a [u64],
+        sinks: &'a mut [&'b mut dyn Write],
+    ) -> Result<Box<dyn GateWriter + 'a>> {
+        static NO_NOTES: Annotations = Annotations::new();
+        let annotations = program.annotations.as_ref().unwrap_or(&NO_NOTES);
+        let names = program.names.as_ref();
+        let writer = DotWriter::new(*self, names, annotations, first_sink(sinks)?)?;
+        Ok(Box::new(writer))
+    }
+}
+
+impl<'a, W: Write> GateWriter for DotWriter<'a, W> {
+    fn write_gate(&mut self, index: usize, gate: &CombineOperation) -> Result<()> {
+        DotWriter::write_gate(self, index, gate)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        DotWriter::finish(*self)
+    }
+}
+
+struct SummaryExporter {
+    json: bool,
+}
+
+impl Exporter for SummaryExporter {
+    fn export(
+        &self,
+        program: &Program,
+        _: &[bool],
+        _: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        self.write(&Summary::of(&program.gates), first_sink(sinks)?)
+    }
+
+    fn writer<'a, 'b: 'a>(
+        &'a self,
+        _: &'a Program,
+        _: &'a [bool],
+        _: &'a [u64],
+        sinks: &'a mut [&'b mut dyn Write],
+    ) -> Result<Box<dyn GateWriter + 'a>> {
+        Ok(Box::new(SummaryWriter {
+            exporter: self,
+            pass: SummaryPass::default(),
+            sink: first_sink(sinks)?,
+        }))
+    }
+}
+
+impl SummaryExporter {
+    fn write(&self, summary: &Summary, sink: &mut impl Write) -> Result<()> {
+        if self.json {
+            summary.write_json(sink)
+        } else {
+            summary.write_text(sink)
+        }
+    }
+}
+
+struct SummaryWriter<'a, W> {
+    exporter: &'a SummaryExporter,
+    pass: SummaryPass,
+    sink: W,
+}
+
+impl<'a, W: Write> GateWriter for SummaryWriter<'a, W> {
+    fn write_gate(&mut self, _: usize, gate: &CombineOperation) -> Result<()> {
+        self.pass.analyze_gate(gate);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let SummaryWriter {
+            exporter,
+            pass,
+            mut sink,
+        } = *self;
+        exporter.write(&pass.finish_analysis(), &mut sink)
+    }
+}
+
+/// `StatsReport` with the default cost model.
+struct StatsExporter;
+
+impl Exporter for StatsExporter {
+    fn export(
+        &self,
+        program: &Program,
+        _: &[bool],
+        _: &[u64],
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        StatsReport::of(program, &CostModel::default()).write_json(first_sink(sinks)?)
+    }
+}
+
+type Registry = RwLock<HashMap<String, Arc<dyn Exporter>>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut builtins: HashMap<String, Arc<dyn Exporter>> = HashMap::new();
+        builtins.insert("bristol".into(), Arc::new(BristolExporter));
+        builtins.insert("dot".into(), Arc::new(Dot::default()));
+        builtins.insert("ir0".into(), Arc::new(IR0Exporter));
+        builtins.insert("ir1".into(), Arc::new(IR1Exporter));
+        builtins.insert("jsonl".into(), Arc::new(JsonlExporter));
+        builtins.insert("shdl".into(), Arc::new(BooleanExporter::<Shdl>::default()));
+        builtins.insert("stats-json".into(), Arc::new(StatsExporter));
+        builtins.insert("summary".into(), Arc::new(SummaryExporter { json: false }));
+        builtins.insert(
+            "summary-json".into(),
+            Arc::new(SummaryExporter { json: true }),
+        );
+        RwLock::new(builtins)
+    })
+}
+
+/// Makes `exporter` available under `name`, replacing any format (including a built-in one)
+/// that was already registered with that name.
+pub fn register_exporter(name: &str, exporter: impl Exporter + 'static) {
+    registry()
+        .write()
+        .expect("Exporter registry was poisoned")
+        .insert(name.to_string(), Arc::new(exporter));
+}
+
+/// Names of every registered format, sorted.
+pub fn exporter_names() -> Vec<String> {
+    let mut names: Vec<String> = registry()
+        .read()
+        .expect("Exporter registry was poisoned")
+        .keys()
+        .cloned()
+        .collect();
+    names.sort();
+    names
+}
+
+fn lookup(format: &str) -> Result<Arc<dyn Exporter>> {
+    // Clone the handle so the lock isn't held while exporting
+    registry()
+        .read()
+        .expect("Exporter registry was poisoned")
+        .get(format)
+        .cloned()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown export format {}", format),
+            )
+        })
+}
+
+/// Exports `program` using the format registered as `format`.
+pub fn export_by_name(
+    format: &str,
+    program: &Program,
+    bool_witness: &[bool],
+    arith_witness: &[u64],
+    sinks: &mut [&mut dyn Write],
+) -> Result<()> {
+    let exporter = lookup(format)?;
+    if exporter.writes_witness(sinks.len()) {
+        check_program_witness(program, bool_witness, arith_witness)?;
+    }
+    exporter.export(program, bool_witness, arith_witness, sinks)
+}
+
+/// Exports `program` in several formats at once. Each target is a format name and the sinks to
+/// write that format to. Gates are read once and handed to every format's `GateWriter` in turn,
+/// so formats that can be written a gate at a time (`dot`, `ir1`, `jsonl`, `summary` and
+/// `summary-json` among the built-ins) share a single walk over the program. The rest are
+/// exported as usual once the walk is over.
+///
+/// Unknown formats and witnesses of the wrong length are caught before anything is written. Other
+/// errors stop the export, and may leave partial output in the sinks of any of the formats.
+pub fn export_many(
+    program: &Program,
+    bool_witness: &[bool],
+    arith_witness: &[u64],
+    targets: &mut [(&str, &mut [&mut dyn Write])],
+) -> Result<()> {
+    let exporters = targets
+        .iter()
+        .map(|(format, _)| lookup(format))
+        .collect::<Result<Vec<_>>>()?;
+    if exporters
+        .iter()
+        .zip(targets.iter())
+        .any(|(exporter, (_, sinks))| exporter.writes_witness(sinks.len()))
+    {
+        check_program_witness(program, bool_witness, arith_witness)?;
+    }
+
+    let mut writers = exporters
+        .iter()
+        .zip(targets.iter_mut())
+        .map(|(exporter, (_, sinks))| exporter.writer(program, bool_witness, arith_witness, sinks))
+        .collect::<Result<Vec<_>>>()?;
+    for (idx, gate) in program.gates.iter().enumerate() {
+        for writer in writers.iter_mut() {
+            writer.write_gate(idx, gate)?;
+        }
+    }
+    for writer in writers {
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Result, Write};
+
+    use crate::exporters::registry::{
+        export_by_name, export_many, exporter_names, register_exporter, Exporter,
+    };
+    use crate::exporters::WitnessLengthError;
+    use crate::{CombineOperation, Domain, NameTable, Operation, Program};
+
+    fn program() -> Program {
+        vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+            CombineOperation::GF2(Operation::AssertZero(1)),
+        ]
+        .into()
+    }
+
+    #[test]
+    fn test_builtin_formats() {
+        let mut circuit = Vec::new();
+        let mut witness = Vec::new();
+        export_by_name(
+            "ir0",
+            &program(),
+            &[true],
+            &[],
+            &mut [&mut circuit, &mut witness],
+        )
+        .unwrap();
+        assert!(String::from_utf8(circuit).unwrap().contains("@private()"));
+        assert!(String::from_utf8(witness)
+            .unwrap()
+            .contains("private_input"));
+
+        // Annotations follow their gates past anything the exporter skips
+        let mut hinted = program();
+        hinted.gates.insert(0, CombineOperation::SizeHint(0, 2));
+        hinted.annotate(2, "invert");
+        let mut circuit = Vec::new();
+        export_by_name("ir1", &hinted, &[true], &[], &mut [&mut circuit]).unwrap();
+        assert!(String::from_utf8(circuit)
+            .unwrap()
+            .contains("// invert\n$1 <- @xor($0, < 1 >);"));
+
+        // DOT labels wires from the name table and shows annotations as tooltips
+        let mut names = NameTable::default();
+        names.insert(Domain::GF2, 0, "adder3::carry[7]".to_string());
+        hinted.names = Some(names);
+        let mut graph = Vec::new();
+        export_by_name("dot", &hinted, &[], &[], &mut [&mut graph]).unwrap();
+        let graph = String::from_utf8(graph).unwrap();
+        assert!(graph.contains("  g2 [label=\"2: GF2 AddConst -> b1\", tooltip=\"invert\"];"));
+        assert!(graph.contains("  g1 -> g2 [label=\"adder3::carry[7]\"];"));
+
+        let mut sink = Vec::new();
+        let mixed: Program = vec![CombineOperation::Z64(Operation::Input(0))].into();
+        assert!(export_by_name("bristol", &mixed, &[], &[], &mut [&mut sink]).is_err());
+        assert!(export_by_name("no-such-format", &program(), &[], &[], &mut [&mut sink]).is_err());
+    }
+
+    #[test]
+    fn test_export_many() {
+        let mut program = program();
+        program.gates.insert(0, CombineOperation::SizeHint(0, 2));
+        program.annotate(2, "invert");
+        let formats = ["ir1", "summary-json", "bristol", "dot", "ir0"];
+
+        let mut outputs = vec![Vec::new(); formats.len()];
+        let mut witness = Vec::new();
+        {
+            let mut sinks: Vec<Vec<&mut dyn Write>> = outputs
+                .iter_mut()
+                .map(|o| vec![o as &mut dyn Write])
+                .collect();
+            sinks[4].push(&mut witness);
+            let mut targets: Vec<(&str, &mut [&mut dyn Write])> = formats
+                .iter()
+                .copied()
+                .zip(sinks.iter_mut().map(|s| s.as_mut_slice()))
+                .collect();
+            export_many(&program, &[true], &[], &mut targets).unwrap();
+        }
+
+        for (format, output) in formats.iter().zip(&outputs) {
+            let mut expected = Vec::new();
+            export_by_name(format, &program, &[true], &[], &mut [&mut expected]).unwrap();
+            assert_eq!(output, &expected, "{} differs", format);
+        }
+        assert!(String::from_utf8(witness)
+            .unwrap()
+            .contains("private_input"));
+
+        // Nothing's written if any format would write the wrong witness
+        let (mut dot, mut ir1) = (Vec::new(), Vec::new());
+        let result = export_many(
+            &program,
+            &[],
+            &[],
+            &mut [("dot", &mut [&mut dot]), ("ir1", &mut [&mut ir1])],
+        );
+        assert!(result.is_err());
+        assert!(dot.is_empty() && ir1.is_empty());
+        let result = export_many(
+            &program,
+            &[true],
+            &[],
+            &mut [("dot", &mut [&mut dot]), ("nope", &mut [&mut ir1])],
+        );
+        assert!(result.is_err());
+        assert!(dot.is_empty());
+    }
+
+    #[test]
+    fn test_witness_length() {
+        for (format, sinks) in [("bristol", 1), ("ir0", 2), ("ir1", 1)] {
+            let mut first = Vec::new();
+            let mut second = Vec::new();
+            let mut all: [&mut dyn Write; 2] = [&mut first, &mut second];
+            let err = export_by_name(format, &program(), &[], &[], &mut all[..sinks]).unwrap_err();
+            assert_eq!(
+                err.get_ref()
+                    .and_then(|e| e.downcast_ref::<WitnessLengthError>()),
+                Some(&WitnessLengthError {
+                    domain: Domain::GF2,
+                    inputs: 1,
+                    witness: 0,
+                })
+            );
+            assert!(first.is_empty() && second.is_empty());
+        }
+
+        // A stray arithmetic value is just as wrong
+        let mut sink = Vec::new();
+        assert!(export_by_name("ir1", &program(), &[true], &[7], &mut [&mut sink]).is_err());
+        assert!(sink.is_empty());
+
+        // Formats that don't write the witness don't care about it
+        export_by_name("shdl", &program(), &[], &[], &mut [&mut sink]).unwrap();
+        export_by_name("ir0", &program(), &[], &[], &mut [&mut Vec::new()]).unwrap();
+    }
+
+    struct GateCount;
+
+    impl Exporter for GateCount {
+        fn export(
+            &self,
+            program: &Program,
+            _: &[bool],
+            _: &[u64],
+            sinks: &mut [&mut dyn Write],
+        ) -> Result<()> {
+            writeln!(sinks[0], "{}", program.gates.len())
+        }
+    }
+
+    #[test]
+    fn test_register_exporter() {
+        register_exporter("test-gate-count", GateCount);
+        assert!(exporter_names().contains(&"test-gate-count".to_string()));
+
+        let mut sink = Vec::new();
+        export_by_name("test-gate-count", &program(), &[], &[], &mut [&mut sink]).unwrap();
+        assert_eq!(sink, b"3\n");
+    }
+}
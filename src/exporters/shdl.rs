@@ -0,0 +1,195 @@
+//! Export functionality for Fairplay's Secure Hardware Definition Language, the netlist format
+//! consumed by JustGarble and other garbled-circuit tools.
+//!
+//! SHDL is stricter about numbering than our circuits are: every line defines exactly one new
+//! wire, wires are numbered consecutively from zero in the order they're defined, and all the
+//! inputs come first. `Shdl::export_circuit` takes care of this by:
+//!
+//! * numbering the `Input` gates `0..n` in the order they appear in the program,
+//! * numbering every other gate's output after that, in program order (so a wire that we write
+//!   twice becomes two different SHDL wires), and
+//! * turning each `AssertZero` into an `output` buffer of the asserted wire, since SHDL has no
+//!   notion of assertions. Consumers should check that every output bit is zero.
+//!
+//! SHDL has no constant wires, so `Const` gates are emitted as one-input gates with a constant
+//! truth table that read wire 0. This means circuits that use constants need at least one input.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+
+use crate::exporters::{write_bit_lines, CircuitSignature, Export};
+use crate::{HasIO, Operation, Translatable};
+
+pub struct Shdl;
+
+const XOR: &str = "0 1 1 0";
+const AND: &str = "0 0 0 1";
+const BUF: &str = "0 1";
+const NOT: &str = "1 0";
+const ZERO: &str = "0 0";
+const ONE: &str = "1 1";
+
+fn write_gate(
+    sink: &mut impl Write,
+    dst: usize,
+    output: bool,
+    table: &str,
+    inputs: &[usize],
+) -> Result<()> {
+    writeln!(
+        sink,
+        "{} {}gate arity {} table [ {} ] inputs [ {} ]",
+        dst,
+        if output { "output " } else { "" },
+        inputs.len(),
+        table,
+        inputs
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    )
+}
+
+impl Export<bool> for Shdl {
+    /// Writes a single gate, assuming its wires are already numbered according to SHDL's rules.
+    /// Assertions need a freshly numbered output wire, so they can only be exported as part of
+    /// `export_circuit`.
+    fn export_gate(gate: &Operation<bool>, sink: &mut impl Write) -> Result<()> {
+        match *gate {
+            Operation::Input(o) => writeln!(sink, "{} input", o),
+            Operation::Random(_) => Err(Error::other("can't use random gates in SHDL")),
+            Operation::Add(o, l, r) | Operation::Sub(o, l, r) => {
+                write_gate(sink, o, false, XOR, &[l, r])
+            }
+            Operation::Mul(o, l, r) => write_gate(sink, o, false, AND, &[l, r]),
+            Operation::AddConst(o, i, c) | Operation::SubConst(o, i, c) => {
+                write_gate(sink, o, false, if c { NOT } else { BUF }, &[i])
+            }
+            Operation::MulConst(o, i, c) => {
+                write_gate(sink, o, false, if c { BUF } else { ZERO }, &[i])
+            }
+            Operation::AssertZero(_) => Err(Error::other(
+                "SHDL assertions must be exported as part of a circuit",
+            )),
+            Operation::Const(o, c) => write_gate(sink, o, false, if c { ONE } else { ZERO }, &[0]),
+        }
+    }
+
+    const WRITES_WITNESS: bool = false;
+
+    /// Renumbers and writes a whole circuit. SHDL doesn't include input values, so the witness
+    /// is ignored.
+    fn export_circuit(gates: &[Operation<bool>], _: &[bool], sink: &mut impl Write) -> Result<()> {
+        // Maps our wires to the SHDL wire that currently holds their value
+        let mut wires: HashMap<usize, usize> = HashMap::new();
+        let mut next = 0;
+
+        for gate in gates {
+            if let Operation::Input(w) = gate {
+                wires.insert(*w, next);
+                Self::export_gate(&Operation::Input(next), sink)?;
+                next += 1;
+            }
+        }
+
+        for gate in gates {
+            if let Operation::Input(_) = gate {
+                continue;
+            }
+            if let Operation::Const(_, _) = gate {
+                if next == 0 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "SHDL constants need at least one input wire",
+                    ));
+                }
+            }
+
+            let inputs = gate
+                .inputs()
+                .map(|w| {
+                    wires.get(&w).copied().ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("wire {} is read before it's written", w),
+                        )
+                    })
+                })
+                .collect::<Result<Vec<usize>>>()?;
+
+            match gate {
+                Operation::AssertZero(_) => write_gate(sink, next, true, BUF, &inputs)?,
+                _ => {
+                    let renumbered = gate
+                        .translate(inputs.iter().copied(), std::iter::once(next))
+                        .expect("Operations are always translatable");
+                    Self::export_gate(&renumbered, sink)?;
+                    wires.insert(gate.dst().expect("Only assertions lack outputs"), next);
+                }
+            }
+            next += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the input bits one per line, in the order of the SHDL `input` wires.
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        signature.check(witness)?;
+        write_bit_lines(witness, sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::shdl::Shdl;
+    use crate::exporters::Export;
+    use crate::Operation;
+
+    #[test]
+    fn print_example() {
+        let mut sink = Vec::new();
+
+        assert!(Shdl::export_circuit(
+            &[
+                Operation::Input(1),
+                Operation::Input(2),
+                Operation::Add(4, 1, 2),
+                Operation::Input(3),
+                Operation::Mul(6, 4, 3),
+                Operation::Const(7, true),
+                Operation::Add(6, 6, 7),
+                Operation::AssertZero(6)
+            ],
+            &[],
+            &mut sink,
+        )
+        .is_ok());
+
+        let shdl = std::str::from_utf8(&sink).unwrap();
+        assert_eq!(
+            shdl,
+            "0 input
+1 input
+2 input
+3 gate arity 2 table [ 0 1 1 0 ] inputs [ 0 1 ]
+4 gate arity 2 table [ 0 0 0 1 ] inputs [ 3 2 ]
+5 gate arity 1 table [ 1 1 ] inputs [ 0 ]
+6 gate arity 2 table [ 0 1 1 0 ] inputs [ 4 5 ]
+7 output gate arity 1 table [ 0 1 ] inputs [ 6 ]
+"
+        );
+    }
+
+    #[test]
+    fn rejects_unwritten_wires() {
+        let mut sink = Vec::new();
+        assert!(Shdl::export_circuit(&[Operation::AssertZero(3)], &[], &mut sink).is_err());
+        assert!(Shdl::export_circuit(&[Operation::Const(0, true)], &[], &mut sink).is_err());
+    }
+}
@@ -1,87 +1,779 @@
 //! Export functionality for SIEVE IRs.
 
+use std::collections::HashMap;
+use std::fmt;
 use std::io::{Error, ErrorKind, Result, Write};
 
-use crate::exporters::Export;
-use crate::Operation;
+use crate::exporters::functions::{find_functions, write_items, FunctionOptions};
+use crate::exporters::parallel::write_gates;
+use crate::exporters::{
+    check_witness, write_line_comment, CircuitSignature, Export, ExportConfig, Ir1Dialect, Skeleton,
+};
+use num_traits::One;
+
+use crate::{Annotations, Domain, Field, Fp, HasConst, HasIO, Mersenne61, Operation, WireValue};
 
 pub struct IR1;
 
-impl Export<bool> for IR1 {
-    fn export_gate(gate: &Operation<bool>, sink: &mut impl Write) -> Result<()> {
-        match gate {
-            Operation::Input(i) => {
-                writeln!(sink, "${} <- @short_witness;", i)
+const NO_SUBTRACTION: &str = "IR1 can't subtract wires; multiply by -1 and add instead";
+
+/// Something in a circuit that IR1 consumers reject. `IR1::validate` finds them, and export fails
+/// (with the first one wrapped in an `InvalidInput` error) before writing anything if there are
+/// any.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IR1Violation {
+    /// The gate at `gate` writes a wire the gate at `first` already wrote. IR1 wires are
+    /// assigned exactly once.
+    Reassigned {
+        gate: usize,
+        wire: usize,
+        first: usize,
+    },
+    /// The gate at `gate` reads a wire before anything assigns it
+    Unassigned { gate: usize, wire: usize },
+    /// The gate at `gate` has a constant that isn't a canonical element of the declared field
+    ConstantOutOfRange { gate: usize, value: u64 },
+    /// Wires `start..end` are never assigned, though later ones are. Only reported when asked
+    /// for dense numbering.
+    Gap { start: usize, end: usize },
+}
+
+impl fmt::Display for IR1Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IR1Violation::Reassigned { gate, wire, first } => write!(
+                f,
+                "gate {} assigns ${}, which gate {} already assigned",
+                gate, wire, first
+            ),
+            IR1Violation::Unassigned { gate, wire } => {
+                write!(f, "gate {} reads ${} before it's assigned", gate, wire)
             }
-            Operation::Random(_) => {
-                // TODO(ww): Is this true?
-                Err(Error::new(
-                    ErrorKind::Other,
-                    "can't use random gates in IR1",
-                ))
+            IR1Violation::ConstantOutOfRange { gate, value } => write!(
+                f,
+                "gate {} has constant {}, which is out of range for the field",
+                gate, value
+            ),
+            IR1Violation::Gap { start, end } => {
+                write!(f, "wires ${} to ${} are never assigned", start, end - 1)
             }
-            Operation::Add(o, l, r) => {
-                writeln!(sink, "${} <- @xor(${}, ${});", o, l, r)
+        }
+    }
+}
+
+impl std::error::Error for IR1Violation {}
+
+/// The gate that assigned each wire so far, for checking gates one at a time.
+#[derive(Default)]
+struct Assignments(HashMap<usize, usize>);
+
+impl Assignments {
+    /// Checks the gate at `idx`, adding anything wrong with it to `violations`.
+    fn check<T: WireValue>(
+        &mut self,
+        field: Field,
+        idx: usize,
+        gate: &Operation<T>,
+        violations: &mut Vec<IR1Violation>,
+    ) {
+        for wire in gate.inputs() {
+            if !self.0.contains_key(&wire) {
+                violations.push(IR1Violation::Unassigned { gate: idx, wire });
             }
-            Operation::AddConst(o, i, c) => {
-                // NOTE(ww): This could be optimized the way we do for
-                // Bristol Fashion: inv when nonzero and just an identity
-                // assign when zero.
-                writeln!(sink, "${} <- @xor(${}, < {} >);", o, i, *c as u32)
+        }
+        for c in gate.constants() {
+            let value = u64::from_le_bytes(c.to_le_bytes());
+            if field.degree == 1 && value >= field.characteristic {
+                violations.push(IR1Violation::ConstantOutOfRange { gate: idx, value });
             }
-            Operation::Sub(o, l, r) => {
-                writeln!(sink, "${} <- @xor(${}, ${});", o, l, r)
+        }
+        if let Some(wire) = gate.dst() {
+            if let Some(first) = self.0.insert(wire, idx) {
+                self.0.insert(wire, first);
+                violations.push(IR1Violation::Reassigned {
+                    gate: idx,
+                    wire,
+                    first,
+                });
             }
+        }
+    }
+}
+
+/// Writes a boolean gate the way `dialect` lays constants out.
+fn write_bool_gate(
+    dialect: &Ir1Dialect,
+    gate: &Operation<bool>,
+    sink: &mut impl Write,
+) -> Result<()> {
+    match gate {
+        Operation::Input(i) => {
+            writeln!(sink, "${} <- @short_witness;", i)
+        }
+        Operation::Random(_) => {
+            // TODO(ww): Is this true?
+            Err(Error::other("can't use random gates in IR1"))
+        }
+        Operation::Add(o, l, r) => {
+            writeln!(sink, "${} <- @xor(${}, ${});", o, l, r)
+        }
+        Operation::AddConst(o, i, c) => {
+            // NOTE(ww): This could be optimized the way we do for
+            // Bristol Fashion: inv when nonzero and just an identity
+            // assign when zero.
+            writeln!(
+                sink,
+                "${} <- @xor(${}, {});",
+                o,
+                i,
+                dialect.constant(*c as u32)
+            )
+        }
+        Operation::Sub(o, l, r) => {
+            writeln!(sink, "${} <- @xor(${}, ${});", o, l, r)
+        }
+        Operation::SubConst(o, i, c) => {
+            // NOTE(ww): This could be optimized the way we do for
+            // Bristol Fashion: inv when nonzero and just an identity
+            // assign when zero.
+            writeln!(
+                sink,
+                "${} <- @xor(${}, {});",
+                o,
+                i,
+                dialect.constant(*c as u32)
+            )
+        }
+        Operation::Mul(o, l, r) => {
+            writeln!(sink, "${} <- @and(${}, ${});", o, l, r)
+        }
+        Operation::MulConst(o, i, c) => {
+            // NOTE(ww): This could be optimized the way we do for
+            // Bristol Fashion: inv when zero and just an identity
+            // assign when nonzero.
+            writeln!(
+                sink,
+                "${} <- @and(${}, {});",
+                o,
+                i,
+                dialect.constant(*c as u32)
+            )
+        }
+        Operation::AssertZero(w) => {
+            writeln!(sink, "@assert_zero(${});", w)
+        }
+        Operation::Const(w, c) => {
+            writeln!(sink, "${} <- {};", w, dialect.constant(*c as u32))
+        }
+    }
+}
+
+impl Export<bool> for IR1 {
+    fn export_gate(gate: &Operation<bool>, sink: &mut impl Write) -> Result<()> {
+        write_bool_gate(&Ir1Dialect::default(), gate, sink)
+    }
+
+    fn export_circuit(
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::export_circuit_in(Field::GF2, gates, witness, sink)
+    }
+
+    fn export_annotated_circuit(
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let config = ExportConfig::new(Field::GF2);
+        Self::write_circuit(&config, gates, Some(witness), annotations, None, sink)
+    }
+
+    /// Writes just the header and `short_witness` block of the relation `export_circuit` writes.
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        signature.check(witness)?;
+        let witness = witness.iter().map(|w| u64::from(*w));
+        Self::write_witness(&ExportConfig::new(Field::GF2), witness, sink)
+    }
+}
+
+/// Circuits over GF(P) use the arithmetic gate set. IR1 has no subtraction between wires, so `Sub`
+/// gates are written with `lower_subtraction`, and can only be exported as part of a circuit;
+/// subtracting a constant becomes adding its negation. `P` has to be at least 2.
+impl<const P: u64> Export<Fp<P>> for IR1 {
+    fn export_gate(gate: &Operation<Fp<P>>, sink: &mut impl Write) -> Result<()> {
+        prime_field::<P>()?;
+        match gate {
+            Operation::Input(i) => writeln!(sink, "${} <- @short_witness;", i),
+            Operation::Random(_) => Err(Error::other("can't use random gates in IR1")),
+            Operation::Add(o, l, r) => writeln!(sink, "${} <- @add(${}, ${});", o, l, r),
+            Operation::AddConst(o, i, c) => writeln!(sink, "${} <- @addc(${}, < {} >);", o, i, c),
+            Operation::Sub(_, _, _) => Err(Error::new(ErrorKind::InvalidInput, NO_SUBTRACTION)),
             Operation::SubConst(o, i, c) => {
-                // NOTE(ww): This could be optimized the way we do for
-                // Bristol Fashion: inv when nonzero and just an identity
-                // assign when zero.
-                writeln!(sink, "${} <- @xor(${}, < {} >);", o, i, *c as u32)
-            }
-            Operation::Mul(o, l, r) => {
-                writeln!(sink, "${} <- @and(${}, ${});", o, l, r)
+                writeln!(sink, "${} <- @addc(${}, < {} >);", o, i, -*c)
             }
-            Operation::MulConst(o, i, c) => {
-                // NOTE(ww): This could be optimized the way we do for
-                // Bristol Fashion: inv when zero and just an identity
-                // assign when nonzero.
-                writeln!(sink, "${} <- @and(${}, < {} >);", o, i, *c as u32)
-            }
-            Operation::AssertZero(w) => {
-                writeln!(sink, "@assert_zero(${});", w)
+            Operation::Mul(o, l, r) => writeln!(sink, "${} <- @mul(${}, ${});", o, l, r),
+            Operation::MulConst(o, i, c) => writeln!(sink, "${} <- @mulc(${}, < {} >);", o, i, c),
+            Operation::AssertZero(w) => writeln!(sink, "@assert_zero(${});", w),
+            Operation::Const(w, c) => writeln!(sink, "${} <- < {} >;", w, c),
+        }
+    }
+
+    fn export_circuit(
+        gates: &[Operation<Fp<P>>],
+        witness: &[Fp<P>],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::export_annotated_circuit(gates, witness, &Annotations::new(), sink)
+    }
+
+    fn export_annotated_circuit(
+        gates: &[Operation<Fp<P>>],
+        witness: &[Fp<P>],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let field = prime_field::<P>()?;
+        if let Some(violation) = Self::validate_gates(field, gates, false).into_iter().next() {
+            return Err(Error::new(ErrorKind::InvalidInput, violation));
+        }
+        check_witness(gates, witness)?;
+        let gates = lower_subtraction(gates, -Fp::one());
+
+        let witness = witness.iter().map(|w| w.value());
+        Self::write_header(
+            &ExportConfig::new(field),
+            witness,
+            "arithmetic",
+            false,
+            sink,
+        )?;
+        write_lowered(&gates, annotations, sink, |gate, sink| {
+            Self::export_gate(gate, sink)
+        })?;
+        writeln!(sink, "@end")
+    }
+
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[Fp<P>],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let field = prime_field::<P>()?;
+        signature.check(witness)?;
+        let witness = witness.iter().map(|w| w.value());
+        Self::write_witness(&ExportConfig::new(field), witness, sink)
+    }
+}
+
+/// `Fp::<P>::FIELD`, unless `P` is too small for GF(P) to be a field.
+fn prime_field<const P: u64>() -> Result<Field> {
+    if P < 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("GF({}) isn't a field", P),
+        ));
+    }
+    Ok(Fp::<P>::FIELD)
+}
+
+/// The directive `export_gate` writes a boolean gate as, if it's one a gate set has to allow.
+fn boolean(gate: &Operation<bool>) -> Option<&'static str> {
+    match gate {
+        Operation::Add(..)
+        | Operation::AddConst(..)
+        | Operation::Sub(..)
+        | Operation::SubConst(..) => Some("@xor"),
+        Operation::Mul(..) | Operation::MulConst(..) => Some("@and"),
+        _ => None,
+    }
+}
+
+/// The directive an arithmetic gate is written as, if it's one a gate set has to allow.
+fn arithmetic<T: WireValue>(gate: &Operation<T>) -> Option<&'static str> {
+    match gate {
+        Operation::Add(..) => Some("@add"),
+        Operation::AddConst(..) | Operation::SubConst(..) => Some("@addc"),
+        Operation::Mul(..) => Some("@mul"),
+        Operation::MulConst(..) => Some("@mulc"),
+        _ => None,
+    }
+}
+
+/// Encodes a Z64 value as an element of the prime field `field`, reading it as a two's complement
+/// signed integer whatever the size of the field: values below 2^63 are non-negative and stay as
+/// they are, and the rest are negative and count down from the characteristic, so `u64::MAX` is
+/// -1 in every field. `None` if the value's magnitude isn't below the characteristic.
+fn encode_z64(field: Field, value: u64) -> Option<u64> {
+    if value < 1 << 63 {
+        return Some(value).filter(|value| *value < field.characteristic);
+    }
+    let magnitude = value.wrapping_neg();
+    if magnitude < field.characteristic {
+        Some(field.characteristic - magnitude)
+    } else {
+        None
+    }
+}
+
+/// Rewrites `Sub(o, l, r)`, which IR1 has no directive for, as `MulConst(t, r, minus_one)` then
+/// `Add(o, l, t)`, where `minus_one` is -1 in the field and each `t` is a fresh wire past every
+/// wire `gates` use. Every gate comes with the index of the gate it was written for.
+fn lower_subtraction<T: WireValue>(
+    gates: &[Operation<T>],
+    minus_one: T,
+) -> Vec<(usize, Operation<T>)> {
+    let mut scratch = gates
+        .iter()
+        .flat_map(|gate| gate.inputs().chain(gate.outputs()))
+        .max()
+        .map_or(0, |wire| wire + 1);
+    let mut lowered = Vec::with_capacity(gates.len());
+    for (idx, gate) in gates.iter().enumerate() {
+        match *gate {
+            Operation::Sub(o, l, r) => {
+                lowered.push((idx, Operation::MulConst(scratch, r, minus_one)));
+                lowered.push((idx, Operation::Add(o, l, scratch)));
+                scratch += 1;
             }
-            Operation::Const(w, c) => {
-                writeln!(sink, "${} <- < {} >;", w, *c as u32)
+            other => lowered.push((idx, other)),
+        }
+    }
+    lowered
+}
+
+/// Writes gates from `lower_subtraction` with `write_gate`, each note in `annotations` before the
+/// first of the gates written for the gate it's on.
+fn write_lowered<T: WireValue, W: Write>(
+    lowered: &[(usize, Operation<T>)],
+    annotations: &Annotations,
+    sink: &mut W,
+    mut write_gate: impl FnMut(&Operation<T>, &mut W) -> Result<()>,
+) -> Result<()> {
+    for (pos, (idx, gate)) in lowered.iter().enumerate() {
+        if pos == 0 || lowered[pos - 1].0 != *idx {
+            if let Some(note) = annotations.get(idx) {
+                write_line_comment(note, sink)?;
             }
         }
+        write_gate(gate, sink)?;
+    }
+    Ok(())
+}
+
+/// `gate`, with its constant encoded as a field element and subtracting a constant turned into
+/// adding its negation. `Sub` is left for `lower_subtraction`.
+fn encode_z64_gate(field: Field, idx: usize, gate: &Operation<u64>) -> Result<Operation<u64>> {
+    let encode = |value: u64| {
+        encode_z64(field, value).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                IR1Violation::ConstantOutOfRange { gate: idx, value },
+            )
+        })
+    };
+    Ok(match *gate {
+        Operation::AddConst(o, i, c) => Operation::AddConst(o, i, encode(c)?),
+        Operation::SubConst(o, i, c) => Operation::AddConst(o, i, encode(c.wrapping_neg())?),
+        Operation::MulConst(o, i, c) => Operation::MulConst(o, i, encode(c)?),
+        Operation::Const(o, c) => Operation::Const(o, encode(c)?),
+        other => other,
+    })
+}
+
+/// Writes a gate that's already been through `encode_z64_gate` and `lower_subtraction`.
+fn write_z64_gate(
+    dialect: &Ir1Dialect,
+    gate: &Operation<u64>,
+    sink: &mut impl Write,
+) -> Result<()> {
+    let c = |value: u64| dialect.constant(value);
+    match *gate {
+        Operation::Input(i) => writeln!(sink, "${} <- @short_witness;", i),
+        Operation::Random(_) => Err(Error::other("can't use random gates in IR1")),
+        Operation::Add(o, l, r) => writeln!(sink, "${} <- @add(${}, ${});", o, l, r),
+        Operation::AddConst(o, i, v) => writeln!(sink, "${} <- @addc(${}, {});", o, i, c(v)),
+        Operation::Mul(o, l, r) => writeln!(sink, "${} <- @mul(${}, ${});", o, l, r),
+        Operation::MulConst(o, i, v) => writeln!(sink, "${} <- @mulc(${}, {});", o, i, c(v)),
+        Operation::AssertZero(w) => writeln!(sink, "@assert_zero(${});", w),
+        Operation::Const(w, v) => writeln!(sink, "${} <- {};", w, c(v)),
+        Operation::Sub(_, _, _) | Operation::SubConst(_, _, _) => {
+            unreachable!("Encoding removes subtraction")
+        }
+    }
+}
+
+/// Z64 circuits use the arithmetic gate set, over GF(2^61 - 1) unless they're exported with
+/// `export_z64_in`. Values are encoded with `encode_z64`, so the export only computes the same
+/// thing as the circuit if nothing it computes wraps around 2^64 or the field's characteristic.
+/// `Sub` gates are written with `lower_subtraction`, which needs wires from the rest of the
+/// circuit, so they can only be exported as part of one.
+impl Export<u64> for IR1 {
+    fn export_gate(gate: &Operation<u64>, sink: &mut impl Write) -> Result<()> {
+        if let Operation::Sub(_, _, _) = gate {
+            return Err(Error::new(ErrorKind::InvalidInput, NO_SUBTRACTION));
+        }
+        let gate = encode_z64_gate(Mersenne61::FIELD, 0, gate)?;
+        write_z64_gate(&Ir1Dialect::default(), &gate, sink)
     }
 
     fn export_circuit(
+        gates: &[Operation<u64>],
+        witness: &[u64],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::export_z64_in(Mersenne61::FIELD, gates, witness, &Annotations::new(), sink)
+    }
+
+    fn export_annotated_circuit(
+        gates: &[Operation<u64>],
+        witness: &[u64],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::export_z64_in(Mersenne61::FIELD, gates, witness, annotations, sink)
+    }
+
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[u64],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        signature.check(witness)?;
+        let witness = encode_z64_witness(Mersenne61::FIELD, witness)?;
+        let config = ExportConfig::new(Mersenne61::FIELD);
+        Self::write_witness(&config, witness.into_iter(), sink)
+    }
+}
+
+/// Encodes every witness value, failing on the first one the field can't hold.
+fn encode_z64_witness(field: Field, witness: &[u64]) -> Result<Vec<u64>> {
+    witness
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| {
+            encode_z64(field, *value).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "witness value {} (number {}) is out of range for {}",
+                        value, idx, field
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+impl IR1 {
+    /// Checks that `gates` follow IR1's rules for wire numbering in `field`: every wire is
+    /// assigned exactly once, before anything reads it, and constants are elements of the field.
+    /// With `dense`, also checks that the assigned wires have no gaps, which some consumers that
+    /// allocate wires up front require. Returns every violation, in gate order (gaps last).
+    pub fn validate(field: Field, gates: &[Operation<bool>], dense: bool) -> Vec<IR1Violation> {
+        Self::validate_gates(field, gates, dense)
+    }
+
+    /// `validate` for gates of any domain.
+    fn validate_gates<T: WireValue>(
+        field: Field,
+        gates: &[Operation<T>],
+        dense: bool,
+    ) -> Vec<IR1Violation> {
+        let mut violations = Vec::new();
+        let mut assigned = Assignments::default();
+        for (idx, gate) in gates.iter().enumerate() {
+            assigned.check(field, idx, gate, &mut violations);
+        }
+
+        if dense {
+            let mut wires: Vec<usize> = assigned.0.into_keys().collect();
+            wires.sort_unstable();
+            let mut next = 0;
+            for wire in wires {
+                if wire > next {
+                    violations.push(IR1Violation::Gap {
+                        start: next,
+                        end: wire,
+                    });
+                }
+                next = wire + 1;
+            }
+        }
+        violations
+    }
+
+    /// Exports a circuit, declaring that it's over `field`. Fails without writing anything if the
+    /// gates can't be represented in that field.
+    pub fn export_circuit_in(
+        field: Field,
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let config = ExportConfig::new(field);
+        Self::write_circuit(
+            &config,
+            gates,
+            Some(witness),
+            &Annotations::new(),
+            None,
+            sink,
+        )
+    }
+
+    /// Exports a circuit with the header `config` asks for. Fails without writing anything if the
+    /// gates can't be represented in its field, or use directives its gate set doesn't allow.
+    pub fn export_configured(
+        config: &ExportConfig,
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::write_circuit(config, gates, Some(witness), annotations, None, sink)
+    }
+
+    /// Exports a Z64 circuit over the prime field `field`, encoding constants and witness values
+    /// as `Export<u64>` does. Fails without writing anything if a gate or value can't be
+    /// represented.
+    pub fn export_z64_in(
+        field: Field,
+        gates: &[Operation<u64>],
+        witness: &[u64],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let config = ExportConfig::new(field);
+        Self::export_z64_configured(&config, gates, witness, annotations, sink)
+    }
+
+    /// `export_z64_in`, with the rest of the header from `config` too.
+    pub fn export_z64_configured(
+        config: &ExportConfig,
+        gates: &[Operation<u64>],
+        witness: &[u64],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let field = config.field;
+        field.check_domain(Domain::Z64)?;
+        let gates = gates
+            .iter()
+            .enumerate()
+            .map(|(idx, gate)| encode_z64_gate(field, idx, gate))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(violation) = Self::validate_gates(field, &gates, false)
+            .into_iter()
+            .next()
+        {
+            return Err(Error::new(ErrorKind::InvalidInput, violation));
+        }
+        check_witness(&gates, witness)?;
+        let witness = encode_z64_witness(field, witness)?;
+        let gates = lower_subtraction(&gates, field.characteristic - 1);
+        let gate_set = config.gate_set_for(
+            "arithmetic",
+            gates.iter().filter_map(|(_, gate)| arithmetic(gate)),
+        )?;
+
+        Self::write_header(config, witness.into_iter(), &gate_set, false, sink)?;
+        write_lowered(&gates, annotations, sink, |gate, sink| {
+            write_z64_gate(&config.dialect, gate, sink)
+        })?;
+        writeln!(sink, "@end")
+    }
+
+    /// Exports a circuit, writing stretches of gates that repeat (like the bodies of unrolled
+    /// loops) as calls to a `@function` defined once.
+    pub fn export_with_functions(
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        options: FunctionOptions,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::write_circuit(
+            &ExportConfig::new(Field::GF2),
+            gates,
+            Some(witness),
+            &Annotations::new(),
+            Some(options),
+            sink,
+        )
+    }
+
+    /// Exports the relation without a witness, noting each input's placeholder in a comment, and
+    /// returns the skeleton a witness has to match. `bind_witness` writes the witness document
+    /// later. Fails without writing anything, as `export_configured` does.
+    pub fn export_skeleton(
+        config: &ExportConfig,
+        gates: &[Operation<bool>],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<Skeleton> {
+        let annotations = Skeleton::annotate(gates, annotations);
+        Self::write_circuit(config, gates, None, &annotations, None, sink)?;
+        Ok(Skeleton::of(config.field, config.ir1_version(), gates))
+    }
+
+    /// Writes the witness document for a relation exported with `export_skeleton`, declaring the
+    /// same version and field. Fails without writing anything if the witness doesn't fit.
+    pub fn bind_witness(
+        skeleton: &Skeleton,
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        skeleton.check(witness)?;
+        let config = ExportConfig {
+            version: Some(skeleton.version.clone()),
+            ..ExportConfig::new(skeleton.field)
+        };
+        Self::write_witness(&config, witness.iter().map(|w| u64::from(*w)), sink)
+    }
+
+    /// Like `export_annotated_circuit`, but formats the gates on up to `threads` threads (one per
+    /// core if it's zero). The output is the same as the sequential export's. Validation and the
+    /// header still happen on the calling thread, before any gates are formatted.
+    pub fn export_parallel(
         gates: &[Operation<bool>],
         witness: &[bool],
+        annotations: &Annotations,
+        threads: usize,
         sink: &mut impl Write,
     ) -> Result<()> {
-        // Header fields.
-        writeln!(sink, "version 1.0.0;")?;
-        writeln!(sink, "field characteristic 2 degree 1;")?;
+        let field = Field::GF2;
+        if let Some(violation) = Self::validate(field, gates, false).into_iter().next() {
+            return Err(Error::new(ErrorKind::InvalidInput, violation));
+        }
+        check_witness(gates, witness)?;
+
+        let witness = witness.iter().map(|w| u64::from(*w));
+        Self::write_header(&ExportConfig::new(field), witness, "boolean", false, sink)?;
+        write_gates::<bool, Self>(gates, annotations, threads, sink)?;
+        writeln!(sink, "@end")
+    }
+
+    /// Writes everything up to the first gate, including the `@begin` of the circuit body.
+    fn write_header(
+        config: &ExportConfig,
+        witness: impl Iterator<Item = u64>,
+        gate_set: &str,
+        uses_functions: bool,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::write_witness(config, witness, sink)?;
+        Self::write_relation_header(config, gate_set, uses_functions, sink)
+    }
+
+    /// Writes the gate set and features, up to the `@begin` of the circuit body.
+    fn write_relation_header(
+        config: &ExportConfig,
+        gate_set: &str,
+        uses_functions: bool,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        // The only special feature (as opposed to @for or @switch) we might use is @function.
+        writeln!(sink, "gate_set: {};", gate_set)?;
+        let features = config.features_with(uses_functions);
+        if !features.is_empty() {
+            writeln!(sink, "features: {};", features.join(", "))?;
+        }
+        writeln!(sink, "@begin")
+    }
+
+    /// Writes the header fields and the witness. On its own, this is a witness document.
+    fn write_witness(
+        config: &ExportConfig,
+        witness: impl Iterator<Item = u64>,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        Self::write_fields(config, sink)?;
 
         // Witness body.
         writeln!(sink, "short_witness @begin")?;
-        for wit_value in witness.iter() {
-            writeln!(sink, "\t< {} >;", *wit_value as u32)?;
+        let indent = if config.dialect.indent_witness {
+            "\t"
+        } else {
+            ""
+        };
+        for wit_value in witness {
+            writeln!(sink, "{}{};", indent, config.dialect.constant(wit_value))?;
         }
-        writeln!(sink, "@end")?;
+        writeln!(sink, "@end")
+    }
+
+    /// Writes the version and field.
+    fn write_fields(config: &ExportConfig, sink: &mut impl Write) -> Result<()> {
+        writeln!(sink, "version {};", config.ir1_version())?;
+        writeln!(
+            sink,
+            "field characteristic {} degree {};",
+            config.field.characteristic, config.field.degree
+        )
+    }
+
+    /// Writes the relation, with the witness in its header unless it's `None`.
+    fn write_circuit(
+        config: &ExportConfig,
+        gates: &[Operation<bool>],
+        witness: Option<&[bool]>,
+        annotations: &Annotations,
+        functions: Option<FunctionOptions>,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let field = config.field;
+        field.check_constants(gates)?;
+        if let Some(violation) = Self::validate(field, gates, false).into_iter().next() {
+            return Err(Error::new(ErrorKind::InvalidInput, violation));
+        }
+        if let Some(witness) = witness {
+            check_witness(gates, witness)?;
+        }
+        let gate_set = config.gate_set_for("boolean", gates.iter().filter_map(boolean))?;
+        let plan = functions.map(|options| find_functions(gates, options));
 
-        // We're emitting a boolean circuit, and we don't currently use any special
-        // features (like @for, @switch, or @function).
-        writeln!(sink, "gate_set: boolean;")?;
+        let uses_functions = plan
+            .as_ref()
+            .is_some_and(|(functions, _)| !functions.is_empty());
+        match witness {
+            Some(witness) => {
+                let witness = witness.iter().map(|w| u64::from(*w));
+                Self::write_header(config, witness, &gate_set, uses_functions, sink)?
+            }
+            None => {
+                Self::write_fields(config, sink)?;
+                Self::write_relation_header(config, &gate_set, uses_functions, sink)?
+            }
+        }
 
-        // Circuit body.
-        // We're allowed to emit functions in here, before any literal
-        // gate directives. But we currently don't need that.
-        writeln!(sink, "@begin")?;
-        for gate in gates.iter() {
-            Self::export_gate(gate, sink)?;
+        // Circuit body. Functions have to be defined before any literal gate directives.
+        match &plan {
+            Some(plan) => write_items::<Self>(
+                gates,
+                plan,
+                annotations,
+                |function| {
+                    format!(
+                        "@out: {}, @in: {}, @instance: 0, @short_witness: {}",
+                        function.outputs, function.inputs, function.witness
+                    )
+                },
+                sink,
+            )?,
+            None => {
+                for (idx, gate) in gates.iter().enumerate() {
+                    if let Some(note) = annotations.get(&idx) {
+                        write_line_comment(note, sink)?;
+                    }
+                    write_bool_gate(&config.dialect, gate, sink)?;
+                }
+            }
         }
         writeln!(sink, "@end")?;
 
@@ -89,11 +781,66 @@ impl Export<bool> for IR1 {
     }
 }
 
+/// Writes an IR1 circuit a gate at a time. Gates are checked as they're written, so unlike
+/// `IR1::export_circuit`, a bad gate leaves partial output behind.
+pub(crate) struct IR1Writer<'a, W> {
+    field: Field,
+    annotations: &'a Annotations,
+    assigned: Assignments,
+    sink: W,
+}
+
+impl<'a, W: Write> IR1Writer<'a, W> {
+    /// Writes the header and witness. `annotations` are keyed by the indices that will be passed
+    /// to `write_gate`.
+    pub fn new(
+        field: Field,
+        witness: &[bool],
+        annotations: &'a Annotations,
+        mut sink: W,
+    ) -> Result<Self> {
+        field.check_domain(crate::Domain::GF2)?;
+        let witness = witness.iter().map(|w| u64::from(*w));
+        IR1::write_header(
+            &ExportConfig::new(field),
+            witness,
+            "boolean",
+            false,
+            &mut sink,
+        )?;
+        Ok(IR1Writer {
+            field,
+            annotations,
+            assigned: Assignments::default(),
+            sink,
+        })
+    }
+
+    pub fn write_gate(&mut self, idx: usize, gate: &Operation<bool>) -> Result<()> {
+        let mut violations = Vec::new();
+        self.assigned.check(self.field, idx, gate, &mut violations);
+        if let Some(violation) = violations.into_iter().next() {
+            return Err(Error::new(ErrorKind::InvalidInput, violation));
+        }
+        if let Some(note) = self.annotations.get(&idx) {
+            write_line_comment(note, &mut self.sink)?;
+        }
+        IR1::export_gate(gate, &mut self.sink)
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        writeln!(self.sink, "@end")
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::exporters::sieve::IR1;
-    use crate::exporters::Export;
-    use crate::Operation;
+    use crate::exporters::sieve::{IR1Violation, IR1};
+    use crate::exporters::{
+        CircuitSignature, Export, ExportConfig, FunctionOptions, Ir1Dialect, Skeleton,
+    };
+    use crate::parsers::ir1::IR1Parser;
+    use crate::{Annotations, Field, Fp, Operation};
 
     #[test]
     fn print_example() {
@@ -139,4 +886,471 @@ $0 <- @xor($6, < 1 >);
 "
         );
     }
+
+    #[test]
+    fn print_witness() {
+        let gates = [
+            Operation::Input(0),
+            Operation::Input(1),
+            Operation::Mul(2, 0, 1),
+            Operation::AssertZero(2),
+        ];
+        let signature = CircuitSignature::of(&gates);
+        let mut relation = Vec::new();
+        IR1::export_circuit(&gates, &[true, false], &mut relation).unwrap();
+
+        // The witness document is the relation's header and witness
+        let mut witness = Vec::new();
+        IR1::export_witness(&signature, &[true, false], &mut witness).unwrap();
+        assert!(relation.starts_with(&witness));
+        assert!(std::str::from_utf8(&witness)
+            .unwrap()
+            .ends_with(">;\n@end\n"));
+
+        let mut sink = Vec::new();
+        assert!(IR1::export_witness(&signature, &[true], &mut sink).is_err());
+        assert!(sink.is_empty());
+        assert!(IR1::export_witness(&signature, &[Fp::<101>::from(1); 2], &mut sink).is_err());
+
+        assert!(signature.verify(&gates).is_ok());
+        assert!(signature.verify(&gates[..3]).is_err());
+    }
+
+    #[test]
+    fn rejects_mismatched_field() {
+        let mut sink = Vec::new();
+        assert!(IR1::export_circuit_in(
+            Field::prime(101),
+            &[Operation::Const(0, true)],
+            &[],
+            &mut sink
+        )
+        .is_err());
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let gates = [
+            Operation::Input(0),
+            Operation::Add(2, 0, 1),
+            Operation::Const(3, true),
+            Operation::AddConst(3, 2, true),
+            Operation::AssertZero(3),
+        ];
+        assert_eq!(
+            IR1::validate(Field::GF2, &gates, true),
+            [
+                IR1Violation::Unassigned { gate: 1, wire: 1 },
+                IR1Violation::Reassigned {
+                    gate: 3,
+                    wire: 3,
+                    first: 2
+                },
+                IR1Violation::Gap { start: 1, end: 2 },
+            ]
+        );
+        assert_eq!(IR1::validate(Field::GF2, &gates[..1], true), []);
+
+        let mut sink = Vec::new();
+        let err = IR1::export_circuit(&gates, &[false], &mut sink).unwrap_err();
+        assert_eq!(err.to_string(), "gate 1 reads $1 before it's assigned");
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn print_annotations() {
+        let mut sink = Vec::new();
+        let annotations = vec![(1, "flip it\ntwice".to_string())]
+            .into_iter()
+            .collect();
+        IR1::export_annotated_circuit(
+            &[Operation::Input(0), Operation::AddConst(1, 0, true)],
+            &[true],
+            &annotations,
+            &mut sink,
+        )
+        .unwrap();
+
+        let bf = std::str::from_utf8(&sink).unwrap();
+        assert!(bf.ends_with(
+            "$0 <- @short_witness;
+// flip it
+// twice
+$1 <- @xor($0, < 1 >);
+@end
+"
+        ));
+    }
+
+    #[test]
+    fn print_prime_field() {
+        type F7 = Fp<7>;
+        let gates = [
+            Operation::Input(0),
+            Operation::MulConst(1, 0, F7::new(3)),
+            Operation::SubConst(2, 1, F7::new(2)),
+            Operation::AssertZero(2),
+        ];
+        let mut sink = Vec::new();
+        IR1::export_circuit(&gates, &[F7::new(3)], &mut sink).unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.starts_with("version 1.0.0;\nfield characteristic 7 degree 1;\n"));
+        assert!(text.contains("\t< 3 >;\n@end\ngate_set: arithmetic;\n"));
+        assert!(text.contains("$1 <- @mulc($0, < 3 >);\n$2 <- @addc($1, < 5 >);\n"));
+
+        // The parser reads it back as the same circuit, with representatives for the constants
+        let parsed = IR1Parser::<u64, _>::from_reader(sink.as_slice())
+            .read_all()
+            .unwrap();
+        assert_eq!(parsed[2], Operation::AddConst(2, 1, 5));
+
+        // Subtraction is multiplying by -1 into a wire past the circuit's, then adding
+        let mut sink = Vec::new();
+        IR1::export_circuit(
+            &[
+                Operation::Input(0),
+                Operation::Input(2),
+                Operation::Sub(1, 0, 2),
+            ],
+            &[F7::new(1), F7::new(3)],
+            &mut sink,
+        )
+        .unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.contains("$3 <- @mulc($2, < 6 >);\n$1 <- @add($0, $3);\n"));
+        let err = IR1::export_gate(&Operation::<F7>::Sub(1, 0, 0), &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("can't subtract"));
+
+        // Nor is there a field with fewer than two elements
+        let mut sink = Vec::new();
+        let err =
+            IR1::export_circuit(&[Operation::<Fp<1>>::AssertZero(0)], &[], &mut sink).unwrap_err();
+        assert_eq!(err.to_string(), "GF(1) isn't a field");
+        assert!(IR1::export_gate(&Operation::<Fp<0>>::AssertZero(0), &mut sink).is_err());
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn print_z64() {
+        let p = (1u64 << 61) - 1;
+        let gates = [
+            Operation::Input(0),
+            Operation::AddConst(1, 0, u64::MAX),
+            Operation::SubConst(2, 1, 3),
+            Operation::MulConst(3, 2, 2),
+            Operation::AssertZero(3),
+        ];
+        let mut sink = Vec::new();
+        IR1::export_circuit(&gates, &[4], &mut sink).unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.starts_with(&format!(
+            "version 1.0.0;\nfield characteristic {} degree 1;\n",
+            p
+        )));
+        assert!(text.contains("\t< 4 >;\n@end\ngate_set: arithmetic;\n"));
+        assert!(text.contains(&format!(
+            "$1 <- @addc($0, < {} >);\n$2 <- @addc($1, < {} >);\n$3 <- @mulc($2, < 2 >);\n",
+            p - 1,
+            p - 3
+        )));
+
+        // A bigger prime keeps the encoding it was given
+        let q = u64::MAX - 58;
+        let mut sink = Vec::new();
+        IR1::export_z64_in(
+            Field::prime(q),
+            &gates,
+            &[u64::MAX],
+            &Default::default(),
+            &mut sink,
+        )
+        .unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.contains(&format!("\t< {} >;\n", q - 1)));
+        assert!(text.contains(&format!("$1 <- @addc($0, < {} >);\n", q - 1)));
+
+        // Values from 2^63 up are negative in every field, even ones they'd fit in as they are
+        let mut sink = Vec::new();
+        let negative = [Operation::Const(0, (1 << 63) + 5)];
+        IR1::export_z64_in(
+            Field::prime(q),
+            &negative,
+            &[],
+            &Default::default(),
+            &mut sink,
+        )
+        .unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.contains(&format!("$0 <- < {} >;\n", q - ((1 << 63) - 5))));
+
+        // Values too big for the field in either direction
+        let mut sink = Vec::new();
+        let big = [Operation::Const(0, 1 << 62)];
+        let err = IR1::export_circuit(&big, &[], &mut sink).unwrap_err();
+        assert!(err.to_string().contains("out of range"));
+        assert!(IR1::export_circuit(&gates, &[1 << 62], &mut sink).is_err());
+        assert!(IR1::export_circuit(&[Operation::<u64>::Sub(0, 1, 2)], &[], &mut sink).is_err());
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn print_z64_subtraction() {
+        let p = (1u64 << 61) - 1;
+        let gates = [
+            Operation::Input(0),
+            Operation::Input(4),
+            Operation::Sub(2, 0, 4),
+            Operation::Sub(3, 2, 0),
+            Operation::AssertZero(3),
+        ];
+        let annotations = vec![(3, "difference".to_string())].into_iter().collect();
+        let mut sink = Vec::new();
+        IR1::export_annotated_circuit(&gates, &[5, 3], &annotations, &mut sink).unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+
+        // Multiplied by -1 into wires past the circuit's, then added
+        assert!(text.contains(&format!(
+            "$5 <- @mulc($4, < {p1} >);\n$2 <- @add($0, $5);\n\
+             // difference\n$6 <- @mulc($0, < {p1} >);\n$3 <- @add($2, $6);\n",
+            p1 = p - 1
+        )));
+
+        // A gate on its own has nowhere to put the product
+        assert!(IR1::export_gate(&gates[2], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn print_configured() {
+        let gates = [
+            Operation::Input(0),
+            Operation::Input(1),
+            Operation::Mul(2, 0, 1),
+            Operation::AssertZero(2),
+        ];
+        let config = ExportConfig {
+            version: Some("1.1.0".into()),
+            gate_set: Some("@and, @xor".into()),
+            features: vec!["@for".into()],
+            ..ExportConfig::new(Field::GF2)
+        };
+        let mut sink = Vec::new();
+        IR1::export_configured(
+            &config,
+            &gates,
+            &[true, false],
+            &Annotations::new(),
+            &mut sink,
+        )
+        .unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.starts_with("version 1.1.0;\nfield characteristic 2 degree 1;\n"));
+        assert!(text.contains("gate_set: @and, @xor;\nfeatures: @for;\n@begin\n"));
+
+        // Gate sets have to allow every gate, and named ones have to match the field
+        for gate_set in ["@xor", "arithmetic"] {
+            let config = ExportConfig {
+                gate_set: Some(gate_set.into()),
+                ..config.clone()
+            };
+            let mut sink = Vec::new();
+            assert!(IR1::export_configured(
+                &config,
+                &gates,
+                &[true, false],
+                &Annotations::new(),
+                &mut sink
+            )
+            .is_err());
+            assert!(sink.is_empty());
+        }
+
+        // Z64 circuits can be declared over any prime field that holds their constants
+        let gates = [
+            Operation::Input(0),
+            Operation::MulConst(1, 0, 3u64),
+            Operation::AssertZero(1),
+        ];
+        let config = ExportConfig {
+            gate_set: Some("@mulc".into()),
+            ..ExportConfig::new(Field::prime(101))
+        };
+        let mut sink = Vec::new();
+        IR1::export_z64_configured(&config, &gates, &[0], &Annotations::new(), &mut sink).unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.starts_with("version 1.0.0;\nfield characteristic 101 degree 1;\n"));
+        assert!(text.contains("gate_set: @mulc;\n@begin\n"));
+    }
+
+    #[test]
+    fn print_dialect() {
+        let gates = [
+            Operation::Input(0),
+            Operation::MulConst(1, 0, true),
+            Operation::AssertZero(1),
+        ];
+        let config = ExportConfig {
+            dialect: Ir1Dialect {
+                version: "1.0.1".into(),
+                ..Ir1Dialect::preset("zki-sieve").unwrap()
+            },
+            ..ExportConfig::new(Field::GF2)
+        };
+        let mut sink = Vec::new();
+        IR1::export_configured(&config, &gates, &[false], &Annotations::new(), &mut sink).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&sink).unwrap(),
+            "version 1.0.1;
+field characteristic 2 degree 1;
+short_witness @begin
+<0>;
+@end
+gate_set: boolean;
+@begin
+$0 <- @short_witness;
+$1 <- @and($0, <1>);
+@assert_zero($1);
+@end
+"
+        );
+
+        // Listing the gate set only names what's used, and a config's own gate set still wins
+        let mut config = ExportConfig {
+            dialect: Ir1Dialect::preset("wiztoolkit").unwrap(),
+            ..ExportConfig::new(Field::GF2)
+        };
+        let mut sink = Vec::new();
+        IR1::export_configured(&config, &gates, &[false], &Annotations::new(), &mut sink).unwrap();
+        let text = std::str::from_utf8(&sink).unwrap();
+        assert!(text.contains("\t< 0 >;\n@end\ngate_set: @and;\n"));
+        config.gate_set = Some("boolean".into());
+        let mut sink = Vec::new();
+        IR1::export_configured(&config, &gates, &[false], &Annotations::new(), &mut sink).unwrap();
+        assert!(std::str::from_utf8(&sink)
+            .unwrap()
+            .contains("gate_set: boolean;\n"));
+
+        // Dialects can be kept in config files, leaving out what they don't change
+        let dialect: Ir1Dialect = serde_json::from_str(r#"{"spaced_constants": false}"#).unwrap();
+        assert_eq!(dialect.version, "1.0.0");
+        assert!(dialect.indent_witness && !dialect.spaced_constants);
+        for name in Ir1Dialect::PRESETS {
+            assert!(Ir1Dialect::preset(name).is_some());
+        }
+        assert_eq!(Ir1Dialect::preset("default"), Some(Ir1Dialect::default()));
+        assert_eq!(Ir1Dialect::preset("nope"), None);
+    }
+
+    #[test]
+    fn print_skeleton() {
+        let gates = [
+            Operation::Input(0),
+            Operation::Input(1),
+            Operation::Mul(2, 0, 1),
+            Operation::AssertZero(2),
+        ];
+        let mut annotations = Annotations::new();
+        annotations.insert(1, "select".into());
+        let config = ExportConfig::new(Field::GF2);
+        let mut relation = Vec::new();
+        let skeleton = IR1::export_skeleton(&config, &gates, &annotations, &mut relation).unwrap();
+        assert_eq!(skeleton.placeholders, [0, 1]);
+        assert_eq!(
+            std::str::from_utf8(&relation).unwrap(),
+            "version 1.0.0;
+field characteristic 2 degree 1;
+gate_set: boolean;
+@begin
+// witness ?0
+$0 <- @short_witness;
+// select
+// witness ?1
+$1 <- @short_witness;
+$2 <- @and($0, $1);
+@assert_zero($2);
+@end
+"
+        );
+
+        // The skeleton survives being shipped, and binds the witness by wire
+        let json = serde_json::to_string(&skeleton).unwrap();
+        let skeleton: Skeleton = serde_json::from_str(&json).unwrap();
+        let witness = skeleton.arrange(&[(0, true), (1, false)]).unwrap();
+        let mut sink = Vec::new();
+        IR1::bind_witness(&skeleton, &witness, &mut sink).unwrap();
+        let mut full = Vec::new();
+        IR1::export_circuit(&gates, &witness, &mut full).unwrap();
+        assert!(full.starts_with(&sink));
+
+        assert!(skeleton.arrange(&[(1, false), (0, true)]).is_err());
+        assert!(skeleton.arrange(&[(0, true)]).is_err());
+        assert!(skeleton.check(&[0u64, 1]).is_err());
+        let mut sink = Vec::new();
+        assert!(IR1::bind_witness(&skeleton, &[true], &mut sink).is_err());
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn print_functions() {
+        // An unrolled loop that flips a bit and checks it each time around
+        let mut gates = vec![Operation::Input(0)];
+        let mut witness = vec![true];
+        for i in 0..10 {
+            gates.extend([
+                Operation::Input(3 * i + 1),
+                Operation::Add(3 * i + 2, 3 * i, 3 * i + 1),
+                Operation::AddConst(3 * i + 3, 3 * i + 2, true),
+                Operation::AssertZero(3 * i + 2),
+            ]);
+            witness.push(i % 2 == 0);
+        }
+
+        // One time around the loop at a time
+        let options = FunctionOptions {
+            min_len: 4,
+            max_len: 4,
+        };
+        let mut sink = Vec::new();
+        IR1::export_with_functions(&gates, &witness, options, &mut sink).unwrap();
+        let bf = std::str::from_utf8(&sink).unwrap();
+        assert!(bf.contains(
+            "gate_set: boolean;
+features: @function;
+@begin
+@function(f0, @out: 1, @in: 1, @instance: 0, @short_witness: 1)
+@begin
+$2 <- @short_witness;
+$3 <- @xor($1, $2);
+$0 <- @xor($3, < 1 >);
+@assert_zero($3);
+@end
+$0 <- @short_witness;
+$3 <- @call(f0, $0);
+$6 <- @call(f0, $3);
+"
+        ));
+        // Nothing reads the last result, so the last time around doesn't match
+        assert!(bf.ends_with(
+            "$27 <- @call(f0, $24);
+$28 <- @short_witness;
+$29 <- @xor($27, $28);
+$30 <- @xor($29, < 1 >);
+@assert_zero($29);
+@end
+"
+        ));
+
+        // Without repeats, it's the same as usual
+        let mut plain = Vec::new();
+        IR1::export_circuit(&gates[..5], &witness[..2], &mut plain).unwrap();
+        let mut sink = Vec::new();
+        IR1::export_with_functions(
+            &gates[..5],
+            &witness[..2],
+            FunctionOptions::default(),
+            &mut sink,
+        )
+        .unwrap();
+        assert_eq!(sink, plain);
+    }
 }
@@ -2,11 +2,18 @@
 
 use std::io::{Error, ErrorKind, Result, Write};
 
-use crate::exporters::Export;
-use crate::Operation;
+use crate::exporters::functions::{find_functions, write_items, FunctionOptions};
+use crate::exporters::parallel::{thread_count, write_gates};
+use crate::exporters::{
+    check_witness, write_line_comment, CircuitSignature, Export, ExportConfig, MultiSink, Skeleton,
+};
+use crate::{Annotations, Domain, Field, Operation};
 
 pub struct IR0;
 
+/// The version of the spec the output follows, unless an `ExportConfig` says otherwise
+const VERSION: &str = "2.0.0-beta";
+
 impl Export<bool> for IR0 {
     fn export_gate(gate: &Operation<bool>, sink: &mut impl Write) -> Result<()> {
         match gate {
@@ -14,10 +21,7 @@ impl Export<bool> for IR0 {
                 //NOTE(lisaoverall): needs to be updated for field switching
                 writeln!(sink, "${} <- @private();", i)
             }
-            Operation::Random(_) => Err(Error::new(
-                ErrorKind::Other,
-                "can't use random gates in IR1",
-            )),
+            Operation::Random(_) => Err(Error::other("can't use random gates in IR1")),
             Operation::Add(o, l, r) => {
                 writeln!(sink, "${} <- @add(${}, ${});", o, l, r)
             }
@@ -45,35 +49,241 @@ impl Export<bool> for IR0 {
         }
     }
 
+    /// The witness goes in a separate file; see `export_private_input`.
+    const WRITES_WITNESS: bool = false;
+
     fn export_circuit(gates: &[Operation<bool>], _: &[bool], sink: &mut impl Write) -> Result<()> {
-        // Header fields.
-        writeln!(sink, "version 2.0.0-beta;")?;
+        Self::export_circuit_in(Field::GF2, gates, sink)
+    }
+
+    fn export_annotated_circuit(
+        gates: &[Operation<bool>],
+        _: &[bool],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let config = ExportConfig::new(Field::GF2);
+        Self::write_circuit(&config, gates, annotations, None, sink)
+    }
+
+    /// Same as `export_private_input`, once the witness is checked against `signature`.
+    fn export_witness(
+        signature: &CircuitSignature,
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        signature.check(witness)?;
+        Self::export_private_input(witness, sink)
+    }
+}
+
+/// IR0 only has syntax for prime fields.
+fn check_prime_field(field: Field) -> Result<()> {
+    if field.degree != 1 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("IR0 can't declare extension field {}", field),
+        ));
+    }
+    Ok(())
+}
+
+impl IR0 {
+    /// Exports a circuit, declaring that it's over `field`. Fails without writing anything if the
+    /// gates can't be represented in that field.
+    pub fn export_circuit_in(
+        field: Field,
+        gates: &[Operation<bool>],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let config = ExportConfig::new(field);
+        Self::write_circuit(&config, gates, &Annotations::new(), None, sink)
+    }
+
+    /// Exports a circuit, writing stretches of gates that repeat (like the bodies of unrolled
+    /// loops) as calls to a `@function` defined once.
+    pub fn export_with_functions(
+        gates: &[Operation<bool>],
+        options: FunctionOptions,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        let config = ExportConfig::new(Field::GF2);
+        Self::write_circuit(&config, gates, &Annotations::new(), Some(options), sink)
+    }
+
+    /// Writes all three IR0 files, to the sinks named `circuit`, `private_input`, and
+    /// `public_input`. Boolean circuits have no public inputs, so the last one is empty. Fails
+    /// before writing anything if the witness doesn't match the circuit's inputs.
+    pub fn export(
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        annotations: &Annotations,
+        sinks: &mut impl MultiSink,
+    ) -> Result<()> {
+        let config = ExportConfig::new(Field::GF2);
+        Self::export_configured(&config, gates, witness, annotations, sinks)
+    }
+
+    /// `export`, with the headers `config` asks for. IR0 has no gate set or features, so it fails
+    /// if `config` sets them.
+    pub fn export_configured(
+        config: &ExportConfig,
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        annotations: &Annotations,
+        sinks: &mut impl MultiSink,
+    ) -> Result<()> {
+        config.check_ir0()?;
+        check_prime_field(config.field)?;
+        config.field.check_constants(gates)?;
+        check_witness(gates, witness)?;
+        Self::write_circuit(
+            config,
+            gates,
+            annotations,
+            None,
+            &mut sinks.sink("circuit")?,
+        )?;
+        let private_input = &mut sinks.sink("private_input")?;
+        Self::export_input(config, Some(witness), "private_input", private_input)?;
+        Self::export_input(
+            config,
+            None,
+            "public_input",
+            &mut sinks.sink("public_input")?,
+        )
+    }
+
+    /// Exports the relation on its own, noting each input's placeholder in a comment, and returns
+    /// the skeleton a witness has to match. `bind_witness` writes the input files later.
+    pub fn export_skeleton(
+        config: &ExportConfig,
+        gates: &[Operation<bool>],
+        annotations: &Annotations,
+        sink: &mut impl Write,
+    ) -> Result<Skeleton> {
+        let annotations = Skeleton::annotate(gates, annotations);
+        Self::write_circuit(config, gates, &annotations, None, sink)?;
+        Ok(Skeleton::of(
+            config.field,
+            config.version_or(VERSION),
+            gates,
+        ))
+    }
+
+    /// Writes the `private_input` and `public_input` files for a relation exported with
+    /// `export_skeleton`, declaring the same version and field. Fails without writing anything if
+    /// the witness doesn't fit.
+    pub fn bind_witness(
+        skeleton: &Skeleton,
+        witness: &[bool],
+        sinks: &mut impl MultiSink,
+    ) -> Result<()> {
+        check_prime_field(skeleton.field)?;
+        skeleton.check(witness)?;
+        let config = ExportConfig {
+            version: Some(skeleton.version.clone()),
+            ..ExportConfig::new(skeleton.field)
+        };
+        let private_input = &mut sinks.sink("private_input")?;
+        Self::export_input(&config, Some(witness), "private_input", private_input)?;
+        Self::export_input(
+            &config,
+            None,
+            "public_input",
+            &mut sinks.sink("public_input")?,
+        )
+    }
+
+    /// Writes the relation and the private input at the same time, one thread on the private
+    /// input and the rest (up to `threads` in all, or one per core if it's zero) formatting
+    /// gates. Each sink gets the same output as the sequential export. Fails before writing
+    /// anything if the witness doesn't match the circuit's inputs.
+    pub fn export_parallel(
+        gates: &[Operation<bool>],
+        witness: &[bool],
+        annotations: &Annotations,
+        threads: usize,
+        relation: &mut (impl Write + Send),
+        private_input: &mut (impl Write + Send),
+    ) -> Result<()> {
+        let field = Field::GF2;
+        field.check_constants(gates)?;
+        check_witness(gates, witness)?;
+        let gate_threads = thread_count(threads).saturating_sub(1).max(1);
+
+        let (relation, private_input) = std::thread::scope(|scope| {
+            let private_input =
+                scope.spawn(move || IR0::export_private_input_in(field, witness, private_input));
+            let relation = (|| {
+                Self::write_relation_header(&ExportConfig::new(field), relation)?;
+                write_gates::<bool, Self>(gates, annotations, gate_threads, relation)?;
+                writeln!(relation, "@end")
+            })();
+            let private_input = private_input
+                .join()
+                .expect("Writing the private input doesn't panic");
+            (relation, private_input)
+        });
+        relation.and(private_input)
+    }
+
+    /// Writes everything up to the first gate, including the `@begin` of the circuit body.
+    fn write_relation_header(config: &ExportConfig, sink: &mut impl Write) -> Result<()> {
+        writeln!(sink, "version {};", config.version_or(VERSION))?;
         writeln!(sink, "circuit;")?;
-        writeln!(sink, "@type field 2;")?;
+        writeln!(sink, "@type field {};", config.field.characteristic)?;
+        writeln!(sink, "@begin")
+    }
 
-        // Circuit body.
-        // We're allowed to emit functions in here, before any literal
-        // gate directives. But we currently don't need that.
-        writeln!(sink, "@begin")?;
-        for gate in gates.iter() {
-            Self::export_gate(gate, sink)?;
+    fn write_circuit(
+        config: &ExportConfig,
+        gates: &[Operation<bool>],
+        annotations: &Annotations,
+        functions: Option<FunctionOptions>,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        config.check_ir0()?;
+        check_prime_field(config.field)?;
+        config.field.check_constants(gates)?;
+
+        // Circuit body. Functions have to be defined before any literal gate directives.
+        Self::write_relation_header(config, sink)?;
+        match functions {
+            Some(options) => write_items::<Self>(
+                gates,
+                &find_functions(gates, options),
+                annotations,
+                |function| format!("@out: 0:{}, @in: 0:{}", function.outputs, function.inputs),
+                sink,
+            )?,
+            None => {
+                for (idx, gate) in gates.iter().enumerate() {
+                    if let Some(note) = annotations.get(&idx) {
+                        write_line_comment(note, sink)?;
+                    }
+                    Self::export_gate(gate, sink)?;
+                }
+            }
         }
         writeln!(sink, "@end")?;
 
         Ok(())
     }
-}
 
-impl IR0 {
     fn export_input(
+        config: &ExportConfig,
         witness: Option<&[bool]>,
         input_type: &str,
         sink: &mut impl Write,
     ) -> Result<()> {
+        check_prime_field(config.field)?;
+        config.field.check_domain(Domain::GF2)?;
+
         // Header fields.
-        writeln!(sink, "version 2.0.0-beta;")?;
+        writeln!(sink, "version {};", config.version_or(VERSION))?;
         writeln!(sink, "{};", input_type)?;
-        writeln!(sink, "@type field 2;")?;
+        writeln!(sink, "@type field {};", config.field.characteristic)?;
 
         // Private input body.
         writeln!(sink, "@begin")?;
@@ -88,19 +298,42 @@ impl IR0 {
     }
 
     pub fn export_private_input(witness: &[bool], sink: &mut impl Write) -> Result<()> {
-        IR0::export_input(Some(witness), "private_input", sink)
+        IR0::export_private_input_in(Field::GF2, witness, sink)
     }
 
     pub fn export_public_input(instance: Option<&[bool]>, sink: &mut impl Write) -> Result<()> {
-        IR0::export_input(instance, "public_input", sink)
+        IR0::export_public_input_in(Field::GF2, instance, sink)
+    }
+
+    pub fn export_private_input_in(
+        field: Field,
+        witness: &[bool],
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        IR0::export_input(
+            &ExportConfig::new(field),
+            Some(witness),
+            "private_input",
+            sink,
+        )
+    }
+
+    pub fn export_public_input_in(
+        field: Field,
+        instance: Option<&[bool]>,
+        sink: &mut impl Write,
+    ) -> Result<()> {
+        IR0::export_input(&ExportConfig::new(field), instance, "public_input", sink)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use crate::exporters::sievephase2::IR0;
-    use crate::exporters::Export;
-    use crate::Operation;
+    use crate::exporters::{Export, ExportConfig, FileSinks};
+    use crate::{Annotations, Field, Operation};
 
     #[test]
     fn print_example_circuit() {
@@ -162,4 +395,112 @@ private_input;
 "
         );
     }
+
+    #[test]
+    fn print_all_files() {
+        let gates = [
+            Operation::Input(0),
+            Operation::AddConst(1, 0, true),
+            Operation::AssertZero(1),
+        ];
+        let mut files = BTreeMap::new();
+        IR0::export(&gates, &[true], &Annotations::new(), &mut files).unwrap();
+        let names: Vec<&str> = files.keys().map(String::as_str).collect();
+        assert_eq!(names, ["circuit", "private_input", "public_input"]);
+
+        let mut circuit = Vec::new();
+        IR0::export_circuit(&gates, &[true], &mut circuit).unwrap();
+        assert_eq!(files["circuit"], circuit);
+        assert!(std::str::from_utf8(&files["public_input"])
+            .unwrap()
+            .contains("public_input;\n@type field 2;\n@begin\n@end\n"));
+
+        let prefix = std::env::temp_dir().join(format!("mcircuit_ir0_{}", std::process::id()));
+        let mut sinks = FileSinks::new(&prefix);
+        IR0::export(&gates, &[true], &Annotations::new(), &mut sinks).unwrap();
+        sinks.finish().unwrap();
+        let private_input = prefix.with_extension("private_input");
+        assert_eq!(
+            std::fs::read(&private_input).unwrap(),
+            files["private_input"]
+        );
+        for name in ["circuit", "private_input", "public_input"] {
+            std::fs::remove_file(prefix.with_extension(name)).unwrap();
+        }
+
+        // Nothing is written for a bad witness
+        let mut files = BTreeMap::new();
+        assert!(IR0::export(&gates, &[], &Annotations::new(), &mut files).is_err());
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn print_configured() {
+        let gates = [Operation::Input(0), Operation::AssertZero(0)];
+        let config = ExportConfig {
+            version: Some("2.0.0".into()),
+            ..ExportConfig::new(Field::GF2)
+        };
+        let mut files = BTreeMap::new();
+        IR0::export_configured(&config, &gates, &[false], &Annotations::new(), &mut files).unwrap();
+        for file in files.values() {
+            assert!(file.starts_with(b"version 2.0.0;\n"));
+        }
+
+        // IR0 has nowhere to declare features
+        let config = ExportConfig {
+            features: vec!["@for".into()],
+            ..config
+        };
+        let mut files = BTreeMap::new();
+        assert!(
+            IR0::export_configured(&config, &gates, &[false], &Annotations::new(), &mut files)
+                .is_err()
+        );
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn print_skeleton() {
+        let gates = [
+            Operation::Input(0),
+            Operation::AddConst(1, 0, true),
+            Operation::AssertZero(1),
+        ];
+        let config = ExportConfig::new(Field::GF2);
+        let mut relation = Vec::new();
+        let skeleton =
+            IR0::export_skeleton(&config, &gates, &Annotations::new(), &mut relation).unwrap();
+        assert!(std::str::from_utf8(&relation)
+            .unwrap()
+            .contains("@begin\n// witness ?0\n$0 <- @private();\n"));
+
+        // The input files are the same as if they'd been exported with the relation
+        let mut bound = BTreeMap::new();
+        IR0::bind_witness(&skeleton, &[true], &mut bound).unwrap();
+        let mut files = BTreeMap::new();
+        IR0::export(&gates, &[true], &Annotations::new(), &mut files).unwrap();
+        files.remove("circuit");
+        assert_eq!(bound, files);
+
+        let mut bound = BTreeMap::new();
+        assert!(IR0::bind_witness(&skeleton, &[true, false], &mut bound).is_err());
+        assert!(bound.is_empty());
+    }
+
+    #[test]
+    fn rejects_mismatched_field() {
+        let mut sink = Vec::new();
+        assert!(IR0::export_private_input_in(Field::prime(101), &[true], &mut sink).is_err());
+        assert!(IR0::export_circuit_in(
+            Field {
+                characteristic: 2,
+                degree: 8
+            },
+            &[],
+            &mut sink
+        )
+        .is_err());
+        assert!(sink.is_empty());
+    }
 }
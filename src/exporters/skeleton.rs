@@ -0,0 +1,92 @@
+//! Relations shipped before their witnesses exist. Exporting a skeleton writes the relation with a
+//! placeholder (`?0`, `?1`, ...) noted at each input, and returns a `Skeleton` describing the
+//! witness it expects. Once the witness is known, the exporter checks it against the skeleton and
+//! writes the witness files on their own.
+
+use std::io::{Error, ErrorKind, Result};
+
+use serde::{Deserialize, Serialize};
+
+use crate::exporters::CircuitSignature;
+use crate::{Annotations, Field, Operation, WireValue};
+
+/// What a witness has to look like to go with a relation exported as a skeleton. Serializable, so
+/// it can be shipped alongside the relation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Skeleton {
+    pub signature: CircuitSignature,
+    /// The field the relation declares
+    pub field: Field,
+    /// The `version` the relation declares, which the witness files declare too
+    pub version: String,
+    /// The wire each placeholder is assigned to: `?i` is the value `placeholders[i]` gets. The
+    /// same wire comes up more than once if more than one input writes it.
+    pub placeholders: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn of<T: WireValue>(field: Field, version: &str, gates: &[Operation<T>]) -> Self {
+        Skeleton {
+            signature: CircuitSignature::of(gates),
+            field,
+            version: version.to_string(),
+            placeholders: gates
+                .iter()
+                .filter_map(|gate| match gate {
+                    Operation::Input(wire) => Some(*wire),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// `annotations`, with the placeholder of each input added to the note on its gate.
+    pub(crate) fn annotate<T: WireValue>(
+        gates: &[Operation<T>],
+        annotations: &Annotations,
+    ) -> Annotations {
+        let mut annotated = annotations.clone();
+        let inputs = gates
+            .iter()
+            .enumerate()
+            .filter(|(_, gate)| matches!(gate, Operation::Input(_)));
+        for (placeholder, (idx, _)) in inputs.enumerate() {
+            let note = annotated.entry(idx).or_default();
+            if !note.is_empty() {
+                note.push('\n');
+            }
+            note.push_str(&format!("witness ?{}", placeholder));
+        }
+        annotated
+    }
+
+    /// Checks that `witness` fills every placeholder, with values from a domain the skeleton's
+    /// field can hold.
+    pub fn check<T: WireValue>(&self, witness: &[T]) -> Result<()> {
+        self.field.check_domain(T::DOMAIN)?;
+        self.signature.check(witness)
+    }
+
+    /// Puts a witness given as `(wire, value)` pairs into placeholder order, checking that it
+    /// lists the placeholders' wires in the order the relation reads them. Witnesses generated
+    /// separately from the relation can get the order wrong in ways the count alone won't catch.
+    pub fn arrange<T: WireValue>(&self, assignments: &[(usize, T)]) -> Result<Vec<T>> {
+        let misordered = assignments
+            .iter()
+            .zip(&self.placeholders)
+            .enumerate()
+            .find(|(_, ((wire, _), expected))| wire != *expected);
+        if let Some((idx, ((wire, _), expected))) = misordered {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "witness value {} is for wire {}, but placeholder ?{} is wire {}",
+                    idx, wire, idx, expected
+                ),
+            ));
+        }
+        let witness: Vec<T> = assignments.iter().map(|(_, value)| *value).collect();
+        self.check(&witness)?;
+        Ok(witness)
+    }
+}
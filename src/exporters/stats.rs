@@ -0,0 +1,171 @@
+//! Gate counts and estimated proving cost as JSON, for dashboards that track how circuits grow
+//! over time.
+//!
+//! Reports carry a `schema_version` of the form `MAJOR.MINOR`. Within a major version, newer
+//! minor versions only add fields: a field that's in one version keeps its name, type and meaning
+//! in every later version with the same major number. Consumers should ignore fields they don't
+//! recognize. Anything else is a new major version.
+
+use std::collections::BTreeMap;
+use std::io::{Error, Result, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{AnalysisPass, CostModel, GateStats};
+use crate::program::content_hash;
+use crate::{HasIO, Program};
+
+/// Version of the report's schema. See the module docs for what a version promises.
+pub const STATS_SCHEMA_VERSION: &str = "1.1";
+
+/// Gate counts and cost of part of a program.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModuleStats {
+    pub stats: GateStats,
+    /// `GateStats::cost` under the report's cost model
+    pub cost: f64,
+}
+
+/// A machine-readable report of a whole program's gates and cost, broken down by module.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatsReport {
+    /// `STATS_SCHEMA_VERSION` when the report was made
+    pub schema_version: String,
+    /// `content_hash` of the program's gates, in hex
+    pub content_hash: String,
+    /// `Program::content_hash` of the program, which also covers its constant vectors and
+    /// generators, in hex. Added in 1.1, and empty when reading an older report.
+    #[serde(default)]
+    pub program_hash: String,
+    pub total: ModuleStats,
+    /// Weights the costs were computed with
+    pub cost_model: CostModel,
+    /// Stats for the gates of each module, keyed by module name. A gate belongs to the module of
+    /// the wire it writes (or, for an assertion, checks): the wire's name in the program's name
+    /// table, up to its last `.` or `::`. Gates on unnamed wires, or on names without a module,
+    /// are only counted in `total`.
+    pub modules: BTreeMap<String, ModuleStats>,
+}
+
+/// The module part of a hierarchical wire name, like `alu0.adder3` for `alu0.adder3.carry[7]` or
+/// `top` for `top::a`.
+fn module_of(name: &str) -> Option<&str> {
+    let dot = name.rfind('.');
+    let colons = name.rfind("::");
+    dot.max(colons).map(|end| &name[..end])
+}
+
+impl StatsReport {
+    pub fn of(program: &Program, model: &CostModel) -> Self {
+        let cost = |stats: GateStats| ModuleStats {
+            stats,
+            cost: stats.cost(model),
+        };
+
+        let mut modules: BTreeMap<String, GateStats> = BTreeMap::new();
+        if let Some(names) = &program.names {
+            for gate in &program.gates {
+                let wire = match gate.dst() {
+                    Some(dst) => gate.output_domain().map(|domain| (domain, dst)),
+                    None => gate.input_domain().zip(gate.inputs().next()),
+                };
+                let module = wire
+                    .and_then(|(domain, wire)| names.get(domain, wire))
+                    .and_then(|name| module_of(name));
+                if let Some(module) = module {
+                    match modules.get_mut(module) {
+                        Some(stats) => stats.analyze_gate(gate),
+                        None => {
+                            let mut stats = GateStats::default();
+                            stats.analyze_gate(gate);
+                            modules.insert(module.to_string(), stats);
+                        }
+                    }
+                }
+            }
+        }
+
+        StatsReport {
+            schema_version: STATS_SCHEMA_VERSION.to_string(),
+            content_hash: format!("{:016x}", content_hash(&program.gates)),
+            program_hash: format!("{:016x}", program.content_hash()),
+            total: cost(GateStats::analyze(program.gates.iter())),
+            cost_model: *model,
+            modules: modules
+                .into_iter()
+                .map(|(module, stats)| (module, cost(stats)))
+                .collect(),
+        }
+    }
+
+    pub fn write_json(&self, sink: &mut impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(&mut *sink, self).map_err(Error::other)?;
+        writeln!(sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::CostModel;
+    use crate::exporters::stats::{StatsReport, STATS_SCHEMA_VERSION};
+    use crate::exporters::summary::Summary;
+    use crate::{CombineOperation, Domain, Generator, NameTable, Operation, Program};
+
+    #[test]
+    fn test_stats_report() {
+        let mut names = NameTable::default();
+        names.insert(Domain::GF2, 0, "x".into());
+        names.insert(Domain::GF2, 1, "alu0.adder3.sum".into());
+        names.insert(Domain::GF2, 2, "alu0.adder3.carry[0]".into());
+        names.insert(Domain::Z64, 0, "top::word".into());
+        let program = Program {
+            gates: vec![
+                CombineOperation::GF2(Operation::Input(0)),
+                CombineOperation::GF2(Operation::Add(1, 0, 0)),
+                CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+                CombineOperation::GF2(Operation::AssertZero(2)),
+                CombineOperation::B2A(0, 0),
+            ],
+            names: Some(names),
+            ..Default::default()
+        };
+        let report = StatsReport::of(&program, &CostModel::default());
+        assert_eq!(report.total.stats.gates(), 5);
+        assert_eq!(report.total.cost, 1.0 + 1.0 + 1.0 + 128.0);
+
+        let modules: Vec<&str> = report.modules.keys().map(String::as_str).collect();
+        assert_eq!(modules, ["alu0.adder3", "top"]);
+        let adder = &report.modules["alu0.adder3"];
+        assert_eq!(adder.stats.gf2.gates(), 3);
+        assert_eq!(adder.cost, 2.0);
+        assert_eq!(report.modules["top"].stats.conversions, 1);
+
+        let mut sink = Vec::new();
+        report.write_json(&mut sink).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&sink).unwrap();
+        assert_eq!(json["schema_version"], STATS_SCHEMA_VERSION);
+        assert_eq!(json["modules"]["top"]["stats"]["conversions"], 1);
+        let parsed: StatsReport = serde_json::from_slice(&sink).unwrap();
+        assert_eq!(parsed, report);
+
+        // `content_hash` is still the gates-only hash the summary uses, and generators only
+        // change `program_hash`
+        assert_eq!(
+            report.content_hash,
+            Summary::of(&program.gates).content_hash
+        );
+        let generated = Program {
+            generators: Some(vec![Generator::new("mcircuit", "0.1.10")]),
+            ..program.clone()
+        };
+        let regenerated = StatsReport::of(&generated, &CostModel::default());
+        assert_eq!(regenerated.content_hash, report.content_hash);
+        assert_ne!(regenerated.program_hash, report.program_hash);
+
+        // Reports from 1.0 didn't have `program_hash`
+        let mut json = json;
+        json.as_object_mut().unwrap().remove("program_hash");
+        let old: StatsReport = serde_json::from_value(json).unwrap();
+        assert_eq!(old.program_hash, "");
+    }
+}
@@ -0,0 +1,196 @@
+//! A short, human- and machine-readable overview of a program, meant for CI artifacts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, Result, Write};
+
+use serde::Serialize;
+
+use crate::analysis::AnalysisPass;
+use crate::program::ContentHasher;
+use crate::{CombineOperation, HasIO, Operation, WireValue};
+
+/// Statistics for the gates of a single domain.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DomainSummary {
+    pub gates: usize,
+    /// Gate counts, keyed by `Operation::kind`
+    pub kinds: BTreeMap<String, usize>,
+    pub inputs: usize,
+    pub assertions: usize,
+    /// Longest chain of `Mul` gates feeding any wire in this domain
+    pub mul_depth: usize,
+}
+
+/// One-page overview of a program.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub gates: usize,
+    pub gf2: DomainSummary,
+    pub z64: DomainSummary,
+    /// Number of B2A gates
+    pub conversions: usize,
+    /// Every size hint in the program, as (z64, gf2)
+    pub size_hints: Vec<(usize, usize)>,
+    /// Longest chain of dependent gates, across both domains
+    pub depth: usize,
+    /// `content_hash` of the program, in hex
+    pub content_hash: String,
+}
+
+/// Tracks the (total, multiplicative) depth of every wire in a domain.
+type Depths = HashMap<usize, (usize, usize)>;
+
+fn count<T: WireValue>(op: &Operation<T>, summary: &mut DomainSummary, depths: &mut Depths) {
+    summary.gates += 1;
+    *summary.kinds.entry(op.kind().to_string()).or_default() += 1;
+    match op {
+        Operation::Input(_) => summary.inputs += 1,
+        Operation::AssertZero(_) => summary.assertions += 1,
+        _ => {}
+    }
+
+    let (depth, mul_depth) = op
+        .inputs()
+        .filter_map(|w| depths.get(&w))
+        .fold((0, 0), |(d, m), (wd, wm)| (d.max(*wd), m.max(*wm)));
+    let mul_depth = mul_depth + usize::from(matches!(op, Operation::Mul(_, _, _)));
+    summary.mul_depth = summary.mul_depth.max(mul_depth);
+
+    if let Some(dst) = op.dst() {
+        depths.insert(dst, (depth + 1, mul_depth));
+    }
+}
+
+/// Builds a `Summary` as an `AnalysisPass`, so it can share a walk over the program with other
+/// passes.
+#[derive(Default)]
+pub struct SummaryPass {
+    summary: Summary,
+    gf2_depths: Depths,
+    z64_depths: Depths,
+    hasher: ContentHasher,
+}
+
+impl AnalysisPass for SummaryPass {
+    type Output = Summary;
+
+    fn analyze_gate(&mut self, gate: &CombineOperation) {
+        let summary = &mut self.summary;
+        summary.gates += 1;
+        self.hasher.update(gate);
+        match gate {
+            CombineOperation::GF2(op) => count(op, &mut summary.gf2, &mut self.gf2_depths),
+            CombineOperation::Z64(op) => count(op, &mut summary.z64, &mut self.z64_depths),
+            CombineOperation::B2A(dst, _) => {
+                summary.conversions += 1;
+                let depth = gate
+                    .inputs()
+                    .filter_map(|w| self.gf2_depths.get(&w))
+                    .fold((0, 0), |(d, m), (wd, wm)| (d.max(*wd), m.max(*wm)));
+                self.z64_depths.insert(*dst, (depth.0 + 1, depth.1));
+            }
+            CombineOperation::SizeHint(z64, gf2) => summary.size_hints.push((*z64, *gf2)),
+        }
+    }
+
+    fn finish_analysis(self) -> Summary {
+        let mut summary = self.summary;
+        summary.content_hash = format!("{:016x}", self.hasher.finish());
+        summary.depth = self
+            .gf2_depths
+            .values()
+            .chain(self.z64_depths.values())
+            .map(|(d, _)| *d)
+            .max()
+            .unwrap_or(0);
+        summary
+    }
+}
+
+impl Summary {
+    pub fn of(program: &[CombineOperation]) -> Self {
+        SummaryPass::analyze(program.iter())
+    }
+
+    pub fn write_json(&self, sink: &mut impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(&mut *sink, self).map_err(Error::other)?;
+        writeln!(sink)
+    }
+
+    pub fn write_text(&self, sink: &mut impl Write) -> Result<()> {
+        writeln!(sink, "gates:       {}", self.gates)?;
+        writeln!(sink, "hash:        {}", self.content_hash)?;
+        writeln!(sink, "depth:       {}", self.depth)?;
+        writeln!(sink, "conversions: {}", self.conversions)?;
+        for (z64, gf2) in &self.size_hints {
+            writeln!(sink, "size hint:   z64 {}, gf2 {}", z64, gf2)?;
+        }
+
+        for (name, domain) in [("GF2", &self.gf2), ("Z64", &self.z64)] {
+            writeln!(
+                sink,
+                "{}: {} gates, {} inputs, {} assertions, multiplicative depth {}",
+                name, domain.gates, domain.inputs, domain.assertions, domain.mul_depth
+            )?;
+            for (kind, count) in &domain.kinds {
+                writeln!(sink, "    {:<12}{}", kind, count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::summary::Summary;
+    use crate::{CombineOperation, Operation};
+
+    fn program() -> Vec<CombineOperation> {
+        vec![
+            CombineOperation::SizeHint(2, 66),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            CombineOperation::GF2(Operation::Mul(3, 2, 1)),
+            CombineOperation::GF2(Operation::AssertZero(3)),
+            CombineOperation::B2A(0, 2),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 1)),
+        ]
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary::of(&program());
+
+        assert_eq!(summary.gates, 8);
+        assert_eq!(summary.gf2.gates, 5);
+        assert_eq!(summary.gf2.inputs, 2);
+        assert_eq!(summary.gf2.assertions, 1);
+        assert_eq!(summary.gf2.kinds["Mul"], 2);
+        assert_eq!(summary.gf2.mul_depth, 2);
+        assert_eq!(summary.z64.gates, 1);
+        // Multiplications on the boolean side count towards the converted value
+        assert_eq!(summary.z64.mul_depth, 2);
+        assert_eq!(summary.conversions, 1);
+        assert_eq!(summary.size_hints, vec![(2, 66)]);
+        // Input -> Mul -> Mul -> B2A -> AddConst
+        assert_eq!(summary.depth, 5);
+    }
+
+    #[test]
+    fn test_summary_formats() {
+        let summary = Summary::of(&program());
+
+        let mut json = Vec::new();
+        summary.write_json(&mut json).unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed["gf2"]["kinds"]["Input"], 2);
+        assert_eq!(parsed["content_hash"], summary.content_hash.as_str());
+
+        let mut text = Vec::new();
+        summary.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("GF2: 5 gates, 2 inputs, 1 assertions, multiplicative depth 2"));
+    }
+}
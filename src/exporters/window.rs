@@ -0,0 +1,263 @@
+//! Exporting a contiguous range of gates on its own, so a backend failure somewhere in the middle
+//! of a large program can be reproduced with a small standalone file.
+//!
+//! The window keeps the original wire numbering. Wires the window reads before writing them
+//! ("live-in" wires) are declared by a preamble of `Input` gates, and the window's witness gives
+//! them the values they had in the original program at the start of the window.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::ops::Range;
+
+use crate::analysis::{AnalysisPass, UnwrittenReads};
+use crate::eval::evaluate_prefix;
+use crate::exporters::{check_program_witness, export_by_name};
+use crate::{CombineOperation, Domain, EvaluationError, Operation, Program};
+
+/// A range of gates from a program, extracted as a standalone program.
+#[derive(Clone, Debug, Default)]
+pub struct Window {
+    /// The preamble followed by the gates in the window. Annotations and parameters follow their
+    /// gates; size hints cover the whole original program.
+    pub program: Program,
+    /// Indices (in the original program) of the gates in the window
+    pub gates: Range<usize>,
+    /// Live-in GF2 wires, in the order the preamble declares them
+    pub gf2_inputs: Vec<usize>,
+    /// Live-in Z64 wires, in the order the preamble declares them
+    pub z64_inputs: Vec<usize>,
+}
+
+impl Window {
+    /// Extracts `program.gates[gates]`, preceded by an `Input` gate for each wire it reads before
+    /// writing. Panics if the range is out of bounds.
+    pub fn new(program: &Program, gates: Range<usize>) -> Self {
+        let body = &program.gates[gates.clone()];
+        let mut window = Window {
+            gates: gates.clone(),
+            ..Default::default()
+        };
+
+        for read in UnwrittenReads::analyze(body.iter()) {
+            let gate = match read.domain {
+                Domain::GF2 => {
+                    window.gf2_inputs.push(read.wire);
+                    CombineOperation::GF2(Operation::Input(read.wire))
+                }
+                Domain::Z64 => {
+                    window.z64_inputs.push(read.wire);
+                    CombineOperation::Z64(Operation::Input(read.wire))
+                }
+            };
+            window.program.gates.push(gate);
+        }
+
+        let preamble = window.program.gates.len();
+        // Size hints come from the whole program, so drop any in the window; they'd be misplaced
+        let mut index = BTreeMap::new();
+        for (idx, gate) in body.iter().enumerate() {
+            if !matches!(gate, CombineOperation::SizeHint(_, _)) {
+                index.insert(gates.start + idx, window.program.gates.len());
+                window.program.gates.push(*gate);
+            }
+        }
+
+        let rekey = |notes: &Option<BTreeMap<usize, String>>| {
+            notes.as_ref().map(|notes| {
+                notes
+                    .iter()
+                    .filter_map(|(idx, note)| index.get(idx).map(|new| (*new, note.clone())))
+                    .collect()
+            })
+        };
+        window.program.annotations = rekey(&program.annotations);
+        window.program.parameters = rekey(&program.parameters);
+        if preamble > 0 {
+            window.program.annotate(
+                0,
+                &format!(
+                    "live-in wires for gates {}..{} of the original program",
+                    gates.start, gates.end
+                ),
+            );
+        }
+
+        window.program.size_hint = program.size_hint();
+        window.program.names = program.names.clone();
+        window.program.buses = program.buses.clone();
+        window.program.fields = program.fields.clone();
+        window.program.provenance = program.provenance.clone();
+        window
+    }
+
+    /// Builds the window's witness from a witness for the whole of `original`: the values of the
+    /// live-in wires at the start of the window, followed by the inputs the window itself reads.
+    /// The gates before the window are evaluated, so it's an error if any assertion among them
+    /// fails, or if the witness runs out before the window.
+    pub fn witness(
+        &self,
+        original: &Program,
+        bool_witness: &[bool],
+        arith_witness: &[u64],
+    ) -> std::result::Result<(Vec<bool>, Vec<u64>), EvaluationError> {
+        let state = evaluate_prefix(
+            &original.gates,
+            self.gates.start,
+            bool_witness,
+            arith_witness,
+        )?;
+        let (mut bool_inputs, mut arith_inputs) = (0, 0);
+        for gate in &original.gates[self.gates.clone()] {
+            match gate {
+                CombineOperation::GF2(Operation::Input(_)) => bool_inputs += 1,
+                CombineOperation::Z64(Operation::Input(_)) => arith_inputs += 1,
+                _ => {}
+            }
+        }
+
+        let bools = self
+            .gf2_inputs
+            .iter()
+            .map(|w| state.bool_wires[w])
+            .chain(
+                bool_witness
+                    .iter()
+                    .copied()
+                    .skip(state.bool_inputs_used)
+                    .take(bool_inputs),
+            )
+            .collect();
+        let ariths = self
+            .z64_inputs
+            .iter()
+            .map(|w| state.arith_wires[w])
+            .chain(
+                arith_witness
+                    .iter()
+                    .copied()
+                    .skip(state.arith_inputs_used)
+                    .take(arith_inputs),
+            )
+            .collect();
+        Ok((bools, ariths))
+    }
+}
+
+/// Exports `program.gates[gates]` as a standalone program using the format registered as
+/// `format`. See `Window`. The witness is for the whole program; if the format writes it out,
+/// it's checked and cut down to the window's witness first, which fails if an assertion before the
+/// window doesn't hold. Formats that don't can be given empty
+/// witnesses.
+pub fn export_window(
+    format: &str,
+    program: &Program,
+    gates: Range<usize>,
+    bool_witness: &[bool],
+    arith_witness: &[u64],
+    sinks: &mut [&mut dyn Write],
+) -> Result<()> {
+    let window = Window::new(program, gates);
+    if bool_witness.is_empty() && arith_witness.is_empty() {
+        return export_by_name(format, &window.program, &[], &[], sinks);
+    }
+    check_program_witness(program, bool_witness, arith_witness)?;
+    let (bools, ariths) = window
+        .witness(program, bool_witness, arith_witness)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    export_by_name(format, &window.program, &bools, &ariths, sinks)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::exporters::window::{export_window, Window};
+    use crate::{
+        evaluate_composite_program, CombineOperation, Domain, EvaluationError, Operation, Program,
+    };
+
+    #[test]
+    fn test_window() {
+        let mut program: Program = vec![
+            CombineOperation::SizeHint(3, 4),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Add(2, 0, 1)),
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::B2A(1, 0),
+            CombineOperation::Z64(Operation::Input(2)),
+            CombineOperation::Z64(Operation::Mul(1, 1, 0)),
+            CombineOperation::Z64(Operation::Sub(2, 1, 2)),
+            CombineOperation::Z64(Operation::AssertZero(2)),
+            CombineOperation::GF2(Operation::AssertZero(3)),
+        ]
+        .into();
+        program.annotate(7, "square");
+        // 0b101 = 5, squared is 25
+        let (bools, ariths) = ([true, false], [5, 25]);
+        evaluate_composite_program(&program.gates, &bools, &ariths);
+
+        let window = Window::new(&program, 6..10);
+        assert!(window.gf2_inputs.is_empty());
+        assert_eq!(window.z64_inputs, [1, 0]);
+        assert_eq!(window.program.gates.len(), 6);
+        assert_eq!(window.program.gates[2], program.gates[6]);
+        assert_eq!(window.program.size_hint, Some((3, 4)));
+        let annotations = window.program.annotations.as_ref().unwrap();
+        assert_eq!(annotations[&3], "square");
+        assert!(annotations[&0].contains("gates 6..10"));
+
+        // Wire 1 holds the B2A of 0b101, and the window reads the second Z64 input itself
+        let (window_bools, window_ariths) = window.witness(&program, &bools, &ariths).unwrap();
+        assert!(window_bools.is_empty());
+        assert_eq!(window_ariths, [5, 5, 25]);
+        evaluate_composite_program(&window.program.gates, &window_bools, &window_ariths);
+
+        // A witness that fails before the window is reported, not a panic
+        let failed = Window::new(&program, 10..11).witness(&program, &[true, true], &ariths);
+        assert_eq!(
+            failed,
+            Err(EvaluationError::AssertionFailed {
+                gate: 9,
+                domain: Domain::Z64,
+                wire: 2,
+                value: 15u64.wrapping_sub(25),
+            })
+        );
+        let short = window.witness(&program, &bools, &[]);
+        assert!(matches!(
+            short,
+            Err(EvaluationError::OutOfInputs { gate: 4, .. })
+        ));
+
+        // The boolean half on its own, for a format that writes the witness
+        let mut circuit = Vec::new();
+        let mut witness = Vec::new();
+        export_window(
+            "ir0",
+            &program,
+            3..4,
+            &bools,
+            &ariths,
+            &mut [&mut circuit as &mut dyn Write, &mut witness],
+        )
+        .unwrap();
+        let circuit = String::from_utf8(circuit).unwrap();
+        assert!(circuit.contains("// live-in wires for gates 3..4"));
+        assert_eq!(circuit.matches("@private()").count(), 2);
+        assert!(export_window(
+            "ir0",
+            &program,
+            3..4,
+            &[true],
+            &ariths,
+            &mut [&mut Vec::new() as &mut dyn Write, &mut Vec::new()]
+        )
+        .is_err());
+
+        // Formats that don't write the witness don't need one
+        let mut dot = Vec::new();
+        export_window("dot", &program, 3..4, &[], &[], &mut [&mut dot]).unwrap();
+        assert!(!dot.is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Domain, HasConst, Operation, WireValue};
+
+/// The field a circuit's values live in, as declared to exporters. Composite programs only ever
+/// compute over GF2 or Z64, so for them this is metadata about how a consumer should interpret the
+/// circuit rather than something that changes how we evaluate it. Prime field values carry theirs
+/// as `Fp::FIELD`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Field {
+    pub characteristic: u64,
+    pub degree: u32,
+}
+
+impl Field {
+    /// The boolean field, which is the only field that matches GF2 gates.
+    pub const GF2: Field = Field {
+        characteristic: 2,
+        degree: 1,
+    };
+
+    /// A prime field of the given characteristic. Doesn't check that it's actually prime.
+    pub fn prime(characteristic: u64) -> Self {
+        Field {
+            characteristic,
+            degree: 1,
+        }
+    }
+
+    /// Checks that gates from `domain` can be faithfully written in this field. GF2 gates need
+    /// exactly GF(2). Z64 has no field that matches it, so we accept any prime field other than
+    /// GF(2), on the understanding that the consumer won't wrap at 2^64; `check_constants` makes
+    /// sure the constants at least mean the same thing.
+    pub fn check_domain(&self, domain: Domain) -> Result<()> {
+        let ok = match domain {
+            Domain::GF2 => *self == Field::GF2,
+            Domain::Z64 => self.degree == 1 && self.characteristic > 2,
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} gates can't be exported in {}", domain, self),
+            ))
+        }
+    }
+
+    /// Checks that the field matches `T`'s domain, and that every constant in `gates` is a
+    /// canonical element of the field.
+    pub fn check_constants<T: WireValue>(&self, gates: &[Operation<T>]) -> Result<()> {
+        self.check_domain(T::DOMAIN)?;
+
+        for gate in gates {
+            for c in gate.constants() {
+                let value = u64::from_le_bytes(c.to_le_bytes());
+                if self.degree == 1 && value >= self.characteristic {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "constant {} in {:?} is out of range for {}",
+                            value, gate, self
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.degree == 1 {
+            write!(f, "GF({})", self.characteristic)
+        } else {
+            write!(f, "GF({}^{})", self.characteristic, self.degree)
+        }
+    }
+}
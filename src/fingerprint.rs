@@ -0,0 +1,164 @@
+//! Quick fingerprints of huge programs, computed from a seeded random sample of their gates.
+//! Two copies of the same program always sample the same gates and so get the same fingerprint,
+//! which makes for a cheap "is this the circuit I think it is" check. Combined with the gate
+//! index that `write_program` adds to large files, `ProgramReader::fingerprint` only decodes the
+//! blocks of gates the sample lands in, rather than the whole file.
+
+use std::collections::BTreeMap;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{CombineOperation, Domain, HasConst, HasIO, Operation, WireValue};
+
+/// Statistics over a sample of a program's gates.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Number of gates in the whole program
+    pub total_gates: usize,
+    /// Number of gates in the sample
+    pub sampled: usize,
+    /// Gate counts, keyed by domain and `Operation::kind` (`GF2 Mul`, `Z64 Input`, ...), or
+    /// `B2A` and `SizeHint`
+    pub kinds: BTreeMap<String, usize>,
+    /// Counts of constants by how many bits they need, so GF2 constants are 0 or 1 bits
+    pub constant_bits: BTreeMap<u32, usize>,
+    /// Smallest and largest wire each domain's sampled gates touch
+    pub wire_ranges: BTreeMap<Domain, (usize, usize)>,
+}
+
+/// Which gates to sample, in increasing order. Only depends on the arguments: the generator is
+/// ChaCha20, whose output is fixed for a given seed, so fingerprints stay comparable across
+/// releases of `rand`.
+pub(crate) fn sample_indices(total: usize, count: usize, seed: u64) -> Vec<usize> {
+    let mut rng = ChaCha20Rng::seed_from_u64(seed);
+    let mut indices = rand::seq::index::sample(&mut rng, total, count.min(total)).into_vec();
+    indices.sort_unstable();
+    indices
+}
+
+/// Picks `count` distinct gates at random (or every gate, if there are fewer), returning them
+/// with their indices in program order.
+pub fn sample_gates(
+    gates: &[CombineOperation],
+    count: usize,
+    seed: u64,
+) -> Vec<(usize, CombineOperation)> {
+    sample_indices(gates.len(), count, seed)
+        .into_iter()
+        .map(|idx| (idx, gates[idx]))
+        .collect()
+}
+
+impl Fingerprint {
+    /// Fingerprints an in-memory program from a sample of `count` of its gates.
+    pub fn of(gates: &[CombineOperation], count: usize, seed: u64) -> Self {
+        Self::of_sample(gates.len(), &sample_gates(gates, count, seed))
+    }
+
+    /// Fingerprints a program with `total_gates` gates from a sample of them.
+    pub fn of_sample(total_gates: usize, sample: &[(usize, CombineOperation)]) -> Self {
+        let mut fingerprint = Fingerprint {
+            total_gates,
+            sampled: sample.len(),
+            ..Default::default()
+        };
+
+        for (_, gate) in sample {
+            match gate {
+                CombineOperation::GF2(op) => fingerprint.count(op),
+                CombineOperation::Z64(op) => fingerprint.count(op),
+                CombineOperation::B2A(dst, low) => {
+                    *fingerprint.kinds.entry("B2A".into()).or_default() += 1;
+                    fingerprint.touch(Domain::Z64, *dst);
+                    fingerprint.touch(Domain::GF2, *low);
+                    fingerprint.touch(Domain::GF2, low.saturating_add(63));
+                }
+                CombineOperation::SizeHint(_, _) => {
+                    *fingerprint.kinds.entry("SizeHint".into()).or_default() += 1;
+                }
+            }
+        }
+        fingerprint
+    }
+
+    fn count<T: WireValue + Into<u64>>(&mut self, op: &Operation<T>) {
+        *self
+            .kinds
+            .entry(format!("{:?} {}", T::DOMAIN, op.kind()))
+            .or_default() += 1;
+        for constant in op.constants() {
+            let bits = u64::BITS - constant.into().leading_zeros();
+            *self.constant_bits.entry(bits).or_default() += 1;
+        }
+        for wire in op.inputs().chain(op.outputs()) {
+            self.touch(T::DOMAIN, wire);
+        }
+    }
+
+    fn touch(&mut self, domain: Domain, wire: usize) {
+        let range = self.wire_ranges.entry(domain).or_insert((wire, wire));
+        range.0 = range.0.min(wire);
+        range.1 = range.1.max(wire);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fingerprint::{sample_gates, sample_indices, Fingerprint};
+    use crate::{CombineOperation, Domain, Operation};
+
+    #[test]
+    fn test_fingerprint() {
+        let gates: Vec<CombineOperation> = (0..1000)
+            .map(|i| match i % 4 {
+                0 => CombineOperation::GF2(Operation::Const(i, true)),
+                1 => CombineOperation::Z64(Operation::MulConst(i, i - 1, 300)),
+                2 => CombineOperation::B2A(i, 0),
+                _ => CombineOperation::Z64(Operation::Add(i, i - 1, i - 2)),
+            })
+            .collect();
+
+        let sample = sample_gates(&gates, 40, 7);
+        assert_eq!(sample.len(), 40);
+        assert!(sample.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(sample, sample_gates(&gates, 40, 7));
+        assert_ne!(sample, sample_gates(&gates, 40, 8));
+
+        let fingerprint = Fingerprint::of(&gates, 40, 7);
+        assert_eq!(fingerprint.sampled, 40);
+        assert_eq!(fingerprint.kinds.values().sum::<usize>(), 40);
+        assert!(fingerprint
+            .constant_bits
+            .keys()
+            .all(|bits| *bits == 1 || *bits == 9));
+        let (low, high) = fingerprint.wire_ranges[&Domain::GF2];
+        assert_eq!(low, 0);
+        assert!(high < 1000);
+
+        // Asking for more gates than there are samples all of them
+        let everything = Fingerprint::of(&gates, 5000, 7);
+        assert_eq!(everything.sampled, 1000);
+        assert_eq!(everything.kinds["GF2 Const"], 250);
+        assert_eq!(everything.constant_bits[&9], 250);
+    }
+
+    #[test]
+    fn test_sample_known_answer() {
+        // Fingerprints are compared across releases, so the sample for a seed mustn't change
+        assert_eq!(
+            sample_indices(1_000_000, 8, 7),
+            [23335, 153475, 283810, 534605, 550698, 718628, 836049, 951384]
+        );
+    }
+
+    #[test]
+    fn test_b2a_at_the_end_of_the_wires() {
+        let fingerprint = Fingerprint::of_sample(1, &[(0, CombineOperation::B2A(0, usize::MAX))]);
+        assert_eq!(
+            fingerprint.wire_ranges[&Domain::GF2],
+            (usize::MAX, usize::MAX)
+        );
+    }
+}
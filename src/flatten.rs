@@ -0,0 +1,919 @@
+//! Inlines the subcircuits of a parsed BLIF design, giving one flat list of gates.
+//!
+//! `BlifParser` reads each `.model` into its own `BlifCircuitDesc`, with `.subckt` lines recorded
+//! as connections rather than gates. `flatten` starts from the top-level model and replaces every
+//! subcircuit with a copy of its model's gates: ports are moved onto the parent's wires they're
+//! connected to, and every other wire of the copy gets a fresh wire past the largest one the
+//! parser handed out, so instances of the same model never collide. The top-level model keeps its
+//! own wires, so its `inputs` and `outputs` still describe the flat circuit.
+//!
+//! Inlining everything can blow up the size of a design whose small models are instantiated all
+//! over, while backends that can't call subcircuits need it all inlined. `inline` sits in
+//! between: it inlines the models an `InlinePolicy` picks and keeps the calls to the rest.
+//!
+//! Errors name the instance they happened in by its path from the top level, like
+//! `top/cpu0/alu3`, along with the file and line of the `.subckt` that created it when the
+//! parser recorded one.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::analysis::ready_order;
+use crate::parsers::blif::{BlifCircuitDesc, BlifSubcircuitDesc, SourceLocation, WidthMismatch};
+use crate::{HasIO, Operation, Translatable, TranslationError, ValidationError, WireValue};
+
+/// The wires `$false` and `$true` are hashed to. Every model drives them with the same constants,
+/// so instances share the top-level model's copies rather than driving them again.
+const CONSTANT_WIRES: [usize; 2] = [0, 1];
+
+/// One instance on the way from the top-level model down to a subcircuit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instance {
+    /// The instance's `.cname`, or its model's name and position among its parent's subcircuits
+    /// (like `alu#3`) if it doesn't have one. The top level is named after its model.
+    pub name: String,
+    pub model: String,
+    /// Where the `.subckt` line (or, for the top level, the `.model` line) is
+    pub location: Option<SourceLocation>,
+}
+
+/// The instances from the top-level model down to one of its subcircuits. Displays as their
+/// names joined with slashes, followed by the location of the last one if it has one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstancePath(pub Vec<Instance>);
+
+impl InstancePath {
+    fn top<T: WireValue>(model: &BlifCircuitDesc<T>) -> Self {
+        InstancePath(vec![Instance {
+            name: model.name.to_string(),
+            model: model.name.to_string(),
+            location: model.location.clone(),
+        }])
+    }
+
+    /// The path to the `idx`th subcircuit of the last instance.
+    fn child(&self, sub: &BlifSubcircuitDesc, idx: usize) -> Self {
+        let name = match &sub.instance {
+            Some(name) => name.to_string(),
+            None => format!("{}#{}", sub.name, idx),
+        };
+        let mut path = self.clone();
+        path.0.push(Instance {
+            name,
+            model: sub.name.to_string(),
+            location: sub.location.clone(),
+        });
+        path
+    }
+
+    /// The model of the last instance.
+    pub fn model(&self) -> &str {
+        self.0.last().map_or("", |instance| &instance.model)
+    }
+
+    /// Where the last instance was created, if that's known.
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.0
+            .last()
+            .and_then(|instance| instance.location.as_ref())
+    }
+
+    /// Whether an instance before the last is of the same model, so the last one is recursive.
+    fn loops(&self) -> bool {
+        match self.0.split_last() {
+            Some((last, outer)) => outer.iter().any(|instance| instance.model == last.model),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for InstancePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self
+            .0
+            .iter()
+            .map(|instance| instance.name.as_str())
+            .collect();
+        write!(f, "{}", names.join("/"))?;
+        if let Some(location) = self.location() {
+            write!(f, " ({})", location)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a design couldn't be flattened. Each error carries the path to the instance it happened
+/// in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FlattenError {
+    /// A model was instantiated, or asked for as the top level, but never defined. `path` ends
+    /// with the instance of the missing model.
+    MissingModel { name: String, path: InstancePath },
+    /// The model instantiates itself, directly or through its subcircuits. `path` ends with the
+    /// instance that closes the loop.
+    Recursive { name: String, path: InstancePath },
+    /// The top-level model's inputs or outputs aren't contiguous blocks of wires
+    Io {
+        path: InstancePath,
+        errors: Vec<ValidationError>,
+    },
+    /// A gate of the model couldn't be moved onto the instance's wires
+    Translation {
+        path: InstancePath,
+        error: TranslationError,
+    },
+    /// The instance connects a wire that isn't one of its model's inputs or outputs, usually
+    /// because the model changed without the `.subckt` lines using it being updated
+    Port { path: InstancePath, wire: usize },
+    /// The instance's `.subckt` line connects wires of different widths
+    Width {
+        path: InstancePath,
+        mismatch: WidthMismatch,
+    },
+    /// The gates writing these wires depend on each other, so there's no order to evaluate them
+    /// in. `instances` are where those gates came from.
+    Cycle {
+        wires: Vec<usize>,
+        instances: Vec<InstancePath>,
+    },
+}
+
+impl FlattenError {
+    /// The instance the error happened in. A cycle can run through several, and reports the
+    /// first.
+    pub fn path(&self) -> Option<&InstancePath> {
+        match self {
+            FlattenError::MissingModel { path, .. }
+            | FlattenError::Recursive { path, .. }
+            | FlattenError::Io { path, .. }
+            | FlattenError::Translation { path, .. }
+            | FlattenError::Port { path, .. }
+            | FlattenError::Width { path, .. } => Some(path),
+            FlattenError::Cycle { instances, .. } => instances.first(),
+        }
+    }
+}
+
+impl fmt::Display for FlattenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlattenError::MissingModel { name, path } if path.0.len() <= 1 => {
+                write!(f, "top-level model {} is not defined", name)
+            }
+            FlattenError::MissingModel { name, path } => {
+                write!(f, "{}: model {} is not defined", path, name)
+            }
+            FlattenError::Recursive { name, path } => {
+                write!(f, "{}: model {} instantiates itself", path, name)
+            }
+            FlattenError::Io { path, errors } => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}: {}", path, messages.join("\n"))
+            }
+            FlattenError::Translation { path, error } => write!(
+                f,
+                "{}: can't instantiate model {}: {}",
+                path,
+                path.model(),
+                error
+            ),
+            FlattenError::Port { path, wire } => write!(
+                f,
+                "{}: wire {} is connected, but isn't a port of model {}",
+                path,
+                wire,
+                path.model()
+            ),
+            FlattenError::Width { path, mismatch } => write!(f, "{}: {}", path, mismatch),
+            FlattenError::Cycle { wires, instances } => {
+                let instances: Vec<String> = instances.iter().map(|p| p.to_string()).collect();
+                write!(
+                    f,
+                    "wires {:?} depend on each other, in {}",
+                    wires,
+                    instances.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlattenError {}
+
+struct Flattener<'a, T: WireValue> {
+    models: &'a HashMap<&'a str, &'a BlifCircuitDesc<T>>,
+    /// Models whose calls are kept rather than inlined
+    kept: &'a HashSet<&'a str>,
+    next_wire: &'a mut usize,
+    gates: Vec<Operation<T>>,
+    /// `undef_inputs` of every instance inlined so far, in the new wires
+    undef_inputs: Vec<usize>,
+    subcircuits: Vec<BlifSubcircuitDesc>,
+    /// Every instance inlined so far
+    paths: Vec<InstancePath>,
+    /// Index into `paths` of the instance each gate came from
+    origins: Vec<usize>,
+}
+
+/// Every wire a model's gates and subcircuit connections mention.
+fn wires<T: WireValue>(model: &BlifCircuitDesc<T>) -> impl Iterator<Item = usize> + '_ {
+    model
+        .gates
+        .iter()
+        .flat_map(|gate| gate.inputs().chain(gate.outputs()))
+        .chain(model.inputs.iter().copied())
+        .chain(model.outputs.iter().copied())
+        .chain(
+            model
+                .subcircuits
+                .iter()
+                .flat_map(|sub| sub.connections.iter().map(|(parent, _)| *parent)),
+        )
+}
+
+impl<'a, T: WireValue> Flattener<'a, T> {
+    /// Appends the gates of the instance at the end of `path`, with its model's wires moved
+    /// according to `mapping`. Wires that aren't in `mapping` are internal to this instance and
+    /// get fresh wires.
+    fn inline(
+        &mut self,
+        model: &'a BlifCircuitDesc<T>,
+        mut mapping: HashMap<usize, usize>,
+        path: InstancePath,
+    ) -> Result<(), FlattenError> {
+        if path.loops() {
+            return Err(FlattenError::Recursive {
+                name: model.name.to_string(),
+                path,
+            });
+        }
+        let top = path.0.len() == 1;
+
+        for wire in wires(model) {
+            let next_wire = &mut *self.next_wire;
+            mapping.entry(wire).or_insert_with(|| {
+                *next_wire += 1;
+                *next_wire - 1
+            });
+        }
+
+        let origin = self.paths.len();
+        for gate in &model.gates {
+            if !top && matches!(gate, Operation::Const(dst, _) if CONSTANT_WIRES.contains(dst)) {
+                continue;
+            }
+            let translated =
+                gate.translate_strict(&mapping)
+                    .map_err(|error| FlattenError::Translation {
+                        path: path.clone(),
+                        error,
+                    })?;
+            self.gates.push(translated);
+            self.origins.push(origin);
+        }
+        self.undef_inputs
+            .extend(model.undef_inputs.iter().map(|wire| mapping[wire]));
+        self.paths.push(path);
+
+        for (idx, sub) in model.subcircuits.iter().enumerate() {
+            let child_path = self.paths[origin].child(sub, idx);
+            let child = child_model(self.models, sub, &child_path)?;
+            if self.kept.contains(&*sub.name) {
+                self.subcircuits.push(BlifSubcircuitDesc {
+                    connections: sub
+                        .connections
+                        .iter()
+                        .map(|(parent, child)| (mapping[parent], *child))
+                        .collect(),
+                    ..sub.clone()
+                });
+                continue;
+            }
+            let mut ports: HashMap<usize, usize> =
+                CONSTANT_WIRES.iter().map(|wire| (*wire, *wire)).collect();
+            ports.extend(
+                sub.connections
+                    .iter()
+                    .map(|(parent, child)| (*child, mapping[parent])),
+            );
+            self.inline(child, ports, child_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// The model a subcircuit instantiates, checking that it's defined and has a port for every
+/// wire the subcircuit connects, and that the connections were all the right width.
+fn child_model<'a, T: WireValue>(
+    models: &HashMap<&str, &'a BlifCircuitDesc<T>>,
+    sub: &BlifSubcircuitDesc,
+    path: &InstancePath,
+) -> Result<&'a BlifCircuitDesc<T>, FlattenError> {
+    let child = *models
+        .get(&*sub.name)
+        .ok_or_else(|| FlattenError::MissingModel {
+            name: sub.name.to_string(),
+            path: path.clone(),
+        })?;
+    if let Some(mismatch) = &sub.mismatch {
+        return Err(FlattenError::Width {
+            path: path.clone(),
+            mismatch: mismatch.clone(),
+        });
+    }
+    let ports: HashSet<usize> = child.inputs.iter().chain(&child.outputs).copied().collect();
+    if let Some((_, wire)) = sub
+        .connections
+        .iter()
+        .find(|(_, wire)| !ports.contains(wire))
+    {
+        return Err(FlattenError::Port {
+            path: path.clone(),
+            wire: *wire,
+        });
+    }
+    Ok(child)
+}
+
+/// A model with the calls to some of its subcircuits inlined, and the instance each of its gates
+/// came from.
+struct Expanded<T: WireValue> {
+    model: BlifCircuitDesc<T>,
+    paths: Vec<InstancePath>,
+    /// Index into `paths` for each gate
+    origins: Vec<usize>,
+}
+
+/// Puts the gates in an order where each is evaluated after the gates writing its inputs, keeping
+/// the original order wherever it doesn't matter. BLIF doesn't order gates, so a parent's gates
+/// can read wires a subcircuit inlined after them writes.
+fn order<T: WireValue>(expanded: Expanded<T>) -> Result<Vec<Operation<T>>, FlattenError> {
+    let Expanded {
+        model,
+        paths,
+        origins,
+    } = expanded;
+    let gates = model.gates;
+    let mut writers: HashMap<usize, usize> = HashMap::new();
+    for (idx, gate) in gates.iter().enumerate() {
+        for wire in gate.outputs() {
+            writers.entry(wire).or_insert(idx);
+        }
+    }
+
+    let mut pending = vec![0; gates.len()];
+    let mut readers: Vec<Vec<usize>> = vec![Vec::new(); gates.len()];
+    for (idx, gate) in gates.iter().enumerate() {
+        for wire in gate.inputs() {
+            if let Some(writer) = writers.get(&wire).filter(|writer| **writer != idx) {
+                pending[idx] += 1;
+                readers[*writer].push(idx);
+            }
+        }
+    }
+
+    let stuck = match ready_order(pending, &readers) {
+        Ok(sorted) => return Ok(sorted.into_iter().map(|idx| gates[idx]).collect()),
+        Err(stuck) => stuck,
+    };
+    let mut wires: Vec<usize> = stuck.iter().flat_map(|idx| gates[*idx].outputs()).collect();
+    wires.sort_unstable();
+    let mut stuck_in: Vec<usize> = stuck.iter().map(|idx| origins[*idx]).collect();
+    stuck_in.sort_unstable();
+    stuck_in.dedup();
+    let instances = stuck_in
+        .into_iter()
+        .map(|origin| paths[origin].clone())
+        .collect();
+    Err(FlattenError::Cycle { wires, instances })
+}
+
+/// The models of a design by name, and the top-level one, whose I/O has to be contiguous.
+type Design<'a, T> = (
+    HashMap<&'a str, &'a BlifCircuitDesc<T>>,
+    &'a BlifCircuitDesc<T>,
+);
+
+fn design<'a, T: WireValue>(
+    circuits: &'a [BlifCircuitDesc<T>],
+    top: &str,
+) -> Result<Design<'a, T>, FlattenError> {
+    let models: HashMap<&str, &BlifCircuitDesc<T>> =
+        circuits.iter().map(|model| (&*model.name, model)).collect();
+    let top = *models.get(top).ok_or_else(|| FlattenError::MissingModel {
+        name: top.to_string(),
+        path: InstancePath(vec![Instance {
+            name: top.to_string(),
+            model: top.to_string(),
+            location: None,
+        }]),
+    })?;
+    let errors = top.io_errors();
+    if !errors.is_empty() {
+        return Err(FlattenError::Io {
+            path: InstancePath::top(top),
+            errors,
+        });
+    }
+    Ok((models, top))
+}
+
+/// The first wire past every one the parser handed out.
+fn first_fresh_wire<T: WireValue>(circuits: &[BlifCircuitDesc<T>]) -> usize {
+    circuits
+        .iter()
+        .flat_map(wires)
+        .max()
+        .map_or(0, |max| max + 1)
+}
+
+/// Copies `model` with the calls to every model not in `kept` inlined.
+fn expand<'a, T: WireValue>(
+    models: &'a HashMap<&'a str, &'a BlifCircuitDesc<T>>,
+    kept: &'a HashSet<&'a str>,
+    next_wire: &'a mut usize,
+    model: &'a BlifCircuitDesc<T>,
+) -> Result<Expanded<T>, FlattenError> {
+    let mut flattener = Flattener {
+        models,
+        kept,
+        next_wire,
+        gates: Vec::new(),
+        undef_inputs: Vec::new(),
+        subcircuits: Vec::new(),
+        paths: Vec::new(),
+        origins: Vec::new(),
+    };
+    let identity = wires(model).map(|wire| (wire, wire)).collect();
+    flattener.inline(model, identity, InstancePath::top(model))?;
+    Ok(Expanded {
+        model: BlifCircuitDesc {
+            name: model.name.clone(),
+            inputs: model.inputs.clone(),
+            outputs: model.outputs.clone(),
+            undef_inputs: flattener.undef_inputs,
+            gates: flattener.gates,
+            subcircuits: flattener.subcircuits,
+            location: model.location.clone(),
+        },
+        paths: flattener.paths,
+        origins: flattener.origins,
+    })
+}
+
+/// Inlines every subcircuit of the model named `top`, recursively, and returns the gates of the
+/// whole design in an order they can be evaluated in.
+pub fn flatten<T: WireValue>(
+    circuits: &[BlifCircuitDesc<T>],
+    top: &str,
+) -> Result<Vec<Operation<T>>, FlattenError> {
+    let (models, top) = design(circuits, top)?;
+    let mut next_wire = first_fresh_wire(circuits);
+    let kept = HashSet::new();
+    order(expand(&models, &kept, &mut next_wire, top)?)
+}
+
+/// Which subcircuits `inline` copies into their callers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InlinePolicy {
+    /// Inline everything, leaving only the top-level model
+    Full,
+    /// Inline a model if its body is at most `max_body` gates, counting the models it inlines in
+    /// turn, or if it's called from at most `max_calls` places. Inlining a model called from one
+    /// place never makes the design bigger.
+    Heuristic { max_body: usize, max_calls: usize },
+}
+
+impl Default for InlinePolicy {
+    fn default() -> Self {
+        InlinePolicy::Heuristic {
+            max_body: 64,
+            max_calls: 1,
+        }
+    }
+}
+
+/// What `inline` decided for one model, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InlineDecision {
+    pub model: String,
+    /// Gates a copy of the model adds to its caller
+    pub body_gates: usize,
+    /// Number of `.subckt` lines calling the model
+    pub calls: usize,
+    pub inlined: bool,
+}
+
+/// How inlining changed a design. Sizes count the gates of every model reachable from the top,
+/// once each; depths are the longest chain of calls below the top (zero without any calls).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InlineReport {
+    /// One decision per model below the top, callees before their callers
+    pub decisions: Vec<InlineDecision>,
+    pub gates_before: usize,
+    pub gates_after: usize,
+    pub depth_before: usize,
+    pub depth_after: usize,
+}
+
+/// Lists the models reachable from the instance at the end of `path`, callees before callers,
+/// checking that every one is defined and none calls itself.
+fn post_order<'a, T: WireValue>(
+    models: &HashMap<&str, &'a BlifCircuitDesc<T>>,
+    model: &'a BlifCircuitDesc<T>,
+    path: &InstancePath,
+    order: &mut Vec<&'a BlifCircuitDesc<T>>,
+) -> Result<(), FlattenError> {
+    if path.loops() {
+        return Err(FlattenError::Recursive {
+            name: model.name.to_string(),
+            path: path.clone(),
+        });
+    }
+    if order.iter().any(|seen| seen.name == model.name) {
+        return Ok(());
+    }
+    for (idx, sub) in model.subcircuits.iter().enumerate() {
+        let child_path = path.child(sub, idx);
+        let child = child_model(models, sub, &child_path)?;
+        post_order(models, child, &child_path, order)?;
+    }
+    order.push(model);
+    Ok(())
+}
+
+/// The longest chain of calls below each model, given models in post order.
+fn call_depths<'a, T: WireValue>(order: &[&'a BlifCircuitDesc<T>]) -> HashMap<&'a str, usize> {
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    for model in order {
+        let depth = model
+            .subcircuits
+            .iter()
+            .map(|sub| depths.get(&*sub.name).map_or(1, |depth| depth + 1))
+            .max()
+            .unwrap_or(0);
+        depths.insert(&model.name, depth);
+    }
+    depths
+}
+
+/// Inlines the subcircuits of the model named `top` that `policy` picks, and returns the models
+/// left, top-level first, with a report of what was inlined. Kept models have the calls they
+/// make inlined by the same rules, so a call is either inlined everywhere or nowhere.
+pub fn inline<T: WireValue>(
+    circuits: &[BlifCircuitDesc<T>],
+    top: &str,
+    policy: InlinePolicy,
+) -> Result<(Vec<BlifCircuitDesc<T>>, InlineReport), FlattenError> {
+    let (models, top) = design(circuits, top)?;
+    let mut reachable = Vec::new();
+    post_order(&models, top, &InstancePath::top(top), &mut reachable)?;
+
+    let mut calls: HashMap<&str, usize> = HashMap::new();
+    for model in &reachable {
+        for sub in &model.subcircuits {
+            *calls.entry(&sub.name).or_default() += 1;
+        }
+    }
+
+    let own_gates = |model: &BlifCircuitDesc<T>| {
+        model
+            .gates
+            .iter()
+            .filter(
+                |gate| !matches!(gate, Operation::Const(dst, _) if CONSTANT_WIRES.contains(dst)),
+            )
+            .count()
+    };
+    let mut body_gates: HashMap<&str, usize> = HashMap::new();
+    let mut kept: HashSet<&str> = HashSet::new();
+    let mut report = InlineReport::default();
+    for model in &reachable {
+        let body = own_gates(model)
+            + model
+                .subcircuits
+                .iter()
+                .filter(|sub| !kept.contains(&*sub.name))
+                .map(|sub| body_gates[&*sub.name])
+                .sum::<usize>();
+        body_gates.insert(&model.name, body);
+        if model.name == top.name {
+            continue;
+        }
+        let calls = calls[&*model.name];
+        let inlined = match policy {
+            InlinePolicy::Full => true,
+            InlinePolicy::Heuristic {
+                max_body,
+                max_calls,
+            } => body <= max_body || calls <= max_calls,
+        };
+        if !inlined {
+            kept.insert(&model.name);
+        }
+        report.decisions.push(InlineDecision {
+            model: model.name.to_string(),
+            body_gates: body,
+            calls,
+            inlined,
+        });
+    }
+
+    let mut next_wire = first_fresh_wire(circuits);
+    let mut result = Vec::new();
+    for model in reachable.iter().rev() {
+        if model.name == top.name || kept.contains(&*model.name) {
+            result.push(expand(&models, &kept, &mut next_wire, model)?.model);
+        }
+    }
+
+    let sizes = |models: &[&BlifCircuitDesc<T>]| -> usize {
+        models.iter().map(|model| model.gates.len()).sum()
+    };
+    report.gates_before = sizes(&reachable);
+    report.depth_before = call_depths(&reachable)[&*top.name];
+    let remaining: Vec<&BlifCircuitDesc<T>> = result.iter().rev().collect();
+    report.gates_after = sizes(&remaining);
+    report.depth_after = call_depths(&remaining)[&*top.name];
+    Ok((result, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flatten::{flatten, inline, FlattenError, InlinePolicy};
+    use crate::parsers::blif::{BlifCircuitDesc, BlifSubcircuitDesc, SourceLocation};
+    use crate::{evaluate_composite_program, CombineOperation, HasIO, Operation};
+
+    fn model(
+        name: &str,
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+        gates: Vec<Operation<bool>>,
+        subcircuits: Vec<(&str, Vec<(usize, usize)>)>,
+    ) -> BlifCircuitDesc<bool> {
+        let constants = vec![Operation::Const(0, false), Operation::Const(1, true)];
+        BlifCircuitDesc {
+            name: name.into(),
+            inputs,
+            outputs,
+            undef_inputs: vec![],
+            gates: [constants, gates].concat(),
+            subcircuits: subcircuits
+                .into_iter()
+                .map(|(name, connections)| BlifSubcircuitDesc {
+                    name: name.into(),
+                    connections,
+                    ..Default::default()
+                })
+                .collect(),
+            location: None,
+        }
+    }
+
+    /// top(a, b, c) = (nxor(a, b), nxor(b, c) & a), built from smaller models.
+    fn design() -> Vec<BlifCircuitDesc<bool>> {
+        // xor(a, b) = (a + b) through an internal wire, and not(a) = a + $true
+        let xor = model(
+            "xor",
+            vec![10, 11],
+            vec![13],
+            vec![
+                Operation::Add(12, 10, 11),
+                Operation::AddConst(13, 12, false),
+            ],
+            vec![],
+        );
+        let not = model(
+            "not",
+            vec![20],
+            vec![21],
+            vec![Operation::Add(21, 20, 1)],
+            vec![],
+        );
+        // nxor(a, b) = not(xor(a, b)), with the two instances listed in reverse
+        let nxor = model(
+            "nxor",
+            vec![30, 31],
+            vec![33],
+            vec![],
+            vec![
+                ("not", vec![(32, 20), (33, 21)]),
+                ("xor", vec![(30, 10), (31, 11), (32, 13)]),
+            ],
+        );
+        // top(a, b, c) = (nxor(a, b), nxor(b, c) * a), with the multiplication before the
+        // subcircuit writing its input
+        let top = model(
+            "top",
+            vec![2, 3, 4],
+            vec![5, 6],
+            vec![
+                Operation::Input(2),
+                Operation::Input(3),
+                Operation::Input(4),
+                Operation::Mul(6, 7, 2),
+            ],
+            vec![
+                ("nxor", vec![(2, 30), (3, 31), (5, 33)]),
+                ("nxor", vec![(3, 30), (4, 31), (7, 33)]),
+            ],
+        );
+        vec![top, nxor, xor, not]
+    }
+
+    /// Checks that the flattened design computes what it should.
+    fn check(gates: Vec<Operation<bool>>) {
+        let program: Vec<CombineOperation> =
+            gates.into_iter().map(CombineOperation::from).collect();
+        for (a, b, c) in [
+            (false, false, true),
+            (true, false, false),
+            (true, true, true),
+        ] {
+            let mut extended = program.clone();
+            extended.push(CombineOperation::GF2(Operation::AddConst(100, 5, a == b)));
+            extended.push(CombineOperation::GF2(Operation::AssertZero(100)));
+            extended.push(CombineOperation::GF2(Operation::AddConst(
+                101,
+                6,
+                a && b == c,
+            )));
+            extended.push(CombineOperation::GF2(Operation::AssertZero(101)));
+            evaluate_composite_program(&extended, &[a, b, c], &[]);
+        }
+    }
+
+    #[test]
+    fn test_flatten() {
+        let gates = flatten(&design(), "top").unwrap();
+        let constants = gates
+            .iter()
+            .filter(|gate| matches!(gate, Operation::Const(_, _)))
+            .count();
+        assert_eq!(constants, 2);
+        // Internal wires of the two instances of each model are kept apart
+        assert_eq!(
+            gates
+                .iter()
+                .filter(|gate| matches!(gate, Operation::Add(_, _, _)))
+                .map(|gate| gate.dst())
+                .collect::<std::collections::HashSet<_>>()
+                .len(),
+            4
+        );
+        check(gates);
+    }
+
+    #[test]
+    fn test_inline() {
+        let circuits = design();
+        let (full, report) = inline(&circuits, "top", InlinePolicy::Full).unwrap();
+        assert_eq!(full.len(), 1);
+        assert!(full[0].subcircuits.is_empty());
+        assert_eq!((report.depth_before, report.depth_after), (2, 0));
+        assert!(report.decisions.iter().all(|decision| decision.inlined));
+        check(flatten(&full, "top").unwrap());
+
+        // nxor is called twice and too big, but what it calls is inlined into it
+        let policy = InlinePolicy::Heuristic {
+            max_body: 2,
+            max_calls: 1,
+        };
+        let (kept, report) = inline(&circuits, "top", policy).unwrap();
+        let names: Vec<&str> = kept.iter().map(|model| &*model.name).collect();
+        assert_eq!(names, ["top", "nxor"]);
+        assert!(kept[1].subcircuits.is_empty());
+        assert_eq!(kept[0].subcircuits.len(), 2);
+        let decisions: Vec<(&str, usize, usize, bool)> = report
+            .decisions
+            .iter()
+            .map(|d| (d.model.as_str(), d.body_gates, d.calls, d.inlined))
+            .collect();
+        assert_eq!(
+            decisions,
+            [
+                ("not", 1, 1, true),
+                ("xor", 2, 1, true),
+                ("nxor", 3, 2, false)
+            ]
+        );
+        assert_eq!((report.gates_before, report.gates_after), (15, 11));
+        assert_eq!((report.depth_before, report.depth_after), (2, 1));
+        check(flatten(&kept, "top").unwrap());
+    }
+
+    #[test]
+    fn test_inline_undef_inputs() {
+        let mut circuits = design();
+        // Give xor an unused input of the kind `UndefPolicy::Input` adds
+        circuits[2].gates.push(Operation::Input(14));
+        circuits[2].undef_inputs.push(14);
+        let (full, _) = inline(&circuits, "top", InlinePolicy::Full).unwrap();
+        let undefs = &full[0].undef_inputs;
+        assert_eq!(undefs.len(), 2);
+        assert_ne!(undefs[0], undefs[1]);
+        for wire in undefs {
+            assert!(full[0].gates.contains(&Operation::Input(*wire)));
+        }
+        assert_eq!(full[0].inputs, [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_flatten_errors() {
+        let circuits = vec![model(
+            "top",
+            vec![2],
+            vec![3],
+            vec![],
+            vec![("missing", vec![(2, 2)])],
+        )];
+        let error = flatten(&circuits, "top").unwrap_err();
+        assert!(matches!(&error, FlattenError::MissingModel { name, .. } if name == "missing"));
+        assert_eq!(
+            error.to_string(),
+            "top/missing#0: model missing is not defined"
+        );
+        assert_eq!(
+            flatten(&circuits, "other").unwrap_err().to_string(),
+            "top-level model other is not defined"
+        );
+
+        let outer = model("outer", vec![2], vec![3], vec![], vec![("inner", vec![])]);
+        let inner = model("inner", vec![4], vec![5], vec![], vec![("outer", vec![])]);
+        let error = flatten(&[outer, inner], "outer").unwrap_err();
+        assert!(matches!(&error, FlattenError::Recursive { name, .. } if name == "outer"));
+        assert_eq!(
+            error.to_string(),
+            "outer/inner#0/outer#0: model outer instantiates itself"
+        );
+
+        let scattered = model("top", vec![2, 4], vec![3], vec![], vec![]);
+        assert!(matches!(
+            flatten(&[scattered], "top"),
+            Err(FlattenError::Io { .. })
+        ));
+
+        let cycle = model(
+            "top",
+            vec![2],
+            vec![3],
+            vec![Operation::Add(3, 2, 4), Operation::Mul(4, 3, 2)],
+            vec![],
+        );
+        assert!(matches!(
+            flatten(&[cycle], "top"),
+            Err(FlattenError::Cycle { wires, .. }) if wires == [3, 4]
+        ));
+    }
+
+    #[test]
+    fn test_flatten_provenance() {
+        let at = |file: &str, line| {
+            Some(SourceLocation {
+                file: file.into(),
+                line,
+            })
+        };
+        let mut circuits = design();
+        // Name the second nxor instance and the xor inside it, as `.cname` lines would
+        circuits[0].subcircuits[1].instance = Some("n1".into());
+        circuits[0].subcircuits[1].location = at("top.blif", 9);
+        circuits[1].subcircuits[1].instance = Some("x0".into());
+        circuits[1].subcircuits[1].location = at("nxor.blif", 4);
+
+        // xor lost its second input
+        let mut broken = circuits.clone();
+        broken[2].inputs.pop();
+        let error = flatten(&broken, "top").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "top/nxor#0/x0 (nxor.blif:4): wire 11 is connected, but isn't a port of model xor"
+        );
+        let error = inline(&broken, "top", InlinePolicy::Full).err().unwrap();
+        assert_eq!(
+            error.path().unwrap().to_string(),
+            "top/nxor#0/x0 (nxor.blif:4)"
+        );
+
+        // not is gone, and the first instance to use it is reported
+        let mut missing = circuits.clone();
+        missing.pop();
+        let error = flatten(&missing, "top").unwrap_err();
+        assert_eq!(error.path().unwrap().0.len(), 3);
+        assert_eq!(
+            error.to_string(),
+            "top/nxor#0/not#0: model not is not defined"
+        );
+
+        // A cycle inside xor, so in both of its instances
+        let mut cycle = circuits;
+        cycle[2].gates.push(Operation::Add(14, 15, 12));
+        cycle[2].gates.push(Operation::Add(15, 14, 12));
+        let error = flatten(&cycle, "top").unwrap_err();
+        assert!(matches!(&error, FlattenError::Cycle { wires, .. } if wires.len() == 4));
+        assert_eq!(
+            error.to_string().split(", in ").nth(1),
+            Some("top/nxor#0/x0 (nxor.blif:4), top/n1/x0 (nxor.blif:4)")
+        );
+    }
+}
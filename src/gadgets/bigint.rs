@@ -0,0 +1,595 @@
+//! Unsigned integers of any size, split into limbs held by Z64 wires, with modular arithmetic for
+//! things like 256-bit field and curve operations.
+//!
+//! Each limb holds `LIMB_BITS` bits, least significant limb first. Limbs are far smaller than a
+//! Z64 wire so that a column of limb products and its carry never wraps around: the ring can then
+//! check an addition or multiplication exactly, with the prover supplying the carries (and, for
+//! reductions, the quotient and remainder) as hints. Every hint is range checked by decomposing it
+//! into GF2 bits and packing them back with `B2A`, so a dishonest prover can't pick values the
+//! checks would only accept modulo 2^64.
+//!
+//! Values are passed in and out as little-endian 64-bit words. A `BigNum` carries its value along
+//! with its wires when it was built from known inputs, which is how the gadgets work out their
+//! hints; see `WitnessRecorder`.
+
+use crate::gadgets::decompose::decompose_gf2;
+use crate::gadgets::GateSink;
+use crate::{CombineOperation, Domain, Operation};
+
+/// Bits per limb
+pub const LIMB_BITS: usize = 28;
+const LIMB_MASK: u64 = (1 << LIMB_BITS) - 1;
+/// Limb products are below 2^56, so a column of up to this many of them plus a carry fits in a
+/// Z64 wire. Multiplications need one operand to have at most this many limbs.
+pub const MAX_LIMBS: usize = 255;
+/// Carries out of a column are below 2^(64 - LIMB_BITS)
+const CARRY_BITS: usize = 64 - LIMB_BITS;
+
+/// An unsigned integer, one Z64 wire per limb. Every limb is known to be below 2^`LIMB_BITS`,
+/// either because it was range checked or because it's a constant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigNum {
+    pub limbs: Vec<usize>,
+    /// The limb values, if the inputs were given
+    value: Option<Vec<u64>>,
+}
+
+impl BigNum {
+    /// The value as little-endian 64-bit words, if it's known.
+    pub fn value(&self) -> Option<Vec<u64>> {
+        self.value.as_deref().map(native::to_words)
+    }
+}
+
+/// A constant modulus for the modular gadgets, along with how many limbs reduced values take.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Modulus {
+    limbs: Vec<u64>,
+}
+
+impl Modulus {
+    /// The modulus with value `words` (little-endian), reducing into `limbs` limbs.
+    pub fn new(words: &[u64], limbs: usize) -> Self {
+        let limbs = native::from_words(words, limbs);
+        assert!(limbs.iter().any(|l| *l != 0), "The modulus can't be zero");
+        Modulus { limbs }
+    }
+
+    pub fn limbs(&self) -> usize {
+        self.limbs.len()
+    }
+
+    /// Limbs up to and including the highest nonzero one
+    fn significant_limbs(&self) -> usize {
+        self.limbs.iter().rposition(|l| *l != 0).unwrap() + 1
+    }
+}
+
+fn emit(sink: &mut impl GateSink, gate: impl FnOnce(usize) -> Operation<u64>) -> usize {
+    let dst = sink.fresh_wire(Domain::Z64);
+    sink.emit(CombineOperation::Z64(gate(dst)));
+    dst
+}
+
+fn assert_zero(sink: &mut impl GateSink, wire: usize) {
+    sink.emit(CombineOperation::Z64(Operation::AssertZero(wire)));
+}
+
+/// Reads a value from the witness, reporting it as a hint if it's known.
+fn hint(sink: &mut impl GateSink, value: Option<u64>) -> usize {
+    let wire = emit(sink, Operation::Input);
+    if let Some(value) = value {
+        sink.hint_arith(value);
+    }
+    wire
+}
+
+/// Asserts that `wire` is below 2^`bits`, by decomposing it into GF2 bits.
+fn range_check(sink: &mut impl GateSink, wire: usize, bits: usize, value: Option<u64>) {
+    decompose_gf2(sink, wire, bits, value);
+}
+
+/// Reads limbs from the witness and range checks them.
+fn input_limbs(sink: &mut impl GateSink, count: usize, value: Option<Vec<u64>>) -> BigNum {
+    let limbs = (0..count)
+        .map(|i| {
+            let limb = value.as_ref().map(|v| v[i]);
+            let wire = hint(sink, limb);
+            range_check(sink, wire, LIMB_BITS, limb);
+            wire
+        })
+        .collect();
+    BigNum { limbs, value }
+}
+
+/// Reads a number of `limbs` limbs from the witness. Give its value (little-endian words) to have
+/// it recorded.
+pub fn input(sink: &mut impl GateSink, limbs: usize, value: Option<&[u64]>) -> BigNum {
+    input_limbs(
+        sink,
+        limbs,
+        value.map(|words| native::from_words(words, limbs)),
+    )
+}
+
+fn constant_limbs(sink: &mut impl GateSink, value: Vec<u64>) -> BigNum {
+    BigNum {
+        limbs: value
+            .iter()
+            .map(|limb| emit(sink, |dst| Operation::Const(dst, *limb)))
+            .collect(),
+        value: Some(value),
+    }
+}
+
+pub fn constant(sink: &mut impl GateSink, words: &[u64], limbs: usize) -> BigNum {
+    constant_limbs(sink, native::from_words(words, limbs))
+}
+
+/// Adds up a column's terms. Zero for an empty column.
+fn sum(sink: &mut impl GateSink, terms: &[usize]) -> usize {
+    match terms.split_first() {
+        None => emit(sink, |dst| Operation::Const(dst, 0)),
+        Some((first, rest)) => rest.iter().fold(*first, |acc, term| {
+            emit(sink, |dst| Operation::Add(dst, acc, *term))
+        }),
+    }
+}
+
+/// Turns columns of terms into `width` limbs, carrying everything above `LIMB_BITS` into the next
+/// column and asserting that nothing is left over. `values` holds each column's sum, which must
+/// be below 2^64 once the carry is added.
+fn carry_columns(
+    sink: &mut impl GateSink,
+    columns: Vec<Vec<usize>>,
+    values: Option<Vec<u64>>,
+    width: usize,
+) -> BigNum {
+    assert!(columns.len() <= width);
+    let mut limbs = Vec::with_capacity(width);
+    let mut limb_values = values.as_ref().map(|_| Vec::with_capacity(width));
+    let mut carry: Option<(usize, Option<u64>)> = None;
+
+    for k in 0..width {
+        let mut terms = columns.get(k).cloned().unwrap_or_default();
+        let mut total_value = values.as_ref().map(|v| v.get(k).copied().unwrap_or(0));
+        if let Some((wire, value)) = carry {
+            terms.push(wire);
+            total_value = total_value.zip(value).map(|(total, carry)| total + carry);
+        }
+        let total = sum(sink, &terms);
+
+        // total = low + high * 2^LIMB_BITS, which can't wrap once both are range checked
+        let low_value = total_value.map(|v| v & LIMB_MASK);
+        let high_value = total_value.map(|v| v >> LIMB_BITS);
+        let low = hint(sink, low_value);
+        range_check(sink, low, LIMB_BITS, low_value);
+        let high = hint(sink, high_value);
+        range_check(sink, high, CARRY_BITS, high_value);
+        let shifted = emit(sink, |dst| Operation::MulConst(dst, high, 1 << LIMB_BITS));
+        let recombined = emit(sink, |dst| Operation::Add(dst, low, shifted));
+        let difference = emit(sink, |dst| Operation::Sub(dst, total, recombined));
+        assert_zero(sink, difference);
+
+        limbs.push(low);
+        if let (Some(limb_values), Some(low)) = (limb_values.as_mut(), low_value) {
+            limb_values.push(low);
+        }
+        carry = Some((high, high_value));
+    }
+
+    if let Some((wire, _)) = carry {
+        assert_zero(sink, wire);
+    }
+    BigNum {
+        limbs,
+        value: limb_values,
+    }
+}
+
+/// `a + b`, with one more limb than the longer operand.
+pub fn add(sink: &mut impl GateSink, a: &BigNum, b: &BigNum) -> BigNum {
+    let width = a.limbs.len().max(b.limbs.len());
+    let columns = (0..width)
+        .map(|k| {
+            a.limbs
+                .get(k)
+                .into_iter()
+                .chain(b.limbs.get(k))
+                .copied()
+                .collect()
+        })
+        .collect();
+    let values = a.value.as_ref().zip(b.value.as_ref()).map(|(a, b)| {
+        (0..width)
+            .map(|k| a.get(k).unwrap_or(&0) + b.get(k).unwrap_or(&0))
+            .collect()
+    });
+    carry_columns(sink, columns, values, width + 1)
+}
+
+/// `a * b`, with as many limbs as both operands together.
+pub fn mul(sink: &mut impl GateSink, a: &BigNum, b: &BigNum) -> BigNum {
+    assert!(
+        a.limbs.len().min(b.limbs.len()) <= MAX_LIMBS,
+        "One operand of a multiplication can have at most {} limbs",
+        MAX_LIMBS
+    );
+    let width = a.limbs.len() + b.limbs.len();
+    let mut columns = vec![Vec::new(); width];
+    for (i, x) in a.limbs.iter().enumerate() {
+        for (j, y) in b.limbs.iter().enumerate() {
+            columns[i + j].push(emit(sink, |dst| Operation::Mul(dst, *x, *y)));
+        }
+    }
+    let values = a
+        .value
+        .as_ref()
+        .zip(b.value.as_ref())
+        .map(|(a, b)| native::product_columns(a, b));
+    carry_columns(sink, columns, values, width)
+}
+
+/// `a * constant`, where `constant` is given in limbs.
+fn mul_constant(sink: &mut impl GateSink, a: &BigNum, constant: &[u64]) -> BigNum {
+    let width = a.limbs.len() + constant.len();
+    let mut columns = vec![Vec::new(); width];
+    for (i, x) in a.limbs.iter().enumerate() {
+        for (j, c) in constant.iter().enumerate().filter(|(_, c)| **c != 0) {
+            columns[i + j].push(emit(sink, |dst| Operation::MulConst(dst, *x, *c)));
+        }
+    }
+    let values = a
+        .value
+        .as_ref()
+        .map(|a| native::product_columns(a, constant));
+    carry_columns(sink, columns, values, width)
+}
+
+/// Asserts that `a` and `b` hold the same number. Since limbs are in range, this is the same as
+/// comparing them limb by limb, with missing limbs as zero.
+pub fn assert_equal(sink: &mut impl GateSink, a: &BigNum, b: &BigNum) {
+    for k in 0..a.limbs.len().max(b.limbs.len()) {
+        match (a.limbs.get(k), b.limbs.get(k)) {
+            (Some(x), Some(y)) => {
+                let difference = emit(sink, |dst| Operation::Sub(dst, *x, *y));
+                assert_zero(sink, difference);
+            }
+            (Some(wire), None) | (None, Some(wire)) => assert_zero(sink, *wire),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// `x mod modulus`. The prover supplies the quotient `q` and remainder `r`, and the circuit checks
+/// that `x = q * modulus + r` and `r < modulus`.
+pub fn reduce(sink: &mut impl GateSink, x: &BigNum, modulus: &Modulus) -> BigNum {
+    let width = modulus.limbs();
+    // The modulus is at least 2^(LIMB_BITS * (significant - 1)), which bounds the quotient
+    let quotient_limbs = (x.limbs.len() + 1).saturating_sub(modulus.significant_limbs());
+    let hints = x.value.as_ref().map(|x| native::divide(x, &modulus.limbs));
+
+    let quotient = input_limbs(
+        sink,
+        quotient_limbs,
+        hints.as_ref().map(|(q, _)| q[..quotient_limbs].to_vec()),
+    );
+    let remainder = input_limbs(sink, width, hints.as_ref().map(|(_, r)| r.clone()));
+    let product = mul_constant(sink, &quotient, &modulus.limbs);
+    let total = add(sink, &product, &remainder);
+    assert_equal(sink, &total, x);
+
+    assert_reduced(sink, &remainder, modulus);
+    remainder
+}
+
+/// Asserts that `a` is below the modulus, which is the case exactly when some in-range `s` has
+/// `a + s = modulus - 1`.
+pub fn assert_reduced(sink: &mut impl GateSink, a: &BigNum, modulus: &Modulus) {
+    let largest = native::sub(&modulus.limbs, &[1]);
+    let slack = input_limbs(
+        sink,
+        modulus.limbs(),
+        a.value.as_ref().map(|a| native::sub(&largest, a)),
+    );
+    let sum = add(sink, a, &slack);
+    let bound = constant_limbs(sink, largest);
+    assert_equal(sink, &sum, &bound);
+}
+
+/// `(a + b) mod modulus`
+pub fn add_mod(sink: &mut impl GateSink, a: &BigNum, b: &BigNum, modulus: &Modulus) -> BigNum {
+    let sum = add(sink, a, b);
+    reduce(sink, &sum, modulus)
+}
+
+/// `(a - b) mod modulus`, for `b` below the modulus. Computed as `a + (modulus - b)`, with the
+/// prover supplying `modulus - b`.
+pub fn sub_mod(sink: &mut impl GateSink, a: &BigNum, b: &BigNum, modulus: &Modulus) -> BigNum {
+    let complement = input_limbs(
+        sink,
+        modulus.limbs(),
+        b.value.as_ref().map(|b| native::sub(&modulus.limbs, b)),
+    );
+    let total = add(sink, b, &complement);
+    let expected = constant_limbs(sink, modulus.limbs.clone());
+    assert_equal(sink, &total, &expected);
+    add_mod(sink, a, &complement, modulus)
+}
+
+/// `(a * b) mod modulus`
+pub fn mul_mod(sink: &mut impl GateSink, a: &BigNum, b: &BigNum, modulus: &Modulus) -> BigNum {
+    let product = mul(sink, a, b);
+    reduce(sink, &product, modulus)
+}
+
+/// `if_true` if the GF2 wire `select` is set, otherwise `if_false`. Give the value of `select`
+/// to keep track of the result's value.
+pub fn select(
+    sink: &mut impl GateSink,
+    select: usize,
+    value: Option<bool>,
+    if_true: &BigNum,
+    if_false: &BigNum,
+) -> BigNum {
+    // Move the bit over to Z64, where it's 0 or 1
+    let low = sink.fresh_wires(Domain::GF2, 64);
+    sink.emit(CombineOperation::GF2(Operation::AddConst(
+        low, select, false,
+    )));
+    for i in 1..64 {
+        sink.emit(CombineOperation::GF2(Operation::Const(low + i, false)));
+    }
+    let flag = sink.fresh_wire(Domain::Z64);
+    sink.emit(CombineOperation::B2A(flag, low));
+
+    let width = if_true.limbs.len().max(if_false.limbs.len());
+    let mut zero = None;
+    let mut limb = |sink: &mut _, number: &BigNum, k: usize| match number.limbs.get(k) {
+        Some(wire) => *wire,
+        None => *zero.get_or_insert_with(|| emit(sink, |dst| Operation::Const(dst, 0))),
+    };
+    let limbs = (0..width)
+        .map(|k| {
+            let t = limb(sink, if_true, k);
+            let f = limb(sink, if_false, k);
+            // f + flag * (t - f), which is exactly t or f even when t - f wraps
+            let difference = emit(sink, |dst| Operation::Sub(dst, t, f));
+            let scaled = emit(sink, |dst| Operation::Mul(dst, flag, difference));
+            emit(sink, |dst| Operation::Add(dst, f, scaled))
+        })
+        .collect();
+    let chosen = value.and_then(|v| if v { &if_true.value } else { &if_false.value }.clone());
+    BigNum {
+        limbs,
+        value: chosen.map(|mut limbs| {
+            limbs.resize(width, 0);
+            limbs
+        }),
+    }
+}
+
+/// Arithmetic on limb values, for working out hints.
+mod native {
+    use super::{LIMB_BITS, LIMB_MASK};
+
+    fn bit(limbs: &[u64], i: usize) -> bool {
+        (limbs[i / LIMB_BITS] >> (i % LIMB_BITS)) & 1 == 1
+    }
+
+    /// Splits little-endian words into `limbs` limbs, which must be enough to hold them.
+    pub fn from_words(words: &[u64], limbs: usize) -> Vec<u64> {
+        let mut result = vec![0; limbs];
+        for i in 0..words.len() * 64 {
+            if (words[i / 64] >> (i % 64)) & 1 == 1 {
+                assert!(
+                    i < limbs * LIMB_BITS,
+                    "Value doesn't fit in {} limbs",
+                    limbs
+                );
+                result[i / LIMB_BITS] |= 1 << (i % LIMB_BITS);
+            }
+        }
+        result
+    }
+
+    pub fn to_words(limbs: &[u64]) -> Vec<u64> {
+        let bits = limbs.len() * LIMB_BITS;
+        let mut words = vec![0; bits.div_ceil(64)];
+        for i in (0..bits).filter(|i| bit(limbs, *i)) {
+            words[i / 64] |= 1 << (i % 64);
+        }
+        words
+    }
+
+    /// Column sums of the schoolbook product, before carrying.
+    pub fn product_columns(a: &[u64], b: &[u64]) -> Vec<u64> {
+        let mut columns = vec![0; a.len() + b.len()];
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                columns[i + j] += x * y;
+            }
+        }
+        columns
+    }
+
+    fn at_least(a: &[u64], b: &[u64]) -> bool {
+        for k in (0..a.len().max(b.len())).rev() {
+            let (x, y) = (a.get(k).unwrap_or(&0), b.get(k).unwrap_or(&0));
+            if x != y {
+                return x > y;
+            }
+        }
+        true
+    }
+
+    /// `a - b`, with as many limbs as `a`. `a` must be at least `b`.
+    pub fn sub(a: &[u64], b: &[u64]) -> Vec<u64> {
+        let mut borrow = 0;
+        let difference = (0..a.len())
+            .map(|k| {
+                let subtrahend = b.get(k).unwrap_or(&0) + borrow;
+                borrow = u64::from(a[k] < subtrahend);
+                (a[k] + (borrow << LIMB_BITS) - subtrahend) & LIMB_MASK
+            })
+            .collect();
+        assert!(
+            borrow == 0 && b[a.len().min(b.len())..].iter().all(|l| *l == 0),
+            "Subtraction underflowed"
+        );
+        difference
+    }
+
+    /// Quotient and remainder, with as many limbs as `x` and `m` respectively.
+    pub fn divide(x: &[u64], m: &[u64]) -> (Vec<u64>, Vec<u64>) {
+        let mut quotient = vec![0; x.len()];
+        let mut remainder = vec![0; m.len() + 1];
+        for i in (0..x.len() * LIMB_BITS).rev() {
+            // remainder = remainder * 2 + bit i of x
+            let mut carry = u64::from(bit(x, i));
+            for limb in remainder.iter_mut() {
+                let shifted = (*limb << 1) | carry;
+                carry = shifted >> LIMB_BITS;
+                *limb = shifted & LIMB_MASK;
+            }
+            if at_least(&remainder, m) {
+                remainder = sub(&remainder, m);
+                quotient[i / LIMB_BITS] |= 1 << (i % LIMB_BITS);
+            }
+        }
+        remainder.truncate(m.len());
+        (quotient, remainder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use crate::gadgets::bigint::{add, add_mod, input, mul, mul_mod, BigNum, Modulus, LIMB_BITS};
+    use crate::gadgets::{evaluate_arith_outputs, WitnessRecorder};
+    use crate::{evaluate_composite_program, CombineOperation, ProgramEditor};
+
+    /// 2^255 - 19
+    const P: [u64; 4] = [
+        0xffff_ffff_ffff_ffed,
+        0xffff_ffff_ffff_ffff,
+        0xffff_ffff_ffff_ffff,
+        0x7fff_ffff_ffff_ffff,
+    ];
+    const A: [u64; 4] = [
+        0x0123_4567_89ab_cdef,
+        0xfedc_ba98_7654_3210,
+        0x1726_3544_5362_7180,
+        0x1f2e_3d4c_5b6a_7988,
+    ];
+    /// p - 16
+    const B: [u64; 4] = [
+        0xffff_ffff_ffff_ffdd,
+        0xffff_ffff_ffff_ffff,
+        0xffff_ffff_ffff_ffff,
+        0x7fff_ffff_ffff_ffff,
+    ];
+    const LIMBS: usize = 10;
+
+    fn to_u128(limbs: &[u64]) -> u128 {
+        limbs
+            .iter()
+            .rev()
+            .fold(0, |value, limb| (value << LIMB_BITS) | u128::from(*limb))
+    }
+
+    /// Builds `(a + b) mod p` and `(a * b) mod p`, recording the witness if the values are given.
+    fn build(known: bool) -> (WitnessRecorder<ProgramEditor>, BigNum, BigNum) {
+        let modulus = Modulus::new(&P, LIMBS);
+        let mut sink = WitnessRecorder::new(ProgramEditor::new(Vec::new()));
+        let a = input(&mut sink, LIMBS, Some(&A[..]).filter(|_| known));
+        let b = input(&mut sink, LIMBS, Some(&B[..]).filter(|_| known));
+        let sum = add_mod(&mut sink, &a, &b, &modulus);
+        let product = mul_mod(&mut sink, &a, &b, &modulus);
+        (sink, sum, product)
+    }
+
+    #[test]
+    fn test_modular_arithmetic() {
+        let (sink, sum, product) = build(true);
+        assert_eq!(sum.limbs.len(), LIMBS);
+        assert_eq!(
+            sum.value().unwrap()[..4],
+            [
+                0x0123_4567_89ab_cddf,
+                0xfedc_ba98_7654_3210,
+                0x1726_3544_5362_7180,
+                0x1f2e_3d4c_5b6a_7988,
+            ]
+        );
+        assert_eq!(
+            product.value().unwrap()[..4],
+            [
+                0xedcb_a987_6543_20c4,
+                0x1234_5678_9abc_deff,
+                0x8d9c_abba_c9d8_e7f0,
+                0x0d1c_2b3a_4958_677e,
+            ]
+        );
+
+        let WitnessRecorder {
+            sink: editor,
+            bool_inputs,
+            arith_inputs,
+        } = sink;
+        let (program, _) = editor.commit();
+        evaluate_composite_program(&program, &bool_inputs, &arith_inputs);
+
+        // The circuit computes the limbs the hints said it would
+        let outputs: Vec<usize> = sum.limbs.iter().chain(&product.limbs).copied().collect();
+        let limbs = evaluate_arith_outputs(&program, &outputs, &bool_inputs, &arith_inputs);
+        let expected: Vec<u64> = sum
+            .value
+            .unwrap()
+            .into_iter()
+            .chain(product.value.unwrap())
+            .collect();
+        assert_eq!(limbs, expected);
+
+        // Knowing the values doesn't change the circuit
+        let (unknown, _, _) = build(false);
+        assert!(unknown.bool_inputs.is_empty() && unknown.arith_inputs.is_empty());
+        assert_eq!(unknown.sink.commit().0, program);
+
+        // Every hint takes part in some check, so changing any of them gets caught
+        for position in (0..arith_inputs.len()).step_by(41) {
+            let mut forged = arith_inputs.clone();
+            forged[position] ^= 1;
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                evaluate_composite_program(&program, &bool_inputs, &forged)
+            }));
+            assert!(result.is_err(), "Forged hint {} went unnoticed", position);
+        }
+    }
+
+    #[test]
+    fn test_add_and_mul() {
+        let a_value = u128::from(u64::MAX) | (0xff << 64);
+        let b_value = (1u128 << 56) - 1;
+        let mut sink = WitnessRecorder::new(ProgramEditor::new(Vec::new()));
+        let a = input(&mut sink, 3, Some(&[u64::MAX, 0xff]));
+        let b = input(&mut sink, 2, Some(&[b_value as u64]));
+        let sum = add(&mut sink, &a, &b);
+        let product = mul(&mut sink, &a, &b);
+        assert_eq!(sum.limbs.len(), 4);
+        assert_eq!(product.limbs.len(), 5);
+        assert_eq!(to_u128(sum.value.as_ref().unwrap()), a_value + b_value);
+        assert_eq!(to_u128(product.value.as_ref().unwrap()), a_value * b_value);
+
+        let (program, _) = sink.sink.commit();
+        let outputs: Vec<usize> = sum.limbs.iter().chain(&product.limbs).copied().collect();
+        let limbs =
+            evaluate_arith_outputs(&program, &outputs, &sink.bool_inputs, &sink.arith_inputs);
+        assert_eq!(to_u128(&limbs[..4]), a_value + b_value);
+        assert_eq!(to_u128(&limbs[4..]), a_value * b_value);
+        assert!(program
+            .iter()
+            .any(|gate| matches!(gate, CombineOperation::B2A(_, _))));
+    }
+}
@@ -0,0 +1,351 @@
+//! Boolean operations on GF2 wires and words. Every function emits fresh wires for its results and
+//! never rewrites its arguments. Words are least significant bit first.
+
+use crate::gadgets::GateSink;
+use crate::{CombineOperation, Domain, Operation};
+
+fn emit(sink: &mut impl GateSink, gate: impl FnOnce(usize) -> Operation<bool>) -> usize {
+    let dst = sink.fresh_wire(Domain::GF2);
+    sink.emit(CombineOperation::GF2(gate(dst)));
+    dst
+}
+
+/// Reads a word of `width` bits from the witness.
+pub fn input(sink: &mut impl GateSink, width: usize) -> Vec<usize> {
+    (0..width).map(|_| emit(sink, Operation::Input)).collect()
+}
+
+pub fn constant(sink: &mut impl GateSink, value: bool) -> usize {
+    emit(sink, |dst| Operation::Const(dst, value))
+}
+
+/// The low `width` bits of `value`, as constants.
+pub fn constant_word(sink: &mut impl GateSink, value: u64, width: usize) -> Vec<usize> {
+    assert!(width <= 64, "Constant words can be at most 64 bits wide");
+    (0..width)
+        .map(|i| constant(sink, (value >> i) & 1 == 1))
+        .collect()
+}
+
+pub fn not(sink: &mut impl GateSink, a: usize) -> usize {
+    emit(sink, |dst| Operation::AddConst(dst, a, true))
+}
+
+pub fn xor(sink: &mut impl GateSink, a: usize, b: usize) -> usize {
+    emit(sink, |dst| Operation::Add(dst, a, b))
+}
+
+pub fn and(sink: &mut impl GateSink, a: usize, b: usize) -> usize {
+    emit(sink, |dst| Operation::Mul(dst, a, b))
+}
+
+/// Asserts that `a` is set. `AssertZero` is the only assertion, so this inverts `a` first.
+pub fn assert_true(sink: &mut impl GateSink, a: usize) {
+    let inverted = not(sink, a);
+    sink.emit(CombineOperation::GF2(Operation::AssertZero(inverted)));
+}
+
+/// `a | b`, computed as `a ^ b ^ (a & b)`.
+pub fn or(sink: &mut impl GateSink, a: usize, b: usize) -> usize {
+    let both = and(sink, a, b);
+    let either = xor(sink, a, b);
+    xor(sink, either, both)
+}
+
+/// `if_true` if `select` is set, otherwise `if_false`. Costs one AND.
+pub fn mux(sink: &mut impl GateSink, select: usize, if_true: usize, if_false: usize) -> usize {
+    let difference = xor(sink, if_true, if_false);
+    let chosen = and(sink, select, difference);
+    xor(sink, if_false, chosen)
+}
+
+/// Whether any bit is set. False for an empty word.
+pub fn any(sink: &mut impl GateSink, bits: &[usize]) -> usize {
+    match bits.split_first() {
+        None => constant(sink, false),
+        Some((first, rest)) => rest.iter().fold(*first, |acc, bit| or(sink, acc, *bit)),
+    }
+}
+
+/// Whether every bit is set. True for an empty word.
+pub fn all(sink: &mut impl GateSink, bits: &[usize]) -> usize {
+    match bits.split_first() {
+        None => constant(sink, true),
+        Some((first, rest)) => rest.iter().fold(*first, |acc, bit| and(sink, acc, *bit)),
+    }
+}
+
+pub fn is_zero(sink: &mut impl GateSink, bits: &[usize]) -> usize {
+    let set = any(sink, bits);
+    not(sink, set)
+}
+
+pub fn not_word(sink: &mut impl GateSink, a: &[usize]) -> Vec<usize> {
+    a.iter().map(|bit| not(sink, *bit)).collect()
+}
+
+pub fn and_word(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> Vec<usize> {
+    assert_eq!(a.len(), b.len(), "Words must have the same width");
+    a.iter().zip(b).map(|(x, y)| and(sink, *x, *y)).collect()
+}
+
+pub fn xor_word(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> Vec<usize> {
+    assert_eq!(a.len(), b.len(), "Words must have the same width");
+    a.iter().zip(b).map(|(x, y)| xor(sink, *x, *y)).collect()
+}
+
+/// `if_true` if `select` is set, otherwise `if_false`.
+pub fn mux_word(
+    sink: &mut impl GateSink,
+    select: usize,
+    if_true: &[usize],
+    if_false: &[usize],
+) -> Vec<usize> {
+    assert_eq!(
+        if_true.len(),
+        if_false.len(),
+        "Words must have the same width"
+    );
+    if_true
+        .iter()
+        .zip(if_false)
+        .map(|(t, f)| mux(sink, select, *t, *f))
+        .collect()
+}
+
+/// Adds two words of the same width plus a carry bit. Returns the sum (of the same width) and the
+/// carry out. Each bit costs one AND.
+pub fn add_with_carry(
+    sink: &mut impl GateSink,
+    a: &[usize],
+    b: &[usize],
+    carry: usize,
+) -> (Vec<usize>, usize) {
+    assert_eq!(a.len(), b.len(), "Words must have the same width");
+    let mut carry = carry;
+    let sum = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| {
+            // carry' = c ^ ((x ^ c) & (y ^ c)) is the majority of x, y and c
+            let x_c = xor(sink, *x, carry);
+            let y_c = xor(sink, *y, carry);
+            let sum = xor(sink, x_c, *y);
+            let both = and(sink, x_c, y_c);
+            carry = xor(sink, carry, both);
+            sum
+        })
+        .collect();
+    (sum, carry)
+}
+
+/// Adds two words of the same width. Returns the sum (of the same width) and the carry out.
+pub fn add(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> (Vec<usize>, usize) {
+    let zero = constant(sink, false);
+    add_with_carry(sink, a, b, zero)
+}
+
+/// Adds a single bit to a word. Returns the sum and the carry out.
+pub fn increment(sink: &mut impl GateSink, a: &[usize], bit: usize) -> (Vec<usize>, usize) {
+    let mut carry = bit;
+    let sum = a
+        .iter()
+        .map(|x| {
+            let sum = xor(sink, *x, carry);
+            carry = and(sink, *x, carry);
+            sum
+        })
+        .collect();
+    (sum, carry)
+}
+
+/// Subtracts `b` from `a` modulo `2^width`. Returns the difference and whether it borrowed (that
+/// is, whether `a < b` as unsigned numbers).
+pub fn sub(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> (Vec<usize>, usize) {
+    let inverted = not_word(sink, b);
+    let one = constant(sink, true);
+    let (difference, carry) = add_with_carry(sink, a, &inverted, one);
+    (difference, not(sink, carry))
+}
+
+/// `a < b`, as unsigned numbers.
+pub fn less_than(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> usize {
+    sub(sink, a, b).1
+}
+
+pub fn equal(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> usize {
+    let difference = xor_word(sink, a, b);
+    is_zero(sink, &difference)
+}
+
+/// Multiplies two unsigned words, returning the full product (as wide as both together).
+pub fn multiply(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> Vec<usize> {
+    let zero = constant(sink, false);
+    let (first, rest) = match b.split_first() {
+        None => return vec![zero; a.len()],
+        Some(split) => split,
+    };
+
+    // Bits of the partial product that later rows can still change, starting at the bit the
+    // next row lines up with
+    let mut pending: Vec<usize> = a.iter().map(|x| and(sink, *x, *first)).collect();
+    let mut product = Vec::with_capacity(a.len() + b.len());
+    for bit in rest {
+        product.push(pending.remove(0));
+        pending.resize(a.len(), zero);
+        let row: Vec<usize> = a.iter().map(|x| and(sink, *x, *bit)).collect();
+        let (sum, carry) = add(sink, &pending, &row);
+        pending = sum;
+        pending.push(carry);
+    }
+    product.extend(pending);
+    product.resize(a.len() + b.len(), zero);
+    product
+}
+
+/// Shifts `a` left by the unsigned amount in `amount`, shifting in zeros.
+pub fn shift_left(sink: &mut impl GateSink, a: &[usize], amount: &[usize]) -> Vec<usize> {
+    let zero = constant(sink, false);
+    let mut shifted = a.to_vec();
+    for (k, select) in amount.iter().enumerate() {
+        let by = 1usize.checked_shl(k as u32).unwrap_or(usize::MAX);
+        let moved: Vec<usize> = (0..a.len())
+            .map(|i| if i >= by { shifted[i - by] } else { zero })
+            .collect();
+        shifted = mux_word(sink, *select, &moved, &shifted);
+    }
+    shifted
+}
+
+/// Shifts `a` right by the unsigned amount in `amount`, shifting in zeros. Also returns whether
+/// any set bits were shifted out (the "sticky" bit rounding needs).
+pub fn shift_right_sticky(
+    sink: &mut impl GateSink,
+    a: &[usize],
+    amount: &[usize],
+) -> (Vec<usize>, usize) {
+    let zero = constant(sink, false);
+    let mut shifted = a.to_vec();
+    let mut sticky = zero;
+    for (k, select) in amount.iter().enumerate() {
+        let by = 1usize.checked_shl(k as u32).unwrap_or(usize::MAX);
+        let lost = any(sink, &shifted[..by.min(a.len())]);
+        let lost = and(sink, *select, lost);
+        sticky = or(sink, sticky, lost);
+
+        let moved: Vec<usize> = (0..a.len())
+            .map(|i| match i.checked_add(by) {
+                Some(from) if from < a.len() => shifted[from],
+                _ => zero,
+            })
+            .collect();
+        shifted = mux_word(sink, *select, &moved, &shifted);
+    }
+    (shifted, sticky)
+}
+
+/// Shifts `a` left until its top bit is set. Returns the shifted word and how far it was shifted
+/// (the number of leading zeros). If `a` is zero, the shift is all ones.
+pub fn normalize(sink: &mut impl GateSink, a: &[usize]) -> (Vec<usize>, Vec<usize>) {
+    let stages = usize::BITS - a.len().saturating_sub(1).leading_zeros();
+    let zero = constant(sink, false);
+    let mut shifted = a.to_vec();
+    let mut count = vec![zero; stages as usize];
+    for k in (0..stages as usize).rev() {
+        let by = 1 << k;
+        let top = &shifted[a.len() - by..];
+        let empty = is_zero(sink, top);
+        let moved: Vec<usize> = (0..a.len())
+            .map(|i| if i >= by { shifted[i - by] } else { zero })
+            .collect();
+        shifted = mux_word(sink, empty, &moved, &shifted);
+        count[k] = empty;
+    }
+    (shifted, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gadgets::bits::{
+        add, constant_word, input, less_than, multiply, normalize, shift_left, shift_right_sticky,
+        sub,
+    };
+    use crate::gadgets::evaluate_outputs;
+    use crate::ProgramEditor;
+
+    fn bits(value: u64, width: usize) -> Vec<bool> {
+        (0..width).map(|i| (value >> i) & 1 == 1).collect()
+    }
+
+    fn value(bits: &[bool]) -> u64 {
+        bits.iter()
+            .enumerate()
+            .map(|(i, b)| u64::from(*b) << i)
+            .sum()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut editor = ProgramEditor::new(Vec::new());
+        let a = input(&mut editor, 8);
+        let b = input(&mut editor, 8);
+        let (sum, carry) = add(&mut editor, &a, &b);
+        let (difference, borrow) = sub(&mut editor, &a, &b);
+        let lt = less_than(&mut editor, &a, &b);
+        let product = multiply(&mut editor, &a, &b[..5]);
+        let mut outputs = sum.clone();
+        outputs.push(carry);
+        outputs.extend(&difference);
+        outputs.push(borrow);
+        outputs.push(lt);
+        outputs.extend(&product);
+        let (program, _) = editor.commit();
+
+        for (x, y) in [(200, 100), (3, 250), (17, 17), (255, 31), (0, 0)] {
+            let mut witness = bits(x, 8);
+            witness.extend(bits(y, 8));
+            let out = evaluate_outputs(&program, &outputs, &witness);
+            assert_eq!(value(&out[..9]), x + y);
+            assert_eq!(value(&out[9..17]), x.wrapping_sub(y) & 0xff);
+            assert_eq!(out[17], x < y);
+            assert_eq!(out[18], x < y);
+            assert_eq!(value(&out[19..]), x * (y & 0x1f));
+        }
+    }
+
+    #[test]
+    fn test_shifts() {
+        let mut editor = ProgramEditor::new(Vec::new());
+        let a = input(&mut editor, 12);
+        let amount = input(&mut editor, 4);
+        let left = shift_left(&mut editor, &a, &amount);
+        let (right, sticky) = shift_right_sticky(&mut editor, &a, &amount);
+        let (normalized, zeros) = normalize(&mut editor, &a);
+        let constant = constant_word(&mut editor, 0b1011, 4);
+        let mut outputs = left.clone();
+        outputs.extend(&right);
+        outputs.push(sticky);
+        outputs.extend(&normalized);
+        outputs.extend(&zeros);
+        outputs.extend(&constant);
+        let (program, _) = editor.commit();
+
+        for (x, by) in [
+            (0b1010_0110_0001, 3),
+            (0b0000_0011_0000, 4),
+            (1, 15),
+            (0, 2),
+        ] {
+            let mut witness = bits(x, 12);
+            witness.extend(bits(by, 4));
+            let out = evaluate_outputs(&program, &outputs, &witness);
+            assert_eq!(value(&out[..12]), (x << by) & 0xfff);
+            assert_eq!(value(&out[12..24]), x >> by);
+            assert_eq!(out[24], x & ((1 << by) - 1) != 0);
+            let zeros = if x == 0 { 15 } else { x.leading_zeros() - 52 };
+            assert_eq!(value(&out[25..37]), (x << zeros) & 0xfff);
+            assert_eq!(value(&out[37..41]), u64::from(zeros));
+            assert_eq!(value(&out[41..]), 0b1011);
+        }
+    }
+}
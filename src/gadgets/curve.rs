@@ -0,0 +1,259 @@
+//! Fixed-base scalar multiplication on twisted Edwards curves with `a = -1`, the shape Ed25519
+//! uses, for proving knowledge of the secret behind a public key. Needs the `curves` feature.
+//!
+//! The circuit adds up the multiples `2^i B` of the base point picked out by the scalar's bits,
+//! using the complete extended-coordinate formulas (`add-2008-hwcd-3`), so there are no special
+//! cases for the identity or for doubling. Each scalar bit costs seven modular multiplications
+//! and seven modular additions or subtractions, and the final conversion back to affine
+//! coordinates two more multiplications, with the prover supplying the inverse.
+
+use num_bigint::BigUint;
+
+use crate::gadgets::bigint::{
+    add_mod, assert_equal, assert_reduced, constant, input, mul_mod, select, sub_mod, BigNum,
+    Modulus, LIMB_BITS,
+};
+use crate::gadgets::GateSink;
+use crate::{CombineOperation, Domain, Operation};
+
+/// The curve `-x^2 + y^2 = 1 + d x^2 y^2` over the integers modulo a prime, with a base point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EdwardsCurve {
+    modulus: BigUint,
+    d: BigUint,
+    base: (BigUint, BigUint),
+}
+
+impl EdwardsCurve {
+    /// The formulas are only complete if `d` is not a square modulo `modulus`, which isn't
+    /// checked.
+    pub fn new(modulus: BigUint, d: BigUint, base_x: BigUint, base_y: BigUint) -> Self {
+        let curve = EdwardsCurve {
+            modulus,
+            d,
+            base: (base_x, base_y),
+        };
+        let (x, y) = &curve.base;
+        let (xx, yy) = (x * x % &curve.modulus, y * y % &curve.modulus);
+        assert_eq!(
+            (yy.clone() + &curve.modulus - &xx) % &curve.modulus,
+            (BigUint::from(1u8) + &curve.d * xx * yy) % &curve.modulus,
+            "The base point isn't on the curve"
+        );
+        curve
+    }
+
+    /// Ed25519: the twisted Edwards form of Curve25519, with its standard base point.
+    pub fn ed25519() -> Self {
+        let modulus = (BigUint::from(1u8) << 255) - 19u8;
+        let hex = |digits: &str| BigUint::parse_bytes(digits.as_bytes(), 16).unwrap();
+        EdwardsCurve::new(
+            modulus,
+            hex("52036cee2b6ffe738cc740797779e89800700a4d4141d8ab75eb4dca135978a3"),
+            hex("216936d3cd6e53fec0a4e231fdd6dc5c692cc7609525a7b2c9562d608f25d51a"),
+            hex("6666666666666666666666666666666666666666666666666666666666666658"),
+        )
+    }
+
+    pub fn modulus(&self) -> &BigUint {
+        &self.modulus
+    }
+
+    pub fn base(&self) -> &(BigUint, BigUint) {
+        &self.base
+    }
+
+    /// Limbs needed for coordinates
+    pub fn limbs(&self) -> usize {
+        (self.modulus.bits() as usize).div_ceil(LIMB_BITS)
+    }
+
+    fn inverse(&self, value: &BigUint) -> BigUint {
+        value.modpow(&(&self.modulus - 2u8), &self.modulus)
+    }
+
+    fn add(&self, a: &(BigUint, BigUint), b: &(BigUint, BigUint)) -> (BigUint, BigUint) {
+        let p = &self.modulus;
+        let t = &self.d * &a.0 * &b.0 % p * &a.1 * &b.1 % p;
+        let x = (&a.0 * &b.1 + &a.1 * &b.0) * self.inverse(&((BigUint::from(1u8) + &t) % p));
+        let y = (&a.1 * &b.1 + &a.0 * &b.0) * self.inverse(&((BigUint::from(1u8) + p - &t) % p));
+        (x % p, y % p)
+    }
+
+    /// `scalar * B`, computed directly, in affine coordinates.
+    pub fn multiply_base(&self, scalar: &BigUint) -> (BigUint, BigUint) {
+        let mut result = (BigUint::from(0u8), BigUint::from(1u8));
+        let mut power = self.base.clone();
+        for i in 0..scalar.bits() {
+            if scalar.bit(i) {
+                result = self.add(&result, &power);
+            }
+            power = self.add(&power, &power);
+        }
+        result
+    }
+}
+
+/// A point in affine coordinates
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Point {
+    pub x: BigNum,
+    pub y: BigNum,
+}
+
+/// A point in extended coordinates: `x = X / Z`, `y = Y / Z` and `T = X Y / Z`
+struct Extended {
+    x: BigNum,
+    y: BigNum,
+    z: BigNum,
+    t: BigNum,
+}
+
+fn to_words(value: &BigUint) -> Vec<u64> {
+    value.to_u64_digits()
+}
+
+fn from_words(words: &[u64]) -> BigUint {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    BigUint::from_bytes_le(&bytes)
+}
+
+/// Reads the bits of a scalar from the witness, least significant first. Give its value to have
+/// it recorded.
+pub fn scalar_input(sink: &mut impl GateSink, bits: usize, value: Option<&BigUint>) -> Vec<usize> {
+    (0..bits)
+        .map(|i| {
+            let wire = sink.fresh_wire(Domain::GF2);
+            sink.emit(CombineOperation::GF2(Operation::Input(wire)));
+            if let Some(value) = value {
+                sink.hint_bool(value.bit(i as u64));
+            }
+            wire
+        })
+        .collect()
+}
+
+/// `scalar * B` for the scalar with the given bits (GF2 wires, least significant first). Give
+/// the scalar's value to have the hints recorded.
+pub fn fixed_base_mul(
+    sink: &mut impl GateSink,
+    curve: &EdwardsCurve,
+    scalar: &[usize],
+    value: Option<&BigUint>,
+) -> Point {
+    let p = &curve.modulus;
+    let limbs = curve.limbs();
+    let modulus = Modulus::new(&to_words(p), limbs);
+    let k = (&curve.d << 1u8) % p;
+    let zero = constant(sink, &[0], limbs);
+    let one = constant(sink, &[1], limbs);
+
+    let mut acc = Extended {
+        x: zero.clone(),
+        y: one.clone(),
+        z: one.clone(),
+        t: zero.clone(),
+    };
+    let mut power = curve.base.clone();
+    for (i, bit) in scalar.iter().enumerate() {
+        let set = value.map(|v| v.bit(i as u64));
+        // The parts of 2^i B the addition needs, or of the identity if the bit is clear. Z is one
+        // either way.
+        let (x, y) = &power;
+        let differences = constant(sink, &to_words(&((y + p - x) % p)), limbs);
+        let sums = constant(sink, &to_words(&((y + x) % p)), limbs);
+        let products = constant(sink, &to_words(&(&k * x * y % p)), limbs);
+        let y_minus_x = select(sink, *bit, set, &differences, &one);
+        let y_plus_x = select(sink, *bit, set, &sums, &one);
+        let t_times_k = select(sink, *bit, set, &products, &zero);
+
+        let a = sub_mod(sink, &acc.y, &acc.x, &modulus);
+        let a = mul_mod(sink, &a, &y_minus_x, &modulus);
+        let b = add_mod(sink, &acc.y, &acc.x, &modulus);
+        let b = mul_mod(sink, &b, &y_plus_x, &modulus);
+        let c = mul_mod(sink, &acc.t, &t_times_k, &modulus);
+        let d = add_mod(sink, &acc.z, &acc.z, &modulus);
+        let e = sub_mod(sink, &b, &a, &modulus);
+        let f = sub_mod(sink, &d, &c, &modulus);
+        let g = add_mod(sink, &d, &c, &modulus);
+        let h = add_mod(sink, &b, &a, &modulus);
+        acc = Extended {
+            x: mul_mod(sink, &e, &f, &modulus),
+            y: mul_mod(sink, &g, &h, &modulus),
+            t: mul_mod(sink, &e, &h, &modulus),
+            z: mul_mod(sink, &f, &g, &modulus),
+        };
+
+        power = curve.add(&power, &power);
+    }
+
+    // Back to affine: the prover supplies X / Z and Y / Z
+    let inverse = acc.z.value().map(|z| curve.inverse(&from_words(&z)));
+    let divide = |sink: &mut _, numerator: &BigNum| {
+        let quotient = numerator
+            .value()
+            .zip(inverse.as_ref())
+            .map(|(n, inverse)| to_words(&(from_words(&n) * inverse % p)));
+        let quotient = input(sink, limbs, quotient.as_deref());
+        assert_reduced(sink, &quotient, &modulus);
+        let check = mul_mod(sink, &quotient, &acc.z, &modulus);
+        assert_equal(sink, &check, numerator);
+        quotient
+    };
+    Point {
+        x: divide(sink, &acc.x),
+        y: divide(sink, &acc.y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use crate::gadgets::bigint::LIMB_BITS;
+    use crate::gadgets::curve::{fixed_base_mul, from_words, scalar_input, EdwardsCurve};
+    use crate::gadgets::{evaluate_arith_outputs, WitnessRecorder};
+    use crate::{evaluate_composite_program, ProgramEditor};
+
+    fn hex(digits: &str) -> BigUint {
+        BigUint::parse_bytes(digits.as_bytes(), 16).unwrap()
+    }
+
+    fn from_limbs(limbs: &[u64]) -> BigUint {
+        limbs.iter().rev().fold(BigUint::from(0u8), |value, limb| {
+            (value << LIMB_BITS) + limb
+        })
+    }
+
+    #[test]
+    fn test_ed25519() {
+        let curve = EdwardsCurve::ed25519();
+        let five = BigUint::from(5u8);
+        let expected = (
+            hex("49fda73eade3587bfcef7cf7d12da5de5c2819f93e1be1a591409cc0322ef233"),
+            hex("5f4825b298feae6fe02c6e148992466631282eca89430b5d10d21f83d676c8ed"),
+        );
+        assert_eq!(curve.multiply_base(&five), expected);
+        assert_eq!(curve.multiply_base(&BigUint::from(1u8)), *curve.base());
+
+        let mut sink = WitnessRecorder::new(ProgramEditor::new(Vec::new()));
+        let scalar = scalar_input(&mut sink, 3, Some(&five));
+        let point = fixed_base_mul(&mut sink, &curve, &scalar, Some(&five));
+        assert_eq!(from_words(&point.x.value().unwrap()), expected.0);
+        assert_eq!(from_words(&point.y.value().unwrap()), expected.1);
+
+        let (program, _) = sink.sink.commit();
+        evaluate_composite_program(&program, &sink.bool_inputs, &sink.arith_inputs);
+        let outputs: Vec<usize> = point
+            .x
+            .limbs
+            .iter()
+            .chain(&point.y.limbs)
+            .copied()
+            .collect();
+        let limbs =
+            evaluate_arith_outputs(&program, &outputs, &sink.bool_inputs, &sink.arith_inputs);
+        let (x, y) = limbs.split_at(point.x.limbs.len());
+        assert_eq!((from_limbs(x), from_limbs(y)), expected);
+    }
+}
@@ -0,0 +1,181 @@
+//! Bit decompositions supplied by the prover, along with the constraints that make them sound.
+//! Circuits often need the bits of an arithmetic value (to compare it, or to range check it),
+//! and the IR has no gate to compute them, so the prover provides them as inputs. Leaving out one
+//! of the checks that go with them is an easy mistake that lets a dishonest prover pick any bits
+//! they like, so these gadgets emit the inputs and the checks together.
+
+use crate::gadgets::{bits, GateSink};
+use crate::{CombineOperation, Domain, Operation};
+
+fn emit(sink: &mut impl GateSink, gate: impl FnOnce(usize) -> Operation<u64>) -> usize {
+    let dst = sink.fresh_wire(Domain::Z64);
+    sink.emit(CombineOperation::Z64(gate(dst)));
+    dst
+}
+
+fn assert_zero(sink: &mut impl GateSink, wire: usize) {
+    sink.emit(CombineOperation::Z64(Operation::AssertZero(wire)));
+}
+
+fn check_width(width: usize) {
+    assert!(
+        (1..=64).contains(&width),
+        "Decompositions are 1 to 64 bits wide"
+    );
+}
+
+/// Reads the low `width` bits of the Z64 wire `value` from the witness, onto Z64 wires (least
+/// significant first), and asserts that:
+///
+/// * every bit is 0 or 1, as `b * b - b = 0`. Zero and one are the only solutions modulo 2^64.
+/// * the bits, weighted by powers of two, add up to `value`. Below 64 bits, this also asserts
+///   that `value` is less than 2^`width`.
+///
+/// Give the value to have the bits recorded as hints.
+pub fn decompose(
+    sink: &mut impl GateSink,
+    value: usize,
+    width: usize,
+    known: Option<u64>,
+) -> Vec<usize> {
+    check_width(width);
+    let bits: Vec<usize> = (0..width)
+        .map(|i| {
+            let bit = emit(sink, Operation::Input);
+            if let Some(known) = known {
+                sink.hint_arith((known >> i) & 1);
+            }
+            let square = emit(sink, |dst| Operation::Mul(dst, bit, bit));
+            let difference = emit(sink, |dst| Operation::Sub(dst, square, bit));
+            assert_zero(sink, difference);
+            bit
+        })
+        .collect();
+
+    let mut total = bits[0];
+    for (i, bit) in bits.iter().enumerate().skip(1) {
+        let term = emit(sink, |dst| Operation::MulConst(dst, *bit, 1 << i));
+        total = emit(sink, |dst| Operation::Add(dst, total, term));
+    }
+    let difference = emit(sink, |dst| Operation::Sub(dst, total, value));
+    assert_zero(sink, difference);
+    bits
+}
+
+/// Reads the low `width` bits of the Z64 wire `value` from the witness onto GF2 wires, where
+/// they're boolean by construction, and asserts that they add up to `value`: the bits sit at the
+/// bottom of a 64-bit window padded with constant zeros, which `B2A` converts back to compare.
+/// Below 64 bits, this also asserts that `value` is less than 2^`width`.
+///
+/// Returns the bit wires, least significant first. They're contiguous, so they can be converted
+/// again later without copying. Give the value to have the bits recorded as hints.
+pub fn decompose_gf2(
+    sink: &mut impl GateSink,
+    value: usize,
+    width: usize,
+    known: Option<u64>,
+) -> Vec<usize> {
+    check_width(width);
+    let low = sink.fresh_wires(Domain::GF2, 64);
+    for i in 0..64 {
+        if i < width {
+            sink.emit(CombineOperation::GF2(Operation::Input(low + i)));
+            if let Some(known) = known {
+                sink.hint_bool((known >> i) & 1 == 1);
+            }
+        } else {
+            sink.emit(CombineOperation::GF2(Operation::Const(low + i, false)));
+        }
+    }
+    let packed = sink.fresh_wire(Domain::Z64);
+    sink.emit(CombineOperation::B2A(packed, low));
+    let difference = emit(sink, |dst| Operation::Sub(dst, packed, value));
+    assert_zero(sink, difference);
+    (low..low + width).collect()
+}
+
+/// Asserts that the Z64 wire `value` isn't zero, by decomposing it onto GF2 wires (see
+/// `decompose_gf2`) and asserting that at least one bit is set. Costs 64 GF2 inputs and 63 ANDs.
+///
+/// The usual trick for fields, where the prover supplies an inverse `y` and the circuit checks
+/// `value * y = 1`, doesn't carry over: modulo 2^64 only odd values have inverses, so it would
+/// reject every nonzero even value. Give the value to have the bits recorded as hints.
+pub fn assert_nonzero(sink: &mut impl GateSink, value: usize, known: Option<u64>) {
+    let bits = decompose_gf2(sink, value, 64, known);
+    let set = bits::any(sink, &bits);
+    bits::assert_true(sink, set);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use crate::gadgets::decompose::{assert_nonzero, decompose, decompose_gf2};
+    use crate::gadgets::{evaluate_arith_outputs, GateSink, WitnessRecorder};
+    use crate::{evaluate_composite_program, CombineOperation, Domain, Operation, ProgramEditor};
+
+    /// Reads a Z64 value and decomposes it both ways.
+    fn build(value: u64, width: usize) -> (Vec<CombineOperation>, Vec<usize>, Vec<usize>) {
+        let mut sink = WitnessRecorder::new(ProgramEditor::new(Vec::new()));
+        let wire = sink.fresh_wire(Domain::Z64);
+        sink.emit(CombineOperation::Z64(Operation::Input(wire)));
+        let arith = decompose(&mut sink, wire, width, Some(value));
+        let bool = decompose_gf2(&mut sink, wire, width, Some(value));
+        (sink.sink.commit().0, arith, bool)
+    }
+
+    fn passes(program: &[CombineOperation], bool_inputs: &[bool], arith_inputs: &[u64]) -> bool {
+        catch_unwind(AssertUnwindSafe(|| {
+            evaluate_composite_program(program, bool_inputs, arith_inputs)
+        }))
+        .is_ok()
+    }
+
+    #[test]
+    fn test_decompositions() {
+        let (program, arith, bool) = build(0b1011, 4);
+        let bits = [true, true, false, true];
+        let arith_inputs = [0b1011, 1, 1, 0, 1];
+        assert!(passes(&program, &bits, &arith_inputs));
+        assert_eq!(
+            evaluate_arith_outputs(&program, &arith, &bits, &arith_inputs),
+            [1, 1, 0, 1]
+        );
+        assert_eq!(bool, (bool[0]..bool[0] + 4).collect::<Vec<_>>());
+
+        // Too big for the width
+        assert!(!passes(&program, &bits, &[0b11011, 1, 1, 0, 1]));
+        // The right total, but from a bit that isn't 0 or 1
+        assert!(!passes(&program, &bits, &[0b1011, 3, 0, 0, 1]));
+        // Wrong GF2 bits
+        assert!(!passes(
+            &program,
+            &[true, false, false, true],
+            &arith_inputs
+        ));
+
+        // At full width the decomposition is unique modulo 2^64
+        let (program, _, _) = build(u64::MAX, 64);
+        let mut arith_inputs = vec![u64::MAX];
+        arith_inputs.extend([1; 64]);
+        assert!(passes(&program, &[true; 64], &arith_inputs));
+    }
+
+    #[test]
+    fn test_assert_nonzero() {
+        for (value, accepted) in [(0, false), (1, true), (2, true), (1 << 63, true)] {
+            let mut sink = WitnessRecorder::new(ProgramEditor::new(Vec::new()));
+            let wire = sink.fresh_wire(Domain::Z64);
+            sink.emit(CombineOperation::Z64(Operation::Input(wire)));
+            sink.arith_inputs.push(value);
+            assert_nonzero(&mut sink, wire, Some(value));
+            let program = sink.sink.commit().0;
+            assert_eq!(
+                passes(&program, &sink.bool_inputs, &sink.arith_inputs),
+                accepted,
+                "{}",
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Z64 addition and subtraction with the flags a CPU sets alongside the result, for circuits that
+//! model instruction sets. Z64 gates wrap silently, so the flags come from a GF2 adder over bit
+//! decompositions of the operands (see `decompose`), while the result itself is a plain Z64 gate.
+
+use crate::gadgets::bits;
+use crate::gadgets::decompose::decompose_gf2;
+use crate::gadgets::GateSink;
+use crate::{CombineOperation, Domain, Operation};
+
+/// The result of a flagged operation. Flags are GF2 wires; `to_z64` turns one into a 0/1 Z64
+/// wire where that's more convenient.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Flagged {
+    /// Z64 wire holding the wrapped result
+    pub result: usize,
+    /// Unsigned carry out of an addition, or borrow out of a subtraction (`a < b` unsigned)
+    pub carry: usize,
+    /// Signed overflow: the result has the wrong sign for the operands, read as two's complement
+    pub overflow: usize,
+    /// The result is zero
+    pub zero: usize,
+    /// The result's top bit
+    pub negative: usize,
+}
+
+fn z64(sink: &mut impl GateSink, gate: impl FnOnce(usize) -> Operation<u64>) -> usize {
+    let dst = sink.fresh_wire(Domain::Z64);
+    sink.emit(CombineOperation::Z64(gate(dst)));
+    dst
+}
+
+/// Decomposes both operands and finishes off the flags from the GF2 result bits.
+fn flagged(
+    sink: &mut impl GateSink,
+    a: usize,
+    b: usize,
+    known: Option<(u64, u64)>,
+    subtract: bool,
+) -> Flagged {
+    let a_bits = decompose_gf2(sink, a, 64, known.map(|(a, _)| a));
+    let b_bits = decompose_gf2(sink, b, 64, known.map(|(_, b)| b));
+    let (bits, carry) = if subtract {
+        bits::sub(sink, &a_bits, &b_bits)
+    } else {
+        bits::add(sink, &a_bits, &b_bits)
+    };
+    let result = if subtract {
+        z64(sink, |dst| Operation::Sub(dst, a, b))
+    } else {
+        z64(sink, |dst| Operation::Add(dst, a, b))
+    };
+
+    // Adding: the operands share a sign the result doesn't have. Subtracting: the operands'
+    // signs differ, and the result's differs from the first one's.
+    let (a_sign, b_sign, sign) = (a_bits[63], b_bits[63], bits[63]);
+    let flipped = bits::xor(sink, a_sign, sign);
+    let other = if subtract {
+        bits::xor(sink, a_sign, b_sign)
+    } else {
+        bits::xor(sink, b_sign, sign)
+    };
+    let overflow = bits::and(sink, flipped, other);
+    let zero = bits::is_zero(sink, &bits);
+
+    Flagged {
+        result,
+        carry,
+        overflow,
+        zero,
+        negative: sign,
+    }
+}
+
+/// `a + b` on Z64 wires, with its flags. Give the operands' values to have the bit decompositions
+/// recorded as hints.
+pub fn add_with_flags(
+    sink: &mut impl GateSink,
+    a: usize,
+    b: usize,
+    known: Option<(u64, u64)>,
+) -> Flagged {
+    flagged(sink, a, b, known, false)
+}
+
+/// `a - b` on Z64 wires, with its flags. `carry` is the borrow.
+pub fn sub_with_flags(
+    sink: &mut impl GateSink,
+    a: usize,
+    b: usize,
+    known: Option<(u64, u64)>,
+) -> Flagged {
+    flagged(sink, a, b, known, true)
+}
+
+/// Converts a GF2 flag to a Z64 wire holding 0 or 1.
+pub fn to_z64(sink: &mut impl GateSink, flag: usize) -> usize {
+    let low = sink.fresh_wires(Domain::GF2, 64);
+    sink.emit(CombineOperation::GF2(Operation::AddConst(low, flag, false)));
+    for i in 1..64 {
+        sink.emit(CombineOperation::GF2(Operation::Const(low + i, false)));
+    }
+    let dst = sink.fresh_wire(Domain::Z64);
+    sink.emit(CombineOperation::B2A(dst, low));
+    dst
+}
+
+/// `a + b` on Z64 wires, clamped to `u64::MAX` instead of wrapping. Returns the result and the
+/// carry, which says whether it was clamped.
+pub fn saturating_add(
+    sink: &mut impl GateSink,
+    a: usize,
+    b: usize,
+    known: Option<(u64, u64)>,
+) -> (usize, usize) {
+    let flags = add_with_flags(sink, a, b, known);
+    // result + carry * (MAX - result), computed as result - carry * result + carry * MAX
+    let carry = to_z64(sink, flags.carry);
+    let scaled = z64(sink, |dst| Operation::Mul(dst, carry, flags.result));
+    let kept = z64(sink, |dst| Operation::Sub(dst, flags.result, scaled));
+    let max = z64(sink, |dst| Operation::MulConst(dst, carry, u64::MAX));
+    let result = z64(sink, |dst| Operation::Add(dst, kept, max));
+    (result, flags.carry)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gadgets::flags::{add_with_flags, saturating_add, sub_with_flags, to_z64};
+    use crate::gadgets::WitnessRecorder;
+    use crate::{
+        evaluate_composite_program_checked, CircuitBuilder, EvalConfig, EvaluationError, Wire,
+    };
+
+    /// Builds `a op b` over inputs `a` and `b`, and returns the result and its four flags as Z64
+    /// values.
+    fn run(a: u64, b: u64, subtract: bool) -> (u64, [u64; 4]) {
+        let mut sink = WitnessRecorder::new(CircuitBuilder::new());
+        let inputs: Vec<Wire<u64>> = sink.sink.inputs(2);
+        sink.arith_inputs.extend([a, b]);
+        let (x, y) = (inputs[0].index, inputs[1].index);
+        let flags = if subtract {
+            sub_with_flags(&mut sink, x, y, Some((a, b)))
+        } else {
+            add_with_flags(&mut sink, x, y, Some((a, b)))
+        };
+        let outputs: Vec<usize> = [flags.carry, flags.overflow, flags.zero, flags.negative]
+            .iter()
+            .map(|flag| to_z64(&mut sink, *flag))
+            .collect();
+
+        let output = evaluate_composite_program_checked(
+            &sink.sink.finish(),
+            &sink.bool_inputs,
+            &sink.arith_inputs,
+            &[],
+            &[vec![flags.result], outputs].concat(),
+            EvalConfig::default(),
+        )
+        .unwrap();
+        let values = output.arith_outputs;
+        (values[0], [values[1], values[2], values[3], values[4]])
+    }
+
+    #[test]
+    fn test_flags() {
+        for (a, b) in [
+            (1, 2),
+            (u64::MAX, 1),
+            (i64::MAX as u64, 1),
+            (i64::MIN as u64, i64::MIN as u64),
+            (0, 0),
+            (5, 7),
+        ] {
+            let (sum, carry) = a.overflowing_add(b);
+            let overflow = (a as i64).overflowing_add(b as i64).1;
+            let expected = [carry, overflow, sum == 0, (sum as i64) < 0].map(u64::from);
+            assert_eq!(run(a, b, false), (sum, expected), "{} + {}", a, b);
+
+            let (difference, borrow) = a.overflowing_sub(b);
+            let overflow = (a as i64).overflowing_sub(b as i64).1;
+            let expected =
+                [borrow, overflow, difference == 0, (difference as i64) < 0].map(u64::from);
+            assert_eq!(run(a, b, true), (difference, expected), "{} - {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_saturating_add() {
+        let build = |a: u64, b: u64| {
+            let mut sink = WitnessRecorder::new(CircuitBuilder::new());
+            let inputs: Vec<Wire<u64>> = sink.sink.inputs(2);
+            sink.arith_inputs.extend([a, b]);
+            let (result, _) =
+                saturating_add(&mut sink, inputs[0].index, inputs[1].index, Some((a, b)));
+            (sink, result)
+        };
+        for (a, b) in [(3, 4), (u64::MAX - 1, 5)] {
+            let (sink, result) = build(a, b);
+            let output = evaluate_composite_program_checked(
+                &sink.sink.finish(),
+                &sink.bool_inputs,
+                &sink.arith_inputs,
+                &[],
+                &[result],
+                EvalConfig::default(),
+            )
+            .unwrap();
+            assert_eq!(output.arith_outputs, [a.saturating_add(b)]);
+        }
+
+        // Lying about the operands' bits breaks the decomposition's check
+        let (mut sink, _) = build(1, 2);
+        sink.bool_inputs[0] = false;
+        let gates = sink.sink.finish();
+        let error = evaluate_composite_program_checked(
+            &gates,
+            &sink.bool_inputs,
+            &sink.arith_inputs,
+            &[],
+            &[],
+            EvalConfig::default(),
+        );
+        assert!(matches!(
+            error,
+            Err(EvaluationError::AssertionFailed { .. })
+        ));
+    }
+}
@@ -0,0 +1,489 @@
+//! IEEE-754 single precision arithmetic on 32-bit GF2 words, laid out as in memory: fraction in
+//! bits 0-22, biased exponent in bits 23-30 and the sign in bit 31.
+//!
+//! Results are rounded to nearest, ties to even, as the default IEEE rounding mode does. To keep
+//! the circuits small, these gadgets depart from IEEE in two documented ways:
+//!
+//! * Subnormals are flushed to zero. Subnormal inputs are read as zero (keeping their sign), and
+//!   results are rounded as if the exponent range were unbounded and then replaced by a zero of
+//!   the same sign if their exponent is below the normal range. Most hardware offers the same
+//!   behavior as "flush to zero" and "denormals are zero" modes.
+//! * Every NaN result is the canonical quiet NaN `0x7fc00000`, whatever NaN payloads went in.
+//!
+//! Otherwise results match IEEE exactly: overflow gives infinity, `inf - inf` and `0 * inf` give
+//! NaN, and exact zero sums are positive unless both operands were negative zeros.
+
+use crate::gadgets::bits::{
+    add, add_with_carry, all, and, any, constant, constant_word, increment, is_zero, less_than,
+    multiply, mux, mux_word, normalize, not, not_word, or, shift_right_sticky, sub, xor, xor_word,
+};
+use crate::gadgets::GateSink;
+
+const FRACTION_BITS: usize = 23;
+const EXPONENT_BITS: usize = 8;
+const WIDTH: usize = 32;
+const BIAS: u64 = 127;
+/// Width used for exponent arithmetic, wide enough that intermediate exponents can't wrap.
+/// Exponents are two's complement at this width.
+const EXPONENT_WORK_BITS: usize = 10;
+const CANONICAL_NAN: u64 = 0x7fc0_0000;
+
+/// The parts of an input, with subnormals already flushed to zero.
+struct Unpacked {
+    sign: usize,
+    /// Biased exponent
+    exponent: Vec<usize>,
+    /// Fraction bits, zeroed for subnormals
+    fraction: Vec<usize>,
+    /// Zero or subnormal
+    is_zero: usize,
+    is_infinite: usize,
+    is_nan: usize,
+}
+
+fn unpack(sink: &mut impl GateSink, value: &[usize]) -> Unpacked {
+    assert_eq!(
+        value.len(),
+        WIDTH,
+        "Single precision floats are 32 bits wide"
+    );
+    let fraction = &value[..FRACTION_BITS];
+    let exponent = value[FRACTION_BITS..WIDTH - 1].to_vec();
+
+    let is_zero = is_zero(sink, &exponent);
+    let normal = not(sink, is_zero);
+    let exponent_max = all(sink, &exponent);
+    let fraction_set = any(sink, fraction);
+    let fraction_clear = not(sink, fraction_set);
+
+    Unpacked {
+        sign: value[WIDTH - 1],
+        fraction: fraction.iter().map(|bit| and(sink, *bit, normal)).collect(),
+        is_infinite: and(sink, exponent_max, fraction_clear),
+        is_nan: and(sink, exponent_max, fraction_set),
+        exponent,
+        is_zero,
+    }
+}
+
+/// Widens an unsigned word to the exponent working width.
+fn widen(sink: &mut impl GateSink, word: &[usize]) -> Vec<usize> {
+    let zero = constant(sink, false);
+    let mut wide = word.to_vec();
+    wide.resize(EXPONENT_WORK_BITS, zero);
+    wide
+}
+
+/// Rounds a 24-bit significand (with its leading one) to nearest, ties to even, given the first
+/// bit below it and whether any bits below that were set. Returns the rounded significand and
+/// its exponent, which goes up by one if rounding carried out of the significand.
+fn round(
+    sink: &mut impl GateSink,
+    significand: &[usize],
+    exponent: &[usize],
+    round_bit: usize,
+    sticky: usize,
+) -> (Vec<usize>, Vec<usize>) {
+    let odd_or_above_half = or(sink, sticky, significand[0]);
+    let up = and(sink, round_bit, odd_or_above_half);
+    let (mut rounded, carry) = increment(sink, significand, up);
+    // Carrying out leaves every bit clear, and the significand is 1.0 again at the next exponent
+    let top = rounded.len() - 1;
+    rounded[top] = or(sink, rounded[top], carry);
+    let (exponent, _) = increment(sink, exponent, carry);
+    (rounded, exponent)
+}
+
+/// Whether a (two's complement) working exponent is too large or too small for a normal number.
+fn out_of_range(sink: &mut impl GateSink, exponent: &[usize]) -> (usize, usize) {
+    let negative = exponent[EXPONENT_WORK_BITS - 1];
+    let positive = not(sink, negative);
+    // Non-negative values of at least 255 have bit 8 set or all of bits 0-7 set
+    let saturated = all(sink, &exponent[..EXPONENT_BITS]);
+    let large = or(sink, exponent[EXPONENT_BITS], saturated);
+    let overflow = and(sink, positive, large);
+
+    let zero = is_zero(sink, exponent);
+    let underflow = or(sink, negative, zero);
+    (overflow, underflow)
+}
+
+/// Puts together the final result, in order of precedence: NaN, infinity, zero, or the finite
+/// number given by `sign`, `exponent` and the significand (whose leading one is dropped).
+#[allow(clippy::too_many_arguments)]
+fn pack(
+    sink: &mut impl GateSink,
+    sign: usize,
+    exponent: &[usize],
+    significand: &[usize],
+    zero_sign: usize,
+    is_nan: usize,
+    is_infinite: usize,
+    is_zero: usize,
+) -> Vec<usize> {
+    let mut finite: Vec<usize> = significand[..FRACTION_BITS].to_vec();
+    finite.extend(&exponent[..EXPONENT_BITS]);
+    finite.push(sign);
+
+    let mut zero = constant_word(sink, 0, WIDTH - 1);
+    zero.push(zero_sign);
+    let mut infinity = constant_word(sink, 0xff << FRACTION_BITS, WIDTH - 1);
+    infinity.push(sign);
+    let nan = constant_word(sink, CANONICAL_NAN, WIDTH);
+
+    let result = mux_word(sink, is_zero, &zero, &finite);
+    let result = mux_word(sink, is_infinite, &infinity, &result);
+    mux_word(sink, is_nan, &nan, &result)
+}
+
+/// A magnitude's significand, with its leading one (unless it's zero), three bits of room below
+/// for rounding, and one above for a carry.
+fn wide_significand(sink: &mut impl GateSink, magnitude: &[usize]) -> Vec<usize> {
+    let zero = constant(sink, false);
+    let mut bits = vec![zero; 3];
+    bits.extend(&magnitude[..FRACTION_BITS]);
+    bits.push(any(sink, &magnitude[FRACTION_BITS..]));
+    bits.push(zero);
+    bits
+}
+
+/// `a + b`.
+pub fn add_f32(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> Vec<usize> {
+    let ua = unpack(sink, a);
+    let ub = unpack(sink, b);
+
+    // Order the operands by magnitude, so x is at least as large as y
+    let mut magnitude_a = ua.fraction.clone();
+    magnitude_a.extend(&ua.exponent);
+    let mut magnitude_b = ub.fraction.clone();
+    magnitude_b.extend(&ub.exponent);
+    let swap = less_than(sink, &magnitude_a, &magnitude_b);
+    let x = mux_word(sink, swap, &magnitude_b, &magnitude_a);
+    let y = mux_word(sink, swap, &magnitude_a, &magnitude_b);
+    let sign_x = mux(sink, swap, ub.sign, ua.sign);
+    let sign_y = mux(sink, swap, ua.sign, ub.sign);
+    let subtract = xor(sink, sign_x, sign_y);
+    let (exponent_x, exponent_y) = (&x[FRACTION_BITS..], &y[FRACTION_BITS..]);
+
+    let significand_x = wide_significand(sink, &x);
+    let significand_y = wide_significand(sink, &y);
+
+    // Line y up with x, folding anything shifted out into the lowest bit
+    let (distance, _) = sub(sink, exponent_x, exponent_y);
+    let (mut aligned, sticky) = shift_right_sticky(sink, &significand_y, &distance);
+    aligned[0] = or(sink, aligned[0], sticky);
+
+    // x - y is x + !y + 1
+    let subtrahend: Vec<usize> = aligned
+        .iter()
+        .map(|bit| xor(sink, *bit, subtract))
+        .collect();
+    let (total, _) = add_with_carry(sink, &significand_x, &subtrahend, subtract);
+    let exact_zero = is_zero(sink, &total);
+
+    // The sum's leading one ends up in the top bit, one place above where x's was
+    let (normalized, shift) = normalize(sink, &total);
+    let significand = &normalized[4..];
+    let round_bit = normalized[3];
+    let sticky = any(sink, &normalized[..3]);
+
+    // exponent = x's exponent + 1 - shift
+    let exponent_x = widen(sink, exponent_x);
+    let shift = widen(sink, &shift);
+    let shift = not_word(sink, &shift);
+    let one = constant(sink, true);
+    let (exponent, _) = add_with_carry(sink, &exponent_x, &shift, one);
+    let (exponent, _) = increment(sink, &exponent, one);
+
+    let (significand, exponent) = round(sink, significand, &exponent, round_bit, sticky);
+    let (overflow, underflow) = out_of_range(sink, &exponent);
+
+    let both_infinite = and(sink, ua.is_infinite, ub.is_infinite);
+    let opposite_infinities = and(sink, both_infinite, subtract);
+    let nan_input = or(sink, ua.is_nan, ub.is_nan);
+    let is_nan = or(sink, nan_input, opposite_infinities);
+    let infinite_input = or(sink, ua.is_infinite, ub.is_infinite);
+    let is_infinite = or(sink, infinite_input, overflow);
+    let is_zero = or(sink, exact_zero, underflow);
+
+    // Exact zeros are only negative if both operands were; tiny results keep their sign
+    let same_sign = not(sink, subtract);
+    let negative_zero = and(sink, sign_x, same_sign);
+    let zero_sign = mux(sink, exact_zero, negative_zero, sign_x);
+
+    pack(
+        sink,
+        sign_x,
+        &exponent,
+        &significand,
+        zero_sign,
+        is_nan,
+        is_infinite,
+        is_zero,
+    )
+}
+
+/// `a - b`, computed as `a + -b`.
+pub fn sub_f32(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> Vec<usize> {
+    assert_eq!(b.len(), WIDTH, "Single precision floats are 32 bits wide");
+    let mut negated = b.to_vec();
+    negated[WIDTH - 1] = not(sink, b[WIDTH - 1]);
+    add_f32(sink, a, &negated)
+}
+
+/// `a * b`.
+pub fn mul_f32(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> Vec<usize> {
+    let ua = unpack(sink, a);
+    let ub = unpack(sink, b);
+    let sign = xor(sink, ua.sign, ub.sign);
+
+    let one = constant(sink, true);
+    let mut significand_a = ua.fraction.clone();
+    significand_a.push(one);
+    let mut significand_b = ub.fraction.clone();
+    significand_b.push(one);
+    // Between 2^46 and 2^48
+    let product = multiply(sink, &significand_a, &significand_b);
+
+    // Shift the product so its leading one is in the top bit
+    let top = product[product.len() - 1];
+    let zero = constant(sink, false);
+    let mut doubled = vec![zero];
+    doubled.extend(&product[..product.len() - 1]);
+    let normalized = mux_word(sink, top, &product, &doubled);
+    let significand = &normalized[FRACTION_BITS + 1..];
+    let round_bit = normalized[FRACTION_BITS];
+    let sticky = any(sink, &normalized[..FRACTION_BITS]);
+
+    // exponent = a's exponent + b's exponent - bias (+ 1 if the product was already normalized)
+    let exponent_a = widen(sink, &ua.exponent);
+    let exponent_b = widen(sink, &ub.exponent);
+    let (exponent, _) = add_with_carry(sink, &exponent_a, &exponent_b, top);
+    let minus_bias = constant_word(sink, (1 << EXPONENT_WORK_BITS) - BIAS, EXPONENT_WORK_BITS);
+    let (exponent, _) = add(sink, &exponent, &minus_bias);
+
+    let (significand, exponent) = round(sink, significand, &exponent, round_bit, sticky);
+    let (overflow, underflow) = out_of_range(sink, &exponent);
+
+    let zero_input = or(sink, ua.is_zero, ub.is_zero);
+    let infinite_input = or(sink, ua.is_infinite, ub.is_infinite);
+    let zero_times_infinity = and(sink, zero_input, infinite_input);
+    let nan_input = or(sink, ua.is_nan, ub.is_nan);
+    let is_nan = or(sink, nan_input, zero_times_infinity);
+    let is_infinite = or(sink, infinite_input, overflow);
+    let is_zero = or(sink, zero_input, underflow);
+
+    pack(
+        sink,
+        sign,
+        &exponent,
+        &significand,
+        sign,
+        is_nan,
+        is_infinite,
+        is_zero,
+    )
+}
+
+/// Results shared by the comparisons: whether either operand is NaN, whether both are zero, and
+/// whether `a < b` for ordered, not-both-zero operands.
+fn compare(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> (usize, usize, usize, usize) {
+    let ua = unpack(sink, a);
+    let ub = unpack(sink, b);
+    let unordered = or(sink, ua.is_nan, ub.is_nan);
+    let both_zero = and(sink, ua.is_zero, ub.is_zero);
+
+    let mut magnitude_a = ua.fraction.clone();
+    magnitude_a.extend(&ua.exponent);
+    let mut magnitude_b = ub.fraction.clone();
+    magnitude_b.extend(&ub.exponent);
+    let smaller = less_than(sink, &magnitude_a, &magnitude_b);
+    let larger = less_than(sink, &magnitude_b, &magnitude_a);
+
+    // With different signs, a is smaller exactly when it's the negative one. With the same sign,
+    // compare magnitudes, flipping the comparison for negative numbers.
+    let same_sign_less = mux(sink, ua.sign, larger, smaller);
+    let different_signs = xor(sink, ua.sign, ub.sign);
+    let less = mux(sink, different_signs, ua.sign, same_sign_less);
+
+    let difference = xor_word(sink, &magnitude_a, &magnitude_b);
+    let same_bits = is_zero(sink, &difference);
+    let same_sign = not(sink, different_signs);
+    let identical = and(sink, same_bits, same_sign);
+    let equal = or(sink, identical, both_zero);
+
+    (unordered, both_zero, less, equal)
+}
+
+/// `a < b`. False if either is NaN, and zeros of either sign are equal.
+pub fn less_than_f32(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> usize {
+    let (unordered, both_zero, less, _) = compare(sink, a, b);
+    let excluded = or(sink, unordered, both_zero);
+    let ordered = not(sink, excluded);
+    and(sink, ordered, less)
+}
+
+/// `a <= b`. False if either is NaN, and zeros of either sign are equal.
+pub fn less_equal_f32(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> usize {
+    let (unordered, both_zero, less, equal) = compare(sink, a, b);
+    let strictly = {
+        let nonzero = not(sink, both_zero);
+        and(sink, less, nonzero)
+    };
+    let either = or(sink, strictly, equal);
+    let ordered = not(sink, unordered);
+    and(sink, ordered, either)
+}
+
+/// `a == b`. False if either is NaN, and zeros of either sign are equal.
+pub fn equal_f32(sink: &mut impl GateSink, a: &[usize], b: &[usize]) -> usize {
+    let (unordered, _, _, equal) = compare(sink, a, b);
+    let ordered = not(sink, unordered);
+    and(sink, ordered, equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::gadgets::bits::input;
+    use crate::gadgets::evaluate_outputs;
+    use crate::gadgets::float::{
+        add_f32, equal_f32, less_equal_f32, less_than_f32, mul_f32, sub_f32, CANONICAL_NAN,
+    };
+    use crate::{CombineOperation, ProgramEditor};
+
+    /// A circuit computing every operation on two inputs, and the wires of each result.
+    struct Harness {
+        program: Vec<CombineOperation>,
+        outputs: Vec<usize>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let mut editor = ProgramEditor::new(Vec::new());
+            let a = input(&mut editor, 32);
+            let b = input(&mut editor, 32);
+            let mut outputs = add_f32(&mut editor, &a, &b);
+            outputs.extend(sub_f32(&mut editor, &a, &b));
+            outputs.extend(mul_f32(&mut editor, &a, &b));
+            outputs.push(less_than_f32(&mut editor, &a, &b));
+            outputs.push(less_equal_f32(&mut editor, &a, &b));
+            outputs.push(equal_f32(&mut editor, &a, &b));
+            let (program, _) = editor.commit();
+            Harness { program, outputs }
+        }
+
+        /// Returns (a + b, a - b, a * b, a < b, a <= b, a == b).
+        fn run(&self, a: u32, b: u32) -> (u32, u32, u32, bool, bool, bool) {
+            let witness: Vec<bool> = (0..64)
+                .map(|i| ((u64::from(b) << 32 | u64::from(a)) >> i) & 1 == 1)
+                .collect();
+            let out = evaluate_outputs(&self.program, &self.outputs, &witness);
+            let word = |bits: &[bool]| {
+                bits.iter()
+                    .enumerate()
+                    .map(|(i, b)| u32::from(*b) << i)
+                    .sum::<u32>()
+            };
+            (
+                word(&out[..32]),
+                word(&out[32..64]),
+                word(&out[64..96]),
+                out[96],
+                out[97],
+                out[98],
+            )
+        }
+    }
+
+    /// What the gadgets should give, from the native result: flushed to zero if it's subnormal,
+    /// and with NaNs made canonical.
+    fn expected(result: f32) -> u32 {
+        if result.is_nan() {
+            CANONICAL_NAN as u32
+        } else if result.is_subnormal() {
+            result.to_bits() & 0x8000_0000
+        } else {
+            result.to_bits()
+        }
+    }
+
+    /// Reads subnormal inputs as zero, as the gadgets do.
+    fn flushed(bits: u32) -> f32 {
+        let value = f32::from_bits(bits);
+        if value.is_subnormal() {
+            f32::from_bits(bits & 0x8000_0000)
+        } else {
+            value
+        }
+    }
+
+    fn check(harness: &Harness, a: u32, b: u32) {
+        let (x, y) = (flushed(a), flushed(b));
+        let result = harness.run(a, b);
+        let context = format!("{:#010x} ({}) and {:#010x} ({})", a, x, b, y);
+        assert_eq!(result.0, expected(x + y), "adding {}", context);
+        assert_eq!(result.1, expected(x - y), "subtracting {}", context);
+        assert_eq!(result.2, expected(x * y), "multiplying {}", context);
+        assert_eq!(result.3, x < y, "comparing {}", context);
+        assert_eq!(result.4, x <= y, "comparing {}", context);
+        assert_eq!(result.5, x == y, "comparing {}", context);
+    }
+
+    #[test]
+    fn test_known_answers() {
+        let harness = Harness::new();
+        assert_eq!(harness.run(0x3f80_0000, 0x4000_0000).0, 0x4040_0000); // 1 + 2 = 3
+        assert_eq!(harness.run(0x3dcc_cccd, 0x3e4c_cccd).0, 0x3e99_999a); // 0.1 + 0.2
+        assert_eq!(harness.run(0x3dcc_cccd, 0x3dcc_cccd).2, 0x3c23_d70b); // 0.1 * 0.1
+
+        let specials = [
+            0x0000_0000, // +0
+            0x8000_0000, // -0
+            0x0000_0001, // smallest subnormal
+            0x807f_ffff, // largest negative subnormal
+            0x0080_0000, // smallest normal
+            0x3f80_0000, // 1
+            0xbf80_0000, // -1
+            0x3f80_0001, // 1 + ulp
+            0x4b80_0000, // 2^24, where the ulp becomes 2
+            0x7f7f_ffff, // largest finite
+            0xff7f_ffff,
+            0x7f80_0000, // +inf
+            0xff80_0000, // -inf
+            0x7fc0_0000, // quiet NaN
+            0xffa0_0001, // signalling NaN with a payload
+            0x3380_0000, // 2^-24, half an ulp of 1
+            0x3400_0000, // 2^-23, an ulp of 1
+        ];
+        for a in specials {
+            for b in specials {
+                check(&harness, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_operands() {
+        let harness = Harness::new();
+        let mut rng = StdRng::seed_from_u64(3475);
+        for i in 0..300 {
+            let (a, b) = if i % 3 == 0 {
+                // Anything at all, including NaNs and overflow
+                (rng.gen(), rng.gen())
+            } else {
+                // Nearby exponents, where cancellation and rounding get interesting
+                let exponent: u32 = rng.gen_range(100..150);
+                let near = |rng: &mut StdRng| {
+                    let sign: u32 = rng.gen_range(0..2);
+                    let offset: u32 = rng.gen_range(0..4);
+                    (sign << 31) | ((exponent + offset) << 23) | rng.gen_range(0..1 << 23)
+                };
+                (near(&mut rng), near(&mut rng))
+            };
+            check(&harness, a, b);
+        }
+    }
+}
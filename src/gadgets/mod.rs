@@ -0,0 +1,137 @@
+//! Circuits for common operations ("gadgets"), generated into anything that can allocate wires and
+//! take gates. Gadgets work on words: slices of wires holding one bit each, least significant
+//! first.
+//!
+//! * `bits` has the basic boolean operations over GF2 words (adders, comparisons, shifts, ...)
+//! * `float` has IEEE-754 single precision arithmetic built from them
+//! * `decompose` has bit decompositions of Z64 values, checked the way they should be
+//! * `flags` has Z64 addition and subtraction with carry, overflow, zero and sign flags
+//! * `bigint` has multi-limb integer and modular arithmetic over Z64 wires
+//! * `rom` has read-only memories, initialized from an image of their contents
+//! * `curve` has elliptic curve scalar multiplication, with the `curves` feature
+//!
+//! Some gadgets need the prover to supply values the circuit can check but not compute, like the
+//! quotient of a division. They read these "hints" with `Input` gates, and when they're given the
+//! values of their operands they work out the hints too, reporting each one to the sink as they
+//! emit its `Input`. Wrap a sink in a `WitnessRecorder` to collect them into a witness.
+
+use crate::{CombineOperation, Domain, ProgramEditor};
+
+pub mod bigint;
+pub mod bits;
+#[cfg(feature = "curves")]
+pub mod curve;
+pub mod decompose;
+pub mod flags;
+pub mod float;
+pub mod rom;
+
+/// Somewhere gadgets can put the circuits they generate.
+pub trait GateSink {
+    /// Allocates `count` contiguous unused wires and returns the lowest.
+    fn fresh_wires(&mut self, domain: Domain, count: usize) -> usize;
+
+    /// Adds a gate to the end of the circuit.
+    fn emit(&mut self, gate: CombineOperation);
+
+    fn fresh_wire(&mut self, domain: Domain) -> usize {
+        self.fresh_wires(domain, 1)
+    }
+
+    /// Called with the witness value of the GF2 `Input` gate just emitted, when the gadget knows
+    /// it. Sinks that only build circuits can ignore it.
+    fn hint_bool(&mut self, _value: bool) {}
+
+    /// Called with the witness value of the Z64 `Input` gate just emitted, when the gadget knows
+    /// it.
+    fn hint_arith(&mut self, _value: u64) {}
+}
+
+impl GateSink for ProgramEditor {
+    fn fresh_wires(&mut self, domain: Domain, count: usize) -> usize {
+        ProgramEditor::fresh_wires(self, domain, count)
+    }
+
+    fn emit(&mut self, gate: CombineOperation) {
+        self.append(gate)
+    }
+}
+
+/// Passes gates through to another sink, collecting the witness values gadgets report as they
+/// go. The witness is only complete if every `Input` came from a gadget that knew its value.
+#[derive(Clone, Debug, Default)]
+pub struct WitnessRecorder<S> {
+    pub sink: S,
+    pub bool_inputs: Vec<bool>,
+    pub arith_inputs: Vec<u64>,
+}
+
+impl<S: GateSink> WitnessRecorder<S> {
+    pub fn new(sink: S) -> Self {
+        WitnessRecorder {
+            sink,
+            bool_inputs: Vec::new(),
+            arith_inputs: Vec::new(),
+        }
+    }
+}
+
+impl<S: GateSink> GateSink for WitnessRecorder<S> {
+    fn fresh_wires(&mut self, domain: Domain, count: usize) -> usize {
+        self.sink.fresh_wires(domain, count)
+    }
+
+    fn emit(&mut self, gate: CombineOperation) {
+        self.sink.emit(gate)
+    }
+
+    fn hint_bool(&mut self, value: bool) {
+        self.bool_inputs.push(value)
+    }
+
+    fn hint_arith(&mut self, value: u64) {
+        self.arith_inputs.push(value)
+    }
+}
+
+/// Evaluates a generated circuit and returns the values of `outputs`.
+#[cfg(test)]
+pub(crate) fn evaluate_outputs(
+    program: &[CombineOperation],
+    outputs: &[usize],
+    bool_inputs: &[bool],
+) -> Vec<bool> {
+    use crate::{evaluate_prefix, Operation};
+
+    // Reading the outputs afterwards makes the evaluator hand back their values
+    let mut program = program.to_vec();
+    let end = program.len();
+    program.extend(
+        outputs
+            .iter()
+            .map(|w| CombineOperation::GF2(Operation::AssertZero(*w))),
+    );
+    let state = evaluate_prefix(&program, end, bool_inputs, &[]).unwrap();
+    outputs.iter().map(|w| state.bool_wires[w]).collect()
+}
+
+/// Evaluates a generated circuit and returns the values of the Z64 wires in `outputs`.
+#[cfg(test)]
+pub(crate) fn evaluate_arith_outputs(
+    program: &[CombineOperation],
+    outputs: &[usize],
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+) -> Vec<u64> {
+    use crate::{evaluate_prefix, Operation};
+
+    let mut program = program.to_vec();
+    let end = program.len();
+    program.extend(
+        outputs
+            .iter()
+            .map(|w| CombineOperation::Z64(Operation::AssertZero(*w))),
+    );
+    let state = evaluate_prefix(&program, end, bool_inputs, arith_inputs).unwrap();
+    outputs.iter().map(|w| state.arith_wires[w]).collect()
+}
@@ -0,0 +1,375 @@
+//! Read-only memories: a table of constant words baked into the circuit, read at an address the
+//! circuit computes, like the program ROM of a CPU being verified.
+//!
+//! A read decodes the address into one line per word, set only for the word being read, then
+//! computes each bit of the result as the XOR of the lines of the words that have that bit set.
+//! Decoding costs about two ANDs per word, shared by every bit of the result, so wide words cost
+//! no more ANDs than narrow ones; the XORs are free. Lines are only built for addresses inside the
+//! image, and reading any other address gives zero.
+
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::gadgets::bits::{and, constant, not, xor};
+use crate::gadgets::GateSink;
+use crate::Domain;
+
+/// Why a ROM image couldn't be made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RomError {
+    /// Words have to be between 1 and 64 bits wide
+    Width(usize),
+    /// The word at `address` doesn't fit in the image's width
+    TooWide {
+        address: usize,
+        value: u64,
+        width: usize,
+    },
+    /// A token in a `$readmemh` file that isn't a hex number or an `@` address
+    Parse { line: usize, token: String },
+    /// An address in a `$readmemh` file at or past `Rom::MAX_WORDS`
+    AddressTooLarge { line: usize, address: u64 },
+    /// An address bus with fewer bits than it takes to address every word
+    AddressTooNarrow { bits: usize, needed: usize },
+    /// An address bus that isn't GF2
+    AddressDomain { bus: String, domain: Domain },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Width(width) => {
+                write!(f, "ROM words can't be {} bits wide (1 to 64 only)", width)
+            }
+            RomError::TooWide {
+                address,
+                value,
+                width,
+            } => write!(
+                f,
+                "word {:#x} at address {:#x} doesn't fit in {} bits",
+                value, address, width
+            ),
+            RomError::Parse { line, token } => {
+                write!(f, "line {}: {:?} is not a hex word or address", line, token)
+            }
+            RomError::AddressTooLarge { line, address } => write!(
+                f,
+                "line {}: address {:#x} is past the largest ROM this reads ({:#x} words)",
+                line,
+                address,
+                Rom::MAX_WORDS
+            ),
+            RomError::AddressTooNarrow { bits, needed } => write!(
+                f,
+                "a {}-bit address can't reach every word of a ROM that needs {} bits",
+                bits, needed
+            ),
+            RomError::AddressDomain { bus, domain } => {
+                write!(f, "ROM address {} is a {:?} bus, not GF2", bus, domain)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+/// The contents of a ROM: words of `width` bits, starting from address zero.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rom {
+    words: Vec<u64>,
+    width: usize,
+}
+
+fn parse_hex(token: &str) -> Option<u64> {
+    let digits: String = token.chars().filter(|c| *c != '_').collect();
+    if digits.is_empty() {
+        return None;
+    }
+    u64::from_str_radix(&digits, 16).ok()
+}
+
+impl Rom {
+    /// Most words `from_readmemh` will make room for. Each word costs about two ANDs to read, so
+    /// images anywhere near this are already impractical in a circuit.
+    pub const MAX_WORDS: usize = 1 << 24;
+
+    pub fn new(width: usize, words: Vec<u64>) -> Result<Self, RomError> {
+        if width == 0 || width > 64 {
+            return Err(RomError::Width(width));
+        }
+        if width < 64 {
+            if let Some((address, value)) = words
+                .iter()
+                .enumerate()
+                .find(|(_, word)| **word >> width != 0)
+            {
+                return Err(RomError::TooWide {
+                    address,
+                    value: *value,
+                    width,
+                });
+            }
+        }
+        Ok(Rom { words, width })
+    }
+
+    /// Splits a raw image into words of `width` bits, each stored little-endian in as many bytes
+    /// as it takes. A partial word at the end is padded with zeros.
+    pub fn from_bytes(width: usize, bytes: &[u8]) -> Result<Self, RomError> {
+        if width == 0 || width > 64 {
+            return Err(RomError::Width(width));
+        }
+        let words = bytes
+            .chunks(width.div_ceil(8))
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .rev()
+                    .fold(0, |word, byte| word << 8 | u64::from(*byte))
+            })
+            .collect();
+        Rom::new(width, words)
+    }
+
+    /// Parses an image in the format of Verilog's `$readmemh`: hex words separated by whitespace,
+    /// `//` comments, underscores inside words, and `@` followed by a hex address to continue
+    /// from. Addresses skipped over hold zero. Block comments aren't supported, and neither are
+    /// addresses from `MAX_WORDS` on.
+    pub fn from_readmemh(width: usize, text: &str) -> Result<Self, RomError> {
+        let mut words = Vec::new();
+        let mut address: u64 = 0;
+        for (line, content) in text.lines().enumerate() {
+            let content = content.split("//").next().unwrap_or_default();
+            for token in content.split_whitespace() {
+                let parse_error = || RomError::Parse {
+                    line: line + 1,
+                    token: token.to_string(),
+                };
+                match token.strip_prefix('@') {
+                    Some(target) => {
+                        address = parse_hex(target).ok_or_else(parse_error)?;
+                    }
+                    None => {
+                        let word = parse_hex(token).ok_or_else(parse_error)?;
+                        if address >= Rom::MAX_WORDS as u64 {
+                            return Err(RomError::AddressTooLarge {
+                                line: line + 1,
+                                address,
+                            });
+                        }
+                        let index = address as usize;
+                        if words.len() <= index {
+                            words.resize(index + 1, 0);
+                        }
+                        words[index] = word;
+                        address += 1;
+                    }
+                }
+            }
+        }
+        Rom::new(width, words)
+    }
+
+    /// Reads a raw image from a file, as `from_bytes` does. A bad image is an `InvalidData`
+    /// error wrapping a `RomError`.
+    pub fn load_bytes(width: usize, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Rom::from_bytes(width, &fs::read(path)?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Reads a `$readmemh` image from a file, as `from_readmemh` does.
+    pub fn load_readmemh(width: usize, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Rom::from_readmemh(width, &fs::read_to_string(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Bits needed to address every word.
+    pub fn address_width(&self) -> usize {
+        (usize::BITS - self.words.len().saturating_sub(1).leading_zeros()) as usize
+    }
+}
+
+/// Whether every address that starts with the `prefix` of the address bits, followed by
+/// `remaining` more bits, is past the end of an image of `words` words.
+fn past_end(prefix: usize, remaining: usize, words: usize) -> bool {
+    if prefix == 0 {
+        return words == 0;
+    }
+    // Shifting the prefix out of a usize would mean an address larger than any image
+    remaining >= usize::BITS as usize
+        || prefix > usize::MAX >> remaining
+        || prefix << remaining >= words
+}
+
+/// One wire per word of the image, set if `address` (least significant bit first) is that word's
+/// address.
+fn decode(sink: &mut impl GateSink, words: usize, address: &[usize]) -> Vec<usize> {
+    // Lines for every prefix of the address, most significant bits first, that starts an address
+    // inside the image. `None` is the empty prefix, which always matches.
+    let mut lines: Vec<Option<usize>> = vec![None];
+    for (remaining, bit) in address.iter().enumerate().rev() {
+        let inverted = not(sink, *bit);
+        let mut next = Vec::with_capacity(lines.len() * 2);
+        for (prefix, line) in lines.iter().enumerate() {
+            for (value, literal) in [(0, inverted), (1, *bit)] {
+                // Only addresses past the end of the image start with this prefix
+                if past_end(prefix << 1 | value, remaining, words) {
+                    break;
+                }
+                next.push(Some(match line {
+                    None => literal,
+                    Some(line) => and(sink, *line, literal),
+                }));
+            }
+        }
+        lines = next;
+    }
+    lines
+        .into_iter()
+        .map(|line| line.unwrap_or_else(|| constant(sink, true)))
+        .collect()
+}
+
+/// The word of `rom` at `address`, a GF2 word least significant bit first, or zero if the image
+/// has no word there. The address needs at least `rom.address_width()` bits, or some words
+/// couldn't be read.
+pub fn read(
+    sink: &mut impl GateSink,
+    rom: &Rom,
+    address: &[usize],
+) -> Result<Vec<usize>, RomError> {
+    if address.len() < rom.address_width() {
+        return Err(RomError::AddressTooNarrow {
+            bits: address.len(),
+            needed: rom.address_width(),
+        });
+    }
+    if rom.words.is_empty() {
+        return Ok((0..rom.width).map(|_| constant(sink, false)).collect());
+    }
+    let lines = decode(sink, rom.words.len(), address);
+    let word = (0..rom.width)
+        .map(|bit| {
+            let set = lines
+                .iter()
+                .zip(&rom.words)
+                .filter(|(_, word)| (*word >> bit) & 1 == 1)
+                .map(|(line, _)| *line);
+            set.reduce(|acc, line| xor(sink, acc, line))
+                .unwrap_or_else(|| constant(sink, false))
+        })
+        .collect();
+    Ok(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::{AnalysisPass, GateStats};
+    use crate::gadgets::bits::input;
+    use crate::gadgets::evaluate_outputs;
+    use crate::gadgets::rom::{read, Rom, RomError};
+    use crate::ProgramEditor;
+
+    fn bits(value: u64, width: usize) -> Vec<bool> {
+        (0..width).map(|i| (value >> i) & 1 == 1).collect()
+    }
+
+    fn value(bits: &[bool]) -> u64 {
+        bits.iter()
+            .enumerate()
+            .map(|(i, b)| u64::from(*b) << i)
+            .sum()
+    }
+
+    #[test]
+    fn test_rom() {
+        let image = "// boot ROM
+            00c0ffee deadbeef
+            @4 1234_5678 // after a gap
+            ffffffff";
+        let rom = Rom::from_readmemh(32, image).unwrap();
+        assert_eq!(
+            rom.words(),
+            [0xc0ffee, 0xdeadbeef, 0, 0, 0x12345678, 0xffffffff]
+        );
+        assert_eq!(rom.address_width(), 3);
+
+        // One address bit more than the image needs
+        let mut editor = ProgramEditor::new(Vec::new());
+        let address = input(&mut editor, 4);
+        let word = read(&mut editor, &rom, &address).unwrap();
+        let (program, _) = editor.commit();
+        for address in 0..16 {
+            let out = evaluate_outputs(&program, &word, &bits(address, 4));
+            let expected = rom.words().get(address as usize).copied().unwrap_or(0);
+            assert_eq!(value(&out), expected, "address {}", address);
+        }
+        // Decoding is shared between the 32 bits of the word
+        assert!(GateStats::analyze(program.iter()).multiplications() <= 2 * rom.words().len());
+
+        // A single word needs no address
+        let rom = Rom::from_bytes(12, &[0x34, 0x02]).unwrap();
+        let mut editor = ProgramEditor::new(Vec::new());
+        let word = read(&mut editor, &rom, &[]).unwrap();
+        let (program, _) = editor.commit();
+        assert_eq!(value(&evaluate_outputs(&program, &word, &[])), 0x234);
+
+        // Addresses wider than a machine word read zero whenever a high bit is set
+        let rom = Rom::new(8, vec![0x5a, 0xa5]).unwrap();
+        let mut editor = ProgramEditor::new(Vec::new());
+        let address = input(&mut editor, 70);
+        let word = read(&mut editor, &rom, &address).unwrap();
+        let (program, _) = editor.commit();
+        let mut one = vec![false; 70];
+        one[0] = true;
+        assert_eq!(value(&evaluate_outputs(&program, &word, &one)), 0xa5);
+        let mut high = one;
+        high[69] = true;
+        assert_eq!(value(&evaluate_outputs(&program, &word, &high)), 0);
+    }
+
+    #[test]
+    fn test_rom_errors() {
+        assert_eq!(
+            Rom::from_bytes(12, &[0x34, 0x12, 0xff]),
+            Err(RomError::TooWide {
+                address: 0,
+                value: 0x1234,
+                width: 12
+            })
+        );
+        assert_eq!(Rom::new(65, vec![]), Err(RomError::Width(65)));
+        let error = Rom::from_readmemh(8, "00\n@zz").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: \"@zz\" is not a hex word or address"
+        );
+        assert_eq!(
+            Rom::from_readmemh(8, "@ffffffffffffffff 00"),
+            Err(RomError::AddressTooLarge {
+                line: 1,
+                address: u64::MAX
+            })
+        );
+        // The address only counts once a word is written there
+        assert!(Rom::from_readmemh(8, "00 @ffffffffffffffff").is_ok());
+
+        let rom = Rom::new(8, vec![0; 5]).unwrap();
+        let mut editor = ProgramEditor::new(Vec::new());
+        let address = input(&mut editor, 2);
+        assert_eq!(
+            read(&mut editor, &rom, &address),
+            Err(RomError::AddressTooNarrow { bits: 2, needed: 3 })
+        );
+    }
+}
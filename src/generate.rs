@@ -0,0 +1,522 @@
+//! Seeded random programs shaped like real workloads, for benchmarking transforms and evaluators
+//! on something closer to what they'll see in practice than uniformly random gates.
+//!
+//! A `Profile` gives the relative frequency of each gate kind, keyed the same way as
+//! `Fingerprint::kinds`, so a profile can be taken straight from a fingerprint of a real circuit.
+//! Generated programs are well formed: every gate reads wires that were already written, every
+//! B2A reads 64 of them, and every assertion holds for the witness that comes with the program.
+//! Keeping them that way takes a few gates the profile didn't ask for, mostly a `SubConst` before
+//! any assertion of a wire that isn't already zero; those are counted in `Generated::support`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{CombineOperation, Domain, Operation, WireValue};
+
+const KINDS: [&str; 10] = [
+    "Input",
+    "Random",
+    "Add",
+    "AddConst",
+    "Sub",
+    "SubConst",
+    "Mul",
+    "MulConst",
+    "AssertZero",
+    "Const",
+];
+
+/// What to generate, as target frequencies of each gate kind.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Relative weights, keyed by domain and `Operation::kind` (`GF2 Mul`, `Z64 Input`, ...) or
+    /// `B2A`
+    weights: BTreeMap<String, f64>,
+    /// Gates read from the most recent `locality` wires of their domain. Small values give deep,
+    /// narrow circuits; large ones shallow, wide ones.
+    pub locality: usize,
+}
+
+impl Profile {
+    /// A profile that generates nothing until weights are added with `with_weight`.
+    pub fn new(locality: usize) -> Self {
+        Profile {
+            weights: BTreeMap::new(),
+            locality: locality.max(1),
+        }
+    }
+
+    /// Sets the weight of a gate kind. Panics if the generator can't produce that kind.
+    pub fn with_weight(mut self, kind: &str, weight: f64) -> Self {
+        assert!(is_known_kind(kind), "Unknown gate kind: {}", kind);
+        assert!(weight >= 0.0, "Weights can't be negative");
+        self.weights.insert(kind.to_string(), weight);
+        self
+    }
+
+    /// Weights proportional to the given gate counts, such as `Fingerprint::kinds` of a circuit
+    /// whose shape should be copied. Kinds the generator doesn't produce, like `SizeHint`, are
+    /// left out.
+    pub fn from_counts(counts: &BTreeMap<String, usize>, locality: usize) -> Self {
+        let mut profile = Profile::new(locality);
+        for (kind, count) in counts {
+            if is_known_kind(kind) {
+                profile = profile.with_weight(kind, *count as f64);
+            }
+        }
+        profile
+    }
+
+    /// Boolean circuits as Yosys emits them: mostly XOR and AND, with NOT as `AddConst`.
+    pub fn yosys_boolean() -> Self {
+        Profile::new(256)
+            .with_weight("GF2 Input", 6.0)
+            .with_weight("GF2 Add", 45.0)
+            .with_weight("GF2 Mul", 28.0)
+            .with_weight("GF2 AddConst", 18.0)
+            .with_weight("GF2 Const", 1.0)
+            .with_weight("GF2 AssertZero", 2.0)
+    }
+
+    /// Arithmetic over Z64, heavy on multiplications.
+    pub fn arithmetic() -> Self {
+        Profile::new(64)
+            .with_weight("Z64 Input", 6.0)
+            .with_weight("Z64 Add", 25.0)
+            .with_weight("Z64 Sub", 10.0)
+            .with_weight("Z64 Mul", 25.0)
+            .with_weight("Z64 AddConst", 10.0)
+            .with_weight("Z64 MulConst", 15.0)
+            .with_weight("Z64 Const", 2.0)
+            .with_weight("Z64 AssertZero", 7.0)
+    }
+
+    /// Mixed circuits that keep moving values from GF2 to Z64, like range checks and bit
+    /// decompositions do.
+    pub fn mixed_conversions() -> Self {
+        Profile::new(128)
+            .with_weight("GF2 Input", 12.0)
+            .with_weight("GF2 Add", 20.0)
+            .with_weight("GF2 Mul", 10.0)
+            .with_weight("GF2 AddConst", 5.0)
+            .with_weight("B2A", 8.0)
+            .with_weight("Z64 Input", 3.0)
+            .with_weight("Z64 Add", 15.0)
+            .with_weight("Z64 Sub", 5.0)
+            .with_weight("Z64 Mul", 10.0)
+            .with_weight("Z64 MulConst", 7.0)
+            .with_weight("Z64 AssertZero", 5.0)
+    }
+
+    pub fn weights(&self) -> &BTreeMap<String, f64> {
+        &self.weights
+    }
+
+    /// The weights scaled to add up to one.
+    pub fn distribution(&self) -> BTreeMap<String, f64> {
+        let total: f64 = self.weights.values().sum();
+        self.weights
+            .iter()
+            .map(|(kind, weight)| (kind.clone(), weight / total))
+            .collect()
+    }
+
+    /// The largest difference between this profile's distribution and the frequencies of gate
+    /// kinds in `counts`, such as `Generated::sampled_kinds`. `SizeHint`s are
+    /// ignored.
+    pub fn distance(&self, counts: &BTreeMap<String, usize>) -> f64 {
+        let total: usize = counts
+            .iter()
+            .filter(|(kind, _)| *kind != "SizeHint")
+            .map(|(_, count)| count)
+            .sum();
+        let target = self.distribution();
+        let kinds: BTreeSet<&String> = target
+            .keys()
+            .chain(counts.keys().filter(|kind| *kind != "SizeHint"))
+            .collect();
+        kinds
+            .into_iter()
+            .map(|kind| {
+                let actual = *counts.get(kind).unwrap_or(&0) as f64 / total.max(1) as f64;
+                (target.get(kind).unwrap_or(&0.0) - actual).abs()
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+/// A generated program and a witness that satisfies it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Generated {
+    pub gates: Vec<CombineOperation>,
+    pub bool_inputs: Vec<bool>,
+    pub arith_inputs: Vec<u64>,
+    /// Counts of the gates added to keep the program well formed, by kind
+    pub support: BTreeMap<String, usize>,
+}
+
+impl Generated {
+    /// Counts of each kind of gate the profile asked for, leaving out the support gates and the
+    /// size hint. This is what the profile's distribution describes.
+    pub fn sampled_kinds(&self) -> BTreeMap<String, usize> {
+        let mut kinds = BTreeMap::new();
+        for gate in &self.gates {
+            if let Some(kind) = kind_of(gate) {
+                *kinds.entry(kind).or_default() += 1;
+            }
+        }
+        for (kind, count) in &self.support {
+            let total = kinds.get_mut(kind).unwrap();
+            *total -= count;
+            if *total == 0 {
+                kinds.remove(kind);
+            }
+        }
+        kinds
+    }
+}
+
+fn kind_of(gate: &CombineOperation) -> Option<String> {
+    match gate {
+        CombineOperation::GF2(op) => Some(format!("GF2 {}", op.kind())),
+        CombineOperation::Z64(op) => Some(format!("Z64 {}", op.kind())),
+        CombineOperation::B2A(_, _) => Some("B2A".to_string()),
+        CombineOperation::SizeHint(_, _) => None,
+    }
+}
+
+fn is_known_kind(kind: &str) -> bool {
+    kind == "B2A" || parse_kind(kind).is_some()
+}
+
+fn parse_kind(kind: &str) -> Option<(Domain, &'static str)> {
+    let (domain, name) = kind.split_at(kind.find(' ')?);
+    let domain = match domain {
+        "GF2" => Domain::GF2,
+        "Z64" => Domain::Z64,
+        _ => return None,
+    };
+    KINDS
+        .iter()
+        .find(|known| **known == &name[1..])
+        .map(|known| (domain, *known))
+}
+
+fn build<T: WireValue>(kind: &str, dst: usize, a: usize, b: usize, c: T) -> Operation<T> {
+    match kind {
+        "Input" => Operation::Input(dst),
+        "Random" => Operation::Random(dst),
+        "Add" => Operation::Add(dst, a, b),
+        "AddConst" => Operation::AddConst(dst, a, c),
+        "Sub" => Operation::Sub(dst, a, b),
+        "SubConst" => Operation::SubConst(dst, a, c),
+        "Mul" => Operation::Mul(dst, a, b),
+        "MulConst" => Operation::MulConst(dst, a, c),
+        "AssertZero" => Operation::AssertZero(a),
+        "Const" => Operation::Const(dst, c),
+        _ => unreachable!(),
+    }
+}
+
+/// What a gate computes, with GF2 values as 0 and 1.
+fn apply(domain: Domain, kind: &str, a: u64, b: u64, c: u64) -> u64 {
+    match (domain, kind) {
+        (Domain::GF2, "Add" | "Sub") => a ^ b,
+        (Domain::GF2, "AddConst" | "SubConst") => a ^ c,
+        (Domain::GF2, "Mul") => a & b,
+        (Domain::GF2, "MulConst") => a & c,
+        (Domain::Z64, "Add") => a.wrapping_add(b),
+        (Domain::Z64, "AddConst") => a.wrapping_add(c),
+        (Domain::Z64, "Sub") => a.wrapping_sub(b),
+        (Domain::Z64, "SubConst") => a.wrapping_sub(c),
+        (Domain::Z64, "Mul") => a.wrapping_mul(b),
+        (Domain::Z64, "MulConst") => a.wrapping_mul(c),
+        (_, "Const") => c,
+        _ => unreachable!(),
+    }
+}
+
+struct Generator {
+    rng: ChaCha20Rng,
+    locality: usize,
+    /// Values of the wires written so far, which are all the wires below the length. `None` for
+    /// the outputs of `Random` gates and anything computed from them.
+    gf2: Vec<Option<u64>>,
+    z64: Vec<Option<u64>>,
+    result: Generated,
+}
+
+impl Generator {
+    fn wires(&mut self, domain: Domain) -> &mut Vec<Option<u64>> {
+        match domain {
+            Domain::GF2 => &mut self.gf2,
+            Domain::Z64 => &mut self.z64,
+        }
+    }
+
+    /// A recently written wire
+    fn operand(&mut self, domain: Domain) -> usize {
+        let written = self.wires(domain).len();
+        let low = written.saturating_sub(self.locality);
+        self.rng.gen_range(low..written)
+    }
+
+    fn constant(&mut self, domain: Domain) -> u64 {
+        match domain {
+            Domain::GF2 => self.rng.gen_range(0..2),
+            Domain::Z64 => self.rng.gen(),
+        }
+    }
+
+    fn push(&mut self, domain: Domain, kind: &str, a: usize, b: usize, c: u64) {
+        let dst = self.wires(domain).len();
+        self.result.gates.push(match domain {
+            Domain::GF2 => CombineOperation::GF2(build(kind, dst, a, b, c != 0)),
+            Domain::Z64 => CombineOperation::Z64(build(kind, dst, a, b, c)),
+        });
+    }
+
+    /// Writes a new wire with a gate of the given kind, returning its value if it's known.
+    fn write(&mut self, domain: Domain, kind: &str) -> Option<u64> {
+        let c = self.constant(domain);
+        let value = match kind {
+            "Input" => {
+                match domain {
+                    Domain::GF2 => self.result.bool_inputs.push(c != 0),
+                    Domain::Z64 => self.result.arith_inputs.push(c),
+                }
+                self.push(domain, kind, 0, 0, 0);
+                Some(c)
+            }
+            "Random" => {
+                self.push(domain, kind, 0, 0, 0);
+                None
+            }
+            "Const" => {
+                self.push(domain, kind, 0, 0, c);
+                Some(c)
+            }
+            _ => {
+                let (a, b) = (self.operand(domain), self.operand(domain));
+                self.push(domain, kind, a, b, c);
+                let wires = self.wires(domain);
+                wires[a]
+                    .zip(wires[b])
+                    .map(|(a, b)| apply(domain, kind, a, b, c))
+            }
+        };
+        self.wires(domain).push(value);
+        value
+    }
+
+    /// Asserts a recent wire is zero, first subtracting its value if it isn't
+    fn assert_zero(&mut self, domain: Domain) {
+        let candidates: Vec<usize> = {
+            let written = self.wires(domain).len();
+            let low = written.saturating_sub(self.locality);
+            (low..written)
+                .filter(|wire| self.wires(domain)[*wire].is_some())
+                .collect()
+        };
+        let wire = if candidates.is_empty() {
+            let wire = self.wires(domain).len();
+            self.push(domain, "Const", 0, 0, 0);
+            self.wires(domain).push(Some(0));
+            wire
+        } else {
+            let wire = candidates[self.rng.gen_range(0..candidates.len())];
+            match self.wires(domain)[wire] {
+                Some(0) => wire,
+                Some(value) => {
+                    let dst = self.wires(domain).len();
+                    self.push(domain, "SubConst", wire, 0, value);
+                    self.wires(domain).push(Some(0));
+                    dst
+                }
+                None => unreachable!(),
+            }
+        };
+        self.push(domain, "AssertZero", wire, 0, 0);
+    }
+
+    fn b2a(&mut self) {
+        let written = self.gf2.len();
+        let low = self
+            .rng
+            .gen_range(written.saturating_sub(self.locality.max(64))..=written - 64);
+        let value = self.gf2[low..low + 64]
+            .iter()
+            .rev()
+            .try_fold(0, |acc, bit| bit.map(|bit| acc << 1 | bit));
+        self.result
+            .gates
+            .push(CombineOperation::B2A(self.z64.len(), low));
+        self.z64.push(value);
+    }
+
+    /// Adds one gate of the given kind, plus whatever it needs to be well formed: inputs when
+    /// there's nothing to read yet, and a subtraction when an assertion wouldn't hold otherwise.
+    fn generate(&mut self, kind: &str) {
+        let start = self.result.gates.len();
+        self.generate_with_support(kind);
+        let end = self.result.gates.len() - 1;
+        for gate in &self.result.gates[start..end] {
+            *self
+                .result
+                .support
+                .entry(kind_of(gate).unwrap())
+                .or_default() += 1;
+        }
+    }
+
+    fn generate_with_support(&mut self, kind: &str) {
+        if kind == "B2A" {
+            while self.gf2.len() < 64 {
+                self.write(Domain::GF2, "Input");
+            }
+            self.b2a();
+            return;
+        }
+
+        let (domain, name) = parse_kind(kind).unwrap();
+        if !matches!(name, "Input" | "Random" | "Const" | "AssertZero")
+            && self.wires(domain).is_empty()
+        {
+            self.write(domain, "Input");
+        }
+        if name == "AssertZero" {
+            self.assert_zero(domain);
+        } else {
+            self.write(domain, name);
+        }
+    }
+}
+
+/// Generates a program of about `gates` gates (a few more, if some had to be added to keep it
+/// well formed) following `profile`, along with a witness. The same arguments always give the
+/// same program.
+///
+/// Generation is coverage-guided to the extent that every kind with a non-zero weight appears at
+/// least once, as long as there's room for all of them: once the remaining budget only covers
+/// the kinds that haven't come up yet, those are generated in turn.
+pub fn generate_program(profile: &Profile, gates: usize, seed: u64) -> Generated {
+    let kinds: Vec<&String> = profile
+        .weights
+        .iter()
+        .filter(|(_, weight)| **weight > 0.0)
+        .map(|(kind, _)| kind)
+        .collect();
+    let distribution = WeightedIndex::new(kinds.iter().map(|kind| profile.weights[*kind]))
+        .expect("A profile needs at least one kind with a positive weight");
+
+    let mut generator = Generator {
+        rng: ChaCha20Rng::seed_from_u64(seed),
+        locality: profile.locality.max(1),
+        gf2: Vec::new(),
+        z64: Vec::new(),
+        result: Generated {
+            gates: Vec::new(),
+            bool_inputs: Vec::new(),
+            arith_inputs: Vec::new(),
+            support: BTreeMap::new(),
+        },
+    };
+    let mut uncovered: BTreeSet<&String> = kinds.iter().copied().collect();
+    for remaining in (1..=gates).rev() {
+        let kind = if remaining <= uncovered.len() {
+            *uncovered.iter().next().unwrap()
+        } else {
+            kinds[distribution.sample(&mut generator.rng)]
+        };
+        uncovered.remove(kind);
+        generator.generate(kind);
+    }
+
+    let mut result = generator.result;
+    if !result.gates.is_empty() {
+        result.gates.insert(
+            0,
+            CombineOperation::SizeHint(generator.z64.len(), generator.gf2.len()),
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate::{generate_program, Profile};
+    use crate::{evaluate_composite_program, CombineOperation, Fingerprint, Operation};
+
+    #[test]
+    fn test_profiles() {
+        for profile in [
+            Profile::yosys_boolean(),
+            Profile::arithmetic(),
+            Profile::mixed_conversions(),
+        ] {
+            let generated = generate_program(&profile, 5000, 7);
+            assert_eq!(generated, generate_program(&profile, 5000, 7));
+            evaluate_composite_program(
+                &generated.gates,
+                &generated.bool_inputs,
+                &generated.arith_inputs,
+            );
+
+            let sampled = generated.sampled_kinds();
+            assert_eq!(sampled.values().sum::<usize>(), 5000);
+            assert!(profile.distance(&sampled) < 0.03);
+            let kinds = Fingerprint::of(&generated.gates, usize::MAX, 0).kinds;
+            for kind in profile.weights().keys() {
+                assert!(kinds.contains_key(kind));
+            }
+
+            // A profile copied from the whole program reproduces its shape, support gates and all
+            let copied = Profile::from_counts(&kinds, profile.locality);
+            let regenerated = generate_program(&copied, 5000, 8);
+            assert!(copied.distance(&regenerated.sampled_kinds()) < 0.03);
+        }
+
+        // Rare kinds still show up in short programs
+        let rare = Profile::new(8)
+            .with_weight("GF2 Input", 1000.0)
+            .with_weight("Z64 Random", 0.001)
+            .with_weight("B2A", 0.001);
+        let generated = generate_program(&rare, 10, 1);
+        let kinds = Fingerprint::of(&generated.gates, usize::MAX, 0).kinds;
+        assert_eq!(kinds["Z64 Random"], 1);
+        assert_eq!(kinds["B2A"], 1);
+        evaluate_composite_program(
+            &generated.gates,
+            &generated.bool_inputs,
+            &generated.arith_inputs,
+        );
+    }
+
+    #[test]
+    fn test_generate_known_answer() {
+        // Benchmarks name programs by profile and seed, so a seed has to keep giving the same one
+        let generated = generate_program(&Profile::arithmetic(), 6, 3);
+        assert_eq!(
+            generated.gates,
+            [
+                CombineOperation::SizeHint(7, 0),
+                CombineOperation::Z64(Operation::Input(0)),
+                CombineOperation::Z64(Operation::Add(1, 0, 0)),
+                CombineOperation::Z64(Operation::AddConst(2, 1, 8637839028320352173)),
+                CombineOperation::Z64(Operation::SubConst(3, 2, 12790478621077184427)),
+                CombineOperation::Z64(Operation::AssertZero(3)),
+                CombineOperation::Z64(Operation::Const(4, 8007496862109284372)),
+                CombineOperation::Z64(Operation::Input(5)),
+                CombineOperation::Z64(Operation::Mul(6, 0, 0)),
+            ]
+        );
+        assert_eq!(
+            generated.arith_inputs,
+            [11299691833233191935, 10029633401867964434]
+        );
+    }
+}
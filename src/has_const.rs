@@ -1,37 +1,62 @@
 use crate::{CombineOperation, Operation, WireValue};
 
 pub trait HasConst<T> {
-    /// For gates that include constant data (AddConst, MulConst, etc) provides a method to access
-    /// the constant. Returns `None` for all other types of gates.
-    fn constant(&self) -> Option<T>;
+    /// Provides access to any constant data included in the gate (AddConst, MulConst, etc), in
+    /// the order it appears in the gate's definition. Empty for gates with no constants.
+    fn constants(&self) -> impl Iterator<Item = T> + '_;
+
+    /// For gates with a single constant, returns it. Returns the first constant for gates with
+    /// several, and `None` for gates with none.
+    fn constant(&self) -> Option<T> {
+        self.constants().next()
+    }
+
+    fn has_constants(&self) -> bool {
+        self.constants().next().is_some()
+    }
 }
 
 impl<T: WireValue> HasConst<T> for Operation<T> {
-    fn constant(&self) -> Option<T> {
-        match *self {
+    fn constants(&self) -> impl Iterator<Item = T> + '_ {
+        let constant = match *self {
             Operation::AddConst(_, _, c) => Some(c),
             Operation::SubConst(_, _, c) => Some(c),
             Operation::MulConst(_, _, c) => Some(c),
             Operation::Const(_, c) => Some(c),
             _ => None,
-        }
+        };
+        constant.into_iter()
     }
 }
 
 impl HasConst<bool> for CombineOperation {
-    fn constant(&self) -> Option<bool> {
-        match self {
-            CombineOperation::GF2(g) => g.constant(),
+    fn constants(&self) -> impl Iterator<Item = bool> + '_ {
+        let gate = match self {
+            CombineOperation::GF2(g) => Some(g),
             _ => None,
-        }
+        };
+        gate.into_iter().flat_map(|g| g.constants())
     }
 }
 
 impl HasConst<u64> for CombineOperation {
-    fn constant(&self) -> Option<u64> {
-        match self {
-            CombineOperation::Z64(g) => g.constant(),
+    fn constants(&self) -> impl Iterator<Item = u64> + '_ {
+        let gate = match self {
+            CombineOperation::Z64(g) => Some(g),
             _ => None,
-        }
+        };
+        gate.into_iter().flat_map(|g| g.constants())
+    }
+}
+
+impl CombineOperation {
+    /// Constants in a GF2 gate. Shorthand for `HasConst::<bool>::constants`.
+    pub fn gf2_constants(&self) -> impl Iterator<Item = bool> + '_ {
+        HasConst::<bool>::constants(self)
+    }
+
+    /// Constants in a Z64 gate. Shorthand for `HasConst::<u64>::constants`.
+    pub fn z64_constants(&self) -> impl Iterator<Item = u64> + '_ {
+        HasConst::<u64>::constants(self)
     }
 }
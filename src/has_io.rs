@@ -3,24 +3,24 @@ use crate::{CombineOperation, Operation, WireValue};
 
 impl<T: WireValue> HasIO for Operation<T> {
     #[inline(always)]
-    fn inputs(&self) -> InputIterator<Operation<T>> {
+    fn inputs(&self) -> InputIterator<'_, Operation<T>> {
         InputIterator::new(self)
     }
 
     #[inline(always)]
-    fn outputs(&self) -> OutputIterator<Operation<T>> {
+    fn outputs(&self) -> OutputIterator<'_, Operation<T>> {
         OutputIterator::new(self)
     }
 }
 
 impl HasIO for CombineOperation {
     #[inline(always)]
-    fn inputs(&self) -> InputIterator<CombineOperation> {
+    fn inputs(&self) -> InputIterator<'_, CombineOperation> {
         InputIterator::new(self)
     }
 
     #[inline(always)]
-    fn outputs(&self) -> OutputIterator<CombineOperation> {
+    fn outputs(&self) -> OutputIterator<'_, CombineOperation> {
         OutputIterator::new(self)
     }
 }
@@ -28,10 +28,10 @@ impl HasIO for CombineOperation {
 pub trait HasIO {
     //! Applies to all gates, allows access to the input and output wire IDs of the gates
 
-    fn inputs(&self) -> InputIterator<Self>
+    fn inputs(&self) -> InputIterator<'_, Self>
     where
         Self: Sized;
-    fn outputs(&self) -> OutputIterator<Self>
+    fn outputs(&self) -> OutputIterator<'_, Self>
     where
         Self: Sized;
 
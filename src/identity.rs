@@ -1,6 +1,6 @@
 use num_traits::{One, Zero};
 
-use crate::{CombineOperation, Operation};
+use crate::{CombineOperation, Fp, Operation};
 
 pub trait Identity<T> {
     /*! Trait related to buffer gates. If the gate doesn't change its input value (ie adding zero,
@@ -56,6 +56,21 @@ impl Identity<bool> for Operation<bool> {
     }
 }
 
+impl<const P: u64> Identity<Fp<P>> for Operation<Fp<P>> {
+    fn is_identity(&self) -> bool {
+        match self {
+            Operation::AddConst(_, _, c) => c.is_zero(),
+            Operation::SubConst(_, _, c) => c.is_zero(),
+            Operation::MulConst(_, _, c) => c.is_one(),
+            _ => false,
+        }
+    }
+
+    fn identity(w_out: usize, w_in: usize) -> Self {
+        Self::AddConst(w_out, w_in, Fp::zero())
+    }
+}
+
 impl Identity<u64> for CombineOperation {
     fn is_identity(&self) -> bool {
         match self {
@@ -1,5 +1,5 @@
 use crate::{CombineOperation, Operation, WireValue};
-/**
+/*
 Defines iterators for getting the inputs and outputs of a gate. Works for both CombineOperation and Operation
 */
 
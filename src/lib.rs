@@ -1,47 +1,165 @@
 //! MCircuit (pronounced mc-urkit) provides a series of types and traits for working with circuits.
 //! Specifically, arithmetic circuits on GF2 and Z64, the former of which are effectively boolean
-//! circuits. It is used by [Reverie](https://github.com/trailofbits/reverie).
+//! circuits, and circuits over prime fields (`Fp`). It is used by
+//! [Reverie](https://github.com/trailofbits/reverie).
 //!
 //! MCircuit includes:
-//! * A circuit parsing library for BLIF files
-//! * Code for evaluating circuits in its gate format
-//! * Traits for constructing, translating, and iterating over gates
-//! * Code to export circuits in the Bristol Fashion format
+//! * A circuit parsing library for BLIF, Bristol Fashion and SIEVE IR1 files, with format
+//!   auto-detection, and an inliner for BLIF subcircuits that can flatten them completely. With
+//!   the `yosys` feature, it also reads Yosys JSON netlists, to cross-check BLIF parses against
+//! * Code for evaluating circuits in its gate format, and for finding where two programs that
+//!   should agree start to differ, and, with the `parallel` feature, for evaluating them on
+//!   several threads
+//! * A `CircuitBuilder` for constructing circuits without numbering wires by hand
+//! * Traits for constructing, translating, and iterating over gates, and queries for finding them
+//! * Recovery of word-level adders from bit-blasted GF2 circuits, as annotations
+//! * Reusable subcircuit templates that can be instantiated with different I/O bindings
+//! * Manifests of the constants in a program, for checking embedded parameters against a spec
+//! * Code to export circuits in the Bristol Fashion, SIEVE IR, SHDL, JSONL, and Graphviz DOT
+//!   formats
+//! * Gadgets that generate circuits for common operations, like floating-point arithmetic
+//! * Random programs shaped like real workloads, for benchmarking
+//! * A `circuit!` macro for writing small circuits by hand, with the `dsl` feature
+//!
+//! ## Unwritten wires
+//!
+//! Wires in each domain start out unwritten. By default, reading a wire that no earlier gate has
+//! written yields zero (or false), and that's what `evaluate_composite_program` does. Proof
+//! backends don't all agree with this, so programs meant for them shouldn't rely on it:
+//! `UnwrittenWires` selects a stricter reading, and the `UnwrittenReads` analysis finds every
+//! place a program depends on it. Often that's a program whose gates are out of order, which
+//! `TopoSort` detects and, where every wire has a single writer, fixes.
+//!
+//! ## Size hints
+//!
+//! A `SizeHint` gate promises that evaluating the program needs no more than the given number of
+//! wires in each domain, so evaluators can allocate them up front. Hints are cumulative: a program
+//! may have several, anywhere in the gate stream, and the hint for the whole program is the
+//! largest of them in each domain (`size_hint`). Hints don't compute anything, so exporters skip
+//! them, and transforms either keep them as they are or rebuild them with `refresh_size_hints`.
+//! Tools are only guaranteed to see a hint that's the first gate, so that's where they belong;
+//! `Program::hoist_size_hints` moves them out of the gate stream altogether, and the
+//! `SizeHintCheck` analysis flags hints that are misplaced, too small (along with the first gate
+//! that goes past them) or bigger than needed. Setting `EvalConfig::check_size_hints` runs it
+//! before evaluating, so hints that are too small fail up front.
 
 #[macro_use]
 extern crate variant_count;
 
-pub use eval::{dump_vcd, evaluate_composite_program, largest_wires, smallest_wires, VcdDumper};
+pub use adders::{annotate_adders, Adder, FindAdders};
+pub use analysis::{
+    CostModel, DomainAudit, DomainUsage, GateStats, LiveRange, Liveness, SharedWire, SizeHintCheck,
+    SizeHintIssue, TopoSort, TopoSortError, UnwrittenRead, UnwrittenReads, WireLiveness,
+};
+pub use builder::{BusError, CircuitBuilder, Wire};
+pub use bundle::{verify_bundle, Bundle, Manifest, Outcome, Verification};
+pub use constants::{ConstantEntry, ConstantManifest, ConstantMismatch};
+pub use def_use::{DefUseIndex, StaleIndexError};
+pub use divergence::{find_divergence, find_divergence_by_position, Divergence};
+pub use edit::ProgramEditor;
+pub use eval::{
+    dump_annotated_vcd, dump_vcd, evaluate_composite_program, evaluate_composite_program_checked,
+    evaluate_composite_program_configured, evaluate_composite_program_limited,
+    evaluate_composite_program_mapped, evaluate_composite_program_with,
+    evaluate_composite_program_with_randomness, evaluate_composite_program_with_strategy,
+    evaluate_prefix, largest_wires, resume_evaluation, size_hint, smallest_wires,
+    CancellationToken, EvalConfig, EvalLimits, EvalOptions, EvalState, EvaluationError,
+    EvaluationOutput, InputMap, InputUse, Interrupted, LimitedEvaluationError, Randomness,
+    StopReason, StorageStrategy, StreamingEvaluator, UnwrittenWires, VcdDumper, WireStorage,
+};
+#[cfg(feature = "parallel")]
+pub use eval::{evaluate_parallel, evaluate_parallel_with_randomness};
+pub use field::Field;
+pub use fingerprint::{sample_gates, Fingerprint};
+pub use generate::{generate_program, Generated, Profile};
 pub use has_const::HasConst;
 pub use has_io::HasIO;
 pub use identity::Identity;
+pub use naming::WireNamer;
 use num_traits::Zero;
 pub use parsers::Parse;
+pub use peephole::{eliminate_redundant_conversions, Peephole};
+pub use pipeline::{Pipeline, Stage, StageReport};
+pub use prime::{evaluate_prime_program, Fp, Mersenne61};
+pub use program::{
+    content_hash, Annotations, Bus, ConstVector, DomainInUse, Generator, NameTable, ParameterError,
+    Parameters, Program, Provenance,
+};
+pub use query::{Query, QueryParseError};
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-pub use translatable::Translatable;
+pub use serialize::{
+    save_circuit, write_program, write_program_version, write_program_with_def_use, ProgramReader,
+    WireIndexError, FORMAT_VERSION, GATE_INDEX_STRIDE, IR_VERSION,
+};
+pub use slice::{slice_gate, slice_wire, Slice};
+pub use split::{split_by_domain, Conversion, DomainSplit};
+pub use template::{Template, TemplateError};
+pub use translatable::{Translatable, TranslationError};
+pub use truth_table::TruthTable;
+pub use validation::{
+    validate_program, Severity, ValidationError, ValidationReport, DEFAULT_REPORT_CAP,
+};
+pub use witness::{
+    convert_witness, read_witness, witness_layout, write_witness, Witness, WitnessMapping,
+    WitnessMode, WitnessSlot, WitnessWarning,
+};
 
-mod analysis;
+mod adders;
+pub mod analysis;
+mod builder;
+mod bundle;
+mod constants;
+mod def_use;
+mod divergence;
+#[cfg(feature = "dsl")]
+pub mod dsl;
+mod edit;
 mod eval;
 pub mod exporters;
+mod field;
+mod fingerprint;
+pub mod flatten;
+pub mod gadgets;
+mod generate;
 mod has_const;
 mod has_io;
 mod identity;
 mod io_extractors;
+mod naming;
+pub mod optimize;
 pub mod parsers;
+mod peephole;
+mod pipeline;
+mod prime;
+mod program;
+mod query;
+mod serialize;
+mod slice;
+mod split;
+mod template;
 mod tests;
 mod translatable;
+mod truth_table;
+mod validation;
+mod witness;
 
 /// Implemented for acceptable types to use as wire values. It would be nice if this could just
 /// be a set of required traits, but `num_traits::is_zero` isn't implemented for `bool`.
 pub trait WireValue: Copy + PartialEq + std::fmt::Debug + Serialize {
+    /// The domain gates over this type belong to. It has no default, since no domain fits every
+    /// type, so implementors from before 0.2 have to add it.
+    const DOMAIN: Domain;
+
     fn is_zero(&self) -> bool;
 
     fn to_le_bytes(&self) -> [u8; 8];
 }
 
 impl WireValue for bool {
+    const DOMAIN: Domain = Domain::GF2;
+
     fn is_zero(&self) -> bool {
         !*self
     }
@@ -52,6 +170,8 @@ impl WireValue for bool {
 }
 
 impl WireValue for u64 {
+    const DOMAIN: Domain = Domain::Z64;
+
     fn is_zero(&self) -> bool {
         Zero::is_zero(self)
     }
@@ -62,11 +182,12 @@ impl WireValue for u64 {
 }
 
 /// Defines the individual logic gate operations we can support
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, VariantCount)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, VariantCount)]
 pub enum Operation<T: WireValue> {
     /// Read a value from input and emit it on the wire
     Input(usize),
-    /// Emit a random value on the wire
+    /// Emit a random value on the wire. Evaluation draws it from the thread's generator, unless
+    /// it's given a seed or tape (see `Randomness`).
     Random(usize),
     /// Add the two wires together
     Add(usize, usize, usize),
@@ -102,7 +223,9 @@ enum OpType<T: WireValue> {
 }
 
 /// Wraps `Operation` to define a field for each gate. Also supports conversions and metadata.
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Reading a wire that hasn't been written is well-defined but discouraged; see the crate
+/// documentation.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum CombineOperation {
     /// Circuit Operation on GF2 Finite Field
     GF2(Operation<bool>),
@@ -120,6 +243,35 @@ pub enum CombineOperation {
     SizeHint(usize, usize),
 }
 
+/// Identifies which field a wire lives in. Wire indices are only unique within a domain.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Domain {
+    GF2,
+    Z64,
+}
+
+impl CombineOperation {
+    /// The domain of the wires this gate reads from, if it reads any.
+    pub fn input_domain(&self) -> Option<Domain> {
+        match self {
+            CombineOperation::GF2(_) => Some(Domain::GF2),
+            CombineOperation::Z64(_) => Some(Domain::Z64),
+            CombineOperation::B2A(_, _) => Some(Domain::GF2),
+            CombineOperation::SizeHint(_, _) => None,
+        }
+    }
+
+    /// The domain of the wires this gate writes to, if it writes any.
+    pub fn output_domain(&self) -> Option<Domain> {
+        match self {
+            CombineOperation::GF2(_) => Some(Domain::GF2),
+            CombineOperation::Z64(_) => Some(Domain::Z64),
+            CombineOperation::B2A(_, _) => Some(Domain::Z64),
+            CombineOperation::SizeHint(_, _) => None,
+        }
+    }
+}
+
 impl<T: WireValue> Operation<T> {
     /// Convenient way to get a random gate for testing
     fn random_variant<R: Rng + ?Sized>(rng: &mut R) -> OpType<T> {
@@ -140,6 +292,22 @@ impl<T: WireValue> Operation<T> {
         }
     }
 
+    /// Name of the gate's variant, for use in reports and statistics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Operation::Input(_) => "Input",
+            Operation::Random(_) => "Random",
+            Operation::Add(_, _, _) => "Add",
+            Operation::AddConst(_, _, _) => "AddConst",
+            Operation::Sub(_, _, _) => "Sub",
+            Operation::SubConst(_, _, _) => "SubConst",
+            Operation::Mul(_, _, _) => "Mul",
+            Operation::MulConst(_, _, _) => "MulConst",
+            Operation::AssertZero(_) => "AssertZero",
+            Operation::Const(_, _) => "Const",
+        }
+    }
+
     /// Rebuild a gate from its fundamental components. Used by parsers to go from text to gates.
     fn construct<I1, I2>(
         ty: OpType<T>,
@@ -209,4 +377,5 @@ where
 pub trait Gate<T>: HasIO + HasConst<T> + Translatable + Identity<T> {}
 impl Gate<u64> for Operation<u64> {}
 impl Gate<bool> for Operation<bool> {}
+impl<const P: u64> Gate<Fp<P>> for Operation<Fp<P>> {}
 impl<T: WireValue> Gate<T> for CombineOperation where CombineOperation: HasConst<T> + Identity<T> {}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use crate::{Domain, NameTable, Program};
+
+/// Gives deterministic, hierarchical names to wires created by code that generates circuits, so
+/// that debugging output can show `adder3::carry[7]` rather than a bare wire index. Scopes are
+/// separated with `::`, as in names from the BLIF parser, so VCD dumps nest them the same way.
+///
+/// Each time a gadget is entered it gets an instance name made from its kind and how many gadgets
+/// of that kind were entered before it in the same scope (`adder0`, `adder1`, ...). Wires named
+/// inside it are prefixed with the instance names of every gadget it's nested in. Since the
+/// names only depend on the order of calls, generating the same circuit twice gives the same
+/// names.
+#[derive(Clone, Debug, Default)]
+pub struct WireNamer {
+    names: NameTable,
+    /// Instance names of the gadgets we're inside, outermost first
+    scopes: Vec<String>,
+    /// Number of instances of each kind entered so far, keyed by (enclosing scope, kind)
+    counts: HashMap<(String, String), usize>,
+}
+
+impl WireNamer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn prefix(&self) -> String {
+        self.scopes.join("::")
+    }
+
+    /// Starts a new instance of a gadget of the given kind, nested in the current one. Returns the
+    /// instance's full name.
+    pub fn enter(&mut self, kind: &str) -> String {
+        let count = self
+            .counts
+            .entry((self.prefix(), kind.to_string()))
+            .or_default();
+        let instance = format!("{}{}", kind, count);
+        *count += 1;
+
+        self.scopes.push(instance);
+        self.prefix()
+    }
+
+    /// Finishes the innermost gadget instance.
+    pub fn exit(&mut self) {
+        self.scopes
+            .pop()
+            .expect("Exited more gadgets than were entered");
+    }
+
+    /// Runs `build` inside a new instance of a gadget.
+    pub fn scoped<R>(&mut self, kind: &str, build: impl FnOnce(&mut Self) -> R) -> R {
+        self.enter(kind);
+        let result = build(self);
+        self.exit();
+        result
+    }
+
+    /// Names a single wire within the current gadget instance.
+    pub fn name(&mut self, domain: Domain, wire: usize, label: &str) {
+        let name = if self.scopes.is_empty() {
+            label.to_string()
+        } else {
+            format!("{}::{}", self.prefix(), label)
+        };
+        self.names.insert(domain, wire, name);
+    }
+
+    /// Names each of `wires` as an element of `label`, least significant first.
+    pub fn name_bus(&mut self, domain: Domain, wires: &[usize], label: &str) {
+        for (i, wire) in wires.iter().enumerate() {
+            self.name(domain, *wire, &format!("{}[{}]", label, i));
+        }
+    }
+
+    pub fn names(&self) -> &NameTable {
+        &self.names
+    }
+
+    /// Adds every name to the program's name table, replacing any names those wires already had.
+    pub fn record(self, program: &mut Program) {
+        let names = program.names.get_or_insert_with(NameTable::default);
+        names.gf2.extend(self.names.gf2);
+        names.z64.extend(self.names.z64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    use crate::naming::WireNamer;
+    use crate::{dump_vcd, CombineOperation, Domain, Operation, Program, VcdDumper};
+
+    /// A one-bit full adder, built under whatever scope the namer is in.
+    fn full_adder(namer: &mut WireNamer, gates: &mut Vec<CombineOperation>, a: usize, b: usize) {
+        let base = gates.len() + 10;
+        namer.scoped("adder", |namer| {
+            gates.push(CombineOperation::GF2(Operation::Add(base, a, b)));
+            gates.push(CombineOperation::GF2(Operation::Mul(base + 1, a, b)));
+            namer.name(Domain::GF2, base, "sum");
+            namer.name_bus(Domain::GF2, &[base + 1], "carry");
+        });
+    }
+
+    #[test]
+    fn test_hierarchical_names() {
+        let mut namer = WireNamer::new();
+        let mut gates = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+        ];
+        namer.name_bus(Domain::GF2, &[0, 1], "x");
+        full_adder(&mut namer, &mut gates, 0, 1);
+        namer.scoped("counter", |namer| full_adder(namer, &mut gates, 0, 1));
+        full_adder(&mut namer, &mut gates, 0, 1);
+
+        let names = namer.names();
+        assert_eq!(names.get(Domain::GF2, 1).unwrap(), "x[1]");
+        assert_eq!(names.get(Domain::GF2, 12).unwrap(), "adder0::sum");
+        assert_eq!(
+            names.get(Domain::GF2, 15).unwrap(),
+            "counter0::adder0::carry[0]"
+        );
+        assert_eq!(names.get(Domain::GF2, 16).unwrap(), "adder1::sum");
+
+        let mut program = Program::from(gates);
+        namer.record(&mut program);
+        let path = std::env::temp_dir().join(format!("mcircuit_names_{}.vcd", std::process::id()));
+        let dumper = VcdDumper::for_program(BufWriter::new(File::create(&path).unwrap()), &program);
+        dump_vcd(&program.gates, &[true, false], &[], dumper);
+        let vcd = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Each gadget is its own scope, and the counter's adder is nested in the counter rather
+        // than mixed in with the top-level adder of the same name. The dumper swaps brackets for
+        // parentheses, which GTKWave handles better.
+        let counter = vcd.find("$scope module counter0 $end\n").unwrap();
+        let nested = &vcd[counter..];
+        assert!(nested.starts_with("$scope module counter0 $end\n$scope module adder0 $end\n"));
+        let adder = &nested[..nested.find("$upscope $end\n$upscope $end\n").unwrap()];
+        assert!(adder.contains("$var wire 1 !15 carry(0) $end"));
+        assert!(adder.contains("$var wire 1 !14 sum $end"));
+        assert!(!adder.contains("!12"));
+        assert_eq!(vcd.matches("$scope module adder0 $end").count(), 2);
+    }
+}
@@ -0,0 +1,920 @@
+//! Transforms that shrink programs without changing what they compute. Each one takes a list of
+//! gates and returns a new one; `Pipeline` chains them together.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+
+use crate::analysis::{
+    AnalysisPass, BackwardAnalysisPass, LiveGates, LiveRange, Liveness, WireCounter,
+};
+use crate::split::{compact, renumber};
+use crate::{
+    CombineOperation, Domain, HasConst, HasIO, Identity, Operation, Translatable, WireValue,
+    WitnessMapping,
+};
+
+/// What value numbering makes of a gate.
+enum Verdict {
+    /// The gate computes something new
+    Keep,
+    /// The gate's output wire already holds the value it computes, or it asserts something that
+    /// was already asserted
+    Redundant,
+    /// The gate recomputes a value that's still on the given wire
+    Available(usize),
+}
+
+/// A computation, in terms of the values (not wires) it reads.
+#[derive(PartialEq, Eq, Hash)]
+struct Expression {
+    domain: Domain,
+    kind: &'static str,
+    constants: Vec<u64>,
+    inputs: Vec<u64>,
+}
+
+/// Tracks which value every wire holds, numbering values so that two wires hold the same number
+/// only if they're certain to hold the same value.
+#[derive(Default)]
+struct ValueNumbering {
+    next: u64,
+    wires: HashMap<(Domain, usize), u64>,
+    /// The value each expression computed, and the wire it was computed into
+    expressions: HashMap<Expression, (u64, usize)>,
+    asserted: HashSet<u64>,
+}
+
+impl ValueNumbering {
+    fn fresh(&mut self) -> u64 {
+        self.next += 1;
+        self.next
+    }
+
+    /// Unwritten wires each hold a value of their own
+    fn value(&mut self, domain: Domain, wire: usize) -> u64 {
+        match self.wires.get(&(domain, wire)) {
+            Some(value) => *value,
+            None => {
+                let value = self.fresh();
+                self.wires.insert((domain, wire), value);
+                value
+            }
+        }
+    }
+
+    fn holds(&self, domain: Domain, wire: usize, value: u64) -> bool {
+        self.wires.get(&(domain, wire)) == Some(&value)
+    }
+
+    fn visit(&mut self, gate: &CombineOperation) -> Verdict {
+        match gate {
+            CombineOperation::GF2(op) => self.visit_operation(op),
+            CombineOperation::Z64(op) => self.visit_operation(op),
+            CombineOperation::B2A(dst, _) => {
+                let inputs = gate.inputs().map(|w| self.value(Domain::GF2, w)).collect();
+                self.compute(
+                    Expression {
+                        domain: Domain::Z64,
+                        kind: "B2A",
+                        constants: Vec::new(),
+                        inputs,
+                    },
+                    *dst,
+                )
+            }
+            CombineOperation::SizeHint(_, _) => Verdict::Keep,
+        }
+    }
+
+    fn visit_operation<T>(&mut self, op: &Operation<T>) -> Verdict
+    where
+        T: WireValue + Into<u64>,
+        Operation<T>: Identity<T>,
+    {
+        let domain = T::DOMAIN;
+        match op {
+            Operation::Input(dst) | Operation::Random(dst) => {
+                let value = self.fresh();
+                self.wires.insert((domain, *dst), value);
+                Verdict::Keep
+            }
+            Operation::AssertZero(src) => {
+                let value = self.value(domain, *src);
+                if self.asserted.insert(value) {
+                    Verdict::Keep
+                } else {
+                    Verdict::Redundant
+                }
+            }
+            _ if op.is_identity() => {
+                let (dst, src) = (op.dst().unwrap(), op.inputs().next().unwrap());
+                let value = self.value(domain, src);
+                if self.holds(domain, dst, value) {
+                    return Verdict::Redundant;
+                }
+                self.wires.insert((domain, dst), value);
+                Verdict::Keep
+            }
+            _ => {
+                let mut inputs: Vec<u64> = op.inputs().map(|w| self.value(domain, w)).collect();
+                if matches!(op, Operation::Add(_, _, _) | Operation::Mul(_, _, _)) {
+                    inputs.sort_unstable();
+                }
+                let expression = Expression {
+                    domain,
+                    kind: op.kind(),
+                    constants: op.constants().map(Into::into).collect(),
+                    inputs,
+                };
+                self.compute(expression, op.dst().unwrap())
+            }
+        }
+    }
+
+    fn compute(&mut self, expression: Expression, dst: usize) -> Verdict {
+        let domain = expression.domain;
+        if let Some((value, holder)) = self.expressions.get(&expression).copied() {
+            if self.holds(domain, holder, value) {
+                if self.holds(domain, dst, value) {
+                    return Verdict::Redundant;
+                }
+                self.wires.insert((domain, dst), value);
+                return Verdict::Available(holder);
+            }
+        }
+        let value = self.fresh();
+        self.wires.insert((domain, dst), value);
+        self.expressions.insert(expression, (value, dst));
+        Verdict::Keep
+    }
+}
+
+/// Removes gates that can't change anything: recomputing a value into a wire that already holds
+/// it, copying a wire onto itself, or asserting a value that was already asserted.
+pub fn deduplicate(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let mut numbering = ValueNumbering::default();
+    program
+        .iter()
+        .filter(|gate| !matches!(numbering.visit(gate), Verdict::Redundant))
+        .copied()
+        .collect()
+}
+
+/// Common subexpression elimination: a gate that recomputes a value some wire still holds is
+/// replaced by an identity gate copying that wire, which costs nothing in the protocols that care
+/// about multiplications and conversions. Also removes the gates `deduplicate` would.
+pub fn eliminate_common_subexpressions(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let mut numbering = ValueNumbering::default();
+    let mut result = Vec::with_capacity(program.len());
+    for gate in program {
+        match numbering.visit(gate) {
+            Verdict::Keep => result.push(*gate),
+            Verdict::Redundant => {}
+            Verdict::Available(holder) => {
+                let dst = gate.dst().unwrap();
+                result.push(match gate.output_domain() {
+                    Some(Domain::GF2) => Identity::<bool>::identity(dst, holder),
+                    _ => Identity::<u64>::identity(dst, holder),
+                });
+            }
+        }
+    }
+    result
+}
+
+/// Arithmetic on constants, for folding them at build time the way evaluation would.
+trait Foldable: WireValue {
+    fn zero() -> Self;
+    fn add(self, other: Self) -> Self;
+    fn sub(self, other: Self) -> Self;
+    fn mul(self, other: Self) -> Self;
+}
+
+impl Foldable for bool {
+    fn zero() -> Self {
+        false
+    }
+
+    fn add(self, other: Self) -> Self {
+        self ^ other
+    }
+
+    fn sub(self, other: Self) -> Self {
+        self ^ other
+    }
+
+    fn mul(self, other: Self) -> Self {
+        self & other
+    }
+}
+
+impl Foldable for u64 {
+    fn zero() -> Self {
+        0
+    }
+
+    fn add(self, other: Self) -> Self {
+        self.wrapping_add(other)
+    }
+
+    fn sub(self, other: Self) -> Self {
+        self.wrapping_sub(other)
+    }
+
+    fn mul(self, other: Self) -> Self {
+        self.wrapping_mul(other)
+    }
+}
+
+/// What constant folding knows about the wires of one domain.
+struct Folder<T> {
+    /// Wires known to hold a constant
+    constants: HashMap<usize, T>,
+    /// Wires written by an identity gate that was dropped, and the wire they copy. Reads go to
+    /// the source instead.
+    aliases: HashMap<usize, usize>,
+    /// The reverse of `aliases`
+    copies: HashMap<usize, Vec<usize>>,
+}
+
+impl<T> Default for Folder<T> {
+    fn default() -> Self {
+        Folder {
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            copies: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Foldable> Folder<T>
+where
+    Operation<T>: Identity<T>,
+    CombineOperation: From<Operation<T>>,
+{
+    fn resolve(&self, wire: usize) -> usize {
+        self.aliases.get(&wire).copied().unwrap_or(wire)
+    }
+
+    /// Writes out the identity gate that was dropped for `wire`, if there was one.
+    fn materialize(&mut self, wire: usize, out: &mut Vec<CombineOperation>) {
+        if let Some(src) = self.aliases.remove(&wire) {
+            out.push(Operation::<T>::identity(wire, src).into());
+            if let Some(copies) = self.copies.get_mut(&src) {
+                copies.retain(|copy| *copy != wire);
+            }
+        }
+    }
+
+    /// Forgets what `dst` held before it's overwritten, first writing out any copies of it that
+    /// are still being read through aliases.
+    fn overwrite(&mut self, dst: usize, out: &mut Vec<CombineOperation>) {
+        for copy in self.copies.remove(&dst).unwrap_or_default() {
+            self.materialize(copy, out);
+        }
+        if let Some(src) = self.aliases.remove(&dst) {
+            if let Some(copies) = self.copies.get_mut(&src) {
+                copies.retain(|copy| *copy != dst);
+            }
+        }
+        self.constants.remove(&dst);
+    }
+
+    /// Rewrites a gate whose inputs have already been resolved to use what's known about them, or
+    /// returns `None` if nothing is.
+    fn simplify(&self, op: Operation<T>) -> Option<Operation<T>> {
+        let constant = |wire: &usize| self.constants.get(wire).copied();
+        let simplified = match op {
+            Operation::AddConst(dst, a, c) => constant(&a).map(|a| Operation::Const(dst, a.add(c))),
+            Operation::SubConst(dst, a, c) => constant(&a).map(|a| Operation::Const(dst, a.sub(c))),
+            Operation::MulConst(dst, a, c) => match constant(&a) {
+                Some(a) => Some(Operation::Const(dst, a.mul(c))),
+                None if c == T::zero() => Some(Operation::Const(dst, c)),
+                None => None,
+            },
+            Operation::Add(dst, a, b) => match (constant(&a), constant(&b)) {
+                (Some(a), Some(b)) => Some(Operation::Const(dst, a.add(b))),
+                (Some(c), None) => Some(Operation::AddConst(dst, b, c)),
+                (None, Some(c)) => Some(Operation::AddConst(dst, a, c)),
+                (None, None) => None,
+            },
+            Operation::Sub(dst, a, b) => match (constant(&a), constant(&b)) {
+                (Some(a), Some(b)) => Some(Operation::Const(dst, a.sub(b))),
+                (None, Some(c)) => Some(Operation::SubConst(dst, a, c)),
+                // There's no gate subtracting a wire from a constant
+                _ => None,
+            },
+            Operation::Mul(dst, a, b) => match (constant(&a), constant(&b)) {
+                (Some(a), Some(b)) => Some(Operation::Const(dst, a.mul(b))),
+                (Some(c), None) => Some(Operation::MulConst(dst, b, c)),
+                (None, Some(c)) => Some(Operation::MulConst(dst, a, c)),
+                (None, None) => None,
+            },
+            _ => None,
+        };
+        match simplified {
+            // A new constant gate might simplify further
+            Some(op) if !matches!(op, Operation::Const(_, _)) => self.simplify(op).or(Some(op)),
+            _ => simplified,
+        }
+    }
+
+    fn fold(&mut self, op: &Operation<T>, out: &mut Vec<CombineOperation>) {
+        let op = op
+            .translate(op.inputs().map(|wire| self.resolve(wire)), op.outputs())
+            .expect("Operations are always translatable");
+        let op = self.simplify(op).unwrap_or(op);
+
+        if let Operation::AssertZero(wire) = op {
+            if self.constants.get(&wire).is_some_and(WireValue::is_zero) {
+                return;
+            }
+        }
+        if let Some(dst) = op.dst() {
+            if op.is_identity() {
+                let src = op.inputs().next().unwrap();
+                if src != dst {
+                    self.overwrite(dst, out);
+                    self.aliases.insert(dst, src);
+                    self.copies.entry(src).or_default().push(dst);
+                    if let Some(c) = self.constants.get(&src).copied() {
+                        self.constants.insert(dst, c);
+                    }
+                }
+                return;
+            }
+            self.overwrite(dst, out);
+            if let Operation::Const(_, c) = op {
+                self.constants.insert(dst, c);
+            }
+        }
+        out.push(op.into());
+    }
+}
+
+/// Constant propagation: gates whose inputs all come from `Const` gates become `Const` gates
+/// themselves, and ones with a single constant input become `AddConst`, `SubConst` or `MulConst`.
+/// Identity gates left over (like adding zero) are dropped, with later reads going to the wire
+/// they copied, as are assertions of constants that are zero. The gates computing constants stay,
+/// since the wires they write might still be read somewhere; `eliminate_dead_code` clears out the
+/// ones that aren't. B2A windows have to be contiguous, so copies a B2A reads are kept.
+///
+/// What's asserted doesn't change, but wires that only held a copy may end up unwritten.
+pub fn constant_fold(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let mut gf2: Folder<bool> = Folder::default();
+    let mut z64: Folder<u64> = Folder::default();
+    let mut out = Vec::with_capacity(program.len());
+    for gate in program {
+        match gate {
+            CombineOperation::GF2(op) => gf2.fold(op, &mut out),
+            CombineOperation::Z64(op) => z64.fold(op, &mut out),
+            CombineOperation::B2A(dst, low) => {
+                let bits: Option<Vec<bool>> = (*low..*low + 64)
+                    .map(|bit| gf2.constants.get(&bit).copied())
+                    .collect();
+                match bits {
+                    Some(bits) => {
+                        let value = bits
+                            .iter()
+                            .rev()
+                            .fold(0, |value, bit| (value << 1) | u64::from(*bit));
+                        z64.fold(&Operation::Const(*dst, value), &mut out);
+                    }
+                    None => {
+                        for bit in *low..*low + 64 {
+                            gf2.materialize(bit, &mut out);
+                        }
+                        z64.overwrite(*dst, &mut out);
+                        out.push(*gate);
+                    }
+                }
+            }
+            CombineOperation::SizeHint(_, _) => out.push(*gate),
+        }
+    }
+    out
+}
+
+/// Removes gates that no assertion depends on (see `LiveGates`). Dropping an `Input` changes
+/// which witness values the later ones read, so `keep_inputs` keeps them all.
+pub fn eliminate_dead_code(
+    program: &[CombineOperation],
+    keep_inputs: bool,
+) -> Vec<CombineOperation> {
+    eliminate_dead_code_with_mapping(program, keep_inputs).0
+}
+
+/// Same as `eliminate_dead_code`, but also returns how the witness changes, so existing witnesses
+/// can be converted to match.
+pub fn eliminate_dead_code_with_mapping(
+    program: &[CombineOperation],
+    keep_inputs: bool,
+) -> (Vec<CombineOperation>, WitnessMapping) {
+    let mut kept = LiveGates::analyze(program.iter());
+    for (gate, kept) in program.iter().zip(kept.iter_mut()) {
+        *kept |= keep_inputs
+            && matches!(
+                gate,
+                CombineOperation::GF2(Operation::Input(_))
+                    | CombineOperation::Z64(Operation::Input(_))
+            );
+    }
+    let gates = program
+        .iter()
+        .zip(&kept)
+        .filter(|(_, kept)| **kept)
+        .map(|(gate, _)| *gate)
+        .collect();
+    (gates, WitnessMapping::from_kept_gates(program, &kept))
+}
+
+/// Removes gates whose outputs are never read, working back from assertions and B2A conversions.
+/// Unlike `eliminate_dead_code`, conversions are kept even when nothing reads their result, and
+/// so is every input, so the program still takes the same witness. Synthesized circuits tend to
+/// be full of logic that drives nothing, which this clears out.
+pub fn eliminate_dead_gates(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let live = LiveGates::keeping_conversions().run(program.iter());
+    program
+        .iter()
+        .zip(live)
+        .filter(|(gate, live)| {
+            *live
+                || matches!(
+                    gate,
+                    CombineOperation::GF2(Operation::Input(_))
+                        | CombineOperation::Z64(Operation::Input(_))
+                )
+        })
+        .map(|(gate, _)| *gate)
+        .collect()
+}
+
+/// Renumbers the wires of each domain to close up any gaps, keeping them in the same order so
+/// the windows read by B2A gates stay contiguous. Size hints are left alone; they only get less
+/// tight, but `refresh_size_hints` will fix them up.
+pub fn renumber_wires(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let mut gf2_used = BTreeSet::new();
+    let mut z64_used = BTreeSet::new();
+    for gate in program {
+        match gate {
+            CombineOperation::GF2(op) => gf2_used.extend(op.inputs().chain(op.outputs())),
+            CombineOperation::Z64(op) => z64_used.extend(op.inputs().chain(op.outputs())),
+            CombineOperation::B2A(dst, _) => {
+                z64_used.insert(*dst);
+                gf2_used.extend(gate.inputs());
+            }
+            CombineOperation::SizeHint(_, _) => {}
+        }
+    }
+
+    let (gf2, z64) = (compact(gf2_used), compact(z64_used));
+    program
+        .iter()
+        .map(|gate| match gate {
+            CombineOperation::GF2(op) => CombineOperation::GF2(renumber(op, &gf2)),
+            CombineOperation::Z64(op) => CombineOperation::Z64(renumber(op, &z64)),
+            CombineOperation::B2A(dst, low) => CombineOperation::B2A(z64[dst], gf2[low]),
+            CombineOperation::SizeHint(_, _) => *gate,
+        })
+        .collect()
+}
+
+/// Renumbers the Z64 wires to start after the last GF2 wire, so no index names a wire in both
+/// domains (see `DomainAudit`). GF2 wires keep their numbers, and size hints grow to match.
+pub fn separate_domains(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let offset = program
+        .iter()
+        .filter_map(|gate| match gate {
+            CombineOperation::GF2(op) => op.inputs().chain(op.outputs()).max(),
+            CombineOperation::B2A(_, _) => gate.inputs().max(),
+            _ => None,
+        })
+        .max()
+        .map_or(0, |wire| wire + 1);
+
+    program
+        .iter()
+        .map(|gate| match gate {
+            CombineOperation::Z64(op) => CombineOperation::Z64(
+                op.translate(
+                    op.inputs().map(|w| w + offset),
+                    op.outputs().map(|w| w + offset),
+                )
+                .expect("Operations are always translatable"),
+            ),
+            CombineOperation::B2A(dst, low) => CombineOperation::B2A(dst + offset, *low),
+            CombineOperation::SizeHint(z64, gf2) => CombineOperation::SizeHint(z64 + offset, *gf2),
+            CombineOperation::GF2(_) => *gate,
+        })
+        .collect()
+}
+
+/// Wires that get consecutive indices: a lone wire, or a run of GF2 wires covered by B2A windows.
+struct Block {
+    low: usize,
+    len: usize,
+    /// First and last gates that use any wire of the block
+    first: usize,
+    last: usize,
+    /// Whether the block needs indices nothing has used yet
+    fresh: bool,
+}
+
+impl Block {
+    fn single(wire: usize, range: &LiveRange) -> Self {
+        Block {
+            low: wire,
+            len: 1,
+            first: range.first,
+            last: range.last,
+            fresh: range.read_first,
+        }
+    }
+}
+
+/// Gives every block indices no other block holds while it's live, reusing the lowest ones freed
+/// first. Returns the new index of every wire, and how many indices were used.
+fn allocate(mut blocks: Vec<Block>) -> (HashMap<usize, usize>, usize) {
+    blocks.sort_unstable_by_key(|block| (block.first, block.low));
+    let mut map = HashMap::new();
+    let mut next = 0;
+    let mut free = BinaryHeap::new();
+    // (last gate, first index, length) of every block holding indices
+    let mut held = BinaryHeap::new();
+    for block in blocks {
+        // Indices are only freed after the gate that last uses them, so a gate never writes over
+        // a wire it reads
+        while let Some(Reverse((last, base, len))) = held.peek().copied() {
+            if last >= block.first {
+                break;
+            }
+            held.pop();
+            free.extend((base..base + len).map(Reverse));
+        }
+        let reused = if block.fresh || block.len > 1 {
+            None
+        } else {
+            free.pop().map(|Reverse(index)| index)
+        };
+        let base = reused.unwrap_or_else(|| {
+            next += block.len;
+            next - block.len
+        });
+        held.push(Reverse((block.last, base, block.len)));
+        map.extend((0..block.len).map(|i| (block.low + i, base + i)));
+    }
+    (map, next)
+}
+
+/// Renumbers the wires of each domain so that wires that aren't live at the same time (see
+/// `Liveness`) share an index, the way a register allocator shares registers, and replaces any
+/// size hints with a single one at the front to match. Evaluators that allocate every wire up to
+/// the largest index need much less memory for circuits where most values are short-lived.
+///
+/// The result writes some wires more than once, so it isn't for exporters that need every wire
+/// assigned exactly once, like IR1. A wire keeps its index from its first use to its last, and
+/// only takes over an index another wire finished with if its first use writes it, so reads of
+/// unwritten wires still read zero. Overlapping B2A windows are allocated together, at indices
+/// nothing has used before, so they stay contiguous.
+pub fn reuse_wires(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let liveness = Liveness::analyze(program.iter());
+
+    let mut windows: Vec<usize> = program
+        .iter()
+        .filter_map(|gate| match gate {
+            CombineOperation::B2A(_, low) => Some(*low),
+            _ => None,
+        })
+        .collect();
+    windows.sort_unstable();
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for low in windows {
+        match runs.last_mut() {
+            Some((_, end)) if low < *end => *end = (*end).max(low + 64),
+            _ => runs.push((low, low + 64)),
+        }
+    }
+
+    let mut in_windows = HashSet::new();
+    let mut gf2_blocks: Vec<Block> = runs
+        .into_iter()
+        .map(|(low, end)| {
+            let ranges = (low..end).map(|wire| &liveness.gf2[&wire]);
+            in_windows.extend(low..end);
+            Block {
+                low,
+                len: end - low,
+                first: ranges.clone().map(|range| range.first).min().unwrap(),
+                last: ranges.map(|range| range.last).max().unwrap(),
+                fresh: true,
+            }
+        })
+        .collect();
+    gf2_blocks.extend(
+        liveness
+            .gf2
+            .iter()
+            .filter(|(wire, _)| !in_windows.contains(*wire))
+            .map(|(wire, range)| Block::single(*wire, range)),
+    );
+    let z64_blocks = liveness
+        .z64
+        .iter()
+        .map(|(wire, range)| Block::single(*wire, range))
+        .collect();
+
+    let (gf2, gf2_count) = allocate(gf2_blocks);
+    let (z64, z64_count) = allocate(z64_blocks);
+    let gates: Vec<CombineOperation> = program
+        .iter()
+        .filter_map(|gate| match gate {
+            CombineOperation::GF2(op) => Some(CombineOperation::GF2(renumber(op, &gf2))),
+            CombineOperation::Z64(op) => Some(CombineOperation::Z64(renumber(op, &z64))),
+            CombineOperation::B2A(dst, low) => Some(CombineOperation::B2A(z64[dst], gf2[low])),
+            CombineOperation::SizeHint(_, _) => None,
+        })
+        .collect();
+    if gates.is_empty() {
+        return gates;
+    }
+    std::iter::once(CombineOperation::SizeHint(z64_count, gf2_count))
+        .chain(gates)
+        .collect()
+}
+
+/// Replaces any size hints with a single one at the front, matching the wires actually used.
+pub fn refresh_size_hints(program: &[CombineOperation]) -> Vec<CombineOperation> {
+    let gates = program
+        .iter()
+        .filter(|gate| !matches!(gate, CombineOperation::SizeHint(_, _)));
+    if gates.clone().next().is_none() {
+        return Vec::new();
+    }
+    let ((z64, gf2), _) = WireCounter::analyze(gates.clone());
+    std::iter::once(CombineOperation::SizeHint(z64, gf2))
+        .chain(gates.copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::{AnalysisPass, DomainAudit, SizeHintCheck};
+    use crate::optimize::{
+        constant_fold, deduplicate, eliminate_common_subexpressions, eliminate_dead_code,
+        eliminate_dead_gates, refresh_size_hints, renumber_wires, reuse_wires, separate_domains,
+    };
+    use crate::{
+        evaluate_composite_program, evaluate_composite_program_checked, CombineOperation,
+        EvalConfig, Operation,
+    };
+
+    #[test]
+    fn test_value_numbering() {
+        let program = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            // Same product, operands swapped
+            CombineOperation::GF2(Operation::Mul(3, 1, 0)),
+            // Wire 2 already has it
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            CombineOperation::GF2(Operation::Add(4, 2, 3)),
+            CombineOperation::GF2(Operation::AssertZero(4)),
+            CombineOperation::GF2(Operation::AssertZero(4)),
+            // Overwriting an input means the product has to be computed again
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Mul(5, 0, 1)),
+            CombineOperation::GF2(Operation::AssertZero(5)),
+        ];
+
+        let deduplicated = deduplicate(&program);
+        let mut expected = program.to_vec();
+        expected.remove(7);
+        expected.remove(4);
+        assert_eq!(deduplicated, expected);
+
+        let eliminated = eliminate_common_subexpressions(&program);
+        assert_eq!(eliminated.len(), program.len() - 2);
+        assert_eq!(
+            eliminated[3],
+            CombineOperation::GF2(Operation::AddConst(3, 2, false))
+        );
+        assert_eq!(
+            eliminated[7],
+            CombineOperation::GF2(Operation::Mul(5, 0, 1))
+        );
+
+        let witness = [true, false, false];
+        evaluate_composite_program(&program, &witness, &[]);
+        evaluate_composite_program(&deduplicated, &witness, &[]);
+        evaluate_composite_program(&eliminated, &witness, &[]);
+    }
+
+    #[test]
+    fn test_dead_code_and_renumbering() {
+        let program = [
+            CombineOperation::SizeHint(100, 100),
+            CombineOperation::GF2(Operation::Input(10)),
+            CombineOperation::GF2(Operation::Input(20)),
+            CombineOperation::GF2(Operation::Mul(30, 10, 20)),
+            CombineOperation::GF2(Operation::AddConst(40, 10, true)),
+            CombineOperation::GF2(Operation::AssertZero(40)),
+        ];
+
+        let live = eliminate_dead_code(&program, true);
+        assert_eq!(live.len(), 5);
+        assert!(!live.contains(&program[3]));
+        assert_eq!(eliminate_dead_code(&program, false).len(), 4);
+
+        let renumbered = renumber_wires(&live);
+        assert_eq!(
+            renumbered[1..],
+            [
+                CombineOperation::GF2(Operation::Input(0)),
+                CombineOperation::GF2(Operation::Input(1)),
+                CombineOperation::GF2(Operation::AddConst(2, 0, true)),
+                CombineOperation::GF2(Operation::AssertZero(2)),
+            ]
+        );
+
+        let refreshed = refresh_size_hints(&renumbered);
+        assert_eq!(refreshed[0], CombineOperation::SizeHint(1, 3));
+        assert_eq!(refreshed.len(), renumbered.len());
+        assert!(refresh_size_hints(&[CombineOperation::SizeHint(1, 1)]).is_empty());
+    }
+
+    #[test]
+    fn test_separate_domains() {
+        let program = [
+            CombineOperation::SizeHint(2, 64),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(63, 0, true)),
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::Input(1)),
+            CombineOperation::Z64(Operation::Sub(1, 0, 1)),
+            CombineOperation::Z64(Operation::AssertZero(1)),
+        ];
+        let separated = separate_domains(&program);
+        assert_eq!(
+            separated,
+            [
+                CombineOperation::SizeHint(66, 64),
+                program[1],
+                program[2],
+                CombineOperation::B2A(64, 0),
+                CombineOperation::Z64(Operation::Input(65)),
+                CombineOperation::Z64(Operation::Sub(65, 64, 65)),
+                CombineOperation::Z64(Operation::AssertZero(65)),
+            ]
+        );
+        assert_eq!(DomainAudit::analyze(separated.iter()).shared().count(), 0);
+        assert!(SizeHintCheck::analyze(separated.iter()).is_empty());
+        let witness = [1u64 << 63];
+        evaluate_composite_program(&program, &[false], &witness);
+        evaluate_composite_program(&separated, &[false], &witness);
+    }
+
+    #[test]
+    fn test_dead_gates() {
+        let program = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            // Dangling: nothing reads wire 2
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+            CombineOperation::GF2(Operation::Add(3, 0, 1)),
+            CombineOperation::GF2(Operation::AssertZero(3)),
+            // Feeds a conversion nothing reads
+            CombineOperation::GF2(Operation::AddConst(4, 0, true)),
+            CombineOperation::B2A(0, 4),
+            CombineOperation::Z64(Operation::MulConst(1, 0, 3)),
+        ];
+
+        let kept = eliminate_dead_gates(&program);
+        assert_eq!(
+            kept,
+            [program[0], program[1], program[3], program[4], program[5], program[6]]
+        );
+        // Without the conversion, there's no reason to keep what it reads
+        assert_eq!(eliminate_dead_code(&program, true).len(), 4);
+        evaluate_composite_program(&kept, &[true, true], &[]);
+    }
+
+    #[test]
+    fn test_constant_fold() {
+        let z64 = CombineOperation::Z64;
+        let program = [
+            z64(Operation::Input(0)),
+            z64(Operation::Const(1, 3)),
+            z64(Operation::Const(2, 4)),
+            z64(Operation::Mul(3, 1, 2)),
+            z64(Operation::Add(4, 0, 3)),
+            // An identity gate, dropped in favor of reading wire 4 directly
+            z64(Operation::MulConst(5, 4, 1)),
+            z64(Operation::MulConst(6, 5, 2)),
+            z64(Operation::AssertZero(6)),
+            z64(Operation::Sub(7, 3, 3)),
+            z64(Operation::AssertZero(7)),
+            // Overwriting wire 4 means wire 5 needs its copy after all
+            z64(Operation::Input(4)),
+            z64(Operation::AssertZero(5)),
+        ];
+        let folded = constant_fold(&program);
+        assert_eq!(
+            folded,
+            [
+                z64(Operation::Input(0)),
+                z64(Operation::Const(1, 3)),
+                z64(Operation::Const(2, 4)),
+                z64(Operation::Const(3, 12)),
+                z64(Operation::AddConst(4, 0, 12)),
+                z64(Operation::MulConst(6, 4, 2)),
+                z64(Operation::AssertZero(6)),
+                z64(Operation::Const(7, 0)),
+                z64(Operation::AddConst(5, 4, 0)),
+                z64(Operation::Input(4)),
+                z64(Operation::AssertZero(5)),
+            ]
+        );
+        let witness = [u64::MAX - 11, 1];
+        evaluate_composite_program(&program, &[], &witness);
+        evaluate_composite_program(&folded, &[], &witness);
+
+        // A conversion of constant bits is a constant, but one reading a copy needs the copy
+        let mut program: Vec<CombineOperation> = (0..64)
+            .map(|bit| CombineOperation::GF2(Operation::Const(bit, bit < 2)))
+            .collect();
+        program.extend([
+            CombineOperation::B2A(0, 0),
+            CombineOperation::GF2(Operation::Input(64)),
+            CombineOperation::GF2(Operation::AddConst(65, 64, false)),
+            CombineOperation::B2A(1, 65),
+            CombineOperation::Z64(Operation::SubConst(2, 1, 1)),
+            CombineOperation::Z64(Operation::AssertZero(2)),
+        ]);
+        let folded = constant_fold(&program);
+        assert_eq!(folded[64], z64(Operation::Const(0, 3)));
+        assert_eq!(
+            folded[66..68],
+            [
+                CombineOperation::GF2(Operation::AddConst(65, 64, false)),
+                CombineOperation::B2A(1, 65),
+            ]
+        );
+        evaluate_composite_program(&folded, &[true], &[]);
+    }
+
+    #[test]
+    fn test_reuse_wires() {
+        // Checks the parity of 32 input bits a bit at a time, then converts the last 64 bits of
+        // the chain, with a wire that's read before it's written thrown in
+        let mut program = vec![
+            CombineOperation::SizeHint(1, 1000),
+            CombineOperation::GF2(Operation::Input(0)),
+        ];
+        for i in 1..100 {
+            program.push(CombineOperation::GF2(Operation::Input(100 + i)));
+            program.push(CombineOperation::GF2(Operation::Add(i, i - 1, 100 + i)));
+        }
+        program.push(CombineOperation::GF2(Operation::Add(500, 99, 400)));
+        program.push(CombineOperation::GF2(Operation::AssertZero(500)));
+        program.push(CombineOperation::B2A(0, 36));
+        program.push(CombineOperation::Z64(Operation::AssertZero(0)));
+
+        let reused = reuse_wires(&program);
+        assert_eq!(reused.len(), program.len());
+        let CombineOperation::SizeHint(z64, gf2) = reused[0] else {
+            panic!("No size hint at the front: {:?}", reused[0]);
+        };
+        assert_eq!(z64, 1);
+        // The window, the wire read before it's written, and three for the chain before the window
+        assert_eq!(gf2, 64 + 1 + 3);
+        let CombineOperation::B2A(_, low) = reused[reused.len() - 2] else {
+            unreachable!()
+        };
+        // Wire 0 is done with once the first sum is taken, so the next input gets its index
+        assert_eq!(
+            reused[2..5],
+            [
+                CombineOperation::GF2(Operation::Input(1)),
+                CombineOperation::GF2(Operation::Add(2, 0, 1)),
+                CombineOperation::GF2(Operation::Input(0)),
+            ]
+        );
+        assert!(low + 64 <= gf2);
+
+        // Same results for good and bad witnesses
+        let check = |gates: &[CombineOperation], bits: &[bool]| {
+            evaluate_composite_program_checked(gates, bits, &[], &[], &[], EvalConfig::default())
+                .is_ok()
+        };
+        let zeros = vec![false; 100];
+        let mut one = zeros.clone();
+        one[50] = true;
+        let mut two = one.clone();
+        two[20] = true;
+        for witness in [zeros, one, two] {
+            assert_eq!(check(&program, &witness), check(&reused, &witness));
+        }
+        assert!(reuse_wires(&[CombineOperation::SizeHint(4, 4)]).is_empty());
+    }
+}
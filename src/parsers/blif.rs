@@ -1,15 +1,18 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fmt::Write as _;
 use std::fs::File;
-use std::io::BufRead;
 use std::io::BufReader;
-use std::mem::swap;
-use std::mem::take;
+use std::io::{BufRead, Error, ErrorKind, Lines, Result};
+use std::marker::PhantomData;
+use std::mem::{replace, take};
+use std::sync::Arc;
 
 use num_traits::Zero;
 
-use crate::parsers::{Parse, WireHasher};
+use crate::parsers::{FastHasher, Interner, Parse, WireHasher};
 use crate::WireValue;
-use crate::{OpType, Operation};
+use crate::{OpType, Operation, Severity, ValidationError};
 
 /// Parses single wire pairs of the format `parent=child`. Returns (parent, child)
 pub fn parse_split(pair: &str) -> (&str, &str) {
@@ -17,13 +20,28 @@ pub fn parse_split(pair: &str) -> (&str, &str) {
     (split.next().unwrap(), split.next().unwrap())
 }
 
+/// Like `parse_split`, but reports a pin without an `=` instead of panicking.
+fn split_pin(pair: &str) -> std::result::Result<(&str, &str), String> {
+    pair.split_once('=')
+        .ok_or_else(|| format!("expected a connection like A=wire, found {}", pair))
+}
+
 /// Parses a gate into an operand, an output wire, and input wires. Returns in that order.
-fn parse_gate(mut line: VecDeque<&str>) -> (&str, &str, Vec<&str>) {
-    let op = line.pop_front().unwrap();
-    let (_, out) = parse_split(line.pop_back().unwrap());
-    let inputs: Vec<&str> = line.drain(..).map(|part| parse_split(part).1).collect();
+fn parse_gate(mut line: VecDeque<&str>) -> std::result::Result<(&str, &str, Vec<&str>), String> {
+    let op = line
+        .pop_front()
+        .filter(|op| !op.is_empty())
+        .ok_or("a .gate line needs a gate type")?;
+    let (_, out) = split_pin(
+        line.pop_back()
+            .ok_or_else(|| format!("the {} gate has no output", op))?,
+    )?;
+    let inputs = line
+        .drain(..)
+        .map(|part| split_pin(part).map(|pin| pin.1))
+        .collect::<std::result::Result<_, _>>()?;
 
-    (op, out, inputs)
+    Ok((op, out, inputs))
 }
 
 /// Parses a line of inputs or outputs into individual wires, then individual bits.
@@ -41,16 +59,30 @@ fn parse_io(mut line: VecDeque<&str>) -> Vec<Vec<&str>> {
     out
 }
 
+/// A subcircuit's model name and its `formal=actual` pin connections.
+type SubcircuitLine<'a> = (&'a str, Vec<(&'a str, &'a str)>);
+
 /// Parses a subcircuit line into the name of the circuit, then the list of I/O connections.
-fn parse_subcircuit(mut line: VecDeque<&str>) -> (&str, Vec<(&str, &str)>) {
-    let name = line.pop_front().unwrap();
-    let io: Vec<(&str, &str)> = line.drain(..).map(parse_split).collect();
+fn parse_subcircuit(mut line: VecDeque<&str>) -> std::result::Result<SubcircuitLine<'_>, String> {
+    let name = line
+        .pop_front()
+        .filter(|name| !name.is_empty())
+        .ok_or("a .subckt line needs a model name")?;
+    let io = line
+        .drain(..)
+        .map(split_pin)
+        .collect::<std::result::Result<_, _>>()?;
 
-    (name, io)
+    Ok((name, io))
 }
 
 /// Splits up a wire that ends with a bit index (`input[3]`) into individual components (`("input", 3)`)
 pub fn get_base_name_and_width(unparsed: &str) -> (String, usize) {
+    base_name_and_width(unparsed).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `get_base_name_and_width`, but reports a malformed bit index instead of panicking.
+fn base_name_and_width(unparsed: &str) -> std::result::Result<(String, usize), String> {
     let (base_name, after): (String, Option<&str>) = match unparsed.split_once('[') {
         None => (unparsed.into(), None),
         Some((before, after)) => (before.to_string(), Some(after)),
@@ -59,18 +91,15 @@ pub fn get_base_name_and_width(unparsed: &str) -> (String, usize) {
         None => 0,
         Some(after) => after
             .split_once(']')
-            .unwrap()
-            .0
-            .parse::<usize>()
-            .expect(after),
+            .and_then(|(idx, _)| idx.parse::<usize>().ok())
+            .ok_or_else(|| format!("{} doesn't end in a bit index like [3]", unparsed))?,
     };
-    (base_name, idx)
+    Ok((base_name, idx))
 }
 
 /// Returns `{context}::{id}`. Double colon syntax is used by the VCD dumper to separate scopes.
-/// Ignores `$true` and `$false` and rejects `$undef`. Since it's currently only used by the VCD
-/// dumper, consider making a release-mode version of this that just returns `id` rather than
-/// calling `format` and doing an extra allocation.
+/// Ignores `$true` and `$false` and rejects `$undef`. The parser hashes names in this form without
+/// building them; this is for looking them up afterwards.
 pub fn format_wire_id(context: &str, id: &str) -> String {
     if (id == "$true") || (id == "$false") {
         id.to_string()
@@ -81,47 +110,184 @@ pub fn format_wire_id(context: &str, id: &str) -> String {
     }
 }
 
+/// What to do when a circuit refers to the `$undef` wire, which Yosys emits for connections it
+/// couldn't (or didn't need to) drive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UndefPolicy {
+    /// Panic, as `format_wire_id` does
+    #[default]
+    Error,
+    /// Tie the connection to `$false`
+    False,
+    /// Drive each occurrence from a fresh `Input` gate, leaving its value up to the witness. The
+    /// new inputs are listed in the model's `undef_inputs`.
+    Input,
+}
+
+/// Records one place where `$undef` was replaced according to the parser's `UndefPolicy`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UndefUse {
+    /// The model that contained the `$undef` connection. This was a `String` before 0.2; `model()`
+    /// gives a `&str`.
+    pub model: Arc<str>,
+    /// The wire it was replaced with
+    pub wire: usize,
+}
+
+/// What to do when a `.model` has the same name as one that was already read, from the same file
+/// or an earlier one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateModelPolicy {
+    /// Panic, naming the model and the files that define it
+    Error,
+    /// Keep the first definition and skip the rest
+    PreferFirst,
+    /// Keep the last definition, as collecting the models by name does. Since a later file might
+    /// always redefine a model, the parser reads every file before returning the first model.
+    #[default]
+    PreferLast,
+    /// Keep every definition, giving later ones a new name (like `adder~2`). References to the
+    /// model elsewhere still mean the first definition.
+    Rename,
+}
+
+/// What happened to a model that was defined more than once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DuplicateAction {
+    /// This definition was skipped in favor of the first
+    KeptFirst,
+    /// The earlier definitions were dropped in favor of this one
+    KeptLast,
+    /// This definition was kept under a new name
+    Renamed(Arc<str>),
+}
+
+/// Records one duplicate definition, and how `DuplicateModelPolicy` resolved it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateModel {
+    pub name: Arc<str>,
+    /// Index (in the order they were added) of the file the model was first defined in
+    pub first_file: usize,
+    /// Index of the file with this definition
+    pub file: usize,
+    pub action: DuplicateAction,
+}
+
+/// A line of one of the files a parser read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The name the file was added under, or `file N` for the Nth file added without one
+    pub file: Arc<str>,
+    /// Line number, starting from 1
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
 /// A set of data that represents the information about a circuit we can glean from the BLIF file.
 /// May have multiple circuits per file.
 #[derive(Clone)]
 pub struct BlifCircuitDesc<T: WireValue> {
-    pub name: String,
+    /// Shared with every other use of the name through the parser's `interner`. This was a
+    /// `String` before 0.2; `name()` gives a `&str`.
+    pub name: Arc<str>,
     pub inputs: Vec<usize>,
     pub outputs: Vec<usize>,
+    /// Inputs driving the `$undef` connections `UndefPolicy::Input` replaced, in the order their
+    /// `Input` gates appear. They aren't in `inputs`, which only holds the declared ports and has
+    /// to stay contiguous in the top-level model.
+    pub undef_inputs: Vec<usize>,
     pub gates: Vec<Operation<T>>,
     pub subcircuits: Vec<BlifSubcircuitDesc>,
+    /// Where the `.model` line is, if the model was parsed
+    pub location: Option<SourceLocation>,
 }
 
 /// Defines the relation between a circuit and its subcircuits
 #[derive(Clone)]
 pub struct BlifSubcircuitDesc {
-    pub name: String,
+    /// The model this instantiates, shared like `BlifCircuitDesc::name`. This was a `String`
+    /// before 0.2; `name()` gives a `&str`.
+    pub name: Arc<str>,
     /// A set of wire ID connections in the format `(parent, subcircuit)`
     pub connections: Vec<(usize, usize)>,
+    /// The instance's name, from the `.cname` line Yosys writes after a `.subckt` when asked to
+    pub instance: Option<Arc<str>>,
+    /// Where the `.subckt` line is, if the subcircuit was parsed
+    pub location: Option<SourceLocation>,
+    /// The first connection whose two sides unpacked to different numbers of bits. It's left out
+    /// of `connections`, and flattening the instance fails with `FlattenError::Width`.
+    pub mismatch: Option<WidthMismatch>,
+}
+
+impl UndefUse {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl BlifSubcircuitDesc {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 impl Default for BlifSubcircuitDesc {
     fn default() -> Self {
         BlifSubcircuitDesc {
-            name: "".to_string(),
+            name: "".into(),
             connections: vec![],
+            instance: None,
+            location: None,
+            mismatch: None,
         }
     }
 }
 
+/// A `.subckt` connection between a packed wire and one of a different width.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WidthMismatch {
+    /// The subcircuit's side of the connection, and the number of bits it unpacked to
+    pub child: String,
+    pub child_bits: usize,
+    /// The parent's side of the connection, and the number of bits it unpacked to
+    pub parent: String,
+    pub parent_bits: usize,
+}
+
+impl fmt::Display for WidthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} expanded to {} bits, but {} expanded to {} bits",
+            self.child, self.child_bits, self.parent, self.parent_bits
+        )
+    }
+}
+
 impl<T: WireValue> Default for BlifCircuitDesc<T> {
     fn default() -> Self {
         BlifCircuitDesc {
-            name: "".to_string(),
+            name: "".into(),
             inputs: vec![],
             outputs: vec![],
+            undef_inputs: vec![],
             gates: vec![],
             subcircuits: vec![],
+            location: None,
         }
     }
 }
 
 impl<T: WireValue> BlifCircuitDesc<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Just pushes to `self.subcircuit`. Used to do packed wire expansion but that's handled
     /// elsewhere now.
     fn add_subcircuit(&mut self, sub: BlifSubcircuitDesc) {
@@ -132,27 +298,33 @@ impl<T: WireValue> BlifCircuitDesc<T> {
     /// top-level circuit after the hashing process. Later called by the flattener on the top-level
     /// circuit. It doesn't necessarily have to be true for anything but the top-level.
     pub fn validate_io(&self) {
-        if let Some(max_input) = self.inputs.iter().max() {
-            let min_input = self.inputs.iter().min().unwrap();
-
-            if (max_input - min_input) != (self.inputs.len() - 1) {
-                panic!(
-                    "{}'s inputs are not contiguous!\n{:?}",
-                    self.name, self.inputs
-                )
-            }
+        let errors = self.io_errors();
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+            panic!("{}", messages.join("\n"))
         }
+    }
 
-        if let Some(max_output) = self.outputs.iter().max() {
-            let min_output = self.outputs.iter().min().unwrap();
-
-            if (max_output - min_output) != (self.outputs.len() - 1) {
-                panic!(
-                    "{}'s outputs are not contiguous!\n{:?}",
-                    self.name, self.outputs
-                )
+    /// Like `validate_io`, but returns every problem rather than panicking.
+    pub fn io_errors(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        for (kind, wires) in [("inputs", &self.inputs), ("outputs", &self.outputs)] {
+            if let Some(max) = wires.iter().max() {
+                let min = wires.iter().min().unwrap();
+                if (max - min) != (wires.len() - 1) {
+                    errors.push(ValidationError {
+                        severity: Severity::Error,
+                        kind: "blif-io",
+                        gate: None,
+                        message: format!(
+                            "{}'s {} are not contiguous!\n{:?}",
+                            self.name, kind, wires
+                        ),
+                    });
+                }
             }
         }
+        errors
     }
 }
 
@@ -169,23 +341,80 @@ pub trait CanConstructVariant<T: WireValue> {
     ) -> Operation<T>;
 
     fn constant_from_str(&self, s: &str) -> T;
+
+    /// Checks that `construct_variant` can build gate `op` from a `.gate` line with `inputs`
+    /// input pins, so malformed files are reported instead of panicking there. The default
+    /// doesn't check anything.
+    fn check_gate(&self, _op: &str, _inputs: usize) -> std::result::Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Checks a `.gate` line's pin count against the number of inputs its gate type takes, or
+/// whether the type is known at all.
+fn check_arity(op: &str, inputs: usize, arity: Option<usize>) -> std::result::Result<(), String> {
+    match arity {
+        None => Err(format!("Unsupported gate type: {}", op)),
+        Some(arity) if arity != inputs => Err(format!(
+            "the {} gate takes {} inputs, but has {}",
+            op, arity, inputs
+        )),
+        Some(_) => Ok(()),
+    }
 }
 
 pub struct BlifParser<T: WireValue> {
-    reader: Option<BufReader<File>>,
-    pub hasher: WireHasher,
-    parsed: bool,
-    /// Vector - can have more than one circuit descriptor per file.
-    circuit: Vec<BlifCircuitDesc<T>>,
+    /// Files that haven't been completely parsed yet, in order
+    readers: VecDeque<Lines<Box<dyn BufRead + Send>>>,
+    /// Names of every file added, in order
+    file_names: Vec<Arc<str>>,
+    /// Gives wires their ids. A `FastHasher` unless it's replaced (before the first call to
+    /// `next`), say with a `BackrefHasher` to keep wire names around.
+    pub hasher: Box<dyn WireHasher + Send>,
+    /// Shared copies of model names, and of the wire names packed wires and constants unpack to.
+    /// Replace this with a clone of another parser's interner to share names between them.
+    pub interner: Interner,
+    /// How to treat `$undef` wires. Set this before the first call to `next`.
+    pub undef_policy: UndefPolicy,
+    /// Every `$undef` that was replaced rather than rejected, in the order they were encountered
+    pub undefs: Vec<UndefUse>,
+    /// How to treat models defined more than once. Set this before the first call to `next`.
+    pub duplicate_policy: DuplicateModelPolicy,
+    /// Every duplicate model definition, in the order they were encountered
+    pub duplicates: Vec<DuplicateModel>,
+    /// Names of the models read so far, and the files they were first defined in
+    models: HashMap<Arc<str>, usize>,
+    /// Index of the file at the front of `readers`
+    file: usize,
+    /// Number of the last line read from that file
+    line: usize,
+    /// Whether the last line read was a `.subckt`, which a `.cname` would name
+    after_subckt: bool,
+    /// Whether the model being read is a duplicate that will be dropped
+    skipping: bool,
+    /// Every model, once they've all been read for `DuplicateModelPolicy::PreferLast`
+    buffered: Option<VecDeque<BlifCircuitDesc<T>>>,
+    phantom: PhantomData<T>,
 }
 
 impl<T: WireValue> Default for BlifParser<T> {
     fn default() -> Self {
         BlifParser {
-            reader: None,
-            hasher: Default::default(),
-            parsed: false,
-            circuit: vec![],
+            readers: VecDeque::new(),
+            file_names: vec![],
+            hasher: Box::new(FastHasher::default()),
+            interner: Default::default(),
+            undef_policy: Default::default(),
+            undefs: vec![],
+            duplicate_policy: Default::default(),
+            duplicates: vec![],
+            models: HashMap::new(),
+            file: 0,
+            line: 0,
+            after_subckt: false,
+            skipping: false,
+            buffered: None,
+            phantom: PhantomData,
         }
     }
 }
@@ -249,6 +478,16 @@ impl CanConstructVariant<bool> for BlifParser<bool> {
                 .unwrap_or_else(|_| panic!("Can't convert {} into a bool", s)),
         }
     }
+
+    fn check_gate(&self, op: &str, inputs: usize) -> std::result::Result<(), String> {
+        let arity = match op {
+            "AND" | "MUL" | "XOR" | "ADD" => Some(2),
+            "NOT" | "INV" | "BUF" => Some(1),
+            "RAND" => Some(0),
+            _ => None,
+        };
+        check_arity(op, inputs, arity)
+    }
 }
 
 /// Translates tokens into arithmetic gates
@@ -328,6 +567,16 @@ impl CanConstructVariant<u64> for BlifParser<u64> {
                 .unwrap_or_else(|_| panic!("Can't convert {} into a u64", s)),
         }
     }
+
+    fn check_gate(&self, op: &str, inputs: usize) -> std::result::Result<(), String> {
+        let arity = match op {
+            "MUL" | "ADD" | "SUB" => Some(2),
+            "BUF" => Some(1),
+            "RAND" => Some(0),
+            _ => None,
+        };
+        check_arity(op, inputs, arity)
+    }
 }
 
 /// Breaks up wires that contain `_PACKED_<width>` into `<width>` bits. Uglier than the old `.attr`
@@ -336,217 +585,429 @@ impl CanConstructVariant<u64> for BlifParser<u64> {
 /// You end up needing to save a lot of things until after you've parsed the next several lines, _then_
 /// parse them all at once, which gets complicated.
 pub fn split_wire_id(id: &str) -> Vec<String> {
-    if id.contains("_PACKED_") {
-        let (base, idx) = get_base_name_and_width(id);
-        match base.split_once("_PACKED_") {
-            None => {
-                unreachable!("Already did .contains!")
-            }
-            Some((name, width_dec)) => {
-                let width: usize = width_dec
-                    .parse()
-                    .unwrap_or_else(|_| panic!("Can't parse {} as an integer", width_dec));
-                // If we ever add endianness information to packed wire names, you could throw a
-                // `.rev()` in here
-                (0..width)
-                    // Multiply the current index by the width of the wire, then add the current bit
-                    // index.
-                    .map(|i| format!("{}[{}]", name, (width * idx) + i))
-                    .collect()
+    try_split_wire_id(id).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `split_wire_id`, but reports a malformed packed wire instead of panicking.
+fn try_split_wire_id(id: &str) -> std::result::Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    unpack_wire_id(id, |name| names.push(name.to_string()))?;
+    Ok(names)
+}
+
+/// Passes each of the names `split_wire_id` would return to `emit`, formatting packed bits into
+/// the same buffer so the caller decides what gets allocated.
+fn unpack_wire_id(id: &str, mut emit: impl FnMut(&str)) -> std::result::Result<(), String> {
+    if !id.contains("_PACKED_") {
+        emit(id);
+        return Ok(());
+    }
+    let (base, idx) = base_name_and_width(id)?;
+    match base.split_once("_PACKED_") {
+        None => {
+            unreachable!("Already did .contains!")
+        }
+        Some((name, width_dec)) => {
+            let width: usize = width_dec
+                .parse()
+                .map_err(|_| format!("Can't parse {} as an integer", width_dec))?;
+            // If we ever add endianness information to packed wire names, you could throw a
+            // `.rev()` in here
+            let mut bit = String::new();
+            for i in 0..width {
+                // Multiply the current index by the width of the wire, then add the current bit
+                // index.
+                bit.clear();
+                let _ = write!(bit, "{}[{}]", name, (width * idx) + i);
+                emit(&bit);
             }
         }
-    } else {
-        // I'd love to figure out how to return an iterator here, but since something needs to own
-        // the formatted strings in the other case, I'm not sure it's possible. If only we could
-        // partially apply arguments to `format`...
-        vec![id.to_string()]
     }
+    Ok(())
 }
 
 impl<T: WireValue> BlifParser<T>
 where
     BlifParser<T>: CanConstructVariant<T>,
 {
-    fn clean_parse(&mut self) {
-        self.parsed = true;
-
-        if self.reader.is_some() {
-            let mut reader: Option<BufReader<File>> = None;
-            swap(&mut reader, &mut self.reader);
-
-            let mut current: BlifCircuitDesc<T> = Default::default();
-
-            // reserve the 0 and 1 wires for true and false.
-            assert_eq!(self.hasher.get_wire_id("$false"), 0);
-            assert_eq!(self.hasher.get_wire_id("$true"), 1);
-
-            // Push const gates for true & false
-            current.gates.push(self.construct_variant(
-                "CONST",
-                0,
-                &[],
-                Some(self.constant_from_str("$false")),
-            ));
-            current.gates.push(self.construct_variant(
-                "CONST",
-                1,
-                &[],
-                Some(self.constant_from_str("$true")),
-            ));
-
-            for line in reader.unwrap().lines().flatten() {
-                let mut line: VecDeque<&str> = line.trim().split(' ').collect();
-                let cmd = line.pop_front().unwrap();
-                match cmd {
-                    ".model" => {
-                        current.name = line.pop_front().unwrap().into();
-                    }
-                    ".inputs" => {
-                        // Break up the I/O line into chunks for each wire
-                        for chunk in parse_io(line) {
-                            // Yosys gives us the wire IDs in descending order in MSP430 because the
-                            // top-level circuit uses [lo:hi] for indexing. With packed wires, this
-                            // shouldn't matter.
-                            for name_maybe_packed in chunk.iter().rev() {
-                                // Split the wire ID into multiple (if it's packed)
-                                for name in split_wire_id(name_maybe_packed) {
-                                    // Format it with the current module name
-                                    let formatted = format_wire_id(&current.name, &name);
-                                    // Take the hash and save it.
-                                    current.inputs.push(self.hasher.get_wire_id(&formatted));
-                                }
-                            }
+    /// An `InvalidData` error for the line that was read last.
+    fn error(&self, message: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", self.location(), message),
+        )
+    }
+
+    /// Splits up a packed wire like `split_wire_id`, with each name shared through `interner`.
+    fn split_wire(&self, id: &str) -> Result<Vec<Arc<str>>> {
+        let mut names = Vec::new();
+        unpack_wire_id(id, |name| names.push(self.interner.intern(name)))
+            .map_err(|e| self.error(&e))?;
+        Ok(names)
+    }
+
+    /// Hashes `id` as `format_wire_id` would name it, without allocating the name.
+    fn wire_id(&mut self, context: &str, id: &str) -> Result<usize> {
+        match id {
+            "$true" | "$false" => Ok(self.hasher.get_wire_id(id)),
+            "$undef" => Err(self.error(&format!("{} contains an $undef wire", context))),
+            _ => Ok(self.hasher.get_scoped_wire_id(&[context, "::", id])),
+        }
+    }
+
+    /// Hashes a wire name in `current`, replacing `$undef` according to `undef_policy`. Inputs
+    /// needed to drive the replacement are added to `current`.
+    fn resolve_wire(&mut self, current: &mut BlifCircuitDesc<T>, name: &str) -> Result<usize> {
+        if name != "$undef" {
+            return self.wire_id(&current.name, name);
+        }
+
+        let wire = match self.undef_policy {
+            UndefPolicy::Error => self.wire_id(&current.name, name)?,
+            UndefPolicy::False => self.hasher.get_wire_id("$false"),
+            UndefPolicy::Input => {
+                let fresh = format!("{}::$undef[{}]", current.name, self.undefs.len());
+                let wire = self.hasher.get_wire_id(&fresh);
+                current.gates.push(Operation::Input(wire));
+                current.undef_inputs.push(wire);
+                wire
+            }
+        };
+
+        self.undefs.push(UndefUse {
+            model: current.name.clone(),
+            wire,
+        });
+        Ok(wire)
+    }
+
+    /// Starts a new circuit description, with the constant wires already driven.
+    fn start_model(&mut self) -> BlifCircuitDesc<T> {
+        let mut current: BlifCircuitDesc<T> = Default::default();
+
+        // reserve the 0 and 1 wires for true and false.
+        assert_eq!(self.hasher.get_wire_id("$false"), 0);
+        assert_eq!(self.hasher.get_wire_id("$true"), 1);
+
+        // Push const gates for true & false
+        current.gates.push(self.construct_variant(
+            "CONST",
+            0,
+            &[],
+            Some(self.constant_from_str("$false")),
+        ));
+        current.gates.push(self.construct_variant(
+            "CONST",
+            1,
+            &[],
+            Some(self.constant_from_str("$true")),
+        ));
+
+        current
+    }
+
+    /// The name to read a `.model` under, applying `duplicate_policy` if it's been seen before.
+    fn model_name(&mut self, name: &str) -> Result<Arc<str>> {
+        let name = self.interner.intern(name);
+        let first_file = match self.models.get(&name) {
+            None => {
+                self.models.insert(name.clone(), self.file);
+                return Ok(name);
+            }
+            Some(first_file) => *first_file,
+        };
+
+        let (action, read_as) = match self.duplicate_policy {
+            DuplicateModelPolicy::Error => {
+                return Err(self.error(&format!(
+                    "model {} is defined in file {} and again in file {}",
+                    name, first_file, self.file
+                )))
+            }
+            DuplicateModelPolicy::PreferFirst => {
+                self.skipping = true;
+                (DuplicateAction::KeptFirst, name.clone())
+            }
+            DuplicateModelPolicy::PreferLast => (DuplicateAction::KeptLast, name.clone()),
+            DuplicateModelPolicy::Rename => {
+                // Rename before any wires are hashed, so they don't collide with the first
+                // definition's
+                let renamed = (2..)
+                    .map(|n| format!("{}~{}", name, n))
+                    .find(|renamed| !self.models.contains_key(renamed.as_str()))
+                    .expect("Some suffix is always free");
+                let renamed = self.interner.intern(&renamed);
+                self.models.insert(renamed.clone(), self.file);
+                (DuplicateAction::Renamed(renamed.clone()), renamed)
+            }
+        };
+        self.duplicates.push(DuplicateModel {
+            name,
+            first_file,
+            file: self.file,
+            action,
+        });
+        Ok(read_as)
+    }
+
+    /// The line that was read last.
+    fn location(&self) -> SourceLocation {
+        SourceLocation {
+            file: self.file_names[self.file].clone(),
+            line: self.line,
+        }
+    }
+
+    /// Adds a single line of BLIF to `current`. Returns true once the model is finished.
+    fn parse_line(&mut self, current: &mut BlifCircuitDesc<T>, line: &str) -> Result<bool> {
+        let mut line: VecDeque<&str> = line.trim().split(' ').collect();
+        let cmd = line.pop_front().unwrap_or_default();
+        let after_subckt = replace(&mut self.after_subckt, cmd == ".subckt");
+        match cmd {
+            ".model" => {
+                let name = line
+                    .pop_front()
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| self.error("a .model line needs a name"))?;
+                current.name = self.model_name(name)?;
+                current.location = Some(self.location());
+            }
+            ".inputs" => {
+                // Break up the I/O line into chunks for each wire
+                for chunk in parse_io(line) {
+                    // Yosys gives us the wire IDs in descending order in MSP430 because the
+                    // top-level circuit uses [lo:hi] for indexing. With packed wires, this
+                    // shouldn't matter.
+                    for name_maybe_packed in chunk.iter().rev() {
+                        // Split the wire ID into multiple (if it's packed)
+                        for name in self.split_wire(name_maybe_packed)? {
+                            // Hash it within the current module and save it.
+                            current.inputs.push(self.wire_id(&current.name, &name)?);
                         }
                     }
-                    ".outputs" => {
-                        for chunk in parse_io(line) {
-                            for name_maybe_packed in chunk.iter().rev() {
-                                for name in split_wire_id(name_maybe_packed) {
-                                    let formatted = format_wire_id(&current.name, &name);
-                                    current.outputs.push(self.hasher.get_wire_id(&formatted));
-                                }
-                            }
+                }
+            }
+            ".outputs" => {
+                for chunk in parse_io(line) {
+                    for name_maybe_packed in chunk.iter().rev() {
+                        for name in self.split_wire(name_maybe_packed)? {
+                            current.outputs.push(self.wire_id(&current.name, &name)?);
                         }
                     }
-                    ".gate" => {
-                        let (op, out, mut inputs) = parse_gate(line);
-                        // get the output
-                        let out_id = self.hasher.get_wire_id(&format_wire_id(&current.name, out));
-                        // get the inputs
-                        let input_ids: Vec<usize> = inputs
-                            .drain(..)
-                            .map(|name| {
-                                self.hasher
-                                    .get_wire_id(&format_wire_id(&current.name, name))
-                            })
-                            .collect();
-                        // Turn the strings and wire IDs into an `Operation`
-                        current
-                            .gates
-                            .push(self.construct_variant(op, out_id, &input_ids, None));
-                    }
-                    ".subckt" => {
-                        let (name, mut io_pairings) = parse_subcircuit(line);
-                        let mut connections: Vec<(usize, usize)> = Vec::new();
-                        for (child_name, parent_name) in io_pairings.drain(..) {
-                            // Split both the parent and child connections if they're both packed
-                            let child_unpacked = split_wire_id(child_name);
-                            let mut parent_unpacked = split_wire_id(parent_name);
-
-                            if child_unpacked.len() != parent_unpacked.len() {
-                                // We can handle packed wires that connect to const gates by just
-                                // duplicating the connection
-                                if parent_name == "$false" || parent_name == "$true" {
-                                    parent_unpacked =
-                                        vec![parent_name.into(); child_unpacked.len()];
-                                }
-                                // but any other time we have a mismatch in sizes, it's not clear
-                                // what to do
-                                else {
-                                    panic!(
-                                        "{} expanded to {} bits, but {} expanded to {} bits",
-                                        child_name,
-                                        child_unpacked.len(),
-                                        parent_name,
-                                        parent_unpacked.len()
-                                    );
-                                }
-                                // I mean maybe if one wire is packed and the other is a single bit,
-                                // we could expand the single wire, but we haven't needed that yet.
-                            }
-
-                            // Does the `rev` on `parent_unpacked` seem weird to you? Well, it should! If a subcircuit wire uses one index convention
-                            // ([hi: lo]) and the parent wire uses another ([lo:hi]), Yosys will expect that the bit indices are inverted when
-                            // hooking up the subcircuit. For that reason, we swap around the parent wires and use descending order.
-                            // This won't always be the case. In the MSP430 circuit, all the wires in the top-level circuit use the same
-                            // convention, and all the wires in the subcircuits use the same (opposite) convention, so universal inverting works
-                            // fine here. If you use the same convention in the top-level as the subcircuits, you'll need to flip this around. If you
-                            // mix and match conventions between different subcircuits, it won't work _at all_ because we don't annotate packed wires
-                            // with an ordering convention.
-
-                            // Hopefully I remembered to document this somewhere else too. If not, sorry. At least now you know...
-                            for (cname, pname) in
-                                child_unpacked.iter().zip(parent_unpacked.iter().rev())
-                            {
-                                connections.push((
-                                    self.hasher
-                                        .get_wire_id(&format_wire_id(&current.name, pname)),
-                                    self.hasher.get_wire_id(&format_wire_id(name, cname)),
-                                ));
-                            }
+                }
+            }
+            ".gate" => {
+                let (op, out, mut inputs) = parse_gate(line).map_err(|e| self.error(&e))?;
+                self.check_gate(op, inputs.len())
+                    .map_err(|e| self.error(&e))?;
+                // get the output
+                let out_id = self.resolve_wire(current, out)?;
+                // get the inputs
+                let mut input_ids: Vec<usize> = Vec::with_capacity(inputs.len());
+                for name in inputs.drain(..) {
+                    input_ids.push(self.resolve_wire(current, name)?);
+                }
+                // Turn the strings and wire IDs into an `Operation`
+                current
+                    .gates
+                    .push(self.construct_variant(op, out_id, &input_ids, None));
+            }
+            ".subckt" => {
+                let (name, mut io_pairings) = parse_subcircuit(line).map_err(|e| self.error(&e))?;
+                let mut connections: Vec<(usize, usize)> = Vec::new();
+                let mut mismatch = None;
+                for (child_name, parent_name) in io_pairings.drain(..) {
+                    // Split both the parent and child connections if they're both packed
+                    let child_unpacked = self.split_wire(child_name)?;
+                    let mut parent_unpacked = self.split_wire(parent_name)?;
+
+                    if child_unpacked.len() != parent_unpacked.len() {
+                        // We can handle packed wires that connect to const gates by just
+                        // duplicating the connection
+                        if parent_name == "$false"
+                            || parent_name == "$true"
+                            || parent_name == "$undef"
+                        {
+                            parent_unpacked =
+                                vec![parent_unpacked[0].clone(); child_unpacked.len()];
                         }
+                        // but any other time we have a mismatch in sizes, it's not clear
+                        // what to do, so it's left for the flattener to report
+                        else {
+                            mismatch.get_or_insert(WidthMismatch {
+                                child: child_name.to_string(),
+                                child_bits: child_unpacked.len(),
+                                parent: parent_name.to_string(),
+                                parent_bits: parent_unpacked.len(),
+                            });
+                            continue;
+                        }
+                        // I mean maybe if one wire is packed and the other is a single bit,
+                        // we could expand the single wire, but we haven't needed that yet.
+                    }
 
-                        let subc = BlifSubcircuitDesc {
-                            name: name.into(),
-                            connections,
-                        };
+                    // Does the `rev` on `parent_unpacked` seem weird to you? Well, it should! If a subcircuit wire uses one index convention
+                    // ([hi: lo]) and the parent wire uses another ([lo:hi]), Yosys will expect that the bit indices are inverted when
+                    // hooking up the subcircuit. For that reason, we swap around the parent wires and use descending order.
+                    // This won't always be the case. In the MSP430 circuit, all the wires in the top-level circuit use the same
+                    // convention, and all the wires in the subcircuits use the same (opposite) convention, so universal inverting works
+                    // fine here. If you use the same convention in the top-level as the subcircuits, you'll need to flip this around. If you
+                    // mix and match conventions between different subcircuits, it won't work _at all_ because we don't annotate packed wires
+                    // with an ordering convention.
 
-                        current.add_subcircuit(subc);
+                    // Hopefully I remembered to document this somewhere else too. If not, sorry. At least now you know...
+                    for (cname, pname) in child_unpacked.iter().zip(parent_unpacked.iter().rev()) {
+                        connections.push((
+                            self.resolve_wire(current, pname)?,
+                            self.wire_id(name, cname)?,
+                        ));
                     }
-                    // These lines shouldn't be generated using the Yosys settings we've chosen, so if you see them, maybe
-                    // double check that the undersigned logic is actually correct.
-                    ".names" | ".conn" => {
-                        let from = self
-                            .hasher
-                            .get_wire_id(&format_wire_id(&current.name, line.pop_front().unwrap()));
-                        let to = self
-                            .hasher
-                            .get_wire_id(&format_wire_id(&current.name, line.pop_back().unwrap()));
-                        current
-                            .gates
-                            .push(self.construct_variant("BUF", to, &[from], None))
+                }
+
+                let subc = BlifSubcircuitDesc {
+                    name: self.interner.intern(name),
+                    connections,
+                    instance: None,
+                    location: Some(self.location()),
+                    mismatch,
+                };
+
+                current.add_subcircuit(subc);
+            }
+            // Yosys names cells this way with `write_blif -cname`. Only the names of subcircuits
+            // are kept, since gates don't have anywhere to put them.
+            ".cname" if after_subckt => {
+                if let (Some(sub), Some(name)) = (current.subcircuits.last_mut(), line.pop_front())
+                {
+                    sub.instance = Some(self.interner.intern(name));
+                }
+            }
+            // These lines shouldn't be generated using the Yosys settings we've chosen, so if you see them, maybe
+            // double check that the undersigned logic is actually correct.
+            ".names" | ".conn" => {
+                let (from, to) = match (line.pop_front(), line.pop_back()) {
+                    (Some(from), Some(to)) if !from.is_empty() => (from, to),
+                    _ => return Err(self.error(&format!("a {} line needs two wires", cmd))),
+                };
+                let from = self.resolve_wire(current, from)?;
+                let to = self.resolve_wire(current, to)?;
+                current
+                    .gates
+                    .push(self.construct_variant("BUF", to, &[from], None))
+            }
+            ".end" => return Ok(true),
+            _ => (),
+        }
+
+        Ok(false)
+    }
+
+    /// Reads lines until the end of the next `.model`, so only one model is held in memory at a
+    /// time no matter how big the file is. Moves on to the next queued file when one runs out; a
+    /// model that's cut off by the end of its file is discarded.
+    fn parse_model(&mut self) -> Result<Option<BlifCircuitDesc<T>>> {
+        let mut current = self.start_model();
+
+        loop {
+            let line = match self.readers.front_mut() {
+                None => return Ok(None),
+                Some(lines) => lines.next(),
+            };
+
+            match line {
+                Some(Ok(line)) => {
+                    self.line += 1;
+                    if !self.parse_line(&mut current, &line)? {
+                        continue;
                     }
-                    ".end" => {
-                        self.circuit.push(take(&mut current));
-                        // Push const gates for true & false to the new circuit
-                        current.gates.push(self.construct_variant(
-                            "CONST",
-                            0,
-                            &[],
-                            Some(self.constant_from_str("$false")),
-                        ));
-                        current.gates.push(self.construct_variant(
-                            "CONST",
-                            1,
-                            &[],
-                            Some(self.constant_from_str("$true")),
-                        ));
+                    if !self.skipping {
+                        return Ok(Some(current));
                     }
-                    _ => (),
+                    self.skipping = false;
+                    current = self.start_model();
+                }
+                // A file that can't be read (or isn't UTF-8) partway through would leave the
+                // model it's in half parsed, so report it and give up on the rest of the file
+                Some(Err(e)) => {
+                    self.line += 1;
+                    let error = Error::new(e.kind(), format!("{}: {}", self.location(), e));
+                    self.next_file();
+                    return Err(error);
+                }
+                None => {
+                    self.next_file();
+                    current = self.start_model();
                 }
             }
         }
     }
 
-    /// Parse the previous file and prepare to parse the next one on a subsequent call to `next`.
-    /// This lets us split up a circuit across multiple BLIF files for simplicity.
-    pub fn add_file(&mut self, new_reader: BufReader<File>) {
-        if !self.parsed {
-            self.clean_parse();
+    /// Like `Parse::new`, but reads from any buffered reader, such as a `Cursor` over BLIF that's
+    /// already in memory.
+    pub fn from_reader(reader: impl BufRead + Send + 'static) -> Self {
+        let mut parser = BlifParser::default();
+        parser.add_file(reader);
+        parser
+    }
+
+    /// Moves on to the next queued file.
+    fn next_file(&mut self) {
+        self.readers.pop_front();
+        self.file += 1;
+        self.line = 0;
+        self.after_subckt = false;
+        self.skipping = false;
+    }
+
+    /// Queues up another file to parse once the current ones are exhausted. This lets us split up
+    /// a circuit across multiple BLIF files for simplicity.
+    pub fn add_file(&mut self, new_reader: impl BufRead + Send + 'static) {
+        let name = format!("file {}", self.file_names.len());
+        self.add_named_file(&name, new_reader);
+    }
+
+    /// Same as `add_file`, but locations in the file (and the messages that mention them) use
+    /// `name`, which is usually the file's path.
+    pub fn add_named_file(&mut self, name: &str, new_reader: impl BufRead + Send + 'static) {
+        self.file_names.push(name.into());
+        let reader: Box<dyn BufRead + Send> = Box::new(new_reader);
+        self.readers.push_back(reader.lines());
+    }
+
+    /// Parses every remaining model at once. Convenient for small files, but `next` only needs
+    /// to hold one model in memory at a time, unless `duplicate_policy` is `PreferLast`.
+    pub fn parse_all(&mut self) -> Vec<BlifCircuitDesc<T>> {
+        std::iter::from_fn(|| self.next()).collect()
+    }
+
+    /// Like `parse_all`, but reports malformed input instead of panicking.
+    pub fn try_parse_all(&mut self) -> Result<Vec<BlifCircuitDesc<T>>> {
+        let mut models = Vec::new();
+        while let Some(model) = self.try_next()? {
+            models.push(model);
         }
+        Ok(models)
+    }
 
-        self.reader = Some(new_reader);
-        self.parsed = false;
+    /// Like `Parse::next`, but reports malformed input instead of panicking. Errors are
+    /// `InvalidData`, with the file and line they were found on.
+    pub fn try_next(&mut self) -> Result<Option<BlifCircuitDesc<T>>> {
+        if self.duplicate_policy != DuplicateModelPolicy::PreferLast {
+            return self.parse_model();
+        }
+        if self.buffered.is_none() {
+            let mut models = Vec::new();
+            while let Some(model) = self.parse_model()? {
+                models.push(model);
+            }
+            // Keep the last definition of each model, in the position it was read in
+            let mut seen = HashSet::new();
+            models.reverse();
+            models.retain(|model| seen.insert(model.name.clone()));
+            models.reverse();
+            self.buffered = Some(models.into());
+        }
+        Ok(self.buffered.as_mut().and_then(VecDeque::pop_front))
     }
 }
 
@@ -557,31 +1018,48 @@ where
     type Item = BlifCircuitDesc<T>;
 
     fn new(reader: BufReader<File>) -> Self {
-        BlifParser {
-            reader: Some(reader),
-            ..Default::default()
-        }
+        BlifParser::from_reader(reader)
     }
 
+    /// Parses and returns the next model. Models are read lazily, one per call, except with
+    /// `DuplicateModelPolicy::PreferLast`, the default. Pick another policy to stream big designs.
+    /// Panics on malformed input; use `try_next` to handle it instead.
     fn next(&mut self) -> Option<BlifCircuitDesc<T>> {
-        if !self.parsed {
-            self.clean_parse();
-        }
-        if !self.circuit.is_empty() {
-            Some(self.circuit.remove(0))
-        } else {
-            None
-        }
+        self.try_next().unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::VecDeque;
+    use std::io::{Cursor, ErrorKind};
+    use std::sync::Arc;
 
+    use crate::flatten::{flatten, FlattenError};
     use crate::parsers::blif::{
-        get_base_name_and_width, parse_gate, parse_io, parse_subcircuit, split_wire_id,
+        format_wire_id, get_base_name_and_width, parse_gate, parse_io, parse_subcircuit,
+        split_wire_id, BlifCircuitDesc, BlifParser, DuplicateAction, DuplicateModelPolicy,
+        UndefPolicy, WidthMismatch,
     };
+    use crate::parsers::Parse;
+    use crate::Operation;
+
+    const UNDEF_BLIF: &str = ".model top
+.inputs a
+.outputs y
+.gate AND A=a B=$undef Y=y
+.end
+";
+
+    fn blif_file(contents: impl AsRef<[u8]>) -> Cursor<Vec<u8>> {
+        Cursor::new(contents.as_ref().to_vec())
+    }
+
+    fn parser_for(contents: &str, policy: UndefPolicy) -> BlifParser<bool> {
+        let mut parser = BlifParser::<bool>::from_reader(blif_file(contents));
+        parser.undef_policy = policy;
+        parser
+    }
 
     #[test]
     fn test_gate_parsing() {
@@ -589,7 +1067,7 @@ mod tests {
             .trim()
             .split(' ')
             .collect();
-        let (op, out, inputs) = parse_gate(line);
+        let (op, out, inputs) = parse_gate(line).unwrap();
         assert_eq!(op, "AND");
         assert_eq!(out, "Output");
         assert_eq!(inputs, vec!["InputA", "InputB"]);
@@ -618,7 +1096,7 @@ mod tests {
             .trim()
             .split(' ')
             .collect();
-        let (op, pairings) = parse_subcircuit(line);
+        let (op, pairings) = parse_subcircuit(line).unwrap();
         assert_eq!(op, "memTraceEntryEncoder");
         assert_eq!(
             pairings,
@@ -680,4 +1158,321 @@ mod tests {
 
         assert_eq!(split_wire_id("foobar_PA"), vec!["foobar_PA"]);
     }
+
+    #[test]
+    #[should_panic(expected = "$undef")]
+    fn test_undef_error() {
+        parser_for(UNDEF_BLIF, UndefPolicy::Error).next();
+    }
+
+    #[test]
+    fn test_undef_false() {
+        let mut parser = parser_for(UNDEF_BLIF, UndefPolicy::False);
+        let circuit = parser.next().unwrap();
+
+        let a = circuit.inputs[0];
+        let y = circuit.outputs[0];
+        assert_eq!(circuit.gates.last(), Some(&Operation::Mul(y, a, 0)));
+        assert_eq!(parser.undefs.len(), 1);
+        assert_eq!(&*parser.undefs[0].model, "top");
+        assert_eq!(parser.undefs[0].wire, 0);
+        assert!(circuit.undef_inputs.is_empty());
+    }
+
+    #[test]
+    fn test_undef_input() {
+        let mut parser = parser_for(UNDEF_BLIF, UndefPolicy::Input);
+        let circuit = parser.next().unwrap();
+
+        let fresh = parser.undefs[0].wire;
+        assert!(fresh > 1);
+        assert!(circuit.gates.contains(&Operation::Input(fresh)));
+        assert_eq!(circuit.undef_inputs, [fresh]);
+        assert_eq!(
+            circuit.gates.last(),
+            Some(&Operation::Mul(
+                circuit.outputs[0],
+                circuit.inputs[0],
+                fresh
+            ))
+        );
+    }
+
+    #[test]
+    fn test_io_errors() {
+        let circuit = BlifCircuitDesc::<bool> {
+            name: "top".into(),
+            inputs: vec![0, 2],
+            outputs: vec![5, 3],
+            ..Default::default()
+        };
+        let errors = circuit.io_errors();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1]
+            .message
+            .starts_with("top's outputs are not contiguous!"));
+    }
+
+    #[test]
+    fn test_unreadable_line() {
+        let mut parser = BlifParser::<bool>::from_reader(blif_file(
+            b".model inv\n.inputs a\n.outputs \xffy\n.gate NOT A=a Y=y\n.end\n",
+        ));
+        let error = parser.try_next().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("file 0:3: "));
+        // The half-parsed model is dropped along with the rest of the file
+        assert!(parser.try_next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_lazy_models() {
+        let mut parser = BlifParser::<bool>::from_reader(blif_file(
+            ".model first
+.inputs a
+.outputs y
+.gate NOT A=a Y=y
+.end
+.model second
+.inputs b
+.outputs z
+.gate BUF A=b Y=z
+.end
+",
+        ));
+        parser.add_file(blif_file(
+            ".model third
+.inputs c
+.outputs x
+.gate NOT A=c Y=x
+.end
+",
+        ));
+        // The default, `PreferLast`, reads every file up front
+        parser.duplicate_policy = DuplicateModelPolicy::PreferFirst;
+
+        assert_eq!(&*parser.next().unwrap().name, "first");
+        // $false, $true, a, and y: nothing from the second model has been read yet
+        assert_eq!(parser.hasher.len(), 4);
+
+        let rest = parser.parse_all();
+        assert_eq!(
+            rest.iter().map(|c| &*c.name).collect::<Vec<_>>(),
+            vec!["second", "third"]
+        );
+        assert_eq!(rest[1].gates[0], Operation::Const(0, false));
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn test_interned_names() {
+        let mut parser = BlifParser::<bool>::from_reader(blif_file(
+            ".model top
+.inputs a b
+.outputs y z
+.subckt inv A=a Y=y
+.subckt inv A=b Y=z
+.end
+",
+        ));
+        let top = parser.next().unwrap();
+
+        let (first, second) = (&top.subcircuits[0], &top.subcircuits[1]);
+        assert!(Arc::ptr_eq(&first.name, &second.name));
+        // Hashing the pieces gives the same wires as hashing the formatted name
+        assert_eq!(
+            first.connections[0],
+            (
+                parser.hasher.get_wire_id(&format_wire_id("top", "a")),
+                parser.hasher.get_wire_id(&format_wire_id("inv", "A")),
+            )
+        );
+        assert_eq!(top.inputs[0], top.subcircuits[0].connections[0].0);
+        assert_eq!(first.name(), "inv");
+    }
+
+    #[test]
+    fn test_interned_wire_names() {
+        let mut parser = BlifParser::<bool>::from_reader(blif_file(
+            ".model top
+.inputs a_PACKED_2[0]
+.outputs y_PACKED_2[0]
+.subckt inv2 A_PACKED_2[0]=a_PACKED_2[0] Y_PACKED_2[0]=y_PACKED_2[0]
+.subckt inv2 A_PACKED_2[0]=$false Y_PACKED_2[0]=y_PACKED_2[0]
+.end
+",
+        ));
+        let top = parser.next().unwrap();
+        // Every bit name and constant is stored once, however often it comes up
+        let names = [
+            "top", "inv2", "a[0]", "a[1]", "y[0]", "y[1]", "A[0]", "A[1]", "Y[0]", "Y[1]", "$false",
+        ];
+        assert_eq!(parser.interner.len(), names.len());
+        for name in names.iter() {
+            parser.interner.intern(name);
+        }
+        assert_eq!(parser.interner.len(), names.len());
+        assert!(Arc::ptr_eq(&top.name, &parser.interner.intern("top")));
+        // Both instances connect the same child ports; the constant side differs
+        assert_eq!(
+            top.subcircuits[0].connections[2..],
+            top.subcircuits[1].connections[2..]
+        );
+    }
+
+    fn duplicated(policy: DuplicateModelPolicy) -> BlifParser<bool> {
+        let mut parser = BlifParser::<bool>::from_reader(blif_file(
+            ".model inv
+.inputs a
+.outputs y
+.gate NOT A=a Y=y
+.end
+",
+        ));
+        parser.add_file(blif_file(
+            ".model top
+.inputs a
+.outputs y
+.subckt inv A=a Y=y
+.end
+.model inv
+.inputs a
+.outputs y
+.gate BUF A=a Y=y
+.end
+",
+        ));
+        parser.duplicate_policy = policy;
+        parser
+    }
+
+    #[test]
+    fn test_duplicate_models() {
+        let not = Operation::AddConst(3, 2, true);
+
+        let mut parser = duplicated(DuplicateModelPolicy::PreferFirst);
+        let models = parser.parse_all();
+        assert_eq!(
+            models.iter().map(|m| &*m.name).collect::<Vec<_>>(),
+            ["inv", "top"]
+        );
+        assert_eq!(models[0].gates[2], not);
+        assert_eq!(parser.duplicates.len(), 1);
+        assert_eq!(
+            (parser.duplicates[0].first_file, parser.duplicates[0].file),
+            (0, 1)
+        );
+        assert_eq!(parser.duplicates[0].action, DuplicateAction::KeptFirst);
+
+        // Duplicates still parse by default
+        let mut parser = duplicated(DuplicateModelPolicy::default());
+        assert_eq!(parser.duplicate_policy, DuplicateModelPolicy::PreferLast);
+        let models = parser.parse_all();
+        assert_eq!(
+            models.iter().map(|m| &*m.name).collect::<Vec<_>>(),
+            ["top", "inv"]
+        );
+        assert_ne!(models[1].gates[2], not);
+        assert_eq!(parser.duplicates[0].action, DuplicateAction::KeptLast);
+
+        let mut parser = duplicated(DuplicateModelPolicy::Rename);
+        let models = parser.parse_all();
+        assert_eq!(
+            models.iter().map(|m| &*m.name).collect::<Vec<_>>(),
+            ["inv", "top", "inv~2"]
+        );
+        // The renamed copy has its own wires
+        assert_ne!(models[0].inputs, models[2].inputs);
+        assert_eq!(
+            parser.duplicates[0].action,
+            DuplicateAction::Renamed("inv~2".into())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "model inv is defined in file 0 and again in file 1")]
+    fn test_duplicate_model_error() {
+        duplicated(DuplicateModelPolicy::Error).parse_all();
+    }
+
+    #[test]
+    fn test_source_locations() {
+        let mut parser = BlifParser::<bool>::from_reader(blif_file(
+            ".model inv
+.inputs a
+.outputs y
+.gate NOT A=a Y=y
+.end
+",
+        ));
+        parser.add_named_file(
+            "top.blif",
+            blif_file(
+                "# Generated by Yosys
+
+.model top
+.inputs a
+.outputs y z
+.subckt inv a=a y=y
+.cname inv0
+.gate NOT A=a Y=z
+.cname not_z
+.subckt inv a=a y=z
+.end
+",
+            ),
+        );
+
+        let models = parser.parse_all();
+        let location = models[0].location.as_ref().unwrap();
+        assert_eq!((&*location.file, location.line), ("file 0", 1));
+        assert_eq!(
+            models[1].location.as_ref().unwrap().to_string(),
+            "top.blif:3"
+        );
+        let subcircuits = &models[1].subcircuits;
+        assert_eq!(subcircuits[0].instance.as_deref(), Some("inv0"));
+        assert_eq!(
+            subcircuits[0].location.as_ref().unwrap().to_string(),
+            "top.blif:6"
+        );
+        // The gate's `.cname` doesn't name the subcircuit before it
+        assert_eq!(subcircuits[1].instance, None);
+        assert_eq!(subcircuits[1].location.as_ref().unwrap().line, 10);
+    }
+
+    #[test]
+    fn test_width_mismatch_location() {
+        let models = BlifParser::<bool>::from_reader(blif_file(
+            ".model top
+.inputs b[0] b[1] b[2]
+.outputs y
+.subckt pair a_PACKED_2=b_PACKED_3 y=y
+.end
+.model pair
+.inputs a[0] a[1]
+.outputs y
+.gate AND A=a[0] B=a[1] Y=y
+.end
+",
+        ))
+        .parse_all();
+        let mismatch = WidthMismatch {
+            child: "a_PACKED_2".into(),
+            child_bits: 2,
+            parent: "b_PACKED_3".into(),
+            parent_bits: 3,
+        };
+        assert_eq!(models[0].subcircuits[0].mismatch, Some(mismatch.clone()));
+
+        // Flattening reports it against the instance, rather than the parser panicking
+        let error = flatten(&models, "top").unwrap_err();
+        assert_eq!(error.path().unwrap().location().unwrap().line, 4);
+        assert!(matches!(&error, FlattenError::Width { mismatch: m, .. } if *m == mismatch));
+        assert_eq!(
+            error.to_string(),
+            "top/pair#0 (file 0:4): \
+             a_PACKED_2 expanded to 2 bits, but b_PACKED_3 expanded to 3 bits"
+        );
+    }
 }
@@ -0,0 +1,220 @@
+//! Reads Bristol Fashion circuits, the format `BristolFashion` exports.
+//!
+//! Bristol circuits have input wires but no input gates, and output wires rather than assertions.
+//! To turn one into a program we can evaluate, the parser emits an `Input` gate for each input
+//! wire before the circuit's own gates, and an `AssertZero` on each output wire after them. That
+//! mirrors how `BristolFashion` maps assertions onto outputs.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Lines, Result};
+
+use crate::parsers::Parse;
+use crate::Operation;
+
+pub struct BristolParser<R: BufRead = BufReader<File>> {
+    lines: Lines<R>,
+    /// Total wires and output wires, once the header has been read
+    header: Option<(usize, usize)>,
+    /// Gates that have been decoded but not returned yet
+    pending: VecDeque<Operation<bool>>,
+    finished: bool,
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn numbers(line: &str) -> Result<Vec<usize>> {
+    line.split_whitespace()
+        .map(|n| {
+            n.parse()
+                .map_err(|_| invalid(format!("expected a number, found {}", n)))
+        })
+        .collect()
+}
+
+/// Sums the per-value widths on a `{count} {width_1} ... {width_count}` header line.
+fn total_width(line: &str) -> Result<usize> {
+    let fields = numbers(line)?;
+    match fields.split_first() {
+        Some((count, widths)) if *count == widths.len() => Ok(widths.iter().sum()),
+        _ => Err(invalid(format!("malformed input/output header: {}", line))),
+    }
+}
+
+fn parse_gate(line: &str) -> Result<Operation<bool>> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (op, operands) = tokens
+        .split_last()
+        .ok_or_else(|| invalid("empty gate".into()))?;
+    let operands = numbers(&operands.join(" "))?;
+    let malformed = || invalid(format!("malformed gate: {}", line));
+
+    if operands.len() < 2 || operands.len() != 2 + operands[0] + operands[1] || operands[1] != 1 {
+        return Err(malformed());
+    }
+    let inputs = &operands[2..2 + operands[0]];
+    let out = operands[operands.len() - 1];
+
+    match (*op, inputs) {
+        ("XOR", [l, r]) => Ok(Operation::Add(out, *l, *r)),
+        ("AND", [l, r]) => Ok(Operation::Mul(out, *l, *r)),
+        ("INV", [i]) | ("NOT", [i]) => Ok(Operation::AddConst(out, *i, true)),
+        ("EQW", [i]) => Ok(Operation::AddConst(out, *i, false)),
+        // For EQ, the "input" is the constant itself
+        ("EQ", [c]) if *c <= 1 => Ok(Operation::Const(out, *c == 1)),
+        _ => Err(malformed()),
+    }
+}
+
+impl<R: BufRead> BristolParser<R> {
+    pub fn from_reader(reader: R) -> Self {
+        BristolParser {
+            lines: reader.lines(),
+            header: None,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Next line with anything on it, or `None` at the end of the file.
+    fn next_line(&mut self) -> Result<Option<String>> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                return Ok(Some(line));
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_header(&mut self) -> Result<()> {
+        let mut header = Vec::with_capacity(3);
+        while header.len() < 3 {
+            header.push(
+                self.next_line()?
+                    .ok_or_else(|| invalid("truncated Bristol header".into()))?,
+            );
+        }
+
+        let wires = match numbers(&header[0])?[..] {
+            [_, wires] => wires,
+            _ => return Err(invalid(format!("malformed gate count line: {}", header[0]))),
+        };
+        let inputs = total_width(&header[1])?;
+        let outputs = total_width(&header[2])?;
+        if inputs + outputs > wires {
+            return Err(invalid(format!(
+                "{} inputs and {} outputs don't fit in {} wires",
+                inputs, outputs, wires
+            )));
+        }
+
+        self.pending.extend((0..inputs).map(Operation::Input));
+        self.header = Some((wires, outputs));
+        Ok(())
+    }
+
+    /// Like `Parse::next`, but reports malformed input instead of panicking.
+    pub fn try_next(&mut self) -> Result<Option<Operation<bool>>> {
+        if self.header.is_none() {
+            self.read_header()?;
+        }
+        if let Some(gate) = self.pending.pop_front() {
+            return Ok(Some(gate));
+        }
+        if self.finished {
+            return Ok(None);
+        }
+
+        match self.next_line()? {
+            Some(line) => parse_gate(&line).map(Some),
+            None => {
+                self.finished = true;
+                let (wires, outputs) = self.header.expect("Header was read above");
+                self.pending
+                    .extend((wires - outputs..wires).map(Operation::AssertZero));
+                Ok(self.pending.pop_front())
+            }
+        }
+    }
+
+    /// Reads the whole circuit.
+    pub fn read_all(&mut self) -> Result<Vec<Operation<bool>>> {
+        let mut gates = Vec::new();
+        while let Some(gate) = self.try_next()? {
+            gates.push(gate);
+        }
+        Ok(gates)
+    }
+}
+
+impl Parse<bool> for BristolParser {
+    type Item = Operation<bool>;
+
+    fn new(reader: BufReader<File>) -> Self {
+        BristolParser::from_reader(reader)
+    }
+
+    /// Returns the next gate. Panics on malformed input; use `try_next` to handle it instead.
+    fn next(&mut self) -> Option<Operation<bool>> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("Couldn't parse Bristol circuit: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::{BristolFashion, Export};
+    use crate::parsers::bristol::BristolParser;
+    use crate::Operation;
+
+    #[test]
+    fn test_round_trip() {
+        let mut sink = Vec::new();
+        BristolFashion::export_circuit(
+            &[
+                Operation::Input(5),
+                Operation::Input(6),
+                Operation::Mul(7, 5, 6),
+                Operation::AddConst(7, 7, true),
+                Operation::AssertZero(7),
+            ],
+            &[true, false],
+            &mut sink,
+        )
+        .unwrap();
+
+        let gates = BristolParser::from_reader(sink.as_slice())
+            .read_all()
+            .unwrap();
+        assert_eq!(
+            gates,
+            vec![
+                Operation::Input(0),
+                Operation::Input(1),
+                // The exporter bakes the witness into the circuit
+                Operation::Const(0, true),
+                Operation::Const(1, false),
+                Operation::Mul(2, 0, 1),
+                Operation::AddConst(3, 2, true),
+                Operation::AddConst(4, 3, false),
+                Operation::AssertZero(4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        let bad_gate = "1 3\n1 1\n1 1\n2 1 0 1 2 NAND\n";
+        assert!(BristolParser::from_reader(bad_gate.as_bytes())
+            .read_all()
+            .is_err());
+
+        let bad_header = "1 3\n2 1\n1 1\n";
+        assert!(BristolParser::from_reader(bad_header.as_bytes())
+            .read_all()
+            .is_err());
+    }
+}
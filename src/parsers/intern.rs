@@ -0,0 +1,38 @@
+//! Deduplicated storage for strings that parsers see over and over, like model names in BLIF
+//! files with thousands of `.subckt` lines.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// A set of shared strings. Cloning an `Interner` gives another handle to the same set, so several
+/// parsers (including ones on other threads) can share one.
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    strings: Arc<Mutex<HashSet<Arc<str>>>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the shared copy of `s`, storing it first if this is the first time it's been seen.
+    pub fn intern(&self, s: &str) -> Arc<str> {
+        let mut strings = self.strings.lock().expect("Interner was poisoned");
+        if let Some(existing) = strings.get(s) {
+            return existing.clone();
+        }
+        let shared: Arc<str> = s.into();
+        strings.insert(shared.clone());
+        shared
+    }
+
+    /// Number of distinct strings stored
+    pub fn len(&self) -> usize {
+        self.strings.lock().expect("Interner was poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
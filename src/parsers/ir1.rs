@@ -0,0 +1,807 @@
+//! Reads SIEVE IR1 text, as written by `exporters::IR1` or other SIEVE tools.
+//!
+//! Both the combined files `IR1` writes (header, witness and relation in one) and separate
+//! relation files are accepted. Boolean relations are read as `Operation<bool>` and arithmetic
+//! ones as `Operation<u64>`; arithmetic over a prime field other than 2^64 is read as is, so
+//! evaluating the gates only agrees with the field when nothing wraps around.
+//!
+//! `@short_witness` gates become `Input` gates, in order, and the values of any `short_witness`
+//! block are kept for `witness`. `@instance` gates become `Const` gates with the next value from
+//! the `instance` block, which has to come first. `@function` definitions are inlined at each
+//! `@call`: the function's outputs and inputs become the wires named in the call, its other wires
+//! are given numbers above any wire the file mentions, and each `@instance` in its body reads the
+//! next instance value. The first call makes the parser read ahead to the end of the relation, to
+//! find the largest wire. `@delete` is ignored.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Lines, Result};
+
+use crate::parsers::Parse;
+use crate::{Domain, Field, HasIO, Operation, Translatable, WireValue};
+
+/// Values that can appear as IR1 literals (`< 1 >`).
+pub trait IR1Value: WireValue {
+    fn from_literal(value: u64) -> Option<Self>;
+}
+
+impl IR1Value for bool {
+    fn from_literal(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl IR1Value for u64 {
+    fn from_literal(value: u64) -> Option<Self> {
+        Some(value)
+    }
+}
+
+/// Longest `$first ... $last` range the parser expands. Anything longer is more likely to be a
+/// typo (or a file built to exhaust memory) than a real list of wires.
+pub const MAX_RANGE: usize = 1 << 20;
+
+fn invalid(line: usize, message: String) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+/// How a statement ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Terminator {
+    Semicolon,
+    Begin,
+    End,
+}
+
+/// Splits IR1 text into statements, each ending in `;`, `@begin` or `@end`, with comments
+/// removed. Statements can span lines.
+struct Statements<R> {
+    lines: Lines<R>,
+    line: usize,
+    /// The unfinished statement so far, and the line it started on
+    partial: String,
+    start: usize,
+    ready: VecDeque<(usize, String, Terminator)>,
+}
+
+/// Position of the first `word` in `text` that isn't the start of a longer word.
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    text.match_indices(word)
+        .map(|(i, _)| i)
+        .find(|i| !text[i + word.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+}
+
+impl<R: BufRead> Statements<R> {
+    fn new(reader: R) -> Self {
+        Statements {
+            lines: reader.lines(),
+            line: 0,
+            partial: String::new(),
+            start: 0,
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<(usize, String, Terminator)>> {
+        while self.ready.is_empty() {
+            if !self.read_line()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.ready.pop_front())
+    }
+
+    /// Reads every statement left, so `ready` holds the rest of the file.
+    fn read_rest(&mut self) -> Result<()> {
+        while self.read_line()? {}
+        Ok(())
+    }
+
+    /// Splits the next line into statements, returning false at the end of the file.
+    fn read_line(&mut self) -> Result<bool> {
+        let line = match self.lines.next() {
+            Some(line) => line?,
+            None if self.partial.trim().is_empty() => return Ok(false),
+            None => {
+                return Err(invalid(
+                    self.start,
+                    format!("unterminated statement {}", self.partial.trim()),
+                ))
+            }
+        };
+        self.line += 1;
+        let mut rest = line.split("//").next().unwrap_or_default();
+        loop {
+            if self.partial.trim().is_empty() {
+                self.start = self.line;
+            }
+            let found = [
+                (rest.find(';'), Terminator::Semicolon, 1),
+                (find_word(rest, "@begin"), Terminator::Begin, 6),
+                (find_word(rest, "@end"), Terminator::End, 4),
+            ]
+            .iter()
+            .filter_map(|&(pos, end, len)| pos.map(|pos| (pos, end, len)))
+            .min_by_key(|(pos, _, _)| *pos);
+            match found {
+                Some((pos, end, len)) => {
+                    self.partial.push_str(&rest[..pos]);
+                    let statement = std::mem::take(&mut self.partial).trim().to_string();
+                    self.ready.push_back((self.start, statement, end));
+                    rest = &rest[pos + len..];
+                }
+                None => {
+                    self.partial.push_str(rest);
+                    self.partial.push(' ');
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+/// The largest wire number in `text`, which saturates rather than failing on numbers too big to
+/// be wires; parsing the statement reports those.
+fn largest_wire(text: &str) -> Option<usize> {
+    text.split('$')
+        .skip(1)
+        .filter_map(|rest| {
+            let rest = rest.trim_start();
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            (digits > 0).then(|| rest[..digits].parse().unwrap_or(usize::MAX))
+        })
+        .max()
+}
+
+/// An argument to a gate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Arg<T> {
+    Wire(usize),
+    Literal(T),
+}
+
+/// A statement of a function body, in the function's own wire numbering.
+enum Step<T: WireValue> {
+    Gate(Operation<T>),
+    /// `$wire <- @instance;`, which reads the next instance value at each call
+    Instance(usize),
+}
+
+/// A function definition.
+struct Function<T: WireValue> {
+    outputs: usize,
+    inputs: usize,
+    /// Number of wires that are neither outputs nor inputs
+    locals: usize,
+    body: Vec<Step<T>>,
+}
+
+/// Where the parser is in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Header,
+    Body,
+    Finished,
+}
+
+pub struct IR1Parser<T: IR1Value, R: BufRead = BufReader<File>> {
+    statements: Statements<R>,
+    state: State,
+    field: Option<Field>,
+    witness: Vec<T>,
+    instance: VecDeque<T>,
+    functions: HashMap<String, Function<T>>,
+    /// Gates that have been decoded but not returned yet
+    pending: VecDeque<Operation<T>>,
+    /// One more than the highest wire seen so far
+    next_wire: usize,
+    /// The first wire free for the locals of the next call, once a call has looked ahead for it
+    next_local: Option<usize>,
+}
+
+fn wire(line: usize, text: &str) -> Result<usize> {
+    text.trim()
+        .strip_prefix('$')
+        .and_then(|w| w.trim().parse().ok())
+        .ok_or_else(|| invalid(line, format!("expected a wire, found {}", text.trim())))
+}
+
+/// Parses a comma-separated list of wires and `$first ... $last` ranges.
+fn wires(line: usize, text: &str) -> Result<Vec<usize>> {
+    let mut wires = Vec::new();
+    for part in text.split(',').filter(|p| !p.trim().is_empty()) {
+        match part.split_once("...") {
+            Some((first, last)) => {
+                let (first, last) = (wire(line, first)?, wire(line, last)?);
+                if last.saturating_sub(first) >= MAX_RANGE {
+                    return Err(invalid(
+                        line,
+                        format!(
+                            "range ${} ... ${} is longer than {} wires",
+                            first, last, MAX_RANGE
+                        ),
+                    ));
+                }
+                wires.extend(first..=last)
+            }
+            None => wires.push(wire(line, part)?),
+        }
+    }
+    Ok(wires)
+}
+
+fn literal<T: IR1Value>(line: usize, text: &str) -> Result<T> {
+    let text = text.trim();
+    let inner = text
+        .strip_prefix('<')
+        .and_then(|t| t.strip_suffix('>'))
+        .map(str::trim)
+        .ok_or_else(|| invalid(line, format!("expected a literal, found {}", text)))?;
+    let value = match inner.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => inner.parse().ok(),
+    };
+    value
+        .and_then(T::from_literal)
+        .ok_or_else(|| invalid(line, format!("{} isn't a {:?} value", text, T::DOMAIN)))
+}
+
+/// Splits `@name(args)` into the name and the text of the arguments.
+fn directive(text: &str) -> Option<(&str, &str)> {
+    let (name, args) = text.strip_prefix('@')?.split_once('(')?;
+    Some((name.trim(), args.trim().strip_suffix(')')?))
+}
+
+impl<T: IR1Value, R: BufRead> IR1Parser<T, R> {
+    pub fn from_reader(reader: R) -> Self {
+        IR1Parser {
+            statements: Statements::new(reader),
+            state: State::Header,
+            field: None,
+            witness: Vec::new(),
+            instance: VecDeque::new(),
+            functions: HashMap::new(),
+            pending: VecDeque::new(),
+            next_wire: 0,
+            next_local: None,
+        }
+    }
+
+    /// The field the header declares, reading the header if it hasn't been read yet.
+    pub fn field(&mut self) -> Result<Option<Field>> {
+        self.read_header()?;
+        Ok(self.field)
+    }
+
+    /// The values in the `short_witness` block, once the header has been read.
+    pub fn witness(&self) -> &[T] {
+        &self.witness
+    }
+
+    /// Reads the values in a `short_witness` or `instance` block.
+    fn read_values(&mut self) -> Result<Vec<T>> {
+        let mut values = Vec::new();
+        loop {
+            match self.statements.next()? {
+                Some((_, s, Terminator::End)) if s.is_empty() => return Ok(values),
+                Some((line, s, Terminator::Semicolon)) => values.push(literal(line, &s)?),
+                Some((line, s, _)) => {
+                    return Err(invalid(line, format!("expected a value, found {}", s)))
+                }
+                None => return Err(invalid(self.statements.line, "missing @end".into())),
+            }
+        }
+    }
+
+    /// Reads everything before the `@begin` of the relation.
+    fn read_header(&mut self) -> Result<()> {
+        while self.state == State::Header {
+            let (line, statement, end) = self
+                .statements
+                .next()?
+                .ok_or_else(|| invalid(self.statements.line, "missing relation body".into()))?;
+            let mut words: Vec<&str> = statement.split_whitespace().collect();
+            // Relation files mark where the relation starts with a bare keyword
+            if words.first() == Some(&"relation") {
+                words.remove(0);
+            }
+            match (&words[..], end) {
+                ([], Terminator::Begin) => self.state = State::Body,
+                (["short_witness"], Terminator::Begin) => self.witness = self.read_values()?,
+                (["instance"], Terminator::Begin) => self.instance = self.read_values()?.into(),
+                (["field", "characteristic", p, "degree", d], Terminator::Semicolon) => {
+                    let (p, d) = p.parse().ok().zip(d.parse().ok()).ok_or_else(|| {
+                        invalid(line, format!("malformed field declaration {}", statement))
+                    })?;
+                    let field = Field {
+                        characteristic: p,
+                        degree: d,
+                    };
+                    if T::DOMAIN == Domain::GF2 && field != Field::GF2 {
+                        return Err(invalid(
+                            line,
+                            format!("{} isn't a boolean field", statement),
+                        ));
+                    }
+                    self.field = Some(field);
+                }
+                (["version", ..], Terminator::Semicolon)
+                | (["features:", ..], Terminator::Semicolon) => {}
+                (["gate_set:", ..], Terminator::Semicolon) => {
+                    let boolean = statement.contains("boolean")
+                        || ["@xor", "@and", "@not"]
+                            .iter()
+                            .any(|g| statement.contains(g));
+                    if boolean != (T::DOMAIN == Domain::GF2) {
+                        return Err(invalid(
+                            line,
+                            format!("can't read {} as {:?} gates", statement, T::DOMAIN),
+                        ));
+                    }
+                }
+                _ => return Err(invalid(line, format!("unexpected {} in header", statement))),
+            }
+        }
+        Ok(())
+    }
+
+    fn note_wires(&mut self, gate: &Operation<T>) {
+        if let Some(max) = gate.inputs().chain(gate.outputs()).max() {
+            self.next_wire = self.next_wire.max(max + 1);
+        }
+    }
+
+    /// Decodes one statement into the gates it stands for (any number, since calls are inlined).
+    fn gates(&mut self, line: usize, statement: &str) -> Result<Vec<Operation<T>>> {
+        let (outputs, rhs) = match statement.split_once("<-") {
+            Some((lhs, rhs)) => (wires(line, lhs)?, rhs.trim()),
+            None => (Vec::new(), statement),
+        };
+        let single = || match outputs[..] {
+            [dst] => Ok(dst),
+            _ => Err(invalid(
+                line,
+                format!("expected one output in {}", statement),
+            )),
+        };
+
+        if rhs == "@short_witness" {
+            return Ok(vec![Operation::Input(single()?)]);
+        }
+        if rhs == "@instance" {
+            let value = self
+                .instance
+                .pop_front()
+                .ok_or_else(|| invalid(line, "ran out of instance values".into()))?;
+            return Ok(vec![Operation::Const(single()?, value)]);
+        }
+        if rhs.starts_with('<') {
+            return Ok(vec![Operation::Const(single()?, literal(line, rhs)?)]);
+        }
+        if rhs.starts_with('$') {
+            let zero = T::from_literal(0).expect("Zero is in every domain");
+            return Ok(vec![Operation::AddConst(single()?, wire(line, rhs)?, zero)]);
+        }
+
+        let (name, args) = directive(rhs)
+            .ok_or_else(|| invalid(line, format!("malformed statement {}", statement)))?;
+        if name == "call" {
+            return self.call(line, outputs, args);
+        }
+        if name == "delete" {
+            return Ok(Vec::new());
+        }
+        let args = args
+            .split(',')
+            .map(|arg| {
+                if arg.trim().starts_with('<') {
+                    literal(line, arg).map(Arg::Literal)
+                } else {
+                    wire(line, arg).map(Arg::Wire)
+                }
+            })
+            .collect::<Result<Vec<Arg<T>>>>()?;
+        if name == "assert_zero" && outputs.is_empty() {
+            if let [Arg::Wire(w)] = args[..] {
+                return Ok(vec![Operation::AssertZero(w)]);
+            }
+        }
+
+        let boolean = T::DOMAIN == Domain::GF2;
+        let dst = single()?;
+        let gate = match (name, &args[..]) {
+            ("xor", [Arg::Wire(l), Arg::Wire(r)]) if boolean => Some(Operation::Add(dst, *l, *r)),
+            ("and", [Arg::Wire(l), Arg::Wire(r)]) if boolean => Some(Operation::Mul(dst, *l, *r)),
+            ("add", [Arg::Wire(l), Arg::Wire(r)]) => Some(Operation::Add(dst, *l, *r)),
+            ("mul", [Arg::Wire(l), Arg::Wire(r)]) => Some(Operation::Mul(dst, *l, *r)),
+            ("not", [Arg::Wire(i)]) if boolean => {
+                T::from_literal(1).map(|one| Operation::AddConst(dst, *i, one))
+            }
+            (
+                "xor" | "add" | "addc",
+                [Arg::Wire(i), Arg::Literal(c)] | [Arg::Literal(c), Arg::Wire(i)],
+            ) if boolean || name != "xor" => Some(Operation::AddConst(dst, *i, *c)),
+            (
+                "and" | "mul" | "mulc",
+                [Arg::Wire(i), Arg::Literal(c)] | [Arg::Literal(c), Arg::Wire(i)],
+            ) if boolean || name != "and" => Some(Operation::MulConst(dst, *i, *c)),
+            _ => None,
+        };
+        gate.map(|gate| vec![gate])
+            .ok_or_else(|| invalid(line, format!("unsupported {:?} gate {}", T::DOMAIN, rhs)))
+    }
+
+    /// The first wire free for locals of a call, reading ahead to find the largest wire in the
+    /// file the first time.
+    fn locals_base(&mut self, line: usize, call: &str) -> Result<usize> {
+        if let Some(base) = self.next_local {
+            return Ok(base);
+        }
+        self.statements.read_rest()?;
+        let largest = self
+            .statements
+            .ready
+            .iter()
+            .filter_map(|(_, statement, _)| largest_wire(statement))
+            .chain(largest_wire(call))
+            .max();
+        let base = match largest {
+            Some(largest) => largest
+                .checked_add(1)
+                .ok_or_else(|| invalid(line, "no wires are left for function locals".into()))?,
+            None => 0,
+        };
+        Ok(base.max(self.next_wire))
+    }
+
+    /// Inlines a call to a function defined earlier.
+    fn call(&mut self, line: usize, outputs: Vec<usize>, args: &str) -> Result<Vec<Operation<T>>> {
+        let base = self.locals_base(line, args)?;
+        let (name, inputs) = args.split_once(',').unwrap_or((args, ""));
+        let inputs = wires(line, inputs)?;
+        let function = self
+            .functions
+            .get(name.trim())
+            .ok_or_else(|| invalid(line, format!("call to undefined function {}", name.trim())))?;
+        if outputs.len() != function.outputs || inputs.len() != function.inputs {
+            return Err(invalid(
+                line,
+                format!(
+                    "{} takes {} inputs and has {} outputs, but the call has {} and {}",
+                    name.trim(),
+                    function.inputs,
+                    function.outputs,
+                    inputs.len(),
+                    outputs.len()
+                ),
+            ));
+        }
+        let next_local = base
+            .checked_add(function.locals)
+            .ok_or_else(|| invalid(line, "no wires are left for function locals".into()))?;
+
+        let map = |w: usize| match w {
+            w if w < outputs.len() => outputs[w],
+            w if w < outputs.len() + inputs.len() => inputs[w - outputs.len()],
+            w => base + w - outputs.len() - inputs.len(),
+        };
+        let mut gates = Vec::with_capacity(function.body.len());
+        for step in &function.body {
+            gates.push(match step {
+                Step::Gate(gate) => gate
+                    .translate(gate.inputs().map(map), gate.outputs().map(map))
+                    .expect("Operations are always translatable"),
+                Step::Instance(dst) => {
+                    let value = self
+                        .instance
+                        .pop_front()
+                        .ok_or_else(|| invalid(line, "ran out of instance values".into()))?;
+                    Operation::Const(map(*dst), value)
+                }
+            });
+        }
+        self.next_local = Some(next_local);
+        Ok(gates)
+    }
+
+    /// Reads a function definition, up to the `@end` of its body.
+    fn define(&mut self, line: usize, signature: &str) -> Result<()> {
+        let mut parts = signature.split(',').map(str::trim);
+        let name = parts.next().unwrap_or_default().to_string();
+        let mut counts = HashMap::new();
+        for part in parts {
+            let (key, value) = part
+                .split_once(':')
+                .and_then(|(k, v)| Some((k.trim(), v.trim().parse::<usize>().ok()?)))
+                .ok_or_else(|| invalid(line, format!("malformed function signature {}", part)))?;
+            counts.insert(key, value);
+        }
+        let count = |key: &str| counts.get(key).copied().unwrap_or(0);
+        let (outputs, inputs) = (count("@out"), count("@in"));
+
+        let mut body = Vec::new();
+        loop {
+            match self.statements.next()? {
+                Some((_, s, Terminator::End)) if s.is_empty() => break,
+                Some((line, s, Terminator::Semicolon)) => {
+                    if s.contains("@call") {
+                        return Err(invalid(
+                            line,
+                            "calls inside functions aren't supported".into(),
+                        ));
+                    }
+                    match s.split_once("<-") {
+                        Some((lhs, rhs)) if rhs.trim() == "@instance" => {
+                            match wires(line, lhs)?[..] {
+                                [dst] => body.push(Step::Instance(dst)),
+                                _ => {
+                                    return Err(invalid(
+                                        line,
+                                        format!("expected one output in {}", s),
+                                    ))
+                                }
+                            }
+                        }
+                        _ => body.extend(self.gates(line, &s)?.into_iter().map(Step::Gate)),
+                    }
+                }
+                Some((line, s, _)) => {
+                    return Err(invalid(line, format!("unexpected {} in function body", s)))
+                }
+                None => return Err(invalid(self.statements.line, "missing @end".into())),
+            }
+        }
+        let wires = body.iter().flat_map(|step| match step {
+            Step::Gate(gate) => gate.inputs().chain(gate.outputs()).max(),
+            Step::Instance(dst) => Some(*dst),
+        });
+        let locals = wires.max().map_or(0, |largest| {
+            largest
+                .saturating_add(1)
+                .saturating_sub(outputs.saturating_add(inputs))
+        });
+        self.functions.insert(
+            name,
+            Function {
+                outputs,
+                inputs,
+                locals,
+                body,
+            },
+        );
+        Ok(())
+    }
+
+    /// Like `Parse::next`, but reports malformed input instead of panicking.
+    pub fn try_next(&mut self) -> Result<Option<Operation<T>>> {
+        self.read_header()?;
+        while self.pending.is_empty() && self.state == State::Body {
+            let (line, statement, end) = self
+                .statements
+                .next()?
+                .ok_or_else(|| invalid(self.statements.line, "missing @end".into()))?;
+            match end {
+                Terminator::End if statement.is_empty() => self.state = State::Finished,
+                Terminator::Begin => match directive(&statement) {
+                    Some(("function", signature)) => self.define(line, signature)?,
+                    _ => return Err(invalid(line, format!("unexpected {} @begin", statement))),
+                },
+                Terminator::Semicolon => {
+                    let gates = self.gates(line, &statement)?;
+                    for gate in &gates {
+                        self.note_wires(gate);
+                    }
+                    self.pending.extend(gates);
+                }
+                Terminator::End => {
+                    return Err(invalid(line, format!("unexpected {} @end", statement)))
+                }
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Reads the whole relation.
+    pub fn read_all(&mut self) -> Result<Vec<Operation<T>>> {
+        let mut gates = Vec::new();
+        while let Some(gate) = self.try_next()? {
+            gates.push(gate);
+        }
+        Ok(gates)
+    }
+}
+
+impl Parse<bool> for IR1Parser<bool> {
+    type Item = Operation<bool>;
+
+    fn new(reader: BufReader<File>) -> Self {
+        IR1Parser::from_reader(reader)
+    }
+
+    /// Returns the next gate. Panics on malformed input; use `try_next` to handle it instead.
+    fn next(&mut self) -> Option<Operation<bool>> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("Couldn't parse IR1 relation: {}", e))
+    }
+}
+
+impl Parse<u64> for IR1Parser<u64> {
+    type Item = Operation<u64>;
+
+    fn new(reader: BufReader<File>) -> Self {
+        IR1Parser::from_reader(reader)
+    }
+
+    /// Returns the next gate. Panics on malformed input; use `try_next` to handle it instead.
+    fn next(&mut self) -> Option<Operation<u64>> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("Couldn't parse IR1 relation: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::{Export, FunctionOptions, IR1};
+    use crate::parsers::ir1::IR1Parser;
+    use crate::{Field, Operation};
+
+    #[test]
+    fn test_round_trip() {
+        let gates = vec![
+            Operation::Input(1),
+            Operation::Input(2),
+            Operation::Input(3),
+            Operation::Add(4, 1, 3),
+            Operation::Mul(6, 2, 4),
+            Operation::AddConst(0, 6, true),
+            Operation::Const(7, false),
+            Operation::MulConst(8, 7, true),
+            Operation::AssertZero(0),
+        ];
+        let mut sink = Vec::new();
+        IR1::export_circuit(&gates, &[false, true, true], &mut sink).unwrap();
+
+        let mut parser = IR1Parser::<bool, _>::from_reader(sink.as_slice());
+        assert_eq!(parser.field().unwrap(), Some(Field::GF2));
+        assert_eq!(parser.witness(), [false, true, true]);
+        assert_eq!(parser.read_all().unwrap(), gates);
+    }
+
+    #[test]
+    fn test_functions() {
+        // Four copies of the same stretch, so the exporter turns them into calls
+        let mut gates = vec![Operation::Input(0)];
+        for i in 0..4 {
+            let base = 3 * i;
+            gates.extend([
+                Operation::Input(base + 1),
+                Operation::Add(base + 2, base, base + 1),
+                Operation::AddConst(base + 3, base + 2, true),
+                Operation::AssertZero(base + 2),
+            ]);
+        }
+        // Each stretch asserts that its input matches the previous stretch's output
+        let witness = [false, false, true, true, true];
+        let mut sink = Vec::new();
+        IR1::export_with_functions(&gates, &witness, FunctionOptions::default(), &mut sink)
+            .unwrap();
+        assert!(String::from_utf8_lossy(&sink).contains("@call"));
+
+        let parsed = IR1Parser::<bool, _>::from_reader(sink.as_slice())
+            .read_all()
+            .unwrap();
+        assert_eq!(parsed.len(), gates.len());
+        crate::evaluate_composite_program(
+            &parsed
+                .into_iter()
+                .map(crate::CombineOperation::GF2)
+                .collect::<Vec<_>>(),
+            &witness,
+            &[],
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let text = "version 1.0.0;
+field characteristic 101 degree 1;
+relation
+gate_set: arithmetic;
+features: simple;
+@begin
+  $0 <- @short_witness;  // x
+  $1 <- @mulc($0, < 0x10 >);
+  $2 <- @add($1,
+             $0);
+  $3 <- $2; @delete($1);
+  @assert_zero($3);
+@end
+";
+        let mut parser = IR1Parser::<u64, _>::from_reader(text.as_bytes());
+        assert_eq!(parser.field().unwrap(), Some(Field::prime(101)));
+        assert_eq!(
+            parser.read_all().unwrap(),
+            [
+                Operation::Input(0),
+                Operation::MulConst(1, 0, 16),
+                Operation::Add(2, 1, 0),
+                Operation::AddConst(3, 2, 0),
+                Operation::AssertZero(3),
+            ]
+        );
+
+        // Boolean relations can't be read as arithmetic ones, or the other way around
+        assert!(IR1Parser::<bool, _>::from_reader(text.as_bytes())
+            .read_all()
+            .is_err());
+        let bad = "version 1.0.0;\nfield characteristic 2 degree 1;\n\
+                   @begin\n$0 <- @nand($1, $2);\n@end\n";
+        let error = IR1Parser::<bool, _>::from_reader(bad.as_bytes())
+            .read_all()
+            .unwrap_err();
+        assert!(error.to_string().starts_with("line 4:"));
+    }
+    #[test]
+    fn test_function_locals() {
+        let text = "version 1.0.0;
+field characteristic 101 degree 1;
+instance @begin
+  < 5 >;
+  < 7 >;
+@end
+relation
+gate_set: arithmetic;
+@begin
+  @function(offset, @out: 1, @in: 1)
+  @begin
+    $2 <- @instance;
+    $0 <- @add($1, $2);
+  @end
+  $0 <- @short_witness;
+  $1 <- @call(offset, $0);
+  $2 <- @call(offset, $1);
+  $3 <- @short_witness;
+  $4 <- @add($2, $3);
+@end
+";
+        // Locals go past every wire in the file, and each call reads its own instance value
+        assert_eq!(
+            IR1Parser::<u64, _>::from_reader(text.as_bytes())
+                .read_all()
+                .unwrap(),
+            [
+                Operation::Input(0),
+                Operation::Const(5, 5),
+                Operation::Add(1, 0, 5),
+                Operation::Const(6, 7),
+                Operation::Add(2, 1, 6),
+                Operation::Input(3),
+                Operation::Add(4, 2, 3),
+            ]
+        );
+
+        let short = text.replace("  < 7 >;\n", "");
+        let error = IR1Parser::<u64, _>::from_reader(short.as_bytes())
+            .read_all()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "line 16: ran out of instance values");
+
+        let huge = text.replace(
+            "$2 <- @call(offset, $1)",
+            "$2 ... $4000000 <- @call(offset, $1)",
+        );
+        let error = IR1Parser::<u64, _>::from_reader(huge.as_bytes())
+            .read_all()
+            .unwrap_err();
+        assert!(error.to_string().contains("longer than 1048576 wires"));
+    }
+}
@@ -0,0 +1,348 @@
+//! A line-oriented JSON format for gates, meant as a pipe format between mcircuit and tools
+//! written in other languages.
+//!
+//! Each non-blank line is a JSON object describing one gate:
+//!
+//! ```text
+//! {"op": "mulc", "domain": "z64", "args": [3, 2], "const": 7}
+//! ```
+//!
+//! * `op` is one of `input`, `random`, `add`, `addc`, `sub`, `subc`, `mul`, `mulc`,
+//!   `assert_zero` and `const`, which need a `domain`, or `b2a` and `size_hint`, which don't.
+//! * `domain` is `gf2` or `z64`.
+//! * `args` holds the gate's wire indices, destination first, in the same order as the fields of
+//!   the matching `Operation` or `CombineOperation` variant. `b2a` takes the Z64 destination and
+//!   the lowest GF2 source wire, and `size_hint` the Z64 and GF2 wire counts.
+//! * `const` is required for `addc`, `subc`, `mulc` and `const`, and not allowed anywhere else. For
+//!   GF2 it's `true`, `false`, `0` or `1`. For Z64 it's an integer from 0 to 2^64 - 1, or a string
+//!   holding one in decimal, since many JSON libraries can't represent integers above 2^53.
+//!
+//! Lines that don't follow the schema are reported along with their line number, and parsing
+//! carries on with the next line.
+//!
+//! `exporters::JSONL` writes this format, and every gate reads back exactly as it was written.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use serde_json::{Map, Value};
+
+use crate::parsers::Parse;
+use crate::{CombineOperation, Operation, WireValue};
+
+/// A line that couldn't be read or didn't describe a gate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonlError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JsonlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for JsonlError {}
+
+/// Reads gates from JSONL, one line at a time. Yields each gate, or the problem with the line
+/// it should have been on. Reading stops after an I/O error.
+pub struct JsonlParser<R> {
+    lines: std::io::Lines<R>,
+    line: usize,
+    failed: bool,
+}
+
+impl<R: BufRead> JsonlParser<R> {
+    pub fn from_reader(reader: R) -> Self {
+        JsonlParser {
+            lines: reader.lines(),
+            line: 0,
+            failed: false,
+        }
+    }
+
+    /// Reads every line, returning the well-formed gates and the problems with the rest.
+    pub fn read_all(self) -> (Vec<CombineOperation>, Vec<JsonlError>) {
+        let mut gates = Vec::new();
+        let mut errors = Vec::new();
+        for result in self {
+            match result {
+                Ok(gate) => gates.push(gate),
+                Err(e) => errors.push(e),
+            }
+        }
+        (gates, errors)
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlParser<R> {
+    type Item = Result<CombineOperation, JsonlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed {
+            let text = self.lines.next()?;
+            self.line += 1;
+            let line = self.line;
+            let error = |message: String| JsonlError { line, message };
+            match text {
+                Ok(text) if text.trim().is_empty() => continue,
+                Ok(text) => return Some(parse_gate(&text).map_err(error)),
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(error(e.to_string())));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<R: BufRead> JsonlParser<R> {
+    /// The next gate in one domain, skipping size hints. Gates in the other domain, or
+    /// conversions, are errors, since a single-domain parse would have to drop them.
+    fn next_in<T: WireValue>(
+        &mut self,
+        pick: fn(CombineOperation) -> Option<Operation<T>>,
+    ) -> Option<Result<Operation<T>, JsonlError>> {
+        loop {
+            let gate = match Iterator::next(self)? {
+                Ok(CombineOperation::SizeHint(_, _)) => continue,
+                Ok(gate) => gate,
+                Err(e) => return Some(Err(e)),
+            };
+            return Some(pick(gate).ok_or_else(|| JsonlError {
+                line: self.line,
+                message: format!("{:?} isn't a {:?} gate", gate, T::DOMAIN),
+            }));
+        }
+    }
+}
+
+/// Reads a GF2 circuit. Panics on lines that aren't GF2 gates or size hints; iterate over the
+/// parser to handle them instead.
+impl Parse<bool> for JsonlParser<BufReader<File>> {
+    type Item = Operation<bool>;
+
+    fn new(reader: BufReader<File>) -> Self {
+        JsonlParser::from_reader(reader)
+    }
+
+    fn next(&mut self) -> Option<Operation<bool>> {
+        let gate = self.next_in(|gate| match gate {
+            CombineOperation::GF2(op) => Some(op),
+            _ => None,
+        })?;
+        Some(gate.unwrap_or_else(|e| panic!("Couldn't parse JSONL circuit: {}", e)))
+    }
+}
+
+/// Reads a Z64 circuit, the same way as a GF2 one.
+impl Parse<u64> for JsonlParser<BufReader<File>> {
+    type Item = Operation<u64>;
+
+    fn new(reader: BufReader<File>) -> Self {
+        JsonlParser::from_reader(reader)
+    }
+
+    fn next(&mut self) -> Option<Operation<u64>> {
+        let gate = self.next_in(|gate| match gate {
+            CombineOperation::Z64(op) => Some(op),
+            _ => None,
+        })?;
+        Some(gate.unwrap_or_else(|e| panic!("Couldn't parse JSONL circuit: {}", e)))
+    }
+}
+
+fn wire(value: &Value) -> Result<usize, String> {
+    value
+        .as_u64()
+        .and_then(|w| usize::try_from(w).ok())
+        .ok_or_else(|| format!("{} isn't a wire index", value))
+}
+
+fn bool_const(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::Number(n) if n.as_u64() == Some(0) => Ok(false),
+        Value::Number(n) if n.as_u64() == Some(1) => Ok(true),
+        _ => Err(format!("{} isn't a GF2 constant", value)),
+    }
+}
+
+fn arith_const(value: &Value) -> Result<u64, String> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{} isn't a Z64 constant", value))
+}
+
+/// Builds the operation named `op` from its wires and constant, once the arguments are known to
+/// be the right shape.
+fn operation<T: WireValue>(op: &str, args: &[usize], c: Option<T>) -> Operation<T> {
+    match (op, c) {
+        ("input", _) => Operation::Input(args[0]),
+        ("random", _) => Operation::Random(args[0]),
+        ("add", _) => Operation::Add(args[0], args[1], args[2]),
+        ("sub", _) => Operation::Sub(args[0], args[1], args[2]),
+        ("mul", _) => Operation::Mul(args[0], args[1], args[2]),
+        ("addc", Some(c)) => Operation::AddConst(args[0], args[1], c),
+        ("subc", Some(c)) => Operation::SubConst(args[0], args[1], c),
+        ("mulc", Some(c)) => Operation::MulConst(args[0], args[1], c),
+        ("assert_zero", _) => Operation::AssertZero(args[0]),
+        ("const", Some(c)) => Operation::Const(args[0], c),
+        _ => unreachable!("Checked against the schema already"),
+    }
+}
+
+/// Parses one line, checking it against the schema in the module documentation.
+pub fn parse_gate(text: &str) -> Result<CombineOperation, String> {
+    let object: Map<String, Value> = match serde_json::from_str(text) {
+        Ok(Value::Object(object)) => object,
+        Ok(_) => return Err("expected a JSON object".into()),
+        Err(e) => return Err(format!("invalid JSON: {}", e)),
+    };
+    if let Some(key) = object
+        .keys()
+        .find(|k| !["op", "domain", "args", "const"].contains(&k.as_str()))
+    {
+        return Err(format!("unknown field {}", key));
+    }
+
+    let op = object
+        .get("op")
+        .and_then(Value::as_str)
+        .ok_or("missing op")?;
+    let (arity, has_const, has_domain) = match op {
+        "input" | "random" | "assert_zero" => (1, false, true),
+        "add" | "sub" | "mul" => (3, false, true),
+        "addc" | "subc" | "mulc" => (2, true, true),
+        "const" => (1, true, true),
+        "b2a" | "size_hint" => (2, false, false),
+        _ => return Err(format!("unknown op {}", op)),
+    };
+
+    let args = object
+        .get("args")
+        .and_then(Value::as_array)
+        .ok_or("missing args")?
+        .iter()
+        .map(wire)
+        .collect::<Result<Vec<usize>, String>>()?;
+    if args.len() != arity {
+        return Err(format!("{} takes {} args, found {}", op, arity, args.len()));
+    }
+
+    let c = object.get("const");
+    match (has_const, c) {
+        (true, None) => return Err(format!("{} needs a const", op)),
+        (false, Some(_)) => return Err(format!("{} doesn't take a const", op)),
+        _ => {}
+    }
+
+    let domain = object.get("domain");
+    if !has_domain {
+        if domain.is_some() {
+            return Err(format!("{} doesn't take a domain", op));
+        }
+        return Ok(match op {
+            "b2a" => CombineOperation::B2A(args[0], args[1]),
+            _ => CombineOperation::SizeHint(args[0], args[1]),
+        });
+    }
+    match domain.and_then(Value::as_str) {
+        Some("gf2") => {
+            let c = c.map(bool_const).transpose()?;
+            Ok(CombineOperation::GF2(operation(op, &args, c)))
+        }
+        Some("z64") => {
+            let c = c.map(arith_const).transpose()?;
+            Ok(CombineOperation::Z64(operation(op, &args, c)))
+        }
+        Some(other) => Err(format!("unknown domain {}", other)),
+        None => Err(format!("{} needs a domain", op)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+
+    use crate::exporters::{Export, JSONL};
+    use crate::parsers::jsonl::{JsonlError, JsonlParser};
+    use crate::parsers::Parse;
+    use crate::{CombineOperation, Operation};
+
+    #[test]
+    fn test_jsonl() {
+        let text = r#"{"op": "input", "domain": "gf2", "args": [0]}
+{"op": "addc", "domain": "gf2", "args": [1, 0], "const": 1}
+
+{"op": "b2a", "args": [0, 0]}
+{"op": "mulc", "domain": "z64", "args": [1, 0], "const": "18446744073709551615"}
+{"op": "add", "domain": "z64", "args": [1, 0]}
+{"op": "const", "domain": "gf2", "args": [2], "const": 2}
+{"op": "assert_zero", "domain": "z64", "args": [1]
+{"op": "size_hint", "domain": "z64", "args": [2, 64]}
+{"op": "assert_zero", "domain": "z64", "args": [1]}
+"#;
+        let (gates, errors) = JsonlParser::from_reader(Cursor::new(text)).read_all();
+        assert_eq!(
+            gates,
+            [
+                CombineOperation::GF2(Operation::Input(0)),
+                CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+                CombineOperation::B2A(0, 0),
+                CombineOperation::Z64(Operation::MulConst(1, 0, u64::MAX)),
+                CombineOperation::Z64(Operation::AssertZero(1)),
+            ]
+        );
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [6, 7, 8, 9]);
+        assert_eq!(
+            errors[0],
+            JsonlError {
+                line: 6,
+                message: "add takes 3 args, found 2".into()
+            }
+        );
+        assert_eq!(errors[1].message, "2 isn't a GF2 constant");
+        assert!(errors[2].message.starts_with("invalid JSON"));
+        assert_eq!(errors[3].message, "size_hint doesn't take a domain");
+    }
+
+    #[test]
+    fn test_parse_domains() {
+        let gates = [
+            Operation::Input(0),
+            Operation::MulConst(1, 0, 3u64),
+            Operation::AssertZero(1),
+        ];
+        let path = std::env::temp_dir().join(format!("mcircuit_jsonl_{}", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        JSONL::export_circuit(&gates, &[], &mut file).unwrap();
+        drop(file);
+
+        let mut parser: JsonlParser<_> =
+            Parse::<u64>::new(BufReader::new(File::open(&path).unwrap()));
+        let mut parsed = Vec::new();
+        while let Some(gate) = Parse::<u64>::next(&mut parser) {
+            parsed.push(gate);
+        }
+        assert_eq!(parsed, gates);
+
+        // Reading it as GF2 doesn't silently drop the Z64 gates
+        let mut parser: JsonlParser<_> =
+            Parse::<bool>::new(BufReader::new(File::open(&path).unwrap()));
+        let read = std::panic::catch_unwind(move || Parse::<bool>::next(&mut parser));
+        assert!(read.is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,15 +1,25 @@
 use std::collections::hash_map::{DefaultHasher, Entry};
 use std::collections::HashMap;
 use std::fs::File;
-use std::hash::{Hash, Hasher};
+use std::hash::Hasher;
 use std::io::BufReader;
 
-/// TODO: WireHasher really ought to be a trait so that we can have a `Hasher` and `BackrefHasher`,
-/// and not have to worry about hiding `backref` and the data that we need to back it up behind such
-/// a complicated compile-time cfg.
 use crate::WireValue;
 
 pub mod blif;
+pub mod bristol;
+mod intern;
+pub mod ir1;
+pub mod jsonl;
+mod registry;
+#[cfg(feature = "yosys")]
+pub mod yosys;
+
+pub use intern::Interner;
+
+pub use registry::{
+    detect_format, load_circuit, load_circuit_as, parser_names, register_parser, CircuitLoader,
+};
 
 pub trait Parse<T: WireValue> {
     type Item;
@@ -19,85 +29,102 @@ pub trait Parse<T: WireValue> {
     fn next(&mut self) -> Option<Self::Item>;
 }
 
-/// Calculates and remembers sequential hashes of wire names.
-#[cfg(not(debug_assertions))]
-pub struct WireHasher {
-    hashes: HashMap<usize, usize>,
-}
-
-#[cfg(not(debug_assertions))]
-impl WireHasher {
-    fn new() -> Self {
-        WireHasher {
-            hashes: HashMap::new(),
-        }
-    }
-
-    pub fn get_wire_id(&mut self, name: &str) -> usize {
-        let mut s = DefaultHasher::new();
-        name.hash(&mut s);
-        let len = self.hashes.len();
-
-        *self.hashes.entry(s.finish() as usize).or_insert(len)
-    }
-
-    /// Allows you to map back to the string that created this hash. Only works in debug mode.
-    pub fn backref(&self, id: usize) -> Option<&String> {
-        None
+/// Hashes a wire name given in pieces as if they'd been concatenated, so callers don't need to
+/// allocate a string just to look a wire up.
+fn hash_name(parts: &[&str]) -> usize {
+    let mut s = DefaultHasher::new();
+    for part in parts {
+        s.write(part.as_bytes());
     }
+    s.write_u8(0xff);
+    s.finish() as usize
 }
 
-/// Calculates and remembers sequential hashes of wire names. For example:
+/// Gives wire names sequential ids, in the order they're first seen. For example:
 /// ```
-/// use mcircuit::parsers::WireHasher;
-/// let mut hasher = WireHasher::default();
+/// use mcircuit::parsers::{BackrefHasher, WireHasher};
+/// let mut hasher = BackrefHasher::default();
 ///
 /// assert_eq!(hasher.get_wire_id("foo"), 0);
 /// assert_eq!(hasher.get_wire_id("bar"), 1);
 /// assert_eq!(hasher.get_wire_id("baz"), 2);
 /// assert_eq!(hasher.get_wire_id("foo"), 0);
 /// assert_eq!(hasher.get_wire_id("baz"), 2);
+/// assert_eq!(hasher.get_scoped_wire_id(&["ba", "z"]), 2);
+/// assert_eq!(hasher.backref(1), Some("bar"));
 /// ```
-#[cfg(debug_assertions)]
-pub struct WireHasher {
+///
+/// `FastHasher` only keeps hashes of the names, and `BackrefHasher` keeps the names as well, for
+/// tools that need to map wires back to them (like `VcdDumper`). Parsers take either one at
+/// runtime, as a `Box<dyn WireHasher + Send>`.
+pub trait WireHasher {
+    /// Same as `get_wire_id` on the concatenation of `parts`, without building it.
+    fn get_scoped_wire_id(&mut self, parts: &[&str]) -> usize;
+
+    fn get_wire_id(&mut self, name: &str) -> usize {
+        self.get_scoped_wire_id(&[name])
+    }
+
+    /// Maps back to the name that was given `id`, if this hasher remembers names.
+    fn backref(&self, id: usize) -> Option<&str>;
+
+    /// Number of distinct wires seen so far
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A `WireHasher` that only remembers the hashes of names, so `backref` always gives `None`.
+#[derive(Clone, Debug, Default)]
+pub struct FastHasher {
     hashes: HashMap<usize, usize>,
-    reverse: Vec<String>,
 }
 
-#[cfg(debug_assertions)]
-impl WireHasher {
-    fn new() -> Self {
-        WireHasher {
-            hashes: HashMap::new(),
-            reverse: Vec::new(),
-        }
+impl WireHasher for FastHasher {
+    fn get_scoped_wire_id(&mut self, parts: &[&str]) -> usize {
+        let len = self.hashes.len();
+        *self.hashes.entry(hash_name(parts)).or_insert(len)
+    }
+
+    fn backref(&self, _: usize) -> Option<&str> {
+        None
     }
 
-    pub fn get_wire_id(&mut self, name: &str) -> usize {
-        let mut s = DefaultHasher::new();
-        name.hash(&mut s);
+    fn len(&self) -> usize {
+        self.hashes.len()
+    }
+}
+
+/// A `WireHasher` that also remembers every name, for `backref`.
+#[derive(Clone, Debug, Default)]
+pub struct BackrefHasher {
+    hashes: HashMap<usize, usize>,
+    reverse: Vec<String>,
+}
+
+impl WireHasher for BackrefHasher {
+    /// The name is only allocated the first time it's seen.
+    fn get_scoped_wire_id(&mut self, parts: &[&str]) -> usize {
         let len = self.hashes.len();
 
-        let hash = s.finish() as usize;
-        match self.hashes.entry(hash) {
+        match self.hashes.entry(hash_name(parts)) {
             Entry::Occupied(e) => *e.get(),
             Entry::Vacant(e) => {
                 e.insert(len);
-                self.reverse.push(name.to_string());
+                self.reverse.push(parts.concat());
                 assert_eq!(self.reverse.len(), len + 1);
                 len
             }
         }
     }
 
-    /// Allows you to map back to the string that created this hash. Only works in debug mode.
-    pub fn backref(&self, id: usize) -> Option<&String> {
-        self.reverse.get(id)
+    fn backref(&self, id: usize) -> Option<&str> {
+        self.reverse.get(id).map(String::as_str)
     }
-}
 
-impl Default for WireHasher {
-    fn default() -> Self {
-        WireHasher::new()
+    fn len(&self) -> usize {
+        self.hashes.len()
     }
 }
@@ -0,0 +1,409 @@
+//! Runtime lookup of input formats, so tools can accept a circuit in whatever format the user has
+//! without asking which one it is.
+//!
+//! The built-in formats are `blif` (single-model boolean BLIF), `bristol`, `ir1` (SIEVE IR1 text),
+//! `jsonl` (see the `jsonl` module), and `mcir` (the binary format written by `write_program`).
+//! Other crates can add to the list with `register_parser`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result};
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::parsers::blif::{BlifParser, DuplicateModelPolicy};
+use crate::parsers::bristol::BristolParser;
+use crate::parsers::ir1::IR1Parser;
+use crate::parsers::jsonl::JsonlParser;
+use crate::parsers::{BackrefHasher, FastHasher, Parse};
+use crate::{Bus, CombineOperation, Domain, Field, NameTable, Operation, Program, ProgramReader};
+
+/// How much of a file `load_circuit` reads to guess its format
+const SNIFF_LEN: u64 = 4096;
+
+/// A format that `load_circuit` can read.
+pub trait CircuitLoader: Send + Sync {
+    /// File extensions (without the dot) this format usually has
+    fn extensions(&self) -> &[&str];
+
+    /// Whether the first few KiB of a file look like this format. Doesn't need to be certain;
+    /// it's only used to choose which loader to try.
+    fn sniff(&self, head: &[u8]) -> bool;
+
+    fn load(&self, reader: BufReader<File>) -> Result<Program>;
+}
+
+/// First line that isn't blank or a comment
+fn first_line<'a>(head: &'a [u8], comment: &str) -> Option<&'a str> {
+    // The sniffed prefix may cut a multibyte character in half, so only look at the valid part
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with(comment))
+}
+
+struct BlifLoader;
+
+impl CircuitLoader for BlifLoader {
+    fn extensions(&self) -> &[&str] {
+        &["blif"]
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        first_line(head, "#")
+            .is_some_and(|line| line.starts_with(".model") || line.starts_with(".inputs"))
+    }
+
+    /// Loads a BLIF file with a single model and no subcircuits. Inputs get `Input` gates, and
+    /// the model's inputs and outputs are recorded as buses.
+    fn load(&self, reader: BufReader<File>) -> Result<Program> {
+        let mut parser = BlifParser::<bool>::new(reader);
+        // Remember wire names, for the program's name table
+        parser.hasher = Box::new(BackrefHasher::default());
+        // Keep duplicates around so they're reported as extra models instead of panicking
+        parser.duplicate_policy = DuplicateModelPolicy::Rename;
+        let mut models = parser.try_parse_all()?;
+        if models.len() != 1 || !models[0].subcircuits.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "can only load BLIF files with a single, flat model",
+            ));
+        }
+        let model = models.remove(0);
+        let bus = |suffix: &str, wires: Vec<usize>| Bus {
+            name: format!("{}::{}", model.name, suffix),
+            domain: Domain::GF2,
+            wires,
+        };
+        let buses = vec![
+            bus("inputs", model.inputs.clone()),
+            bus("outputs", model.outputs.clone()),
+        ];
+
+        let gates = model
+            .inputs
+            .iter()
+            .map(|w| Operation::Input(*w))
+            .chain(model.gates)
+            .map(CombineOperation::GF2)
+            .collect();
+
+        Ok(Program {
+            gates,
+            names: Some(NameTable::from_hashers(
+                parser.hasher.as_ref(),
+                &FastHasher::default(),
+            )),
+            buses: Some(buses),
+            ..Default::default()
+        })
+    }
+}
+
+struct BristolLoader;
+
+impl CircuitLoader for BristolLoader {
+    fn extensions(&self) -> &[&str] {
+        &["bristol", "txt"]
+    }
+
+    /// Bristol files start with a line containing just the gate and wire counts.
+    fn sniff(&self, head: &[u8]) -> bool {
+        first_line(head, "#").is_some_and(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields.len() == 2 && fields.iter().all(|f| f.parse::<usize>().is_ok())
+        })
+    }
+
+    fn load(&self, reader: BufReader<File>) -> Result<Program> {
+        let gates = BristolParser::from_reader(reader).read_all()?;
+        Ok(gates
+            .into_iter()
+            .map(CombineOperation::GF2)
+            .collect::<Vec<_>>()
+            .into())
+    }
+}
+
+struct IR1Loader;
+
+impl CircuitLoader for IR1Loader {
+    fn extensions(&self) -> &[&str] {
+        &["ir1", "sieve"]
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        first_line(head, "//").is_some_and(|line| line.starts_with("version 1."))
+    }
+
+    /// Reads boolean relations as GF2 gates and everything else as Z64 gates, recording the
+    /// declared field. The witness, if the file has one, isn't kept.
+    fn load(&self, mut reader: BufReader<File>) -> Result<Program> {
+        let mut text = Vec::new();
+        reader.read_to_end(&mut text)?;
+        let boolean = IR1Parser::<bool, _>::from_reader(text.as_slice())
+            .field()
+            .is_ok_and(|field| field.is_none_or(|field| field == Field::GF2));
+
+        let (gates, field, domain): (Vec<CombineOperation>, _, _) = if boolean {
+            let mut parser = IR1Parser::<bool, _>::from_reader(text.as_slice());
+            let gates = parser.read_all()?;
+            let gates = gates.into_iter().map(CombineOperation::GF2).collect();
+            (gates, parser.field()?, Domain::GF2)
+        } else {
+            let mut parser = IR1Parser::<u64, _>::from_reader(text.as_slice());
+            let gates = parser.read_all()?;
+            let gates = gates.into_iter().map(CombineOperation::Z64).collect();
+            (gates, parser.field()?, Domain::Z64)
+        };
+        Ok(Program {
+            gates,
+            fields: field.map(|field| std::iter::once((domain, field)).collect()),
+            ..Default::default()
+        })
+    }
+}
+
+struct JsonlLoader;
+
+impl CircuitLoader for JsonlLoader {
+    fn extensions(&self) -> &[&str] {
+        &["jsonl"]
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        first_line(head, "//").is_some_and(|line| line.starts_with('{') && line.contains("\"op\""))
+    }
+
+    /// Fails if any line isn't a well-formed gate, reporting the first.
+    fn load(&self, reader: BufReader<File>) -> Result<Program> {
+        let (gates, errors) = JsonlParser::from_reader(reader).read_all();
+        match errors.first() {
+            None => Ok(gates.into()),
+            Some(first) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} (and {} more errors)", first, errors.len() - 1),
+            )),
+        }
+    }
+}
+
+struct McirLoader;
+
+impl CircuitLoader for McirLoader {
+    fn extensions(&self) -> &[&str] {
+        &["mcir"]
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        head.starts_with(b"MCIR")
+    }
+
+    fn load(&self, reader: BufReader<File>) -> Result<Program> {
+        ProgramReader::new(reader)?.read_program()
+    }
+}
+
+type Registry = RwLock<HashMap<String, Arc<dyn CircuitLoader>>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut builtins: HashMap<String, Arc<dyn CircuitLoader>> = HashMap::new();
+        builtins.insert("blif".into(), Arc::new(BlifLoader));
+        builtins.insert("bristol".into(), Arc::new(BristolLoader));
+        builtins.insert("ir1".into(), Arc::new(IR1Loader));
+        builtins.insert("jsonl".into(), Arc::new(JsonlLoader));
+        builtins.insert("mcir".into(), Arc::new(McirLoader));
+        RwLock::new(builtins)
+    })
+}
+
+/// Makes `loader` available under `name`, replacing any format (including a built-in one) that
+/// was already registered with that name.
+pub fn register_parser(name: &str, loader: impl CircuitLoader + 'static) {
+    registry()
+        .write()
+        .expect("Parser registry was poisoned")
+        .insert(name.to_string(), Arc::new(loader));
+}
+
+/// Names of every registered format, sorted.
+pub fn parser_names() -> Vec<String> {
+    let mut names: Vec<String> = registry()
+        .read()
+        .expect("Parser registry was poisoned")
+        .keys()
+        .cloned()
+        .collect();
+    names.sort();
+    names
+}
+
+/// Guesses the format of the file at `path`. Formats whose content sniffing matches win over
+/// ones that only match the extension, and a match on both beats either alone. Ties are broken
+/// by name, so the result is deterministic.
+pub fn detect_format(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let mut head = Vec::new();
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut head)?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    let registry = registry().read().expect("Parser registry was poisoned");
+    let mut names: Vec<&String> = registry.keys().collect();
+    names.sort();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let loader = &registry[name];
+            let by_content = loader.sniff(&head);
+            let by_extension = extension
+                .as_deref()
+                .is_some_and(|e| loader.extensions().contains(&e));
+            match (by_content, by_extension) {
+                (true, true) => Some((0, name)),
+                (true, false) => Some((1, name)),
+                (false, true) => Some((2, name)),
+                (false, false) => None,
+            }
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, name)| name.clone())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("couldn't tell what format {} is in", path.display()),
+            )
+        })
+}
+
+/// Reads the file at `path` using the format registered as `format`.
+pub fn load_circuit_as(format: &str, path: impl AsRef<Path>) -> Result<Program> {
+    // Clone the handle so the lock isn't held while parsing
+    let loader = registry()
+        .read()
+        .expect("Parser registry was poisoned")
+        .get(format)
+        .cloned()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown input format {}", format),
+            )
+        })?;
+
+    loader.load(BufReader::new(File::open(path)?))
+}
+
+/// Reads the file at `path`, guessing its format with `detect_format`.
+pub fn load_circuit(path: impl AsRef<Path>) -> Result<Program> {
+    let format = detect_format(&path)?;
+    load_circuit_as(&format, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{ErrorKind, Write};
+    use std::path::PathBuf;
+
+    use crate::exporters::{Export, IR1};
+    use crate::parsers::registry::{detect_format, load_circuit};
+    use crate::{write_program, CombineOperation, Operation, Program};
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mcircuit_{}_{}", std::process::id(), name));
+        File::create(&path).unwrap().write_all(contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_detection() {
+        let blif = temp_file(
+            "detect.blif",
+            b"# generated\n.model top\n.inputs a\n.outputs y\n.gate NOT A=a Y=y\n.end\n",
+        );
+        assert_eq!(detect_format(&blif).unwrap(), "blif");
+        let program = load_circuit(&blif).unwrap();
+        assert_eq!(program.buses.unwrap()[0].name, "top::inputs");
+        // Names are kept in release builds too
+        assert_eq!(program.names.unwrap().gf2[&2], "top::a");
+
+        // Content wins over a misleading extension
+        let bristol = temp_file("detect.blif.txt", b"1 2\n1 1\n1 1\n1 1 0 1 INV\n");
+        assert_eq!(detect_format(&bristol).unwrap(), "bristol");
+
+        let mut binary = Vec::new();
+        let original: Program = vec![CombineOperation::Z64(Operation::Const(0, 3))].into();
+        write_program(&original, &mut binary).unwrap();
+        let mcir = temp_file("detect.bin", &binary);
+        assert_eq!(load_circuit(&mcir).unwrap(), original);
+
+        let jsonl = temp_file(
+            "detect.txt",
+            b"{\"op\": \"const\", \"domain\": \"z64\", \"args\": [0], \"const\": 3}\n",
+        );
+        assert_eq!(detect_format(&jsonl).unwrap(), "jsonl");
+        assert_eq!(load_circuit(&jsonl).unwrap(), original);
+        let broken = temp_file("detect.jsonl", b"{\"op\": \"const\"}\n");
+        assert!(load_circuit(&broken).is_err());
+
+        let mut relation = Vec::new();
+        IR1::export_circuit(
+            &[Operation::Input(0), Operation::AssertZero(0)],
+            &[false],
+            &mut relation,
+        )
+        .unwrap();
+        let ir1 = temp_file("detect.ir", &relation);
+        assert_eq!(detect_format(&ir1).unwrap(), "ir1");
+        assert_eq!(
+            load_circuit(&ir1).unwrap().gates,
+            [
+                CombineOperation::GF2(Operation::Input(0)),
+                CombineOperation::GF2(Operation::AssertZero(0))
+            ]
+        );
+
+        let unknown = temp_file("detect.unknown", b"\x00\x01\x02");
+        assert!(load_circuit(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_malformed_blif() {
+        for (name, gate, message) in [
+            ("no_pins", ".gate NOT", "the NOT gate has no output"),
+            (
+                "no_equals",
+                ".gate NOT A Y=y",
+                "expected a connection like A=wire",
+            ),
+            ("undef", ".gate NOT A=$undef Y=y", "contains an $undef wire"),
+            (
+                "arity",
+                ".gate AND A=a Y=y",
+                "the AND gate takes 2 inputs, but has 1",
+            ),
+            (
+                "unknown",
+                ".gate NAND A=a B=a Y=y",
+                "Unsupported gate type: NAND",
+            ),
+        ] {
+            let blif = format!(".model top\n.inputs a\n.outputs y\n{}\n.end\n", gate);
+            let path = temp_file(&format!("malformed_{}.blif", name), blif.as_bytes());
+            let err = load_circuit(&path).unwrap_err();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(err.to_string().contains(":4: "), "{}", err);
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,719 @@
+//! Yosys JSON netlists (`write_json`), and a cross-check of the BLIF parser against them.
+//!
+//! Yosys can write the same design as both BLIF and JSON. The JSON is much easier to parse
+//! correctly, so reading both and comparing the results is a cheap way to catch BLIF parsing
+//! bugs: `cross_check_files` compares each model's ports, gate counts, and the logic driving
+//! each output, and reports every difference it finds.
+//!
+//! Wires are named the way Yosys names them in BLIF: bit `i` of a multi-bit net `a` is `a[i]`,
+//! and a single-bit net is just `a`. Nets without a visible name are called `$N`, after their
+//! number in the netlist. Inputs and outputs come in order of port name, then bit.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::parsers::blif::{BlifCircuitDesc, BlifParser, BlifSubcircuitDesc};
+use crate::parsers::{BackrefHasher, Interner, Parse, WireHasher};
+use crate::{HasIO, Operation};
+
+#[derive(Deserialize)]
+struct Design {
+    modules: BTreeMap<String, Module>,
+}
+
+#[derive(Deserialize)]
+struct Module {
+    #[serde(default)]
+    ports: BTreeMap<String, Port>,
+    #[serde(default)]
+    cells: BTreeMap<String, Cell>,
+    #[serde(default)]
+    netnames: BTreeMap<String, NetName>,
+}
+
+#[derive(Deserialize)]
+struct Port {
+    direction: String,
+    bits: Vec<Bit>,
+}
+
+#[derive(Deserialize)]
+struct Cell {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    port_directions: BTreeMap<String, String>,
+    #[serde(default)]
+    connections: BTreeMap<String, Vec<Bit>>,
+}
+
+#[derive(Deserialize)]
+struct NetName {
+    bits: Vec<Bit>,
+    #[serde(default)]
+    hide_name: u8,
+}
+
+/// A net number, or a constant bit (`"0"`, `"1"`, `"x"` or `"z"`).
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum Bit {
+    Net(usize),
+    Constant(String),
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Name of bit `idx` of a net or port `width` bits wide.
+fn bit_name(name: &str, idx: usize, width: usize) -> String {
+    if width == 1 {
+        name.to_string()
+    } else {
+        format!("{}[{}]", name, idx)
+    }
+}
+
+impl Module {
+    /// Names every net, preferring port names, then visible net names.
+    fn net_names(&self) -> HashMap<usize, String> {
+        let mut names = HashMap::new();
+        let ports = self.ports.iter().map(|(name, port)| (name, &port.bits, 0));
+        let nets = self
+            .netnames
+            .iter()
+            .map(|(name, net)| (name, &net.bits, net.hide_name));
+        let mut named: Vec<_> = ports.chain(nets).collect();
+        // Stable, so ports stay ahead of visible nets with the same visibility
+        named.sort_by_key(|(_, _, hidden)| *hidden);
+        for (name, bits, _) in named {
+            for (idx, bit) in bits.iter().enumerate() {
+                if let Bit::Net(net) = bit {
+                    names
+                        .entry(*net)
+                        .or_insert_with(|| bit_name(name, idx, bits.len()));
+                }
+            }
+        }
+        names
+    }
+}
+
+/// Turns the nets of one module into wire ids.
+struct Wires<'a> {
+    model: &'a str,
+    names: HashMap<usize, String>,
+}
+
+impl<'a> Wires<'a> {
+    fn id(&self, hasher: &mut dyn WireHasher, bit: &Bit) -> Result<usize> {
+        match bit {
+            Bit::Net(net) => Ok(match self.names.get(net) {
+                Some(name) => hasher.get_scoped_wire_id(&[self.model, "::", name]),
+                None => hasher.get_scoped_wire_id(&[self.model, "::$", &net.to_string()]),
+            }),
+            Bit::Constant(c) if c == "0" => Ok(hasher.get_wire_id("$false")),
+            Bit::Constant(c) if c == "1" => Ok(hasher.get_wire_id("$true")),
+            Bit::Constant(c) => Err(invalid(format!(
+                "{} has an undefined ({}) bit",
+                self.model, c
+            ))),
+        }
+    }
+}
+
+/// The gate a cell type stands for, or `None` if it isn't one. Covers the cell library our BLIF
+/// files use, and Yosys' own single-bit cells.
+fn gate(kind: &str, out: usize, inputs: &[usize]) -> Option<Operation<bool>> {
+    let binary = |op: fn(usize, usize, usize) -> Operation<bool>| match inputs {
+        [a, b] => Some(op(out, *a, *b)),
+        _ => None,
+    };
+    let unary = |c: bool| match inputs {
+        [a] => Some(Operation::AddConst(out, *a, c)),
+        _ => None,
+    };
+    match kind {
+        "AND" | "MUL" | "$_AND_" => binary(Operation::Mul),
+        "XOR" | "ADD" | "$_XOR_" => binary(Operation::Add),
+        "NOT" | "INV" | "$_NOT_" => unary(true),
+        "BUF" | "$_BUF_" => unary(false),
+        _ => None,
+    }
+}
+
+/// Parses a Yosys JSON netlist into the same models the BLIF parser would give for the design.
+/// Wire ids come from `hasher`, which can be shared with other parsers like `BlifParser::hasher`.
+pub fn parse_yosys_json(
+    reader: impl Read,
+    hasher: &mut dyn WireHasher,
+) -> Result<Vec<BlifCircuitDesc<bool>>> {
+    let design: Design = serde_json::from_reader(reader).map_err(Error::from)?;
+    let interner = Interner::default();
+    let mut models = Vec::with_capacity(design.modules.len());
+
+    for (model, module) in &design.modules {
+        let wires = Wires {
+            model,
+            names: module.net_names(),
+        };
+        let mut desc = BlifCircuitDesc {
+            name: interner.intern(model),
+            ..Default::default()
+        };
+        // Same reserved constant wires as the BLIF parser
+        let (zero, one) = (hasher.get_wire_id("$false"), hasher.get_wire_id("$true"));
+        if (zero, one) != (0, 1) {
+            return Err(invalid(
+                "the hasher has to give $false and $true wires 0 and 1".into(),
+            ));
+        }
+        desc.gates.push(Operation::Const(0, false));
+        desc.gates.push(Operation::Const(1, true));
+
+        for port in module.ports.values() {
+            let ids = port
+                .bits
+                .iter()
+                .map(|bit| wires.id(hasher, bit))
+                .collect::<Result<Vec<_>>>()?;
+            match port.direction.as_str() {
+                "input" => desc.inputs.extend(ids),
+                "output" => desc.outputs.extend(ids),
+                other => {
+                    return Err(invalid(format!(
+                        "{} has an {} port, which isn't supported",
+                        model, other
+                    )))
+                }
+            }
+        }
+
+        for (name, cell) in &module.cells {
+            if let Some(child) = design.modules.get(&cell.kind) {
+                let child_wires = Wires {
+                    model: &cell.kind,
+                    names: child.net_names(),
+                };
+                let mut connections = Vec::new();
+                for (port, bits) in &cell.connections {
+                    let child_bits = &child
+                        .ports
+                        .get(port)
+                        .ok_or_else(|| {
+                            invalid(format!("{} has no port {} for {}", cell.kind, port, name))
+                        })?
+                        .bits;
+                    for (parent, child) in bits.iter().zip(child_bits) {
+                        connections
+                            .push((wires.id(hasher, parent)?, child_wires.id(hasher, child)?));
+                    }
+                }
+                desc.subcircuits.push(BlifSubcircuitDesc {
+                    name: interner.intern(&cell.kind),
+                    connections,
+                    instance: Some(interner.intern(name)),
+                    location: None,
+                    mismatch: None,
+                });
+                continue;
+            }
+            if cell.kind.starts_with("$scopeinfo") {
+                continue;
+            }
+
+            let is_output = |port: &str| match cell.port_directions.get(port) {
+                Some(direction) => direction == "output",
+                None => port == "Y",
+            };
+            let (mut outputs, mut inputs) = (Vec::new(), Vec::new());
+            for (port, bits) in &cell.connections {
+                for bit in bits {
+                    let wire = wires.id(hasher, bit)?;
+                    if is_output(port) {
+                        outputs.push(wire);
+                    } else {
+                        inputs.push(wire);
+                    }
+                }
+            }
+            let op = match outputs[..] {
+                [out] => gate(&cell.kind, out, &inputs),
+                _ => None,
+            };
+            desc.gates.push(op.ok_or_else(|| {
+                invalid(format!(
+                    "{} in {} is a {} cell, which isn't supported",
+                    name, model, cell.kind
+                ))
+            })?);
+        }
+        models.push(desc);
+    }
+    Ok(models)
+}
+
+/// Which of the two netlists in a cross-check something is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Netlist {
+    Blif,
+    Json,
+}
+
+/// Something the BLIF and JSON parses of a design disagree on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetlistDifference {
+    /// A model only one of the netlists has
+    MissingModel {
+        model: String,
+        missing_from: Netlist,
+    },
+    /// An input or output bit only one of the netlists has
+    MissingPort {
+        model: String,
+        port: String,
+        missing_from: Netlist,
+    },
+    /// Different numbers of gates of a kind (`AND`, `XOR`, `NOT`, `CONST`, `RAND`), or of
+    /// instances of a subcircuit (named after its model). Buffers aren't counted, since BLIF
+    /// writes connections between nets as buffers and JSON merges the nets.
+    GateCount {
+        model: String,
+        kind: String,
+        blif: usize,
+        json: usize,
+    },
+    /// The logic driving an output bit has a different structure
+    Output { model: String, port: String },
+}
+
+impl fmt::Display for NetlistDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetlistDifference::MissingModel {
+                model,
+                missing_from,
+            } => write!(
+                f,
+                "model {} is missing from the {:?} netlist",
+                model, missing_from
+            ),
+            NetlistDifference::MissingPort {
+                model,
+                port,
+                missing_from,
+            } => write!(
+                f,
+                "port {} of {} is missing from the {:?} netlist",
+                port, model, missing_from
+            ),
+            NetlistDifference::GateCount {
+                model,
+                kind,
+                blif,
+                json,
+            } => write!(
+                f,
+                "{} has {} {} gates in BLIF but {} in JSON",
+                model, blif, kind, json
+            ),
+            NetlistDifference::Output { model, port } => write!(
+                f,
+                "output {} of {} is computed differently in BLIF and JSON",
+                port, model
+            ),
+        }
+    }
+}
+
+/// What drives a wire of a model.
+enum Driver<'a> {
+    Input(&'a str),
+    Gate(Operation<bool>),
+    /// An output of the subcircuit at this index, and the port's name in the child
+    Subcircuit(usize, &'a str),
+}
+
+/// One side of a cross-check: a netlist's models, and the hasher that named their wires.
+struct Side<'a> {
+    models: HashMap<&'a str, &'a BlifCircuitDesc<bool>>,
+    names: &'a dyn WireHasher,
+}
+
+impl<'a> Side<'a> {
+    fn new(models: &'a [BlifCircuitDesc<bool>], names: &'a dyn WireHasher) -> Self {
+        Side {
+            models: models.iter().map(|m| (&*m.name, m)).collect(),
+            names,
+        }
+    }
+
+    /// A wire's name within its model, without the model's prefix.
+    fn local_name(&self, model: &str, wire: usize) -> Option<&'a str> {
+        let name = self.names.backref(wire)?;
+        Some(
+            name.strip_prefix(model)
+                .and_then(|rest| rest.strip_prefix("::"))
+                .unwrap_or(name),
+        )
+    }
+
+    /// Names of a model's inputs or outputs, with their wires.
+    fn ports(&self, model: &str, wires: &[usize]) -> BTreeMap<&'a str, usize> {
+        wires
+            .iter()
+            .filter_map(|w| Some((self.local_name(model, *w)?, *w)))
+            .collect()
+    }
+
+    /// Gate and subcircuit counts, by kind, without buffers.
+    fn counts(&self, desc: &BlifCircuitDesc<bool>) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for gate in &desc.gates {
+            let kind = match gate {
+                Operation::Mul(_, _, _) => "AND",
+                Operation::Add(_, _, _) => "XOR",
+                Operation::AddConst(_, _, true) => "NOT",
+                Operation::Const(_, _) => "CONST",
+                Operation::Random(_) => "RAND",
+                _ => continue,
+            };
+            *counts.entry(kind.to_string()).or_default() += 1;
+        }
+        for sub in &desc.subcircuits {
+            *counts.entry(sub.name.to_string()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Structural hashes of the logic driving each output of a model, by output name. Inputs are
+    /// identified by name, buffers are looked through, and the operands of commutative gates
+    /// are sorted, so two netlists agree whenever they compute the same expressions. Subcircuits
+    /// are identified by model, port and inputs, since each model is checked separately.
+    fn cones(&self, desc: &'a BlifCircuitDesc<bool>) -> BTreeMap<&'a str, u64> {
+        let model = &*desc.name;
+        let mut drivers: HashMap<usize, Driver> = HashMap::new();
+        for wire in &desc.inputs {
+            let name = self.local_name(model, *wire).unwrap_or("?");
+            drivers.insert(*wire, Driver::Input(name));
+        }
+        for gate in &desc.gates {
+            if let Some(dst) = gate.dst() {
+                drivers.insert(dst, Driver::Gate(*gate));
+            }
+        }
+        // Each subcircuit's inputs, as (child port, parent wire)
+        let mut sub_inputs: Vec<Vec<(&str, usize)>> = Vec::new();
+        for (idx, sub) in desc.subcircuits.iter().enumerate() {
+            let mut inputs = Vec::new();
+            if let Some(child) = self.models.get(&*sub.name) {
+                let outputs: HashSet<usize> = child.outputs.iter().copied().collect();
+                for (parent, child_wire) in &sub.connections {
+                    let port = self.local_name(&sub.name, *child_wire).unwrap_or("?");
+                    if outputs.contains(child_wire) {
+                        drivers.insert(*parent, Driver::Subcircuit(idx, port));
+                    } else {
+                        inputs.push((port, *parent));
+                    }
+                }
+            }
+            inputs.sort_unstable();
+            sub_inputs.push(inputs);
+        }
+
+        let hash = |value: &dyn Fn(&mut DefaultHasher)| {
+            let mut hasher = DefaultHasher::new();
+            value(&mut hasher);
+            hasher.finish()
+        };
+        let mut memo: HashMap<usize, u64> = HashMap::new();
+        let mut on_stack: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<usize> = desc.outputs.clone();
+        while let Some(&wire) = stack.last() {
+            if memo.contains_key(&wire) {
+                stack.pop();
+                continue;
+            }
+            let deps: Vec<usize> = match drivers.get(&wire) {
+                Some(Driver::Gate(gate)) => gate.inputs().collect(),
+                Some(Driver::Subcircuit(idx, _)) => {
+                    sub_inputs[*idx].iter().map(|(_, w)| *w).collect()
+                }
+                _ => vec![],
+            };
+            let pending: Vec<usize> = deps
+                .iter()
+                .copied()
+                .filter(|w| !memo.contains_key(w) && !on_stack.contains(w))
+                .collect();
+            if !pending.is_empty() && on_stack.insert(wire) {
+                stack.extend(pending);
+                continue;
+            }
+            // Operands still on the stack are part of a loop
+            let operand = |w: usize| memo.get(&w).copied().unwrap_or(0);
+            let value = match drivers.get(&wire) {
+                None => hash(&|h| ("undriven", self.local_name(model, wire)).hash(h)),
+                Some(Driver::Input(name)) => hash(&|h| ("input", name).hash(h)),
+                Some(Driver::Gate(Operation::AddConst(_, a, false))) => operand(*a),
+                Some(Driver::Gate(gate)) => {
+                    let mut operands: Vec<u64> = gate.inputs().map(operand).collect();
+                    if matches!(gate, Operation::Add(..) | Operation::Mul(..)) {
+                        operands.sort_unstable();
+                    }
+                    let constant = match gate {
+                        Operation::AddConst(_, _, c) | Operation::Const(_, c) => Some(*c),
+                        _ => None,
+                    };
+                    hash(&|h| (gate.kind(), constant, &operands).hash(h))
+                }
+                Some(Driver::Subcircuit(idx, port)) => {
+                    let sub = &desc.subcircuits[*idx];
+                    let inputs: Vec<(&str, u64)> = sub_inputs[*idx]
+                        .iter()
+                        .map(|(p, w)| (*p, operand(*w)))
+                        .collect();
+                    hash(&|h| (&*sub.name, port, &inputs).hash(h))
+                }
+            };
+            memo.insert(wire, value);
+            on_stack.remove(&wire);
+            stack.pop();
+        }
+
+        self.ports(model, &desc.outputs)
+            .into_iter()
+            .map(|(name, wire)| (name, memo[&wire]))
+            .collect()
+    }
+}
+
+/// Compares the BLIF and JSON parses of the same design, model by model. The hashers have to
+/// remember names (like `BackrefHasher`), since ports are matched by name. Returns every
+/// difference, grouped by model in name order; an empty list means the parses agree.
+pub fn cross_check(
+    blif: &[BlifCircuitDesc<bool>],
+    blif_names: &dyn WireHasher,
+    json: &[BlifCircuitDesc<bool>],
+    json_names: &dyn WireHasher,
+) -> Vec<NetlistDifference> {
+    let (blif, json) = (Side::new(blif, blif_names), Side::new(json, json_names));
+    let mut models: Vec<&str> = blif
+        .models
+        .keys()
+        .chain(json.models.keys())
+        .copied()
+        .collect();
+    models.sort_unstable();
+    models.dedup();
+
+    let mut differences = Vec::new();
+    for model in models {
+        let (b, j) = match (blif.models.get(model), json.models.get(model)) {
+            (Some(b), Some(j)) => (*b, *j),
+            (b, _) => {
+                differences.push(NetlistDifference::MissingModel {
+                    model: model.to_string(),
+                    missing_from: if b.is_some() {
+                        Netlist::Json
+                    } else {
+                        Netlist::Blif
+                    },
+                });
+                continue;
+            }
+        };
+
+        for (b_wires, j_wires) in [(&b.inputs, &j.inputs), (&b.outputs, &j.outputs)] {
+            let (b_ports, j_ports) = (blif.ports(model, b_wires), json.ports(model, j_wires));
+            for (port, missing_from) in b_ports
+                .keys()
+                .filter(|p| !j_ports.contains_key(*p))
+                .map(|p| (p, Netlist::Json))
+                .chain(
+                    j_ports
+                        .keys()
+                        .filter(|p| !b_ports.contains_key(*p))
+                        .map(|p| (p, Netlist::Blif)),
+                )
+            {
+                differences.push(NetlistDifference::MissingPort {
+                    model: model.to_string(),
+                    port: port.to_string(),
+                    missing_from,
+                });
+            }
+        }
+
+        let (b_counts, j_counts) = (blif.counts(b), json.counts(j));
+        let mut kinds: Vec<&String> = b_counts.keys().chain(j_counts.keys()).collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+        for kind in kinds {
+            let (b_count, j_count) = (
+                b_counts.get(kind).copied().unwrap_or(0),
+                j_counts.get(kind).copied().unwrap_or(0),
+            );
+            if b_count != j_count {
+                differences.push(NetlistDifference::GateCount {
+                    model: model.to_string(),
+                    kind: kind.clone(),
+                    blif: b_count,
+                    json: j_count,
+                });
+            }
+        }
+
+        let j_cones = json.cones(j);
+        for (port, cone) in blif.cones(b) {
+            if j_cones.get(port).is_some_and(|other| *other != cone) {
+                differences.push(NetlistDifference::Output {
+                    model: model.to_string(),
+                    port: port.to_string(),
+                });
+            }
+        }
+    }
+    differences
+}
+
+/// Parses a design from a BLIF file and a Yosys JSON file of it, and `cross_check`s them.
+pub fn cross_check_files(
+    blif: impl AsRef<Path>,
+    json: impl AsRef<Path>,
+) -> Result<Vec<NetlistDifference>> {
+    let mut parser = BlifParser::<bool>::new(BufReader::new(File::open(blif)?));
+    parser.hasher = Box::new(BackrefHasher::default());
+    let blif_models = parser.parse_all();
+
+    let mut json_names = BackrefHasher::default();
+    let json_models = parse_yosys_json(BufReader::new(File::open(json)?), &mut json_names)?;
+    Ok(cross_check(
+        &blif_models,
+        parser.hasher.as_ref(),
+        &json_models,
+        &json_names,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::parsers::yosys::{cross_check_files, NetlistDifference};
+
+    const BLIF: &str = "\
+.model top
+.inputs a b c
+.outputs y z
+.subckt maj A=a B=b C=c Y=m
+.gate XOR A=m B=c Y=y
+.conn m z
+.end
+
+.model maj
+.inputs A B C
+.outputs Y
+.gate AND A=A B=B Y=ab
+.gate XOR A=A B=B Y=x
+.gate AND A=x B=C Y=xc
+.gate XOR A=ab B=xc Y=Y
+.end
+";
+
+    fn json(carry: &str) -> String {
+        format!(
+            r#"{{"modules": {{
+  "top": {{
+    "ports": {{
+      "a": {{"direction": "input", "bits": [2]}},
+      "b": {{"direction": "input", "bits": [3]}},
+      "c": {{"direction": "input", "bits": [4]}},
+      "y": {{"direction": "output", "bits": [5]}},
+      "z": {{"direction": "output", "bits": [6]}}
+    }},
+    "cells": {{
+      "u0": {{"type": "maj", "connections": {{"A": [2], "B": [3], "C": [4], "Y": [6]}}}},
+      "u1": {{"type": "XOR", "port_directions": {{"A": "input", "B": "input", "Y": "output"}},
+              "connections": {{"A": [4], "B": [6], "Y": [5]}}}}
+    }},
+    "netnames": {{"m": {{"bits": [6], "hide_name": 0}}}}
+  }},
+  "maj": {{
+    "ports": {{
+      "A": {{"direction": "input", "bits": [2]}},
+      "B": {{"direction": "input", "bits": [3]}},
+      "C": {{"direction": "input", "bits": [4]}},
+      "Y": {{"direction": "output", "bits": [5]}}
+    }},
+    "cells": {{
+      "g0": {{"type": "$_AND_", "connections": {{"A": [2], "B": [3], "Y": [6]}}}},
+      "g1": {{"type": "$_XOR_", "connections": {{"A": [2], "B": [3], "Y": [7]}}}},
+      "g2": {{"type": "$_AND_", "connections": {{"A": [7], "B": [4], "Y": [8]}}}},
+      "g3": {{"type": "{}", "connections": {{"A": [6], "B": [8], "Y": [5]}}}}
+    }}
+  }}
+}}}}"#,
+            carry
+        )
+    }
+
+    fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("mcircuit_yosys_{}_{}", std::process::id(), name));
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let blif = temp_file("design.blif", BLIF);
+        let good = temp_file("good.json", &json("$_XOR_"));
+        assert_eq!(cross_check_files(&blif, &good).unwrap(), vec![]);
+
+        // An AND where BLIF has an XOR only shows up in maj: top's outputs still come from the same
+        // maj instance, and maj is checked on its own
+        let bad = temp_file("bad.json", &json("$_AND_"));
+        let differences = cross_check_files(&blif, &bad).unwrap();
+        assert_eq!(
+            differences,
+            vec![
+                NetlistDifference::GateCount {
+                    model: "maj".into(),
+                    kind: "AND".into(),
+                    blif: 2,
+                    json: 3
+                },
+                NetlistDifference::GateCount {
+                    model: "maj".into(),
+                    kind: "XOR".into(),
+                    blif: 2,
+                    json: 1
+                },
+                NetlistDifference::Output {
+                    model: "maj".into(),
+                    port: "Y".into()
+                },
+            ]
+        );
+        assert_eq!(
+            differences[2].to_string(),
+            "output Y of maj is computed differently in BLIF and JSON"
+        );
+
+        for path in [blif, good, bad] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{CombineOperation, HasIO, Identity, Operation};
+
+/// The result of removing redundant B2A conversions from a program.
+#[derive(Clone, Debug, Default)]
+pub struct Peephole {
+    /// The rewritten program. Gates keep their positions and wire numbering; each eliminated
+    /// conversion is replaced by a Z64 identity gate.
+    pub program: Vec<CombineOperation>,
+    /// Indices (in the original program) of the conversions that were eliminated
+    pub eliminated: Vec<usize>,
+}
+
+/// Arithmetic wires currently known to hold the value of each converted 64-bit window.
+#[derive(Default)]
+struct KnownWindows {
+    /// Low bit of the window → Z64 wires with its value, the one to copy first
+    by_window: HashMap<usize, Vec<usize>>,
+    /// Z64 wire → windows it holds the value of
+    by_wire: HashMap<usize, HashSet<usize>>,
+}
+
+impl KnownWindows {
+    fn insert(&mut self, low: usize, wire: usize) {
+        self.by_window.entry(low).or_default().push(wire);
+        self.by_wire.entry(wire).or_default().insert(low);
+    }
+
+    /// Records that `a` and `b` were asserted equal. Each gets the windows the other holds, ahead
+    /// of the wires already holding them: a window's bits are checked against the value they were
+    /// decomposed from, so that's the wire a conversion back should copy.
+    fn equate(&mut self, a: usize, b: usize) {
+        for (from, to) in [(a, b), (b, a)] {
+            let windows = self.by_wire.get(&from).cloned().unwrap_or_default();
+            for low in windows {
+                if self.by_wire.entry(to).or_default().insert(low) {
+                    self.by_window.entry(low).or_default().insert(0, to);
+                }
+            }
+        }
+    }
+
+    /// A GF2 write to `bit` changes the value of every window that covers it.
+    fn forget_bit(&mut self, bit: usize) {
+        for low in bit.saturating_sub(63)..=bit {
+            for wire in self.by_window.remove(&low).unwrap_or_default() {
+                if let Some(windows) = self.by_wire.get_mut(&wire) {
+                    windows.remove(&low);
+                }
+            }
+        }
+    }
+
+    /// A Z64 write to `wire` means it no longer holds any window's value.
+    fn forget_wire(&mut self, wire: usize) {
+        for low in self.by_wire.remove(&wire).unwrap_or_default() {
+            if let Some(wires) = self.by_window.get_mut(&low) {
+                wires.retain(|w| *w != wire);
+                if wires.is_empty() {
+                    self.by_window.remove(&low);
+                }
+            }
+        }
+    }
+}
+
+/// Replaces B2A gates that convert a window of bits whose arithmetic value is already on hand.
+///
+/// The IR has no A2B gate, so an arithmetic value reaches the boolean domain as witness bits that
+/// are checked against it: a B2A of the bits, subtracted from the value, asserted to be zero (as
+/// `decompose_gf2` lays it out). That assertion pairs the window with the original value, so
+/// converting the bits back later (the A2B/B2A round trip) cancels out to a copy of the value.
+/// Any repeat conversion of a window whose bits haven't been rewritten in between is a copy of
+/// the earlier result, too. Either way the later conversion is a cross-domain no-op: it's
+/// replaced with a Z64 identity gate, which later passes can fold out along with the other
+/// identity gates.
+pub fn eliminate_redundant_conversions(program: &[CombineOperation]) -> Peephole {
+    let mut known = KnownWindows::default();
+    // Index of the last gate writing each Z64 wire, and the operands of each `Sub`, so an
+    // assertion is only taken to equate wires that haven't changed since they were subtracted
+    let mut last_write: HashMap<usize, usize> = HashMap::new();
+    let mut differences: HashMap<usize, (usize, usize, usize)> = HashMap::new();
+    let unchanged_since =
+        |last_write: &HashMap<usize, usize>, wire: usize, at: usize| match last_write.get(&wire) {
+            Some(written) => *written < at,
+            None => true,
+        };
+    let mut peephole = Peephole {
+        program: Vec::with_capacity(program.len()),
+        eliminated: Vec::new(),
+    };
+
+    for (idx, gate) in program.iter().enumerate() {
+        match gate {
+            CombineOperation::GF2(op) => {
+                for bit in op.outputs() {
+                    known.forget_bit(bit);
+                }
+            }
+            CombineOperation::Z64(op) => {
+                for wire in op.outputs() {
+                    known.forget_wire(wire);
+                    last_write.insert(wire, idx);
+                }
+                match *op {
+                    Operation::Sub(dst, a, b) if dst != a && dst != b => {
+                        differences.insert(dst, (idx, a, b));
+                    }
+                    Operation::AssertZero(wire) => {
+                        if let Some((at, a, b)) = differences.get(&wire).copied() {
+                            if last_write.get(&wire) == Some(&at)
+                                && unchanged_since(&last_write, a, at)
+                                && unchanged_since(&last_write, b, at)
+                            {
+                                known.equate(a, b);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            CombineOperation::B2A(dst, low) => {
+                let holders = known.by_window.get(low).cloned().unwrap_or_default();
+                if holders.contains(dst) {
+                    // Converting into a wire that already has the value changes nothing
+                    peephole.program.push(Identity::<u64>::identity(*dst, *dst));
+                    peephole.eliminated.push(idx);
+                    continue;
+                }
+
+                known.forget_wire(*dst);
+                last_write.insert(*dst, idx);
+                match holders.first() {
+                    Some(wire) => {
+                        peephole
+                            .program
+                            .push(Identity::<u64>::identity(*dst, *wire));
+                        peephole.eliminated.push(idx);
+                    }
+                    None => peephole.program.push(*gate),
+                }
+                known.insert(*low, *dst);
+                continue;
+            }
+            CombineOperation::SizeHint(_, _) => {}
+        }
+        peephole.program.push(*gate);
+    }
+
+    peephole
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flatten::flatten;
+    use crate::gadgets::decompose::decompose_gf2;
+    use crate::gadgets::{GateSink, WitnessRecorder};
+    use crate::parsers::blif::{BlifCircuitDesc, BlifSubcircuitDesc};
+    use crate::peephole::eliminate_redundant_conversions;
+    use crate::{
+        evaluate_composite_program, CombineOperation, Domain, HasIO, Operation, ProgramEditor,
+    };
+
+    fn conversions(program: &[CombineOperation]) -> usize {
+        program
+            .iter()
+            .filter(|g| matches!(g, CombineOperation::B2A(_, _)))
+            .count()
+    }
+
+    #[test]
+    fn test_round_trip_elimination() {
+        // Decompose x into witness bits, then use the bits in both domains
+        let mut program: Vec<CombineOperation> = (0..64)
+            .map(|i| CombineOperation::GF2(Operation::Input(i)))
+            .collect();
+        program.extend([
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::B2A(1, 0),
+            CombineOperation::Z64(Operation::Sub(2, 1, 0)),
+            CombineOperation::Z64(Operation::AssertZero(2)),
+            // Round trip back to the arithmetic domain
+            CombineOperation::B2A(3, 0),
+            CombineOperation::Z64(Operation::Sub(4, 3, 0)),
+            CombineOperation::Z64(Operation::AssertZero(4)),
+            // Rewriting the result doesn't change what the bits are worth...
+            CombineOperation::Z64(Operation::AddConst(1, 1, 1)),
+            CombineOperation::B2A(5, 0),
+            // ...but rewriting a bit does
+            CombineOperation::GF2(Operation::AddConst(10, 10, true)),
+            CombineOperation::B2A(6, 0),
+            CombineOperation::Z64(Operation::AssertZero(6)),
+        ]);
+
+        let peephole = eliminate_redundant_conversions(&program);
+        assert_eq!(peephole.eliminated, vec![68, 72]);
+        assert_eq!(conversions(&program), 4);
+        assert_eq!(conversions(&peephole.program), 2);
+        assert_eq!(
+            peephole.program[68],
+            CombineOperation::Z64(Operation::AddConst(3, 0, 0))
+        );
+        assert_eq!(
+            peephole.program[72],
+            CombineOperation::Z64(Operation::AddConst(5, 0, 0))
+        );
+
+        // Every assertion still holds, including the one that needs the last conversion redone
+        let bits: Vec<bool> = (0..64).map(|i| i == 10).collect();
+        evaluate_composite_program(&peephole.program, &bits, &[1 << 10]);
+    }
+
+    #[test]
+    fn test_flattened_round_trip() {
+        // top(a[0..64]) = a[0] ^ a[1], through an xor subcircuit
+        let xor = BlifCircuitDesc {
+            name: "xor".into(),
+            inputs: vec![100, 101],
+            outputs: vec![102],
+            gates: vec![Operation::Add(102, 100, 101)],
+            ..Default::default()
+        };
+        let top = BlifCircuitDesc {
+            name: "top".into(),
+            inputs: (2..66).collect(),
+            outputs: vec![66],
+            gates: vec![Operation::Const(0, false), Operation::Const(1, true)],
+            subcircuits: vec![BlifSubcircuitDesc {
+                name: "xor".into(),
+                connections: vec![(2, 100), (3, 101), (66, 102)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let flattened = flatten(&[top, xor], "top").unwrap();
+        let used = flattened
+            .iter()
+            .flat_map(|gate| gate.inputs().chain(gate.outputs()))
+            .max()
+            .unwrap();
+
+        // Decompose x onto the circuit's inputs, run the circuit, then convert the bits back
+        let x = 0b10;
+        let mut sink = WitnessRecorder::new(ProgramEditor::new(Vec::new()));
+        sink.fresh_wires(Domain::GF2, used + 1);
+        let value = sink.fresh_wire(Domain::Z64);
+        sink.emit(CombineOperation::Z64(Operation::Input(value)));
+        sink.hint_arith(x);
+        let bits = decompose_gf2(&mut sink, value, 64, Some(x));
+        for (input, bit) in (2..66).zip(&bits) {
+            sink.emit(CombineOperation::GF2(Operation::AddConst(
+                input, *bit, false,
+            )));
+        }
+        for gate in flattened {
+            sink.emit(CombineOperation::GF2(gate));
+        }
+        let check = sink.fresh_wire(Domain::GF2);
+        sink.emit(CombineOperation::GF2(Operation::AddConst(check, 66, true)));
+        sink.emit(CombineOperation::GF2(Operation::AssertZero(check)));
+        let round_trip = sink.fresh_wire(Domain::Z64);
+        sink.emit(CombineOperation::B2A(round_trip, bits[0]));
+        let difference = sink.fresh_wire(Domain::Z64);
+        sink.emit(CombineOperation::Z64(Operation::Sub(
+            difference, round_trip, value,
+        )));
+        sink.emit(CombineOperation::Z64(Operation::AssertZero(difference)));
+        let (bool_inputs, arith_inputs) = (sink.bool_inputs, sink.arith_inputs);
+        let program = sink.sink.commit().0;
+
+        let peephole = eliminate_redundant_conversions(&program);
+        assert_eq!(conversions(&program), 2);
+        assert_eq!(conversions(&peephole.program), 1);
+        // The conversion back is a copy of the value that was decomposed
+        let idx = peephole.eliminated[0];
+        assert_eq!(peephole.eliminated.len(), 1);
+        assert_eq!(
+            peephole.program[idx],
+            CombineOperation::Z64(Operation::AddConst(round_trip, value, 0))
+        );
+        evaluate_composite_program(&peephole.program, &bool_inputs, &arith_inputs);
+    }
+}
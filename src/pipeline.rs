@@ -0,0 +1,280 @@
+//! Chains of program transforms, declared up front so they can be saved alongside the artifacts
+//! they produced and rerun later.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{AnalysisPass, WireCounter};
+use crate::optimize::{
+    constant_fold, deduplicate, eliminate_common_subexpressions, eliminate_dead_code,
+    eliminate_dead_code_with_mapping, eliminate_dead_gates, refresh_size_hints, renumber_wires,
+    reuse_wires, separate_domains,
+};
+use crate::{CombineOperation, WitnessMapping};
+
+fn default_true() -> bool {
+    true
+}
+
+/// One transform in a pipeline, along with its options. See the functions in `optimize` for what
+/// each one does.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Stage {
+    /// `deduplicate`
+    Dedupe,
+    /// `eliminate_dead_code`
+    DeadCode {
+        #[serde(default = "default_true")]
+        keep_inputs: bool,
+    },
+    /// `eliminate_dead_gates`
+    DeadGates,
+    /// `eliminate_common_subexpressions`
+    Cse,
+    /// `constant_fold`
+    ConstantFold,
+    /// `renumber_wires`
+    Renumber,
+    /// `refresh_size_hints`
+    RefreshSizeHints,
+    /// `separate_domains`
+    SeparateDomains,
+    /// `reuse_wires`
+    ReuseWires,
+}
+
+impl Stage {
+    /// The name the stage is saved under, like `dead_code`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Dedupe => "dedupe",
+            Stage::DeadCode { .. } => "dead_code",
+            Stage::DeadGates => "dead_gates",
+            Stage::Cse => "cse",
+            Stage::ConstantFold => "constant_fold",
+            Stage::Renumber => "renumber",
+            Stage::RefreshSizeHints => "refresh_size_hints",
+            Stage::SeparateDomains => "separate_domains",
+            Stage::ReuseWires => "reuse_wires",
+        }
+    }
+
+    pub fn apply(&self, program: &[CombineOperation]) -> Vec<CombineOperation> {
+        match self {
+            Stage::Dedupe => deduplicate(program),
+            Stage::DeadCode { keep_inputs } => eliminate_dead_code(program, *keep_inputs),
+            Stage::DeadGates => eliminate_dead_gates(program),
+            Stage::Cse => eliminate_common_subexpressions(program),
+            Stage::ConstantFold => constant_fold(program),
+            Stage::Renumber => renumber_wires(program),
+            Stage::RefreshSizeHints => refresh_size_hints(program),
+            Stage::SeparateDomains => separate_domains(program),
+            Stage::ReuseWires => reuse_wires(program),
+        }
+    }
+
+    /// Like `apply`, but also returns how the stage changed the witness. Only dead code
+    /// elimination without `keep_inputs` ever changes it.
+    pub fn apply_with_mapping(
+        &self,
+        program: &[CombineOperation],
+    ) -> (Vec<CombineOperation>, WitnessMapping) {
+        match self {
+            Stage::DeadCode { keep_inputs } => {
+                eliminate_dead_code_with_mapping(program, *keep_inputs)
+            }
+            _ => (self.apply(program), WitnessMapping::identity(program)),
+        }
+    }
+}
+
+/// What one stage of a pipeline did.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StageReport {
+    /// Position of the stage in the pipeline
+    pub index: usize,
+    pub stage: Stage,
+    pub gates_before: usize,
+    pub gates_after: usize,
+    /// Wires used in each domain before the stage, as (Z64, GF2)
+    pub wires_before: (usize, usize),
+    pub wires_after: (usize, usize),
+    pub elapsed: Duration,
+}
+
+/// An ordered list of transforms. Serializes to JSON, so the exact pipeline that produced an
+/// artifact can be recorded with it.
+///
+/// ```
+/// use mcircuit::{CombineOperation, Operation, Pipeline, Stage};
+///
+/// let pipeline = Pipeline::new()
+///     .then(Stage::Cse)
+///     .then(Stage::DeadCode { keep_inputs: true })
+///     .then(Stage::Renumber)
+///     .then(Stage::RefreshSizeHints);
+/// let saved = pipeline.to_json();
+/// assert_eq!(Pipeline::from_json(&saved).unwrap(), pipeline);
+///
+/// let program = vec![
+///     CombineOperation::GF2(Operation::Input(5)),
+///     CombineOperation::GF2(Operation::AssertZero(5)),
+/// ];
+/// let (optimized, reports) = pipeline.run(program);
+/// assert_eq!(optimized[0], CombineOperation::SizeHint(1, 1));
+/// assert_eq!(reports.len(), 4);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a stage to the end of the pipeline.
+    pub fn then(mut self, stage: Stage) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Pipelines are always serializable")
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Runs every stage in order, returning the transformed program and a report on each stage.
+    pub fn run(&self, program: Vec<CombineOperation>) -> (Vec<CombineOperation>, Vec<StageReport>) {
+        self.run_with_progress(program, |_| {})
+    }
+
+    /// Like `run`, but calls `progress` with each stage's report as soon as the stage finishes.
+    pub fn run_with_progress(
+        &self,
+        program: Vec<CombineOperation>,
+        progress: impl FnMut(&StageReport),
+    ) -> (Vec<CombineOperation>, Vec<StageReport>) {
+        let (program, reports, _) = self.run_stages(program, progress, false);
+        (program, reports)
+    }
+
+    /// Like `run`, but also returns how the pipeline changed the witness, so witnesses for the
+    /// original program can be converted with `convert_witness`.
+    pub fn run_with_mapping(
+        &self,
+        program: Vec<CombineOperation>,
+    ) -> (Vec<CombineOperation>, Vec<StageReport>, WitnessMapping) {
+        self.run_stages(program, |_| {}, true)
+    }
+
+    fn run_stages(
+        &self,
+        mut program: Vec<CombineOperation>,
+        mut progress: impl FnMut(&StageReport),
+        track_witness: bool,
+    ) -> (Vec<CombineOperation>, Vec<StageReport>, WitnessMapping) {
+        let mut reports = Vec::with_capacity(self.stages.len());
+        let mut wires = WireCounter::analyze(program.iter()).0;
+        let mut mapping = if track_witness {
+            WitnessMapping::identity(&program)
+        } else {
+            WitnessMapping::default()
+        };
+        for (index, stage) in self.stages.iter().enumerate() {
+            let start = Instant::now();
+            let transformed = if track_witness {
+                let (transformed, stage_mapping) = stage.apply_with_mapping(&program);
+                mapping = mapping.then(&stage_mapping);
+                transformed
+            } else {
+                stage.apply(&program)
+            };
+            let elapsed = start.elapsed();
+
+            let wires_after = WireCounter::analyze(transformed.iter()).0;
+            let report = StageReport {
+                index,
+                stage: stage.clone(),
+                gates_before: program.len(),
+                gates_after: transformed.len(),
+                wires_before: wires,
+                wires_after,
+                elapsed,
+            };
+            progress(&report);
+            reports.push(report);
+            program = transformed;
+            wires = wires_after;
+        }
+        (program, reports, mapping)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::{Pipeline, Stage};
+    use crate::{evaluate_composite_program, CombineOperation, Domain, Operation};
+
+    #[test]
+    fn test_pipeline() {
+        let program = vec![
+            CombineOperation::Z64(Operation::Input(100)),
+            CombineOperation::Z64(Operation::Input(200)),
+            CombineOperation::Z64(Operation::Mul(300, 100, 200)),
+            CombineOperation::Z64(Operation::Mul(400, 200, 100)),
+            CombineOperation::Z64(Operation::Sub(500, 300, 400)),
+            CombineOperation::Z64(Operation::AssertZero(500)),
+            CombineOperation::Z64(Operation::AddConst(600, 100, 7)),
+        ];
+        let pipeline = Pipeline::new()
+            .then(Stage::Cse)
+            .then(Stage::DeadCode { keep_inputs: true })
+            .then(Stage::Renumber)
+            .then(Stage::RefreshSizeHints);
+
+        let mut seen = Vec::new();
+        let (optimized, reports) =
+            pipeline.run_with_progress(program.clone(), |report| seen.push(report.index));
+        assert_eq!(seen, [0, 1, 2, 3]);
+        assert_eq!(reports[1].gates_before, 7);
+        assert_eq!(reports[1].gates_after, 6);
+        assert_eq!(reports[2].wires_before.0, 501);
+        assert_eq!(reports[2].wires_after.0, 5);
+        assert_eq!(optimized[0], CombineOperation::SizeHint(5, 1));
+        assert_eq!(
+            optimized[4],
+            CombineOperation::Z64(Operation::AddConst(3, 2, 0))
+        );
+        evaluate_composite_program(&program, &[], &[3, 4]);
+        evaluate_composite_program(&optimized, &[], &[3, 4]);
+
+        // Options left out of a saved pipeline get their defaults
+        let saved = r#"{"stages": [{"stage": "dedupe"}, {"stage": "dead_code"}]}"#;
+        assert_eq!(
+            Pipeline::from_json(saved).unwrap().stages,
+            [Stage::Dedupe, Stage::DeadCode { keep_inputs: true }]
+        );
+        assert!(Pipeline::from_json(r#"{"stages": [{"stage": "inline"}]}"#).is_err());
+
+        // Dropping inputs shows up in the witness mapping
+        let dropping = Pipeline::new()
+            .then(Stage::DeadCode { keep_inputs: false })
+            .then(Stage::Renumber);
+        let (optimized, _, mapping) = dropping.run_with_mapping(program.clone());
+        assert_eq!(mapping.z64, [Some(0), Some(1)]);
+        let (_, _, mapping) = dropping.run_with_mapping(program[..2].to_vec());
+        assert_eq!(mapping.z64, [None, None]);
+        assert!(mapping
+            .map_values(Domain::Z64, &[3u64, 4])
+            .unwrap()
+            .is_empty());
+        evaluate_composite_program(&optimized, &[], &[3, 4]);
+    }
+}
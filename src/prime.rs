@@ -0,0 +1,264 @@
+//! Values in prime fields, for arithmetic circuits over the fields SIEVE backends prove in rather
+//! than over Z64.
+//!
+//! `Fp<P>` is an element of GF(P), stored canonically (below `P`). It's a `WireValue` in the
+//! arithmetic domain, so `Operation<Fp<P>>` works with the single-domain machinery (`HasConst`,
+//! `Identity`, `Translatable`, the IR1 exporter), and `evaluate_prime_program` evaluates it.
+//! Composite programs stay GF2 and Z64: a `CombineOperation` can't hold prime field gates.
+
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use num_traits::{One, Zero};
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::eval::EvaluationError;
+use crate::{Domain, Field, Operation, WireValue};
+
+/// An element of the prime field GF(P). `P` isn't checked to be prime, but has to be at least 2:
+/// `Fp::new` doesn't compile for smaller ones, and the IR1 exporter refuses them.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(from = "u64", into = "u64")]
+pub struct Fp<const P: u64>(u64);
+
+/// GF(2^61 - 1), whose Mersenne prime modulus makes it popular with ZK backends.
+pub type Mersenne61 = Fp<{ (1 << 61) - 1 }>;
+
+impl<const P: u64> Fp<P> {
+    /// The field, as declared to exporters.
+    pub const FIELD: Field = Field {
+        characteristic: P,
+        degree: 1,
+    };
+
+    /// Reduces `value` into the field.
+    pub fn new(value: u64) -> Self {
+        const { assert!(P >= 2, "GF(P) needs P to be at least 2") };
+        Fp(value % P)
+    }
+
+    /// The canonical representative, below `P`.
+    pub fn value(self) -> u64 {
+        self.0
+    }
+
+    /// The multiplicative inverse, or `None` for zero.
+    pub fn inverse(self) -> Option<Self> {
+        if self.0 == 0 {
+            return None;
+        }
+        // Fermat's little theorem: x^(P-2) = x^-1
+        let (mut base, mut exp, mut acc) = (self, P - 2, Fp::one());
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = acc * base;
+            }
+            base = base * base;
+            exp >>= 1;
+        }
+        Some(acc)
+    }
+}
+
+impl<const P: u64> From<u64> for Fp<P> {
+    fn from(value: u64) -> Self {
+        Fp::new(value)
+    }
+}
+
+impl<const P: u64> From<Fp<P>> for u64 {
+    fn from(value: Fp<P>) -> Self {
+        value.0
+    }
+}
+
+impl<const P: u64> fmt::Display for Fp<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<const P: u64> Add for Fp<P> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Fp(((u128::from(self.0) + u128::from(other.0)) % u128::from(P)) as u64)
+    }
+}
+
+impl<const P: u64> Sub for Fp<P> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self + -other
+    }
+}
+
+impl<const P: u64> Neg for Fp<P> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        if self.0 == 0 {
+            self
+        } else {
+            Fp(P - self.0)
+        }
+    }
+}
+
+impl<const P: u64> Mul for Fp<P> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Fp(((u128::from(self.0) * u128::from(other.0)) % u128::from(P)) as u64)
+    }
+}
+
+impl<const P: u64> Zero for Fp<P> {
+    fn zero() -> Self {
+        Fp(0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl<const P: u64> One for Fp<P> {
+    fn one() -> Self {
+        Fp(1 % P)
+    }
+}
+
+impl<const P: u64> WireValue for Fp<P> {
+    const DOMAIN: Domain = Domain::Z64;
+
+    fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn to_le_bytes(&self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl<const P: u64> Distribution<Fp<P>> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Fp<P> {
+        Fp(rng.gen_range(0..P))
+    }
+}
+
+/// Evaluates a circuit over GF(P), returning the final value of every wire (zero for ones that
+/// were never written). Fails at the first assertion that doesn't hold, or when the witness runs
+/// out; errors report values as their canonical representatives.
+pub fn evaluate_prime_program<const P: u64>(
+    gates: &[Operation<Fp<P>>],
+    inputs: &[Fp<P>],
+) -> Result<Vec<Fp<P>>, EvaluationError> {
+    let mut wires: Vec<Fp<P>> = Vec::new();
+    let mut inputs = inputs.iter().copied();
+    let get = |wires: &Vec<Fp<P>>, wire: usize| wires.get(wire).copied().unwrap_or_default();
+
+    for (idx, gate) in gates.iter().enumerate() {
+        let (dst, value) = match *gate {
+            Operation::Input(dst) => match inputs.next() {
+                Some(value) => (dst, value),
+                None => {
+                    return Err(EvaluationError::OutOfInputs {
+                        gate: idx,
+                        domain: Domain::Z64,
+                    })
+                }
+            },
+            Operation::Random(dst) => (dst, rand::random()),
+            Operation::Add(dst, a, b) => (dst, get(&wires, a) + get(&wires, b)),
+            Operation::AddConst(dst, a, c) => (dst, get(&wires, a) + c),
+            Operation::Sub(dst, a, b) => (dst, get(&wires, a) - get(&wires, b)),
+            Operation::SubConst(dst, a, c) => (dst, get(&wires, a) - c),
+            Operation::Mul(dst, a, b) => (dst, get(&wires, a) * get(&wires, b)),
+            Operation::MulConst(dst, a, c) => (dst, get(&wires, a) * c),
+            Operation::Const(dst, c) => (dst, c),
+            Operation::AssertZero(wire) => {
+                let value = get(&wires, wire);
+                if value.0 != 0 {
+                    return Err(EvaluationError::AssertionFailed {
+                        gate: idx,
+                        domain: Domain::Z64,
+                        wire,
+                        value: value.value(),
+                    });
+                }
+                continue;
+            }
+        };
+        if dst >= wires.len() {
+            wires.resize(dst + 1, Fp::zero());
+        }
+        wires[dst] = value;
+    }
+    Ok(wires)
+}
+
+#[cfg(test)]
+mod tests {
+    use num_traits::{One, Zero};
+
+    use crate::eval::EvaluationError;
+    use crate::prime::{evaluate_prime_program, Fp, Mersenne61};
+    use crate::{Identity, Operation};
+
+    type F7 = Fp<7>;
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(F7::new(12).value(), 5);
+        assert_eq!(F7::new(5) + F7::new(4), F7::new(2));
+        assert_eq!(F7::new(2) - F7::new(5), F7::new(4));
+        assert_eq!(F7::new(3) * F7::new(5), F7::new(1));
+        assert_eq!(F7::new(3).inverse(), Some(F7::new(5)));
+        assert_eq!(F7::zero().inverse(), None);
+
+        let big = Mersenne61::new(u64::MAX);
+        assert_eq!(big.value(), (u64::MAX) % ((1 << 61) - 1));
+        assert_eq!(big * big.inverse().unwrap(), Mersenne61::one());
+        assert_eq!(Mersenne61::FIELD.characteristic, (1 << 61) - 1);
+        assert_eq!(serde_json::to_string(&F7::new(3)).unwrap(), "3");
+        assert_eq!(serde_json::from_str::<F7>("10").unwrap(), F7::new(3));
+
+        assert!(Operation::MulConst(1, 0, F7::one()).is_identity());
+        assert!(Operation::AddConst(1, 0, F7::new(7)).is_identity());
+        assert!(!Operation::SubConst(1, 0, F7::new(1)).is_identity());
+    }
+
+    #[test]
+    fn test_prime_evaluation() {
+        // x * y = 1, so y is the inverse of x
+        let gates = [
+            Operation::Input(0),
+            Operation::Input(1),
+            Operation::Mul(2, 0, 1),
+            Operation::SubConst(3, 2, F7::one()),
+            Operation::AssertZero(3),
+        ];
+        let wires = evaluate_prime_program(&gates, &[F7::new(3), F7::new(5)]).unwrap();
+        assert_eq!(wires[2], F7::one());
+
+        assert_eq!(
+            evaluate_prime_program(&gates, &[F7::new(3), F7::new(4)]),
+            Err(EvaluationError::AssertionFailed {
+                gate: 4,
+                domain: crate::Domain::Z64,
+                wire: 3,
+                value: 4,
+            })
+        );
+        assert!(matches!(
+            evaluate_prime_program(&gates, &[F7::new(3)]),
+            Err(EvaluationError::OutOfInputs { gate: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,1155 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{AnalysisPass, WireCounter};
+use crate::eval::size_hint;
+use crate::parsers::WireHasher;
+use crate::{CombineOperation, Domain, Field, HasIO, Operation, Translatable, WireValue};
+
+/// Human-readable names for wires, kept separately for each domain.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameTable {
+    pub gf2: BTreeMap<usize, String>,
+    pub z64: BTreeMap<usize, String>,
+}
+
+impl NameTable {
+    /// Recovers the names of every wire the hashers have seen. Only hashers that remember names
+    /// (like `BackrefHasher`) have any to recover.
+    pub fn from_hashers(bool_hasher: &dyn WireHasher, arith_hasher: &dyn WireHasher) -> Self {
+        let recover = |hasher: &dyn WireHasher| {
+            (0..hasher.len())
+                .filter_map(|id| hasher.backref(id).map(|name| (id, name.to_string())))
+                .collect()
+        };
+
+        NameTable {
+            gf2: recover(bool_hasher),
+            z64: recover(arith_hasher),
+        }
+    }
+
+    pub fn get(&self, domain: Domain, wire: usize) -> Option<&String> {
+        match domain {
+            Domain::GF2 => self.gf2.get(&wire),
+            Domain::Z64 => self.z64.get(&wire),
+        }
+    }
+
+    pub fn insert(&mut self, domain: Domain, wire: usize, name: String) {
+        match domain {
+            Domain::GF2 => self.gf2.insert(wire, name),
+            Domain::Z64 => self.z64.insert(wire, name),
+        };
+    }
+}
+
+/// A group of wires that together represent a single multi-bit value.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bus {
+    pub name: String,
+    pub domain: Domain,
+    /// Member wires, least significant first
+    pub wires: Vec<usize>,
+}
+
+/// GF2 constants for a run of consecutive wires, packed 64 to a word, so a wide constant like a
+/// hash IV doesn't need a `Const` gate for every bit.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstVector {
+    /// The first wire
+    pub low: usize,
+    /// How many wires the vector writes
+    pub len: usize,
+    /// Wire `low + i` gets bit `i % 64` of word `i / 64`
+    pub words: Vec<u64>,
+}
+
+impl ConstVector {
+    /// Bits for wires `low`, `low + 1`, and so on.
+    pub fn from_bits(low: usize, bits: &[bool]) -> Self {
+        let mut words = vec![0; bits.len().div_ceil(64)];
+        for (i, bit) in bits.iter().enumerate() {
+            words[i / 64] |= u64::from(*bit) << (i % 64);
+        }
+        ConstVector {
+            low,
+            len: bits.len(),
+            words,
+        }
+    }
+
+    /// Parses a number written in hex, most significant digit first, so the low bit of the last
+    /// digit goes to `low`. Every digit is four wires, leading zeros included. Allows a `0x`
+    /// prefix and `_` between digits. `None` if `hex` isn't a hex number.
+    pub fn from_hex(low: usize, hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix("0x").unwrap_or(hex);
+        let digits: Vec<u32> = hex
+            .chars()
+            .filter(|c| *c != '_')
+            .rev()
+            .map(|c| c.to_digit(16))
+            .collect::<Option<_>>()?;
+        if digits.is_empty() {
+            return None;
+        }
+        let bits: Vec<bool> = digits
+            .iter()
+            .flat_map(|digit| (0..4).map(move |i| digit >> i & 1 == 1))
+            .collect();
+        Some(ConstVector::from_bits(low, &bits))
+    }
+
+    /// The constant for the `i`th wire of the vector.
+    pub fn bit(&self, i: usize) -> bool {
+        assert!(i < self.len, "bit {} of a {}-bit vector", i, self.len);
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    pub fn wires(&self) -> Range<usize> {
+        self.low..self.low + self.len
+    }
+
+    /// The `Const` gates the vector stands for.
+    pub fn gates(&self) -> impl Iterator<Item = CombineOperation> + '_ {
+        (0..self.len)
+            .map(move |i| CombineOperation::GF2(Operation::Const(self.low + i, self.bit(i))))
+    }
+}
+
+/// Where a program came from.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Name and version of the tool that produced the program
+    pub tool: String,
+    /// Files the program was generated from
+    pub sources: Vec<String>,
+}
+
+/// The library (or tool) that generated some of a program's gates, and how it was configured.
+/// Gadget libraries change how they build things between versions, and with their options, so
+/// `Program::content_hash` covers these to keep caches and proofs from outliving the logic that
+/// generated them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Generator {
+    pub library: String,
+    pub version: String,
+    /// Feature flags or options that affect the gates it generates
+    pub features: BTreeSet<String>,
+}
+
+impl Generator {
+    pub fn new(library: &str, version: &str) -> Self {
+        Generator {
+            library: library.to_string(),
+            version: version.to_string(),
+            features: BTreeSet::new(),
+        }
+    }
+
+    /// The gadgets in this crate, at this version. None of its cargo features change the gates
+    /// existing gadgets generate, so there are none to record.
+    pub fn mcircuit() -> Self {
+        Generator::new("mcircuit", env!("CARGO_PKG_VERSION"))
+    }
+
+    pub fn with_feature(mut self, feature: &str) -> Self {
+        self.features.insert(feature.to_string());
+        self
+    }
+}
+
+/// Notes on individual gates, keyed by gate index.
+pub type Annotations = BTreeMap<usize, String>;
+
+/// Names of the parameters that gates' constants stand for, keyed by gate index.
+pub type Parameters = BTreeMap<usize, String>;
+
+/// Why `Program::instantiate` couldn't substitute parameter values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParameterError {
+    /// No value was given for the parameter
+    Missing(String),
+    /// A value was given for a parameter the program doesn't have
+    Unknown(String),
+    /// The gate a parameter is attached to has no constant
+    NotConstant { gate: usize },
+    /// A parameter is attached to a gate past the end of the program
+    NoSuchGate { gate: usize, name: String },
+    /// A GF2 gate's parameter was given a value other than 0 or 1
+    OutOfRange {
+        gate: usize,
+        name: String,
+        value: u64,
+    },
+}
+
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterError::Missing(name) => write!(f, "no value for parameter {}", name),
+            ParameterError::Unknown(name) => write!(f, "program has no parameter {}", name),
+            ParameterError::NotConstant { gate } => {
+                write!(f, "gate {} has a parameter but no constant", gate)
+            }
+            ParameterError::NoSuchGate { gate, name } => {
+                write!(
+                    f,
+                    "parameter {} is on gate {}, which doesn't exist",
+                    name, gate
+                )
+            }
+            ParameterError::OutOfRange { gate, name, value } => write!(
+                f,
+                "parameter {} is {}, but gate {} is boolean",
+                name, value, gate
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParameterError {}
+
+/// Why `Program::strip_domain` couldn't remove a domain: removing the gate at `gate` would change
+/// the witness the program takes or what it checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DomainInUse {
+    pub domain: Domain,
+    pub gate: usize,
+}
+
+impl fmt::Display for DomainInUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gate {} depends on the {:?} domain, so it can't be stripped",
+            self.gate, self.domain
+        )
+    }
+}
+
+impl std::error::Error for DomainInUse {}
+
+/// `gate` with its constant replaced by `value`, or `None` if it doesn't have a constant. GF2
+/// values other than 0 and 1 are errors.
+fn substitute(gate: &CombineOperation, value: u64) -> Option<Result<CombineOperation, ()>> {
+    fn replace<T: WireValue>(op: &Operation<T>, c: T) -> Option<Operation<T>> {
+        match *op {
+            Operation::AddConst(dst, src, _) => Some(Operation::AddConst(dst, src, c)),
+            Operation::SubConst(dst, src, _) => Some(Operation::SubConst(dst, src, c)),
+            Operation::MulConst(dst, src, _) => Some(Operation::MulConst(dst, src, c)),
+            Operation::Const(dst, _) => Some(Operation::Const(dst, c)),
+            _ => None,
+        }
+    }
+
+    match gate {
+        CombineOperation::GF2(op) => {
+            let replaced = replace(op, value == 1)?;
+            Some(if value > 1 {
+                Err(())
+            } else {
+                Ok(CombineOperation::GF2(replaced))
+            })
+        }
+        CombineOperation::Z64(op) => replace(op, value).map(|op| Ok(CombineOperation::Z64(op))),
+        _ => None,
+    }
+}
+
+/// A circuit, along with optional metadata that makes it easier to debug. None of the metadata
+/// affects how the circuit evaluates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub gates: Vec<CombineOperation>,
+    pub names: Option<NameTable>,
+    pub buses: Option<Vec<Bus>>,
+    pub provenance: Option<Provenance>,
+    /// The field each domain's values should be exported in
+    pub fields: Option<BTreeMap<Domain, Field>>,
+    /// Short human-readable notes on individual gates, keyed by index into `gates`. Exporters
+    /// that support comments write them next to the gate.
+    pub annotations: Option<Annotations>,
+    /// Gates whose constants stand for named parameters, like a memory size, so one program can
+    /// serve several configurations. Until `instantiate` substitutes real values, the constants
+    /// in those gates are placeholders (typically the default values), and evaluating or
+    /// exporting the program uses them as is.
+    pub parameters: Option<Parameters>,
+    /// A size hint kept out of the gate stream, as (Z64, GF2). It combines with any `SizeHint`
+    /// gates the way they combine with each other; see `size_hint`.
+    pub size_hint: Option<(usize, usize)>,
+    /// GF2 constants kept out of the gate stream, packed. Their wires are written before the first
+    /// gate, and aren't written by any gate. Evaluators and exporters only see gates, so call
+    /// `lower_const_vectors` before handing them the program.
+    pub const_vectors: Option<Vec<ConstVector>>,
+    /// Domains `strip_domain` removed, which the program has no gates or size hints in. Backends
+    /// that handle both domains can take this as a promise that they'll only see the other one.
+    pub stripped_domains: Option<BTreeSet<Domain>>,
+    /// Everything that generated the gates, in the order they were added. Like `const_vectors`,
+    /// and unlike the rest of the metadata, these count towards `content_hash`.
+    pub generators: Option<Vec<Generator>>,
+}
+
+impl Program {
+    /// See `content_hash`. Covers the gates, `const_vectors` and `generators`, but no other
+    /// metadata, since it doesn't change what the program computes. Without packed constants or
+    /// generators, it's the hash of the gates alone.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = ContentHasher::default();
+        for gate in &self.gates {
+            hasher.update(gate);
+        }
+        if let Some(vectors) = self.const_vectors.as_ref().filter(|v| !v.is_empty()) {
+            hasher.update_tagged(b"const-vectors", vectors);
+        }
+        if let Some(generators) = self.generators.as_ref().filter(|g| !g.is_empty()) {
+            hasher.update_tagged(b"generators", generators);
+        }
+        hasher.finish()
+    }
+
+    /// Records that `generator` generated some of the gates, unless it's already recorded.
+    pub fn generated_by(&mut self, generator: Generator) {
+        let generators = self.generators.get_or_insert_with(Vec::new);
+        if !generators.contains(&generator) {
+            generators.push(generator);
+        }
+    }
+
+    /// Attaches a note to the gate at `index`, replacing any existing one.
+    pub fn annotate(&mut self, index: usize, note: &str) {
+        self.annotations
+            .get_or_insert_with(BTreeMap::new)
+            .insert(index, note.to_string());
+    }
+
+    /// Marks the constant of the gate at `index` as standing for the parameter `name`. Several
+    /// gates can share a parameter.
+    pub fn parameterize(&mut self, index: usize, name: &str) {
+        self.parameters
+            .get_or_insert_with(BTreeMap::new)
+            .insert(index, name.to_string());
+    }
+
+    /// Names of all the program's parameters.
+    pub fn parameter_names(&self) -> BTreeSet<&str> {
+        self.parameters
+            .iter()
+            .flat_map(|parameters| parameters.values())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// A copy of the program with `values` substituted for its parameters, and no parameters
+    /// left. Every parameter needs a value, and every value a parameter.
+    pub fn instantiate(&self, values: &BTreeMap<String, u64>) -> Result<Program, ParameterError> {
+        let names = self.parameter_names();
+        if let Some(unknown) = values.keys().find(|name| !names.contains(name.as_str())) {
+            return Err(ParameterError::Unknown(unknown.clone()));
+        }
+
+        let mut program = self.clone();
+        for (gate, name) in program.parameters.take().unwrap_or_default() {
+            let value = *values
+                .get(&name)
+                .ok_or_else(|| ParameterError::Missing(name.clone()))?;
+            let slot = match program.gates.get_mut(gate) {
+                Some(slot) => slot,
+                None => return Err(ParameterError::NoSuchGate { gate, name }),
+            };
+            *slot = substitute(slot, value)
+                .ok_or(ParameterError::NotConstant { gate })?
+                .map_err(|_| ParameterError::OutOfRange { gate, name, value })?;
+        }
+        Ok(program)
+    }
+
+    /// The largest size hint in each domain, counting both `size_hint` and any `SizeHint` gates.
+    pub fn size_hint(&self) -> Option<(usize, usize)> {
+        let hints = self.size_hint.into_iter().chain(size_hint(&self.gates));
+        hints.reduce(|(z64, gf2), (hint_z64, hint_gf2)| (z64.max(hint_z64), gf2.max(hint_gf2)))
+    }
+
+    /// Moves any `SizeHint` gates into `size_hint`. Annotations and parameters follow the gates
+    /// they're attached to.
+    pub fn hoist_size_hints(&mut self) {
+        self.size_hint = self.size_hint();
+        let dropped: Vec<bool> = self
+            .gates
+            .iter()
+            .map(|gate| matches!(gate, CombineOperation::SizeHint(_, _)))
+            .collect();
+        self.drop_gates(&dropped);
+    }
+
+    /// Removes the gates marked in `dropped`. Annotations and parameters on the others follow
+    /// them, and those on a removed gate move to the next one kept.
+    fn drop_gates(&mut self, dropped: &[bool]) {
+        let mut new_index = Vec::with_capacity(self.gates.len());
+        let mut kept = 0;
+        for drop in dropped {
+            new_index.push(kept);
+            if !drop {
+                kept += 1;
+            }
+        }
+        let mut dropped = dropped.iter();
+        self.gates.retain(|_| !dropped.next().unwrap());
+        self.reindex(|idx| new_index[idx]);
+    }
+
+    fn reindex(&mut self, new_index: impl Fn(usize) -> usize) {
+        let reindex = |notes: &mut BTreeMap<usize, String>| {
+            *notes = std::mem::take(notes)
+                .into_iter()
+                .map(|(idx, note)| (new_index(idx), note))
+                .collect();
+        };
+        self.annotations.iter_mut().for_each(reindex);
+        self.parameters.iter_mut().for_each(reindex);
+    }
+
+    /// Packs runs of at least `min_len` GF2 `Const` gates on consecutive wires into
+    /// `const_vectors`, wherever that doesn't change what the program computes: each wire has to
+    /// be written by its `Const` alone, and not read before it.
+    pub fn compress_constants(&mut self, min_len: usize) {
+        let mut writes: HashMap<usize, usize> = HashMap::new();
+        let mut first_read: HashMap<usize, usize> = HashMap::new();
+        for (idx, gate) in self.gates.iter().enumerate() {
+            if gate.output_domain() == Some(Domain::GF2) {
+                for wire in gate.outputs() {
+                    *writes.entry(wire).or_default() += 1;
+                }
+            }
+            if gate.input_domain() == Some(Domain::GF2) {
+                for wire in gate.inputs() {
+                    first_read.entry(wire).or_insert(idx);
+                }
+            }
+        }
+
+        let mut candidates: Vec<(usize, usize, bool)> = self
+            .gates
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, gate)| match gate {
+                CombineOperation::GF2(Operation::Const(wire, value))
+                    if writes[wire] == 1 && first_read.get(wire).is_none_or(|r| *r > idx) =>
+                {
+                    Some((*wire, idx, *value))
+                }
+                _ => None,
+            })
+            .collect();
+        candidates.sort_unstable();
+
+        let mut dropped = vec![false; self.gates.len()];
+        let mut vectors = Vec::new();
+        for run in candidates.chunk_by(|a, b| b.0 == a.0 + 1) {
+            if run.len() < min_len.max(1) {
+                continue;
+            }
+            let bits: Vec<bool> = run.iter().map(|(_, _, value)| *value).collect();
+            vectors.push(ConstVector::from_bits(run[0].0, &bits));
+            for (_, idx, _) in run {
+                dropped[*idx] = true;
+            }
+        }
+        if !vectors.is_empty() {
+            self.const_vectors
+                .get_or_insert_with(Vec::new)
+                .extend(vectors);
+            self.drop_gates(&dropped);
+        }
+    }
+
+    /// Turns `const_vectors` back into `Const` gates, at the front of the program (after a leading
+    /// size hint, if there is one).
+    pub fn lower_const_vectors(&mut self) {
+        let vectors = match self.const_vectors.take() {
+            Some(vectors) => vectors,
+            None => return,
+        };
+        let at = usize::from(matches!(
+            self.gates.first(),
+            Some(CombineOperation::SizeHint(_, _))
+        ));
+        let gates: Vec<CombineOperation> = vectors.iter().flat_map(ConstVector::gates).collect();
+        let added = gates.len();
+        self.gates.splice(at..at, gates);
+        self.reindex(|idx| if idx < at { idx } else { idx + added });
+    }
+
+    /// Whether the program has anything in `domain`: a gate that reads or writes its wires, a size
+    /// hint for more than zero of them, or (for GF2) constant vectors.
+    pub fn uses_domain(&self, domain: Domain) -> bool {
+        let hinted = |(z64, gf2): (usize, usize)| match domain {
+            Domain::Z64 => z64 > 0,
+            Domain::GF2 => gf2 > 0,
+        };
+        let in_gates = self.gates.iter().any(|gate| match gate {
+            CombineOperation::SizeHint(z64, gf2) => hinted((*z64, *gf2)),
+            gate => gate.input_domain() == Some(domain) || gate.output_domain() == Some(domain),
+        });
+        let vectors = domain == Domain::GF2
+            && self
+                .const_vectors
+                .as_ref()
+                .is_some_and(|vectors| !vectors.is_empty());
+        in_gates || vectors || self.size_hint.is_some_and(hinted)
+    }
+
+    /// Removes every gate in `domain`, along with its size hints and any metadata about its wires,
+    /// and records the domain in `stripped_domains`. Only works if nothing else depends on the
+    /// domain: it can't have inputs or assertions, and GF2 can't be read by a B2A conversion.
+    /// Otherwise, fails without changing anything. Annotations and parameters on the removed
+    /// gates go with them.
+    pub fn strip_domain(&mut self, domain: Domain) -> Result<(), DomainInUse> {
+        let depends = self.gates.iter().position(|gate| match gate {
+            CombineOperation::GF2(Operation::Input(_) | Operation::AssertZero(_)) => {
+                domain == Domain::GF2
+            }
+            CombineOperation::Z64(Operation::Input(_) | Operation::AssertZero(_)) => {
+                domain == Domain::Z64
+            }
+            CombineOperation::B2A(_, _) => domain == Domain::GF2,
+            _ => false,
+        });
+        if let Some(gate) = depends {
+            return Err(DomainInUse { domain, gate });
+        }
+
+        let strip = |(z64, gf2): (usize, usize)| match domain {
+            Domain::Z64 => (0, gf2),
+            Domain::GF2 => (z64, 0),
+        };
+        let mut dropped = Vec::with_capacity(self.gates.len());
+        for gate in self.gates.iter_mut() {
+            dropped.push(match gate {
+                CombineOperation::SizeHint(z64, gf2) => {
+                    (*z64, *gf2) = strip((*z64, *gf2));
+                    (*z64, *gf2) == (0, 0)
+                }
+                gate => gate.output_domain() == Some(domain),
+            });
+        }
+        for notes in self
+            .annotations
+            .iter_mut()
+            .chain(self.parameters.iter_mut())
+        {
+            notes.retain(|idx, _| !dropped[*idx]);
+        }
+        self.drop_gates(&dropped);
+        self.size_hint = self.size_hint.map(strip).filter(|hint| *hint != (0, 0));
+
+        if let Some(names) = &mut self.names {
+            match domain {
+                Domain::GF2 => names.gf2.clear(),
+                Domain::Z64 => names.z64.clear(),
+            }
+        }
+        if let Some(buses) = &mut self.buses {
+            buses.retain(|bus| bus.domain != domain);
+        }
+        if let Some(fields) = &mut self.fields {
+            fields.remove(&domain);
+        }
+        if domain == Domain::GF2 {
+            self.const_vectors = None;
+        }
+        self.stripped_domains
+            .get_or_insert_with(BTreeSet::new)
+            .insert(domain);
+        Ok(())
+    }
+
+    /// Strips every domain the program has something in but doesn't depend on (see
+    /// `strip_domain`), returning the ones it stripped. Z64 goes first, since dropping its
+    /// conversions can leave GF2 unused too.
+    pub fn strip_dead_domains(&mut self) -> Vec<Domain> {
+        let mut stripped = Vec::new();
+        for domain in [Domain::Z64, Domain::GF2] {
+            if self.uses_domain(domain) && self.strip_domain(domain).is_ok() {
+                stripped.push(domain);
+            }
+        }
+        stripped
+    }
+
+    /// The gates, with the program's size hint (if it has one) as a single `SizeHint` at the
+    /// front, ready for tools that only look there.
+    pub fn gates_with_size_hint(&self) -> Vec<CombineOperation> {
+        let hint = self
+            .size_hint()
+            .map(|(z64, gf2)| CombineOperation::SizeHint(z64, gf2));
+        hint.into_iter()
+            .chain(
+                self.gates
+                    .iter()
+                    .filter(|gate| !matches!(gate, CombineOperation::SizeHint(_, _)))
+                    .copied(),
+            )
+            .collect()
+    }
+
+    /// How many wires of each domain, as (Z64, GF2), the program uses or its size hint allows for,
+    /// whichever is more.
+    fn wire_span(&self) -> (usize, usize) {
+        let ((z64, gf2), _) = WireCounter::analyze(
+            self.gates
+                .iter()
+                .filter(|g| !matches!(g, CombineOperation::SizeHint(_, _))),
+        );
+        let gf2 = self
+            .const_vectors
+            .iter()
+            .flatten()
+            .map(|vector| vector.wires().end)
+            .fold(gf2, usize::max);
+        match self.size_hint() {
+            Some((hint_z64, hint_gf2)) => (z64.max(hint_z64), gf2.max(hint_gf2)),
+            None => (z64, gf2),
+        }
+    }
+
+    /// Appends gates that work on the program's own wires, like the output of a gadget emitted
+    /// into a `ProgramEditor` built from it. Any `SizeHint` gates among them are merged into
+    /// `size_hint` rather than left in the middle of the program.
+    pub fn extend_with(&mut self, gates: impl IntoIterator<Item = CombineOperation>) -> &mut Self {
+        for gate in gates {
+            match gate {
+                CombineOperation::SizeHint(z64, gf2) => {
+                    let (old_z64, old_gf2) = self.size_hint.unwrap_or_default();
+                    self.size_hint = Some((old_z64.max(z64), old_gf2.max(gf2)));
+                }
+                gate => self.gates.push(gate),
+            }
+        }
+        self
+    }
+
+    /// Appends an assertion that each of `wires` is zero.
+    pub fn assert_zero(
+        &mut self,
+        domain: Domain,
+        wires: impl IntoIterator<Item = usize>,
+    ) -> &mut Self {
+        self.extend_with(wires.into_iter().map(|wire| match domain {
+            Domain::GF2 => CombineOperation::GF2(Operation::AssertZero(wire)),
+            Domain::Z64 => CombineOperation::Z64(Operation::AssertZero(wire)),
+        }))
+    }
+
+    /// Runs `next` after this program, on wires of its own: every wire of `next` is moved past the
+    /// wires this program uses or has a size hint for, so the two parts can't interfere. Inputs are
+    /// consumed in order, so the combined program reads this program's inputs followed by those
+    /// of `next`.
+    ///
+    /// Size hints (in either program, as gates or in `size_hint`) end up in `size_hint`, covering
+    /// both parts. Names, buses, annotations and parameters follow their wires and gates. This
+    /// program's provenance and fields win over those of `next`. A domain stays stripped only if
+    /// neither part uses it. Generators of both parts are kept.
+    pub fn then(mut self, mut next: Program) -> Program {
+        self.hoist_size_hints();
+        next.hoist_size_hints();
+        let (z64_base, gf2_base) = self.wire_span();
+        let (next_z64, next_gf2) = next.wire_span();
+        let shift = |domain: Domain, wire: usize| match domain {
+            Domain::Z64 => z64_base + wire,
+            Domain::GF2 => gf2_base + wire,
+        };
+
+        let gate_base = self.gates.len();
+        self.gates.extend(next.gates.iter().map(|gate| {
+            match gate {
+                CombineOperation::GF2(op) => CombineOperation::GF2(
+                    op.translate(
+                        op.inputs().map(|w| shift(Domain::GF2, w)),
+                        op.outputs().map(|w| shift(Domain::GF2, w)),
+                    )
+                    .expect("Operations are always translatable"),
+                ),
+                CombineOperation::Z64(op) => CombineOperation::Z64(
+                    op.translate(
+                        op.inputs().map(|w| shift(Domain::Z64, w)),
+                        op.outputs().map(|w| shift(Domain::Z64, w)),
+                    )
+                    .expect("Operations are always translatable"),
+                ),
+                CombineOperation::B2A(dst, low) => {
+                    CombineOperation::B2A(shift(Domain::Z64, *dst), shift(Domain::GF2, *low))
+                }
+                CombineOperation::SizeHint(_, _) => unreachable!("Hoisted above"),
+            }
+        }));
+
+        if self.size_hint.is_some() || next.size_hint.is_some() {
+            self.size_hint = Some((z64_base + next_z64, gf2_base + next_gf2));
+        }
+
+        if let Some(names) = next.names {
+            let table = self.names.get_or_insert_with(NameTable::default);
+            for (wire, name) in names.gf2 {
+                table.insert(Domain::GF2, shift(Domain::GF2, wire), name);
+            }
+            for (wire, name) in names.z64 {
+                table.insert(Domain::Z64, shift(Domain::Z64, wire), name);
+            }
+        }
+        if let Some(vectors) = next.const_vectors {
+            self.const_vectors
+                .get_or_insert_with(Vec::new)
+                .extend(vectors.into_iter().map(|vector| ConstVector {
+                    low: shift(Domain::GF2, vector.low),
+                    ..vector
+                }));
+        }
+        if let Some(buses) = next.buses {
+            self.buses
+                .get_or_insert_with(Vec::new)
+                .extend(buses.into_iter().map(|bus| Bus {
+                    wires: bus.wires.iter().map(|w| shift(bus.domain, *w)).collect(),
+                    ..bus
+                }));
+        }
+        for (ours, theirs) in [
+            (&mut self.annotations, next.annotations),
+            (&mut self.parameters, next.parameters),
+        ] {
+            if let Some(theirs) = theirs {
+                ours.get_or_insert_with(BTreeMap::new).extend(
+                    theirs
+                        .into_iter()
+                        .map(|(idx, note)| (gate_base + idx, note)),
+                );
+            }
+        }
+        if let Some(fields) = next.fields {
+            let ours = self.fields.get_or_insert_with(BTreeMap::new);
+            for (domain, field) in fields {
+                ours.entry(domain).or_insert(field);
+            }
+        }
+        self.provenance = self.provenance.or(next.provenance);
+        for generator in next.generators.into_iter().flatten() {
+            self.generated_by(generator);
+        }
+        let stripped: BTreeSet<Domain> = self
+            .stripped_domains
+            .take()
+            .into_iter()
+            .chain(next.stripped_domains)
+            .flatten()
+            .filter(|domain| !self.uses_domain(*domain))
+            .collect();
+        if !stripped.is_empty() {
+            self.stripped_domains = Some(stripped);
+        }
+        self
+    }
+
+    /// The field recorded for `domain`. GF2 gates default to GF(2), but there's no default for
+    /// Z64, since no field matches it exactly.
+    pub fn field(&self, domain: Domain) -> Option<Field> {
+        self.fields
+            .as_ref()
+            .and_then(|fields| fields.get(&domain).copied())
+            .or(match domain {
+                Domain::GF2 => Some(Field::GF2),
+                Domain::Z64 => None,
+            })
+    }
+}
+
+/// A fingerprint of a list of gates that's stable across platforms and Rust versions (unlike
+/// `DefaultHasher`), so it can be recorded in artifacts and compared later. Uses 64-bit FNV-1a over
+/// the binary encoding of each gate.
+pub fn content_hash(gates: &[CombineOperation]) -> u64 {
+    let mut hasher = ContentHasher::default();
+    for gate in gates {
+        hasher.update(gate);
+    }
+    hasher.finish()
+}
+
+/// Computes `content_hash` a gate at a time.
+pub(crate) struct ContentHasher {
+    hash: u64,
+}
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        ContentHasher {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl ContentHasher {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub(crate) fn update(&mut self, gate: &CombineOperation) {
+        let bytes = bincode::serialize(gate).expect("Gates are always serializable");
+        self.update_bytes(&bytes);
+    }
+
+    /// Hashes `tag`, then `value`, so the same bytes under different tags hash differently.
+    fn update_tagged<T: Serialize>(&mut self, tag: &[u8], value: &T) {
+        self.update_bytes(tag);
+        let bytes = bincode::serialize(value).expect("Metadata is always serializable");
+        self.update_bytes(&bytes);
+    }
+
+    pub(crate) fn update_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= u64::from(*byte);
+            self.hash = self.hash.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl From<Vec<CombineOperation>> for Program {
+    fn from(gates: Vec<CombineOperation>) -> Self {
+        Program {
+            gates,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    use crate::builder::CircuitBuilder;
+    use crate::program::{
+        content_hash, ConstVector, DomainInUse, Generator, ParameterError, Program,
+    };
+    use crate::serialize::{write_program, ProgramReader};
+    use crate::{
+        evaluate_composite_program, largest_wires, Bus, CombineOperation, Domain, Field, Operation,
+    };
+
+    #[test]
+    fn test_parameters() {
+        // Checks that the input is less than the memory size, which defaults to 16
+        let mut program: Program = vec![
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::SubConst(1, 0, 16)),
+            CombineOperation::GF2(Operation::Const(0, true)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+            CombineOperation::GF2(Operation::AssertZero(1)),
+        ]
+        .into();
+        program.parameterize(1, "memory_size");
+        program.parameterize(2, "enabled");
+        assert_eq!(
+            program.parameter_names().into_iter().collect::<Vec<_>>(),
+            ["enabled", "memory_size"]
+        );
+
+        let values: BTreeMap<String, u64> = vec![
+            ("memory_size".to_string(), 1024),
+            ("enabled".to_string(), 1),
+        ]
+        .into_iter()
+        .collect();
+        let instance = program.instantiate(&values).unwrap();
+        assert_eq!(instance.parameters, None);
+        assert_eq!(
+            instance.gates[1],
+            CombineOperation::Z64(Operation::SubConst(1, 0, 1024))
+        );
+        evaluate_composite_program(&instance.gates, &[], &[7]);
+
+        let mut wrong = values.clone();
+        wrong.insert("enabled".into(), 2);
+        assert_eq!(
+            program.instantiate(&wrong),
+            Err(ParameterError::OutOfRange {
+                gate: 2,
+                name: "enabled".into(),
+                value: 2
+            })
+        );
+        wrong.remove("enabled");
+        assert_eq!(
+            program.instantiate(&wrong),
+            Err(ParameterError::Missing("enabled".into()))
+        );
+        wrong.insert("enabled".into(), 0);
+        wrong.insert("memory".into(), 0);
+        assert_eq!(
+            program.instantiate(&wrong),
+            Err(ParameterError::Unknown("memory".into()))
+        );
+
+        // Parameters loaded from a file can name gates that aren't there
+        let mut truncated = program.clone();
+        truncated.gates.truncate(2);
+        assert_eq!(
+            truncated.instantiate(&values),
+            Err(ParameterError::NoSuchGate {
+                gate: 2,
+                name: "enabled".into()
+            })
+        );
+
+        program.parameterize(0, "memory_size");
+        assert_eq!(
+            program.instantiate(&values),
+            Err(ParameterError::NotConstant { gate: 0 })
+        );
+    }
+
+    #[test]
+    fn test_size_hints() {
+        let mut program: Program = vec![
+            CombineOperation::SizeHint(1, 2),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::SizeHint(0, 8),
+            CombineOperation::GF2(Operation::AssertZero(0)),
+        ]
+        .into();
+        program.annotate(3, "check it");
+        assert_eq!(largest_wires(&program.gates), (1, 8));
+        assert_eq!(program.size_hint(), Some((1, 8)));
+
+        program.hoist_size_hints();
+        assert_eq!(program.gates.len(), 2);
+        assert_eq!(program.size_hint, Some((1, 8)));
+        assert_eq!(program.annotations.as_ref().unwrap()[&1], "check it");
+        assert_eq!(
+            program.gates_with_size_hint()[..2],
+            [
+                CombineOperation::SizeHint(1, 8),
+                CombineOperation::GF2(Operation::Input(0))
+            ]
+        );
+        evaluate_composite_program(&program.gates_with_size_hint(), &[false], &[]);
+
+        let unhinted: Program = vec![CombineOperation::GF2(Operation::Input(0))].into();
+        assert_eq!(unhinted.gates_with_size_hint(), unhinted.gates);
+    }
+
+    #[test]
+    fn test_const_vectors() {
+        let vector = ConstVector::from_hex(3, "0x1_f").unwrap();
+        assert_eq!((vector.wires(), vector.words.clone()), (3..11, vec![0x1f]));
+        assert!(vector.bit(4) && !vector.bit(5));
+        assert_eq!(ConstVector::from_hex(0, "0xfg"), None);
+        assert_eq!(ConstVector::from_bits(0, &[true; 65]).words, [u64::MAX, 1]);
+
+        // Wires 1 to 4 are constants, but wire 4 is read before it's written and wire 5 is
+        // written twice
+        let gates = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Const(2, true)),
+            CombineOperation::GF2(Operation::Add(6, 0, 4)),
+            CombineOperation::GF2(Operation::Const(1, false)),
+            CombineOperation::GF2(Operation::Const(3, true)),
+            CombineOperation::GF2(Operation::Const(4, true)),
+            CombineOperation::GF2(Operation::Const(5, true)),
+            CombineOperation::GF2(Operation::Const(5, false)),
+            CombineOperation::GF2(Operation::Add(7, 2, 3)),
+            CombineOperation::GF2(Operation::AssertZero(7)),
+        ];
+        let mut program: Program = gates.clone().into();
+        program.annotate(9, "check");
+        program.compress_constants(2);
+        assert_eq!(
+            program.const_vectors,
+            Some(vec![ConstVector::from_bits(1, &[false, true, true])])
+        );
+        assert_eq!(program.gates.len(), 7);
+        assert_eq!(program.annotations.as_ref().unwrap()[&6], "check");
+
+        // Packed constants count towards the hash, like the gates they stand for
+        let hash = program.content_hash();
+        assert_ne!(hash, content_hash(&program.gates));
+        let mut flipped = program.clone();
+        flipped.const_vectors.as_mut().unwrap()[0].words[0] ^= 1;
+        assert_ne!(flipped.content_hash(), hash);
+
+        // Lowering puts the constants first, so the rest of the gates still see them
+        program.lower_const_vectors();
+        assert_eq!(program.const_vectors, None);
+        assert_eq!(program.gates.len(), gates.len());
+        assert_eq!(program.annotations.as_ref().unwrap()[&9], "check");
+        evaluate_composite_program(&program.gates, &[true], &[]);
+    }
+
+    #[test]
+    fn test_composition() {
+        // Checks that the witness bit is set
+        let mut check: Program = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+        ]
+        .into();
+        check.assert_zero(Domain::GF2, [1]);
+        check.annotate(2, "bit is set");
+
+        // Checks that the witness value is 5, with a size hint that leaves room for a scratch wire
+        let mut five: Program = vec![
+            CombineOperation::SizeHint(2, 0),
+            CombineOperation::Z64(Operation::Input(0)),
+        ]
+        .into();
+        five.extend_with([
+            CombineOperation::Z64(Operation::SubConst(1, 0, 5)),
+            CombineOperation::SizeHint(3, 0),
+        ])
+        .assert_zero(Domain::Z64, [1]);
+        assert_eq!(five.size_hint, Some((3, 0)));
+        five.buses = Some(vec![Bus {
+            name: "value".into(),
+            domain: Domain::Z64,
+            wires: vec![0],
+        }]);
+        five.annotate(3, "value is 5");
+
+        let both = check.clone().then(five.clone()).then(five);
+        assert_eq!(both.gates.len(), 9);
+        // Wire counts start at one even for unused domains, so everything moves up one Z64 wire
+        assert_eq!(
+            both.gates[3..6],
+            [
+                CombineOperation::Z64(Operation::Input(1)),
+                CombineOperation::Z64(Operation::SubConst(2, 1, 5)),
+                CombineOperation::Z64(Operation::AssertZero(2)),
+            ]
+        );
+        // The second copy starts after the first one's size hint, not just its wires
+        assert_eq!(both.gates[6], CombineOperation::Z64(Operation::Input(4)));
+        assert_eq!(both.size_hint, Some((7, 4)));
+        assert_eq!(both.buses.as_ref().unwrap()[1].wires, [4]);
+        let annotations = both.annotations.as_ref().unwrap();
+        assert_eq!(annotations.keys().collect::<Vec<_>>(), [&2, &5, &8]);
+        evaluate_composite_program(&both.gates_with_size_hint(), &[true], &[5, 5]);
+
+        // Unhinted programs just get shifted past each other's wires
+        let twice = check.clone().then(check);
+        assert_eq!(twice.size_hint, None);
+        assert_eq!(
+            twice.gates[4],
+            CombineOperation::GF2(Operation::AddConst(3, 2, true))
+        );
+    }
+
+    #[test]
+    fn test_strip_domains() {
+        // A boolean circuit that picked up arithmetic leftovers while it was flattened
+        let mut program: Program = vec![
+            CombineOperation::SizeHint(4, 3),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Const(0, 0)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+            CombineOperation::Z64(Operation::AddConst(1, 0, 1)),
+            CombineOperation::SizeHint(2, 0),
+            CombineOperation::GF2(Operation::AssertZero(1)),
+        ]
+        .into();
+        program.parameterize(4, "offset");
+        program.annotate(6, "bit is set");
+        program.size_hint = Some((4, 0));
+        program.fields = Some(vec![(Domain::Z64, Field::prime(101))].into_iter().collect());
+
+        assert_eq!(program.strip_dead_domains(), [Domain::Z64]);
+        assert_eq!(
+            program.gates,
+            [
+                CombineOperation::SizeHint(0, 3),
+                CombineOperation::GF2(Operation::Input(0)),
+                CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+                CombineOperation::GF2(Operation::AssertZero(1)),
+            ]
+        );
+        assert!(!program.uses_domain(Domain::Z64));
+        assert_eq!(program.size_hint, None);
+        assert_eq!(program.parameters, Some(BTreeMap::new()));
+        assert_eq!(program.annotations.as_ref().unwrap()[&3], "bit is set");
+        assert_eq!(program.fields, Some(BTreeMap::new()));
+        let stripped = program.stripped_domains.clone();
+        assert_eq!(stripped, Some(std::iter::once(Domain::Z64).collect()));
+
+        // The record survives saving, and composing with a part that uses the domain drops it
+        let mut sink = Vec::new();
+        write_program(&program, &mut sink).unwrap();
+        let mut reader = ProgramReader::new(Cursor::new(sink)).unwrap();
+        assert_eq!(reader.stripped_domains().unwrap(), stripped);
+        let arith: Program = vec![CombineOperation::Z64(Operation::Input(0))].into();
+        assert_eq!(program.clone().then(arith).stripped_domains, None);
+
+        // Domains the program depends on stay, and so does everything else
+        let before = program.clone();
+        assert_eq!(
+            program.strip_domain(Domain::GF2),
+            Err(DomainInUse {
+                domain: Domain::GF2,
+                gate: 1
+            })
+        );
+        assert_eq!(program, before);
+        assert_eq!(program.strip_dead_domains(), []);
+        let mut converts: Program = vec![
+            CombineOperation::GF2(Operation::Const(0, true)),
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::AssertZero(0)),
+        ]
+        .into();
+        assert_eq!(converts.strip_dead_domains(), []);
+        converts.gates.pop();
+        assert_eq!(converts.strip_dead_domains(), [Domain::Z64, Domain::GF2]);
+        assert!(converts.gates.is_empty());
+    }
+
+    #[test]
+    fn test_generators() {
+        let gates = vec![CombineOperation::GF2(Operation::Input(0))];
+        let mut program: Program = gates.clone().into();
+        let plain = program.content_hash();
+        assert_eq!(plain, crate::program::content_hash(&gates));
+
+        // What generated the gates counts, down to the version and features, but not their order
+        let v1 = Generator::new("gadgets", "1.0.0");
+        program.generated_by(v1.clone().with_feature("a").with_feature("b"));
+        let with_v1 = program.content_hash();
+        assert_ne!(with_v1, plain);
+        let mut reordered: Program = gates.clone().into();
+        reordered.generated_by(v1.clone().with_feature("b").with_feature("a"));
+        assert_eq!(reordered.content_hash(), with_v1);
+        let mut bumped: Program = gates.clone().into();
+        bumped.generated_by(
+            Generator::new("gadgets", "1.0.1")
+                .with_feature("a")
+                .with_feature("b"),
+        );
+        assert_ne!(bumped.content_hash(), with_v1);
+        let mut fewer: Program = gates.clone().into();
+        fewer.generated_by(v1.with_feature("a"));
+        assert_ne!(fewer.content_hash(), with_v1);
+
+        // Composing keeps every generator once, and the builder records itself
+        let combined = program.clone().then(reordered).then(bumped);
+        assert_eq!(combined.generators.as_ref().unwrap().len(), 2);
+        let mut builder = CircuitBuilder::new();
+        builder.input::<bool>();
+        let built = builder.finish_program();
+        assert_eq!(built.generators, Some(vec![Generator::mcircuit()]));
+
+        let mut sink = Vec::new();
+        write_program(&program, &mut sink).unwrap();
+        let mut reader = ProgramReader::new(Cursor::new(sink)).unwrap();
+        assert_eq!(reader.generators().unwrap(), program.generators);
+    }
+}
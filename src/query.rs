@@ -0,0 +1,420 @@
+//! Predicates over gates, for the one-off questions that otherwise take a hand-written loop: which
+//! gates multiply by a zero constant, which gates read some wire, and so on.
+//!
+//! Queries are built from a few basic tests combined with `and`, `or` and `not`, either in code or
+//! parsed from text:
+//!
+//! ```text
+//! kind=MulConst & const=0
+//! reads=gf2:12345 | writes=gf2:12345
+//! domain=z64 & !(kind=Input | kind=AssertZero)
+//! writes=z64:100..200,500
+//! ```
+//!
+//! * `kind` is an `Operation::kind` (`Add`, `MulConst`, ...), `B2A` or `SizeHint`.
+//! * `domain` is `gf2` or `z64`, and matches gates that read or write wires in that domain.
+//! * `reads` and `writes` take a domain and a comma-separated list of wires and half-open ranges
+//!   of wires, and match gates that read (or write) any of them.
+//! * `const` matches gates with that constant. GF2 constants are 0 or 1.
+//!
+//! `!` binds tightest, then `&`, then `|`; parentheses group.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::{CombineOperation, Domain, HasIO};
+
+/// A test that gates either pass or fail.
+///
+/// ```
+/// use mcircuit::{CombineOperation, Domain, Operation, Query};
+///
+/// let gates = [
+///     CombineOperation::Z64(Operation::Input(0)),
+///     CombineOperation::Z64(Operation::MulConst(1, 0, 0)),
+///     CombineOperation::Z64(Operation::MulConst(2, 0, 3)),
+/// ];
+/// let query = Query::kind("MulConst").and(Query::constant(0));
+/// assert_eq!(query.find(&gates), [1]);
+/// assert_eq!(Query::parse("kind=MulConst & const=0").unwrap(), query);
+/// assert_eq!(Query::reads(Domain::Z64, 0).not().find(&gates), [0]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Query {
+    Kind(String),
+    Domain(Domain),
+    Reads(Domain, Vec<Range<usize>>),
+    Writes(Domain, Vec<Range<usize>>),
+    Constant(u64),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+/// Why a query couldn't be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryParseError {
+    /// Byte offset into the query where the problem is
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for QueryParseError {}
+
+fn kind(gate: &CombineOperation) -> &'static str {
+    match gate {
+        CombineOperation::GF2(op) => op.kind(),
+        CombineOperation::Z64(op) => op.kind(),
+        CombineOperation::B2A(_, _) => "B2A",
+        CombineOperation::SizeHint(_, _) => "SizeHint",
+    }
+}
+
+fn contains(ranges: &[Range<usize>], wire: usize) -> bool {
+    ranges.iter().any(|range| range.contains(&wire))
+}
+
+impl Query {
+    pub fn kind(kind: &str) -> Self {
+        Query::Kind(kind.to_string())
+    }
+
+    pub fn domain(domain: Domain) -> Self {
+        Query::Domain(domain)
+    }
+
+    /// Gates that read `wire`.
+    pub fn reads(domain: Domain, wire: usize) -> Self {
+        Query::reads_any(domain, wire..wire + 1)
+    }
+
+    /// Gates that read any wire in `wires`.
+    pub fn reads_any(domain: Domain, wires: Range<usize>) -> Self {
+        Query::Reads(domain, vec![wires])
+    }
+
+    /// Gates that write `wire`.
+    pub fn writes(domain: Domain, wire: usize) -> Self {
+        Query::writes_any(domain, wire..wire + 1)
+    }
+
+    /// Gates that write any wire in `wires`.
+    pub fn writes_any(domain: Domain, wires: Range<usize>) -> Self {
+        Query::Writes(domain, vec![wires])
+    }
+
+    pub fn constant(value: u64) -> Self {
+        Query::Constant(value)
+    }
+
+    pub fn and(self, other: Query) -> Self {
+        Query::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Query) -> Self {
+        Query::Or(Box::new(self), Box::new(other))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Query::Not(Box::new(self))
+    }
+
+    pub fn matches(&self, gate: &CombineOperation) -> bool {
+        match self {
+            Query::Kind(k) => kind(gate) == k,
+            Query::Domain(domain) => {
+                gate.input_domain() == Some(*domain) || gate.output_domain() == Some(*domain)
+            }
+            Query::Reads(domain, wires) => {
+                gate.input_domain() == Some(*domain) && gate.inputs().any(|w| contains(wires, w))
+            }
+            Query::Writes(domain, wires) => {
+                gate.output_domain() == Some(*domain)
+                    && gate.dst().is_some_and(|w| contains(wires, w))
+            }
+            Query::Constant(value) => gate
+                .gf2_constants()
+                .map(u64::from)
+                .chain(gate.z64_constants())
+                .any(|c| c == *value),
+            Query::And(a, b) => a.matches(gate) && b.matches(gate),
+            Query::Or(a, b) => a.matches(gate) || b.matches(gate),
+            Query::Not(a) => !a.matches(gate),
+        }
+    }
+
+    /// Indices of the gates that match.
+    pub fn find(&self, gates: &[CombineOperation]) -> Vec<usize> {
+        gates
+            .iter()
+            .enumerate()
+            .filter(|(_, gate)| self.matches(gate))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Parses the text syntax described in the module documentation.
+    pub fn parse(text: &str) -> Result<Self, QueryParseError> {
+        let mut parser = Parser { text, position: 0 };
+        let query = parser.or()?;
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("expected & or | between tests"));
+        }
+        Ok(query)
+    }
+}
+
+fn domain_name(domain: Domain) -> &'static str {
+    match domain {
+        Domain::GF2 => "gf2",
+        Domain::Z64 => "z64",
+    }
+}
+
+fn write_wires(f: &mut fmt::Formatter<'_>, domain: Domain, wires: &[Range<usize>]) -> fmt::Result {
+    let wires: Vec<String> = wires
+        .iter()
+        .map(|range| {
+            if range.len() == 1 {
+                range.start.to_string()
+            } else {
+                format!("{}..{}", range.start, range.end)
+            }
+        })
+        .collect();
+    write!(f, "{}:{}", domain_name(domain), wires.join(","))
+}
+
+/// Writes the query in the text syntax, fully parenthesized.
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Query::Kind(kind) => write!(f, "kind={}", kind),
+            Query::Domain(domain) => write!(f, "domain={}", domain_name(*domain)),
+            Query::Reads(domain, wires) => {
+                write!(f, "reads=")?;
+                write_wires(f, *domain, wires)
+            }
+            Query::Writes(domain, wires) => {
+                write!(f, "writes=")?;
+                write_wires(f, *domain, wires)
+            }
+            Query::Constant(value) => write!(f, "const={}", value),
+            Query::And(a, b) => write!(f, "({} & {})", a, b),
+            Query::Or(a, b) => write!(f, "({} | {})", a, b),
+            Query::Not(a) => write!(f, "!{}", a),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> QueryParseError {
+        QueryParseError {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `token` if it's next.
+    fn eat(&mut self, token: char) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Query, QueryParseError> {
+        let mut query = self.and()?;
+        while self.eat('|') {
+            query = query.or(self.and()?);
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, QueryParseError> {
+        let mut query = self.unary()?;
+        while self.eat('&') {
+            query = query.and(self.unary()?);
+        }
+        Ok(query)
+    }
+
+    fn unary(&mut self) -> Result<Query, QueryParseError> {
+        if self.eat('!') {
+            return Ok(self.unary()?.not());
+        }
+        if self.eat('(') {
+            let query = self.or()?;
+            if !self.eat(')') {
+                return Err(self.error("expected )"));
+            }
+            return Ok(query);
+        }
+        self.test()
+    }
+
+    /// The next run of characters that can be part of a key or value.
+    fn word(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | ':' | ',' | '.')))
+            .unwrap_or(rest.len());
+        self.position += len;
+        &rest[..len]
+    }
+
+    fn test(&mut self) -> Result<Query, QueryParseError> {
+        let start = self.position;
+        let key = self.word();
+        if key.is_empty() {
+            return Err(self.error("expected a test like kind=Mul"));
+        }
+        if !self.eat('=') {
+            return Err(self.error("expected = after the key"));
+        }
+        let value_start = self.position;
+        let value = self.word();
+        let invalid = |message: String| QueryParseError {
+            position: value_start,
+            message,
+        };
+        match key {
+            "kind" if !value.is_empty() => Ok(Query::kind(value)),
+            "domain" => parse_domain(value).map(Query::Domain).map_err(invalid),
+            "reads" => parse_wires(value)
+                .map(|(domain, wires)| Query::Reads(domain, wires))
+                .map_err(invalid),
+            "writes" => parse_wires(value)
+                .map(|(domain, wires)| Query::Writes(domain, wires))
+                .map_err(invalid),
+            "const" => value
+                .parse()
+                .map(Query::Constant)
+                .map_err(|_| invalid(format!("{} isn't a constant", value))),
+            "kind" => Err(invalid("expected a gate kind".into())),
+            _ => Err(QueryParseError {
+                position: start,
+                message: format!("unknown key {}", key),
+            }),
+        }
+    }
+}
+
+fn parse_domain(text: &str) -> Result<Domain, String> {
+    match text.to_ascii_lowercase().as_str() {
+        "gf2" => Ok(Domain::GF2),
+        "z64" => Ok(Domain::Z64),
+        _ => Err(format!("unknown domain {}", text)),
+    }
+}
+
+/// Parses `domain:wires`, where wires are single wires or half-open ranges separated by commas.
+fn parse_wires(text: &str) -> Result<(Domain, Vec<Range<usize>>), String> {
+    let (domain, wires) = text
+        .split_once(':')
+        .ok_or_else(|| format!("expected domain:wires, found {}", text))?;
+    let wire = |w: &str| {
+        w.parse::<usize>()
+            .map_err(|_| format!("{} isn't a wire index", w))
+    };
+    let wires = wires
+        .split(',')
+        .map(|part| match part.split_once("..") {
+            Some((start, end)) => Ok(wire(start)?..wire(end)?),
+            None => {
+                let w = wire(part)?;
+                let end = w
+                    .checked_add(1)
+                    .ok_or_else(|| format!("{} is past the last wire index", w))?;
+                Ok(w..end)
+            }
+        })
+        .collect::<Result<_, String>>()?;
+    Ok((parse_domain(domain)?, wires))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{Query, QueryParseError};
+    use crate::{CombineOperation, Domain, Operation};
+
+    #[test]
+    fn test_query() {
+        let gates = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::MulConst(1, 0, false)),
+            CombineOperation::GF2(Operation::AddConst(2, 1, true)),
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::MulConst(1, 0, 0)),
+            CombineOperation::Z64(Operation::Add(150, 1, 0)),
+            CombineOperation::Z64(Operation::AssertZero(150)),
+            CombineOperation::SizeHint(151, 64),
+        ];
+        let find = |text: &str| Query::parse(text).unwrap().find(&gates);
+
+        assert_eq!(find("kind=MulConst & const=0"), [1, 4]);
+        assert_eq!(find("const=1"), [2]);
+        // B2A reads GF2 wires and writes a Z64 one
+        assert_eq!(find("reads=gf2:0"), [1, 3]);
+        assert_eq!(find("reads=z64:0"), [4, 5]);
+        assert_eq!(find("domain=gf2 & domain=z64"), [3]);
+        assert_eq!(find("writes=z64:100..200,1"), [4, 5]);
+        assert_eq!(find("kind=SizeHint | kind=AssertZero"), [6, 7]);
+        assert_eq!(
+            find("domain=z64 & !(kind=MulConst | kind=AssertZero)"),
+            [3, 5]
+        );
+        // & binds tighter than |
+        assert_eq!(find("kind=Input | kind=B2A & reads=gf2:70"), [0]);
+        assert_eq!(find(" ! ! kind=Input "), [0]);
+
+        let query = Query::domain(Domain::Z64)
+            .and(Query::writes_any(Domain::Z64, 100..200).or(Query::reads(Domain::Z64, 150)))
+            .not();
+        assert_eq!(
+            query.to_string(),
+            "!(domain=z64 & (writes=z64:100..200 | reads=z64:150))"
+        );
+        assert_eq!(Query::parse(&query.to_string()).unwrap(), query);
+
+        assert_eq!(
+            Query::parse("kind=Mul & wire=3"),
+            Err(QueryParseError {
+                position: 11,
+                message: "unknown key wire".into()
+            })
+        );
+        assert_eq!(Query::parse("reads=gf3:1").unwrap_err().position, 6);
+        assert!(Query::parse("(kind=Mul").is_err());
+        assert!(Query::parse("kind=Mul kind=Add").is_err());
+        assert!(Query::parse("const=-1").is_err());
+        let max = format!("reads=gf2:{}", usize::MAX);
+        assert_eq!(Query::parse(&max).unwrap_err().position, 6);
+    }
+}
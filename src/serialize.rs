@@ -0,0 +1,1166 @@
+//! A compact binary format for programs.
+//!
+//! A file starts with a fixed header (magic bytes and format version), followed by a table of
+//! named sections and then the section payloads. Only the `gates` section is required; the
+//! metadata sections are each optional, and `ProgramReader` only decodes the ones you ask for.
+//! Readers skip sections they don't recognize, so new section kinds can be added without breaking
+//! old readers.
+//!
+//! # Versions
+//!
+//! Files record two versions. The *format version* covers the container: the header, section
+//! table and metadata sections. The *IR version* covers the encoding of the `gates` section, which
+//! changes whenever a gate is added to or removed from `CombineOperation` or `Operation`. A reader
+//! upgrades gates from any IR version it knows about, so a circuit compiler and a prover built
+//! against different versions of this crate can still exchange programs, as long as the older of
+//! the two can read what the newer one writes. `write_program_version` can write an older format
+//! for that purpose.
+//!
+//! * Format 1 has no IR version in its header. Its gates are always IR version 1.
+//! * Format 2 adds the IR version (a little-endian `u32`) straight after the format version.
+//! * Format 3 requires a `checksums` section, described below.
+//!
+//! Files with more than `GATE_INDEX_STRIDE` gates also get a `gate-index` section, holding the
+//! offset of every `GATE_INDEX_STRIDE`th gate in the `gates` section, so readers can get at
+//! individual gates without decoding everything before them.
+//!
+//! A `const-vectors` section holds `Program::const_vectors`: GF2 constants packed 64 to a word
+//! instead of stored as a `Const` gate per wire. They're read back packed, and stay that way until
+//! `Program::lower_const_vectors` turns them into gates.
+//!
+//! Format 3 files have a `checksums` section, holding a 64-bit FNV-1a hash of every other
+//! section's payload. `ProgramReader` checks each section against it as it's decoded, so a file
+//! that was truncated or corrupted after it was written (a stale cache, say) is rejected rather
+//! than decoded into the wrong gates. Gates read one at a time, by `sample_gates`, aren't checked;
+//! `ProgramReader::verify` checks everything at once. A format 3 file without the section, or
+//! with a section it doesn't cover, is rejected when it's opened. Older formats are still read,
+//! but without any checks.
+//!
+//! `save_circuit` writes a bare list of gates to a file, for caching the output of a slow parse or
+//! flattening pass; `parsers::load_circuit` recognizes the file and reads it back.
+//!
+//! A `stripped-domains` section holds `Program::stripped_domains`, the domains
+//! `Program::strip_domain` removed.
+//!
+//! A `generators` section holds `Program::generators`.
+//!
+//! A `def-use` section can hold a `DefUseIndex` for the gates, written by
+//! `write_program_with_def_use`. It records the hash of the gates it was built from, and
+//! `ProgramReader::def_use` rejects it if they don't match.
+//!
+//! IR version 1 is the gate set of mcircuit 0.1. Its encoding is frozen in the private `ir1`
+//! module, and converting it to the current gates is one-to-one.
+//!
+//! # Wire indices
+//!
+//! Wire indices (and the counts in size hints) are always stored as 64-bit integers, whatever the
+//! width of `usize` on the machine that wrote them. Reading a program with an index that doesn't
+//! fit in this machine's `usize` fails with a `WireIndexError`, rather than truncating it. A
+//! reader can also be given a lower limit with `ProgramReader::with_wire_limit`, to check on a
+//! 64-bit host that a program will load on a 32-bit target like wasm32. The limit covers every
+//! section with wires in it (names, buses, the size hint, and constant vectors) as well as the
+//! gates.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::def_use::DefUseIndex;
+use crate::fingerprint::{sample_gates, sample_indices};
+use crate::program::{
+    Annotations, Bus, ConstVector, ContentHasher, Generator, NameTable, Parameters, Program,
+    Provenance,
+};
+use crate::Fingerprint;
+use crate::{CombineOperation, Domain, Field};
+
+const MAGIC: &[u8; 4] = b"MCIR";
+/// Format version written by `write_program`
+pub const FORMAT_VERSION: u32 = 3;
+/// IR version of the gates written by `write_program`
+pub const IR_VERSION: u32 = 1;
+
+const GATES: &str = "gates";
+const NAMES: &str = "names";
+const BUSES: &str = "buses";
+const PROVENANCE: &str = "provenance";
+const FIELDS: &str = "fields";
+const ANNOTATIONS: &str = "annotations";
+const PARAMETERS: &str = "parameters";
+const SIZE_HINT: &str = "size-hint";
+const CONST_VECTORS: &str = "const-vectors";
+const STRIPPED_DOMAINS: &str = "stripped-domains";
+const GENERATORS: &str = "generators";
+const GATE_INDEX: &str = "gate-index";
+const DEF_USE: &str = "def-use";
+const CHECKSUMS: &str = "checksums";
+
+/// How many gates apart the entries of the gate index are
+pub const GATE_INDEX_STRIDE: usize = 1024;
+
+#[derive(Serialize, Deserialize)]
+struct SectionEntry {
+    name: String,
+    /// Offset from the end of the section table
+    offset: u64,
+    len: u64,
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(Error::other)
+}
+
+/// Offset of every `GATE_INDEX_STRIDE`th gate from the start of the `gates` section.
+fn gate_index<T: Serialize>(gates: &[T]) -> Result<Vec<u64>> {
+    // Skip the length of the vector
+    let mut offset = std::mem::size_of::<u64>() as u64;
+    let mut index = Vec::with_capacity(gates.len() / GATE_INDEX_STRIDE + 1);
+    for (i, gate) in gates.iter().enumerate() {
+        if i % GATE_INDEX_STRIDE == 0 {
+            index.push(offset);
+        }
+        offset += bincode::serialized_size(gate).map_err(Error::other)?;
+    }
+    Ok(index)
+}
+
+/// FNV-1a hash of a section's payload, the same hash `content_hash` uses.
+fn checksum(payload: &[u8]) -> u64 {
+    let mut hasher = ContentHasher::default();
+    hasher.update_bytes(payload);
+    hasher.finish()
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads the `len` bytes of `what`. The lengths come from the file, so a truncated or corrupt one
+/// can claim anything; the buffer only grows as bytes actually arrive.
+fn read_exactly(reader: &mut impl Read, len: u64, what: &str) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    reader.take(len).read_to_end(&mut payload)?;
+    if (payload.len() as u64) < len {
+        return Err(invalid(format!(
+            "the {} should be {} bytes, but the file ends after {}",
+            what,
+            len,
+            payload.len()
+        )));
+    }
+    Ok(payload)
+}
+
+/// A wire index (or size hint) in a program file that's larger than the reader allows, which is
+/// at most the largest `usize`. Returned wrapped in an `InvalidData` error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WireIndexError {
+    /// The section the index is in, like `gates` or `buses`
+    pub section: &'static str,
+    /// Index of the gate in the program, or of the entry in other sections
+    pub entry: usize,
+    pub index: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for WireIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.section == GATES {
+            write!(f, "gate {}", self.entry)?;
+        } else {
+            write!(f, "entry {} of the {} section", self.entry, self.section)?;
+        }
+        write!(
+            f,
+            " uses wire index {}, but this reader only allows up to {}",
+            self.index, self.limit
+        )
+    }
+}
+
+impl std::error::Error for WireIndexError {}
+
+/// The gates with 64-bit wire indices, as they're stored.
+fn widen(gates: &[CombineOperation]) -> Vec<ir1::CombineOperation> {
+    gates.iter().map(|gate| (*gate).into()).collect()
+}
+
+/// Fails with the first of `wires`, given with the entry they're in, that's larger than `limit`.
+fn check_wires(
+    section: &'static str,
+    wires: impl IntoIterator<Item = (usize, u64)>,
+    limit: u64,
+) -> Result<()> {
+    match wires.into_iter().find(|(_, wire)| *wire > limit) {
+        Some((entry, index)) => Err(Error::new(
+            ErrorKind::InvalidData,
+            WireIndexError {
+                section,
+                entry,
+                index,
+                limit,
+            },
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Converts a stored gate back, checking that its wires are at most `limit`.
+fn narrow(gate: ir1::CombineOperation, idx: usize, limit: u64) -> Result<CombineOperation> {
+    gate.narrow(limit).map_err(|index| {
+        Error::new(
+            ErrorKind::InvalidData,
+            WireIndexError {
+                section: GATES,
+                entry: idx,
+                index,
+                limit,
+            },
+        )
+    })
+}
+
+/// Writes a program and whichever metadata sections it has, in the current format.
+pub fn write_program(program: &Program, sink: &mut impl Write) -> Result<()> {
+    write_program_version(program, FORMAT_VERSION, sink)
+}
+
+/// Writes `gates` to a new file at `path` (replacing any file already there), in the current
+/// format and without any metadata. `parsers::load_circuit` reads it back.
+pub fn save_circuit(path: impl AsRef<Path>, gates: &[CombineOperation]) -> Result<()> {
+    let mut sink = BufWriter::new(File::create(path)?);
+    write_sections(
+        gates,
+        &Program::default(),
+        FORMAT_VERSION,
+        Vec::new(),
+        &mut sink,
+    )?;
+    sink.flush()
+}
+
+/// Writes a program along with a def-use index for it, which tools can load with
+/// `ProgramReader::def_use` instead of building it again. Fails if the index was built from other
+/// gates.
+pub fn write_program_with_def_use(
+    program: &Program,
+    index: &DefUseIndex,
+    sink: &mut impl Write,
+) -> Result<()> {
+    index
+        .check(&program.gates)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    write_sections(
+        &program.gates,
+        program,
+        FORMAT_VERSION,
+        vec![(DEF_USE, encode(index)?)],
+        sink,
+    )
+}
+
+/// Writes a program in an older format version, so it can be read by older versions of this
+/// crate. Fails if `format_version` isn't one this version knows how to write.
+pub fn write_program_version(
+    program: &Program,
+    format_version: u32,
+    sink: &mut impl Write,
+) -> Result<()> {
+    write_sections(&program.gates, program, format_version, Vec::new(), sink)
+}
+
+/// Writes `gates` with the metadata of `program` (but not its gates), and `extra` sections after
+/// the ones that come from the metadata.
+fn write_sections(
+    gates: &[CombineOperation],
+    program: &Program,
+    format_version: u32,
+    extra: Vec<(&str, Vec<u8>)>,
+    sink: &mut impl Write,
+) -> Result<()> {
+    // Every format stores IR version 1 gates
+    let (gates, index) = match format_version {
+        1..=FORMAT_VERSION => {
+            let gates = widen(gates);
+            (encode(&gates)?, gate_index(&gates)?)
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("can't write program format version {}", format_version),
+            ))
+        }
+    };
+    let count = gates.len();
+    let mut sections: Vec<(&str, Vec<u8>)> = vec![(GATES, gates)];
+    if count > GATE_INDEX_STRIDE {
+        sections.push((GATE_INDEX, encode(&index)?));
+    }
+    if let Some(names) = &program.names {
+        sections.push((NAMES, encode(names)?));
+    }
+    if let Some(buses) = &program.buses {
+        sections.push((BUSES, encode(buses)?));
+    }
+    if let Some(provenance) = &program.provenance {
+        sections.push((PROVENANCE, encode(provenance)?));
+    }
+    if let Some(fields) = &program.fields {
+        sections.push((FIELDS, encode(fields)?));
+    }
+    if let Some(annotations) = &program.annotations {
+        sections.push((ANNOTATIONS, encode(annotations)?));
+    }
+    if let Some(parameters) = &program.parameters {
+        sections.push((PARAMETERS, encode(parameters)?));
+    }
+    if let Some(size_hint) = &program.size_hint {
+        sections.push((SIZE_HINT, encode(size_hint)?));
+    }
+    if let Some(vectors) = &program.const_vectors {
+        sections.push((CONST_VECTORS, encode(vectors)?));
+    }
+    if let Some(domains) = &program.stripped_domains {
+        sections.push((STRIPPED_DOMAINS, encode(domains)?));
+    }
+    if let Some(generators) = &program.generators {
+        sections.push((GENERATORS, encode(generators)?));
+    }
+    sections.extend(extra);
+    if format_version >= 3 {
+        let checksums: BTreeMap<&str, u64> = sections
+            .iter()
+            .map(|(name, payload)| (*name, checksum(payload)))
+            .collect();
+        sections.push((CHECKSUMS, encode(&checksums)?));
+    }
+
+    let mut offset = 0;
+    let table: Vec<SectionEntry> = sections
+        .iter()
+        .map(|(name, payload)| {
+            let entry = SectionEntry {
+                name: name.to_string(),
+                offset,
+                len: payload.len() as u64,
+            };
+            offset += payload.len() as u64;
+            entry
+        })
+        .collect();
+    let table = encode(&table)?;
+
+    sink.write_all(MAGIC)?;
+    sink.write_all(&format_version.to_le_bytes())?;
+    if format_version >= 2 {
+        sink.write_all(&IR_VERSION.to_le_bytes())?;
+    }
+    sink.write_all(&(table.len() as u64).to_le_bytes())?;
+    sink.write_all(&table)?;
+    for (_, payload) in sections {
+        sink.write_all(&payload)?;
+    }
+
+    Ok(())
+}
+
+/// Reads programs written by `write_program`. Opening a reader only parses the section table;
+/// each section is decoded when it's requested.
+pub struct ProgramReader<R: Read + Seek> {
+    reader: R,
+    sections: Vec<SectionEntry>,
+    /// Position of the first payload byte
+    base: u64,
+    format_version: u32,
+    ir_version: u32,
+    /// Largest wire index to accept
+    wire_limit: u64,
+    /// Expected checksum of each section's payload. Empty for files older than format 3, which
+    /// aren't checked.
+    checksums: BTreeMap<String, u64>,
+}
+
+impl<R: Read + Seek> ProgramReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "not an mcircuit program file",
+            ));
+        }
+
+        let mut word = [0u8; 4];
+        reader.read_exact(&mut word)?;
+        let format_version = u32::from_le_bytes(word);
+        let ir_version = match format_version {
+            1 => 1,
+            2 | 3 => {
+                reader.read_exact(&mut word)?;
+                u32::from_le_bytes(word)
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "unsupported program format version {}",
+                    format_version
+                )))
+            }
+        };
+        if ir_version == 0 || ir_version > IR_VERSION {
+            return Err(invalid(format!(
+                "unsupported IR version {} (this version of mcircuit reads up to {})",
+                ir_version, IR_VERSION
+            )));
+        }
+
+        let mut len = [0u8; 8];
+        reader.read_exact(&mut len)?;
+        let table = read_exactly(&mut reader, u64::from_le_bytes(len), "section table")?;
+        let sections =
+            bincode::deserialize(&table).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let base = reader.stream_position()?;
+
+        let mut program = ProgramReader {
+            reader,
+            sections,
+            base,
+            format_version,
+            ir_version,
+            wire_limit: usize::MAX as u64,
+            checksums: BTreeMap::new(),
+        };
+        if format_version >= 3 {
+            let checksums: BTreeMap<String, u64> = program
+                .read_section(CHECKSUMS)?
+                .ok_or_else(|| invalid("the checksums section is missing".into()))?;
+            let unchecked = program
+                .section_names()
+                .find(|name| *name != CHECKSUMS && !checksums.contains_key(*name));
+            if let Some(name) = unchecked {
+                return Err(invalid(format!("the {} section has no checksum", name)));
+            }
+            program.checksums = checksums;
+        }
+        Ok(program)
+    }
+
+    /// Rejects gates and other sections with wire indices (or size hints) larger than `limit`, or
+    /// larger than the largest `usize`, whichever is smaller. Use `u32::MAX` to check that a
+    /// program can be loaded on a 32-bit target.
+    pub fn with_wire_limit(mut self, limit: u64) -> Self {
+        self.wire_limit = limit.min(usize::MAX as u64);
+        self
+    }
+
+    /// Format version of the file, which may be older than `FORMAT_VERSION`.
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    /// IR version of the file's gates, before they're upgraded to the current one.
+    pub fn ir_version(&self) -> u32 {
+        self.ir_version
+    }
+
+    /// Names of all the sections in the file, including ones this version doesn't understand.
+    pub fn section_names(&self) -> impl Iterator<Item = &str> {
+        self.sections.iter().map(|s| s.name.as_str())
+    }
+
+    pub fn has_section(&self, name: &str) -> bool {
+        self.sections.iter().any(|s| s.name == name)
+    }
+
+    /// Offset and length of a section's payload.
+    fn locate(&self, name: &str) -> Option<(u64, u64)> {
+        self.sections
+            .iter()
+            .find(|s| s.name == name)
+            .map(|entry| (self.base + entry.offset, entry.len))
+    }
+
+    /// Fails if the file has a checksum for the section and `payload` doesn't match it.
+    fn check(&self, name: &str, payload: &[u8]) -> Result<()> {
+        match self.checksums.get(name) {
+            Some(expected) if *expected != checksum(payload) => Err(invalid(format!(
+                "the {} section doesn't match its checksum; the file is corrupt",
+                name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks every section the file has a checksum for, without decoding any of them.
+    pub fn verify(&mut self) -> Result<()> {
+        let names: Vec<String> = self.checksums.keys().cloned().collect();
+        for name in names {
+            let (offset, len) = self
+                .locate(&name)
+                .ok_or_else(|| invalid(format!("the {} section is missing", name)))?;
+            self.reader.seek(SeekFrom::Start(offset))?;
+            let payload = read_exactly(&mut self.reader, len, &format!("{} section", name))?;
+            self.check(&name, &payload)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a single section, or returns `None` if the file doesn't have it.
+    pub fn read_section<T: DeserializeOwned>(&mut self, name: &str) -> Result<Option<T>> {
+        let (offset, len) = match self.locate(name) {
+            None => return Ok(None),
+            Some(found) => found,
+        };
+
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let payload = read_exactly(&mut self.reader, len, &format!("{} section", name))?;
+        self.check(name, &payload)?;
+
+        bincode::deserialize(&payload)
+            .map(Some)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Decodes the gates, upgrading them to the current IR version.
+    pub fn gates(&mut self) -> Result<Vec<CombineOperation>> {
+        let gates: Vec<ir1::CombineOperation> = self
+            .read_section(GATES)?
+            .ok_or_else(|| invalid("program has no gates section".into()))?;
+        gates
+            .into_iter()
+            .enumerate()
+            .map(|(idx, gate)| narrow(gate, idx, self.wire_limit))
+            .collect()
+    }
+
+    /// Number of gates in the file, without decoding any of them.
+    pub fn gate_count(&mut self) -> Result<usize> {
+        let (offset, _) = self
+            .locate(GATES)
+            .ok_or_else(|| invalid("program has no gates section".into()))?;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut len = [0u8; 8];
+        self.reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        usize::try_from(len).map_err(|_| invalid(format!("program has too many gates ({})", len)))
+    }
+
+    /// Decodes the gate at the reader's current position, which is gate `idx`.
+    fn next_gate(&mut self, idx: usize) -> Result<CombineOperation> {
+        let gate = bincode::deserialize_from(&mut self.reader)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        narrow(gate, idx, self.wire_limit)
+    }
+
+    /// Samples `count` distinct gates at random, returning them with their indices in program
+    /// order. Picks the same gates as `sample_gates` does for the same program and seed. If the
+    /// file has a gate index, only the blocks of gates the sample lands in are decoded.
+    pub fn sample_gates(
+        &mut self,
+        count: usize,
+        seed: u64,
+    ) -> Result<Vec<(usize, CombineOperation)>> {
+        let index: Vec<u64> = match self.read_section(GATE_INDEX)? {
+            Some(index) => index,
+            None => return Ok(sample_gates(&self.gates()?, count, seed)),
+        };
+        let total = self.gate_count()?;
+        let (gates_offset, _) = self.locate(GATES).expect("Checked by gate_count");
+
+        let mut sample = Vec::with_capacity(count.min(total));
+        // Index of the gate the reader is positioned at, if it's somewhere useful
+        let mut position: Option<usize> = None;
+        for idx in sample_indices(total, count, seed) {
+            let block = idx / GATE_INDEX_STRIDE;
+            let start = block * GATE_INDEX_STRIDE;
+            let mut at = match position {
+                Some(at) if at >= start && at <= idx => at,
+                _ => {
+                    let offset = index.get(block).ok_or_else(|| {
+                        invalid(format!("gate index has no entry for gate {}", start))
+                    })?;
+                    self.reader.seek(SeekFrom::Start(gates_offset + offset))?;
+                    start
+                }
+            };
+            while at < idx {
+                self.next_gate(at)?;
+                at += 1;
+            }
+            sample.push((idx, self.next_gate(idx)?));
+            position = Some(idx + 1);
+        }
+        Ok(sample)
+    }
+
+    /// Fingerprints the program from `count` randomly sampled gates. See `sample_gates`.
+    pub fn fingerprint(&mut self, count: usize, seed: u64) -> Result<Fingerprint> {
+        let sample = self.sample_gates(count, seed)?;
+        Ok(Fingerprint::of_sample(self.gate_count()?, &sample))
+    }
+
+    pub fn names(&mut self) -> Result<Option<NameTable>> {
+        let names: Option<NameTable> = self.read_section(NAMES)?;
+        if let Some(names) = &names {
+            let wires = names.gf2.keys().chain(names.z64.keys());
+            check_wires(NAMES, wires.map(|w| *w as u64).enumerate(), self.wire_limit)?;
+        }
+        Ok(names)
+    }
+
+    pub fn buses(&mut self) -> Result<Option<Vec<Bus>>> {
+        let buses: Option<Vec<Bus>> = self.read_section(BUSES)?;
+        let wires = buses
+            .iter()
+            .flatten()
+            .enumerate()
+            .flat_map(|(idx, bus)| bus.wires.iter().map(move |w| (idx, *w as u64)));
+        check_wires(BUSES, wires, self.wire_limit)?;
+        Ok(buses)
+    }
+
+    pub fn provenance(&mut self) -> Result<Option<Provenance>> {
+        self.read_section(PROVENANCE)
+    }
+
+    pub fn fields(&mut self) -> Result<Option<BTreeMap<Domain, Field>>> {
+        self.read_section(FIELDS)
+    }
+
+    pub fn annotations(&mut self) -> Result<Option<Annotations>> {
+        self.read_section(ANNOTATIONS)
+    }
+
+    pub fn parameters(&mut self) -> Result<Option<Parameters>> {
+        self.read_section(PARAMETERS)
+    }
+
+    /// The size hint kept outside the gate stream, as (Z64, GF2). See `Program::size_hint`.
+    pub fn size_hint(&mut self) -> Result<Option<(usize, usize)>> {
+        let hint: Option<(usize, usize)> = self.read_section(SIZE_HINT)?;
+        let counts = hint
+            .iter()
+            .flat_map(|(z64, gf2)| [(0, *z64 as u64), (0, *gf2 as u64)]);
+        check_wires(SIZE_HINT, counts, self.wire_limit)?;
+        Ok(hint)
+    }
+
+    /// Packed GF2 constants. See `Program::const_vectors`. Rejects vectors without exactly the
+    /// words their length needs, or that reach past the wire limit.
+    pub fn const_vectors(&mut self) -> Result<Option<Vec<ConstVector>>> {
+        let vectors: Option<Vec<ConstVector>> = self.read_section(CONST_VECTORS)?;
+        let last_wires = vectors.iter().flatten().enumerate().map(|(idx, vector)| {
+            let last = (vector.low as u64).saturating_add((vector.len as u64).saturating_sub(1));
+            (idx, last)
+        });
+        check_wires(CONST_VECTORS, last_wires, self.wire_limit)?;
+        for vector in vectors.iter().flatten() {
+            if vector.words.len() != vector.len.div_ceil(64) {
+                return Err(invalid(format!(
+                    "the {}-bit constant vector at wire {} has {} words",
+                    vector.len,
+                    vector.low,
+                    vector.words.len()
+                )));
+            }
+        }
+        Ok(vectors)
+    }
+
+    /// Domains stripped from the program. See `Program::stripped_domains`.
+    pub fn stripped_domains(&mut self) -> Result<Option<BTreeSet<Domain>>> {
+        self.read_section(STRIPPED_DOMAINS)
+    }
+
+    /// What generated the program's gates. See `Program::generators`.
+    pub fn generators(&mut self) -> Result<Option<Vec<Generator>>> {
+        self.read_section(GENERATORS)
+    }
+
+    /// Decodes the def-use index saved with `write_program_with_def_use`, if there is one, and
+    /// checks that it belongs to `gates` (which should be what `gates()` returned). An index for
+    /// other gates is an `InvalidData` error wrapping a `StaleIndexError`. A corrupt index, with
+    /// inconsistent lists or gates past the end of the program, is also `InvalidData`.
+    pub fn def_use(&mut self, gates: &[CombineOperation]) -> Result<Option<DefUseIndex>> {
+        let index: Option<DefUseIndex> = self.read_section(DEF_USE)?;
+        if let Some(index) = &index {
+            index
+                .check(gates)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if !index.is_well_formed() {
+                return Err(invalid("the def-use index is corrupt".into()));
+            }
+        }
+        Ok(index)
+    }
+
+    /// Decodes the gates and every metadata section present in the file.
+    pub fn read_program(&mut self) -> Result<Program> {
+        Ok(Program {
+            gates: self.gates()?,
+            names: self.names()?,
+            buses: self.buses()?,
+            provenance: self.provenance()?,
+            fields: self.fields()?,
+            annotations: self.annotations()?,
+            parameters: self.parameters()?,
+            size_hint: self.size_hint()?,
+            const_vectors: self.const_vectors()?,
+            stripped_domains: self.stripped_domains()?,
+            generators: self.generators()?,
+        })
+    }
+}
+
+/// The gate encoding of IR version 1, frozen so that it can still be read after the gates in
+/// the rest of the crate change. Variants must never be reordered, added or removed here. Wire
+/// indices are `u64` rather than `usize`, which bincode encodes the same way on 64-bit machines.
+mod ir1 {
+    use std::convert::Infallible;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    pub enum Operation<T> {
+        Input(u64),
+        Random(u64),
+        Add(u64, u64, u64),
+        AddConst(u64, u64, T),
+        Sub(u64, u64, u64),
+        SubConst(u64, u64, T),
+        Mul(u64, u64, u64),
+        MulConst(u64, u64, T),
+        AssertZero(u64),
+        Const(u64, T),
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    pub enum CombineOperation {
+        GF2(Operation<bool>),
+        Z64(Operation<u64>),
+        B2A(u64, u64),
+        SizeHint(u64, u64),
+    }
+
+    /// Converts every wire of a gate, or returns the first one that `convert` rejects.
+    macro_rules! convert_operation {
+        ($op:expr, $($from:ident)::+ => $($to:ident)::+, $convert:expr) => {{
+            use $($from)::+ as Src;
+            use $($to)::+ as Dst;
+            let w = $convert;
+            match $op {
+                Src::Input(dst) => Dst::Input(w(dst)?),
+                Src::Random(dst) => Dst::Random(w(dst)?),
+                Src::Add(dst, a, b) => Dst::Add(w(dst)?, w(a)?, w(b)?),
+                Src::AddConst(dst, src, c) => Dst::AddConst(w(dst)?, w(src)?, c),
+                Src::Sub(dst, a, b) => Dst::Sub(w(dst)?, w(a)?, w(b)?),
+                Src::SubConst(dst, src, c) => Dst::SubConst(w(dst)?, w(src)?, c),
+                Src::Mul(dst, a, b) => Dst::Mul(w(dst)?, w(a)?, w(b)?),
+                Src::MulConst(dst, src, c) => Dst::MulConst(w(dst)?, w(src)?, c),
+                Src::AssertZero(src) => Dst::AssertZero(w(src)?),
+                Src::Const(dst, c) => Dst::Const(w(dst)?, c),
+            }
+        }};
+    }
+
+    fn check(wire: u64, limit: u64) -> Result<usize, u64> {
+        if wire > limit {
+            Err(wire)
+        } else {
+            Ok(wire as usize)
+        }
+    }
+
+    impl<T: crate::WireValue> Operation<T> {
+        fn narrow(self, limit: u64) -> Result<crate::Operation<T>, u64> {
+            let narrow = |wire| check(wire, limit);
+            Ok(convert_operation!(self, Operation => crate::Operation, narrow))
+        }
+    }
+
+    fn widen<T: crate::WireValue>(op: crate::Operation<T>) -> Result<Operation<T>, Infallible> {
+        let widen = |wire: usize| Ok::<_, Infallible>(wire as u64);
+        Ok(convert_operation!(op, crate::Operation => Operation, widen))
+    }
+
+    impl<T: crate::WireValue> From<crate::Operation<T>> for Operation<T> {
+        fn from(op: crate::Operation<T>) -> Self {
+            match widen(op) {
+                Ok(op) => op,
+                Err(never) => match never {},
+            }
+        }
+    }
+
+    impl CombineOperation {
+        /// The gate with `usize` wires, or the first wire (or size hint) larger than `limit`,
+        /// which must be at most the largest `usize`. A B2A's whole window of 64 wires has to be
+        /// within the limit.
+        pub fn narrow(self, limit: u64) -> Result<crate::CombineOperation, u64> {
+            let narrow = |wire| check(wire, limit);
+            Ok(match self {
+                CombineOperation::GF2(op) => crate::CombineOperation::GF2(op.narrow(limit)?),
+                CombineOperation::Z64(op) => crate::CombineOperation::Z64(op.narrow(limit)?),
+                CombineOperation::B2A(dst, low) => {
+                    let gate = crate::CombineOperation::B2A(narrow(dst)?, narrow(low)?);
+                    narrow(low.saturating_add(63))?;
+                    gate
+                }
+                CombineOperation::SizeHint(z64, gf2) => {
+                    crate::CombineOperation::SizeHint(narrow(z64)?, narrow(gf2)?)
+                }
+            })
+        }
+    }
+
+    impl From<crate::CombineOperation> for CombineOperation {
+        fn from(gate: crate::CombineOperation) -> Self {
+            match gate {
+                crate::CombineOperation::GF2(op) => CombineOperation::GF2(op.into()),
+                crate::CombineOperation::Z64(op) => CombineOperation::Z64(op.into()),
+                crate::CombineOperation::B2A(dst, low) => {
+                    CombineOperation::B2A(dst as u64, low as u64)
+                }
+                crate::CombineOperation::SizeHint(z64, gf2) => {
+                    CombineOperation::SizeHint(z64 as u64, gf2 as u64)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind};
+
+    use crate::parsers::load_circuit;
+    use crate::program::{Bus, ConstVector, Generator, NameTable, Program, Provenance};
+    use crate::serialize::{
+        save_circuit, write_program, write_program_version, write_program_with_def_use,
+        ProgramReader, WireIndexError, GATE_INDEX_STRIDE, IR_VERSION,
+    };
+    use crate::{CombineOperation, Domain, Field, Operation};
+    use crate::{DefUseIndex, Fingerprint, StaleIndexError};
+
+    fn gates() -> Vec<CombineOperation> {
+        vec![
+            CombineOperation::SizeHint(2, 2),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(1, 0, true)),
+            CombineOperation::Z64(Operation::Const(0, 42)),
+            CombineOperation::Z64(Operation::MulConst(1, 0, 3)),
+            CombineOperation::GF2(Operation::AssertZero(1)),
+        ]
+    }
+
+    fn round_trip(program: &Program) -> ProgramReader<Cursor<Vec<u8>>> {
+        let mut sink = Vec::new();
+        write_program(program, &mut sink).unwrap();
+        ProgramReader::new(Cursor::new(sink)).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_gates_only() {
+        let program: Program = gates().into();
+        let mut reader = round_trip(&program);
+
+        assert_eq!(
+            reader.section_names().collect::<Vec<_>>(),
+            vec!["gates", "checksums"]
+        );
+        assert_eq!(reader.names().unwrap(), None);
+        assert_eq!(reader.read_program().unwrap(), program);
+    }
+
+    #[test]
+    fn test_round_trip_with_metadata() {
+        let mut names = NameTable::default();
+        names.insert(Domain::GF2, 0, "top::in[0]".into());
+        names.insert(Domain::Z64, 1, "top::product".into());
+
+        let program = Program {
+            gates: gates(),
+            names: Some(names.clone()),
+            buses: Some(vec![Bus {
+                name: "top::in".into(),
+                domain: Domain::GF2,
+                wires: vec![0, 1],
+            }]),
+            provenance: Some(Provenance {
+                tool: "mcircuit tests".into(),
+                sources: vec!["top.blif".into()],
+            }),
+            fields: Some(vec![(Domain::Z64, Field::prime(101))].into_iter().collect()),
+            annotations: Some(vec![(4, "scale by 3".to_string())].into_iter().collect()),
+            parameters: Some(vec![(3, "seed".to_string())].into_iter().collect()),
+            size_hint: Some((4, 8)),
+            const_vectors: Some(vec![ConstVector::from_hex(8, "6a09e667f3bcc908").unwrap()]),
+            stripped_domains: None,
+            generators: Some(vec![Generator::mcircuit().with_feature("wide-adders")]),
+        };
+
+        let mut reader = round_trip(&program);
+        assert!(reader.has_section("provenance"));
+        assert!(reader.has_section("fields"));
+
+        // Sections can be read independently, in any order
+        assert_eq!(reader.names().unwrap(), Some(names));
+        assert_eq!(reader.gates().unwrap(), program.gates);
+        assert_eq!(reader.read_program().unwrap(), program);
+
+        // Vectors without the words their length needs would panic when lowered
+        let mut short = program.clone();
+        short.const_vectors.as_mut().unwrap()[0].words.pop();
+        let err = round_trip(&short).read_program().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("64-bit constant vector at wire 8 has 0 words"));
+    }
+
+    #[test]
+    fn test_def_use_section() {
+        let program: Program = gates().into();
+        let index = DefUseIndex::build(&program.gates);
+        let mut sink = Vec::new();
+        write_program_with_def_use(&program, &index, &mut sink).unwrap();
+
+        let mut reader = ProgramReader::new(Cursor::new(sink)).unwrap();
+        let gates = reader.gates().unwrap();
+        let loaded = reader.def_use(&gates).unwrap().unwrap();
+        assert_eq!(loaded, index);
+        assert_eq!(loaded.uses(Domain::GF2, 1), [5]);
+        // Loaded with the wrong program, the index is rejected
+        let err = reader.def_use(&gates[1..]).unwrap_err();
+        assert!(err.into_inner().unwrap().is::<StaleIndexError>());
+        assert!(write_program_with_def_use(
+            &program,
+            &DefUseIndex::build(&gates[1..]),
+            &mut Vec::new()
+        )
+        .is_err());
+
+        // Files without an index just don't have one
+        assert_eq!(round_trip(&program).def_use(&gates).unwrap(), None);
+    }
+
+    #[test]
+    fn test_save_circuit() {
+        let path = std::env::temp_dir().join(format!("mcircuit_{}_saved", std::process::id()));
+        save_circuit(&path, &gates()).unwrap();
+        assert_eq!(load_circuit(&path).unwrap(), Program::from(gates()));
+
+        // Flip a bit in the last gate, which is still a valid gate
+        let mut bytes = std::fs::read(&path).unwrap();
+        let mut reader = ProgramReader::new(Cursor::new(bytes.clone())).unwrap();
+        assert!(reader.verify().is_ok());
+        let (offset, len) = reader.locate("gates").unwrap();
+        bytes[(offset + len) as usize - 1] ^= 1;
+        let mut reader = ProgramReader::new(Cursor::new(bytes.clone())).unwrap();
+        let err = reader.gates().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("gates section doesn't match its checksum"));
+        assert!(reader.verify().is_err());
+        // Other sections still read
+        assert_eq!(reader.names().unwrap(), None);
+
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(load_circuit(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_checksums_required() {
+        let mut legacy = Vec::new();
+        write_program_version(&gates().into(), 2, &mut legacy).unwrap();
+        let mut reader = ProgramReader::new(Cursor::new(legacy.clone())).unwrap();
+        assert!(!reader.has_section("checksums"));
+        assert_eq!(reader.gates().unwrap(), gates());
+
+        // Format 3 files can't leave the checksums out, or leave a section unchecked
+        let mut unchecked = legacy;
+        unchecked[4..8].copy_from_slice(&3u32.to_le_bytes());
+        let err = ProgramReader::new(Cursor::new(unchecked)).err().unwrap();
+        assert!(err.to_string().contains("checksums section is missing"));
+        let mut renamed = Vec::new();
+        write_program(&gates().into(), &mut renamed).unwrap();
+        // The last letter of the first section's name, after the table's length, its entry count
+        // and the name's length
+        renamed[20 + 8 + 8 + 4] = b'z';
+        let err = ProgramReader::new(Cursor::new(renamed)).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("the gatez section has no checksum"));
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(ProgramReader::new(Cursor::new(b"BLIF and other things".to_vec())).is_err());
+
+        // Lengths larger than the file are rejected before anything that size is allocated
+        let mut file = Vec::new();
+        write_program(&gates().into(), &mut file).unwrap();
+        let mut huge_table = file[..12].to_vec();
+        huge_table.extend_from_slice(&u64::MAX.to_le_bytes());
+        let err = ProgramReader::new(Cursor::new(huge_table)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("section table should be"));
+
+        // The length of the gates section: the table's length, its entry count, then "gates"
+        // (with its length) and its offset
+        let at = 20 + 8 + 8 + 5 + 8;
+        file[at..at + 8].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        let mut reader = ProgramReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.gates().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(reader.verify().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_reads_format_1() {
+        let mut program: Program = gates().into();
+        program.annotate(2, "flip");
+
+        let mut old = Vec::new();
+        write_program_version(&program, 1, &mut old).unwrap();
+        assert_eq!(&old[4..8], &1u32.to_le_bytes());
+
+        let mut reader = ProgramReader::new(Cursor::new(old)).unwrap();
+        assert_eq!(reader.format_version(), 1);
+        assert_eq!(reader.ir_version(), 1);
+        assert_eq!(reader.read_program().unwrap(), program);
+
+        let reader = round_trip(&program);
+        assert_eq!(reader.format_version(), 3);
+        assert_eq!(reader.ir_version(), IR_VERSION);
+
+        assert!(write_program_version(&program, 7, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_newer_ir() {
+        let mut file = Vec::new();
+        write_program(&gates().into(), &mut file).unwrap();
+        file[8..12].copy_from_slice(&(IR_VERSION + 1).to_le_bytes());
+        let err = ProgramReader::new(Cursor::new(file)).err().unwrap();
+        assert!(err.to_string().contains("unsupported IR version"));
+    }
+
+    #[test]
+    fn test_indexed_sampling() {
+        let large: Vec<CombineOperation> = (0..3 * GATE_INDEX_STRIDE + 17)
+            .map(|i| match i % 3 {
+                0 => CombineOperation::GF2(Operation::Const(i, i % 2 == 0)),
+                1 => CombineOperation::Z64(Operation::MulConst(i, i - 1, i as u64)),
+                _ => CombineOperation::B2A(i, i - 2),
+            })
+            .collect();
+        let program: Program = large.clone().into();
+
+        for version in [1, 2, 3] {
+            let mut file = Vec::new();
+            write_program_version(&program, version, &mut file).unwrap();
+            let mut reader = ProgramReader::new(Cursor::new(file)).unwrap();
+            assert!(reader.has_section("gate-index"));
+            assert_eq!(reader.gate_count().unwrap(), large.len());
+
+            let sample = reader.sample_gates(200, 3).unwrap();
+            assert_eq!(sample.len(), 200);
+            for (idx, gate) in &sample {
+                assert_eq!(large[*idx], *gate);
+            }
+            assert_eq!(
+                reader.fingerprint(200, 3).unwrap(),
+                Fingerprint::of(&large, 200, 3)
+            );
+        }
+
+        // Small files don't get an index, but can still be sampled
+        let mut reader = round_trip(&gates().into());
+        assert!(!reader.has_section("gate-index"));
+        assert_eq!(
+            reader.fingerprint(3, 1).unwrap(),
+            Fingerprint::of(&gates(), 3, 1)
+        );
+    }
+
+    #[test]
+    fn test_wire_limit() {
+        let mut program: Program = gates().into();
+        program
+            .gates
+            .push(CombineOperation::B2A(0, u32::MAX as usize + 1));
+        let mut file = Vec::new();
+        write_program(&program, &mut file).unwrap();
+
+        let mut reader = ProgramReader::new(Cursor::new(file.clone())).unwrap();
+        assert_eq!(reader.gates().unwrap(), program.gates);
+
+        // As it would go on a 32-bit machine
+        let mut reader = ProgramReader::new(Cursor::new(file))
+            .unwrap()
+            .with_wire_limit(u32::MAX as u64);
+        let err = reader.gates().unwrap_err();
+        assert_eq!(
+            err.get_ref()
+                .and_then(|e| e.downcast_ref::<WireIndexError>()),
+            Some(&WireIndexError {
+                section: "gates",
+                entry: 6,
+                index: u32::MAX as u64 + 1,
+                limit: u32::MAX as u64,
+            })
+        );
+        assert!(err
+            .to_string()
+            .contains("gate 6 uses wire index 4294967296"));
+        assert!(reader.sample_gates(3, 0).is_err());
+
+        // Every wire of a B2A's window, and of the other sections, has to be in range too
+        let limit = u32::MAX as usize;
+        let limited = |program: &Program| {
+            let mut file = Vec::new();
+            write_program(program, &mut file).unwrap();
+            let mut reader = ProgramReader::new(Cursor::new(file))
+                .unwrap()
+                .with_wire_limit(limit as u64);
+            let err = reader.read_program().unwrap_err();
+            err.get_ref()
+                .and_then(|e| e.downcast_ref::<WireIndexError>())
+                .map(|e| (e.section, e.entry, e.index))
+        };
+        let mut program: Program = gates().into();
+        program.gates.push(CombineOperation::B2A(0, limit - 10));
+        assert_eq!(limited(&program), Some(("gates", 6, limit as u64 + 53)));
+
+        let mut program: Program = gates().into();
+        program.size_hint = Some((1, limit + 1));
+        assert_eq!(limited(&program), Some(("size-hint", 0, limit as u64 + 1)));
+
+        let mut program: Program = gates().into();
+        program.const_vectors = Some(vec![ConstVector::from_bits(limit - 1, &[true; 3])]);
+        assert_eq!(
+            limited(&program),
+            Some(("const-vectors", 0, limit as u64 + 1))
+        );
+
+        let mut program: Program = gates().into();
+        program.buses = Some(vec![Bus {
+            name: "far".into(),
+            domain: Domain::GF2,
+            wires: vec![0, limit + 1],
+        }]);
+        assert_eq!(limited(&program), Some(("buses", 0, limit as u64 + 1)));
+
+        let mut program: Program = gates().into();
+        let mut names = NameTable::default();
+        names.z64.insert(limit + 1, "far".into());
+        program.names = Some(names);
+        assert_eq!(limited(&program), Some(("names", 0, limit as u64 + 1)));
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use crate::analysis::BackwardAnalysisPass;
+use crate::{CombineOperation, Domain, HasIO, Operation};
+
+/// The backward cone of influence of a gate or wire, extracted as a standalone program.
+#[derive(Clone, Debug, Default)]
+pub struct Slice {
+    /// The extracted gates, in their original order and wire numbering. Cut points are prepended
+    /// as `Input` gates.
+    pub program: Vec<CombineOperation>,
+    /// Indices (in the original program) of the gates that were kept
+    pub gates: Vec<usize>,
+    /// For each input the slice consumes from the boolean witness, the original wire it stands in
+    /// for. Evaluate the original program and read these wires to build a witness for the slice.
+    pub gf2_inputs: Vec<usize>,
+    /// Same as `gf2_inputs`, but for the arithmetic witness.
+    pub z64_inputs: Vec<usize>,
+}
+
+/// Extracts the gates that `program[gate]` (typically a failing `AssertZero`) depends on. Wires
+/// in `cuts` are not chased any further; they become fresh inputs to the slice instead.
+pub fn slice_gate(
+    program: &[CombineOperation],
+    gate: usize,
+    cuts: &HashSet<(Domain, usize)>,
+) -> Slice {
+    let target = &program[gate];
+    let needed = match target.input_domain() {
+        Some(domain) => target.inputs().map(|w| (domain, w)).collect(),
+        None => HashSet::new(),
+    };
+
+    let mut slice = cone(&program[..gate], needed, cuts);
+    slice.program.push(*target);
+    slice.gates.push(gate);
+    slice
+}
+
+/// Extracts the gates that determine the final value of `wire`.
+pub fn slice_wire(
+    program: &[CombineOperation],
+    domain: Domain,
+    wire: usize,
+    cuts: &HashSet<(Domain, usize)>,
+) -> Slice {
+    cone(program, std::iter::once((domain, wire)).collect(), cuts)
+}
+
+/// Backward pass that collects the gates writing any wire in `needed`, chasing their inputs in
+/// turn.
+struct Cone<'c> {
+    needed: HashSet<(Domain, usize)>,
+    cuts: &'c HashSet<(Domain, usize)>,
+    cut_wires: Vec<(Domain, usize)>,
+    kept: Vec<usize>,
+}
+
+impl<'c> Cone<'c> {
+    fn new(needed: HashSet<(Domain, usize)>, cuts: &'c HashSet<(Domain, usize)>) -> Self {
+        let mut cone = Cone {
+            needed: HashSet::new(),
+            cuts,
+            cut_wires: Vec::new(),
+            kept: Vec::new(),
+        };
+        for wire in needed {
+            cone.chase(wire);
+        }
+        cone
+    }
+
+    /// Cut wires become inputs to the slice rather than being chased any further
+    fn chase(&mut self, wire: (Domain, usize)) {
+        if self.cuts.contains(&wire) {
+            if !self.cut_wires.contains(&wire) {
+                self.cut_wires.push(wire);
+            }
+        } else {
+            self.needed.insert(wire);
+        }
+    }
+}
+
+impl BackwardAnalysisPass for Cone<'_> {
+    /// (cut wires, sorted; indices of the kept gates, in order)
+    type Output = (Vec<(Domain, usize)>, Vec<usize>);
+
+    fn analyze_gate(&mut self, index: usize, gate: &CombineOperation) {
+        let writes_needed = match gate.output_domain() {
+            // Only the last write before the use matters, so stop looking for this wire once we
+            // find it. Gates have at most one output, so short-circuiting is fine.
+            Some(domain) => gate.outputs().any(|w| self.needed.remove(&(domain, w))),
+            None => false,
+        };
+
+        if writes_needed {
+            self.kept.push(index);
+            if let Some(domain) = gate.input_domain() {
+                for w in gate.inputs() {
+                    self.chase((domain, w));
+                }
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.needed.is_empty()
+    }
+
+    fn finish_analysis(mut self) -> Self::Output {
+        self.kept.reverse();
+        self.cut_wires.sort();
+        (self.cut_wires, self.kept)
+    }
+}
+
+/// Walks backwards from the end of `program`, keeping any gate that writes a wire in `needed`.
+fn cone(
+    program: &[CombineOperation],
+    needed: HashSet<(Domain, usize)>,
+    cuts: &HashSet<(Domain, usize)>,
+) -> Slice {
+    let (cut_wires, kept) = Cone::new(needed, cuts).run(program.iter());
+
+    let mut slice = Slice::default();
+    for (domain, wire) in cut_wires {
+        match domain {
+            Domain::GF2 => {
+                slice
+                    .program
+                    .push(CombineOperation::GF2(Operation::Input(wire)));
+                slice.gf2_inputs.push(wire);
+            }
+            Domain::Z64 => {
+                slice
+                    .program
+                    .push(CombineOperation::Z64(Operation::Input(wire)));
+                slice.z64_inputs.push(wire);
+            }
+        }
+    }
+
+    for idx in kept {
+        let gate = program[idx];
+        match gate {
+            CombineOperation::GF2(Operation::Input(w)) => slice.gf2_inputs.push(w),
+            CombineOperation::Z64(Operation::Input(w)) => slice.z64_inputs.push(w),
+            _ => {}
+        }
+        slice.program.push(gate);
+        slice.gates.push(idx);
+    }
+
+    slice
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::slice::{slice_gate, slice_wire};
+    use crate::{CombineOperation, Domain, Operation};
+
+    fn program() -> Vec<CombineOperation> {
+        vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Input(2)),
+            CombineOperation::GF2(Operation::Mul(3, 0, 1)),
+            CombineOperation::GF2(Operation::Add(4, 2, 2)),
+            CombineOperation::GF2(Operation::AddConst(5, 3, true)),
+            CombineOperation::Z64(Operation::Const(5, 9)),
+            CombineOperation::GF2(Operation::AssertZero(4)),
+            CombineOperation::GF2(Operation::AssertZero(5)),
+        ]
+    }
+
+    #[test]
+    fn test_slice_assertion() {
+        let program = program();
+        let slice = slice_gate(&program, 8, &HashSet::new());
+
+        assert_eq!(slice.gates, vec![0, 1, 3, 5, 8]);
+        assert_eq!(slice.gf2_inputs, vec![0, 1]);
+        assert!(slice.z64_inputs.is_empty());
+        assert_eq!(
+            slice.program,
+            slice.gates.iter().map(|i| program[*i]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_slice_with_cuts() {
+        let program = program();
+        let cuts: HashSet<(Domain, usize)> = std::iter::once((Domain::GF2, 3)).collect();
+        let slice = slice_gate(&program, 8, &cuts);
+
+        assert_eq!(slice.gates, vec![5, 8]);
+        assert_eq!(slice.gf2_inputs, vec![3]);
+        assert_eq!(slice.program[0], CombineOperation::GF2(Operation::Input(3)));
+    }
+
+    #[test]
+    fn test_slice_wire() {
+        let program = program();
+        let slice = slice_wire(&program, Domain::Z64, 5, &HashSet::new());
+        assert_eq!(slice.gates, vec![6]);
+
+        let slice = slice_wire(&program, Domain::GF2, 4, &HashSet::new());
+        assert_eq!(slice.gates, vec![2, 4]);
+        assert_eq!(slice.gf2_inputs, vec![2]);
+    }
+}
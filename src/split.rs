@@ -0,0 +1,187 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{CombineOperation, HasIO, Operation, Translatable, WireValue};
+
+/// A point where a value crosses from the GF2 subprogram into the Z64 subprogram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Conversion {
+    /// Index of the B2A gate in the original program
+    pub gate: usize,
+    /// Number of gates in `DomainSplit::gf2` that precede the conversion
+    pub gf2_position: usize,
+    /// Number of gates in `DomainSplit::z64` that precede the conversion
+    pub z64_position: usize,
+    /// Destination wire, numbered according to `DomainSplit::z64_wires`
+    pub dst: usize,
+    /// Low bit of the 64-bit source window, numbered according to `DomainSplit::gf2_wires`
+    pub low: usize,
+}
+
+/// A `CombineOperation` program broken up into one subprogram per domain. Both halves are
+/// renumbered into compact wire ranges; the wire maps go from the original program's wire indices
+/// to the new ones. Renumbering preserves wire order, so the 64-bit windows read by conversions
+/// remain contiguous.
+#[derive(Clone, Debug, Default)]
+pub struct DomainSplit {
+    pub gf2: Vec<Operation<bool>>,
+    pub z64: Vec<Operation<u64>>,
+    pub conversions: Vec<Conversion>,
+    pub gf2_wires: HashMap<usize, usize>,
+    pub z64_wires: HashMap<usize, usize>,
+}
+
+/// Assigns each wire its rank among all the wires used in a domain.
+pub(crate) fn compact(wires: BTreeSet<usize>) -> HashMap<usize, usize> {
+    wires
+        .into_iter()
+        .enumerate()
+        .map(|(new, old)| (old, new))
+        .collect()
+}
+
+pub(crate) fn renumber<T: WireValue>(
+    op: &Operation<T>,
+    map: &HashMap<usize, usize>,
+) -> Operation<T> {
+    op.translate(op.inputs().map(|w| map[&w]), op.outputs().map(|w| map[&w]))
+        .expect("Operations are always translatable")
+}
+
+/// Splits a program into its GF2 and Z64 halves, plus the list of conversions between them. Size
+/// hints are dropped, since they don't apply to the renumbered halves.
+pub fn split_by_domain(program: &[CombineOperation]) -> DomainSplit {
+    let mut gf2_used = BTreeSet::new();
+    let mut z64_used = BTreeSet::new();
+
+    for gate in program {
+        match gate {
+            CombineOperation::GF2(op) => gf2_used.extend(op.inputs().chain(op.outputs())),
+            CombineOperation::Z64(op) => z64_used.extend(op.inputs().chain(op.outputs())),
+            CombineOperation::B2A(dst, _) => {
+                z64_used.insert(*dst);
+                gf2_used.extend(gate.inputs());
+            }
+            CombineOperation::SizeHint(_, _) => {}
+        }
+    }
+
+    let mut split = DomainSplit {
+        gf2_wires: compact(gf2_used),
+        z64_wires: compact(z64_used),
+        ..Default::default()
+    };
+
+    for (idx, gate) in program.iter().enumerate() {
+        match gate {
+            CombineOperation::GF2(op) => split.gf2.push(renumber(op, &split.gf2_wires)),
+            CombineOperation::Z64(op) => split.z64.push(renumber(op, &split.z64_wires)),
+            CombineOperation::B2A(dst, low) => split.conversions.push(Conversion {
+                gate: idx,
+                gf2_position: split.gf2.len(),
+                z64_position: split.z64.len(),
+                dst: split.z64_wires[dst],
+                low: split.gf2_wires[low],
+            }),
+            CombineOperation::SizeHint(_, _) => {}
+        }
+    }
+
+    split
+}
+
+impl DomainSplit {
+    /// Reassembles the two halves into a single program, using the compacted wire numbering. Every
+    /// conversion is placed after all the gates that preceded it in both domains, so the result
+    /// evaluates identically to the original program.
+    pub fn merge(&self) -> Vec<CombineOperation> {
+        let mut merged = Vec::with_capacity(self.gf2.len() + self.z64.len() + 1);
+        merged.push(CombineOperation::SizeHint(
+            self.z64_wires.len(),
+            self.gf2_wires.len(),
+        ));
+
+        let mut gf2 = self.gf2.iter().copied();
+        let mut z64 = self.z64.iter().copied();
+        let (mut gf2_done, mut z64_done) = (0, 0);
+
+        for conversion in &self.conversions {
+            merged.extend(
+                gf2.by_ref()
+                    .take(conversion.gf2_position - gf2_done)
+                    .map(CombineOperation::GF2),
+            );
+            merged.extend(
+                z64.by_ref()
+                    .take(conversion.z64_position - z64_done)
+                    .map(CombineOperation::Z64),
+            );
+            gf2_done = conversion.gf2_position;
+            z64_done = conversion.z64_position;
+
+            merged.push(CombineOperation::B2A(conversion.dst, conversion.low));
+        }
+
+        merged.extend(gf2.map(CombineOperation::GF2));
+        merged.extend(z64.map(CombineOperation::Z64));
+
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::split::split_by_domain;
+    use crate::{evaluate_composite_program, CombineOperation, Operation};
+
+    fn mixed_program() -> Vec<CombineOperation> {
+        let mut program = vec![CombineOperation::SizeHint(300, 600)];
+        for i in 0..64 {
+            program.push(CombineOperation::GF2(Operation::Input(500 + i)));
+        }
+        program.push(CombineOperation::Z64(Operation::Input(200)));
+        program.push(CombineOperation::B2A(100, 500));
+        program.push(CombineOperation::Z64(Operation::Sub(250, 100, 200)));
+        program.push(CombineOperation::Z64(Operation::AssertZero(250)));
+        program.push(CombineOperation::GF2(Operation::Add(10, 500, 501)));
+        program.push(CombineOperation::GF2(Operation::AssertZero(10)));
+        program
+    }
+
+    #[test]
+    fn test_split() {
+        let program = mixed_program();
+        let split = split_by_domain(&program);
+
+        assert_eq!(split.gf2.len(), 66);
+        assert_eq!(split.z64.len(), 3);
+        assert_eq!(split.conversions.len(), 1);
+
+        let conversion = split.conversions[0];
+        assert_eq!(conversion.gate, 66);
+        assert_eq!(conversion.gf2_position, 64);
+        assert_eq!(conversion.z64_position, 1);
+        assert_eq!(conversion.dst, split.z64_wires[&100]);
+        assert_eq!(conversion.low, split.gf2_wires[&500]);
+
+        // The conversion window must stay contiguous after renumbering
+        for i in 0..64 {
+            assert_eq!(split.gf2_wires[&(500 + i)], conversion.low + i);
+        }
+        assert_eq!(split.gf2_wires.len(), 65);
+        assert_eq!(split.z64_wires.len(), 3);
+    }
+
+    #[test]
+    fn test_merge() {
+        let program = mixed_program();
+        let merged = split_by_domain(&program).merge();
+
+        assert_eq!(merged.len(), program.len());
+        assert_eq!(merged[0], CombineOperation::SizeHint(3, 65));
+
+        let value: u64 = 0xdead_beef_0000_0003;
+        let bits: Vec<bool> = (0..64).map(|i| (value >> i) & 1 == 1).collect();
+        evaluate_composite_program(&program, &bits, &[value]);
+        evaluate_composite_program(&merged, &bits, &[value]);
+    }
+}
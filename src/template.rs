@@ -0,0 +1,416 @@
+//! Stored subcircuits that can be stamped out many times, each copy wired up to different parts of
+//! a larger program.
+//!
+//! A template is a program plus its formal inputs and outputs: named groups of wires that a caller
+//! binds to wires of their own when instantiating it. Every other wire in the template is
+//! internal, and gets moved to fresh wires allocated from a `GateSink`, so copies never collide
+//! with each other or with the program they're spliced into.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::analysis::{AnalysisPass, WireCounter};
+use crate::gadgets::GateSink;
+use crate::{Bus, CombineOperation, Domain, HasIO, Program, Translatable};
+
+/// Why a template couldn't be built or instantiated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    /// Two formal ports share a name
+    DuplicatePort(String),
+    /// `Template::from_program` was asked for a bus the program doesn't have
+    NoSuchBus(String),
+    /// The body writes to a wire of a formal input
+    InputWritten(String),
+    /// The body never writes to some wire of a formal output
+    OutputUnwritten(String),
+    /// No wires were bound to the port
+    Unbound(String),
+    /// Wires were bound to a port the template doesn't have
+    UnknownPort(String),
+    /// The wires bound to a port don't match its width
+    Width {
+        port: String,
+        expected: usize,
+        found: usize,
+    },
+    /// The bindings would scatter the 64-bit window of the B2A gate at this index in the body
+    SplitWindow { gate: usize },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::DuplicatePort(name) => write!(f, "more than one port named {}", name),
+            TemplateError::NoSuchBus(name) => write!(f, "program has no bus named {}", name),
+            TemplateError::InputWritten(name) => write!(f, "input {} is written by the body", name),
+            TemplateError::OutputUnwritten(name) => {
+                write!(f, "output {} isn't fully written by the body", name)
+            }
+            TemplateError::Unbound(name) => write!(f, "no wires bound to port {}", name),
+            TemplateError::UnknownPort(name) => write!(f, "template has no port {}", name),
+            TemplateError::Width {
+                port,
+                expected,
+                found,
+            } => write!(
+                f,
+                "port {} is {} wires wide, but {} were bound to it",
+                port, expected, found
+            ),
+            TemplateError::SplitWindow { gate } => write!(
+                f,
+                "bindings split the B2A window of gate {} across non-contiguous wires",
+                gate
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// A subcircuit with named formal inputs and outputs.
+///
+/// ```
+/// use std::collections::BTreeMap;
+///
+/// use mcircuit::{Bus, CombineOperation, Domain, Operation, ProgramEditor, Template};
+///
+/// // out = a * b + 1, computed through an internal wire
+/// let body = vec![
+///     CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+///     CombineOperation::GF2(Operation::AddConst(3, 2, true)),
+/// ];
+/// let port = |name: &str, wires: Vec<usize>| Bus {
+///     name: name.into(),
+///     domain: Domain::GF2,
+///     wires,
+/// };
+/// let template = Template::new(
+///     &body,
+///     vec![port("a", vec![0]), port("b", vec![1])],
+///     vec![port("out", vec![3])],
+/// )
+/// .unwrap();
+///
+/// let mut editor = ProgramEditor::new(vec![
+///     CombineOperation::GF2(Operation::Input(0)),
+///     CombineOperation::GF2(Operation::Input(1)),
+/// ]);
+/// let bindings: BTreeMap<String, Vec<usize>> = vec![
+///     ("a".to_string(), vec![0]),
+///     ("b".to_string(), vec![1]),
+///     ("out".to_string(), vec![7]),
+/// ]
+/// .into_iter()
+/// .collect();
+/// let gates = template.instantiate(&bindings, &mut editor).unwrap();
+/// assert_eq!(gates[0], CombineOperation::GF2(Operation::Mul(4, 0, 1)));
+/// assert_eq!(gates[1], CombineOperation::GF2(Operation::AddConst(7, 4, true)));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    gates: Vec<CombineOperation>,
+    inputs: Vec<Bus>,
+    outputs: Vec<Bus>,
+    /// Wires the body spans in each domain, as (Z64, GF2)
+    span: (usize, usize),
+}
+
+/// The wires written by `gates`, in each domain
+fn written(gates: &[CombineOperation]) -> HashSet<(Domain, usize)> {
+    gates
+        .iter()
+        .filter_map(|gate| Some((gate.output_domain()?, gate.dst()?)))
+        .collect()
+}
+
+impl Template {
+    /// Makes a template of `gates`, with the given formal ports. Size hints in `gates` are
+    /// dropped, since an instance's wires are counted by the program it's spliced into.
+    pub fn new(
+        gates: &[CombineOperation],
+        inputs: Vec<Bus>,
+        outputs: Vec<Bus>,
+    ) -> Result<Self, TemplateError> {
+        let mut names = HashSet::new();
+        if let Some(port) = inputs
+            .iter()
+            .chain(outputs.iter())
+            .find(|port| !names.insert(port.name.as_str()))
+        {
+            return Err(TemplateError::DuplicatePort(port.name.clone()));
+        }
+
+        let gates: Vec<CombineOperation> = gates
+            .iter()
+            .filter(|gate| !matches!(gate, CombineOperation::SizeHint(_, _)))
+            .copied()
+            .collect();
+        let written = written(&gates);
+        let writes = |port: &Bus| {
+            port.wires
+                .iter()
+                .any(|w| written.contains(&(port.domain, *w)))
+        };
+        if let Some(port) = inputs.iter().find(|port| writes(port)) {
+            return Err(TemplateError::InputWritten(port.name.clone()));
+        }
+        if let Some(port) = outputs.iter().find(|port| {
+            !port
+                .wires
+                .iter()
+                .all(|w| written.contains(&(port.domain, *w)))
+        }) {
+            return Err(TemplateError::OutputUnwritten(port.name.clone()));
+        }
+
+        let span = WireCounter::analyze(gates.iter()).0;
+        Ok(Template {
+            gates,
+            inputs,
+            outputs,
+            span,
+        })
+    }
+
+    /// Makes a template of a program, using its buses with the given names as the formal ports.
+    pub fn from_program(
+        program: &Program,
+        inputs: &[&str],
+        outputs: &[&str],
+    ) -> Result<Self, TemplateError> {
+        let buses = program.buses.as_deref().unwrap_or_default();
+        let find = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| {
+                    buses
+                        .iter()
+                        .find(|bus| bus.name == *name)
+                        .cloned()
+                        .ok_or_else(|| TemplateError::NoSuchBus(name.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Template::new(&program.gates, find(inputs)?, find(outputs)?)
+    }
+
+    pub fn gates(&self) -> &[CombineOperation] {
+        &self.gates
+    }
+
+    pub fn inputs(&self) -> &[Bus] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[Bus] {
+        &self.outputs
+    }
+
+    /// A copy of the body with each port's wires replaced by the ones bound to it in `bindings`,
+    /// and its internal wires moved to fresh wires allocated from `wires`, ready to splice into
+    /// the program `wires` allocates for. Every port needs binding.
+    ///
+    /// Internal wires keep their positions relative to each other, so the allocation is as wide
+    /// as the body's wire span in each domain.
+    pub fn instantiate(
+        &self,
+        bindings: &BTreeMap<String, Vec<usize>>,
+        wires: &mut impl GateSink,
+    ) -> Result<Vec<CombineOperation>, TemplateError> {
+        let ports: HashMap<&str, &Bus> = self
+            .inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .map(|port| (port.name.as_str(), port))
+            .collect();
+        if let Some(name) = bindings
+            .keys()
+            .find(|name| !ports.contains_key(name.as_str()))
+        {
+            return Err(TemplateError::UnknownPort(name.clone()));
+        }
+
+        let mut bound: HashMap<(Domain, usize), usize> = HashMap::new();
+        for port in self.inputs.iter().chain(self.outputs.iter()) {
+            let actual = bindings
+                .get(&port.name)
+                .ok_or_else(|| TemplateError::Unbound(port.name.clone()))?;
+            if actual.len() != port.wires.len() {
+                return Err(TemplateError::Width {
+                    port: port.name.clone(),
+                    expected: port.wires.len(),
+                    found: actual.len(),
+                });
+            }
+            for (formal, actual) in port.wires.iter().zip(actual) {
+                bound.insert((port.domain, *formal), *actual);
+            }
+        }
+
+        let (z64_span, gf2_span) = self.span;
+        let z64_base = wires.fresh_wires(Domain::Z64, z64_span);
+        let gf2_base = wires.fresh_wires(Domain::GF2, gf2_span);
+        let map = |domain: Domain, wire: usize| match bound.get(&(domain, wire)) {
+            Some(actual) => *actual,
+            None => match domain {
+                Domain::Z64 => z64_base + wire,
+                Domain::GF2 => gf2_base + wire,
+            },
+        };
+
+        self.gates
+            .iter()
+            .enumerate()
+            .map(|(idx, gate)| match gate {
+                CombineOperation::GF2(op) => Ok(CombineOperation::GF2(
+                    op.translate(
+                        op.inputs().map(|w| map(Domain::GF2, w)),
+                        op.outputs().map(|w| map(Domain::GF2, w)),
+                    )
+                    .expect("Operations are always translatable"),
+                )),
+                CombineOperation::Z64(op) => Ok(CombineOperation::Z64(
+                    op.translate(
+                        op.inputs().map(|w| map(Domain::Z64, w)),
+                        op.outputs().map(|w| map(Domain::Z64, w)),
+                    )
+                    .expect("Operations are always translatable"),
+                )),
+                CombineOperation::B2A(dst, low) => {
+                    let new_low = map(Domain::GF2, *low);
+                    if (1..64).any(|bit| map(Domain::GF2, low + bit) != new_low + bit) {
+                        return Err(TemplateError::SplitWindow { gate: idx });
+                    }
+                    Ok(CombineOperation::B2A(map(Domain::Z64, *dst), new_low))
+                }
+                CombineOperation::SizeHint(_, _) => unreachable!("Templates have no size hints"),
+            })
+            .collect()
+    }
+
+    /// Like `instantiate`, but emits the gates straight into `sink` as well.
+    pub fn instantiate_into(
+        &self,
+        bindings: &BTreeMap<String, Vec<usize>>,
+        sink: &mut impl GateSink,
+    ) -> Result<(), TemplateError> {
+        for gate in self.instantiate(bindings, sink)? {
+            sink.emit(gate);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::template::{Template, TemplateError};
+    use crate::{
+        evaluate_composite_program, Bus, CombineOperation, Domain, Operation, ProgramEditor,
+    };
+
+    fn bindings(pairs: &[(&str, Vec<usize>)]) -> BTreeMap<String, Vec<usize>> {
+        pairs
+            .iter()
+            .map(|(name, wires)| (name.to_string(), wires.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_template() {
+        // Converts a 64-bit word to Z64, then adds it to an accumulator
+        let mut body: Vec<CombineOperation> = vec![CombineOperation::SizeHint(3, 64)];
+        body.push(CombineOperation::B2A(1, 0));
+        body.push(CombineOperation::Z64(Operation::Add(2, 0, 1)));
+        let template = Template::new(
+            &body,
+            vec![
+                Bus {
+                    name: "word".into(),
+                    domain: Domain::GF2,
+                    wires: (0..64).collect(),
+                },
+                Bus {
+                    name: "acc".into(),
+                    domain: Domain::Z64,
+                    wires: vec![0],
+                },
+            ],
+            vec![Bus {
+                name: "sum".into(),
+                domain: Domain::Z64,
+                wires: vec![2],
+            }],
+        )
+        .unwrap();
+        assert_eq!(template.gates().len(), 2);
+
+        // Sum two words, with the running total starting at zero
+        let mut program = vec![CombineOperation::Z64(Operation::Const(0, 0))];
+        program.extend((0..128).map(|w| CombineOperation::GF2(Operation::Input(w))));
+        let mut editor = ProgramEditor::new(program);
+        let first = bindings(&[
+            ("word", (0..64).collect()),
+            ("acc", vec![0]),
+            ("sum", vec![10]),
+        ]);
+        template.instantiate_into(&first, &mut editor).unwrap();
+        let second = bindings(&[
+            ("word", (64..128).collect()),
+            ("acc", vec![10]),
+            ("sum", vec![20]),
+        ]);
+        let gates = template.instantiate(&second, &mut editor).unwrap();
+        // Internal wires of the two copies don't overlap
+        assert_eq!(gates[0], CombineOperation::B2A(5, 64));
+        for gate in gates {
+            editor.append(gate);
+        }
+        editor.append(CombineOperation::Z64(Operation::SubConst(30, 20, 14)));
+        editor.append(CombineOperation::Z64(Operation::AssertZero(30)));
+        let (program, _) = editor.commit();
+        assert_eq!(program[129], CombineOperation::B2A(2, 0));
+        let mut inputs = vec![false; 128];
+        inputs[2] = true;
+        inputs[3] = true;
+        inputs[65] = true;
+        evaluate_composite_program(&program, &inputs, &[]);
+
+        // Binding the word to scattered wires can't work
+        let mut scattered = first.clone();
+        scattered.insert("word".into(), (0..64).map(|w| 2 * w).collect());
+        assert_eq!(
+            template.instantiate(&scattered, &mut ProgramEditor::new(vec![])),
+            Err(TemplateError::SplitWindow { gate: 0 })
+        );
+        let mut missing = first.clone();
+        missing.remove("acc");
+        assert_eq!(
+            template.instantiate(&missing, &mut ProgramEditor::new(vec![])),
+            Err(TemplateError::Unbound("acc".into()))
+        );
+        let mut narrow = first;
+        narrow.insert("acc".into(), vec![]);
+        assert_eq!(
+            template.instantiate(&narrow, &mut ProgramEditor::new(vec![])),
+            Err(TemplateError::Width {
+                port: "acc".into(),
+                expected: 1,
+                found: 0
+            })
+        );
+
+        let sum = Bus {
+            name: "sum".into(),
+            domain: Domain::Z64,
+            wires: vec![3],
+        };
+        assert_eq!(
+            Template::new(&body, vec![], vec![sum]),
+            Err(TemplateError::OutputUnwritten("sum".into()))
+        );
+    }
+}
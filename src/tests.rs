@@ -1,15 +1,28 @@
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::BufWriter;
     use std::iter::FromIterator;
 
     use rand::distributions::{Distribution, Standard};
     use rand::thread_rng;
 
-    use crate::eval::{evaluate_composite_program, largest_wires, smallest_wires};
+    use crate::eval::{
+        evaluate_composite_program, evaluate_composite_program_checked,
+        evaluate_composite_program_configured, evaluate_composite_program_limited,
+        evaluate_composite_program_mapped, evaluate_composite_program_with,
+        evaluate_composite_program_with_randomness, evaluate_composite_program_with_strategy,
+        evaluate_prefix, largest_wires, resume_evaluation, smallest_wires, CancellationToken,
+        EvalConfig, EvalLimits, EvalOptions, EvaluationError, LimitedEvaluationError, Randomness,
+        StopReason, StorageStrategy, StreamingEvaluator, UnwrittenWires, WireStorage,
+    };
+    use crate::has_const::HasConst;
     use crate::has_io::HasIO;
-    use crate::translatable::Translatable;
-    use crate::{CombineOperation, OpType, Operation, WireValue};
+    use crate::parsers::jsonl::JsonlParser;
+    use crate::translatable::{Translatable, TranslationError};
+    use crate::{dump_annotated_vcd, dump_vcd, Program, VcdDumper};
+    use crate::{CombineOperation, Domain, OpType, Operation, WireValue};
 
     #[test]
     fn test_io_operations() {
@@ -124,6 +137,19 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_constants() {
+        let gate = Operation::MulConst(1, 0, 7u64);
+        assert_eq!(gate.constants().collect::<Vec<_>>(), vec![7]);
+        assert_eq!(gate.constant(), Some(7));
+        assert!(!Operation::<u64>::Add(2, 1, 0).has_constants());
+
+        let gate = CombineOperation::GF2(Operation::Const(0, true));
+        assert_eq!(gate.gf2_constants().collect::<Vec<_>>(), vec![true]);
+        assert!(gate.z64_constants().next().is_none());
+        assert!(CombineOperation::B2A(0, 0).gf2_constants().next().is_none());
+    }
+
     #[test]
     fn test_translation_operations() {
         fn do_gate_test<T: WireValue>()
@@ -257,6 +283,34 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_strict_translation() {
+        let gate = Operation::<u64>::Mul(2, 0, 1);
+        let table = HashMap::from_iter([(0, 10), (2, 12)]);
+        assert_eq!(
+            gate.translate_strict(&table),
+            Err(TranslationError::Unmapped {
+                inputs: vec![1],
+                outputs: vec![],
+            })
+        );
+        // The lenient version keeps the unmapped wire
+        assert_eq!(
+            gate.translate_from_hashmap(table.clone()),
+            Some(Operation::Mul(12, 10, 1))
+        );
+
+        let table = HashMap::from_iter([(0, 10), (1, 11), (2, 12)]);
+        assert_eq!(
+            gate.translate_strict(&table),
+            Ok(Operation::Mul(12, 10, 11))
+        );
+        assert_eq!(
+            CombineOperation::SizeHint(1, 1).translate_strict(&table),
+            Err(TranslationError::Untranslatable)
+        );
+    }
+
     #[test]
     fn test_simple_eval() {
         let circuit = vec![
@@ -336,6 +390,40 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_vcd_more_boolean_wires() {
+        // `largest_wires` counts (arithmetic, boolean) wires. Read the other way round, a circuit
+        // with fewer arithmetic wires than boolean ones got too little room for the boolean ones.
+        let program = vec![
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 0, 1)),
+        ];
+        let path = std::env::temp_dir().join(format!("mcircuit_bool_{}.vcd", std::process::id()));
+        let hasher = crate::parsers::FastHasher::default();
+        let writer = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+        let dumper = crate::VcdDumper::for_circuit(writer, &program, &hasher, &hasher);
+        crate::dump_vcd(&program, &[true, true], &[5], dumper);
+        let vcd = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(vcd.contains("1!2\n"));
+    }
+
+    #[test]
+    fn test_vcd_comment_end() {
+        let mut program: Program = vec![CombineOperation::GF2(Operation::Input(0))].into();
+        program.annotate(0, "reads $end\nof the witness");
+        let path = std::env::temp_dir().join(format!("mcircuit_note_{}.vcd", std::process::id()));
+        let writer = BufWriter::new(File::create(&path).unwrap());
+        let dumper = VcdDumper::for_program(writer, &program);
+        dump_annotated_vcd(&program, &[true], &[], dumper);
+        let vcd = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // The note can't close the comment early
+        assert!(vcd.contains("$comment reads $ end of the witness $end\n"));
+    }
+
     #[test]
     fn test_size_hinting() {
         let mut circuit = vec![
@@ -350,4 +438,575 @@ mod test {
 
         assert_eq!((400, 300), largest_wires(&circuit));
     }
+
+    #[test]
+    fn test_storage_strategies() {
+        let circuit = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Mul(2, 1, 0)),
+            CombineOperation::GF2(Operation::SubConst(3, 2, true)),
+            CombineOperation::GF2(Operation::AssertZero(3)),
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::MulConst(1, 0, 3)),
+            CombineOperation::Z64(Operation::SubConst(2, 1, 42)),
+            CombineOperation::Z64(Operation::AssertZero(2)),
+        ];
+
+        for strategy in [
+            StorageStrategy::Dense,
+            StorageStrategy::Paged,
+            StorageStrategy::Packed,
+            StorageStrategy::Auto,
+        ] {
+            evaluate_composite_program_with_strategy(&circuit, &[true, true], &[14], strategy);
+        }
+    }
+
+    #[test]
+    fn test_sparse_eval() {
+        // Allocating these densely would take terabytes, so this only passes if `Auto` notices
+        // how few wires are actually used.
+        let far = 1 << 40;
+        let circuit = vec![
+            CombineOperation::GF2(Operation::Input(3)),
+            CombineOperation::GF2(Operation::AddConst(far, 3, true)),
+            CombineOperation::GF2(Operation::AssertZero(far)),
+            CombineOperation::Z64(Operation::Const(far, 7)),
+            CombineOperation::Z64(Operation::SubConst(far + 1, far, 7)),
+            CombineOperation::Z64(Operation::AssertZero(far + 1)),
+        ];
+
+        evaluate_composite_program(&circuit, &[true], &[]);
+    }
+
+    #[test]
+    fn test_paged_storage() {
+        let mut storage: WireStorage<u64> = WireStorage::new(StorageStrategy::Paged, 0, 0);
+        for wire in (0..100_000).step_by(997) {
+            storage.set(wire, wire as u64);
+        }
+        for wire in (0..100_000).step_by(997) {
+            assert_eq!(storage.get(wire), wire as u64);
+            assert_eq!(storage.get(wire + 1), 0);
+        }
+    }
+
+    #[test]
+    fn test_packed_storage() {
+        let mut storage: WireStorage<bool> = WireStorage::new(StorageStrategy::Packed, 10, 10);
+        assert!(matches!(storage, WireStorage::Packed(_)));
+        for wire in (0..1000).step_by(3) {
+            storage.set(wire, true);
+        }
+        storage.set(9, false);
+        for wire in 0..1000 {
+            assert_eq!(storage.get(wire), wire % 3 == 0 && wire != 9);
+        }
+
+        // Z64 values don't fit in a bit
+        let storage: WireStorage<u64> = WireStorage::new(StorageStrategy::Packed, 10, 10);
+        assert!(matches!(storage, WireStorage::Dense(_)));
+    }
+
+    #[test]
+    fn test_eval_config() {
+        // A big boolean circuit that uses an eighth of its index space, and a sparse arithmetic one
+        let mut circuit: Vec<CombineOperation> = (0..1 << 18)
+            .map(|i| CombineOperation::GF2(Operation::Const(i * 8, i % 2 == 1)))
+            .collect();
+        circuit.push(CombineOperation::GF2(Operation::AssertZero(16)));
+        circuit.push(CombineOperation::Z64(Operation::Const(1 << 30, 5)));
+        circuit.push(CombineOperation::Z64(Operation::SubConst(0, 1 << 30, 5)));
+        circuit.push(CombineOperation::Z64(Operation::AssertZero(0)));
+
+        let config = EvalConfig::for_program(&circuit);
+        assert_eq!(config.bool_storage, StorageStrategy::Packed);
+        assert_eq!(config.arith_storage, StorageStrategy::Paged);
+        // Wide, but the parallel evaluator would store the paged domain densely
+        assert!(!config.parallel);
+        evaluate_composite_program_configured(&circuit, &[], &[], config);
+
+        // Overriding one domain leaves the other alone
+        let config = EvalConfig {
+            bool_storage: StorageStrategy::Dense,
+            ..config
+        };
+        evaluate_composite_program_configured(&circuit, &[], &[], config);
+
+        let small = EvalConfig::for_program(&circuit[..10]);
+        assert_eq!(small.bool_storage, StorageStrategy::Dense);
+        assert_eq!(small.arith_storage, StorageStrategy::Dense);
+    }
+
+    #[test]
+    fn test_eval_limits() {
+        let circuit: Vec<CombineOperation> = (0..10_000)
+            .map(|i| CombineOperation::Z64(Operation::AddConst(i + 1, i, 1)))
+            .collect();
+        let config = EvalConfig::for_program(&circuit);
+        let run = |limits: &EvalLimits| {
+            evaluate_composite_program_limited(&circuit, &[], &[], config, limits).map_err(|e| {
+                match e {
+                    LimitedEvaluationError::Interrupted(interrupted) => interrupted,
+                    LimitedEvaluationError::Failed(e) => panic!("{}", e),
+                }
+            })
+        };
+        assert_eq!(run(&EvalLimits::default()), Ok(()));
+
+        let limits = EvalLimits {
+            max_gates: Some(2500),
+            check_interval: 1000,
+            ..Default::default()
+        };
+        let stopped = run(&limits).unwrap_err();
+        assert_eq!(stopped.reason, StopReason::GateLimit);
+        assert_eq!(stopped.gates, 2500);
+
+        let token = CancellationToken::new();
+        let limits = EvalLimits {
+            token: Some(token.clone()),
+            ..Default::default()
+        };
+        token.cancel();
+        let stopped = run(&limits).unwrap_err();
+        assert_eq!(stopped.reason, StopReason::Cancelled);
+        assert_eq!(stopped.gates, 0);
+
+        let limits = EvalLimits {
+            timeout: Some(std::time::Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(run(&limits).unwrap_err().reason, StopReason::Timeout);
+    }
+
+    #[test]
+    fn test_eval_limits_report_failures() {
+        let limits = EvalLimits::default();
+        let program = [
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::AssertZero(0)),
+        ];
+        let config = EvalConfig::default();
+        assert_eq!(
+            evaluate_composite_program_limited(&program, &[], &[3], config, &limits),
+            Err(LimitedEvaluationError::Failed(
+                EvaluationError::AssertionFailed {
+                    gate: 1,
+                    domain: Domain::Z64,
+                    wire: 0,
+                    value: 3,
+                }
+            ))
+        );
+        assert_eq!(
+            evaluate_composite_program_limited(&program, &[], &[], config, &limits),
+            Err(LimitedEvaluationError::Failed(
+                EvaluationError::OutOfInputs {
+                    gate: 0,
+                    domain: Domain::Z64,
+                }
+            ))
+        );
+
+        let config = EvalConfig {
+            unwritten: UnwrittenWires::Error,
+            ..config
+        };
+        let failed =
+            evaluate_composite_program_limited(&reads_unwritten(), &[false], &[], config, &limits);
+        assert!(matches!(
+            failed,
+            Err(LimitedEvaluationError::Failed(
+                EvaluationError::UnwrittenRead(_)
+            ))
+        ));
+    }
+
+    fn reads_unwritten() -> Vec<CombineOperation> {
+        vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            // Wire 1 is never written
+            CombineOperation::GF2(Operation::Add(2, 0, 1)),
+            CombineOperation::GF2(Operation::AssertZero(2)),
+        ]
+    }
+
+    #[test]
+    fn test_unwritten_as_zero() {
+        evaluate_composite_program(&reads_unwritten(), &[false], &[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "before it's written")]
+    fn test_unwritten_as_error() {
+        let options = EvalOptions {
+            unwritten: UnwrittenWires::Error,
+            ..Default::default()
+        };
+        evaluate_composite_program_with(&reads_unwritten(), &[false], &[], options);
+    }
+
+    #[test]
+    #[should_panic(expected = "size hints allow 2 GF2 wires, but gate 2 uses more")]
+    fn test_stale_size_hints() {
+        let program = [
+            CombineOperation::SizeHint(0, 2),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AddConst(5, 0, true)),
+        ];
+        let config = EvalConfig {
+            check_size_hints: true,
+            ..Default::default()
+        };
+        // Fine without the check, since storage grows as needed
+        evaluate_composite_program_configured(&program, &[false], &[], EvalConfig::default());
+        evaluate_composite_program_configured(&program, &[false], &[], config);
+    }
+
+    #[test]
+    fn test_unwritten_as_input() {
+        let options = EvalOptions {
+            unwritten: UnwrittenWires::Input,
+            ..Default::default()
+        };
+        // The second witness value is consumed by wire 1, so the XOR is zero
+        evaluate_composite_program_with(&reads_unwritten(), &[true, true], &[], options);
+    }
+
+    #[test]
+    fn test_checked_evaluation() {
+        let program = [
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::Z64(Operation::MulConst(1, 0, 3)),
+            CombineOperation::Z64(Operation::SubConst(2, 1, 21)),
+            CombineOperation::Z64(Operation::AssertZero(2)),
+        ];
+        let config = EvalConfig::default();
+        let output =
+            evaluate_composite_program_checked(&program, &[true], &[7], &[0], &[1, 2], config)
+                .unwrap();
+        assert_eq!(output.bool_outputs, [true]);
+        assert_eq!(output.arith_outputs, [21, 0]);
+
+        let error = evaluate_composite_program_checked(&program, &[true], &[8], &[], &[], config)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            EvaluationError::AssertionFailed {
+                gate: 4,
+                domain: Domain::Z64,
+                wire: 2,
+                value: 3,
+            }
+        );
+        assert_eq!(
+            evaluate_composite_program_checked(&program, &[true], &[], &[], &[], config),
+            Err(EvaluationError::OutOfInputs {
+                gate: 1,
+                domain: Domain::Z64
+            })
+        );
+
+        // Gate indices are in the original program, even with inputs added for unwritten wires
+        let config = EvalConfig {
+            unwritten: UnwrittenWires::Input,
+            ..Default::default()
+        };
+        let error =
+            evaluate_composite_program_checked(&reads_unwritten(), &[true], &[], &[], &[], config)
+                .unwrap_err();
+        assert_eq!(error.gate(), Some(1));
+        let error = evaluate_composite_program_checked(
+            &reads_unwritten(),
+            &[true, false],
+            &[],
+            &[],
+            &[],
+            config,
+        )
+        .unwrap_err();
+        assert_eq!(error.gate(), Some(2));
+        assert_eq!(
+            error.to_string(),
+            "Gate 2 asserts that GF2 wire 2 is zero, but it's 1"
+        );
+
+        let config = EvalConfig {
+            unwritten: UnwrittenWires::Error,
+            ..Default::default()
+        };
+        assert!(matches!(
+            evaluate_composite_program_checked(&reads_unwritten(), &[true], &[], &[], &[], config),
+            Err(EvaluationError::UnwrittenRead(_))
+        ));
+    }
+
+    #[test]
+    fn test_random_tape() {
+        let program = [
+            CombineOperation::GF2(Operation::Random(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Add(2, 0, 1)),
+            CombineOperation::Z64(Operation::Random(0)),
+            CombineOperation::Z64(Operation::Random(1)),
+            CombineOperation::Z64(Operation::Sub(2, 0, 1)),
+        ];
+        let config = EvalConfig::default();
+        let evaluate = |randomness| {
+            evaluate_composite_program_with_randomness(
+                &program,
+                &[true],
+                &[],
+                &[0, 2],
+                &[0, 1, 2],
+                config,
+                randomness,
+            )
+        };
+
+        // Tapes are read in order, a domain at a time, like the witness
+        let tape = Randomness::Tape {
+            bool_tape: &[true],
+            arith_tape: &[10, 3],
+        };
+        let output = evaluate(tape).unwrap();
+        assert_eq!(output.bool_outputs, [true, false]);
+        assert_eq!(output.arith_outputs, [10, 3, 7]);
+        let short = Randomness::Tape {
+            bool_tape: &[true],
+            arith_tape: &[10],
+        };
+        assert_eq!(
+            evaluate(short),
+            Err(EvaluationError::OutOfRandomness {
+                gate: 4,
+                domain: Domain::Z64
+            })
+        );
+
+        // The same seed gives the same values
+        let seeded = evaluate(Randomness::Seed(7)).unwrap();
+        assert_eq!(evaluate(Randomness::Seed(7)).unwrap(), seeded);
+        assert_ne!(evaluate(Randomness::Seed(8)).unwrap(), seeded);
+        // ... on every platform and release, since seeds end up in test vectors
+        assert_eq!(seeded.bool_outputs, [true, false]);
+        assert_eq!(
+            seeded.arith_outputs,
+            [
+                15422459894761607863,
+                13256395593369783574,
+                2166064301391824289
+            ]
+        );
+
+        let mut evaluator = StreamingEvaluator::new(vec![], vec![], config).with_randomness(tape);
+        evaluator.step(&program[3]);
+        assert_eq!(evaluator.arith_wire(0), 10);
+    }
+
+    #[test]
+    fn test_mapped_evaluation() {
+        let program = [
+            CombineOperation::Z64(Operation::Input(4)),
+            CombineOperation::GF2(Operation::Input(2)),
+            CombineOperation::Z64(Operation::Input(1)),
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::Z64(Operation::Sub(5, 4, 1)),
+            CombineOperation::Z64(Operation::AssertZero(5)),
+        ];
+        let config = EvalConfig::default();
+        let map =
+            evaluate_composite_program_mapped(&program, &[true, false], &[9, 9], config).unwrap();
+        assert_eq!(map.wire(Domain::GF2, 1), Some(0));
+        assert_eq!(map.wire(Domain::Z64, 1), Some(1));
+        assert_eq!(map.wire(Domain::Z64, 2), None);
+        let arith: Vec<(usize, usize)> = map
+            .inputs(Domain::Z64)
+            .map(|input| (input.gate, input.ordinal))
+            .collect();
+        assert_eq!(arith, [(0, 0), (2, 1)]);
+
+        // Checked before the first gate runs, so a short witness isn't mistaken for a bad one
+        let error =
+            evaluate_composite_program_mapped(&program, &[true, false], &[9], config).unwrap_err();
+        assert_eq!(
+            error,
+            EvaluationError::WitnessTooShort {
+                needed: 2,
+                got: 1,
+                domain: Domain::Z64
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "The program reads 2 Z64 inputs, but the witness only has 1"
+        );
+        assert!(matches!(
+            evaluate_composite_program_mapped(&program, &[true, false], &[9, 8], config),
+            Err(EvaluationError::AssertionFailed { gate: 5, .. })
+        ));
+
+        // Inputs added for unwritten wires are counted, and belong to the gate reading the wire
+        let config = EvalConfig {
+            unwritten: UnwrittenWires::Input,
+            ..Default::default()
+        };
+        let map = evaluate_composite_program_mapped(&reads_unwritten(), &[true, true], &[], config)
+            .unwrap();
+        assert_eq!(map.uses.last().map(|input| input.gate), Some(1));
+    }
+
+    #[test]
+    fn test_prefix_evaluation() {
+        let program = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(1)),
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Add(2, 0, 1)),
+            // Overwritten before the rest of the program reads it, so it isn't kept
+            CombineOperation::GF2(Operation::Const(3, true)),
+            CombineOperation::GF2(Operation::Const(3, false)),
+            CombineOperation::GF2(Operation::AssertZero(3)),
+            CombineOperation::GF2(Operation::Input(4)),
+            CombineOperation::GF2(Operation::Mul(5, 2, 4)),
+            CombineOperation::GF2(Operation::AssertZero(5)),
+            CombineOperation::Z64(Operation::MulConst(1, 0, 3)),
+            CombineOperation::Z64(Operation::SubConst(1, 1, 21)),
+            CombineOperation::Z64(Operation::AssertZero(1)),
+        ];
+        let bool_inputs = [true, false, false];
+        let arith_inputs = [7];
+
+        let state = evaluate_prefix(&program, 5, &bool_inputs, &arith_inputs).unwrap();
+        assert_eq!(state.gates, 5);
+        assert_eq!(
+            state.bool_wires.into_iter().collect::<Vec<_>>(),
+            vec![(2, true)]
+        );
+        assert_eq!(
+            state.arith_wires.into_iter().collect::<Vec<_>>(),
+            vec![(0, 7)]
+        );
+        assert_eq!((state.bool_inputs_used, state.arith_inputs_used), (2, 1));
+
+        // Picking up from the state gives the same result as evaluating in one go
+        let state = evaluate_prefix(&program, 5, &bool_inputs, &arith_inputs).unwrap();
+        resume_evaluation(&program, &state, &bool_inputs, &arith_inputs).unwrap();
+        evaluate_composite_program(&program, &bool_inputs, &arith_inputs);
+
+        // A shorter witness than the state was saved with
+        assert_eq!(
+            resume_evaluation(&program, &state, &bool_inputs[..1], &arith_inputs),
+            Err(EvaluationError::WitnessTooShort {
+                needed: 2,
+                got: 1,
+                domain: Domain::GF2
+            })
+        );
+        assert_eq!(
+            resume_evaluation(&program, &state, &bool_inputs, &[]),
+            Err(EvaluationError::WitnessTooShort {
+                needed: 1,
+                got: 0,
+                domain: Domain::Z64
+            })
+        );
+        assert_eq!(
+            resume_evaluation(&program, &state, &bool_inputs[..2], &arith_inputs),
+            Err(EvaluationError::OutOfInputs {
+                gate: 7,
+                domain: Domain::GF2
+            })
+        );
+    }
+
+    #[test]
+    fn test_resumed_assertion() {
+        let program = vec![
+            CombineOperation::GF2(Operation::Input(0)),
+            CombineOperation::GF2(Operation::AssertZero(0)),
+        ];
+        let state = evaluate_prefix(&program, 1, &[true], &[]).unwrap();
+        let err = resume_evaluation(&program, &state, &[true], &[]).unwrap_err();
+        assert_eq!(err.gate(), Some(1));
+
+        // The prefix half reports the same failures
+        let err = evaluate_prefix(&program, 2, &[true], &[]).unwrap_err();
+        assert_eq!(err.gate(), Some(1));
+        assert_eq!(
+            evaluate_prefix(&program, 1, &[], &[]),
+            Err(EvaluationError::OutOfInputs {
+                gate: 0,
+                domain: Domain::GF2
+            })
+        );
+    }
+
+    #[test]
+    fn test_vcd_conversions() {
+        let mut program: Vec<CombineOperation> = (0..64)
+            .map(|w| CombineOperation::GF2(Operation::Input(w)))
+            .collect();
+        program.push(CombineOperation::B2A(5, 0));
+
+        let path = std::env::temp_dir().join(format!("mcircuit_b2a_{}.vcd", std::process::id()));
+        let writer = BufWriter::new(File::create(&path).unwrap());
+        let dumper = VcdDumper::for_program(writer, &program.clone().into());
+        let mut witness = [false; 64];
+        witness[1] = true;
+        dump_vcd(&program, &witness, &[], dumper);
+        let vcd = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The output is declared with the other arithmetic wires, and again with its window
+        assert!(vcd.contains("$var wire 64 @5 5 $end"));
+        assert!(vcd.contains(
+            "$scope module conversions $end\n$scope module b2a_64 $end\n$var wire 64 @5 out $end\n\
+             $var wire 1 !0 in(0) $end\n"
+        ));
+        assert!(vcd.contains("$var wire 1 !63 in(63) $end\n$upscope $end\n$upscope $end"));
+        assert!(vcd.contains("b10 @5"));
+    }
+
+    #[test]
+    fn test_streaming_evaluation() {
+        // Streamed straight from the parser, without collecting the gates
+        let jsonl = r#"{"op": "size_hint", "args": [3, 2]}
+{"op": "input", "domain": "gf2", "args": [0]}
+{"op": "input", "domain": "gf2", "args": [1]}
+{"op": "mul", "domain": "gf2", "args": [1, 0, 1]}
+{"op": "b2a", "args": [0, 0]}
+{"op": "input", "domain": "z64", "args": [1]}
+{"op": "mulc", "domain": "z64", "args": [2, 0], "const": 7}
+{"op": "sub", "domain": "z64", "args": [2, 2, 1]}
+{"op": "assert_zero", "domain": "z64", "args": [2]}
+"#;
+        let config = EvalConfig {
+            bool_storage: StorageStrategy::Packed,
+            arith_storage: StorageStrategy::Paged,
+            ..Default::default()
+        };
+        let mut evaluator = StreamingEvaluator::new(vec![true, true], vec![21, 99], config);
+        for gate in JsonlParser::from_reader(jsonl.as_bytes()) {
+            evaluator.step(&gate.unwrap());
+        }
+        assert_eq!(evaluator.gates(), 9);
+        assert!(evaluator.bool_wire(1));
+        assert_eq!(evaluator.arith_wire(0), 3);
+        assert_eq!(evaluator.arith_wire(1), 21);
+        assert_eq!(evaluator.arith_wire(2), 0);
+        let (mut bools, mut ariths) = evaluator.into_remaining_inputs();
+        assert_eq!((bools.next(), ariths.next()), (None, Some(99)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_streaming_assertion() {
+        let mut evaluator = StreamingEvaluator::new(vec![true], vec![], EvalConfig::default());
+        evaluator.step(&CombineOperation::GF2(Operation::Input(0)));
+        evaluator.step(&CombineOperation::GF2(Operation::AssertZero(0)));
+    }
 }
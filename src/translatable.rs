@@ -1,8 +1,36 @@
 use std::collections::HashMap;
+use std::fmt;
 
 use crate::io_extractors::{InputIterator, OutputIterator};
 use crate::{CombineOperation, HasIO, OpType, Operation, WireValue};
 
+/// Why `translate_strict` couldn't translate a gate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TranslationError {
+    /// Wires the translation table has no entry for, in the order the gate lists them
+    Unmapped {
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+    },
+    /// The gate has no wires to translate (like a size hint)
+    Untranslatable,
+}
+
+impl fmt::Display for TranslationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranslationError::Unmapped { inputs, outputs } => write!(
+                f,
+                "no mapping for input wires {:?} or output wires {:?}",
+                inputs, outputs
+            ),
+            TranslationError::Untranslatable => write!(f, "gate can't be translated"),
+        }
+    }
+}
+
+impl std::error::Error for TranslationError {}
+
 /// Defines a number of helper methods for replacing the I/O wires on a gate with new ones
 pub trait Translatable {
     /// takes an iterator of input wires and an iterator of output wires, and creates a new gate
@@ -15,7 +43,8 @@ pub trait Translatable {
         I2: Iterator<Item = usize>;
 
     /// Takes a hashmap, and looks for existing wires in the keys. Replaces any existing wire keys
-    /// with the value from the hashmap.
+    /// with the value from the hashmap. Wires that aren't in the hashmap are left as they are; see
+    /// `translate_strict` to catch them.
     fn translate_from_hashmap<'a>(
         &'a self,
         translation_table: HashMap<usize, usize>,
@@ -33,6 +62,35 @@ pub trait Translatable {
         )
     }
 
+    /// Like `translate_from_hashmap`, but every input and output wire has to be in the table.
+    /// Fails with the ones that aren't, rather than quietly keeping them, which is how a gate ends
+    /// up wired to the wrong part of a linked circuit.
+    fn translate_strict<'a>(
+        &'a self,
+        translation_table: &HashMap<usize, usize>,
+    ) -> Result<Self, TranslationError>
+    where
+        Self: Sized + HasIO,
+        InputIterator<'a, Self>: Iterator<Item = usize>,
+        OutputIterator<'a, Self>: Iterator<Item = usize>,
+    {
+        let missing = |wires: &mut dyn Iterator<Item = usize>| -> Vec<usize> {
+            wires
+                .filter(|w| !translation_table.contains_key(w))
+                .collect()
+        };
+        let inputs = missing(&mut self.inputs());
+        let outputs = missing(&mut self.outputs());
+        if !inputs.is_empty() || !outputs.is_empty() {
+            return Err(TranslationError::Unmapped { inputs, outputs });
+        }
+        self.translate(
+            self.inputs().map(|x| translation_table[&x]),
+            self.outputs().map(|x| translation_table[&x]),
+        )
+        .ok_or(TranslationError::Untranslatable)
+    }
+
     /// Calls a function on the I/O wires and replaces them with the output of the function.
     fn translate_from_fn<'a>(
         &'a self,
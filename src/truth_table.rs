@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use crate::Operation;
+
+/// A boolean function of a few inputs, given by listing its outputs for every input combination.
+/// Meant for turning small specifications (S-boxes, control logic) into circuits without going
+/// through an external synthesis tool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TruthTable {
+    inputs: usize,
+    /// One column per output. Row `r` holds the outputs when input `i` is bit `i` of `r`.
+    columns: Vec<Vec<bool>>,
+}
+
+impl TruthTable {
+    /// Tables grow exponentially, so anything past this is almost certainly a mistake
+    pub const MAX_INPUTS: usize = 16;
+
+    /// Builds a table from its columns, one per output, each with `2^inputs` rows. Row `r` holds
+    /// the outputs when input `i` is bit `i` of `r`.
+    pub fn new(inputs: usize, columns: Vec<Vec<bool>>) -> Self {
+        assert!(
+            inputs <= Self::MAX_INPUTS,
+            "Truth tables can have at most {} inputs",
+            Self::MAX_INPUTS
+        );
+        for column in &columns {
+            assert_eq!(
+                column.len(),
+                1 << inputs,
+                "A truth table with {} inputs needs {} rows",
+                inputs,
+                1 << inputs
+            );
+        }
+        TruthTable { inputs, columns }
+    }
+
+    /// Builds a table by calling `f` on every row. Bit `i` of the row number is input `i`, and
+    /// bit `j` of the result is output `j`.
+    pub fn from_fn(inputs: usize, outputs: usize, f: impl Fn(usize) -> u64) -> Self {
+        assert!(
+            inputs <= Self::MAX_INPUTS,
+            "Truth tables can have at most {} inputs",
+            Self::MAX_INPUTS
+        );
+        assert!(outputs <= 64, "from_fn can only produce 64 outputs");
+        let rows: Vec<u64> = (0..1usize << inputs).map(f).collect();
+        let columns = (0..outputs)
+            .map(|j| rows.iter().map(|row| (row >> j) & 1 == 1).collect())
+            .collect();
+        Self::new(inputs, columns)
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// The monomials in the algebraic normal form of an output, each as a mask of the inputs
+    /// it's the product of. The empty mask is the constant one.
+    fn monomials(&self, output: usize) -> Vec<usize> {
+        let mut coefficients = self.columns[output].clone();
+        for i in 0..self.inputs {
+            for row in 0..coefficients.len() {
+                if row & (1 << i) != 0 {
+                    coefficients[row] ^= coefficients[row ^ (1 << i)];
+                }
+            }
+        }
+        (0..coefficients.len())
+            .filter(|mask| coefficients[*mask])
+            .collect()
+    }
+
+    /// Synthesizes a GF2 circuit computing the table, reading input `i` from `inputs[i]`.
+    ///
+    /// Each output is built from its algebraic normal form: an XOR of ANDs of inputs, which is
+    /// exactly what GF2 `Add` and `Mul` gates compute. Products are shared between outputs, and
+    /// each one is built from a smaller one, so a product of `k` inputs costs at most one `Mul`
+    /// once the product of its first `k - 1` inputs exists.
+    ///
+    /// Every gate writes a new wire, counting up from `first_free`, so the circuit uses wires
+    /// `first_free..first_free + gates.len()`. Returns the gates along with the wire holding each
+    /// output. Outputs that are just one input, or the same as another output, aren't copied, so
+    /// the returned wires may repeat or appear in `inputs`.
+    pub fn synthesize(
+        &self,
+        inputs: &[usize],
+        first_free: usize,
+    ) -> (Vec<Operation<bool>>, Vec<usize>) {
+        assert_eq!(
+            inputs.len(),
+            self.inputs,
+            "The table needs {} input wires",
+            self.inputs
+        );
+
+        let mut synthesis = Synthesis {
+            inputs,
+            next: first_free,
+            gates: Vec::new(),
+            products: HashMap::new(),
+        };
+        let outputs = (0..self.outputs())
+            .map(|output| synthesis.sum(&self.monomials(output)))
+            .collect();
+        (synthesis.gates, outputs)
+    }
+}
+
+struct Synthesis<'a> {
+    inputs: &'a [usize],
+    next: usize,
+    gates: Vec<Operation<bool>>,
+    /// Wires already holding the product of each mask of inputs
+    products: HashMap<usize, usize>,
+}
+
+impl Synthesis<'_> {
+    fn emit(&mut self, gate: impl FnOnce(usize) -> Operation<bool>) -> usize {
+        let wire = self.next;
+        self.next += 1;
+        self.gates.push(gate(wire));
+        wire
+    }
+
+    /// A wire holding the product of the inputs in `mask`, which must not be empty.
+    fn product(&mut self, mask: usize) -> usize {
+        if mask.count_ones() == 1 {
+            return self.inputs[mask.trailing_zeros() as usize];
+        }
+        if let Some(wire) = self.products.get(&mask) {
+            return *wire;
+        }
+
+        let highest = usize::BITS - 1 - mask.leading_zeros();
+        let rest = self.product(mask ^ (1 << highest));
+        let input = self.inputs[highest as usize];
+        let wire = self.emit(|dst| Operation::Mul(dst, rest, input));
+        self.products.insert(mask, wire);
+        wire
+    }
+
+    /// A wire holding the XOR of the given monomials.
+    fn sum(&mut self, monomials: &[usize]) -> usize {
+        let mut total: Option<usize> = None;
+        for mask in monomials.iter().filter(|mask| **mask != 0) {
+            let term = self.product(*mask);
+            total = Some(match total {
+                None => term,
+                Some(sum) => self.emit(|dst| Operation::Add(dst, sum, term)),
+            });
+        }
+
+        let constant = monomials.contains(&0);
+        match (total, constant) {
+            (Some(sum), false) => sum,
+            (Some(sum), true) => self.emit(|dst| Operation::AddConst(dst, sum, true)),
+            (None, _) => self.emit(|dst| Operation::Const(dst, constant)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::truth_table::TruthTable;
+    use crate::{evaluate_composite_program, CombineOperation, HasIO, Operation};
+
+    /// The PRESENT S-box
+    const SBOX: [u64; 16] = [
+        0xc, 0x5, 0x6, 0xb, 0x9, 0x0, 0xa, 0xd, 0x3, 0xe, 0xf, 0x8, 0x4, 0x7, 0x1, 0x2,
+    ];
+
+    /// Checks the synthesized circuit against the table on every row.
+    fn check(table: &TruthTable, f: impl Fn(usize) -> u64) {
+        let inputs: Vec<usize> = (0..table.inputs()).collect();
+        let (gates, outputs) = table.synthesize(&inputs, 100);
+        let mut program: Vec<CombineOperation> = inputs
+            .iter()
+            .map(|w| CombineOperation::GF2(Operation::Input(*w)))
+            .collect();
+        program.extend(gates.into_iter().map(CombineOperation::GF2));
+
+        for row in 0..1 << table.inputs() {
+            let mut checked = program.clone();
+            for (j, wire) in outputs.iter().enumerate() {
+                let expected = (f(row) >> j) & 1 == 1;
+                checked.push(CombineOperation::GF2(Operation::AddConst(
+                    1000 + j,
+                    *wire,
+                    expected,
+                )));
+                checked.push(CombineOperation::GF2(Operation::AssertZero(1000 + j)));
+            }
+            let bits: Vec<bool> = inputs.iter().map(|i| (row >> i) & 1 == 1).collect();
+            evaluate_composite_program(&checked, &bits, &[]);
+        }
+    }
+
+    #[test]
+    fn test_sbox() {
+        let sbox = |row: usize| SBOX[row];
+        let table = TruthTable::from_fn(4, 4, sbox);
+        check(&table, sbox);
+
+        // Every gate writes the next free wire
+        let (gates, _) = table.synthesize(&[0, 1, 2, 3], 100);
+        for (i, gate) in gates.iter().enumerate() {
+            assert_eq!(gate.outputs().collect::<Vec<_>>(), vec![100 + i]);
+        }
+    }
+
+    #[test]
+    fn test_small_functions() {
+        // a & b & c needs two ANDs, and a ^ b one XOR
+        let table = TruthTable::from_fn(3, 2, |row| {
+            u64::from(row == 7) | (u64::from((row ^ (row >> 1)) & 1 == 1) << 1)
+        });
+        let (gates, outputs) = table.synthesize(&[10, 11, 12], 20);
+        assert_eq!(
+            gates,
+            vec![
+                Operation::Mul(20, 10, 11),
+                Operation::Mul(21, 20, 12),
+                Operation::Add(22, 10, 11),
+            ]
+        );
+        assert_eq!(outputs, vec![21, 22]);
+
+        // Constant and pass-through outputs
+        let table = TruthTable::from_fn(2, 3, |row| 0b010 | (((row as u64) & 1) << 2));
+        let (gates, outputs) = table.synthesize(&[0, 1], 5);
+        assert_eq!(
+            gates,
+            vec![Operation::Const(5, false), Operation::Const(6, true)]
+        );
+        assert_eq!(outputs, vec![5, 6, 0]);
+        check(&table, |row| 0b010 | (((row as u64) & 1) << 2));
+    }
+}
@@ -0,0 +1,277 @@
+//! Collected results of validation passes, so a single run can report every problem in a program
+//! rather than stopping at the first one.
+//!
+//! Validators produce `ValidationError`s, and a `ValidationReport` gathers them up to a cap (so a
+//! badly broken program can't produce a report bigger than the program), counting the ones it
+//! drops. Reports can be written out as plain text for people or JSON for CI jobs.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::analysis::{
+    AnalysisPass, SizeHintCheck, SizeHintIssue, UnderconstrainedConversion,
+    UnderconstrainedConversions, UnwrittenRead, UnwrittenReads,
+};
+use crate::exporters::{IR1Violation, WitnessLengthError};
+use crate::CombineOperation;
+
+/// How many problems a report keeps by default.
+pub const DEFAULT_REPORT_CAP: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Something that's probably a mistake, but that the program can still be used with
+    Warning,
+    /// Something that makes the program wrong or unusable
+    Error,
+}
+
+/// One problem found by a validator.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ValidationError {
+    pub severity: Severity,
+    /// Short, stable name for the kind of problem, like `unwritten-read`
+    pub kind: &'static str,
+    /// Index of the gate the problem is at, if it's at one
+    pub gate: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.gate {
+            Some(gate) => write!(
+                f,
+                "{}[{}]: gate {}: {}",
+                severity, self.kind, gate, self.message
+            ),
+            None => write!(f, "{}[{}]: {}", severity, self.kind, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<UnwrittenRead> for ValidationError {
+    fn from(read: UnwrittenRead) -> Self {
+        ValidationError {
+            severity: Severity::Warning,
+            kind: "unwritten-read",
+            gate: Some(read.gate),
+            message: format!(
+                "reads {:?} wire {} before anything writes it",
+                read.domain, read.wire
+            ),
+        }
+    }
+}
+
+impl From<UnderconstrainedConversion> for ValidationError {
+    fn from(found: UnderconstrainedConversion) -> Self {
+        ValidationError {
+            severity: Severity::Error,
+            kind: "underconstrained-conversion",
+            gate: Some(found.assertion),
+            message: format!(
+                "depends on the B2A at gate {}, which reads {} unwritten bits",
+                found.conversion,
+                found.unwritten_bits.len()
+            ),
+        }
+    }
+}
+
+impl From<SizeHintIssue> for ValidationError {
+    fn from(issue: SizeHintIssue) -> Self {
+        match issue {
+            SizeHintIssue::Misplaced { gate } => ValidationError {
+                severity: Severity::Warning,
+                kind: "size-hint-placement",
+                gate: Some(gate),
+                message: "size hint isn't the first gate".into(),
+            },
+            SizeHintIssue::TooSmall {
+                domain,
+                hinted,
+                needed,
+                gate,
+            } => ValidationError {
+                severity: Severity::Error,
+                kind: "size-hint-too-small",
+                gate: Some(gate),
+                message: format!(
+                    "size hints allow {} {:?} wires, but the program uses {}, starting here",
+                    hinted, domain, needed
+                ),
+            },
+            SizeHintIssue::Oversized {
+                domain,
+                hinted,
+                needed,
+            } => ValidationError {
+                severity: Severity::Warning,
+                kind: "size-hint-oversized",
+                gate: None,
+                message: format!(
+                    "size hints allow {} {:?} wires, but the program only uses {}",
+                    hinted, domain, needed
+                ),
+            },
+        }
+    }
+}
+
+impl From<IR1Violation> for ValidationError {
+    fn from(violation: IR1Violation) -> Self {
+        let (kind, gate) = match violation {
+            IR1Violation::Reassigned { gate, .. } => ("ir1-reassigned", Some(gate)),
+            IR1Violation::Unassigned { gate, .. } => ("ir1-unassigned", Some(gate)),
+            IR1Violation::ConstantOutOfRange { gate, .. } => ("ir1-constant", Some(gate)),
+            IR1Violation::Gap { .. } => ("ir1-gap", None),
+        };
+        ValidationError {
+            severity: Severity::Error,
+            kind,
+            gate,
+            message: violation.to_string(),
+        }
+    }
+}
+
+impl From<WitnessLengthError> for ValidationError {
+    fn from(error: WitnessLengthError) -> Self {
+        ValidationError {
+            severity: Severity::Error,
+            kind: "witness-length",
+            gate: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Problems found by one or more validators, in the order they were found.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ValidationReport {
+    pub errors: Vec<ValidationError>,
+    /// Most problems to keep
+    pub cap: usize,
+    /// Problems found after the report was full
+    pub omitted: usize,
+}
+
+impl Default for ValidationReport {
+    fn default() -> Self {
+        ValidationReport::new(DEFAULT_REPORT_CAP)
+    }
+}
+
+impl ValidationReport {
+    pub fn new(cap: usize) -> Self {
+        ValidationReport {
+            errors: Vec::new(),
+            cap,
+            omitted: 0,
+        }
+    }
+
+    pub fn push(&mut self, error: impl Into<ValidationError>) {
+        if self.errors.len() < self.cap {
+            self.errors.push(error.into());
+        } else {
+            self.omitted += 1;
+        }
+    }
+
+    /// Whether nothing at all was found, warnings included.
+    pub fn is_clean(&self) -> bool {
+        self.errors.is_empty() && self.omitted == 0
+    }
+
+    /// Whether any problem of `Severity::Error` was kept.
+    pub fn has_errors(&self) -> bool {
+        self.errors.iter().any(|e| e.severity == Severity::Error)
+    }
+
+    /// One problem per line, followed by a count of the ones that didn't fit.
+    pub fn to_text(&self) -> String {
+        let mut text: String = self.errors.iter().map(|e| format!("{}\n", e)).collect();
+        if self.omitted > 0 {
+            text.push_str(&format!("... and {} more\n", self.omitted));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Reports are always serializable")
+    }
+}
+
+impl<E: Into<ValidationError>> Extend<E> for ValidationReport {
+    fn extend<I: IntoIterator<Item = E>>(&mut self, errors: I) {
+        for error in errors {
+            self.push(error);
+        }
+    }
+}
+
+/// Runs the program-level validators (`UnwrittenReads`, `UnderconstrainedConversions` and
+/// `SizeHintCheck`) in a single walk over `program`, keeping at most `cap` problems.
+pub fn validate_program(program: &[CombineOperation], cap: usize) -> ValidationReport {
+    let (unwritten, conversions, size_hints) =
+        <(UnwrittenReads, UnderconstrainedConversions, SizeHintCheck)>::analyze(program.iter());
+    let mut report = ValidationReport::new(cap);
+    report.extend(unwritten);
+    report.extend(conversions);
+    report.extend(size_hints);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporters::IR1;
+    use crate::validation::{validate_program, Severity, ValidationReport};
+    use crate::{CombineOperation, Field, Operation};
+
+    #[test]
+    fn test_report() {
+        let program = [
+            CombineOperation::GF2(Operation::Add(0, 1, 2)),
+            CombineOperation::B2A(0, 0),
+            CombineOperation::Z64(Operation::AssertZero(0)),
+        ];
+        // Two reads by the Add, 61 more by the B2A, and the assertion that depends on it
+        let report = validate_program(&program, 100);
+        assert_eq!(report.errors.len(), 64);
+        assert_eq!(report.errors[0].severity, Severity::Warning);
+        assert_eq!(report.errors[63].gate, Some(2));
+        assert!(report.has_errors());
+        assert_eq!(
+            report.to_text().lines().next(),
+            Some("warning[unwritten-read]: gate 0: reads GF2 wire 1 before anything writes it")
+        );
+
+        let capped = validate_program(&program, 1);
+        assert_eq!(capped.errors.len(), 1);
+        assert_eq!(capped.omitted, 63);
+        assert!(capped.to_text().ends_with("... and 63 more\n"));
+        let json: serde_json::Value = serde_json::from_str(&capped.to_json()).unwrap();
+        assert_eq!(json["errors"][0]["severity"], "warning");
+        assert_eq!(json["omitted"], 63);
+
+        // Validators with their own error types feed into the same reports
+        let mut report = ValidationReport::default();
+        report.extend(IR1::validate(
+            Field::GF2,
+            &[Operation::Input(0), Operation::Input(0)],
+            false,
+        ));
+        assert_eq!(report.errors[0].kind, "ir1-reassigned");
+        assert!(!report.is_clean());
+    }
+}
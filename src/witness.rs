@@ -0,0 +1,620 @@
+//! Text witness files, checked against the inputs a program declares.
+//!
+//! A witness file has one line per input slot, in the order the program reads them. A slot is
+//! either an input bus (a bus whose wires are read by consecutive `Input` gates, in bus order) or a
+//! single input wire that isn't part of one. Each line holds the slot's values separated by
+//! whitespace, least significant first. Blank lines and `#` comments are skipped.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Error, ErrorKind, Result, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::exporters::WitnessLengthError;
+use crate::{Bus, CombineOperation, Domain, Field, Operation, Program};
+
+/// One line of a witness file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WitnessSlot {
+    /// The bus this slot fills, if it's a whole bus rather than a single wire
+    pub name: Option<String>,
+    pub domain: Domain,
+    /// How many values the line holds
+    pub width: usize,
+}
+
+impl fmt::Display for WitnessSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{:?} input", self.domain),
+        }
+    }
+}
+
+/// What to do when a witness file doesn't match the program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WitnessMode {
+    /// Reject the file
+    #[default]
+    Strict,
+    /// Pad missing values with zeros, drop extra ones, and reduce out-of-range values into the
+    /// field, recording a warning for each fix. Values that can't be parsed are still rejected.
+    Permissive,
+}
+
+/// A problem with a witness file, located by 1-based line and column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WitnessWarning {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for WitnessWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Witness {
+    pub bool_inputs: Vec<bool>,
+    pub arith_inputs: Vec<u64>,
+    /// Everything that was fixed up in permissive mode
+    pub warnings: Vec<WitnessWarning>,
+}
+
+/// How the witness of a program lines up with the witness of a transformed version of it. For
+/// each domain, entry `i` is the position the `i`th witness value moves to, or `None` if the
+/// transform removed the `Input` gate that read it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WitnessMapping {
+    pub gf2: Vec<Option<usize>>,
+    pub z64: Vec<Option<usize>>,
+}
+
+impl WitnessMapping {
+    /// The mapping for a transform that leaves the witness of `gates` alone.
+    pub fn identity(gates: &[CombineOperation]) -> Self {
+        WitnessMapping::from_kept_gates(gates, &vec![true; gates.len()])
+    }
+
+    /// The mapping for a transform that keeps the gates of `gates` marked in `kept` (and may
+    /// change the rest of the program, as long as it doesn't add, remove or reorder `Input`
+    /// gates).
+    pub fn from_kept_gates(gates: &[CombineOperation], kept: &[bool]) -> Self {
+        let mut mapping = WitnessMapping::default();
+        let (mut next_gf2, mut next_z64) = (0, 0);
+        for (gate, kept) in gates.iter().zip(kept) {
+            let (positions, next) = match gate {
+                CombineOperation::GF2(Operation::Input(_)) => (&mut mapping.gf2, &mut next_gf2),
+                CombineOperation::Z64(Operation::Input(_)) => (&mut mapping.z64, &mut next_z64),
+                _ => continue,
+            };
+            if *kept {
+                positions.push(Some(*next));
+                *next += 1;
+            } else {
+                positions.push(None);
+            }
+        }
+        mapping
+    }
+
+    fn positions(&self, domain: Domain) -> &[Option<usize>] {
+        match domain {
+            Domain::GF2 => &self.gf2,
+            Domain::Z64 => &self.z64,
+        }
+    }
+
+    /// The mapping for running the transform behind this mapping, then the one behind `next`.
+    pub fn then(&self, next: &WitnessMapping) -> WitnessMapping {
+        let compose = |ours: &[Option<usize>], theirs: &[Option<usize>]| {
+            ours.iter()
+                .map(|position| position.and_then(|p| theirs.get(p).copied().flatten()))
+                .collect()
+        };
+        WitnessMapping {
+            gf2: compose(&self.gf2, &next.gf2),
+            z64: compose(&self.z64, &next.z64),
+        }
+    }
+
+    /// Whether every witness value stays where it is.
+    pub fn is_identity(&self) -> bool {
+        [&self.gf2, &self.z64].iter().all(|positions| {
+            positions
+                .iter()
+                .enumerate()
+                .all(|(i, position)| *position == Some(i))
+        })
+    }
+
+    /// Rearranges the values of one domain's witness to match the transformed program. Fails if
+    /// there are more or fewer values than the original program reads.
+    pub fn map_values<T: Copy>(
+        &self,
+        domain: Domain,
+        values: &[T],
+    ) -> std::result::Result<Vec<T>, WitnessLengthError> {
+        let positions = self.positions(domain);
+        if values.len() != positions.len() {
+            return Err(WitnessLengthError {
+                domain,
+                inputs: positions.len(),
+                witness: values.len(),
+            });
+        }
+        let mut mapped: Vec<(usize, T)> = positions
+            .iter()
+            .zip(values)
+            .filter_map(|(position, value)| position.map(|p| (p, *value)))
+            .collect();
+        mapped.sort_by_key(|(position, _)| *position);
+        Ok(mapped.into_iter().map(|(_, value)| value).collect())
+    }
+
+    /// Rearranges both domains of a witness. Warnings are left behind, since they refer to the
+    /// original file.
+    pub fn map_witness(
+        &self,
+        witness: &Witness,
+    ) -> std::result::Result<Witness, WitnessLengthError> {
+        Ok(Witness {
+            bool_inputs: self.map_values(Domain::GF2, &witness.bool_inputs)?,
+            arith_inputs: self.map_values(Domain::Z64, &witness.arith_inputs)?,
+            warnings: Vec::new(),
+        })
+    }
+}
+
+/// Converts a witness file for `original` into one for `transformed`, which `mapping` describes.
+/// The file is read strictly.
+pub fn convert_witness(
+    original: &Program,
+    transformed: &Program,
+    mapping: &WitnessMapping,
+    reader: impl BufRead,
+    sink: &mut impl Write,
+) -> Result<()> {
+    let witness = read_witness(original, reader, WitnessMode::Strict)?;
+    let mapped = mapping
+        .map_witness(&witness)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    write_witness(transformed, &mapped.bool_inputs, &mapped.arith_inputs, sink)
+}
+
+/// Works out which witness lines `program` expects.
+pub fn witness_layout(program: &Program) -> Vec<WitnessSlot> {
+    let inputs: Vec<(Domain, usize)> = program
+        .gates
+        .iter()
+        .filter_map(|gate| match gate {
+            CombineOperation::GF2(Operation::Input(w)) => Some((Domain::GF2, *w)),
+            CombineOperation::Z64(Operation::Input(w)) => Some((Domain::Z64, *w)),
+            _ => None,
+        })
+        .collect();
+
+    let mut buses: HashMap<(Domain, usize), &Bus> = HashMap::new();
+    for bus in program.buses.iter().flatten() {
+        if let Some(first) = bus.wires.first() {
+            buses.entry((bus.domain, *first)).or_insert(bus);
+        }
+    }
+
+    let mut slots = Vec::new();
+    let mut next = 0;
+    while next < inputs.len() {
+        let (domain, wire) = inputs[next];
+        let bus = buses.get(&(domain, wire)).filter(|bus| {
+            inputs
+                .get(next..next + bus.wires.len())
+                .is_some_and(|run| run.iter().zip(&bus.wires).all(|(i, w)| *i == (domain, *w)))
+        });
+
+        let slot = match bus {
+            Some(bus) => WitnessSlot {
+                name: Some(bus.name.clone()),
+                domain,
+                width: bus.wires.len(),
+            },
+            None => WitnessSlot {
+                name: None,
+                domain,
+                width: 1,
+            },
+        };
+        next += slot.width;
+        slots.push(slot);
+    }
+    slots
+}
+
+fn invalid(line: usize, column: usize, message: String) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        WitnessWarning {
+            line,
+            column,
+            message,
+        }
+        .to_string(),
+    )
+}
+
+/// Whitespace-separated tokens, along with the (1-based, in characters) column each starts at.
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut found = Vec::new();
+    let mut start = None;
+    for (column, (offset, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((column + 1, offset)),
+            (true, Some((col, from))) => {
+                found.push((col, &line[from..offset]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((col, from)) = start {
+        found.push((col, &line[from..]));
+    }
+    found
+}
+
+struct WitnessReader {
+    mode: WitnessMode,
+    z64_field: Option<Field>,
+    witness: Witness,
+}
+
+impl WitnessReader {
+    /// Fails in strict mode, or records a warning in permissive mode.
+    fn problem(&mut self, line: usize, column: usize, message: String) -> Result<()> {
+        match self.mode {
+            WitnessMode::Strict => Err(invalid(line, column, message)),
+            WitnessMode::Permissive => {
+                self.witness.warnings.push(WitnessWarning {
+                    line,
+                    column,
+                    message,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn push_value(
+        &mut self,
+        domain: Domain,
+        line: usize,
+        column: usize,
+        token: &str,
+    ) -> Result<()> {
+        match domain {
+            Domain::GF2 => match token {
+                "0" | "1" => self.witness.bool_inputs.push(token == "1"),
+                _ => {
+                    return Err(invalid(
+                        line,
+                        column,
+                        format!("expected 0 or 1, found {}", token),
+                    ))
+                }
+            },
+            Domain::Z64 => {
+                let mut value: u64 = token.parse().map_err(|_| {
+                    invalid(line, column, format!("expected a number, found {}", token))
+                })?;
+                if let Some(field) = self.z64_field.filter(|f| f.degree == 1) {
+                    if value >= field.characteristic {
+                        self.problem(
+                            line,
+                            column,
+                            format!("{} is out of range for {}", value, field),
+                        )?;
+                        value %= field.characteristic;
+                    }
+                }
+                self.witness.arith_inputs.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    fn pad(&mut self, slot: &WitnessSlot, count: usize) {
+        match slot.domain {
+            Domain::GF2 => self
+                .witness
+                .bool_inputs
+                .extend(std::iter::repeat_n(false, count)),
+            Domain::Z64 => self
+                .witness
+                .arith_inputs
+                .extend(std::iter::repeat_n(0, count)),
+        }
+    }
+}
+
+/// Reads a witness file for `program`, checking every line against the slot it fills and every
+/// Z64 value against the program's Z64 field (if it has one).
+pub fn read_witness(program: &Program, reader: impl BufRead, mode: WitnessMode) -> Result<Witness> {
+    let slots = witness_layout(program);
+    let mut slots_left = slots.iter();
+    let mut state = WitnessReader {
+        mode,
+        z64_field: program.field(Domain::Z64),
+        witness: Witness::default(),
+    };
+
+    let mut line_number = 0;
+    for line in reader.lines() {
+        let line = line?;
+        line_number += 1;
+        let content = line.split('#').next().unwrap_or_default();
+        let values = tokens(content);
+        if values.is_empty() {
+            continue;
+        }
+
+        let slot = match slots_left.next() {
+            Some(slot) => slot,
+            None => {
+                state.problem(
+                    line_number,
+                    values[0].0,
+                    format!("program only reads {} lines of witness", slots.len()),
+                )?;
+                continue;
+            }
+        };
+
+        for (column, token) in values.iter().take(slot.width) {
+            state.push_value(slot.domain, line_number, *column, token)?;
+        }
+        if values.len() < slot.width {
+            state.problem(
+                line_number,
+                content.chars().count() + 1,
+                format!(
+                    "{} has width {}, but the line has {} values",
+                    slot,
+                    slot.width,
+                    values.len()
+                ),
+            )?;
+            state.pad(slot, slot.width - values.len());
+        } else if values.len() > slot.width {
+            state.problem(
+                line_number,
+                values[slot.width].0,
+                format!(
+                    "{} has width {}, but the line has {} values",
+                    slot,
+                    slot.width,
+                    values.len()
+                ),
+            )?;
+        }
+    }
+
+    for slot in slots_left {
+        state.problem(
+            line_number + 1,
+            1,
+            format!("no line for {} (width {})", slot, slot.width),
+        )?;
+        state.pad(slot, slot.width);
+    }
+
+    Ok(state.witness)
+}
+
+/// Writes a witness file for `program`. Fails without writing anything if the number of values
+/// in either domain doesn't match what the program reads, or a Z64 value is out of range for the
+/// program's Z64 field.
+pub fn write_witness(
+    program: &Program,
+    bool_inputs: &[bool],
+    arith_inputs: &[u64],
+    sink: &mut impl Write,
+) -> Result<()> {
+    let slots = witness_layout(program);
+    let width = |domain: Domain| -> usize {
+        slots
+            .iter()
+            .filter(|s| s.domain == domain)
+            .map(|s| s.width)
+            .sum()
+    };
+    let mismatch = |domain: Domain, expected: usize, found: usize| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "program reads {} {:?} witness values, but {} were given",
+                expected, domain, found
+            ),
+        )
+    };
+    if width(Domain::GF2) != bool_inputs.len() {
+        return Err(mismatch(Domain::GF2, width(Domain::GF2), bool_inputs.len()));
+    }
+    if width(Domain::Z64) != arith_inputs.len() {
+        return Err(mismatch(
+            Domain::Z64,
+            width(Domain::Z64),
+            arith_inputs.len(),
+        ));
+    }
+    if let Some(field) = program.field(Domain::Z64).filter(|f| f.degree == 1) {
+        if let Some(value) = arith_inputs.iter().find(|v| **v >= field.characteristic) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is out of range for {}", value, field),
+            ));
+        }
+    }
+
+    let mut bools = bool_inputs.iter();
+    let mut ariths = arith_inputs.iter();
+    for slot in &slots {
+        let values: Vec<String> = match slot.domain {
+            Domain::GF2 => bools
+                .by_ref()
+                .take(slot.width)
+                .map(|b| u8::from(*b).to_string())
+                .collect(),
+            Domain::Z64 => ariths
+                .by_ref()
+                .take(slot.width)
+                .map(|v| v.to_string())
+                .collect(),
+        };
+        write!(sink, "{}", values.join(" "))?;
+        match &slot.name {
+            Some(name) => writeln!(sink, " # {}", name)?,
+            None => writeln!(sink)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::optimize::eliminate_dead_code_with_mapping;
+    use crate::witness::{
+        convert_witness, read_witness, witness_layout, write_witness, WitnessMapping, WitnessMode,
+        WitnessWarning,
+    };
+    use crate::{
+        evaluate_composite_program, Bus, CombineOperation, Domain, Field, Operation, Program,
+    };
+
+    fn program() -> Program {
+        let mut program: Program = vec![
+            CombineOperation::GF2(Operation::Input(4)),
+            CombineOperation::GF2(Operation::Input(5)),
+            CombineOperation::GF2(Operation::Input(6)),
+            CombineOperation::Z64(Operation::Input(0)),
+            CombineOperation::GF2(Operation::Input(7)),
+        ]
+        .into();
+        program.buses = Some(vec![Bus {
+            name: "nibble".into(),
+            domain: Domain::GF2,
+            wires: vec![4, 5, 6],
+        }]);
+        program.fields = Some(BTreeMap::from([(Domain::Z64, Field::prime(101))]));
+        program
+    }
+
+    #[test]
+    fn test_layout_and_round_trip() {
+        let program = program();
+        let widths: Vec<usize> = witness_layout(&program).iter().map(|s| s.width).collect();
+        assert_eq!(widths, vec![3, 1, 1]);
+
+        let mut file = Vec::new();
+        write_witness(&program, &[true, false, true, true], &[42], &mut file).unwrap();
+        assert_eq!(
+            String::from_utf8(file.clone()).unwrap(),
+            "1 0 1 # nibble\n42\n1\n"
+        );
+
+        let witness = read_witness(&program, file.as_slice(), WitnessMode::Strict).unwrap();
+        assert_eq!(witness.bool_inputs, vec![true, false, true, true]);
+        assert_eq!(witness.arith_inputs, vec![42]);
+        assert!(witness.warnings.is_empty());
+
+        assert!(write_witness(&program, &[true], &[42], &mut Vec::new()).is_err());
+        assert!(write_witness(&program, &[true; 4], &[101], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_witness() {
+        let program = program();
+        let file = "# comment\n1 0\n  500\n1 1\n";
+
+        let err = read_witness(&program, file.as_bytes(), WitnessMode::Strict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2, column 4: nibble has width 3, but the line has 2 values"
+        );
+
+        let witness = read_witness(&program, file.as_bytes(), WitnessMode::Permissive).unwrap();
+        assert_eq!(witness.bool_inputs, vec![true, false, false, true]);
+        assert_eq!(witness.arith_inputs, vec![500 % 101]);
+        assert_eq!(
+            witness.warnings,
+            vec![
+                WitnessWarning {
+                    line: 2,
+                    column: 4,
+                    message: "nibble has width 3, but the line has 2 values".into(),
+                },
+                WitnessWarning {
+                    line: 3,
+                    column: 3,
+                    message: "500 is out of range for GF(101)".into(),
+                },
+                WitnessWarning {
+                    line: 4,
+                    column: 3,
+                    message: "GF2 input has width 1, but the line has 2 values".into(),
+                },
+            ]
+        );
+
+        // Unparseable values aren't fixed up, even in permissive mode
+        assert!(read_witness(&program, "1 0 2".as_bytes(), WitnessMode::Permissive).is_err());
+    }
+
+    #[test]
+    fn test_witness_mapping() {
+        // Only the last boolean input and the arithmetic one are checked
+        let original = program();
+        let mut checked = original.clone();
+        checked.gates.extend([
+            CombineOperation::GF2(Operation::AddConst(8, 7, true)),
+            CombineOperation::GF2(Operation::AssertZero(8)),
+            CombineOperation::Z64(Operation::SubConst(1, 0, 42)),
+            CombineOperation::Z64(Operation::AssertZero(1)),
+        ]);
+        let (gates, mapping) = eliminate_dead_code_with_mapping(&checked.gates, false);
+        assert_eq!(mapping.gf2, [None, None, None, Some(0)]);
+        assert_eq!(mapping.z64, [Some(0)]);
+        assert!(!mapping.is_identity());
+        assert!(WitnessMapping::identity(&gates).is_identity());
+        assert_eq!(mapping.then(&WitnessMapping::identity(&gates)), mapping);
+
+        let optimized: Program = gates.into();
+        let mut file = Vec::new();
+        convert_witness(
+            &checked,
+            &optimized,
+            &mapping,
+            "1 0 1 # nibble\n42\n1\n".as_bytes(),
+            &mut file,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(file.clone()).unwrap(), "42\n1\n");
+        let witness = read_witness(&optimized, file.as_slice(), WitnessMode::Strict).unwrap();
+        evaluate_composite_program(
+            &optimized.gates,
+            &witness.bool_inputs,
+            &witness.arith_inputs,
+        );
+
+        assert!(mapping.map_values(Domain::GF2, &[true]).is_err());
+    }
+}